@@ -0,0 +1,765 @@
+use {
+    crate::{
+        acme_gateway::AcmeGateway,
+        constants::{
+            DEFAULT_ACME_ACCOUNT_LIMIT, ENV_ACCOUNT_KEY_KMS_KEY_ID, ENV_ACCOUNT_KEY_STORE, ENV_ACME_ACCOUNT_LIMIT,
+        },
+        dry_run::PlannedWrite,
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        jws::{encode, jwk, new_nonce, sign_jws},
+        secrets::{resolve_secret, validate_secret_reference, DefaultSecretStore},
+        utils::{now_epoch_secs, ssm_acme_parameter_path},
+        xray::aws_client,
+    },
+    acme2::Directory,
+    lambda_runtime::Error as LambdaError,
+    log::{debug, error, info},
+    openssl::{
+        hash::MessageDigest,
+        pkey::{PKey, Private},
+        rsa::Rsa,
+        sign::Signer,
+    },
+    reqwest::header::CONTENT_TYPE,
+    rusoto_core::{Region, RusotoError},
+    rusoto_secretsmanager::{
+        CreateSecretError, CreateSecretRequest, Filter, GetSecretValueError, GetSecretValueRequest, ListSecretsRequest,
+        SecretsManager, SecretsManagerClient,
+    },
+    rusoto_ssm::{
+        GetParameterError, GetParameterRequest, GetParametersByPathRequest, PutParameterError, PutParameterRequest,
+        Ssm, SsmClient,
+    },
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::{json, Value},
+    std::{env::var, str::from_utf8},
+};
+
+/// Where ACME account keys are kept.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AccountKeyBackend {
+    /// A SecureString SSM parameter. This is the default.
+    SsmParameter,
+
+    /// A Secrets Manager secret.
+    SecretsManager,
+}
+
+/// Persistence for ACME account keys. Each (contact, ACME server) pair has one account; its key is generated the
+/// first time the account is needed and reused from then on, so later invocations find the same account instead of
+/// registering a new one. These are global settings, configured through environment variables:
+///
+/// * `AccountKeyStore`: `SsmParameter` (the default) or `SecretsManager`.
+/// * `AccountKeyKmsKeyId`: the KMS key used to encrypt the account key. This defaults to the AWS managed key for
+///   the service.
+/// * `AcmeAccountLimit`: the most named accounts (see RegisteredAccount) that may be created. This defaults to 10;
+///   once reached, requests naming a new account fail instead of registering it.
+///
+/// Keys are named `{AcmeParameterPath}/PrivateKeys/{contact}/{directory host}`, and named accounts
+/// `{AcmeParameterPath}/Accounts/{name}`; Secrets Manager names omit the leading slash.
+#[derive(Clone, Debug)]
+pub struct AccountKeyStore {
+    backend: AccountKeyBackend,
+    kms_key_id: Option<String>,
+    account_limit: usize,
+}
+
+impl AccountKeyStore {
+    pub fn from_env() -> Result<Self, LambdaError> {
+        let backend = match var(ENV_ACCOUNT_KEY_STORE).ok().filter(|value| !value.is_empty()).as_deref() {
+            None | Some("SsmParameter") => AccountKeyBackend::SsmParameter,
+            Some("SecretsManager") => AccountKeyBackend::SecretsManager,
+            Some(other) => {
+                return Err(CertificateRequestError::invalid_account_key_store(format!(
+                    "{} must be SsmParameter or SecretsManager: {}",
+                    ENV_ACCOUNT_KEY_STORE, other
+                )))
+            }
+        };
+
+        let account_limit = match var(ENV_ACME_ACCOUNT_LIMIT).ok().filter(|value| !value.is_empty()) {
+            None => DEFAULT_ACME_ACCOUNT_LIMIT,
+            Some(value) => value.parse().map_err(|_| {
+                CertificateRequestError::invalid_account_key_store(format!(
+                    "{} must be a non-negative integer: {}",
+                    ENV_ACME_ACCOUNT_LIMIT, value
+                ))
+            })?,
+        };
+
+        Ok(Self {
+            backend,
+            kms_key_id: var(ENV_ACCOUNT_KEY_KMS_KEY_ID).ok().filter(|key_id| !key_id.is_empty()),
+            account_limit,
+        })
+    }
+
+    /// The name of the parameter or secret holding the key for an account.
+    pub fn key_name(&self, contact: &str, dir_host: &str) -> String {
+        let name = format!(
+            "{}/PrivateKeys/{}/{}",
+            ssm_acme_parameter_path(),
+            contact.replace(":", "-").replace("/", "-").replace("@", "_"),
+            dir_host,
+        );
+
+        match self.backend {
+            AccountKeyBackend::SsmParameter => name,
+            AccountKeyBackend::SecretsManager => name.trim_start_matches('/').to_string(),
+        }
+    }
+
+    /// Identifies where the key for an account is kept: the backend and the name of the parameter or secret. Accounts
+    /// found through different key sources are different accounts, even for the same directory and contacts.
+    pub fn key_source(&self, account_name: Option<&str>, contact: &str, dir_host: &str) -> String {
+        format!("{:?}:{}", self.backend, self.account_key_name(account_name, contact, dir_host))
+    }
+
+    /// The write that saves a new account key, reported by Staging dry runs. It's only made if no key exists yet, in
+    /// which case the account is registered with the directory as well.
+    pub fn planned_write(&self, account_name: Option<&str>, contact: &str, dir_host: &str) -> PlannedWrite {
+        let action = match self.backend {
+            AccountKeyBackend::SsmParameter => "ssm:PutParameter",
+            AccountKeyBackend::SecretsManager => "secretsmanager:CreateSecret",
+        };
+        let name = self.account_key_name(account_name, contact, dir_host);
+        PlannedWrite::new(action, format!("{} (if no account key exists)", name))
+    }
+
+    /// The name of the parameter or secret holding an account's key: the named account's entry in the registry, or
+    /// the key for the contact and directory host.
+    fn account_key_name(&self, account_name: Option<&str>, contact: &str, dir_host: &str) -> String {
+        match account_name {
+            Some(account_name) => format!("{}/{}", self.registry_path(), account_name),
+            None => self.key_name(contact, dir_host),
+        }
+    }
+
+    /// Returns the key for an account, generating and saving a new one if none exists. The boolean is true if an
+    /// existing key (and therefore an existing account) was found.
+    pub async fn load_or_generate(&self, contact: &str, dir_host: &str) -> Result<(PKey<Private>, bool), LambdaError> {
+        let name = self.key_name(contact, dir_host);
+        info!("Looking for existing account private key in {}", name);
+
+        if let Some(pem) = self.read(&name).await? {
+            info!("Using existing account private key from {}", name);
+            return Ok((parse_key(&name, &pem)?, true));
+        }
+
+        let pkey = generate_key()?;
+        let pem = from_utf8(&pkey.private_key_to_pem_pkcs8()?)?.to_string();
+        let description = format!("ACMEv02 private key for {}", contact);
+
+        info!("Saving account private key to {}", name);
+        if self.create(&name, &description, pem).await? {
+            info!("Account private key saved");
+            Ok((pkey, false))
+        } else {
+            // Another invocation created the key first; use theirs so both end up with the same account.
+            info!("Account private key {} was created concurrently; using it instead", name);
+            match self.read(&name).await? {
+                Some(pem) => Ok((parse_key(&name, &pem)?, true)),
+                None => Err(CertificateRequestError::unexpected_aws_response(format!(
+                    "Account private key {} exists but could not be read",
+                    name
+                ))),
+            }
+        }
+    }
+
+    /// The name of the parameter or secret holding the named accounts.
+    fn registry_path(&self) -> String {
+        let path = format!("{}/Accounts", ssm_acme_parameter_path());
+        match self.backend {
+            AccountKeyBackend::SsmParameter => path,
+            AccountKeyBackend::SecretsManager => path.trim_start_matches('/').to_string(),
+        }
+    }
+
+    /// Returns the key for a named account, registering the account on first use. The boolean is true if the
+    /// account already existed.
+    ///
+    /// Each account is created with a conditional write, so concurrent invocations racing to create the same account
+    /// end up sharing whichever key was written first. An account is tied to the directory it was created for;
+    /// naming it in a request for another directory is an error rather than a reason to create another account.
+    pub async fn load_or_register(
+        &self,
+        account_name: &str,
+        directory: &str,
+        contacts: &[String],
+    ) -> Result<(PKey<Private>, bool), LambdaError> {
+        let name = format!("{}/{}", self.registry_path(), account_name);
+        info!("Looking for registered ACME account {} in {}", account_name, name);
+
+        if let Some(value) = self.read(&name).await? {
+            let account = RegisteredAccount::parse(&name, &value)?;
+            return Ok((account.private_key(account_name, directory)?, true));
+        }
+
+        let registered = self.count_registered().await?;
+        if registered >= self.account_limit {
+            error!(
+                "Refusing to register ACME account {}: {} of {} accounts already registered",
+                account_name, registered, self.account_limit
+            );
+            return Err(CertificateRequestError::account_limit_reached(account_name, self.account_limit));
+        }
+
+        let pkey = generate_key()?;
+        let account = RegisteredAccount {
+            directory: directory.to_string(),
+            contacts: contacts.to_vec(),
+            private_key: from_utf8(&pkey.private_key_to_pem_pkcs8()?)?.to_string(),
+            created_at: now_epoch_secs(),
+        };
+        let description = format!("ACME account {} for {}", account_name, directory);
+
+        info!("Registering ACME account {} in {}", account_name, name);
+        if self.create(&name, &description, serde_json::to_string(&account)?).await? {
+            info!("ACME account {} registered", account_name);
+            return Ok((pkey, false));
+        }
+
+        info!("ACME account {} was registered concurrently; using it instead", account_name);
+        match self.read(&name).await? {
+            Some(value) => {
+                let account = RegisteredAccount::parse(&name, &value)?;
+                Ok((account.private_key(account_name, directory)?, true))
+            }
+            None => Err(CertificateRequestError::unexpected_aws_response(format!(
+                "ACME account {} exists but could not be read",
+                name
+            ))),
+        }
+    }
+
+    /// The number of named accounts.
+    async fn count_registered(&self) -> Result<usize, LambdaError> {
+        let path = self.registry_path();
+        let mut count = 0;
+        let mut next_token = None;
+
+        loop {
+            next_token = match self.backend {
+                AccountKeyBackend::SsmParameter => {
+                    let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+                    let gpbp_request = GetParametersByPathRequest {
+                        path: path.clone(),
+                        recursive: Some(false),
+                        with_decryption: Some(false),
+                        next_token,
+                        ..Default::default()
+                    };
+
+                    let response = match ssm.get_parameters_by_path(gpbp_request).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            error!("Failed to list ACME accounts in {}: {:#}", path, e);
+                            return Err(Box::new(e));
+                        }
+                    };
+
+                    count += response.parameters.map(|parameters| parameters.len()).unwrap_or(0);
+                    response.next_token
+                }
+                AccountKeyBackend::SecretsManager => {
+                    let sm = SecretsManagerClient::new_with_client(aws_client(), Region::default());
+                    let ls_request = ListSecretsRequest {
+                        filters: Some(vec![Filter {
+                            key: Some("name".to_string()),
+                            values: Some(vec![format!("{}/", path)]),
+                        }]),
+                        next_token,
+                        ..Default::default()
+                    };
+
+                    let response = match sm.list_secrets(ls_request).await {
+                        Ok(response) => response,
+                        Err(e) => {
+                            error!("Failed to list ACME accounts in {}: {:#}", path, e);
+                            return Err(Box::new(e));
+                        }
+                    };
+
+                    count += response.secret_list.map(|secrets| secrets.len()).unwrap_or(0);
+                    response.next_token
+                }
+            };
+
+            if next_token.is_none() {
+                return Ok(count);
+            }
+        }
+    }
+
+    /// Read a key's PEM, returning None if it doesn't exist.
+    async fn read(&self, name: &str) -> Result<Option<String>, LambdaError> {
+        let value = match self.backend {
+            AccountKeyBackend::SsmParameter => {
+                let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+                let gp_request = GetParameterRequest {
+                    name: name.to_string(),
+                    with_decryption: Some(true),
+                };
+
+                match ssm.get_parameter(gp_request).await {
+                    Ok(response) => match response.parameter {
+                        None => return Ok(None),
+                        Some(parameter) => parameter.value,
+                    },
+                    Err(RusotoError::Service(GetParameterError::ParameterNotFound(_))) => return Ok(None),
+                    Err(e) => {
+                        debug!("SSM error: {:#}", e);
+                        return Err(Box::new(e));
+                    }
+                }
+            }
+            AccountKeyBackend::SecretsManager => {
+                let sm = SecretsManagerClient::new_with_client(aws_client(), Region::default());
+                let gsv_request = GetSecretValueRequest {
+                    secret_id: name.to_string(),
+                    ..Default::default()
+                };
+
+                match sm.get_secret_value(gsv_request).await {
+                    Ok(response) => response.secret_string,
+                    Err(RusotoError::Service(GetSecretValueError::ResourceNotFound(_))) => return Ok(None),
+                    Err(e) => {
+                        debug!("Secrets Manager error: {:#}", e);
+                        return Err(Box::new(e));
+                    }
+                }
+            }
+        };
+
+        match value {
+            Some(value) => Ok(Some(value)),
+            None => {
+                error!("No value returned for {}", name);
+                Err(CertificateRequestError::unexpected_aws_response(format!("No value returned for {}", name)))
+            }
+        }
+    }
+
+    /// Create a key, never overwriting an existing one (which would orphan its account). Returns false if the key
+    /// already exists.
+    async fn create(&self, name: &str, description: &str, pem: String) -> Result<bool, LambdaError> {
+        let result = match self.backend {
+            AccountKeyBackend::SsmParameter => {
+                let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+                let pp_request = PutParameterRequest {
+                    name: name.to_string(),
+                    description: Some(description.to_string()),
+                    overwrite: Some(false),
+                    type_: Some("SecureString".into()),
+                    key_id: self.kms_key_id.clone(),
+                    value: pem,
+                    ..Default::default()
+                };
+
+                match ssm.put_parameter(pp_request).await {
+                    Ok(_) => Ok(true),
+                    Err(RusotoError::Service(PutParameterError::ParameterAlreadyExists(_))) => Ok(false),
+                    Err(e) => Err(Box::new(e) as LambdaError),
+                }
+            }
+            AccountKeyBackend::SecretsManager => {
+                let sm = SecretsManagerClient::new_with_client(aws_client(), Region::default());
+                let cs_request = CreateSecretRequest {
+                    name: name.to_string(),
+                    description: Some(description.to_string()),
+                    kms_key_id: self.kms_key_id.clone(),
+                    secret_string: Some(pem),
+                    ..Default::default()
+                };
+
+                match sm.create_secret(cs_request).await {
+                    Ok(_) => Ok(true),
+                    Err(RusotoError::Service(CreateSecretError::ResourceExists(_))) => Ok(false),
+                    Err(e) => Err(Box::new(e) as LambdaError),
+                }
+            }
+        };
+
+        if let Err(e) = &result {
+            error!("Failed to save account private key to {}: {:#}", name, e);
+        }
+
+        result
+    }
+}
+
+/// A named ACME account, selected by a request's "Account". This lets one deployment keep separate accounts for,
+/// e.g., staging, production, and alternate CAs, independently of the request's Contacts. Each account is stored as
+/// JSON in the account key store:
+///
+///     {
+///         // The directory the account was registered with.
+///         "Directory": str,
+///
+///         // The contacts the account was registered with.
+///         "Contacts": [str],
+///
+///         // The account's private key, PEM-encoded.
+///         "PrivateKey": str,
+///
+///         // When the account was registered, in seconds since the Unix epoch.
+///         "CreatedAt": int,
+///     }
+#[derive(Clone, Deserialize, Serialize)]
+pub struct RegisteredAccount {
+    #[serde(rename = "Directory")]
+    pub directory: String,
+
+    #[serde(rename = "Contacts", default)]
+    pub contacts: Vec<String>,
+
+    #[serde(rename = "PrivateKey")]
+    private_key: String,
+
+    #[serde(rename = "CreatedAt", default)]
+    pub created_at: i64,
+}
+
+impl RegisteredAccount {
+    fn parse(name: &str, value: &str) -> Result<Self, LambdaError> {
+        match serde_json::from_str(value) {
+            Ok(account) => Ok(account),
+            Err(e) => {
+                error!("Failed to parse ACME account from {}: {}", name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Returns the account's key, checking that the account belongs to the directory.
+    fn private_key(&self, account_name: &str, directory: &str) -> Result<PKey<Private>, LambdaError> {
+        if self.directory != directory {
+            return Err(InvalidCertificateRequest::invalid_account(format!(
+                "Account {} is registered with {}, not {}",
+                account_name, self.directory, directory
+            )));
+        }
+
+        parse_key(account_name, &self.private_key)
+    }
+}
+
+/// Check the name of a registered account.
+pub fn validate_account_name(account_name: &str) -> Result<(), LambdaError> {
+    let valid = !account_name.is_empty()
+        && account_name.len() <= 64
+        && account_name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+
+    if valid {
+        Ok(())
+    } else {
+        Err(InvalidCertificateRequest::invalid_account(format!(
+            "Account must be 1-64 letters, digits, '-', '_', or '.': {:?}",
+            account_name
+        )))
+    }
+}
+
+fn generate_key() -> Result<PKey<Private>, LambdaError> {
+    info!("Generating new account private key");
+    match Rsa::generate(2048).and_then(PKey::from_rsa) {
+        Ok(pkey) => Ok(pkey),
+        Err(e) => {
+            error!("Failed to generate 2048-bit RSA key: {}", e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+fn parse_key(name: &str, pem: &str) -> Result<PKey<Private>, LambdaError> {
+    match PKey::private_key_from_pem(pem.as_bytes()) {
+        Ok(pkey) => Ok(pkey),
+        Err(e) => {
+            error!("Failed to parse account private key from {}: {:#}", name, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// ACME directories that can be named in a request in place of their URLs.
+const DIRECTORY_PRESETS: &[(&str, &str)] = &[
+    ("LetsEncryptProduction", "https://acme-v02.api.letsencrypt.org/directory"),
+    ("LetsEncryptStaging", "https://acme-staging-v02.api.letsencrypt.org/directory"),
+    ("ZeroSSL", "https://acme.zerossl.com/v2/DV90"),
+    ("BuyPass", "https://api.buypass.com/acme/directory"),
+    ("GoogleTrust", "https://dv.acme-v02.api.pki.goog/directory"),
+];
+
+/// Returns the URL for a directory preset name; anything else is assumed to already be a URL and is returned as-is.
+pub fn resolve_directory(directory: &str) -> String {
+    match DIRECTORY_PRESETS.iter().find(|(name, _)| name.eq_ignore_ascii_case(directory)) {
+        Some((_, url)) => url.to_string(),
+        None => directory.to_string(),
+    }
+}
+
+/// External Account Binding (RFC 8555 section 7.3.4) credentials, required to create an account with CAs such as
+/// ZeroSSL and Google Trust Services. In JSON:
+///
+///     {
+///         // The EAB key id issued by the CA.
+///         "KeyId": str,
+///
+///         // The name of a SecureString SSM parameter holding the EAB HMAC key issued by the CA, base64url-encoded
+///         // as the CA provides it, or a secret reference to another backend (e.g.
+///         // "vault:secret/data/acme#hmac_key"; see SecretBackend). The key itself is never placed in the request,
+///         // since requests are recorded in the inventory.
+///         "HmacKeyParameter": str,
+///     }
+///
+/// The binding is only used when creating a new ACME account; once the account key has been saved, later runs find
+/// the existing account without it.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ExternalAccountBinding {
+    #[serde(rename = "KeyId")]
+    pub key_id: String,
+
+    #[serde(rename = "HmacKeyParameter")]
+    pub hmac_key_parameter: String,
+}
+
+impl ExternalAccountBinding {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        if self.key_id.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_external_account_binding("KeyId cannot be empty"));
+        }
+
+        if validate_secret_reference(&self.hmac_key_parameter, DefaultSecretStore::Ssm).is_err() {
+            return Err(InvalidCertificateRequest::invalid_external_account_binding(format!(
+                "Invalid HmacKeyParameter: {}",
+                self.hmac_key_parameter
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Read the HMAC key from SSM or the secret backend named by HmacKeyParameter.
+    pub async fn hmac_key(&self) -> Result<PKey<Private>, LambdaError> {
+        match resolve_secret(&self.hmac_key_parameter, DefaultSecretStore::Ssm).await {
+            Ok(value) => decode_hmac_key(&value.0),
+            Err(e) => {
+                error!("Failed to read EAB HMAC key from {}: {:#}", self.hmac_key_parameter, e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Create an ACME account for the key, bound to the external account. acme2 can't send a binding, so the
+    /// newAccount request is made directly; the account is then found through acme2 as an existing account.
+    pub async fn register_account(
+        &self,
+        directory: &str,
+        account_key: &PKey<Private>,
+        contacts: &[String],
+    ) -> Result<(), LambdaError> {
+        let hmac_key = self.hmac_key().await?;
+        let gateway = AcmeGateway::from_env()?;
+        let client = gateway.http_client().await?.unwrap_or_default();
+        let urls: Value =
+            serde_json::from_str(&client.get(gateway.rewrite_directory(directory)).send().await?.text().await?)?;
+        let (new_nonce_url, new_account_url) = match (urls["newNonce"].as_str(), urls["newAccount"].as_str()) {
+            (Some(new_nonce_url), Some(new_account_url)) => (new_nonce_url, new_account_url),
+            _ => {
+                return Err(CertificateRequestError::unexpected_acme_response(format!(
+                    "The ACME directory at {} has no newNonce or newAccount URL",
+                    directory
+                )))
+            }
+        };
+
+        let payload = json!({
+            "contact": contacts,
+            "termsOfServiceAgreed": true,
+            "externalAccountBinding": self.sign(&hmac_key, &jwk(account_key)?, new_account_url)?,
+        });
+
+        let nonce = new_nonce(&client, new_nonce_url).await?;
+        let body = sign_jws(account_key, new_account_url, &nonce, &payload)?;
+        let response = client
+            .post(new_account_url)
+            .header(CONTENT_TYPE, "application/jose+json")
+            .body(body.to_string())
+            .send()
+            .await?;
+        let status = response.status();
+        if status.is_success() {
+            info!("Created ACME account bound to external account {}", self.key_id);
+            return Ok(());
+        }
+
+        let problem: Value = serde_json::from_str(&response.text().await?).unwrap_or(Value::Null);
+        let detail = problem["detail"].as_str().unwrap_or_default();
+        error!("Failed to create ACME account bound to external account {}: HTTP {}: {}", self.key_id, status, detail);
+        Err(CertificateRequestError::account_registration_failed(format!(
+            "External account {}: HTTP {}: {}",
+            self.key_id, status, detail
+        )))
+    }
+
+    /// Sign the binding for the newAccount request (RFC 8555, section 7.3.4): a JWS over the account key's JWK,
+    /// MACed with the HMAC key issued by the CA and identified by KeyId.
+    fn sign(&self, hmac_key: &PKey<Private>, account_jwk: &Value, url: &str) -> Result<Value, LambdaError> {
+        let protected = json!({"alg": "HS256", "kid": self.key_id, "url": url});
+        let protected = encode(protected.to_string().as_bytes());
+        let payload = encode(account_jwk.to_string().as_bytes());
+
+        let mut signer = Signer::new(MessageDigest::sha256(), hmac_key)?;
+        signer.update(format!("{}.{}", protected, payload).as_bytes())?;
+        Ok(json!({"protected": protected, "payload": payload, "signature": encode(&signer.sign_to_vec()?)}))
+    }
+}
+
+/// Decode an EAB HMAC key. CAs issue these base64url-encoded, usually without padding.
+fn decode_hmac_key(encoded: &str) -> Result<PKey<Private>, LambdaError> {
+    let encoded = encoded.trim().trim_end_matches('=');
+    let key = match base64::decode_config(encoded, base64::URL_SAFE_NO_PAD) {
+        Ok(key) if !key.is_empty() => key,
+        _ => {
+            return Err(InvalidCertificateRequest::invalid_external_account_binding(
+                "The HMAC key must be base64url-encoded",
+            ))
+        }
+    };
+
+    Ok(PKey::hmac(&key)?)
+}
+
+/// Indicates whether the ACME server requires External Account Binding to create an account.
+pub fn external_account_required(directory: &Directory) -> bool {
+    directory.meta.as_ref().and_then(|meta| meta.external_account_required).unwrap_or(false)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{
+            decode_hmac_key, resolve_directory, validate_account_name, AccountKeyBackend, AccountKeyStore,
+            ExternalAccountBinding, RegisteredAccount,
+        },
+        crate::{jws::jwk, keys::KeyAlgorithm},
+        ring::hmac::{verify, Key, HMAC_SHA256},
+        serde_json::{json, Value},
+    };
+
+    #[test]
+    fn test_account_key_name() {
+        let ssm = AccountKeyStore {
+            backend: AccountKeyBackend::SsmParameter,
+            kms_key_id: None,
+            account_limit: 10,
+        };
+        assert_eq!(
+            ssm.key_name("mailto:admin@example.com", "acme-v02.api.letsencrypt.org"),
+            "/AcmeParameters/PrivateKeys/mailto-admin_example.com/acme-v02.api.letsencrypt.org"
+        );
+
+        let sm = AccountKeyStore {
+            backend: AccountKeyBackend::SecretsManager,
+            kms_key_id: None,
+            account_limit: 10,
+        };
+        assert_eq!(
+            sm.key_name("mailto:admin@example.com", "acme-v02.api.letsencrypt.org"),
+            "AcmeParameters/PrivateKeys/mailto-admin_example.com/acme-v02.api.letsencrypt.org"
+        );
+
+        assert_eq!(
+            ssm.key_source(None, "mailto:admin@example.com", "acme-v02.api.letsencrypt.org"),
+            "SsmParameter:/AcmeParameters/PrivateKeys/mailto-admin_example.com/acme-v02.api.letsencrypt.org"
+        );
+        assert_eq!(
+            sm.key_source(Some("production"), "mailto:admin@example.com", "acme-v02.api.letsencrypt.org"),
+            "SecretsManager:AcmeParameters/Accounts/production"
+        );
+
+        let write = ssm.planned_write(None, "mailto:admin@example.com", "acme-staging-v02.api.letsencrypt.org");
+        assert_eq!(write.action, "ssm:PutParameter");
+        assert_eq!(
+            write.resource,
+            "/AcmeParameters/PrivateKeys/mailto-admin_example.com/acme-staging-v02.api.letsencrypt.org (if no account \
+             key exists)"
+        );
+        let write = sm.planned_write(Some("production"), "mailto:admin@example.com", "acme-v02.api.letsencrypt.org");
+        assert_eq!(write.action, "secretsmanager:CreateSecret");
+        assert_eq!(write.resource, "AcmeParameters/Accounts/production (if no account key exists)");
+    }
+
+    #[test]
+    fn test_registered_account() {
+        assert!(validate_account_name("production").is_ok());
+        assert!(validate_account_name("zerossl.eab-1").is_ok());
+        assert!(validate_account_name("").is_err());
+        assert!(validate_account_name("prod/../staging").is_err());
+        assert!(validate_account_name(&"a".repeat(65)).is_err());
+
+        let account: RegisteredAccount = serde_json::from_str(
+            r#"{"Directory": "https://acme-staging-v02.api.letsencrypt.org/directory", "PrivateKey": "-"}"#,
+        )
+        .unwrap();
+        assert!(account.contacts.is_empty());
+        let e = account.private_key("staging", "https://acme-v02.api.letsencrypt.org/directory").err().unwrap();
+        assert!(e.to_string().contains("is registered with https://acme-staging-v02"));
+    }
+
+    #[test]
+    fn test_resolve_directory() {
+        assert_eq!(resolve_directory("LetsEncryptStaging"), "https://acme-staging-v02.api.letsencrypt.org/directory");
+        assert_eq!(resolve_directory("zerossl"), "https://acme.zerossl.com/v2/DV90");
+        assert_eq!(resolve_directory("https://acme.example.com/directory"), "https://acme.example.com/directory");
+    }
+
+    #[test]
+    fn test_external_account_binding() {
+        let eab = ExternalAccountBinding {
+            key_id: "kid-1".to_string(),
+            hmac_key_parameter: "/AcmeParameters/Eab/ZeroSsl".to_string(),
+        };
+        assert!(eab.validate().is_ok());
+
+        let eab = ExternalAccountBinding {
+            key_id: String::new(),
+            hmac_key_parameter: "/AcmeParameters/Eab/ZeroSsl".to_string(),
+        };
+        assert!(eab.validate().is_err());
+
+        assert!(decode_hmac_key("c2VjcmV0LWhtYWMta2V5LWZvci10ZXN0aW5n").is_ok());
+        assert!(decode_hmac_key("c2VjcmV0LWhtYWMta2V5LWZvci10ZXN0aW5n\n").is_ok());
+        assert!(decode_hmac_key("c2VjcmV0LWhtYWMta2V5_-8=").is_ok());
+        assert!(decode_hmac_key("not base64!").is_err());
+        assert!(decode_hmac_key("").is_err());
+    }
+
+    #[test]
+    fn test_external_account_binding_jws() {
+        let decode = |data: &Value| base64::decode_config(data.as_str().unwrap(), base64::URL_SAFE_NO_PAD).unwrap();
+        let eab = ExternalAccountBinding {
+            key_id: "kid-1".to_string(),
+            hmac_key_parameter: "/AcmeParameters/Eab/ZeroSsl".to_string(),
+        };
+        let hmac_key = decode_hmac_key("c2VjcmV0LWhtYWMta2V5LWZvci10ZXN0aW5n").unwrap();
+        let account_key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let account_jwk = jwk(&account_key).unwrap();
+
+        let jws = eab.sign(&hmac_key, &account_jwk, "https://acme.example/new-account").unwrap();
+        let protected: Value = serde_json::from_slice(&decode(&jws["protected"])).unwrap();
+        assert_eq!(protected, json!({"alg": "HS256", "kid": "kid-1", "url": "https://acme.example/new-account"}));
+
+        let payload: Value = serde_json::from_slice(&decode(&jws["payload"])).unwrap();
+        assert_eq!(payload, account_jwk);
+        assert_eq!(payload["kty"], "EC");
+
+        // The MAC is keyed with the base64url-decoded HMAC key.
+        let key = Key::new(HMAC_SHA256, b"secret-hmac-key-for-testing");
+        let signing_input = format!("{}.{}", jws["protected"].as_str().unwrap(), jws["payload"].as_str().unwrap());
+        assert!(verify(&key, signing_input.as_bytes(), &decode(&jws["signature"])).is_ok());
+    }
+}
@@ -0,0 +1,370 @@
+use {
+    crate::{
+        constants::{
+            ACM_ALL_KEY_TYPES, ACM_STATUS_EXPIRED, ACM_STATUS_ISSUED, ACM_TYPE_IMPORTED,
+            DEFAULT_ACM_CACHE_FULL_SYNC_HOURS, ENV_ACM_CACHE_FULL_SYNC_HOURS, ENV_ACM_CACHE_TABLE,
+        },
+        faults::{self, Fault},
+        throttle,
+        utils::{attr_n, attr_s, normalize_serial, now_epoch_secs},
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{debug, error, info},
+    rusoto_acm::{
+        Acm, AcmClient, CertificateDetail, DescribeCertificateError, DescribeCertificateRequest, Filters,
+        ListCertificatesRequest,
+    },
+    rusoto_core::{Region, RusotoError},
+    rusoto_dynamodb::{
+        AttributeValue, DeleteItemInput, DynamoDb, DynamoDbClient, GetItemInput, PutItemInput, ScanInput,
+    },
+    std::{
+        collections::{HashMap, HashSet},
+        env::var,
+        time::Duration,
+    },
+    tokio::time::sleep,
+};
+
+/// The partition key value of the item holding the cache's synchronization state.
+const SYNC_STATE_KEY: &str = "#SyncState";
+
+/// Delay between DescribeCertificate calls during a full sync. This keeps us well under the ACM request rate limit
+/// even when another process is also calling ACM.
+const DESCRIBE_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A certificate as recorded in the cache.
+#[derive(Clone, Debug)]
+pub struct CachedCertificate {
+    pub certificate_arn: String,
+    pub domain_name: String,
+    pub subject_alternative_names: Vec<String>,
+    pub type_: String,
+    pub status: String,
+    pub serial: String,
+    pub not_after: i64,
+}
+
+impl CachedCertificate {
+    fn from_detail(detail: CertificateDetail) -> Option<Self> {
+        let mut sans: Vec<String> =
+            detail.subject_alternative_names.unwrap_or_default().iter().map(|san| san.to_lowercase()).collect();
+        sans.sort();
+        sans.dedup();
+
+        Some(Self {
+            certificate_arn: detail.certificate_arn?,
+            domain_name: detail.domain_name.unwrap_or_default(),
+            subject_alternative_names: sans,
+            type_: detail.type_.unwrap_or_default(),
+            status: detail.status.unwrap_or_default(),
+            serial: normalize_serial(detail.serial.as_deref().unwrap_or("")),
+            not_after: detail.not_after.map(|t| t as i64).unwrap_or(0),
+        })
+    }
+
+    fn to_item(&self) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert("CertificateArn".to_string(), attr_s(&self.certificate_arn));
+        item.insert("DomainName".to_string(), attr_s(&self.domain_name));
+        item.insert("Type".to_string(), attr_s(&self.type_));
+        item.insert("Status".to_string(), attr_s(&self.status));
+        item.insert("Serial".to_string(), attr_s(&self.serial));
+        item.insert("NotAfter".to_string(), attr_n(self.not_after));
+        item.insert("UpdatedAt".to_string(), attr_n(now_epoch_secs()));
+        if !self.subject_alternative_names.is_empty() {
+            item.insert(
+                "SubjectAlternativeNames".to_string(),
+                AttributeValue {
+                    ss: Some(self.subject_alternative_names.clone()),
+                    ..Default::default()
+                },
+            );
+        }
+        item
+    }
+
+    fn from_item(item: &HashMap<String, AttributeValue>) -> Option<Self> {
+        let get_s = |name: &str| item.get(name).and_then(|v| v.s.clone());
+        let mut sans = item.get("SubjectAlternativeNames").and_then(|v| v.ss.clone()).unwrap_or_default();
+        sans.sort();
+
+        Some(Self {
+            certificate_arn: get_s("CertificateArn")?,
+            domain_name: get_s("DomainName").unwrap_or_default(),
+            subject_alternative_names: sans,
+            type_: get_s("Type").unwrap_or_default(),
+            status: get_s("Status").unwrap_or_default(),
+            serial: get_s("Serial").unwrap_or_default(),
+            not_after: item.get("NotAfter").and_then(|v| v.n.as_ref()).and_then(|n| n.parse().ok()).unwrap_or(0),
+        })
+    }
+}
+
+/// An incremental cache of ACM certificate state stored in DynamoDB. The cache is enabled by setting the
+/// `AcmCacheTable` environment variable to the name of a table with a string partition key named `CertificateArn`.
+///
+/// The cache is updated when certificates are imported by this function, when ACM EventBridge events are delivered
+/// to this function, and by a full sync that runs at most once every `AcmCacheFullSyncHours` hours (default 24).
+/// A full sync only describes certificates not already in the cache, so large accounts stay within ACM API limits.
+pub struct AcmCache {
+    table_name: String,
+    full_sync_interval_secs: i64,
+    region: Region,
+}
+
+impl AcmCache {
+    /// Returns the cache configured via environment variables, or None if the cache is not enabled.
+    pub fn from_env() -> Option<Self> {
+        let table_name = var(ENV_ACM_CACHE_TABLE).ok().filter(|t| !t.is_empty())?;
+        let full_sync_hours = var(ENV_ACM_CACHE_FULL_SYNC_HOURS)
+            .ok()
+            .and_then(|h| h.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_ACM_CACHE_FULL_SYNC_HOURS);
+
+        Some(Self {
+            table_name,
+            full_sync_interval_secs: full_sync_hours * 3600,
+            region: Region::default(),
+        })
+    }
+
+    /// Find imported certificates whose subject alternative names exactly match the given domain names.
+    pub async fn find_matching_certificates(&self, domain_names: &[String]) -> Result<Vec<String>, LambdaError> {
+        if self.full_sync_due().await? {
+            self.full_sync().await?;
+        }
+
+        let mut wanted: Vec<String> = domain_names.iter().map(|dn| dn.to_lowercase()).collect();
+        wanted.sort();
+        wanted.dedup();
+
+        let matches = self
+            .scan()
+            .await?
+            .into_iter()
+            .filter(|cert| cert.type_ == ACM_TYPE_IMPORTED)
+            .filter(|cert| cert.status == ACM_STATUS_ISSUED || cert.status == ACM_STATUS_EXPIRED)
+            .filter(|cert| cert.subject_alternative_names == wanted)
+            .map(|cert| {
+                info!("Certificate {} is a cached match", cert.certificate_arn);
+                cert.certificate_arn
+            })
+            .collect();
+
+        Ok(matches)
+    }
+
+    /// Refresh the cache entry for a single certificate, removing it if the certificate no longer exists.
+    pub async fn refresh_certificate(&self, certificate_arn: &str) -> Result<(), LambdaError> {
+        let acm = AcmClient::new_with_client(aws_client(), self.region.clone());
+        match self.describe_certificate(&acm, certificate_arn).await? {
+            Some(cert) => self.put(&cert).await,
+            None => self.delete(certificate_arn).await,
+        }
+    }
+
+    /// Run a full sync if one is due.
+    pub async fn sync_if_due(&self) -> Result<(), LambdaError> {
+        if self.full_sync_due().await? {
+            self.full_sync().await
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn full_sync_due(&self) -> Result<bool, LambdaError> {
+        let ddb = DynamoDbClient::new_with_client(aws_client(), self.region.clone());
+        let gi_request = GetItemInput {
+            table_name: self.table_name.clone(),
+            key: key_for(SYNC_STATE_KEY),
+            consistent_read: Some(true),
+            ..Default::default()
+        };
+
+        let last_sync = match ddb.get_item(gi_request).await {
+            Ok(response) => response
+                .item
+                .and_then(|item| item.get("LastFullSync").and_then(|v| v.n.clone()))
+                .and_then(|n| n.parse::<i64>().ok())
+                .unwrap_or(0),
+            Err(e) => {
+                error!("Failed to read ACM cache sync state from {}: {}", self.table_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        Ok(now_epoch_secs() - last_sync >= self.full_sync_interval_secs)
+    }
+
+    /// List all certificates in the account, describe any that aren't already cached, and remove cached
+    /// certificates that no longer exist.
+    async fn full_sync(&self) -> Result<(), LambdaError> {
+        info!("Starting full sync of ACM cache {}", self.table_name);
+        let acm = AcmClient::new_with_client(aws_client(), self.region.clone());
+        // ListCertificates only returns RSA-2048 certificates unless other key types are requested explicitly.
+        let mut lc_request = ListCertificatesRequest {
+            includes: Some(Filters {
+                key_types: Some(ACM_ALL_KEY_TYPES.iter().map(|kt| kt.to_string()).collect()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut live_arns = HashSet::new();
+
+        loop {
+            let response = match throttle::call("ListCertificates", || {
+                faults::aws_call(Fault::AcmThrottling, acm.list_certificates(lc_request.clone()))
+            })
+            .await
+            {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("ListCertificates failed: {}", e);
+                    return Err(Box::new(e));
+                }
+            };
+
+            for summary in response.certificate_summary_list.unwrap_or_default() {
+                if let Some(arn) = summary.certificate_arn {
+                    live_arns.insert(arn);
+                }
+            }
+
+            match response.next_token {
+                None => break,
+                Some(token) => lc_request.next_token = Some(token),
+            }
+        }
+
+        let cached: HashSet<String> = self.scan().await?.into_iter().map(|cert| cert.certificate_arn).collect();
+
+        for arn in live_arns.difference(&cached) {
+            debug!("Describing uncached certificate {}", arn);
+            if let Some(cert) = self.describe_certificate(&acm, arn).await? {
+                self.put(&cert).await?;
+            }
+            sleep(DESCRIBE_INTERVAL).await;
+        }
+
+        for arn in cached.difference(&live_arns) {
+            debug!("Removing deleted certificate {} from cache", arn);
+            self.delete(arn).await?;
+        }
+
+        let ddb = DynamoDbClient::new_with_client(aws_client(), self.region.clone());
+        let mut item = key_for(SYNC_STATE_KEY);
+        item.insert("LastFullSync".to_string(), attr_n(now_epoch_secs()));
+        let pi_request = PutItemInput {
+            table_name: self.table_name.clone(),
+            item,
+            ..Default::default()
+        };
+
+        if let Err(e) = ddb.put_item(pi_request).await {
+            error!("Failed to record ACM cache sync state in {}: {}", self.table_name, e);
+            return Err(Box::new(e));
+        }
+
+        info!("Full sync of ACM cache {} complete: {} certificate(s)", self.table_name, live_arns.len());
+        Ok(())
+    }
+
+    async fn describe_certificate(
+        &self,
+        acm: &AcmClient,
+        certificate_arn: &str,
+    ) -> Result<Option<CachedCertificate>, LambdaError> {
+        let dc_request = DescribeCertificateRequest {
+            certificate_arn: certificate_arn.to_string(),
+        };
+
+        match throttle::call("DescribeCertificate", || {
+            faults::aws_call(Fault::AcmThrottling, acm.describe_certificate(dc_request.clone()))
+        })
+        .await
+        {
+            Ok(response) => Ok(response.certificate.and_then(CachedCertificate::from_detail)),
+            Err(RusotoError::Service(DescribeCertificateError::ResourceNotFound(_))) => Ok(None),
+            Err(e) => {
+                error!("Failed to describe ACM certificate {}: {}", certificate_arn, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    async fn scan(&self) -> Result<Vec<CachedCertificate>, LambdaError> {
+        let ddb = DynamoDbClient::new_with_client(aws_client(), self.region.clone());
+        let mut scan_request = ScanInput {
+            table_name: self.table_name.clone(),
+            ..Default::default()
+        };
+        let mut results = Vec::new();
+
+        loop {
+            let response = match ddb.scan(scan_request.clone()).await {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("Failed to scan ACM cache table {}: {}", self.table_name, e);
+                    return Err(Box::new(e));
+                }
+            };
+
+            for item in response.items.unwrap_or_default() {
+                if let Some(cert) = CachedCertificate::from_item(&item) {
+                    if cert.certificate_arn != SYNC_STATE_KEY {
+                        results.push(cert);
+                    }
+                }
+            }
+
+            match response.last_evaluated_key {
+                None => break,
+                Some(key) => scan_request.exclusive_start_key = Some(key),
+            }
+        }
+
+        Ok(results)
+    }
+
+    async fn put(&self, cert: &CachedCertificate) -> Result<(), LambdaError> {
+        let ddb = DynamoDbClient::new_with_client(aws_client(), self.region.clone());
+        let pi_request = PutItemInput {
+            table_name: self.table_name.clone(),
+            item: cert.to_item(),
+            ..Default::default()
+        };
+
+        match ddb.put_item(pi_request).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Failed to write {} to ACM cache {}: {}", cert.certificate_arn, self.table_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    async fn delete(&self, certificate_arn: &str) -> Result<(), LambdaError> {
+        let ddb = DynamoDbClient::new_with_client(aws_client(), self.region.clone());
+        let di_request = DeleteItemInput {
+            table_name: self.table_name.clone(),
+            key: key_for(certificate_arn),
+            ..Default::default()
+        };
+
+        match ddb.delete_item(di_request).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Failed to remove {} from ACM cache {}: {}", certificate_arn, self.table_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+fn key_for(certificate_arn: &str) -> HashMap<String, AttributeValue> {
+    let mut key = HashMap::new();
+    key.insert("CertificateArn".to_string(), attr_s(certificate_arn));
+    key
+}
@@ -0,0 +1,176 @@
+use {
+    crate::{
+        constants::{ENV_ACME_DIRECTORY_REWRITES, ENV_ACME_PROXY_URL, ENV_ACME_TRUST_ANCHORS_PARAMETER},
+        errors::CertificateRequestError,
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    openssl::x509::X509,
+    reqwest::{Certificate, Client, Proxy},
+    rusoto_core::Region,
+    rusoto_ssm::{GetParameterRequest, Ssm, SsmClient},
+    std::{collections::BTreeMap, env::var},
+};
+
+/// Settings for reaching the ACME server through an internal gateway, for environments (e.g. GovCloud or air-gapped
+/// VPCs) that can't connect to the CA directly. These are global settings, configured through environment
+/// variables:
+///
+/// * `AcmeDirectoryRewrites`: a JSON object mapping directory URL prefixes to their replacements, e.g.
+///   `{"https://acme-v02.api.letsencrypt.org/": "https://acme-gateway.internal/letsencrypt/"}`. The longest matching
+///   prefix is applied to the request's Directory when connecting. The gateway must serve a directory whose URLs
+///   point back at itself. Account keys and the inventory are still keyed on the original Directory, so the
+///   gateway can be introduced or removed without creating new accounts.
+/// * `AcmeTrustAnchorsParameter`: the name of an SSM parameter holding PEM-encoded CA certificates to trust (in
+///   addition to the system trust store) when connecting to the ACME server, e.g. the gateway's private CA.
+/// * `AcmeProxyUrl`: an HTTP(S) proxy to send ACME requests through.
+#[derive(Clone, Debug, Default)]
+pub struct AcmeGateway {
+    rewrites: BTreeMap<String, String>,
+    trust_anchors_parameter: Option<String>,
+    proxy_url: Option<String>,
+}
+
+impl AcmeGateway {
+    pub fn from_env() -> Result<Self, LambdaError> {
+        let rewrites = match var(ENV_ACME_DIRECTORY_REWRITES).ok().filter(|value| !value.is_empty()) {
+            None => BTreeMap::new(),
+            Some(value) => serde_json::from_str(&value).map_err(|e| {
+                CertificateRequestError::invalid_acme_gateway(format!(
+                    "{} must be a JSON object of URL prefixes: {}",
+                    ENV_ACME_DIRECTORY_REWRITES, e
+                ))
+            })?,
+        };
+
+        Ok(Self {
+            rewrites,
+            trust_anchors_parameter: var(ENV_ACME_TRUST_ANCHORS_PARAMETER).ok().filter(|name| !name.is_empty()),
+            proxy_url: var(ENV_ACME_PROXY_URL).ok().filter(|url| !url.is_empty()),
+        })
+    }
+
+    /// Returns the URL to fetch the directory from.
+    pub fn rewrite_directory(&self, directory: &str) -> String {
+        let rewrite = self
+            .rewrites
+            .iter()
+            .filter(|(prefix, _)| directory.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len());
+
+        match rewrite {
+            Some((prefix, replacement)) => {
+                let rewritten = format!("{}{}", replacement, &directory[prefix.len()..]);
+                info!("Rewrote ACME directory {} to {}", directory, rewritten);
+                rewritten
+            }
+            None => directory.to_string(),
+        }
+    }
+
+    /// Returns the HTTP client to use for the ACME server, or None if the default client will do.
+    pub async fn http_client(&self) -> Result<Option<Client>, LambdaError> {
+        if self.trust_anchors_parameter.is_none() && self.proxy_url.is_none() {
+            return Ok(None);
+        }
+
+        let mut builder = Client::builder();
+
+        if let Some(param_name) = &self.trust_anchors_parameter {
+            for cert in self.trust_anchors(param_name).await? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+
+        if let Some(proxy_url) = &self.proxy_url {
+            let proxy = Proxy::all(proxy_url).map_err(|e| {
+                CertificateRequestError::invalid_acme_gateway(format!("Invalid {}: {}", ENV_ACME_PROXY_URL, e))
+            })?;
+            builder = builder.proxy(proxy);
+        }
+
+        Ok(Some(builder.build()?))
+    }
+
+    async fn trust_anchors(&self, param_name: &str) -> Result<Vec<Certificate>, LambdaError> {
+        let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+        let gp_request = GetParameterRequest {
+            name: param_name.to_string(),
+            with_decryption: Some(true),
+        };
+
+        let pem = match ssm.get_parameter(gp_request).await {
+            Ok(response) => response.parameter.and_then(|parameter| parameter.value),
+            Err(e) => {
+                error!("Failed to read ACME trust anchors from SSM parameter {}: {:#}", param_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let pem = pem.ok_or_else(|| {
+            CertificateRequestError::unexpected_aws_response(format!(
+                "No value returned for SSM parameter {}",
+                param_name
+            ))
+        })?;
+
+        let certs = parse_trust_anchors(&pem)?;
+        info!("Trusting {} additional CA certificate(s) from {} for ACME connections", certs.len(), param_name);
+        Ok(certs)
+    }
+}
+
+/// Parse a PEM bundle of CA certificates.
+fn parse_trust_anchors(pem: &str) -> Result<Vec<Certificate>, LambdaError> {
+    let certs = match X509::stack_from_pem(pem.as_bytes()) {
+        Ok(certs) if !certs.is_empty() => certs,
+        _ => {
+            return Err(CertificateRequestError::invalid_acme_gateway(format!(
+                "{} must contain PEM-encoded certificates",
+                ENV_ACME_TRUST_ANCHORS_PARAMETER
+            )))
+        }
+    };
+
+    let mut result = Vec::with_capacity(certs.len());
+    for cert in certs {
+        result.push(Certificate::from_der(&cert.to_der()?)?);
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {super::AcmeGateway, std::collections::BTreeMap};
+
+    #[test]
+    fn test_rewrite_directory() {
+        let mut rewrites = BTreeMap::new();
+        rewrites.insert("https://acme-v02.api.letsencrypt.org/".to_string(), "https://gw.internal/le/".to_string());
+        rewrites.insert(
+            "https://acme-v02.api.letsencrypt.org/directory".to_string(),
+            "https://gw.internal/le-directory".to_string(),
+        );
+
+        let gateway = AcmeGateway {
+            rewrites,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            gateway.rewrite_directory("https://acme-v02.api.letsencrypt.org/directory"),
+            "https://gw.internal/le-directory"
+        );
+        assert_eq!(
+            gateway.rewrite_directory("https://acme-v02.api.letsencrypt.org/other"),
+            "https://gw.internal/le/other"
+        );
+        assert_eq!(
+            gateway.rewrite_directory("https://acme-staging-v02.api.letsencrypt.org/directory"),
+            "https://acme-staging-v02.api.letsencrypt.org/directory"
+        );
+    }
+}
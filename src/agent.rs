@@ -0,0 +1,364 @@
+use {
+    crate::{
+        constants::{
+            DEFAULT_AGENT_INTERVAL_MINUTES, DEFAULT_AGENT_RELOAD_SECONDS, DEFAULT_HEALTH_CHECK_PORT,
+            ENV_AGENT_CONFIG_PARAMETER, ENV_HEALTH_CHECK_PORT, ENV_LAMBDA_RUNTIME_API, ENV_RUN_MODE, RUN_MODE_AGENT,
+            RUN_MODE_LAMBDA, RUN_MODE_ONCE,
+        },
+        errors::CertificateRequestError,
+        events::Request,
+        handle_request,
+        health::{serve_health, AgentStatus},
+        report::RunBudget,
+        utils::ssm_acme_parameter_path,
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info, warn},
+    rusoto_core::Region,
+    rusoto_ssm::{GetParameterRequest, Ssm, SsmClient},
+    serde::{self, Deserialize, Serialize},
+    serde_json::Value,
+    std::{
+        env::{var, var_os},
+        fmt::{Display, Formatter, Result as FmtResult},
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::{
+        signal::{
+            ctrl_c,
+            unix::{signal, SignalKind},
+        },
+        time::{sleep, sleep_until, Instant},
+    },
+};
+
+/// The configuration for agent mode, where the function runs as a long-lived process (in a container or on EC2)
+/// instead of in Lambda, for batches that need more than Lambda's 15 minutes. This is read from an SSM parameter (by
+/// default `{AcmeParameterPath}/AgentConfig`, or the parameter named by the `AgentConfigParameter` environment
+/// variable) and reloaded whenever the parameter changes. In JSON:
+///
+///     {
+///         // How often to run the request, in minutes. The default is 720 (twice a day).
+///         "IntervalMinutes": int,
+///
+///         // How often to check the SSM parameter for changes, in seconds. The default is 60.
+///         "ReloadSeconds": int,
+///
+///         // The certificate or batch request to run, exactly as it would be passed to the Lambda function.
+///         "Request": { ... }
+///     }
+///
+/// A reloaded configuration takes effect after the current run (if any) finishes. A configuration that fails to
+/// parse is logged and ignored; the previous configuration stays in effect.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct AgentConfig {
+    #[serde(rename = "IntervalMinutes", default = "default_interval_minutes")]
+    pub interval_minutes: u64,
+
+    #[serde(rename = "ReloadSeconds", default = "default_reload_seconds")]
+    pub reload_seconds: u64,
+
+    #[serde(rename = "Request")]
+    pub request: Value,
+}
+
+const fn default_interval_minutes() -> u64 {
+    DEFAULT_AGENT_INTERVAL_MINUTES
+}
+
+const fn default_reload_seconds() -> u64 {
+    DEFAULT_AGENT_RELOAD_SECONDS
+}
+
+impl AgentConfig {
+    /// Returns the name of the SSM parameter holding the configuration.
+    fn parameter_name() -> String {
+        match var(ENV_AGENT_CONFIG_PARAMETER) {
+            Ok(name) if !name.is_empty() => name,
+            _ => format!("{}/AgentConfig", ssm_acme_parameter_path()),
+        }
+    }
+
+    /// Parse and check a configuration.
+    fn parse(value: &str) -> Result<Self, LambdaError> {
+        let config: Self = match serde_json::from_str(value) {
+            Ok(config) => config,
+            Err(e) => return Err(CertificateRequestError::invalid_agent_configuration(e.to_string())),
+        };
+
+        if config.interval_minutes == 0 {
+            return Err(CertificateRequestError::invalid_agent_configuration("IntervalMinutes must be positive"));
+        }
+
+        if config.reload_seconds == 0 {
+            return Err(CertificateRequestError::invalid_agent_configuration("ReloadSeconds must be positive"));
+        }
+
+        match serde_json::from_value(config.request.clone()) {
+            Ok(Request::Certificate(_)) | Ok(Request::Batch(_)) | Ok(Request::BatchList(_)) => Ok(config),
+            Ok(_) => Err(CertificateRequestError::invalid_agent_configuration(
+                "Request must be a certificate or batch request",
+            )),
+            Err(e) => Err(CertificateRequestError::invalid_agent_configuration(format!("Invalid Request: {}", e))),
+        }
+    }
+
+    fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval_minutes * 60)
+    }
+}
+
+/// A configuration and the SSM parameter version it was read from.
+struct LoadedConfig {
+    version: i64,
+    config: AgentConfig,
+}
+
+/// Read the configuration parameter, returning its version and value.
+async fn read_config_parameter(param_name: &str) -> Result<(i64, String), LambdaError> {
+    let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+    let gp_request = GetParameterRequest {
+        name: param_name.to_string(),
+        with_decryption: Some(true),
+    };
+
+    let parameter = match ssm.get_parameter(gp_request).await {
+        Ok(response) => response.parameter,
+        Err(e) => {
+            error!("Failed to read agent configuration from SSM parameter {}: {:#}", param_name, e);
+            return Err(Box::new(e));
+        }
+    };
+
+    match parameter {
+        Some(parameter) => Ok((parameter.version.unwrap_or(0), parameter.value.unwrap_or_default())),
+        None => Err(CertificateRequestError::unexpected_aws_response(format!(
+            "No value returned for SSM parameter {}",
+            param_name
+        ))),
+    }
+}
+
+/// How the process runs. The same binary (and container image) serves as a Lambda function, a long-running agent
+/// (e.g. an ECS service), and a one-shot job (e.g. an EKS CronJob).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RunMode {
+    /// Handle Lambda invocations.
+    Lambda,
+
+    /// Run the agent configuration's request on a schedule, serving health checks.
+    Agent,
+
+    /// Run the agent configuration's request once and exit, with a non-zero status if it failed.
+    Once,
+}
+
+impl RunMode {
+    /// Determine the run mode from the RunMode environment variable ("Lambda", "Agent", or "Once"). If that isn't
+    /// set, this is Lambda when running under the Lambda runtime API and Agent otherwise.
+    pub fn detect() -> Result<Self, LambdaError> {
+        match var(ENV_RUN_MODE) {
+            Ok(mode) if !mode.is_empty() => {
+                if mode.eq_ignore_ascii_case(RUN_MODE_LAMBDA) {
+                    Ok(Self::Lambda)
+                } else if mode.eq_ignore_ascii_case(RUN_MODE_AGENT) {
+                    Ok(Self::Agent)
+                } else if mode.eq_ignore_ascii_case(RUN_MODE_ONCE) {
+                    Ok(Self::Once)
+                } else {
+                    Err(CertificateRequestError::invalid_agent_configuration(format!("Unknown RunMode: {}", mode)))
+                }
+            }
+            _ => match var_os(ENV_LAMBDA_RUNTIME_API) {
+                Some(_) => Ok(Self::Lambda),
+                None => Ok(Self::Agent),
+            },
+        }
+    }
+}
+
+impl Display for RunMode {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Lambda => f.write_str(RUN_MODE_LAMBDA),
+            Self::Agent => f.write_str(RUN_MODE_AGENT),
+            Self::Once => f.write_str(RUN_MODE_ONCE),
+        }
+    }
+}
+
+/// Returns the port to serve health checks on, or None if they're disabled (HealthCheckPort is 0).
+fn health_check_port() -> Result<Option<u16>, LambdaError> {
+    match var(ENV_HEALTH_CHECK_PORT) {
+        Ok(port) if !port.is_empty() => match port.parse::<u16>() {
+            Ok(0) => Ok(None),
+            Ok(port) => Ok(Some(port)),
+            Err(_) => Err(CertificateRequestError::invalid_agent_configuration(format!(
+                "Invalid {}: {}",
+                ENV_HEALTH_CHECK_PORT, port
+            ))),
+        },
+        _ => Ok(Some(DEFAULT_HEALTH_CHECK_PORT)),
+    }
+}
+
+/// Load the agent configuration.
+async fn load_config(param_name: &str) -> Result<LoadedConfig, LambdaError> {
+    let (version, value) = read_config_parameter(param_name).await?;
+    let loaded = LoadedConfig {
+        version,
+        config: AgentConfig::parse(&value)?,
+    };
+    info!("Loaded agent configuration version {} from SSM parameter {}", loaded.version, param_name);
+    Ok(loaded)
+}
+
+/// Run the configured request once (for Once mode), returning an error if the run failed.
+pub async fn run_agent_once() -> Result<(), LambdaError> {
+    let loaded = load_config(&AgentConfig::parameter_name()).await?;
+    match run_once(&loaded.config).await {
+        true => Ok(()),
+        false => Err(CertificateRequestError::agent_run_failed()),
+    }
+}
+
+/// Run the configured request on a schedule until the process receives SIGTERM or SIGINT. The first run starts
+/// immediately. A signal received during a run lets that run finish before exiting.
+pub async fn run_agent() -> Result<(), LambdaError> {
+    // Start serving health checks before loading the configuration so a slow start isn't mistaken for a hang.
+    let status = Arc::new(Mutex::new(AgentStatus::default()));
+    if let Some(port) = health_check_port()? {
+        tokio::spawn(serve_health(port, status.clone()));
+    }
+
+    let param_name = AgentConfig::parameter_name();
+    let mut loaded = load_config(&param_name).await?;
+    status.lock().expect("Agent status lock poisoned").config_version = Some(loaded.version);
+
+    let mut last_run: Option<Instant> = None;
+    let mut next_run = Instant::now();
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+
+    loop {
+        let reload_interval = Duration::from_secs(loaded.config.reload_seconds);
+        let reload = sleep(reload_interval);
+        status.lock().expect("Agent status lock poisoned").heartbeat(reload_interval * 3);
+
+        tokio::select! {
+            _ = &mut shutdown => {
+                info!("Received shutdown signal; exiting agent");
+                return Ok(());
+            }
+            _ = sleep_until(next_run) => {
+                let started = Instant::now();
+                status.lock().expect("Agent status lock poisoned").run_started();
+                let succeeded = run_once(&loaded.config).await;
+                status.lock().expect("Agent status lock poisoned").run_finished(succeeded);
+                last_run = Some(started);
+                next_run = started + loaded.config.interval();
+                info!("Next run in {} minute(s)", loaded.config.interval_minutes);
+            }
+            _ = reload => {
+                if let Some(config) = reload_config(&param_name, &mut loaded).await {
+                    status.lock().expect("Agent status lock poisoned").config_version = Some(loaded.version);
+                    // Reschedule relative to the last run so a shorter interval takes effect right away.
+                    next_run = match last_run {
+                        Some(last_run) => last_run + config.interval(),
+                        None => next_run,
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Check the configuration parameter for a new version, returning the new configuration if it changed and is
+/// valid.
+async fn reload_config(param_name: &str, loaded: &mut LoadedConfig) -> Option<AgentConfig> {
+    let (version, value) = match read_config_parameter(param_name).await {
+        Ok(result) => result,
+        Err(e) => {
+            warn!("Unable to check agent configuration; keeping version {}: {:#}", loaded.version, e);
+            return None;
+        }
+    };
+
+    if version == loaded.version {
+        return None;
+    }
+
+    match AgentConfig::parse(&value) {
+        Ok(config) => {
+            info!("Reloaded agent configuration version {} (was version {})", version, loaded.version);
+            loaded.version = version;
+            loaded.config = config.clone();
+            Some(config)
+        }
+        Err(e) => {
+            error!("Ignoring agent configuration version {}; keeping version {}: {:#}", version, loaded.version, e);
+            // Don't report the same bad version on every check.
+            loaded.version = version;
+            None
+        }
+    }
+}
+
+/// Run the configured request once, logging the result. This returns false if the run failed or any certificate in
+/// it failed outright; a partial success counts as success.
+async fn run_once(config: &AgentConfig) -> bool {
+    info!("Starting agent run");
+    let budget = RunBudget::new(None);
+    let response = match handle_request(config.request.clone(), &budget).await {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Agent run failed: {:#}", e);
+            return false;
+        }
+    };
+
+    match serde_json::to_string(&response) {
+        Ok(response) => info!("Agent run finished: {}", response),
+        Err(e) => error!("Agent run finished but the response could not be serialized: {:#}", e),
+    }
+
+    response.succeeded()
+}
+
+/// Resolves when the process receives SIGTERM (as sent by ECS, Kubernetes, and systemd) or SIGINT.
+async fn shutdown_signal() {
+    let mut terminate = match signal(SignalKind::terminate()) {
+        Ok(terminate) => terminate,
+        Err(e) => {
+            error!("Unable to listen for SIGTERM: {:#}", e);
+            ctrl_c().await.ok();
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = ctrl_c() => (),
+        _ = terminate.recv() => (),
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::AgentConfig;
+
+    #[test]
+    fn test_parse_agent_config() {
+        let config = AgentConfig::parse(r#"{"Request": {"Certificates": []}, "IntervalMinutes": 60}"#).unwrap();
+        assert_eq!(config.interval_minutes, 60);
+        assert_eq!(config.reload_seconds, 60);
+
+        assert!(AgentConfig::parse(r#"{"Request": {"Certificates": []}, "IntervalMinutes": 0}"#).is_err());
+        assert!(
+            AgentConfig::parse(r#"{"Request": {"source": "aws.events", "detail-type": "x", "detail": {}}}"#).is_err()
+        );
+        assert!(AgentConfig::parse(r#"{"IntervalMinutes": 60}"#).is_err());
+    }
+}
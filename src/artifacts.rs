@@ -0,0 +1,548 @@
+use {
+    crate::{
+        errors::InvalidCertificateRequest,
+        reconcile::ObservedCertificate,
+        secrets::{resolve_secret, validate_secret_reference, DefaultSecretStore},
+        utils::{default_false, now_epoch_secs, CertificateComponents, CertificateInfo},
+    },
+    lambda_runtime::Error as LambdaError,
+    openssl::{
+        nid::Nid,
+        pkcs12::Pkcs12,
+        pkey::PKey,
+        rand::rand_bytes,
+        sha::{sha1, Sha1},
+        stack::Stack,
+        x509::X509,
+    },
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+};
+
+/// The magic number and version that start a Java KeyStore.
+const JKS_MAGIC: u32 = 0xfeed_feed;
+const JKS_VERSION: u32 = 2;
+
+/// The JKS entry tag for a private key with its certificate chain.
+const JKS_PRIVATE_KEY_ENTRY: u32 = 1;
+
+/// The DER AlgorithmIdentifier for Sun's JKS key protector (OID 1.3.6.1.4.1.42.2.17.1.1, NULL parameters).
+const JKS_KEY_PROTECTOR_ALGORITHM: &[u8] =
+    &[0x30, 0x0e, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x2a, 0x02, 0x11, 0x01, 0x01, 0x05, 0x00];
+
+/// The salt mixed into a Java KeyStore's integrity digest.
+const JKS_INTEGRITY_SALT: &[u8] = b"Mighty Aphrodite";
+
+/// A format a storage target can write the certificate in.
+///
+/// * `"Pem"`: the certificate, chain, full chain, and private key as PEM files.
+/// * `"Der"`: the certificate and the private key (in PKCS#8 form) as DER files. DER holds a single object, so the
+///   chain isn't included.
+/// * `"Pkcs12"`: a password-protected PKCS#12 bundle of the private key, certificate, and chain, e.g. for IIS.
+/// * `"Jks"`: a password-protected Java KeyStore holding the private key and certificate chain, for JVM services.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum OutputFormat {
+    Pem,
+    Der,
+    Pkcs12,
+    Jks,
+}
+
+/// A file generated for a non-PEM output format.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ArtifactKind {
+    DerCertificate,
+    DerPrivateKey,
+    Pkcs12,
+    Jks,
+}
+
+impl ArtifactKind {
+    /// The default file name (or object key suffix) of the artifact.
+    pub fn default_name(self) -> &'static str {
+        match self {
+            Self::DerCertificate => "cert.der",
+            Self::DerPrivateKey => "privkey.der",
+            Self::Pkcs12 => "certificate.pfx",
+            Self::Jks => "keystore.jks",
+        }
+    }
+
+    /// Indicates whether the artifact holds the private key, and should be protected like it.
+    pub fn is_private(self) -> bool {
+        !matches!(self, Self::DerCertificate)
+    }
+}
+
+/// A generated artifact and its contents.
+#[derive(Clone, Debug)]
+pub struct Artifact {
+    pub kind: ArtifactKind,
+    pub data: Vec<u8>,
+}
+
+/// The output formats of a storage target that writes files or objects. In JSON, these keys are part of the storage
+/// target's configuration:
+///
+///     {
+///         // The formats to write: "Pem", "Der", "Pkcs12", and/or "Jks" (see OutputFormat). At least one of
+///         // "Pem", "Der", or "Pkcs12" is required so the stored certificate can be read back. The default is
+///         // ["Pem"].
+///         "OutputFormats": [str, ...],
+///
+///         // The secret holding the password of the PKCS#12 bundle and Java KeyStore: a Secrets Manager secret
+///         // name or ARN, or a reference to another secret backend (e.g. "ssm:/certs/keystore-password"). This is
+///         // required if OutputFormats includes "Pkcs12" or "Jks". "Pkcs12Password" is accepted as an alias.
+///         "KeystorePassword": str,
+///
+///         // If true, encrypt the PKCS#12 bundle with the legacy 3DES algorithm so that Windows Server 2016
+///         // and earlier and older Java releases can read it. The default is false.
+///         "Pkcs12Legacy": bool,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ArtifactFormats {
+    #[serde(rename = "OutputFormats", default = "default_output_formats")]
+    pub formats: Vec<OutputFormat>,
+
+    #[serde(rename = "KeystorePassword", alias = "Pkcs12Password", default, skip_serializing_if = "Option::is_none")]
+    pub keystore_password: Option<String>,
+
+    #[serde(rename = "Pkcs12Legacy", default = "default_false")]
+    pub pkcs12_legacy: bool,
+}
+
+impl Default for ArtifactFormats {
+    fn default() -> Self {
+        Self {
+            formats: default_output_formats(),
+            keystore_password: None,
+            pkcs12_legacy: false,
+        }
+    }
+}
+
+fn default_output_formats() -> Vec<OutputFormat> {
+    vec![OutputFormat::Pem]
+}
+
+impl ArtifactFormats {
+    pub fn writes(&self, format: OutputFormat) -> bool {
+        self.formats.contains(&format)
+    }
+
+    pub fn writes_pem(&self) -> bool {
+        self.writes(OutputFormat::Pem)
+    }
+
+    pub fn validate(&self, allow_private_key: bool) -> Result<(), LambdaError> {
+        if self.formats.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_output_format("OutputFormats cannot be empty"));
+        }
+
+        if !self.writes_pem() && !self.writes(OutputFormat::Der) && !self.writes(OutputFormat::Pkcs12) {
+            return Err(InvalidCertificateRequest::invalid_output_format(
+                "OutputFormats must include Pem, Der, or Pkcs12 so the stored certificate can be read back",
+            ));
+        }
+
+        let keystore = self.writes(OutputFormat::Pkcs12) || self.writes(OutputFormat::Jks);
+        if keystore && !allow_private_key {
+            return Err(InvalidCertificateRequest::invalid_output_format(
+                "Pkcs12 and Jks hold the private key and cannot be used when AllowPrivateKey is false",
+            ));
+        }
+
+        match (&self.keystore_password, keystore) {
+            (Some(password), true) => validate_secret_reference(password, DefaultSecretStore::SecretsManager),
+            (None, true) => Err(InvalidCertificateRequest::invalid_output_format(
+                "KeystorePassword is required for the Pkcs12 and Jks output formats",
+            )),
+            (Some(_), false) => Err(InvalidCertificateRequest::invalid_output_format(
+                "KeystorePassword requires the Pkcs12 or Jks output format",
+            )),
+            (None, false) => Ok(()),
+        }
+    }
+
+    /// The artifact whose presence means a certificate is stored if PEM components aren't written: the PKCS#12
+    /// bundle, which holds everything, or else the DER certificate. This is None if PEM components are written.
+    pub fn primary(&self) -> Option<ArtifactKind> {
+        if self.writes_pem() {
+            None
+        } else if self.writes(OutputFormat::Pkcs12) {
+            Some(ArtifactKind::Pkcs12)
+        } else {
+            Some(ArtifactKind::DerCertificate)
+        }
+    }
+
+    /// Returns the artifacts written for the non-PEM output formats. The private key is only written in DER form if
+    /// include_key is set.
+    pub fn kinds(&self, include_key: bool) -> Vec<ArtifactKind> {
+        let mut kinds = Vec::new();
+        if self.writes(OutputFormat::Der) {
+            kinds.push(ArtifactKind::DerCertificate);
+            if include_key {
+                kinds.push(ArtifactKind::DerPrivateKey);
+            }
+        }
+
+        if self.writes(OutputFormat::Pkcs12) {
+            kinds.push(ArtifactKind::Pkcs12);
+        }
+
+        if self.writes(OutputFormat::Jks) {
+            kinds.push(ArtifactKind::Jks);
+        }
+
+        kinds
+    }
+
+    /// Resolve the keystore password.
+    async fn password(&self) -> Result<String, LambdaError> {
+        let reference = self.keystore_password.as_deref().expect("KeystorePassword should be validated here");
+        Ok(resolve_secret(reference, DefaultSecretStore::SecretsManager).await?.0)
+    }
+
+    /// Generate the artifacts for the non-PEM output formats. The alias names the key in the PKCS#12 bundle and
+    /// Java KeyStore.
+    pub async fn build(
+        &self,
+        components: &CertificateComponents,
+        alias: &str,
+        include_key: bool,
+    ) -> Result<Vec<Artifact>, LambdaError> {
+        let kinds = self.kinds(include_key);
+        let password = if kinds.iter().any(|kind| matches!(kind, ArtifactKind::Pkcs12 | ArtifactKind::Jks)) {
+            Some(self.password().await?)
+        } else {
+            None
+        };
+        let password = password.as_deref().unwrap_or_default();
+
+        let mut artifacts = Vec::with_capacity(kinds.len());
+        for kind in kinds {
+            let data = match kind {
+                ArtifactKind::DerCertificate => X509::from_pem(components.cert_pem.as_bytes())?.to_der()?,
+                ArtifactKind::DerPrivateKey => pkcs8_der(&components.pkey_pem)?,
+                ArtifactKind::Pkcs12 => build_pkcs12(components, alias, password, self.pkcs12_legacy)?,
+                ArtifactKind::Jks => build_jks(components, alias, password, now_epoch_secs() * 1000)?,
+            };
+            artifacts.push(Artifact {
+                kind,
+                data,
+            });
+        }
+
+        Ok(artifacts)
+    }
+
+    /// Observe a certificate stored without PEM components from its artifacts. The components are read back from
+    /// the PKCS#12 bundle if it was written; a DER certificate only yields the certificate's details.
+    pub async fn observe(
+        &self,
+        location: String,
+        artifacts: &[Artifact],
+    ) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let find = |kind| artifacts.iter().find(|artifact| artifact.kind == kind);
+
+        if let Some(pfx) = find(ArtifactKind::Pkcs12) {
+            let components = self.read_pkcs12(&pfx.data).await?;
+            return Ok(Some(ObservedCertificate {
+                location,
+                info: CertificateInfo::from_pem(&components.cert_pem)?,
+                components: Some(components),
+            }));
+        }
+
+        match find(ArtifactKind::DerCertificate) {
+            Some(der) => Ok(Some(ObservedCertificate {
+                location,
+                info: CertificateInfo::from_pem(&String::from_utf8(X509::from_der(&der.data)?.to_pem()?)?)?,
+                components: None,
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Read a PKCS#12 bundle written by build() back into its components.
+    async fn read_pkcs12(&self, pfx: &[u8]) -> Result<CertificateComponents, LambdaError> {
+        let parsed = Pkcs12::from_der(pfx)?.parse(&self.password().await?)?;
+        let cert_pem = String::from_utf8(parsed.cert.to_pem()?)?;
+        let mut chain_pem = Vec::new();
+        for ca in parsed.chain.into_iter().flatten() {
+            chain_pem.push(String::from_utf8(ca.to_pem()?)?);
+        }
+        let chain_pem = chain_pem.join("\n");
+
+        Ok(CertificateComponents {
+            fullchain_pem: format!("{}\n{}", cert_pem, chain_pem),
+            cert_pem,
+            chain_pem,
+            pkey_pem: String::from_utf8(parsed.pkey.private_key_to_pem_pkcs8()?)?,
+            alternate_chain: None,
+        })
+    }
+}
+
+/// Build a password-protected PKCS#12 bundle of the key, certificate, and chain in DER form. If legacy is true, 3DES
+/// is used instead of OpenSSL's defaults so that older Windows and Java releases can read it; RC2, the other legacy
+/// choice, isn't available in OpenSSL 3 without the legacy provider.
+pub fn build_pkcs12(
+    components: &CertificateComponents,
+    friendly_name: &str,
+    password: &str,
+    legacy: bool,
+) -> Result<Vec<u8>, LambdaError> {
+    let pkey = PKey::private_key_from_pem(components.pkey_pem.as_bytes())?;
+    let cert = X509::from_pem(components.cert_pem.as_bytes())?;
+    let mut chain = Stack::new()?;
+    for ca in X509::stack_from_pem(components.chain_pem.as_bytes())? {
+        chain.push(ca)?;
+    }
+
+    let mut builder = Pkcs12::builder();
+    builder.ca(chain);
+    if legacy {
+        builder.key_algorithm(Nid::PBE_WITHSHA1AND3_KEY_TRIPLEDES_CBC);
+        builder.cert_algorithm(Nid::PBE_WITHSHA1AND3_KEY_TRIPLEDES_CBC);
+    }
+
+    Ok(builder.build(password, friendly_name, &pkey, &cert)?.to_der()?)
+}
+
+/// Build a Java KeyStore (JKS) holding the private key and the certificate chain under the given alias, created at
+/// the given time in milliseconds since the Unix epoch.
+///
+/// The key is protected with Sun's JKS key protector, the only algorithm JKS supports: the PKCS#8 key is XORed with
+/// a SHA-1 keystream seeded by a random salt and the password, and followed by a SHA-1 checksum. The keystore ends
+/// with a SHA-1 digest over the password, a fixed salt, and its contents.
+pub fn build_jks(
+    components: &CertificateComponents,
+    alias: &str,
+    password: &str,
+    created_at_millis: i64,
+) -> Result<Vec<u8>, LambdaError> {
+    let password = utf16_be(password);
+    let mut chain = vec![X509::from_pem(components.cert_pem.as_bytes())?];
+    chain.extend(X509::stack_from_pem(components.chain_pem.as_bytes())?);
+
+    let mut protected = Vec::new();
+    protected.extend_from_slice(JKS_KEY_PROTECTOR_ALGORITHM);
+    der_tlv(0x04, &jks_protect_key(&pkcs8_der(&components.pkey_pem)?, &password)?, &mut protected);
+    let mut encrypted_key_info = Vec::new();
+    der_tlv(0x30, &protected, &mut encrypted_key_info);
+
+    let mut jks = Vec::new();
+    jks.extend_from_slice(&JKS_MAGIC.to_be_bytes());
+    jks.extend_from_slice(&JKS_VERSION.to_be_bytes());
+    jks.extend_from_slice(&1u32.to_be_bytes());
+
+    jks.extend_from_slice(&JKS_PRIVATE_KEY_ENTRY.to_be_bytes());
+    java_utf(&alias.to_lowercase(), &mut jks);
+    jks.extend_from_slice(&created_at_millis.to_be_bytes());
+    jks.extend_from_slice(&(encrypted_key_info.len() as u32).to_be_bytes());
+    jks.extend_from_slice(&encrypted_key_info);
+    jks.extend_from_slice(&(chain.len() as u32).to_be_bytes());
+    for cert in &chain {
+        let der = cert.to_der()?;
+        java_utf("X.509", &mut jks);
+        jks.extend_from_slice(&(der.len() as u32).to_be_bytes());
+        jks.extend_from_slice(&der);
+    }
+
+    let mut integrity = Sha1::new();
+    integrity.update(&password);
+    integrity.update(JKS_INTEGRITY_SALT);
+    integrity.update(&jks);
+    jks.extend_from_slice(&integrity.finish());
+
+    Ok(jks)
+}
+
+/// Protect a PKCS#8 key with Sun's JKS key protector, returning the salt, the encrypted key, and the checksum.
+fn jks_protect_key(plaintext: &[u8], password: &[u8]) -> Result<Vec<u8>, LambdaError> {
+    let mut salt = [0u8; 20];
+    rand_bytes(&mut salt)?;
+
+    let mut protected = salt.to_vec();
+    protected.extend(plaintext.iter().zip(jks_keystream(&salt, password, plaintext.len())).map(|(p, k)| p ^ k));
+
+    let mut checksum = Sha1::new();
+    checksum.update(password);
+    checksum.update(plaintext);
+    protected.extend_from_slice(&checksum.finish());
+    Ok(protected)
+}
+
+/// The JKS key protector's keystream: repeated SHA-1 digests of the password and the previous digest, starting
+/// with the salt.
+fn jks_keystream(salt: &[u8], password: &[u8], len: usize) -> Vec<u8> {
+    let mut stream = Vec::with_capacity(len + 20);
+    let mut digest = salt.to_vec();
+    while stream.len() < len {
+        digest = sha1(&[password, &digest].concat()).to_vec();
+        stream.extend_from_slice(&digest);
+    }
+
+    stream.truncate(len);
+    stream
+}
+
+/// The password as Java represents it for keystores: each UTF-16 code unit as two big-endian bytes.
+fn utf16_be(s: &str) -> Vec<u8> {
+    s.encode_utf16().flat_map(|unit| unit.to_be_bytes()).collect()
+}
+
+/// Append a string as Java's DataOutput.writeUTF does: a 16-bit length followed by the (modified) UTF-8 bytes.
+fn java_utf(s: &str, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+/// Append a DER tag, length, and value.
+fn der_tlv(tag: u8, value: &[u8], out: &mut Vec<u8>) {
+    out.push(tag);
+    let len = value.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes: Vec<u8> = len.to_be_bytes().iter().copied().skip_while(|b| *b == 0).collect();
+        out.push(0x80 | bytes.len() as u8);
+        out.extend_from_slice(&bytes);
+    }
+    out.extend_from_slice(value);
+}
+
+/// Convert a PEM private key to PKCS#8 DER. OpenSSL only writes unencrypted PKCS#8 as PEM, so this decodes the body of
+/// the PEM document.
+fn pkcs8_der(pkey_pem: &str) -> Result<Vec<u8>, LambdaError> {
+    let pkcs8_pem = PKey::private_key_from_pem(pkey_pem.as_bytes())?.private_key_to_pem_pkcs8()?;
+    let body: String = String::from_utf8(pkcs8_pem)?.lines().filter(|line| !line.starts_with("-----")).collect();
+    Ok(base64::decode(body)?)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{
+            build_jks, build_pkcs12, jks_keystream, pkcs8_der, utf16_be, ArtifactFormats, ArtifactKind, JKS_MAGIC,
+        },
+        crate::{keys::KeyAlgorithm, utils::CertificateComponents},
+        openssl::{
+            asn1::Asn1Time,
+            hash::MessageDigest,
+            nid::Nid,
+            pkcs12::Pkcs12,
+            pkey::PKey,
+            sha::{sha1, Sha1},
+            x509::{X509Builder, X509NameBuilder},
+        },
+        serde_json::json,
+        std::str::from_utf8,
+    };
+
+    fn components() -> CertificateComponents {
+        let pkey = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "example.com").unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(90).unwrap()).unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let cert_pem = from_utf8(&builder.build().to_pem().unwrap()).unwrap().to_string();
+
+        // The formats don't check the chain, so the self-signed certificate stands in for the intermediate.
+        CertificateComponents {
+            fullchain_pem: format!("{}{}", cert_pem, cert_pem),
+            chain_pem: cert_pem.clone(),
+            cert_pem,
+            pkey_pem: from_utf8(&pkey.private_key_to_pem_pkcs8().unwrap()).unwrap().to_string(),
+            alternate_chain: None,
+        }
+    }
+
+    #[test]
+    fn test_artifact_formats() {
+        let formats: ArtifactFormats = serde_json::from_value(json!({})).unwrap();
+        assert!(formats.writes_pem());
+        assert!(formats.validate(false).is_ok());
+        assert!(formats.kinds(true).is_empty());
+
+        let formats: ArtifactFormats = serde_json::from_value(json!({"OutputFormats": ["Der"]})).unwrap();
+        assert!(formats.validate(false).is_ok());
+        assert_eq!(formats.kinds(false), vec![ArtifactKind::DerCertificate]);
+        assert_eq!(formats.kinds(true), vec![ArtifactKind::DerCertificate, ArtifactKind::DerPrivateKey]);
+
+        let formats: ArtifactFormats = serde_json::from_value(json!({"OutputFormats": ["Jks"]})).unwrap();
+        assert!(formats.validate(true).is_err());
+
+        let formats: ArtifactFormats =
+            serde_json::from_value(json!({"OutputFormats": ["Pem", "Jks"], "Pkcs12Password": "keystore-password"}))
+                .unwrap();
+        assert_eq!(formats.keystore_password.as_deref(), Some("keystore-password"));
+        assert!(formats.validate(true).is_ok());
+        assert!(formats.validate(false).is_err());
+
+        let formats: ArtifactFormats = serde_json::from_value(json!({"OutputFormats": ["Pem", "Pkcs12"]})).unwrap();
+        assert!(formats.validate(true).is_err());
+    }
+
+    #[test]
+    fn test_pkcs12_and_der() {
+        let components = components();
+        let pfx = build_pkcs12(&components, "example.com", "changeit", false).unwrap();
+        let parsed = Pkcs12::from_der(&pfx).unwrap().parse("changeit").unwrap();
+        assert_eq!(parsed.cert.to_pem().unwrap(), components.cert_pem.as_bytes());
+
+        let der = pkcs8_der(&components.pkey_pem).unwrap();
+        let pkey = PKey::private_key_from_der(&der).unwrap();
+        assert_eq!(pkey.private_key_to_pem_pkcs8().unwrap(), components.pkey_pem.as_bytes());
+    }
+
+    #[test]
+    fn test_jks() {
+        let components = components();
+        let jks = build_jks(&components, "Example.com", "changeit", 1_600_000_000_000).unwrap();
+        let password = utf16_be("changeit");
+        assert_eq!(password, b"\0c\0h\0a\0n\0g\0e\0i\0t");
+
+        let u32_at =
+            |offset: usize| u32::from_be_bytes([jks[offset], jks[offset + 1], jks[offset + 2], jks[offset + 3]]);
+        assert_eq!(u32_at(0), JKS_MAGIC);
+        assert_eq!(u32_at(4), 2);
+        assert_eq!(u32_at(8), 1);
+        assert_eq!(u32_at(12), 1);
+
+        // The alias is stored in lowercase, as Java does.
+        assert_eq!(&jks[16..18], &[0, 11]);
+        assert_eq!(&jks[18..29], b"example.com");
+
+        // The integrity digest covers the password, the fixed salt, and everything before it.
+        let (body, digest) = jks.split_at(jks.len() - 20);
+        let mut integrity = Sha1::new();
+        integrity.update(&password);
+        integrity.update(b"Mighty Aphrodite");
+        integrity.update(body);
+        assert_eq!(digest, integrity.finish());
+
+        // Recover the key: skip the timestamp and the EncryptedPrivateKeyInfo header (a two-byte long-form length
+        // for the outer SEQUENCE, the 16-byte AlgorithmIdentifier, and the OCTET STRING header).
+        let key_len = u32_at(37) as usize;
+        let key_info = &body[41..41 + key_len];
+        let protected = &key_info[key_info.len() - (20 + pkcs8_der(&components.pkey_pem).unwrap().len() + 20)..];
+        let (salt, rest) = protected.split_at(20);
+        let (encrypted, checksum) = rest.split_at(rest.len() - 20);
+        let keystream = jks_keystream(salt, &password, encrypted.len());
+        let plaintext: Vec<u8> = encrypted.iter().zip(keystream).map(|(c, k)| c ^ k).collect();
+        assert_eq!(plaintext, pkcs8_der(&components.pkey_pem).unwrap());
+        assert_eq!(checksum, sha1(&[&password[..], &plaintext[..]].concat()));
+    }
+}
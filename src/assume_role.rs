@@ -0,0 +1,166 @@
+use {
+    crate::{
+        errors::InvalidCertificateRequest,
+        warm::{cache_role_client, cached_role_client, config_key, RoleCredentialsProvider},
+        xray::{aws_client, default_credentials, TracingDispatcher},
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    rusoto_core::{
+        credential::{AutoRefreshingProvider, AwsCredentials, ProvideAwsCredentials},
+        Client, Region,
+    },
+    rusoto_sts::{StsAssumeRoleSessionCredentialsProvider, StsClient},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::sync::Arc,
+};
+
+/// The session name used when assuming a role, visible in the target account's CloudTrail logs.
+const ROLE_SESSION_NAME: &str = "letsencrypt-certs-aws";
+
+/// An IAM role to assume before accessing a storage target, e.g. to write certificates into another account. These
+/// keys are accepted on every storage configuration. In JSON:
+///
+///     {
+///         // The ARN of the role to assume. If omitted, the function's own credentials are used.
+///         "RoleArn": str,
+///
+///         // The external id required by the role's trust policy, if any. This requires RoleArn.
+///         "ExternalId": str,
+///     }
+///
+/// For deployment targets (ApiGateway, CloudFront, LoadBalancer), the role is also used to import the certificate
+/// into ACM.
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct AssumeRole {
+    #[serde(rename = "RoleArn", default, skip_serializing_if = "Option::is_none")]
+    pub role_arn: Option<String>,
+
+    #[serde(rename = "ExternalId", default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+}
+
+impl AssumeRole {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        match &self.role_arn {
+            None if self.external_id.is_some() => {
+                Err(InvalidCertificateRequest::invalid_role_arn("ExternalId cannot be specified without RoleArn"))
+            }
+            None => Ok(()),
+            Some(role_arn) => {
+                let parts: Vec<&str> = role_arn.splitn(6, ':').collect();
+                if parts.len() != 6 || parts[0] != "arn" || parts[2] != "iam" || !parts[5].starts_with("role/") {
+                    Err(InvalidCertificateRequest::invalid_role_arn(role_arn.clone()))
+                } else {
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Indicates whether a role is assumed, i.e. the target may be in another account.
+    pub fn is_assumed(&self) -> bool {
+        self.role_arn.is_some()
+    }
+
+    /// Returns the Rusoto client to use for AWS calls: the shared client if no role is assumed, otherwise a client
+    /// whose credentials come from assuming the role. These are cached per role and external id, and refresh their
+    /// credentials automatically before they expire. Either way, calls are traced with X-Ray when it's enabled.
+    pub fn client(&self) -> Result<Client, LambdaError> {
+        match &self.role_arn {
+            None => Ok(aws_client()),
+            Some(role_arn) => Ok(self.role_session(role_arn)?.0),
+        }
+    }
+
+    /// Returns the credentials for the role (or the function's own credentials if no role is assumed), for callers
+    /// that sign requests themselves rather than going through a Rusoto client. These come from the same cached
+    /// providers as `client`.
+    pub async fn credentials(&self) -> Result<AwsCredentials, LambdaError> {
+        let result = match &self.role_arn {
+            None => default_credentials().credentials().await,
+            Some(role_arn) => self.role_session(role_arn)?.1.credentials().await,
+        };
+
+        match result {
+            Ok(credentials) => Ok(credentials),
+            Err(e) => {
+                error!("Failed to get credentials: {}", e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Returns the client and credentials for an assumed role, creating and caching them if this is the first use of
+    /// the role and external id.
+    fn role_session(&self, role_arn: &str) -> Result<(Client, RoleCredentialsProvider), LambdaError> {
+        let key = config_key(&[role_arn, self.external_id.as_deref().unwrap_or_default()]);
+        if let Some(session) = cached_role_client(&key) {
+            return Ok(session);
+        }
+
+        info!("Assuming role {} for storage", role_arn);
+        let credentials = match AutoRefreshingProvider::new(self.session_provider(role_arn)) {
+            Ok(credentials) => Arc::new(credentials),
+            Err(e) => {
+                error!("Failed to set up credentials for role {}: {}", role_arn, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let client = Client::new_with(credentials.clone(), TracingDispatcher::new()?);
+        cache_role_client(key, client.clone(), credentials.clone());
+        Ok((client, credentials))
+    }
+
+    fn session_provider(&self, role_arn: &str) -> StsAssumeRoleSessionCredentialsProvider {
+        StsAssumeRoleSessionCredentialsProvider::new(
+            StsClient::new_with_client(aws_client(), Region::default()),
+            role_arn.to_string(),
+            ROLE_SESSION_NAME.to_string(),
+            self.external_id.clone(),
+            None,
+            None,
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::AssumeRole,
+        crate::warm::{cached_role_client, config_key},
+    };
+
+    #[test]
+    fn test_validate_assume_role() {
+        let role = |role_arn: Option<&str>, external_id: Option<&str>| AssumeRole {
+            role_arn: role_arn.map(str::to_string),
+            external_id: external_id.map(str::to_string),
+        };
+
+        assert!(role(None, None).validate().is_ok());
+        assert!(role(Some("arn:aws:iam::123456789012:role/CertificateWriter"), Some("xyz")).validate().is_ok());
+        assert!(role(Some("arn:aws-us-gov:iam::123456789012:role/path/Writer"), None).validate().is_ok());
+        assert!(role(None, Some("xyz")).validate().is_err());
+        assert!(role(Some("arn:aws:iam::123456789012:user/alice"), None).validate().is_err());
+        assert!(role(Some("CertificateWriter"), None).validate().is_err());
+    }
+
+    #[test]
+    fn test_role_session_cached() {
+        let role_arn = "arn:aws:iam::123456789012:role/CachedWriter";
+        let role = AssumeRole {
+            role_arn: Some(role_arn.to_string()),
+            external_id: Some("xyz".to_string()),
+        };
+
+        assert!(cached_role_client(&config_key(&[role_arn, "xyz"])).is_none());
+        role.client().unwrap();
+        assert!(cached_role_client(&config_key(&[role_arn, "xyz"])).is_some());
+        assert!(cached_role_client(&config_key(&[role_arn, ""])).is_none());
+    }
+}
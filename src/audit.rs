@@ -0,0 +1,266 @@
+use {
+    crate::{
+        reconcile::{ActualState, ObservedCertificate},
+        storage::CertificateStorage,
+    },
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+};
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// The state of a storage target found by an audit.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum AuditStatus {
+    /// The target holds a certificate that expires after the threshold.
+    Valid,
+
+    /// The target holds a certificate that expires within the threshold.
+    Expiring,
+
+    /// The target holds a certificate that has already expired.
+    Expired,
+
+    /// The target holds no certificate for the domain names.
+    Missing,
+
+    /// The target could not be read.
+    Error,
+}
+
+/// The audit of one storage target. In JSON:
+///
+///     {
+///         // The index of the storage target in the request, and its type (e.g. "Acm", "S3").
+///         "StorageIndex": int,
+///         "Type": str,
+///
+///         // "Valid", "Expiring", "Expired", "Missing", or "Error".
+///         "Status": str,
+///
+///         // Where the certificate was found (ARN, S3 URL, parameter or secret name), its serial number, its
+///         // expiration time (seconds since the Unix epoch), and the whole days remaining until then. These are
+///         // omitted if the target holds no certificate.
+///         "Location": str,
+///         "Serial": str,
+///         "NotAfter": int,
+///         "DaysRemaining": int,
+///
+///         // For Error, why the target could not be read.
+///         "Error": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct AuditTargetResult {
+    #[serde(rename = "StorageIndex")]
+    pub storage_index: usize,
+
+    #[serde(rename = "Type")]
+    pub type_name: String,
+
+    #[serde(rename = "Status")]
+    pub status: AuditStatus,
+
+    #[serde(rename = "Location", default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<String>,
+
+    #[serde(rename = "Serial", default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+
+    #[serde(rename = "NotAfter", default, skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<i64>,
+
+    #[serde(rename = "DaysRemaining", default, skip_serializing_if = "Option::is_none")]
+    pub days_remaining: Option<i64>,
+
+    #[serde(rename = "Error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The result of an audit: the certificate held by each storage target, checked against the renewal threshold. No
+/// certificate is issued and nothing is written. In JSON:
+///
+///     {
+///         // Certificates expiring within this many days are reported as "Expiring".
+///         "ThresholdDays": int,
+///
+///         // The audit of each storage target, in the order of the request. See AuditTargetResult.
+///         "Targets": [{ ... }, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct AuditReport {
+    #[serde(rename = "ThresholdDays")]
+    pub threshold_days: i64,
+
+    #[serde(rename = "Targets")]
+    pub targets: Vec<AuditTargetResult>,
+}
+
+impl AuditReport {
+    /// Audit the observed state of the storage targets as of `now` (seconds since the Unix epoch).
+    pub fn new(storage: &[CertificateStorage], actual: &ActualState, threshold_days: i64, now: i64) -> Self {
+        let targets = storage
+            .iter()
+            .zip(actual.targets.iter())
+            .enumerate()
+            .map(|(index, (provider, observed))| {
+                audit_target(index, provider.type_name(), observed, threshold_days, now)
+            })
+            .collect();
+
+        Self {
+            threshold_days,
+            targets,
+        }
+    }
+
+    /// Indicates whether every target holds a certificate that expires after the threshold.
+    pub fn healthy(&self) -> bool {
+        self.targets.iter().all(|target| target.status == AuditStatus::Valid)
+    }
+
+    /// Returns the earliest expiration time of the certificates found.
+    pub fn earliest_not_after(&self) -> Option<i64> {
+        self.targets.iter().filter_map(|target| target.not_after).min()
+    }
+
+    /// Describe each target that needs attention, for notifications.
+    pub fn problems(&self) -> Vec<String> {
+        self.targets
+            .iter()
+            .filter_map(|target| {
+                let name = format!("Storage[{}] ({})", target.storage_index, target.type_name);
+                let location = target.location.as_deref().unwrap_or_default();
+                match target.status {
+                    AuditStatus::Valid => None,
+                    AuditStatus::Expiring => Some(format!(
+                        "{}: {} expires in {} day(s)",
+                        name,
+                        location,
+                        target.days_remaining.unwrap_or_default()
+                    )),
+                    AuditStatus::Expired => Some(format!("{}: {} has expired", name, location)),
+                    AuditStatus::Missing => Some(format!("{}: no certificate found", name)),
+                    AuditStatus::Error => {
+                        Some(format!("{}: {}", name, target.error.as_deref().unwrap_or("could not be read")))
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+fn audit_target(
+    storage_index: usize,
+    type_name: &str,
+    observed: &Result<Option<ObservedCertificate>, String>,
+    threshold_days: i64,
+    now: i64,
+) -> AuditTargetResult {
+    let mut result = AuditTargetResult {
+        storage_index,
+        type_name: type_name.to_string(),
+        status: AuditStatus::Missing,
+        location: None,
+        serial: None,
+        not_after: None,
+        days_remaining: None,
+        error: None,
+    };
+
+    match observed {
+        Err(e) => {
+            result.status = AuditStatus::Error;
+            result.error = Some(e.clone());
+        }
+        Ok(None) => (),
+        Ok(Some(observed)) => {
+            result.status = expiry_status(observed.info.not_after, threshold_days, now);
+            result.location = Some(observed.location.clone());
+            result.serial = Some(observed.info.serial.clone());
+            result.not_after = Some(observed.info.not_after);
+            result.days_remaining = Some(days_remaining(observed.info.not_after, now));
+        }
+    }
+
+    result
+}
+
+/// Classify a certificate expiring at `not_after` as of `now` (both seconds since the Unix epoch).
+pub fn expiry_status(not_after: i64, threshold_days: i64, now: i64) -> AuditStatus {
+    let remaining = not_after - now;
+    if remaining <= 0 {
+        AuditStatus::Expired
+    } else if remaining < threshold_days * SECONDS_PER_DAY {
+        AuditStatus::Expiring
+    } else {
+        AuditStatus::Valid
+    }
+}
+
+/// Returns the whole days remaining until `not_after`, or 0 if it has passed.
+pub fn days_remaining(not_after: i64, now: i64) -> i64 {
+    (not_after - now).max(0) / SECONDS_PER_DAY
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{audit_target, AuditReport, AuditStatus, SECONDS_PER_DAY},
+        crate::{reconcile::ObservedCertificate, utils::CertificateInfo},
+    };
+
+    fn observed(not_after: i64) -> Result<Option<ObservedCertificate>, String> {
+        Ok(Some(ObservedCertificate {
+            location: "s3://certs/www/cert.pem".to_string(),
+            info: CertificateInfo {
+                domain_names: vec!["www.example.com".to_string()],
+                serial: "1234".to_string(),
+                not_before: 0,
+                not_after,
+                key_algorithm: None,
+            },
+            components: None,
+        }))
+    }
+
+    #[test]
+    fn test_audit_target() {
+        let now = 1_000_000_000;
+
+        let valid = audit_target(0, "S3", &observed(now + 45 * SECONDS_PER_DAY), 30, now);
+        assert_eq!(valid.status, AuditStatus::Valid);
+        assert_eq!(valid.days_remaining, Some(45));
+
+        let expiring = audit_target(1, "S3", &observed(now + 10 * SECONDS_PER_DAY + 60), 30, now);
+        assert_eq!(expiring.status, AuditStatus::Expiring);
+        assert_eq!(expiring.days_remaining, Some(10));
+
+        let expired = audit_target(2, "S3", &observed(now - 60), 30, now);
+        assert_eq!(expired.status, AuditStatus::Expired);
+        assert_eq!(expired.days_remaining, Some(0));
+
+        let missing = audit_target(3, "Acm", &Ok(None), 30, now);
+        assert_eq!(missing.status, AuditStatus::Missing);
+        assert_eq!(missing.not_after, None);
+
+        let error = audit_target(4, "SsmParameter", &Err("AccessDenied".to_string()), 30, now);
+        assert_eq!(error.status, AuditStatus::Error);
+
+        let report = AuditReport {
+            threshold_days: 30,
+            targets: vec![valid.clone(), expiring, expired, missing, error],
+        };
+        assert!(!report.healthy());
+        assert_eq!(report.earliest_not_after(), Some(now - 60));
+        assert_eq!(report.problems().len(), 4);
+        assert_eq!(report.problems()[0], "Storage[1] (S3): s3://certs/www/cert.pem expires in 10 day(s)");
+
+        let report = AuditReport {
+            threshold_days: 30,
+            targets: vec![valid],
+        };
+        assert!(report.healthy());
+        assert!(report.problems().is_empty());
+    }
+}
@@ -0,0 +1,553 @@
+use {
+    super::{get_challenge_token_for_auth, AuthorizationHandler, CleanupDirective},
+    crate::{
+        constants::CHALLENGE_TYPE_DNS01,
+        debug_artifacts::DnsLookupEvidence,
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        faults,
+        utils::{default_true, now_epoch_secs},
+        xray::traced,
+    },
+    acme2::{Authorization, AuthorizationStatus, Challenge, ChallengeStatus},
+    async_trait::async_trait,
+    lambda_runtime::Error as LambdaError,
+    log::{debug, error, info},
+    ring::digest::{digest, SHA256},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::{
+        collections::HashSet,
+        net::IpAddr,
+        sync::Mutex as StdMutex,
+        time::{Duration, Instant},
+    },
+    tokio::{sync::Mutex, time::sleep},
+    trust_dns_resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        proto::{
+            rr::{RData, RecordType},
+            xfer::DnsRequestOptions,
+        },
+        TokioAsyncResolver,
+    },
+};
+
+/// How long to wait for a challenge record to be visible on the zone's authoritative nameservers by default.
+const DEFAULT_PROPAGATION_TIMEOUT_SECS: u64 = 120;
+
+/// How often to query the authoritative nameservers while waiting for propagation.
+const PROPAGATION_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The longest chain of CNAMEs to follow from a challenge record.
+const MAX_CNAME_CHAIN: usize = 8;
+
+/// A DNS service that can host dns-01 challenge records. Record values are passed without the quotes used in zone
+/// files.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// The name of the service, for log messages.
+    fn name(&self) -> &'static str;
+
+    /// Validate the configuration and load any credentials.
+    async fn setup(&mut self) -> Result<(), LambdaError> {
+        Ok(())
+    }
+
+    /// Returns the id of the zone to write a challenge record to. This is the zone with the longest name matching
+    /// the record name, unless the provider is configured to use a specific zone.
+    async fn find_zone(&self, record_name: &str) -> Result<String, LambdaError>;
+
+    /// Returns the current values of a TXT record, or an empty list if the record does not exist.
+    async fn get_txt_record_values(&self, zone_id: &str, record_name: &str) -> Result<Vec<String>, LambdaError>;
+
+    /// Replace the values of a TXT record, deleting the record if there are no values, and wait for the service to
+    /// report the change as applied.
+    async fn set_txt_record_values(
+        &self,
+        zone_id: &str,
+        record_name: &str,
+        values: &[String],
+    ) -> Result<(), LambdaError>;
+
+    /// Returns the authoritative nameservers for a zone.
+    async fn name_servers(&self, zone_id: &str) -> Result<Vec<String>, LambdaError>;
+}
+
+/// DNS-01 authorization using a DnsProvider. The provider's settings are flattened into the authorization's JSON;
+/// see DnsRoute53Authorization, DnsCloudflareAuthorization, and DnsGoogleAuthorization.
+///
+/// If `_acme-challenge.{domain name}` is a CNAME, the challenge record is written at the end of the CNAME chain
+/// (in whichever of the provider's zones holds it) instead, unless FollowCname is false. This lets the challenges
+/// for a production zone that can't be written to be delegated to a dedicated zone, in the style of acme-dns.
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct DnsAuthorization<P> {
+    #[serde(flatten)]
+    pub provider: P,
+
+    #[serde(rename = "PropagationTimeout", default)]
+    pub propagation_timeout: Option<u64>,
+
+    #[serde(rename = "FollowCname", default = "default_true")]
+    pub follow_cname: bool,
+
+    /// Challenge records that have been cleaned of stale values during this run. A wildcard and its base domain
+    /// ("*.example.com" and "example.com") share the same challenge record, so the lock also serializes updates to
+    /// challenge records.
+    #[serde(skip)]
+    pub challenge_records: Mutex<HashSet<String>>,
+
+    /// The last answer from each nameserver for each challenge record checked during this run, for debug artifacts.
+    #[serde(skip)]
+    pub dns_lookups: StdMutex<Vec<DnsLookupEvidence>>,
+}
+
+impl<P: DnsProvider> DnsAuthorization<P> {
+    /// Returns the name to write the challenge record for a domain name to: `_acme-challenge.{domain name}`, or the
+    /// end of the CNAME chain starting there.
+    async fn challenge_record_name(&self, domain_name: &str) -> Result<String, LambdaError> {
+        let record_name = format!("_acme-challenge.{}", domain_name);
+        if !self.follow_cname {
+            return Ok(record_name);
+        }
+
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+        let mut chain = vec![record_name.clone()];
+        let mut target = record_name.clone();
+
+        while chain.len() <= MAX_CNAME_CHAIN {
+            let fqdn = format!("{}.", target);
+            let next = match resolver.lookup(fqdn, RecordType::CNAME, DnsRequestOptions::default()).await {
+                Ok(lookup) => lookup.iter().find_map(|rdata| match rdata {
+                    RData::CNAME(name) => Some(name.to_utf8().trim_end_matches('.').to_string()),
+                    _ => None,
+                }),
+                Err(e) => {
+                    debug!("CNAME lookup of {} failed: {}", target, e);
+                    None
+                }
+            };
+
+            match next {
+                Some(next) if chain.iter().any(|name| record_names_equal(name, &next)) => {
+                    return Err(InvalidCertificateRequest::invalid_dns_provider_configuration(format!(
+                        "CNAME loop at {}: {}",
+                        record_name,
+                        chain.join(" -> ")
+                    )));
+                }
+                Some(next) => {
+                    chain.push(next.clone());
+                    target = next;
+                }
+                None => break,
+            }
+        }
+
+        if target != record_name {
+            info!("{} is delegated to {} by CNAME", record_name, target);
+        }
+
+        Ok(target)
+    }
+
+    /// Wait until every authoritative nameserver for the zone serves the challenge record. The provider may report a
+    /// change as applied once it has been distributed, but the ACME server may still query a nameserver that hasn't
+    /// picked it up yet (or a resolver that cached a negative answer).
+    async fn wait_for_public_propagation(
+        &self,
+        zone_id: &str,
+        record_name: &str,
+        expected_value: &str,
+    ) -> Result<(), LambdaError> {
+        let timeout = Duration::from_secs(self.propagation_timeout.unwrap_or(DEFAULT_PROPAGATION_TIMEOUT_SECS));
+        if timeout.as_secs() == 0 {
+            return Ok(());
+        }
+
+        let name_servers = self.provider.name_servers(zone_id).await?;
+        if name_servers.is_empty() {
+            info!("{} zone {} has no nameservers; skipping propagation check", self.provider.name(), zone_id);
+            return Ok(());
+        }
+
+        // Resolve the nameserver addresses using the system resolver, then query them directly with caching
+        // disabled.
+        let system_resolver = TokioAsyncResolver::tokio_from_system_conf()?;
+        let mut ns_ips: Vec<IpAddr> = Vec::new();
+        for name_server in &name_servers {
+            match system_resolver.lookup_ip(name_server.as_str()).await {
+                Ok(lookup) => ns_ips.extend(lookup.iter()),
+                Err(e) => error!("Failed to resolve nameserver {}: {}", name_server, e),
+            }
+        }
+
+        if ns_ips.is_empty() {
+            error!("Unable to resolve any nameservers for {} zone {}", self.provider.name(), zone_id);
+            return Err(CertificateRequestError::dns_provider_failed(format!(
+                "Unable to resolve any nameservers for {} zone {}",
+                self.provider.name(),
+                zone_id
+            )));
+        }
+
+        let mut opts = ResolverOpts::default();
+        opts.cache_size = 0;
+
+        let start = Instant::now();
+
+        info!("Waiting for {} to be served by nameservers {}", record_name, name_servers.join(", "));
+
+        loop {
+            let mut all_visible = true;
+
+            // Query each nameserver individually; a resolver spanning all of them would be satisfied by the first
+            // one to answer.
+            for ns_ip in &ns_ips {
+                let config = ResolverConfig::from_parts(
+                    None,
+                    vec![],
+                    NameServerConfigGroup::from_ips_clear(&[*ns_ip], 53, true),
+                );
+                let resolver = TokioAsyncResolver::tokio(config, opts)?;
+
+                let (values, lookup_error) = match resolver.txt_lookup(record_name).await {
+                    Ok(lookup) => {
+                        let values: Vec<String> = lookup
+                            .iter()
+                            .map(|txt| {
+                                let value: Vec<u8> =
+                                    txt.txt_data().iter().flat_map(|part| part.iter().copied()).collect();
+                                String::from_utf8_lossy(&value).to_string()
+                            })
+                            .collect();
+                        (values, None)
+                    }
+                    Err(e) => {
+                        debug!("TXT lookup of {} on {} failed: {}", record_name, ns_ip, e);
+                        (vec![], Some(e.to_string()))
+                    }
+                };
+
+                let visible = values.iter().any(|value| value == expected_value);
+                self.record_lookup(DnsLookupEvidence {
+                    record_name: record_name.to_string(),
+                    nameserver: ns_ip.to_string(),
+                    expected: expected_value.to_string(),
+                    values,
+                    error: lookup_error,
+                    checked_at: now_epoch_secs(),
+                });
+
+                if !visible {
+                    debug!("{} not yet visible on {}", record_name, ns_ip);
+                    all_visible = false;
+                    break;
+                }
+            }
+
+            if all_visible {
+                info!("{} is visible on all nameservers", record_name);
+                return Ok(());
+            }
+
+            if start.elapsed() >= timeout {
+                error!("Timed out waiting for {} to propagate to all nameservers", record_name);
+                return Err(CertificateRequestError::dns_propagation_timeout(record_name));
+            }
+
+            sleep(PROPAGATION_CHECK_INTERVAL).await;
+        }
+    }
+
+    /// Remember a nameserver's answer, replacing any earlier answer from it for the same record.
+    fn record_lookup(&self, evidence: DnsLookupEvidence) {
+        let mut dns_lookups = self.dns_lookups.lock().expect("DNS lookup lock poisoned");
+        dns_lookups
+            .retain(|other| other.record_name != evidence.record_name || other.nameserver != evidence.nameserver);
+        dns_lookups.push(evidence);
+    }
+}
+
+#[async_trait]
+impl<P: DnsProvider> AuthorizationHandler for DnsAuthorization<P> {
+    async fn setup(&mut self) -> Result<(), LambdaError> {
+        self.provider.setup().await
+    }
+
+    async fn auth(
+        &self,
+        auth: Authorization,
+    ) -> Result<(Authorization, Challenge, Vec<CleanupDirective>), LambdaError> {
+        debug!("Handling authorization: {:?}", auth);
+        let domain_name: &str = &auth.identifier.value;
+        let (challenge, _token) = get_challenge_token_for_auth(&auth, CHALLENGE_TYPE_DNS01)?;
+
+        let key_auth = match challenge.key_authorization() {
+            Ok(maybe_key_auth) => match maybe_key_auth {
+                Some(ka) => ka,
+                None => {
+                    error!("No {} key authorization found for {}", CHALLENGE_TYPE_DNS01, domain_name);
+                    return Err(CertificateRequestError::token_not_available(CHALLENGE_TYPE_DNS01, domain_name));
+                }
+            },
+            Err(e) => {
+                error!("Failed to get ACME key authorization for {}: {}", domain_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        // Find the best zone for the challenge record.
+        let record_name = self.challenge_record_name(domain_name).await?;
+        let zone_id = self.provider.find_zone(&record_name).await?;
+
+        // DNS challenges need to SHA256-hash the key again and base64 encode the result without padding.
+        let record_value =
+            base64::encode_config(digest(&SHA256, key_auth.as_bytes()).as_ref(), base64::URL_SAFE_NO_PAD);
+        info!(
+            "Writing key authorization for {} to {} zone {}: {}",
+            record_name,
+            self.provider.name(),
+            zone_id,
+            record_value
+        );
+
+        // Serialize updates to challenge records; see challenge_records.
+        let mut challenge_records = self.challenge_records.lock().await;
+
+        // Replace any values left over from a previous run (but not values written by this run for a wildcard
+        // sharing the same record).
+        let mut record_values = if challenge_records.insert(record_name.clone()) {
+            vec![]
+        } else {
+            self.provider.get_txt_record_values(&zone_id, &record_name).await?
+        };
+
+        if !record_values.contains(&record_value) {
+            record_values.push(record_value.clone());
+        }
+
+        if let Err(e) = self.provider.set_txt_record_values(&zone_id, &record_name, &record_values).await {
+            error!(
+                "Failed to write key authorization for {} to {} zone {}: {}",
+                domain_name,
+                self.provider.name(),
+                zone_id,
+                e
+            );
+            return Err(e);
+        }
+        drop(challenge_records);
+
+        faults::dns_propagation_delay().await;
+        traced(
+            format!("DNS propagation {}", record_name),
+            self.wait_for_public_propagation(&zone_id, &record_name, &record_value),
+        )
+        .await?;
+
+        let cleanup = vec![CleanupDirective::DeleteTxtRecord {
+            zone_id,
+            record_name,
+            record_value,
+        }];
+
+        info!("Informing ACME server that dns-01 validation is ready for {}", domain_name);
+        // Signal to the ACME server that we're ready for verification.
+        match challenge.validate().await {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to send validation request to ACME server for {}: {}", domain_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        Ok((auth, challenge, cleanup))
+    }
+
+    async fn check(
+        &self,
+        auth: Authorization,
+        challenge: Challenge,
+    ) -> Result<(Authorization, Challenge, bool), LambdaError> {
+        let domain_name: String = auth.identifier.value.clone();
+        let (challenge_result, auth_result) = tokio::join!(challenge.poll(), auth.poll(),);
+
+        let challenge: Challenge = match challenge_result {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to update challenge status for {}: {}", domain_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let auth: Authorization = match auth_result {
+            Ok(a) => a,
+            Err(e) => {
+                error!("Failed to update authorization status for {}: {}", domain_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let challenge_valid: bool = match challenge.status {
+            ChallengeStatus::Invalid => {
+                error!("Challenge for {} is invalid", domain_name);
+                return Err(CertificateRequestError::challenge_failed(domain_name));
+            }
+            ChallengeStatus::Valid => true,
+            _ => false,
+        };
+
+        let auth_valid: bool = match auth.status {
+            AuthorizationStatus::Invalid => {
+                error!("Authorization for {} is invalid", domain_name);
+                return Err(CertificateRequestError::authorization_failed(domain_name));
+            }
+            AuthorizationStatus::Valid => true,
+            _ => false,
+        };
+
+        Ok((auth, challenge, challenge_valid && auth_valid))
+    }
+
+    fn dns_lookups(&self) -> Vec<DnsLookupEvidence> {
+        self.dns_lookups.lock().expect("DNS lookup lock poisoned").clone()
+    }
+
+    async fn cleanup(&self, directives: Vec<CleanupDirective>) -> Result<(), LambdaError> {
+        for directive in directives {
+            match directive {
+                CleanupDirective::DeleteTxtRecord {
+                    zone_id,
+                    record_name,
+                    record_value,
+                } => {
+                    let _challenge_records = self.challenge_records.lock().await;
+
+                    // Remove our value from the challenge record, leaving values for other authorizations sharing
+                    // the record in place. The record is deleted entirely once no values remain.
+                    let current_values = match self.provider.get_txt_record_values(&zone_id, &record_name).await {
+                        Ok(values) => values,
+                        Err(e) => {
+                            error!("Failed to read {} for cleanup: {}", record_name, e);
+                            vec![record_value.clone()]
+                        }
+                    };
+
+                    if !current_values.contains(&record_value) {
+                        info!("Record {} no longer contains {}; nothing to clean up", record_name, record_value);
+                        continue;
+                    }
+
+                    let remaining_values: Vec<String> =
+                        current_values.into_iter().filter(|value| *value != record_value).collect();
+
+                    info!("Removing {} record {}", self.provider.name(), record_name);
+                    if let Err(e) = self.provider.set_txt_record_values(&zone_id, &record_name, &remaining_values).await
+                    {
+                        error!(
+                            "Failed to delete key authorization for {} in {} zone {}: {}",
+                            record_name,
+                            self.provider.name(),
+                            zone_id,
+                            e
+                        );
+                    }
+                }
+                _ => {
+                    error!("Unsupported cleanup directive: {:?}", directive);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare two DNS record names, ignoring case and any trailing dot.
+pub fn record_names_equal(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.').eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+pub fn domain_name_matches_zone(domain_name: &str, zone: &str) -> bool {
+    let domain_name_with_dot = if domain_name.ends_with('.') {
+        domain_name.to_string()
+    } else {
+        format!("{}.", domain_name)
+    };
+
+    let zone_with_dot = if zone.ends_with('.') {
+        zone.to_string()
+    } else {
+        format!("{}.", zone)
+    };
+
+    // The zone must match whole labels, so www.badexample.com isn't in example.com.
+    domain_name_with_dot == zone_with_dot || domain_name_with_dot.ends_with(&format!(".{}", zone_with_dot))
+}
+
+/// Returns the zone with the longest name matching a domain name, from a list of (zone id, zone name) pairs.
+pub fn best_matching_zone<I: IntoIterator<Item = (String, String)>>(domain_name: &str, zones: I) -> Option<String> {
+    zones
+        .into_iter()
+        .filter(|(_, zone_name)| domain_name_matches_zone(domain_name, zone_name))
+        .max_by_key(|(_, zone_name)| zone_name.trim_end_matches('.').len())
+        .map(|(zone_id, _)| zone_id)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{best_matching_zone, domain_name_matches_zone, record_names_equal},
+        crate::{auth::CertificateAuthorization, secrets::SecretValue},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_zone_matching() {
+        assert!(domain_name_matches_zone("www.example.com", "example.com."));
+        assert!(domain_name_matches_zone("example.com", "example.com"));
+        assert!(!domain_name_matches_zone("www.badexample.com", "example.com"));
+        assert!(record_names_equal("_acme-challenge.Example.com.", "_acme-challenge.example.com"));
+
+        let zones = vec![
+            ("Z1".to_string(), "example.com.".to_string()),
+            ("Z2".to_string(), "dev.example.com.".to_string()),
+            ("Z3".to_string(), "example.org.".to_string()),
+        ];
+        assert_eq!(best_matching_zone("www.dev.example.com", zones.clone()), Some("Z2".to_string()));
+        assert_eq!(best_matching_zone("www.example.com", zones.clone()), Some("Z1".to_string()));
+        assert_eq!(best_matching_zone("www.example.net", zones), None);
+    }
+
+    #[test]
+    fn test_secret_value_debug() {
+        assert_eq!(format!("{:?}", SecretValue("token".to_string())), "SecretValue(..)");
+    }
+
+    #[test]
+    fn test_follow_cname() {
+        let auth: CertificateAuthorization =
+            serde_json::from_value(json!({"Type": "DnsRoute53", "HostedZoneId": "Z123"})).unwrap();
+        match auth {
+            CertificateAuthorization::DnsRoute53(auth) => {
+                assert!(auth.follow_cname);
+                assert_eq!(auth.provider.hosted_zone_id.as_deref(), Some("Z123"));
+            }
+            _ => panic!("Expected a DnsRoute53 authorization"),
+        }
+
+        let auth: CertificateAuthorization = serde_json::from_value(json!({
+            "Type": "DnsCloudflare",
+            "ApiTokenSecretId": "cloudflare-token",
+            "FollowCname": false,
+        }))
+        .unwrap();
+        match auth {
+            CertificateAuthorization::DnsCloudflare(auth) => {
+                assert!(!auth.follow_cname);
+                assert_eq!(auth.provider.api_token_secret_id, "cloudflare-token");
+            }
+            _ => panic!("Expected a DnsCloudflare authorization"),
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use {
+    super::dns::{best_matching_zone, domain_name_matches_zone, record_names_equal, DnsAuthorization, DnsProvider},
+    crate::{
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        secrets::{read_secret, SecretValue},
+        warm::{cache_hosted_zone, cached_hosted_zone, config_key},
+    },
+    async_trait::async_trait,
+    lambda_runtime::Error as LambdaError,
+    log::{debug, error, info},
+    reqwest::{header::CONTENT_TYPE, Client, Method},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::{json, Value},
+};
+
+/// The base URL of the Cloudflare API.
+const CLOUDFLARE_API_URL: &str = "https://api.cloudflare.com/client/v4";
+
+/// The TTL of challenge records, in seconds. This is the lowest TTL Cloudflare allows on all plans.
+const CHALLENGE_RECORD_TTL: u64 = 60;
+
+/// Configuration for DNS-01 authorization using Cloudflare DNS. In JSON:
+///
+///     {
+///         // The type of authorization to perform. This must be "DnsCloudflare".
+///         "Type": "DnsCloudflare",
+///
+///         // The name or ARN of the Secrets Manager secret holding a Cloudflare API token with the Zone:Read
+///         // and DNS:Edit permissions. The secret value is the token itself. This may also be a secret reference
+///         // to another backend, e.g. "vault:secret/data/cloudflare#token"; see SecretBackend. This is required.
+///         "ApiTokenSecretId": str,
+///
+///         // The Cloudflare zone id to write challenge records to. If unspecified, the zone with the longest
+///         // name matching each domain name is used.
+///         "ZoneId": str,
+///
+///         // Wait up to this many seconds for the challenge record to be served by every Cloudflare
+///         // nameserver for the zone before asking the ACME server to validate it. Set to 0 to skip this
+///         // check. This defaults to 120.
+///         "PropagationTimeout": int,
+///
+///         // Whether to write the challenge record at the end of the CNAME chain if
+///         // _acme-challenge.{domain name} is a CNAME; see DnsAuthorization. This defaults to true.
+///         "FollowCname": bool,
+///     }
+pub type DnsCloudflareAuthorization = DnsAuthorization<CloudflareDns>;
+
+/// The Cloudflare settings of a DnsCloudflareAuthorization.
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct CloudflareDns {
+    #[serde(rename = "ApiTokenSecretId")]
+    pub api_token_secret_id: String,
+
+    #[serde(rename = "ZoneId", default)]
+    pub zone_id: Option<String>,
+
+    /// The API token, read from its secret backend during setup.
+    #[serde(skip)]
+    pub api_token: Option<SecretValue>,
+}
+
+impl CloudflareDns {
+    /// Make a Cloudflare API request, returning the response envelope if it succeeded.
+    async fn request(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value, LambdaError> {
+        let api_token = match &self.api_token {
+            Some(api_token) => api_token,
+            None => {
+                return Err(InvalidCertificateRequest::invalid_dns_provider_configuration(
+                    "Cloudflare API token has not been loaded",
+                ))
+            }
+        };
+
+        let mut request =
+            Client::new().request(method.clone(), format!("{}{}", CLOUDFLARE_API_URL, path)).bearer_auth(&api_token.0);
+        if let Some(body) = body {
+            request = request.header(CONTENT_TYPE, "application/json").body(body.to_string());
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let response: Value = serde_json::from_str(&response.text().await?).unwrap_or(Value::Null);
+
+        if !status.is_success() || response["success"].as_bool() != Some(true) {
+            let messages: Vec<&str> = response["errors"]
+                .as_array()
+                .map(|errors| errors.iter().filter_map(|e| e["message"].as_str()).collect())
+                .unwrap_or_default();
+            error!("Cloudflare API request {} {} failed: HTTP {}: {}", method, path, status, messages.join("; "));
+            return Err(CertificateRequestError::dns_provider_failed(format!(
+                "Cloudflare {} {}: HTTP {}: {}",
+                method,
+                path,
+                status,
+                messages.join("; ")
+            )));
+        }
+
+        Ok(response)
+    }
+
+    /// Returns the ids and contents of the TXT records with the given name. Cloudflare stores each value as a
+    /// separate record.
+    async fn get_txt_records(&self, zone_id: &str, record_name: &str) -> Result<Vec<(String, String)>, LambdaError> {
+        let path = format!("/zones/{}/dns_records?type=TXT&name={}&per_page=100", zone_id, record_name);
+        let response = self.request(Method::GET, &path, None).await?;
+
+        Ok(response["result"]
+            .as_array()
+            .map(|records| {
+                records
+                    .iter()
+                    .filter(|record| record_names_equal(record["name"].as_str().unwrap_or_default(), record_name))
+                    .filter_map(|record| match (record["id"].as_str(), record["content"].as_str()) {
+                        (Some(id), Some(content)) => Some((id.to_string(), content.trim_matches('"').to_string())),
+                        _ => None,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for CloudflareDns {
+    fn name(&self) -> &'static str {
+        "Cloudflare"
+    }
+
+    async fn setup(&mut self) -> Result<(), LambdaError> {
+        if self.api_token_secret_id.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_dns_provider_configuration(
+                "ApiTokenSecretId cannot be empty",
+            ));
+        }
+
+        if self.api_token.is_none() {
+            self.api_token = Some(read_secret(&self.api_token_secret_id).await?);
+        }
+
+        Ok(())
+    }
+
+    async fn find_zone(&self, record_name: &str) -> Result<String, LambdaError> {
+        if let Some(zone_id) = &self.zone_id {
+            let response = self.request(Method::GET, &format!("/zones/{}", zone_id), None).await?;
+            let zone_name = response["result"]["name"].as_str().unwrap_or_default();
+            if !domain_name_matches_zone(record_name, zone_name) {
+                error!("Cloudflare zone {} is {}, which does not contain {}", zone_id, zone_name, record_name);
+                return Err(InvalidCertificateRequest::invalid_dns_provider_configuration(format!(
+                    "Cloudflare zone {} is {}, which does not contain {}",
+                    zone_id, zone_name, record_name
+                )));
+            }
+
+            return Ok(zone_id.clone());
+        }
+
+        // Reuse the zone discovered by a previous (warm) invocation if possible.
+        let cache_key = config_key(&[self.name(), self.api_token_secret_id.as_str(), record_name]);
+        if let Some(zone_id) = cached_hosted_zone(&cache_key) {
+            debug!("Using cached Cloudflare zone {} for {}", zone_id, record_name);
+            return Ok(zone_id);
+        }
+
+        let mut zones = Vec::new();
+        let mut page = 1;
+
+        loop {
+            let response = self.request(Method::GET, &format!("/zones?per_page=50&page={}", page), None).await?;
+            for zone in response["result"].as_array().into_iter().flatten() {
+                if let (Some(id), Some(name)) = (zone["id"].as_str(), zone["name"].as_str()) {
+                    zones.push((id.to_string(), name.to_string()));
+                }
+            }
+
+            if page >= response["result_info"]["total_pages"].as_u64().unwrap_or(1) {
+                break;
+            }
+
+            page += 1;
+        }
+
+        let zone_id = best_matching_zone(record_name, zones)
+            .ok_or_else(|| InvalidCertificateRequest::no_matching_dns_zones(self.name(), record_name))?;
+        cache_hosted_zone(cache_key, zone_id.clone());
+        Ok(zone_id)
+    }
+
+    async fn get_txt_record_values(&self, zone_id: &str, record_name: &str) -> Result<Vec<String>, LambdaError> {
+        Ok(self.get_txt_records(zone_id, record_name).await?.into_iter().map(|(_, content)| content).collect())
+    }
+
+    async fn set_txt_record_values(
+        &self,
+        zone_id: &str,
+        record_name: &str,
+        values: &[String],
+    ) -> Result<(), LambdaError> {
+        let existing = self.get_txt_records(zone_id, record_name).await?;
+
+        for value in values {
+            if existing.iter().all(|(_, content)| content != value) {
+                let record = json!({
+                    "type": "TXT",
+                    "name": record_name,
+                    "content": value,
+                    "ttl": CHALLENGE_RECORD_TTL,
+                });
+                self.request(Method::POST, &format!("/zones/{}/dns_records", zone_id), Some(record)).await?;
+            }
+        }
+
+        for (record_id, content) in existing {
+            if !values.contains(&content) {
+                info!("Deleting Cloudflare record {} ({}) from {}", record_id, record_name, zone_id);
+                self.request(Method::DELETE, &format!("/zones/{}/dns_records/{}", zone_id, record_id), None).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn name_servers(&self, zone_id: &str) -> Result<Vec<String>, LambdaError> {
+        let response = self.request(Method::GET, &format!("/zones/{}", zone_id), None).await?;
+        Ok(response["result"]["name_servers"]
+            .as_array()
+            .map(|name_servers| name_servers.iter().filter_map(|ns| ns.as_str().map(str::to_string)).collect())
+            .unwrap_or_default())
+    }
+}
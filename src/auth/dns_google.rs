@@ -0,0 +1,392 @@
+use {
+    super::dns::{best_matching_zone, domain_name_matches_zone, DnsAuthorization, DnsProvider},
+    crate::{
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        secrets::{read_secret, SecretValue},
+        utils::now_epoch_secs,
+        warm::{cache_hosted_zone, cached_hosted_zone, config_key},
+    },
+    async_trait::async_trait,
+    lambda_runtime::Error as LambdaError,
+    log::{debug, error, info},
+    openssl::{hash::MessageDigest, pkey::PKey, sign::Signer},
+    reqwest::{header::CONTENT_TYPE, Client, Method},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::{json, Value},
+    std::{
+        fmt::{Debug, Error as FormatError, Formatter},
+        time::Duration,
+    },
+    tokio::time::sleep,
+};
+
+/// The base URL of the Cloud DNS API.
+const GOOGLE_DNS_API_URL: &str = "https://dns.googleapis.com/dns/v1";
+
+/// The OAuth scope needed to read zones and change records.
+const GOOGLE_DNS_SCOPE: &str = "https://www.googleapis.com/auth/ndev.clouddns.readwrite";
+
+/// The default OAuth token endpoint, if the service account key doesn't specify one.
+const GOOGLE_TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+
+/// The TTL of challenge records, in seconds.
+const CHALLENGE_RECORD_TTL: u64 = 60;
+
+/// Configuration for DNS-01 authorization using Google Cloud DNS. In JSON:
+///
+///     {
+///         // The type of authorization to perform. This must be "DnsGoogle".
+///         "Type": "DnsGoogle",
+///
+///         // The name or ARN of the Secrets Manager secret holding a JSON key for a Google Cloud service
+///         // account with the DNS Administrator role (or the dns.managedZones.list, dns.resourceRecordSets.*,
+///         // and dns.changes.* permissions). This may also be a secret reference to another backend; see
+///         // SecretBackend. This is required.
+///         "CredentialsSecretId": str,
+///
+///         // The Google Cloud project holding the managed zones. This defaults to the project of the
+///         // service account.
+///         "Project": str,
+///
+///         // The name (not the DNS name) of the managed zone to write challenge records to. If unspecified,
+///         // the public managed zone with the longest DNS name matching each domain name is used.
+///         "ManagedZone": str,
+///
+///         // After Cloud DNS reports a change as done, wait up to this many seconds for the challenge record
+///         // to be served by every authoritative nameserver for the zone before asking the ACME server to
+///         // validate it. Set to 0 to skip this check. This defaults to 120.
+///         "PropagationTimeout": int,
+///
+///         // Whether to write the challenge record at the end of the CNAME chain if
+///         // _acme-challenge.{domain name} is a CNAME; see DnsAuthorization. This defaults to true.
+///         "FollowCname": bool,
+///     }
+pub type DnsGoogleAuthorization = DnsAuthorization<GoogleCloudDns>;
+
+/// The Cloud DNS settings of a DnsGoogleAuthorization.
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct GoogleCloudDns {
+    #[serde(rename = "CredentialsSecretId")]
+    pub credentials_secret_id: String,
+
+    #[serde(rename = "Project", default)]
+    pub project: Option<String>,
+
+    #[serde(rename = "ManagedZone", default)]
+    pub managed_zone: Option<String>,
+
+    /// The project the zones are in, and an access token for the service account, obtained during setup.
+    #[serde(skip)]
+    pub session: Option<(String, SecretValue)>,
+}
+
+/// The fields used from a Google Cloud service account key.
+#[derive(Deserialize)]
+pub struct ServiceAccountKey {
+    pub client_email: String,
+    pub private_key: String,
+
+    #[serde(default)]
+    pub project_id: Option<String>,
+
+    #[serde(default)]
+    pub token_uri: Option<String>,
+}
+
+impl Debug for ServiceAccountKey {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
+        f.debug_struct("ServiceAccountKey").field("client_email", &self.client_email).finish()
+    }
+}
+
+impl ServiceAccountKey {
+    fn token_uri(&self) -> &str {
+        self.token_uri.as_deref().unwrap_or(GOOGLE_TOKEN_URI)
+    }
+
+    /// Create the signed JWT to exchange for an access token (RFC 7523).
+    pub fn assertion(&self, now: i64) -> Result<String, LambdaError> {
+        let encode = |data: &[u8]| base64::encode_config(data, base64::URL_SAFE_NO_PAD);
+        let header = json!({"alg": "RS256", "typ": "JWT"});
+        let claims = json!({
+            "iss": self.client_email,
+            "scope": GOOGLE_DNS_SCOPE,
+            "aud": self.token_uri(),
+            "iat": now,
+            "exp": now + 3600,
+        });
+        let signing_input =
+            format!("{}.{}", encode(header.to_string().as_bytes()), encode(claims.to_string().as_bytes()));
+
+        let pkey = PKey::private_key_from_pem(self.private_key.as_bytes())?;
+        let mut signer = Signer::new(MessageDigest::sha256(), &pkey)?;
+        signer.update(signing_input.as_bytes())?;
+        Ok(format!("{}.{}", signing_input, encode(&signer.sign_to_vec()?)))
+    }
+}
+
+impl GoogleCloudDns {
+    /// Exchange the service account key for an access token.
+    async fn access_token(&self, key: &ServiceAccountKey) -> Result<SecretValue, LambdaError> {
+        let assertion = key.assertion(now_epoch_secs())?;
+        let form = [("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"), ("assertion", assertion.as_str())];
+        let response = Client::new().post(key.token_uri()).form(&form).send().await?;
+        let status = response.status();
+        let response: Value = serde_json::from_str(&response.text().await?).unwrap_or(Value::Null);
+
+        match response["access_token"].as_str() {
+            Some(access_token) if status.is_success() => Ok(SecretValue(access_token.to_string())),
+            _ => {
+                let message = response["error_description"].as_str().or(response["error"].as_str()).unwrap_or_default();
+                error!("Failed to get an access token for {}: HTTP {}: {}", key.client_email, status, message);
+                Err(CertificateRequestError::dns_provider_failed(format!(
+                    "Failed to get a Google Cloud access token for {}: HTTP {}: {}",
+                    key.client_email, status, message
+                )))
+            }
+        }
+    }
+
+    /// Make a Cloud DNS API request for a path relative to the project, returning the response if it succeeded.
+    async fn request(&self, method: Method, path: &str, body: Option<Value>) -> Result<Value, LambdaError> {
+        let (project, access_token) = match &self.session {
+            Some(session) => session,
+            None => {
+                return Err(InvalidCertificateRequest::invalid_dns_provider_configuration(
+                    "Google Cloud credentials have not been loaded",
+                ))
+            }
+        };
+
+        let url = format!("{}/projects/{}{}", GOOGLE_DNS_API_URL, project, path);
+        let mut request = Client::new().request(method.clone(), url).bearer_auth(&access_token.0);
+        if let Some(body) = body {
+            request = request.header(CONTENT_TYPE, "application/json").body(body.to_string());
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let response: Value = serde_json::from_str(&response.text().await?).unwrap_or(Value::Null);
+
+        if !status.is_success() {
+            let message = response["error"]["message"].as_str().unwrap_or_default();
+            error!("Cloud DNS request {} {} failed: HTTP {}: {}", method, path, status, message);
+            return Err(CertificateRequestError::dns_provider_failed(format!(
+                "Cloud DNS {} {}: HTTP {}: {}",
+                method, path, status, message
+            )));
+        }
+
+        Ok(response)
+    }
+
+    /// Returns the TXT record set with the given name, if it exists.
+    async fn get_txt_record_set(&self, zone_id: &str, record_name: &str) -> Result<Option<Value>, LambdaError> {
+        let path = format!("/managedZones/{}/rrsets?name={}&type=TXT", zone_id, fully_qualified(record_name));
+        let response = self.request(Method::GET, &path, None).await?;
+        Ok(response["rrsets"].as_array().and_then(|rrsets| rrsets.first()).cloned())
+    }
+}
+
+#[async_trait]
+impl DnsProvider for GoogleCloudDns {
+    fn name(&self) -> &'static str {
+        "Cloud DNS"
+    }
+
+    async fn setup(&mut self) -> Result<(), LambdaError> {
+        if self.credentials_secret_id.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_dns_provider_configuration(
+                "CredentialsSecretId cannot be empty",
+            ));
+        }
+
+        if self.session.is_some() {
+            return Ok(());
+        }
+
+        let secret = read_secret(&self.credentials_secret_id).await?;
+        let key: ServiceAccountKey = serde_json::from_str(&secret.0).map_err(|e| {
+            InvalidCertificateRequest::invalid_dns_provider_configuration(format!(
+                "Secret {} is not a service account key: {}",
+                self.credentials_secret_id, e
+            ))
+        })?;
+
+        let project = match self.project.clone().or_else(|| key.project_id.clone()) {
+            Some(project) => project,
+            None => {
+                return Err(InvalidCertificateRequest::invalid_dns_provider_configuration(
+                    "Project must be specified when the service account key has no project_id",
+                ))
+            }
+        };
+
+        self.session = Some((project, self.access_token(&key).await?));
+        Ok(())
+    }
+
+    async fn find_zone(&self, record_name: &str) -> Result<String, LambdaError> {
+        if let Some(managed_zone) = &self.managed_zone {
+            let response = self.request(Method::GET, &format!("/managedZones/{}", managed_zone), None).await?;
+            let dns_name = response["dnsName"].as_str().unwrap_or_default();
+            if !domain_name_matches_zone(record_name, dns_name) {
+                error!("Managed zone {} is {}, which does not contain {}", managed_zone, dns_name, record_name);
+                return Err(InvalidCertificateRequest::invalid_dns_provider_configuration(format!(
+                    "Managed zone {} is {}, which does not contain {}",
+                    managed_zone, dns_name, record_name
+                )));
+            }
+
+            return Ok(managed_zone.clone());
+        }
+
+        // Reuse the zone discovered by a previous (warm) invocation if possible.
+        let project = self.session.as_ref().map(|(project, _)| project.as_str()).unwrap_or_default();
+        let cache_key = config_key(&[self.name(), project, record_name]);
+        if let Some(managed_zone) = cached_hosted_zone(&cache_key) {
+            debug!("Using cached managed zone {} for {}", managed_zone, record_name);
+            return Ok(managed_zone);
+        }
+
+        let mut zones = Vec::new();
+        let mut page_token: Option<String> = None;
+
+        loop {
+            let path = match &page_token {
+                Some(page_token) => format!("/managedZones?pageToken={}", page_token),
+                None => "/managedZones".to_string(),
+            };
+            let response = self.request(Method::GET, &path, None).await?;
+
+            for zone in response["managedZones"].as_array().into_iter().flatten() {
+                // The ACME server can only see public zones.
+                if zone["visibility"].as_str() == Some("private") {
+                    continue;
+                }
+
+                if let (Some(name), Some(dns_name)) = (zone["name"].as_str(), zone["dnsName"].as_str()) {
+                    zones.push((name.to_string(), dns_name.to_string()));
+                }
+            }
+
+            match response["nextPageToken"].as_str() {
+                Some(next) if !next.is_empty() => page_token = Some(next.to_string()),
+                _ => break,
+            }
+        }
+
+        let managed_zone = best_matching_zone(record_name, zones)
+            .ok_or_else(|| InvalidCertificateRequest::no_matching_dns_zones(self.name(), record_name))?;
+        cache_hosted_zone(cache_key, managed_zone.clone());
+        Ok(managed_zone)
+    }
+
+    async fn get_txt_record_values(&self, zone_id: &str, record_name: &str) -> Result<Vec<String>, LambdaError> {
+        // Cloud DNS returns TXT values quoted.
+        Ok(self
+            .get_txt_record_set(zone_id, record_name)
+            .await?
+            .and_then(|rrset| rrset["rrdatas"].as_array().cloned())
+            .map(|rrdatas| {
+                rrdatas.iter().filter_map(|rrdata| rrdata.as_str().map(|s| s.trim_matches('"').to_string())).collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn set_txt_record_values(
+        &self,
+        zone_id: &str,
+        record_name: &str,
+        values: &[String],
+    ) -> Result<(), LambdaError> {
+        // Cloud DNS replaces a record set by deleting the existing one (which must match exactly) and adding the
+        // new one in a single change.
+        let deletions: Vec<Value> = self.get_txt_record_set(zone_id, record_name).await?.into_iter().collect();
+        let additions: Vec<Value> = if values.is_empty() {
+            vec![]
+        } else {
+            vec![json!({
+                "name": fully_qualified(record_name),
+                "type": "TXT",
+                "ttl": CHALLENGE_RECORD_TTL,
+                "rrdatas": values.iter().map(|value| format!(r#""{}""#, value)).collect::<Vec<String>>(),
+            })]
+        };
+
+        if additions.is_empty() && deletions.is_empty() {
+            return Ok(());
+        }
+
+        let change = json!({"additions": additions, "deletions": deletions});
+        let path = format!("/managedZones/{}/changes", zone_id);
+        let mut response = self.request(Method::POST, &path, Some(change)).await?;
+        let change_id = response["id"].as_str().unwrap_or_default().to_string();
+
+        info!("Waiting for Cloud DNS change {} to be applied", change_id);
+        while response["status"].as_str() == Some("pending") {
+            sleep(Duration::from_secs(1)).await;
+            response = self.request(Method::GET, &format!("{}/{}", path, change_id), None).await?;
+        }
+
+        match response["status"].as_str() {
+            Some("done") => Ok(()),
+            other => Err(CertificateRequestError::dns_provider_failed(format!(
+                "Cloud DNS change {} has unexpected status {}",
+                change_id,
+                other.unwrap_or_default()
+            ))),
+        }
+    }
+
+    async fn name_servers(&self, zone_id: &str) -> Result<Vec<String>, LambdaError> {
+        let response = self.request(Method::GET, &format!("/managedZones/{}", zone_id), None).await?;
+        Ok(response["nameServers"]
+            .as_array()
+            .map(|name_servers| name_servers.iter().filter_map(|ns| ns.as_str().map(str::to_string)).collect())
+            .unwrap_or_default())
+    }
+}
+
+/// Cloud DNS record names are fully qualified.
+fn fully_qualified(record_name: &str) -> String {
+    format!("{}.", record_name.trim_end_matches('.'))
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::ServiceAccountKey,
+        openssl::{hash::MessageDigest, pkey::PKey, rsa::Rsa, sign::Verifier},
+        serde_json::Value,
+    };
+
+    #[test]
+    fn test_service_account_assertion() {
+        let pkey = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let key = ServiceAccountKey {
+            client_email: "acme@example-project.iam.gserviceaccount.com".to_string(),
+            private_key: String::from_utf8(pkey.private_key_to_pem_pkcs8().unwrap()).unwrap(),
+            project_id: Some("example-project".to_string()),
+            token_uri: None,
+        };
+        assert!(!format!("{:?}", key).contains("PRIVATE KEY"));
+
+        let assertion = key.assertion(1700000000).unwrap();
+        let parts: Vec<&str> = assertion.split('.').collect();
+        assert_eq!(parts.len(), 3);
+
+        let claims: Value =
+            serde_json::from_slice(&base64::decode_config(parts[1], base64::URL_SAFE_NO_PAD).unwrap()).unwrap();
+        assert_eq!(claims["iss"], "acme@example-project.iam.gserviceaccount.com");
+        assert_eq!(claims["aud"], "https://oauth2.googleapis.com/token");
+        assert_eq!(claims["exp"], 1700003600);
+
+        let signature = base64::decode_config(parts[2], base64::URL_SAFE_NO_PAD).unwrap();
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &pkey).unwrap();
+        verifier.update(format!("{}.{}", parts[0], parts[1]).as_bytes()).unwrap();
+        assert!(verifier.verify(&signature).unwrap());
+    }
+}
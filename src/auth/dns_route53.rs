@@ -1,37 +1,71 @@
 use {
-    super::{get_challenge_token_for_auth, AuthorizationHandler, CleanupDirective},
+    super::dns::{domain_name_matches_zone, record_names_equal, DnsAuthorization, DnsProvider},
     crate::{
-        constants::CHALLENGE_TYPE_DNS01,
         errors::{CertificateRequestError, InvalidCertificateRequest},
+        warm::{cache_hosted_zone, cached_hosted_zone, config_key},
+        xray::{aws_client, traced},
     },
-    acme2::{Authorization, AuthorizationStatus, Challenge, ChallengeStatus},
     async_trait::async_trait,
-    base64,
     lambda_runtime::Error as LambdaError,
     log::{debug, error, info},
-    ring::digest::{digest, SHA256},
     rusoto_core::Region,
     rusoto_route53::{
         Change, ChangeBatch, ChangeResourceRecordSetsRequest, GetChangeRequest, GetHostedZoneRequest, HostedZone,
         ListHostedZonesRequest, ListResourceRecordSetsRequest, ResourceRecord, ResourceRecordSet, Route53,
         Route53Client,
     },
+    schemars::JsonSchema,
     serde::{self, Deserialize, Serialize},
     std::{str::FromStr, time::Duration},
     tokio::time::sleep,
 };
 
-/// Configuration for DNS-01 authorization using Route 53.
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct DnsRoute53Authorization {
+/// The TTL of challenge records, in seconds.
+const CHALLENGE_RECORD_TTL: i64 = 10;
+
+/// Configuration for DNS-01 authorization using Route 53. In JSON:
+///
+///     {
+///         // The type of authorization to perform. This must be "DnsRoute53".
+///         "Type": "DnsRoute53",
+///
+///         // The Route 53 hosted zone to write challenge records to. If unspecified, the public hosted zone
+///         // with the longest name matching each domain name is used.
+///         "HostedZoneId": str,
+///
+///         // The region to use for Route 53 API calls. This defaults to "us-east-1".
+///         "Region": str,
+///
+///         // After Route 53 reports a change as in sync, wait up to this many seconds for the challenge
+///         // record to be served by every authoritative nameserver for the zone before asking the ACME
+///         // server to validate it. Set to 0 to skip this check. This defaults to 120.
+///         "PropagationTimeout": int,
+///
+///         // Whether to write the challenge record at the end of the CNAME chain if
+///         // _acme-challenge.{domain name} is a CNAME; see DnsAuthorization. This defaults to true.
+///         "FollowCname": bool,
+///     }
+pub type DnsRoute53Authorization = DnsAuthorization<Route53Dns>;
+
+/// The Route 53 settings of a DnsRoute53Authorization.
+#[derive(Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct Route53Dns {
     #[serde(rename = "HostedZoneId", default)]
-    pub(crate) hosted_zone_id: Option<String>,
+    pub hosted_zone_id: Option<String>,
 
     #[serde(rename = "Region", default)]
-    pub(crate) region: Option<String>,
+    pub region: Option<String>,
 }
 
-impl DnsRoute53Authorization {
+impl Route53Dns {
+    fn client(&self) -> Result<Route53Client, LambdaError> {
+        let region = match self.region {
+            Some(ref region) => Region::from_str(region.as_str())?,
+            None => Region::UsEast1,
+        };
+        Ok(Route53Client::new_with_client(aws_client(), region))
+    }
+
     async fn get_hosted_zone_id_for_domain_name(
         &self,
         route53_client: &Route53Client,
@@ -70,6 +104,13 @@ impl DnsRoute53Authorization {
             }
 
             None => {
+                // Reuse the zone discovered by a previous (warm) invocation if possible.
+                let cache_key = config_key(&[self.region.as_deref().unwrap_or_default(), domain_name]);
+                if let Some(hosted_zone_id) = cached_hosted_zone(&cache_key) {
+                    debug!("Using cached hosted zone {} for {}", hosted_zone_id, domain_name);
+                    return Ok(hosted_zone_id);
+                }
+
                 let mut lhzi = ListHostedZonesRequest::default();
                 let mut best_match: Option<HostedZone> = None;
 
@@ -86,6 +127,12 @@ impl DnsRoute53Authorization {
                     };
 
                     for hz in lhzo.hosted_zones {
+                        // The ACME server can only see public zones.
+                        if hz.config.as_ref().and_then(|c| c.private_zone).unwrap_or(false) {
+                            debug!("Ignoring private hosted zone {} ({})", hz.id, hz.name);
+                            continue;
+                        }
+
                         if domain_name_matches_zone(domain_name, hz.name.as_str()) {
                             match best_match {
                                 None => best_match = Some(hz),
@@ -98,9 +145,9 @@ impl DnsRoute53Authorization {
                     if lhzo.is_truncated {
                         if lhzo.next_marker.is_none() {
                             error!("Route53 indicated the list was truncated but did not provide a marker to continue");
-                            return Err(CertificateRequestError::unexpected_aws_response(format!(
-                                "Route53 indicated the list was truncated but did not provide a marker to continue"
-                            )));
+                            return Err(CertificateRequestError::unexpected_aws_response(
+                                "Route53 indicated the list was truncated but did not provide a marker to continue",
+                            ));
                         }
 
                         lhzi.marker = lhzo.next_marker;
@@ -109,86 +156,48 @@ impl DnsRoute53Authorization {
                     }
                 }
 
-                best_match
+                let hosted_zone_id = best_match
                     .map(|hz| hz.id)
-                    .ok_or(InvalidCertificateRequest::no_matching_route53_zones(domain_name.to_string()))
+                    .ok_or(InvalidCertificateRequest::no_matching_route53_zones(domain_name.to_string()))?;
+                cache_hosted_zone(cache_key, hosted_zone_id.clone());
+                Ok(hosted_zone_id)
             }
         }
     }
 
-    async fn remove_existing_records(
+    /// Returns the TXT record set with the given name, if it exists.
+    async fn get_txt_record_set(
         &self,
-        route53_client: &mut Route53Client,
+        route53_client: &Route53Client,
         hosted_zone_id: &str,
         record_name: &str,
-    ) -> Result<(), LambdaError> {
-        let mut lrrsi = ListResourceRecordSetsRequest {
+    ) -> Result<Option<ResourceRecordSet>, LambdaError> {
+        let lrrsi = ListResourceRecordSetsRequest {
             hosted_zone_id: hosted_zone_id.to_string(),
             start_record_name: Some(record_name.to_string()),
             start_record_type: Some("TXT".to_string()),
+            max_items: Some("1".to_string()),
             ..Default::default()
         };
 
-        let mut records_to_delete = vec![];
-
-        'list_records: loop {
-            let lrrso = route53_client.list_resource_record_sets(lrrsi.clone()).await?;
-            if !lrrso.is_truncated || lrrso.next_record_name.is_none() {
-                break;
-            }
-
-            for resource_record in lrrso.resource_record_sets {
-                if resource_record.name.as_str() != record_name || resource_record.type_.as_str() != "TXT" {
-                    break 'list_records;
-                }
-
-                records_to_delete.push(Change {
-                    action: "DELETE".to_string(),
-                    resource_record_set: resource_record,
-                });
-            }
-
-            match lrrso.next_record_name {
-                None => break,
-                Some(ref nrn) if nrn.as_str() != record_name => break,
-                _ => (),
-            }
-
-            match lrrso.next_record_type {
-                None => break,
-                Some(ref nrt) if nrt.as_str() != "TXT" => break,
-                _ => (),
+        let lrrso = match route53_client.list_resource_record_sets(lrrsi).await {
+            Ok(lrrso) => lrrso,
+            Err(e) => {
+                error!("Failed to read {} from Route 53 zone {}: {}", record_name, hosted_zone_id, e);
+                return Err(CertificateRequestError::unexpected_aws_response(format!(
+                    "Failed to read {} from Route 53 zone {}: {}",
+                    record_name, hosted_zone_id, e
+                )));
             }
+        };
 
-            lrrsi.start_record_identifier = lrrso.next_record_identifier;
-        }
-
-        if records_to_delete.len() > 0 {
-            info!("Deleting {} record(s) from {}", records_to_delete.len(), hosted_zone_id);
-            let crrsi = ChangeResourceRecordSetsRequest {
-                hosted_zone_id: hosted_zone_id.to_string(),
-                change_batch: ChangeBatch {
-                    changes: records_to_delete,
-                    comment: None,
-                },
-            };
-
-            let crrso = route53_client.change_resource_record_sets(crrsi).await?;
-            info!("Waiting for Route 53 change {} to propagate", crrso.change_info.id);
-            self.wait_for_change_sync(route53_client, &crrso.change_info.id).await?;
-
-            info!("Sleeping for 10 seconds to let Route 53 settle down");
-            sleep(Duration::from_secs(10)).await;
-        }
-
-        Ok(())
+        Ok(lrrso
+            .resource_record_sets
+            .into_iter()
+            .find(|rrs| record_names_equal(&rrs.name, record_name) && rrs.type_.as_str() == "TXT"))
     }
 
-    async fn wait_for_change_sync(
-        &self,
-        route53_client: &mut Route53Client,
-        change_id: &str,
-    ) -> Result<(), LambdaError> {
+    async fn wait_for_change_sync(&self, route53_client: &Route53Client, change_id: &str) -> Result<(), LambdaError> {
         // Due to a rusoto bug, we need to trim leading slashes from the change id.
         let gci = GetChangeRequest {
             id: change_id.trim_start_matches('/').to_string(),
@@ -223,233 +232,100 @@ impl DnsRoute53Authorization {
     }
 }
 
-impl Default for DnsRoute53Authorization {
-    fn default() -> Self {
-        Self {
-            hosted_zone_id: None,
-            region: None,
-        }
-    }
-}
-
 #[async_trait]
-impl AuthorizationHandler for DnsRoute53Authorization {
-    async fn setup(&mut self) -> Result<(), LambdaError> {
-        Ok(())
+impl DnsProvider for Route53Dns {
+    fn name(&self) -> &'static str {
+        "Route 53"
     }
 
-    async fn auth(
-        &self,
-        auth: Authorization,
-    ) -> Result<(Authorization, Challenge, Vec<CleanupDirective>), LambdaError> {
-        debug!("Handling authorization: {:?}", auth);
-        let domain_name: &str = &auth.identifier.value;
-        let (challenge, _token) = get_challenge_token_for_auth(&auth, CHALLENGE_TYPE_DNS01)?;
-
-        let key_auth = match challenge.key_authorization() {
-            Ok(maybe_key_auth) => match maybe_key_auth {
-                Some(ka) => ka,
-                None => {
-                    error!("No {} key authorization found for {}", CHALLENGE_TYPE_DNS01, domain_name);
-                    return Err(CertificateRequestError::token_not_available(CHALLENGE_TYPE_DNS01, domain_name));
-                }
-            },
-            Err(e) => {
-                error!("Failed to get ACME key authorization for {}: {}", domain_name, e);
-                return Err(Box::new(e));
-            }
-        };
-
-        let region = match self.region {
-            Some(ref region) => Region::from_str(region.as_str())?,
-            None => Region::UsEast1,
-        };
-        let mut route53_client = Route53Client::new(region);
+    async fn find_zone(&self, record_name: &str) -> Result<String, LambdaError> {
+        self.get_hosted_zone_id_for_domain_name(&self.client()?, record_name).await
+    }
 
-        // Find the best hosted zone for the domain.
-        let hosted_zone_id = self.get_hosted_zone_id_for_domain_name(&route53_client, domain_name).await?;
-        let record_name = format!("_acme-challenge.{}", domain_name);
+    async fn get_txt_record_values(&self, zone_id: &str, record_name: &str) -> Result<Vec<String>, LambdaError> {
+        let record_set = self.get_txt_record_set(&self.client()?, zone_id, record_name).await?;
 
-        // Remove any existing records for the domain.
-        self.remove_existing_records(&mut route53_client, &hosted_zone_id, &record_name).await?;
+        // Route 53 returns TXT values quoted.
+        Ok(record_set
+            .and_then(|rrs| rrs.resource_records)
+            .map(|rrs| rrs.into_iter().map(|rr| rr.value.trim_matches('"').to_string()).collect())
+            .unwrap_or_default())
+    }
 
-        // Write the challenge to Route53.
-        // DNS challenges need to SHA256-hash the key again and base64 encode the result without padding.
-        let hashed_key = base64::encode_config(digest(&SHA256, key_auth.as_bytes()).as_ref(), base64::URL_SAFE_NO_PAD);
-        let record_value = format!(r#""{}""#, hashed_key); // TXT record must be quoted
-        info!("Writing key authorization for {} to Route 53 zone {}: {}", record_name, hosted_zone_id, record_value);
+    async fn set_txt_record_values(
+        &self,
+        zone_id: &str,
+        record_name: &str,
+        values: &[String],
+    ) -> Result<(), LambdaError> {
+        let route53_client = self.client()?;
 
-        let change_batch = ChangeBatch {
-            comment: Some(format!("ACMEv2 Challenge for {}", domain_name)),
-            changes: vec![Change {
+        let change = if values.is_empty() {
+            // Deletions must match the existing record set exactly.
+            match self.get_txt_record_set(&route53_client, zone_id, record_name).await? {
+                Some(resource_record_set) => Change {
+                    action: "DELETE".to_string(),
+                    resource_record_set,
+                },
+                None => return Ok(()),
+            }
+        } else {
+            Change {
                 action: "UPSERT".to_string(),
                 resource_record_set: ResourceRecordSet {
-                    name: record_name.clone(),
-                    resource_records: Some(vec![ResourceRecord {
-                        value: record_value.clone(),
-                    }]),
-                    ttl: Some(10),
+                    name: record_name.to_string(),
+                    resource_records: Some(
+                        values
+                            .iter()
+                            .map(|value| ResourceRecord {
+                                value: format!(r#""{}""#, value), // TXT record must be quoted
+                            })
+                            .collect(),
+                    ),
+                    ttl: Some(CHALLENGE_RECORD_TTL),
                     type_: "TXT".to_string(),
                     ..Default::default()
                 },
-            }],
+            }
         };
+
         let crrsi = ChangeResourceRecordSetsRequest {
-            hosted_zone_id: hosted_zone_id.clone(),
-            change_batch,
+            hosted_zone_id: zone_id.to_string(),
+            change_batch: ChangeBatch {
+                comment: Some(format!("ACMEv2 Challenge for {}", record_name)),
+                changes: vec![change],
+            },
         };
 
         let crrso = match route53_client.change_resource_record_sets(crrsi).await {
             Ok(crrso) => crrso,
             Err(e) => {
-                error!(
-                    "Failed to write key authorization for {} to Route 53 zone {}: {}",
-                    domain_name, hosted_zone_id, e
-                );
                 return Err(CertificateRequestError::unexpected_aws_response(format!(
-                    "Failed to write key authorization for {} to Route 53 zone {}: {}",
-                    domain_name, hosted_zone_id, e
+                    "Failed to update {} in Route 53 zone {}: {}",
+                    record_name, zone_id, e
                 )));
             }
         };
 
         // Wait for the change to propagate.
-        info!("Waiting for Route 53 change to propagate for {}", domain_name);
-        self.wait_for_change_sync(&mut route53_client, &crrso.change_info.id).await?;
-
-        let cleanup = vec![CleanupDirective::DeleteRoute53Record {
-            hosted_zone_id: hosted_zone_id.clone(),
-            record_name: format!("_acme-challenge.{}", domain_name),
-            record_type: "TXT".to_string(),
-            record_value,
-            ttl: 10,
-        }];
-
-        info!("Informing ACME server that dns-01 validation is ready for {}", domain_name);
-        // Signal to the ACME server that we're ready for verification.
-        match challenge.validate().await {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Failed to send validation request to ACME server for {}: {}", domain_name, e);
-                return Err(Box::new(e));
-            }
-        };
-
-        Ok((auth, challenge, cleanup))
+        info!("Waiting for Route 53 change {} to propagate", crrso.change_info.id);
+        traced("Route53 change sync", self.wait_for_change_sync(&route53_client, &crrso.change_info.id)).await
     }
 
-    async fn check(
-        &self,
-        auth: Authorization,
-        challenge: Challenge,
-    ) -> Result<(Authorization, Challenge, bool), LambdaError> {
-        let domain_name: String = auth.identifier.value.clone();
-        let (challenge_result, auth_result) = tokio::join!(challenge.poll(), auth.poll(),);
-
-        let challenge: Challenge = match challenge_result {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Failed to update challenge status for {}: {}", domain_name, e);
-                return Err(Box::new(e));
-            }
+    async fn name_servers(&self, zone_id: &str) -> Result<Vec<String>, LambdaError> {
+        let ghzi = GetHostedZoneRequest {
+            id: zone_id.to_string(),
         };
 
-        let auth: Authorization = match auth_result {
-            Ok(a) => a,
+        match self.client()?.get_hosted_zone(ghzi).await {
+            Ok(ghzo) => Ok(ghzo.delegation_set.map(|ds| ds.name_servers).unwrap_or_default()),
             Err(e) => {
-                error!("Failed to update authorization status for {}: {}", domain_name, e);
-                return Err(Box::new(e));
-            }
-        };
-
-        let challenge_valid: bool = match challenge.status {
-            ChallengeStatus::Invalid => {
-                error!("Challenge for {} is invalid", domain_name);
-                return Err(CertificateRequestError::challenge_failed(domain_name));
-            }
-            ChallengeStatus::Valid => true,
-            _ => false,
-        };
-
-        let auth_valid: bool = match auth.status {
-            AuthorizationStatus::Invalid => {
-                error!("Authorization for {} is invalid", domain_name);
-                return Err(CertificateRequestError::authorization_failed(domain_name));
-            }
-            AuthorizationStatus::Valid => true,
-            _ => false,
-        };
-
-        Ok((auth, challenge, challenge_valid && auth_valid))
-    }
-
-    async fn cleanup(&self, directives: Vec<CleanupDirective>) -> Result<(), LambdaError> {
-        let region = match self.region {
-            Some(ref region) => Region::from_str(region.as_str())?,
-            None => Region::UsEast1,
-        };
-        let route53_client = Route53Client::new(region);
-
-        for directive in directives {
-            match directive {
-                CleanupDirective::DeleteRoute53Record {
-                    hosted_zone_id,
-                    record_name,
-                    record_type,
-                    record_value,
-                    ttl,
-                } => {
-                    // Remove the challenge from Route 53.
-                    let change_batch = ChangeBatch {
-                        comment: Some(format!("ACMEv2 Challenge Cleanup for {}", record_name)),
-                        changes: vec![Change {
-                            action: "DELETE".to_string(),
-                            resource_record_set: ResourceRecordSet {
-                                name: record_name.clone(),
-                                resource_records: Some(vec![ResourceRecord {
-                                    value: record_value.clone(),
-                                }]),
-                                type_: record_type,
-                                ttl: Some(ttl),
-                                ..Default::default()
-                            },
-                        }],
-                    };
-
-                    let crrsi = ChangeResourceRecordSetsRequest {
-                        hosted_zone_id: hosted_zone_id.clone(),
-                        change_batch,
-                    };
-
-                    info!("Removing Route 53 record {}", record_name);
-                    if let Err(e) = route53_client.change_resource_record_sets(crrsi).await {
-                        error!(
-                            "Failed to delete key authorization for {} in Route 53 zone {}: {}",
-                            record_name, hosted_zone_id, e
-                        );
-                    }
-                }
-                _ => {
-                    error!("Unsupported cleanup directive: {:?}", directive);
-                }
+                error!("Failed to get nameservers for hosted zone {}: {}", zone_id, e);
+                Err(CertificateRequestError::unexpected_aws_response(format!(
+                    "Failed to get nameservers for hosted zone {}: {}",
+                    zone_id, e
+                )))
             }
         }
-        Ok(())
     }
 }
-
-fn domain_name_matches_zone(domain_name: &str, zone: &str) -> bool {
-    let domain_name_with_dot = if domain_name.ends_with('.') {
-        domain_name.to_string()
-    } else {
-        format!("{}.", domain_name)
-    };
-
-    let zone_with_dot = if zone.ends_with('.') {
-        zone.to_string()
-    } else {
-        format!("{}.", zone)
-    };
-
-    domain_name_with_dot.ends_with(&zone_with_dot)
-}
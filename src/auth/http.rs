@@ -5,19 +5,31 @@ use {
             CHALLENGE_TYPE_HTTP01, S3_ENCRYPTION_AES, S3_ENCRYPTION_KMS, SSM_TIER_ADVANCED,
             SSM_TIER_INTELLIGENT_TIERING, SSM_TIER_STANDARD, SSM_TYPE_SECURE_STRING,
         },
+        dry_run::PlannedWrite,
         errors::{CertificateRequestError, InvalidCertificateRequest},
         utils::{s3_bucket_location_constraint_to_region, ssm_acme_parameter_path},
+        xray::aws_client,
     },
     acme2::{Authorization, AuthorizationStatus, Challenge, ChallengeStatus},
     async_trait::async_trait,
     lambda_runtime::Error as LambdaError,
     log::{debug, error, info},
+    reqwest::{redirect::Policy, Client},
     rusoto_core::Region,
     rusoto_s3::{DeleteObjectRequest, GetBucketLocationRequest, PutObjectRequest, S3Client, S3},
     rusoto_ssm::{DeleteParameterRequest, PutParameterRequest, Ssm, SsmClient},
+    schemars::JsonSchema,
     serde::{Deserialize, Serialize},
+    std::time::{Duration, Instant},
+    tokio::time::sleep,
 };
 
+/// How long to wait for a challenge written to S3 to be served at its URL by default.
+const DEFAULT_SELF_CHECK_TIMEOUT_SECS: u64 = 60;
+
+/// How often to fetch the challenge URL while waiting for it to be served.
+const SELF_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
 /// Configuration for HTTP-01 authorization using S3 to serve a website. In JSON:
 ///
 ///      {
@@ -28,7 +40,7 @@ use {
 ///         "Bucket": str,
 ///         
 ///         // Prefix URLs with this string. Note that a '/' is *not*
-///         // automatically appended. Instances of "{{DomainName}}" in the prefix will be
+///         // automatically appended. Instances of "{DomainName}" in the prefix will be
 ///         // replaced with the domain name being requested.
 ///         "Prefix": str,
 ///         
@@ -38,24 +50,37 @@ use {
 ///         
 ///         // If EncryptionAlgorithm is "aws:kms", this KMS key will be used to encrypt the
 ///         // ACME HTTP-01 challenge written to S3. If unset, the default "aws/s3" key will be used.
-///         "S3KmsKeyId": str
+///         "KmsKeyId": str,
+///
+///         // After writing a challenge, wait up to this many seconds for it to be served at
+///         // http://{domain}/.well-known/acme-challenge/{token} before asking the ACME server to
+///         // validate it. Redirects are followed, as the ACME server does. Set to 0 to skip this
+///         // check. This defaults to 60.
+///         "SelfCheckTimeout": int,
 ///     }
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct HttpS3Authorization {
+///
+/// The bucket must be served for each domain name at /.well-known/acme-challenge/, e.g. as a CloudFront origin
+/// with a cache behavior for that path (with caching disabled), or through an ALB listener rule that redirects that
+/// path to the bucket's website endpoint.
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct HttpS3Authorization {
     #[serde(rename = "Bucket")]
-    pub(crate) bucket: String,
+    pub bucket: String,
 
     #[serde(rename = "Prefix", default)]
-    pub(crate) prefix: Option<String>,
+    pub prefix: Option<String>,
 
     #[serde(rename = "EncryptionAlgorithm", default)]
-    pub(crate) enc_alg: Option<String>,
+    pub enc_alg: Option<String>,
 
     #[serde(rename = "KmsKeyId", default)]
-    pub(crate) kms_key_id: Option<String>,
+    pub kms_key_id: Option<String>,
+
+    #[serde(rename = "SelfCheckTimeout", default)]
+    pub self_check_timeout: Option<u64>,
 
     #[serde(skip)]
-    pub(crate) region: Option<Region>,
+    pub region: Option<Region>,
 }
 
 impl Default for HttpS3Authorization {
@@ -65,16 +90,69 @@ impl Default for HttpS3Authorization {
             prefix: None,
             enc_alg: None,
             kms_key_id: None,
+            self_check_timeout: None,
             region: None,
         }
     }
 }
 
 impl HttpS3Authorization {
-    fn get_s3_key_for_token(&self, token: &str) -> String {
+    /// The object answering the challenge for a domain name, for dry runs. The token is only known once the order is
+    /// placed.
+    pub fn planned_write(&self, domain_name: &str) -> PlannedWrite {
+        let key = self.get_s3_key_for_token(domain_name, "<token>");
+        PlannedWrite::new("s3:PutObject", format!("s3://{}/{}", self.bucket, key))
+    }
+
+    fn get_s3_key_for_token(&self, domain_name: &str, token: &str) -> String {
         match &self.prefix {
             None => format!(".well-known/acme-challenge/{}", token),
-            Some(prefix) => format!("{}.well-known/acme-challenge/{}", prefix, token),
+            Some(prefix) => {
+                format!("{}.well-known/acme-challenge/{}", prefix.replace("{DomainName}", domain_name), token)
+            }
+        }
+    }
+
+    /// Wait until the challenge is served at its URL, so the ACME server isn't asked to validate a challenge that
+    /// CloudFront or the load balancer can't serve yet.
+    async fn wait_for_self_check(&self, domain_name: &str, token: &str, key_auth: &str) -> Result<(), LambdaError> {
+        let timeout = Duration::from_secs(self.self_check_timeout.unwrap_or(DEFAULT_SELF_CHECK_TIMEOUT_SECS));
+        if timeout.as_secs() == 0 {
+            return Ok(());
+        }
+
+        // The ACME server follows redirects to HTTPS without checking the certificate (which may be the expired one
+        // being replaced), so do the same here.
+        let client = Client::builder().redirect(Policy::limited(10)).danger_accept_invalid_certs(true).build()?;
+        let url = format!("http://{}/.well-known/acme-challenge/{}", domain_name, token);
+        let start = Instant::now();
+
+        info!("Waiting for the challenge for {} to be served at {}", domain_name, url);
+
+        loop {
+            let body = match client.get(&url).send().await {
+                Ok(response) if response.status().is_success() => response.text().await.ok(),
+                Ok(response) => {
+                    debug!("Fetching {} returned HTTP {}", url, response.status());
+                    None
+                }
+                Err(e) => {
+                    debug!("Fetching {} failed: {}", url, e);
+                    None
+                }
+            };
+
+            if body.as_deref().map(str::trim) == Some(key_auth) {
+                info!("Challenge for {} is being served at {}", domain_name, url);
+                return Ok(());
+            }
+
+            if start.elapsed() >= timeout {
+                error!("Timed out waiting for the challenge for {} to be served at {}", domain_name, url);
+                return Err(CertificateRequestError::challenge_not_served(url));
+            }
+
+            sleep(SELF_CHECK_INTERVAL).await;
         }
     }
 }
@@ -91,7 +169,7 @@ impl AuthorizationHandler for HttpS3Authorization {
         };
 
         // Figure out where the S3 bucket resides; we'll need to use this for making S3 calls.
-        let s3_client = S3Client::new(Region::default());
+        let s3_client = S3Client::new_with_client(aws_client(), Region::default());
         let gbr_req = GetBucketLocationRequest {
             bucket: self.bucket.clone(),
             expected_bucket_owner: None,
@@ -131,8 +209,9 @@ impl AuthorizationHandler for HttpS3Authorization {
         };
 
         // Write the challenge to S3.
-        let s3_client = S3Client::new(self.region.as_ref().expect("Region not initialized").clone());
-        let s3_key = self.get_s3_key_for_token(&token);
+        let s3_client =
+            S3Client::new_with_client(aws_client(), self.region.as_ref().expect("Region not initialized").clone());
+        let s3_key = self.get_s3_key_for_token(domain_name, &token);
 
         let mut po_request = PutObjectRequest {
             body: Some(key_auth.as_bytes().to_vec().into()),
@@ -162,6 +241,17 @@ impl AuthorizationHandler for HttpS3Authorization {
             }
         }
 
+        let cleanup = vec![CleanupDirective::DeleteS3Object {
+            bucket: self.bucket.clone(),
+            key: s3_key,
+        }];
+
+        // Don't burn a failed validation (which counts against rate limits) on a challenge that isn't reachable.
+        if let Err(e) = self.wait_for_self_check(domain_name, &token, &key_auth).await {
+            self.cleanup(cleanup).await?;
+            return Err(e);
+        }
+
         info!("Informing ACME server that http-01 validation is ready for  {}", domain_name);
         // Signal to the ACME server that we're ready for verification.
         match challenge.validate().await {
@@ -172,11 +262,6 @@ impl AuthorizationHandler for HttpS3Authorization {
             }
         };
 
-        let cleanup = vec![CleanupDirective::DeleteS3Object {
-            bucket: self.bucket.clone(),
-            key: s3_key,
-        }];
-
         Ok((auth, challenge, cleanup))
     }
 
@@ -226,7 +311,8 @@ impl AuthorizationHandler for HttpS3Authorization {
     }
 
     async fn cleanup(&self, directives: Vec<CleanupDirective>) -> Result<(), LambdaError> {
-        let s3_client = S3Client::new(self.region.as_ref().expect("Region not initialized").clone());
+        let s3_client =
+            S3Client::new_with_client(aws_client(), self.region.as_ref().expect("Region not initialized").clone());
 
         for directive in directives {
             match directive {
@@ -267,13 +353,20 @@ impl AuthorizationHandler for HttpS3Authorization {
 ///         // UpdateServiceSetting has been called to change it.
 ///         "SsmTier": str,
 ///     }
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct HttpApiGatewayAuthorization {
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct HttpApiGatewayAuthorization {
     #[serde(rename = "KmsKeyId", default)]
-    pub(crate) kms_key_id: Option<String>,
+    pub kms_key_id: Option<String>,
 
     #[serde(rename = "SsmTier", default)]
-    pub(crate) ssm_tier: Option<String>,
+    pub ssm_tier: Option<String>,
+}
+
+impl HttpApiGatewayAuthorization {
+    /// The parameter answering a challenge, for dry runs. The token is only known once the order is placed.
+    pub fn planned_write(&self) -> PlannedWrite {
+        PlannedWrite::new("ssm:PutParameter", get_ssm_parameter_for_token("<token>"))
+    }
 }
 
 #[async_trait]
@@ -313,7 +406,7 @@ impl AuthorizationHandler for HttpApiGatewayAuthorization {
         };
 
         // Write the challenge to SSM.
-        let ssm_client = SsmClient::new(Region::default());
+        let ssm_client = SsmClient::new_with_client(aws_client(), Region::default());
         let parameter_name = get_ssm_parameter_for_token(&token);
 
         let ppr = PutParameterRequest {
@@ -402,7 +495,7 @@ impl AuthorizationHandler for HttpApiGatewayAuthorization {
     }
 
     async fn cleanup(&self, directives: Vec<CleanupDirective>) -> Result<(), LambdaError> {
-        let ssm_client = SsmClient::new(Region::default());
+        let ssm_client = SsmClient::new_with_client(aws_client(), Region::default());
         for directive in directives {
             match directive {
                 CleanupDirective::DeleteSSMParameter {
@@ -410,7 +503,6 @@ impl AuthorizationHandler for HttpApiGatewayAuthorization {
                 } => {
                     let dpr = DeleteParameterRequest {
                         name: parameter_name.clone(),
-                        ..Default::default()
                     };
 
                     if let Err(e) = ssm_client.delete_parameter(dpr).await {
@@ -429,3 +521,21 @@ impl AuthorizationHandler for HttpApiGatewayAuthorization {
 fn get_ssm_parameter_for_token(token: &str) -> String {
     format!("{}/AcmeChallenge/{}", ssm_acme_parameter_path(), token)
 }
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::HttpS3Authorization;
+
+    #[test]
+    fn test_s3_key_for_token() {
+        let mut auth = HttpS3Authorization::default();
+        assert_eq!(auth.get_s3_key_for_token("example.com", "abc"), ".well-known/acme-challenge/abc");
+
+        auth.prefix = Some("sites/{DomainName}/".to_string());
+        assert_eq!(
+            auth.get_s3_key_for_token("www.example.com", "abc"),
+            "sites/www.example.com/.well-known/acme-challenge/abc"
+        );
+    }
+}
@@ -1,29 +1,90 @@
+mod dns;
+mod dns_cloudflare;
+mod dns_google;
 mod dns_route53;
 mod http;
+mod tls_alpn;
 
 use {
     self::{
+        dns_cloudflare::DnsCloudflareAuthorization,
+        dns_google::DnsGoogleAuthorization,
         dns_route53::DnsRoute53Authorization,
         http::{HttpApiGatewayAuthorization, HttpS3Authorization},
+        tls_alpn::TlsAlpnAuthorization,
+    },
+    crate::{
+        constants::{CHALLENGE_TYPE_DNS01, CHALLENGE_TYPE_HTTP01, CHALLENGE_TYPE_TLS_ALPN01},
+        debug_artifacts::DnsLookupEvidence,
+        dry_run::PlannedWrite,
+        errors::CertificateRequestError,
     },
-    crate::errors::CertificateRequestError,
     acme2::{Authorization, Challenge},
     async_trait::async_trait,
     lambda_runtime::Error as LambdaError,
     log::error,
+    schemars::JsonSchema,
     serde::{Deserialize, Serialize},
 };
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
 #[serde(tag = "Type")]
-pub(crate) enum CertificateAuthorization {
+pub enum CertificateAuthorization {
+    DnsCloudflare(DnsCloudflareAuthorization),
+    DnsGoogle(DnsGoogleAuthorization),
     DnsRoute53(DnsRoute53Authorization),
     HttpApiGateway(HttpApiGatewayAuthorization),
     HttpS3(HttpS3Authorization),
+    TlsAlpn(TlsAlpnAuthorization),
+}
+
+impl CertificateAuthorization {
+    /// The ACME challenge type answered by this authorization handler.
+    pub fn challenge_type(&self) -> &'static str {
+        match self {
+            Self::DnsCloudflare(_) | Self::DnsGoogle(_) | Self::DnsRoute53(_) => CHALLENGE_TYPE_DNS01,
+            Self::HttpApiGateway(_) | Self::HttpS3(_) => CHALLENGE_TYPE_HTTP01,
+            Self::TlsAlpn(_) => CHALLENGE_TYPE_TLS_ALPN01,
+        }
+    }
+
+    /// Indicates whether this authorization handler can validate wildcard domain names. ACME servers only allow
+    /// wildcards to be validated via dns-01.
+    pub fn supports_wildcards(&self) -> bool {
+        self.challenge_type() == CHALLENGE_TYPE_DNS01
+    }
+
+    /// The challenge responses written while authorizing the given domain names, reported by Staging dry runs. A
+    /// wildcard shares its challenge with its base domain. Each response is deleted once its authorization completes,
+    /// and a DNS record may be written at the end of a CNAME chain instead; see DnsAuthorization.
+    pub fn planned_writes(&self, domain_names: &[String]) -> Vec<PlannedWrite> {
+        let mut identifiers: Vec<&str> = Vec::with_capacity(domain_names.len());
+        for domain_name in domain_names {
+            let identifier = domain_name.trim_start_matches("*.");
+            if !identifiers.contains(&identifier) {
+                identifiers.push(identifier);
+            }
+        }
+
+        identifiers
+            .into_iter()
+            .map(|identifier| {
+                let record = format!("_acme-challenge.{} (TXT)", identifier);
+                match self {
+                    Self::DnsCloudflare(_) => PlannedWrite::new("cloudflare:CreateDnsRecord", record),
+                    Self::DnsGoogle(_) => PlannedWrite::new("dns:ChangesCreate", record),
+                    Self::DnsRoute53(_) => PlannedWrite::new("route53:ChangeResourceRecordSets", record),
+                    Self::HttpApiGateway(inner) => inner.planned_write(),
+                    Self::HttpS3(inner) => inner.planned_write(identifier),
+                    Self::TlsAlpn(inner) => inner.planned_write(identifier),
+                }
+            })
+            .collect()
+    }
 }
 
 #[async_trait]
-pub(crate) trait AuthorizationHandler {
+pub trait AuthorizationHandler {
     async fn setup(&mut self) -> Result<(), LambdaError> {
         Ok(())
     }
@@ -37,15 +98,23 @@ pub(crate) trait AuthorizationHandler {
     async fn cleanup(&self, _directives: Vec<CleanupDirective>) -> Result<(), LambdaError> {
         Ok(())
     }
+
+    /// The DNS lookups made while waiting for challenge records to propagate, for debug artifacts.
+    fn dns_lookups(&self) -> Vec<DnsLookupEvidence> {
+        vec![]
+    }
 }
 
 #[async_trait]
 impl AuthorizationHandler for CertificateAuthorization {
     async fn setup(&mut self) -> Result<(), LambdaError> {
         match self {
+            Self::DnsCloudflare(inner) => inner.setup().await,
+            Self::DnsGoogle(inner) => inner.setup().await,
             Self::DnsRoute53(inner) => inner.setup().await,
             Self::HttpApiGateway(inner) => inner.setup().await,
             Self::HttpS3(inner) => inner.setup().await,
+            Self::TlsAlpn(inner) => inner.setup().await,
         }
     }
 
@@ -54,9 +123,12 @@ impl AuthorizationHandler for CertificateAuthorization {
         auth: Authorization,
     ) -> Result<(Authorization, Challenge, Vec<CleanupDirective>), LambdaError> {
         match self {
+            Self::DnsCloudflare(inner) => inner.auth(auth).await,
+            Self::DnsGoogle(inner) => inner.auth(auth).await,
             Self::DnsRoute53(inner) => inner.auth(auth).await,
             Self::HttpApiGateway(inner) => inner.auth(auth).await,
             Self::HttpS3(inner) => inner.auth(auth).await,
+            Self::TlsAlpn(inner) => inner.auth(auth).await,
         }
     }
 
@@ -66,31 +138,40 @@ impl AuthorizationHandler for CertificateAuthorization {
         challenge: Challenge,
     ) -> Result<(Authorization, Challenge, bool), LambdaError> {
         match self {
+            Self::DnsCloudflare(inner) => inner.check(auth, challenge).await,
+            Self::DnsGoogle(inner) => inner.check(auth, challenge).await,
             Self::DnsRoute53(inner) => inner.check(auth, challenge).await,
             Self::HttpApiGateway(inner) => inner.check(auth, challenge).await,
             Self::HttpS3(inner) => inner.check(auth, challenge).await,
+            Self::TlsAlpn(inner) => inner.check(auth, challenge).await,
         }
     }
 
     async fn cleanup(&self, auth: Vec<CleanupDirective>) -> Result<(), LambdaError> {
         match self {
+            Self::DnsCloudflare(inner) => inner.cleanup(auth).await,
+            Self::DnsGoogle(inner) => inner.cleanup(auth).await,
             Self::DnsRoute53(inner) => inner.cleanup(auth).await,
             Self::HttpApiGateway(inner) => inner.cleanup(auth).await,
             Self::HttpS3(inner) => inner.cleanup(auth).await,
+            Self::TlsAlpn(inner) => inner.cleanup(auth).await,
+        }
+    }
+
+    fn dns_lookups(&self) -> Vec<DnsLookupEvidence> {
+        match self {
+            Self::DnsCloudflare(inner) => inner.dns_lookups(),
+            Self::DnsGoogle(inner) => inner.dns_lookups(),
+            Self::DnsRoute53(inner) => inner.dns_lookups(),
+            Self::HttpApiGateway(inner) => inner.dns_lookups(),
+            Self::HttpS3(inner) => inner.dns_lookups(),
+            Self::TlsAlpn(inner) => inner.dns_lookups(),
         }
     }
 }
 
 #[derive(Debug)]
-pub(crate) enum CleanupDirective {
-    DeleteRoute53Record {
-        hosted_zone_id: String,
-        record_name: String,
-        record_type: String,
-        record_value: String,
-        ttl: i64,
-    },
-
+pub enum CleanupDirective {
     DeleteS3Object {
         bucket: String,
         key: String,
@@ -99,6 +180,13 @@ pub(crate) enum CleanupDirective {
     DeleteSSMParameter {
         parameter_name: String,
     },
+
+    /// Remove a value from a DNS challenge record, deleting the record if no values remain.
+    DeleteTxtRecord {
+        zone_id: String,
+        record_name: String,
+        record_value: String,
+    },
 }
 
 fn get_challenge_token_for_auth(
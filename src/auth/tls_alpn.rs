@@ -0,0 +1,299 @@
+use {
+    super::{get_challenge_token_for_auth, AuthorizationHandler, CleanupDirective},
+    crate::{
+        constants::{
+            CHALLENGE_TYPE_TLS_ALPN01, SSM_TIER_ADVANCED, SSM_TIER_INTELLIGENT_TIERING, SSM_TIER_STANDARD,
+            SSM_TYPE_SECURE_STRING,
+        },
+        dry_run::PlannedWrite,
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        utils::ssm_acme_parameter_path,
+        xray::aws_client,
+    },
+    acme2::{Authorization, AuthorizationStatus, Challenge, ChallengeStatus},
+    async_trait::async_trait,
+    lambda_runtime::Error as LambdaError,
+    log::{debug, error, info},
+    openssl::{
+        asn1::Asn1Time,
+        bn::{BigNum, MsbOption},
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        x509::{extension::SubjectAlternativeName, X509Builder, X509Extension, X509NameBuilder, X509},
+    },
+    ring::digest::{digest, SHA256},
+    rusoto_core::Region,
+    rusoto_ssm::{DeleteParameterRequest, PutParameterRequest, Ssm, SsmClient},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::{str::from_utf8, time::Duration},
+    tokio::time::sleep,
+};
+
+/// The id-pe-acmeIdentifier extension holding the key authorization digest (RFC 8737 section 6.1).
+const ACME_IDENTIFIER_OID: &str = "1.3.6.1.5.5.7.1.31";
+
+/// Configuration for TLS-ALPN-01 authorization (RFC 8737), for environments where neither port 80 nor DNS can be
+/// used. In JSON:
+///
+///     {
+///         // The type of authorization to perform. This must be "TlsAlpn".
+///         "Type": "TlsAlpn",
+///
+///         // The KMS key to use to encrypt the parameter value. If not specified, defaults to "aws/ssm"
+///         "KmsKeyId": str,
+///
+///         // The SSM tier to use to store the parameter. Allowed values are "Standard", "Advanced",  and
+///         // "Intelligent-Tiering". This defaults to the account default.
+///         "SsmTier": str,
+///
+///         // Wait this many seconds after writing the challenge certificate before asking the ACME server to
+///         // validate it, e.g. to give the responder time to pick it up. This defaults to 0.
+///         "ValidationDelay": int,
+///     }
+///
+/// This function can't accept TLS connections itself. Instead, the self-signed challenge certificate for each domain
+/// name is written (as a PEM certificate followed by its PEM private key) to the SecureString SSM parameter
+/// `{AcmeParameterPath}/TlsAlpnChallenge/{domain name}` and deleted once the authorization completes. A responder
+/// behind a Network Load Balancer's TCP listener on port 443 must, for a ClientHello offering the "acme-tls/1"
+/// protocol, read the parameter named by the SNI host name, negotiate "acme-tls/1", and present that certificate.
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct TlsAlpnAuthorization {
+    #[serde(rename = "KmsKeyId", default)]
+    pub kms_key_id: Option<String>,
+
+    #[serde(rename = "SsmTier", default)]
+    pub ssm_tier: Option<String>,
+
+    #[serde(rename = "ValidationDelay", default)]
+    pub validation_delay: u64,
+}
+
+impl TlsAlpnAuthorization {
+    /// The parameter holding the challenge certificate for a domain name, for dry runs.
+    pub fn planned_write(&self, domain_name: &str) -> PlannedWrite {
+        PlannedWrite::new("ssm:PutParameter", get_ssm_parameter_for_domain(domain_name))
+    }
+}
+
+#[async_trait]
+impl AuthorizationHandler for TlsAlpnAuthorization {
+    async fn setup(&mut self) -> Result<(), LambdaError> {
+        match &self.ssm_tier {
+            None => (),
+            Some(value) => match value.as_ref() {
+                SSM_TIER_STANDARD | SSM_TIER_ADVANCED | SSM_TIER_INTELLIGENT_TIERING => (),
+                _ => return Err(InvalidCertificateRequest::invalid_ssm_tier(value)),
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn auth(
+        &self,
+        auth: Authorization,
+    ) -> Result<(Authorization, Challenge, Vec<CleanupDirective>), LambdaError> {
+        debug!("Handling authorization: {:?}", auth);
+        let domain_name: &str = &auth.identifier.value;
+        let (challenge, _) = get_challenge_token_for_auth(&auth, CHALLENGE_TYPE_TLS_ALPN01)?;
+
+        let key_auth = match challenge.key_authorization() {
+            Ok(Some(ka)) => ka,
+            Ok(None) => {
+                error!("No {} key authorization found for {}", CHALLENGE_TYPE_TLS_ALPN01, domain_name);
+                return Err(CertificateRequestError::token_not_available(CHALLENGE_TYPE_TLS_ALPN01, domain_name));
+            }
+            Err(e) => {
+                error!("Failed to get ACME key authorization for {}: {}", domain_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let (cert, pkey) = challenge_certificate(domain_name, &key_auth)?;
+        let value = format!("{}{}", from_utf8(&cert.to_pem()?)?, from_utf8(&pkey.private_key_to_pem_pkcs8()?)?);
+
+        // Write the challenge certificate to SSM for the responder.
+        let ssm_client = SsmClient::new_with_client(aws_client(), Region::default());
+        let parameter_name = get_ssm_parameter_for_domain(domain_name);
+
+        let ppr = PutParameterRequest {
+            description: Some(format!("TLS-ALPN-01 challenge certificate for {}", domain_name)),
+            key_id: self.kms_key_id.clone(),
+            name: parameter_name.clone(),
+            overwrite: Some(true),
+            tier: self.ssm_tier.clone(),
+            type_: Some(SSM_TYPE_SECURE_STRING.to_string()),
+            value,
+            ..Default::default()
+        };
+
+        info!("Writing challenge certificate for {} to SSM parameter {}", domain_name, parameter_name);
+        if let Err(e) = ssm_client.put_parameter(ppr).await {
+            error!(
+                "Failed to write challenge certificate for {} to SSM parameter {}: {}",
+                domain_name, parameter_name, e
+            );
+            return Err(Box::new(e));
+        }
+
+        let cleanup = vec![CleanupDirective::DeleteSSMParameter {
+            parameter_name,
+        }];
+
+        if self.validation_delay > 0 {
+            info!("Waiting {} second(s) for the responder to pick up the challenge certificate", self.validation_delay);
+            sleep(Duration::from_secs(self.validation_delay)).await;
+        }
+
+        info!("Informing ACME server that tls-alpn-01 validation is ready for {}", domain_name);
+        if let Err(e) = challenge.validate().await {
+            error!("Failed to send validation request to ACME server for {}: {}", domain_name, e);
+            self.cleanup(cleanup).await?;
+            return Err(Box::new(e));
+        }
+
+        Ok((auth, challenge, cleanup))
+    }
+
+    async fn check(
+        &self,
+        auth: Authorization,
+        challenge: Challenge,
+    ) -> Result<(Authorization, Challenge, bool), LambdaError> {
+        let domain_name: String = auth.identifier.value.clone();
+        let (challenge_result, auth_result) = tokio::join!(challenge.poll(), auth.poll(),);
+
+        let challenge: Challenge = match challenge_result {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Failed to update challenge status for {}: {}", domain_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let auth: Authorization = match auth_result {
+            Ok(a) => a,
+            Err(e) => {
+                error!("Failed to update authorization status for {}: {}", domain_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let challenge_valid: bool = match challenge.status {
+            ChallengeStatus::Invalid => {
+                error!("Challenge for {} is invalid", domain_name);
+                return Err(CertificateRequestError::challenge_failed(domain_name));
+            }
+            ChallengeStatus::Valid => true,
+            _ => false,
+        };
+
+        let auth_valid: bool = match auth.status {
+            AuthorizationStatus::Invalid => {
+                error!("Authorization for {} is invalid", domain_name);
+                return Err(CertificateRequestError::authorization_failed(domain_name));
+            }
+            AuthorizationStatus::Valid => true,
+            _ => false,
+        };
+
+        Ok((auth, challenge, challenge_valid && auth_valid))
+    }
+
+    async fn cleanup(&self, directives: Vec<CleanupDirective>) -> Result<(), LambdaError> {
+        let ssm_client = SsmClient::new_with_client(aws_client(), Region::default());
+        for directive in directives {
+            match directive {
+                CleanupDirective::DeleteSSMParameter {
+                    parameter_name,
+                } => {
+                    let dpr = DeleteParameterRequest {
+                        name: parameter_name.clone(),
+                    };
+
+                    if let Err(e) = ssm_client.delete_parameter(dpr).await {
+                        error!("Failed to delete challenge certificate from SSM parameter {}: {}", parameter_name, e);
+                    }
+                }
+
+                _ => error!("Unsupported cleanup directive: {:?}", directive),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn get_ssm_parameter_for_domain(domain_name: &str) -> String {
+    format!("{}/TlsAlpnChallenge/{}", ssm_acme_parameter_path(), domain_name)
+}
+
+/// Create the self-signed challenge certificate for a domain name: its only subject alternative name is the domain
+/// name, and it carries the SHA-256 digest of the key authorization in a critical acmeIdentifier extension.
+pub fn challenge_certificate(domain_name: &str, key_auth: &str) -> Result<(X509, PKey<Private>), LambdaError> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let pkey = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, "ACME TLS-ALPN-01 challenge")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(127, MsbOption::MAYBE_ZERO, false)?;
+
+    let serial = serial.to_asn1_integer()?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(7)?;
+
+    let mut builder = X509Builder::new()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&pkey)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    let san = SubjectAlternativeName::new().dns(domain_name).build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+
+    // The extension's value is the DER encoding of the digest as an OCTET STRING.
+    let key_auth_digest = digest(&SHA256, key_auth.as_bytes());
+    let digest_hex: Vec<String> = key_auth_digest.as_ref().iter().map(|b| format!("{:02X}", b)).collect();
+    let acme_identifier =
+        X509Extension::new(None, None, ACME_IDENTIFIER_OID, &format!("critical,DER:04:20:{}", digest_hex.join(":")))?;
+    builder.append_extension(acme_identifier)?;
+
+    builder.sign(&pkey, MessageDigest::sha256())?;
+    Ok((builder.build(), pkey))
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::challenge_certificate,
+        ring::digest::{digest, SHA256},
+    };
+
+    #[test]
+    fn test_challenge_certificate() {
+        let key_auth = "token.thumbprint";
+        let (cert, pkey) = challenge_certificate("www.example.com", key_auth).unwrap();
+        assert!(cert.verify(&pkey).unwrap());
+
+        let sans = cert.subject_alt_names().unwrap();
+        assert_eq!(sans.len(), 1);
+        assert_eq!(sans.get(0).unwrap().dnsname(), Some("www.example.com"));
+
+        // The acmeIdentifier OID (1.3.6.1.5.5.7.1.31), the critical flag, and the digest as an OCTET STRING.
+        let der = cert.to_der().unwrap();
+        let mut extension = vec![0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x1f, 0x01, 0x01, 0xff];
+        extension.extend_from_slice(&[0x04, 0x22, 0x04, 0x20]);
+        extension.extend_from_slice(digest(&SHA256, key_auth.as_bytes()).as_ref());
+        assert!(der.windows(extension.len()).any(|window| window == extension.as_slice()));
+    }
+}
@@ -0,0 +1,875 @@
+use {
+    crate::{
+        account::resolve_directory,
+        constants::{DEFAULT_BATCH_MAX_CONCURRENCY, DEFAULT_BATCH_PROGRESS_INTERVAL_SECONDS},
+        errors::InvalidCertificateRequest,
+        events::{CertificateRequest, CertificateResponse, CertificateResponseStatus, Response},
+        lifecycle::{LifecycleEvent, LifecycleEventEmitter},
+        report::RunReport,
+        utils::default_false,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{info, warn},
+    openssl::rand::rand_bytes,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::{
+        collections::{BTreeSet, HashMap},
+        time::{Duration, Instant},
+    },
+};
+
+/// A request for several certificates in a single invocation. For Lambda, this is a JSON structure (annotated
+/// below; do not include comments in your JSON):
+///
+///     {
+///         // The certificate requests to process. See CertificateRequest.
+///         "Certificates": [{ ... }, ...],
+///
+///         // What to do when two requests for the same ACME directory would produce certificates with identical
+///         // domain names, or when one request's domain names are a subset of another's:
+///         //   "Warn" (the default): log a warning, report it in the response, and process both.
+///         //   "Fail": reject the batch before any certificate is requested.
+///         //   "Merge": fold the storage targets of the smaller request into the larger one so a single
+///         //            certificate is issued for both.
+///         "DuplicatePolicy": str,
+///
+///         // The maximum number of requests to process at once. The default is 4.
+///         "MaxConcurrency": int,
+///
+///         // The EventBridge event bus to send BatchProgress events to. If unspecified, this defaults to the
+///         // LifecycleEventBus environment variable; if neither is set, no progress events are sent.
+///         "EventBusName": str,
+///
+///         // Send a BatchProgress event at most once every this many seconds while the batch runs, in addition
+///         // to one when it starts and one when it completes. Set to 0 to disable progress events. The default
+///         // is 60.
+///         "ProgressInterval": int,
+///
+///         // If true, every request in the batch is a dry run (see DryRun in CertificateRequest), whatever its own
+///         // setting, and no progress events are sent. The default is false.
+///         "DryRun": bool,
+///     }
+///
+/// Each request may also specify Id, DependsOn, and Priority (see CertificateRequest). A request starts only after
+/// every request it depends on has succeeded; if one fails, the request is skipped and reported as failed. Requests
+/// without an ordering constraint between them run concurrently, and when more requests are ready than can run,
+/// those with a higher Priority (then those earlier in the batch) start first. For example, to renew the
+/// certificate for an API before the CloudFront distribution that fronts it:
+///
+///     {
+///         "Certificates": [
+///             {"Id": "cdn", "DomainNames": ["www.example.com"], "DependsOn": ["api"], ...},
+///             {"Id": "api", "DomainNames": ["api.example.com"], ...}
+///         ]
+///     }
+///
+/// A bare array of certificate requests is also accepted as a batch with the default settings:
+///
+///     [
+///         {"DomainNames": ["www.example.com"], "Authorization": { ... }, "Storage": [ ... ], ...},
+///         {"DomainNames": ["mail.example.com"], "Authorization": { ... }, "Storage": [ ... ], ...}
+///     ]
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CertificateBatchRequest {
+    #[serde(rename = "Certificates")]
+    pub certificates: Vec<CertificateRequest>,
+
+    #[serde(rename = "DuplicatePolicy", default)]
+    pub duplicate_policy: DuplicatePolicy,
+
+    #[serde(rename = "MaxConcurrency", default = "default_max_concurrency")]
+    pub max_concurrency: usize,
+
+    #[serde(rename = "EventBusName", default)]
+    pub event_bus_name: Option<String>,
+
+    #[serde(rename = "ProgressInterval", default = "default_progress_interval")]
+    pub progress_interval: u64,
+
+    #[serde(rename = "DryRun", default = "default_false")]
+    pub dry_run: bool,
+}
+
+impl From<Vec<CertificateRequest>> for CertificateBatchRequest {
+    fn from(certificates: Vec<CertificateRequest>) -> Self {
+        Self {
+            certificates,
+            duplicate_policy: DuplicatePolicy::default(),
+            max_concurrency: default_max_concurrency(),
+            event_bus_name: None,
+            progress_interval: default_progress_interval(),
+            dry_run: false,
+        }
+    }
+}
+
+fn default_max_concurrency() -> usize {
+    DEFAULT_BATCH_MAX_CONCURRENCY
+}
+
+fn default_progress_interval() -> u64 {
+    DEFAULT_BATCH_PROGRESS_INTERVAL_SECONDS
+}
+
+/// How to handle requests in a batch with overlapping domain names.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum DuplicatePolicy {
+    #[default]
+    Warn,
+    Fail,
+    Merge,
+}
+
+/// How the domain names of two requests overlap.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum OverlapKind {
+    /// Both requests have the same domain names.
+    Identical,
+
+    /// The domain names of the first request are a strict subset of the second's.
+    Subset,
+}
+
+/// A pair of requests in a batch whose certificates would overlap. Indices refer to positions in the batch.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct DomainNameOverlap {
+    #[serde(rename = "Request")]
+    pub request: usize,
+
+    #[serde(rename = "CoveredBy")]
+    pub covered_by: usize,
+
+    #[serde(rename = "Kind")]
+    pub kind: OverlapKind,
+}
+
+impl DomainNameOverlap {
+    fn describe(&self, requests: &[CertificateRequest]) -> String {
+        let relation = match self.kind {
+            OverlapKind::Identical => "has the same domain names as",
+            OverlapKind::Subset => "is covered by",
+        };
+
+        format!(
+            "request {} ({}) {} request {} ({})",
+            self.request,
+            requests[self.request].domain_names.join(" "),
+            relation,
+            self.covered_by,
+            requests[self.covered_by].domain_names.join(" ")
+        )
+    }
+}
+
+/// The response to a batch request. In JSON:
+///
+///     {
+///         // The results for each certificate that was processed, in order.
+///         "Results": [{ ... }, ...],
+///
+///         // Overlapping requests that were detected. See DomainNameOverlap.
+///         "Overlaps": [{ "Request": int, "CoveredBy": int, "Kind": "Identical" | "Subset" }, ...],
+///
+///         // The time and memory used by the batch, with advice on sizing the function. See RunReport.
+///         "Report": { ... },
+///     }
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct BatchResponse {
+    #[serde(rename = "Results")]
+    pub results: Vec<BatchItemResult>,
+
+    #[serde(rename = "Overlaps", default)]
+    pub overlaps: Vec<DomainNameOverlap>,
+
+    #[serde(rename = "Report")]
+    pub report: RunReport,
+}
+
+/// The result of processing one certificate in a batch. Exactly one of Response and Error is set.
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct BatchItemResult {
+    #[serde(rename = "DomainNames")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "Response", default, skip_serializing_if = "Option::is_none")]
+    #[schemars(with = "Option<CertificateResponse>")]
+    pub response: Option<Response>,
+
+    #[serde(rename = "Error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl BatchItemResult {
+    /// Indicates whether the request succeeded, so that requests depending on it may run. A request that is still
+    /// pending validation or order fulfillment has not failed; one that another invocation is already renewing hasn't
+    /// finished yet, so its dependents wait for a later run.
+    fn succeeded(&self) -> bool {
+        match &self.response {
+            None => false,
+            Some(Response::Certificate(response)) => !matches!(
+                response.status,
+                CertificateResponseStatus::Failed
+                    | CertificateResponseStatus::RolledBack
+                    | CertificateResponseStatus::AlreadyInProgress
+            ),
+            Some(_) => true,
+        }
+    }
+}
+
+/// The identifier of a request for DependsOn: its Id, or its first domain name.
+fn request_id(request: &CertificateRequest) -> String {
+    match &request.id {
+        Some(id) => id.clone(),
+        None => {
+            request.domain_names.first().map(|dn| dn.trim_end_matches('.').to_ascii_lowercase()).unwrap_or_default()
+        }
+    }
+}
+
+/// Normalize a set of domain names for comparison: lowercase, without any trailing dot.
+fn san_set(domain_names: &[String]) -> BTreeSet<String> {
+    domain_names.iter().map(|dn| dn.trim_end_matches('.').to_ascii_lowercase()).collect()
+}
+
+/// Find requests whose certificates would duplicate or be covered by another request's certificate. Requests for
+/// different ACME directories are never considered duplicates (e.g. staging and production).
+///
+/// Each request is reported at most once, against the first request that covers it. For identical domain names,
+/// the later request is reported as covered by the earlier one.
+pub fn find_overlaps(requests: &[CertificateRequest]) -> Vec<DomainNameOverlap> {
+    let sets: Vec<BTreeSet<String>> = requests.iter().map(|req| san_set(&req.domain_names)).collect();
+    let directories: Vec<String> = requests.iter().map(|req| resolve_directory(&req.directory)).collect();
+    let mut overlaps = Vec::new();
+
+    for (i, set) in sets.iter().enumerate() {
+        for (j, other) in sets.iter().enumerate() {
+            if i == j || directories[i] != directories[j] {
+                continue;
+            }
+
+            let kind = if set == other {
+                // Only report the later of two identical requests.
+                if i < j {
+                    continue;
+                }
+                OverlapKind::Identical
+            } else if set.is_subset(other) {
+                OverlapKind::Subset
+            } else {
+                continue;
+            };
+
+            overlaps.push(DomainNameOverlap {
+                request: i,
+                covered_by: j,
+                kind,
+            });
+            break;
+        }
+    }
+
+    overlaps
+}
+
+/// Apply the duplicate policy to a batch. Returns the requests to process and the overlaps that were found.
+pub fn apply_duplicate_policy(
+    batch: CertificateBatchRequest,
+) -> Result<(Vec<CertificateRequest>, Vec<DomainNameOverlap>), LambdaError> {
+    let overlaps = find_overlaps(&batch.certificates);
+    if overlaps.is_empty() {
+        return Ok((batch.certificates, overlaps));
+    }
+
+    match batch.duplicate_policy {
+        DuplicatePolicy::Warn => {
+            for overlap in &overlaps {
+                warn!("Duplicate certificate in batch: {}", overlap.describe(&batch.certificates));
+            }
+            Ok((batch.certificates, overlaps))
+        }
+
+        DuplicatePolicy::Fail => {
+            let descriptions: Vec<String> =
+                overlaps.iter().map(|overlap| overlap.describe(&batch.certificates)).collect();
+            Err(InvalidCertificateRequest::duplicate_domain_names(descriptions.join("; ")))
+        }
+
+        DuplicatePolicy::Merge => Ok((merge(batch.certificates, &overlaps), overlaps)),
+    }
+}
+
+/// Fold the storage targets of each covered request into the request that covers it. Chains (A covered by B,
+/// B covered by C) are followed so everything ends up on the request that isn't covered by anything.
+fn merge(requests: Vec<CertificateRequest>, overlaps: &[DomainNameOverlap]) -> Vec<CertificateRequest> {
+    let mut target: Vec<usize> = (0..requests.len()).collect();
+    for overlap in overlaps {
+        target[overlap.request] = overlap.covered_by;
+    }
+
+    // Resolve chains. Each request points at most once, and identical requests always point to an earlier index,
+    // so this terminates.
+    for i in 0..target.len() {
+        let mut t = target[i];
+        while target[t] != t {
+            t = target[t];
+        }
+        target[i] = t;
+    }
+
+    // Requests that depended on a covered request now depend on the request it was merged into.
+    let ids: Vec<String> = requests.iter().map(request_id).collect();
+    let mut aliases: HashMap<String, String> = HashMap::new();
+
+    let mut slots: Vec<Option<CertificateRequest>> = requests.into_iter().map(Some).collect();
+    for i in 0..slots.len() {
+        if target[i] == i {
+            continue;
+        }
+
+        let covered = slots[i].take().expect("request should not have been merged yet");
+        let into = slots[target[i]].as_mut().expect("merge target should not have been merged");
+        info!(
+            "Merging storage for {} into the request for {}",
+            covered.domain_names.join(" "),
+            into.domain_names.join(" ")
+        );
+        into.storage.extend(covered.storage);
+        into.hooks.extend(covered.hooks);
+        into.depends_on.extend(covered.depends_on);
+        into.priority = into.priority.max(covered.priority);
+        aliases.insert(ids[i].clone(), ids[target[i]].clone());
+    }
+
+    let mut merged: Vec<CertificateRequest> = slots.into_iter().flatten().collect();
+    for request in merged.iter_mut() {
+        let id = request_id(request);
+        let mut depends_on: Vec<String> = Vec::with_capacity(request.depends_on.len());
+        for dependency in request.depends_on.drain(..) {
+            let dependency = aliases.get(&dependency).cloned().unwrap_or(dependency);
+            if dependency != id && !depends_on.contains(&dependency) {
+                depends_on.push(dependency);
+            }
+        }
+        request.depends_on = depends_on;
+    }
+
+    merged
+}
+
+/// The state of a request in a BatchSchedule.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ScheduleState {
+    Waiting,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// Decides the order in which the requests of a batch run, honoring their DependsOn and Priority, and collects
+/// their results.
+pub struct BatchSchedule {
+    ids: Vec<String>,
+    domain_names: Vec<Vec<String>>,
+    priorities: Vec<i32>,
+
+    /// The indices of the requests each request depends on.
+    dependencies: Vec<Vec<usize>>,
+
+    states: Vec<ScheduleState>,
+    results: Vec<Option<BatchItemResult>>,
+}
+
+impl BatchSchedule {
+    /// Resolve the dependencies of each request, failing if any refer to an unknown or ambiguous request or form a
+    /// cycle.
+    pub fn new(requests: &[CertificateRequest]) -> Result<Self, LambdaError> {
+        let ids: Vec<String> = requests.iter().map(request_id).collect();
+
+        let mut explicit_ids = BTreeSet::new();
+        for id in requests.iter().filter_map(|request| request.id.as_ref()) {
+            if !explicit_ids.insert(id) {
+                return Err(InvalidCertificateRequest::invalid_batch_configuration(format!("Duplicate Id: {}", id)));
+            }
+        }
+
+        let mut dependencies = Vec::with_capacity(requests.len());
+        for (i, request) in requests.iter().enumerate() {
+            let mut resolved = Vec::with_capacity(request.depends_on.len());
+            for dependency in &request.depends_on {
+                let matches: Vec<usize> = (0..ids.len()).filter(|&j| &ids[j] == dependency).collect();
+                match matches.as_slice() {
+                    [] => {
+                        return Err(InvalidCertificateRequest::invalid_batch_configuration(format!(
+                            "Request {} depends on unknown request {}",
+                            ids[i], dependency
+                        )))
+                    }
+                    [j] if *j == i => {
+                        return Err(InvalidCertificateRequest::invalid_batch_configuration(format!(
+                            "Request {} depends on itself",
+                            ids[i]
+                        )))
+                    }
+                    [j] => resolved.push(*j),
+                    _ => {
+                        return Err(InvalidCertificateRequest::invalid_batch_configuration(format!(
+                            "Request {} depends on {}, which matches more than one request; give it a unique Id",
+                            ids[i], dependency
+                        )))
+                    }
+                }
+            }
+            dependencies.push(resolved);
+        }
+
+        let schedule = Self {
+            ids,
+            domain_names: requests.iter().map(|request| request.domain_names.clone()).collect(),
+            priorities: requests.iter().map(|request| request.priority).collect(),
+            dependencies,
+            states: vec![ScheduleState::Waiting; requests.len()],
+            results: requests.iter().map(|_| None).collect(),
+        };
+        schedule.check_acyclic()?;
+        Ok(schedule)
+    }
+
+    /// Check that the dependencies form no cycles by repeatedly removing requests whose dependencies have all been
+    /// removed.
+    fn check_acyclic(&self) -> Result<(), LambdaError> {
+        let mut removed = vec![false; self.ids.len()];
+        loop {
+            let ready: Vec<usize> = (0..self.ids.len())
+                .filter(|&i| !removed[i] && self.dependencies[i].iter().all(|&j| removed[j]))
+                .collect();
+            if ready.is_empty() {
+                break;
+            }
+            for i in ready {
+                removed[i] = true;
+            }
+        }
+
+        let cycle: Vec<&str> = (0..self.ids.len()).filter(|&i| !removed[i]).map(|i| self.ids[i].as_str()).collect();
+        if cycle.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidCertificateRequest::invalid_batch_configuration(format!(
+                "Circular DependsOn among requests {}",
+                cycle.join(", ")
+            )))
+        }
+    }
+
+    /// Returns the next request to start, if any is ready, and marks it as running.
+    pub fn next_ready(&mut self) -> Option<usize> {
+        let next = (0..self.ids.len())
+            .filter(|&i| {
+                self.states[i] == ScheduleState::Waiting
+                    && self.dependencies[i].iter().all(|&j| self.states[j] == ScheduleState::Succeeded)
+            })
+            .max_by_key(|&i| (self.priorities[i], std::cmp::Reverse(i)))?;
+        self.states[next] = ScheduleState::Running;
+        Some(next)
+    }
+
+    /// Record the result of a request. If it failed, every request that depends on it (directly or indirectly) is
+    /// skipped and reported as failed.
+    pub fn record(&mut self, index: usize, result: BatchItemResult) {
+        let succeeded = result.succeeded();
+        self.states[index] = if succeeded {
+            ScheduleState::Succeeded
+        } else {
+            ScheduleState::Failed
+        };
+        self.results[index] = Some(result);
+
+        if succeeded {
+            return;
+        }
+
+        let mut failed = vec![index];
+        while let Some(failed_index) = failed.pop() {
+            for i in 0..self.ids.len() {
+                if self.states[i] == ScheduleState::Waiting && self.dependencies[i].contains(&failed_index) {
+                    warn!("Skipping request {} because request {} failed", self.ids[i], self.ids[failed_index]);
+                    self.states[i] = ScheduleState::Failed;
+                    self.results[i] = Some(BatchItemResult {
+                        domain_names: self.domain_names[i].clone(),
+                        response: None,
+                        error: Some(format!("Skipped because request {} failed", self.ids[failed_index])),
+                    });
+                    failed.push(i);
+                }
+            }
+        }
+    }
+
+    /// Returns the results of the requests that were processed, in batch order.
+    pub fn into_results(self) -> Vec<BatchItemResult> {
+        self.results.into_iter().flatten().collect()
+    }
+
+    /// Returns how far the batch has progressed.
+    pub fn progress(&self, batch_id: &str, phase: BatchPhase, elapsed: Duration) -> BatchProgress {
+        let count = |state| self.states.iter().filter(|&&s| s == state).count();
+        let succeeded = count(ScheduleState::Succeeded);
+        let failed = count(ScheduleState::Failed);
+
+        BatchProgress {
+            batch_id: batch_id.to_string(),
+            phase,
+            total: self.ids.len(),
+            completed: succeeded + failed,
+            succeeded,
+            failed,
+            running: (0..self.ids.len())
+                .filter(|&i| self.states[i] == ScheduleState::Running)
+                .map(|i| self.ids[i].clone())
+                .collect(),
+            elapsed_seconds: elapsed.as_secs(),
+        }
+    }
+}
+
+/// The phase of a running batch.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum BatchPhase {
+    /// The requests are being validated; none have started.
+    Validating,
+
+    /// The requests are being processed.
+    Running,
+
+    /// Every request has finished or been skipped.
+    Completed,
+}
+
+/// The detail of a BatchProgress lifecycle event. In JSON:
+///
+///     {
+///         // Identifies the batch run. Every event from a run has the same id.
+///         "BatchId": str,
+///
+///         // "Validating", "Running", or "Completed".
+///         "Phase": str,
+///
+///         // The number of requests in the batch (after any merging by DuplicatePolicy), and how many of them have
+///         // finished. Requests skipped because a dependency failed are counted as failed.
+///         "Total": int,
+///         "Completed": int,
+///         "Succeeded": int,
+///         "Failed": int,
+///
+///         // The requests being processed. See CertificateRequest.Id.
+///         "Running": [str, ...],
+///
+///         // The number of seconds since the batch started.
+///         "ElapsedSeconds": int,
+///     }
+///
+/// Events are sent on a timer rather than as requests finish, so a batch whose Completed count stops changing
+/// between events is stalled on the requests listed in Running.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct BatchProgress {
+    #[serde(rename = "BatchId")]
+    pub batch_id: String,
+
+    #[serde(rename = "Phase")]
+    pub phase: BatchPhase,
+
+    #[serde(rename = "Total")]
+    pub total: usize,
+
+    #[serde(rename = "Completed")]
+    pub completed: usize,
+
+    #[serde(rename = "Succeeded")]
+    pub succeeded: usize,
+
+    #[serde(rename = "Failed")]
+    pub failed: usize,
+
+    #[serde(rename = "Running")]
+    pub running: Vec<String>,
+
+    #[serde(rename = "ElapsedSeconds")]
+    pub elapsed_seconds: u64,
+}
+
+/// Sends the BatchProgress events of a batch run.
+pub struct BatchProgressReporter {
+    emitter: LifecycleEventEmitter,
+    batch_id: String,
+    interval: Duration,
+    started: Instant,
+    last_sent: Instant,
+}
+
+impl BatchProgressReporter {
+    /// Returns a reporter for the batch, or None if progress events are disabled.
+    pub fn new(batch: &CertificateBatchRequest) -> Result<Option<Self>, LambdaError> {
+        if batch.progress_interval == 0 || batch.dry_run {
+            return Ok(None);
+        }
+
+        let emitter = match LifecycleEventEmitter::resolve(batch.event_bus_name.clone()) {
+            Some(emitter) => emitter,
+            None => return Ok(None),
+        };
+
+        let mut batch_id = [0u8; 8];
+        rand_bytes(&mut batch_id)?;
+        let batch_id: String = batch_id.iter().map(|b| format!("{:02x}", b)).collect();
+        info!("Reporting progress of batch {} to event bus {}", batch_id, emitter.event_bus_name);
+
+        let now = Instant::now();
+        Ok(Some(Self {
+            emitter,
+            batch_id,
+            interval: Duration::from_secs(batch.progress_interval),
+            started: now,
+            last_sent: now,
+        }))
+    }
+
+    /// Returns how long until the next periodic event is due.
+    pub fn until_next(&self) -> Duration {
+        self.interval.checked_sub(self.last_sent.elapsed()).unwrap_or_default()
+    }
+
+    /// Send a BatchProgress event for the current state of the schedule.
+    pub async fn send(&mut self, phase: BatchPhase, schedule: &BatchSchedule) {
+        let progress = schedule.progress(&self.batch_id, phase, self.started.elapsed());
+        info!(
+            "Batch {} {:?}: {} of {} complete ({} failed)",
+            self.batch_id, phase, progress.completed, progress.total, progress.failed
+        );
+        self.emitter.emit(vec![LifecycleEvent::batch_progress(&progress)]).await;
+        self.last_sent = Instant::now();
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{
+            apply_duplicate_policy, find_overlaps, BatchItemResult, BatchPhase, BatchSchedule, CertificateBatchRequest,
+            DuplicatePolicy, OverlapKind,
+        },
+        crate::{
+            constants::DEFAULT_BATCH_MAX_CONCURRENCY,
+            events::{CertificateRequest, EventResponse, Request, Response},
+        },
+        serde_json::json,
+        std::time::Duration,
+    };
+
+    fn request(directory: &str, domain_names: &[&str]) -> CertificateRequest {
+        serde_json::from_value(json!({
+            "Directory": directory,
+            "DomainNames": domain_names,
+            "Contacts": ["mailto:hello@example.com"],
+            "Authorization": {"Type": "DnsRoute53"},
+            "Storage": [{"Type": "Acm"}],
+        }))
+        .unwrap()
+    }
+
+    const STAGING: &str = "https://acme-staging-v02.api.letsencrypt.org/directory";
+    const PRODUCTION: &str = "https://acme-v02.api.letsencrypt.org/directory";
+
+    #[test]
+    fn test_batch_list() {
+        let request = |domain_name| {
+            json!({
+                "Directory": STAGING,
+                "DomainNames": [domain_name],
+                "Contacts": ["mailto:hello@example.com"],
+                "Authorization": {"Type": "DnsRoute53"},
+                "Storage": [{"Type": "Acm"}],
+            })
+        };
+
+        let requests = match serde_json::from_value(json!([request("www.example.com"), request("api.example.com")])) {
+            Ok(Request::BatchList(requests)) => requests,
+            other => panic!("Expected a batch list: {:?}", other),
+        };
+
+        let batch = CertificateBatchRequest::from(requests);
+        assert_eq!(batch.certificates.len(), 2);
+        assert_eq!(batch.certificates[1].domain_names, vec!["api.example.com".to_string()]);
+        assert_eq!(batch.duplicate_policy, DuplicatePolicy::Warn);
+        assert_eq!(batch.max_concurrency, DEFAULT_BATCH_MAX_CONCURRENCY);
+    }
+
+    #[test]
+    fn test_find_overlaps() {
+        let requests = vec![
+            request(STAGING, &["example.com", "www.example.com"]),
+            request(STAGING, &["WWW.example.com.", "example.com"]),
+            request(STAGING, &["example.com"]),
+            request(PRODUCTION, &["example.com"]),
+            request(STAGING, &["api.example.com"]),
+        ];
+
+        let overlaps = find_overlaps(&requests);
+        assert_eq!(overlaps.len(), 2, "{:?}", overlaps);
+        assert_eq!((overlaps[0].request, overlaps[0].covered_by, overlaps[0].kind), (1, 0, OverlapKind::Identical));
+        assert_eq!((overlaps[1].request, overlaps[1].covered_by, overlaps[1].kind), (2, 0, OverlapKind::Subset));
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let batch = |policy| CertificateBatchRequest {
+            certificates: vec![
+                request(STAGING, &["example.com", "www.example.com"]),
+                request(STAGING, &["www.example.com"]),
+                request(STAGING, &["api.example.com"]),
+            ],
+            duplicate_policy: policy,
+            max_concurrency: 1,
+            event_bus_name: None,
+            progress_interval: 60,
+            dry_run: false,
+        };
+
+        let (requests, overlaps) = apply_duplicate_policy(batch(DuplicatePolicy::Warn)).unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(overlaps.len(), 1);
+
+        assert!(apply_duplicate_policy(batch(DuplicatePolicy::Fail)).is_err());
+
+        let (requests, _) = apply_duplicate_policy(batch(DuplicatePolicy::Merge)).unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].storage.len(), 2);
+        assert_eq!(requests[1].domain_names, vec!["api.example.com".to_string()]);
+    }
+
+    fn with_dependencies(
+        mut request: CertificateRequest,
+        id: &str,
+        depends_on: &[&str],
+        priority: i32,
+    ) -> CertificateRequest {
+        request.id = Some(id.to_string());
+        request.depends_on = depends_on.iter().map(|s| s.to_string()).collect();
+        request.priority = priority;
+        request
+    }
+
+    fn result(ok: bool) -> BatchItemResult {
+        if ok {
+            BatchItemResult {
+                domain_names: vec![],
+                response: Some(Response::Event(EventResponse {
+                    handled: true,
+                    message: String::new(),
+                })),
+                error: None,
+            }
+        } else {
+            BatchItemResult {
+                domain_names: vec![],
+                response: None,
+                error: Some("failed".to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_schedule() {
+        let requests = vec![
+            with_dependencies(request(STAGING, &["www.example.com"]), "cdn", &["api"], 0),
+            with_dependencies(request(STAGING, &["api.example.com"]), "api", &[], 0),
+            with_dependencies(request(STAGING, &["mail.example.com"]), "mail", &[], 5),
+            with_dependencies(request(STAGING, &["static.example.com"]), "static", &["cdn"], 0),
+        ];
+
+        let mut schedule = BatchSchedule::new(&requests).unwrap();
+        assert_eq!(schedule.next_ready(), Some(2));
+        assert_eq!(schedule.next_ready(), Some(1));
+        assert_eq!(schedule.next_ready(), None);
+
+        schedule.record(1, result(true));
+        assert_eq!(schedule.next_ready(), Some(0));
+        schedule.record(0, result(false));
+        assert_eq!(schedule.next_ready(), None);
+        schedule.record(2, result(false));
+
+        let results = schedule.into_results();
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[3].error.as_deref(), Some("Skipped because request cdn failed"));
+
+        // Unknown, circular, and self dependencies are rejected.
+        let invalid = |depends_on: &[&str]| {
+            BatchSchedule::new(&[
+                with_dependencies(request(STAGING, &["a.example.com"]), "a", depends_on, 0),
+                with_dependencies(request(STAGING, &["b.example.com"]), "b", &["a"], 0),
+            ])
+            .is_err()
+        };
+        assert!(!invalid(&[]));
+        assert!(invalid(&["c"]));
+        assert!(invalid(&["b"]));
+        assert!(invalid(&["a"]));
+
+        // Without an Id, a request is referred to by its first domain name.
+        let requests = vec![
+            with_dependencies(request(STAGING, &["www.example.com"]), "cdn", &["api.example.com"], 0),
+            request(STAGING, &["API.example.com"]),
+        ];
+        assert!(BatchSchedule::new(&requests).is_ok());
+    }
+
+    #[test]
+    fn test_batch_progress() {
+        let requests = vec![
+            with_dependencies(request(STAGING, &["www.example.com"]), "cdn", &["api"], 0),
+            with_dependencies(request(STAGING, &["api.example.com"]), "api", &[], 0),
+            with_dependencies(request(STAGING, &["mail.example.com"]), "mail", &[], 0),
+        ];
+
+        let mut schedule = BatchSchedule::new(&requests).unwrap();
+        assert_eq!(schedule.next_ready(), Some(1));
+        assert_eq!(schedule.next_ready(), Some(2));
+        schedule.record(1, result(false));
+
+        let progress = schedule.progress("0123", BatchPhase::Running, Duration::from_secs(90));
+        assert_eq!(progress.total, 3);
+        assert_eq!(progress.completed, 2);
+        assert_eq!(progress.succeeded, 0);
+        assert_eq!(progress.failed, 2);
+        assert_eq!(progress.running, vec!["mail".to_string()]);
+
+        let detail = json!(progress);
+        assert_eq!(detail["Phase"], json!("Running"));
+        assert_eq!(detail["ElapsedSeconds"], json!(90));
+    }
+
+    #[test]
+    fn test_merge_dependencies() {
+        let batch = CertificateBatchRequest {
+            certificates: vec![
+                request(STAGING, &["example.com", "www.example.com"]),
+                with_dependencies(request(STAGING, &["www.example.com"]), "www", &["api.example.com"], 3),
+                request(STAGING, &["api.example.com"]),
+                with_dependencies(request(STAGING, &["static.example.com"]), "static", &["www"], 0),
+            ],
+            duplicate_policy: DuplicatePolicy::Merge,
+            max_concurrency: 1,
+            event_bus_name: None,
+            progress_interval: 60,
+            dry_run: false,
+        };
+
+        let (requests, _) = apply_duplicate_policy(batch).unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].depends_on, vec!["api.example.com".to_string()]);
+        assert_eq!(requests[0].priority, 3);
+        assert_eq!(requests[2].depends_on, vec!["example.com".to_string()]);
+    }
+}
@@ -0,0 +1,32 @@
+#![warn(clippy::all)]
+#![allow(clippy::redundant_field_names)]
+
+use {
+    env_logger::Env,
+    letsencrypt_certs_aws::{run_cli, CliOptions},
+};
+
+/// Entrypoint for the command-line tool. This runs a request document with local AWS credentials and prints the
+/// response; see CliOptions. The exit status is 0 if the request succeeded, 1 if it failed, and 2 if the arguments
+/// or request document were invalid.
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match CliOptions::from_args(&args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
+
+    match run_cli(&options).await {
+        Ok(true) => (),
+        Ok(false) => std::process::exit(1),
+        Err(e) => {
+            eprintln!("Request failed: {:#}", e);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use {
+    crate::errors::InvalidCertificateRequest,
+    lambda_runtime::Error as LambdaError,
+    log::{debug, info},
+    openssl::{
+        nid::Nid,
+        stack::Stack,
+        x509::{store::X509StoreBuilder, X509NameRef, X509StoreContext, X509},
+    },
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::str::from_utf8,
+};
+
+/// Which chain a storage target receives. In JSON, this is the "Chain" key of a storage target:
+///
+/// * `"Default"`: the chain served by the ACME server. This is the default.
+/// * `"Alternate"`: the shorter alternate chain offered during a CA chain transition (see find_alternate_chain).
+///   Targets fall back to the default chain when the CA isn't offering an alternate.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum ChainVariant {
+    #[default]
+    Default,
+    Alternate,
+}
+
+/// An intermediate certificate chain in PEM format, along with the leaf certificate concatenated with it.
+#[derive(Clone, Debug)]
+pub struct CertificateChain {
+    pub chain_pem: String,
+    pub fullchain_pem: String,
+}
+
+impl CertificateChain {
+    /// Create a chain from the leaf certificate PEM and the intermediate certificates.
+    pub fn from_certs(cert_pem: &str, intermediates: &[X509]) -> Result<Self, LambdaError> {
+        let mut intermediates_pem = Vec::with_capacity(intermediates.len());
+        for cert in intermediates {
+            intermediates_pem.push(from_utf8(&cert.to_pem()?)?.to_string());
+        }
+
+        let chain_pem = intermediates_pem.join("\n");
+        let fullchain_pem = format!("{}\n{}", cert_pem, chain_pem);
+
+        Ok(Self {
+            chain_pem,
+            fullchain_pem,
+        })
+    }
+}
+
+/// Find the alternate chain for a certificate during a CA chain transition.
+///
+/// During a transition, CAs serve a long default chain that ends in a cross-signed certificate (so older clients
+/// that only trust the old root keep working) and offer a shorter alternate chain that ends at the new root. The
+/// ACME client only downloads the default chain, so the alternate is found by trimming certificates from the end
+/// of the default chain: the shortest prefix that still verifies against the system trust store is the alternate.
+/// Outside of a transition, no shorter prefix verifies and None is returned.
+///
+/// `certs` is the default chain as returned by the ACME server, starting with the leaf certificate.
+pub fn find_alternate_chain(certs: &[X509]) -> Result<Option<Vec<X509>>, LambdaError> {
+    if certs.len() < 3 {
+        return Ok(None);
+    }
+
+    let mut store_builder = X509StoreBuilder::new()?;
+    store_builder.set_default_paths()?;
+    let store = store_builder.build();
+
+    for len in 2..certs.len() {
+        let mut intermediates = Stack::new()?;
+        for cert in &certs[1..len] {
+            intermediates.push(cert.clone())?;
+        }
+
+        let mut context = X509StoreContext::new()?;
+        let verified = context.init(&store, &certs[0], &intermediates, |c| c.verify_cert())?;
+        debug!("Chain prefix of length {} verified: {}", len, verified);
+
+        if verified {
+            info!("Found alternate chain with {} intermediate certificate(s)", len - 1);
+            return Ok(Some(certs[1..len].to_vec()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Select the chain ending at the root CA with the given common name.
+///
+/// The candidates are the default chain and each chain formed by trimming certificates from its end, longest first;
+/// the CA's alternate chains are shortened forms of its default chain (see find_alternate_chain). A candidate ends
+/// at the root that issued its topmost certificate. The first candidate whose root's common name matches is
+/// returned, starting with the leaf certificate. None is returned if no candidate matches.
+pub fn select_preferred_chain(certs: &[X509], root_cn: &str) -> Result<Option<Vec<X509>>, LambdaError> {
+    for len in (2..=certs.len()).rev() {
+        let issuer_cn = common_name(certs[len - 1].issuer_name());
+        debug!("Chain prefix of length {} ends at {:?}", len, issuer_cn);
+
+        if issuer_cn.as_deref() == Some(root_cn) {
+            if len < certs.len() {
+                info!("Selected chain ending at {} with {} intermediate certificate(s)", root_cn, len - 1);
+            }
+            return Ok(Some(certs[..len].to_vec()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Check the common name of a preferred root CA.
+pub fn validate_preferred_chain(root_cn: &str) -> Result<(), LambdaError> {
+    if root_cn.trim().is_empty() {
+        Err(InvalidCertificateRequest::invalid_preferred_chain("PreferredChain cannot be empty"))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn common_name(name: &X509NameRef) -> Option<String> {
+    name.entries_by_nid(Nid::COMMONNAME).next().and_then(|entry| entry.data().as_utf8().ok()).map(|cn| cn.to_string())
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{select_preferred_chain, validate_preferred_chain},
+        crate::keys::KeyAlgorithm,
+        openssl::{
+            asn1::Asn1Time,
+            hash::MessageDigest,
+            nid::Nid,
+            x509::{X509Builder, X509NameBuilder, X509},
+        },
+    };
+
+    fn cert(subject_cn: &str, issuer_cn: &str) -> X509 {
+        let pkey = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let name = |cn: &str| {
+            let mut name = X509NameBuilder::new().unwrap();
+            name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+            name.build()
+        };
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name(subject_cn)).unwrap();
+        builder.set_issuer_name(&name(issuer_cn)).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(90).unwrap()).unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn test_select_preferred_chain() {
+        let certs = vec![cert("example.com", "R3"), cert("R3", "ISRG Root X1"), cert("ISRG Root X1", "DST Root CA X3")];
+
+        let chain = select_preferred_chain(&certs, "DST Root CA X3").unwrap().unwrap();
+        assert_eq!(chain.len(), 3);
+
+        let chain = select_preferred_chain(&certs, "ISRG Root X1").unwrap().unwrap();
+        assert_eq!(chain.len(), 2);
+        assert_eq!(chain[1].to_der().unwrap(), certs[1].to_der().unwrap());
+
+        // The leaf alone is never a chain.
+        assert!(select_preferred_chain(&certs, "R3").unwrap().is_none());
+        assert!(select_preferred_chain(&certs, "ISRG Root X2").unwrap().is_none());
+
+        assert!(validate_preferred_chain("ISRG Root X1").is_ok());
+        assert!(validate_preferred_chain(" ").is_err());
+    }
+}
@@ -0,0 +1,172 @@
+use {
+    crate::{errors::CertificateRequestError, events::Request, handle_request, report::RunBudget},
+    lambda_runtime::Error as LambdaError,
+    serde_json::Value,
+    std::{
+        env::set_var,
+        fs::read_to_string,
+        io::{stdin, Read},
+    },
+};
+
+pub const CLI_USAGE: &str = "Usage: letsencrypt-certs-cli [--profile NAME] [--region REGION] [--dry-run] [--compact] \
+                             REQUEST_FILE";
+
+/// Options for the command-line tool, which runs a request document from a laptop or CI runner using local AWS
+/// credentials, e.g. to issue the first certificates before the Lambda function is deployed. The request is exactly
+/// what would be passed to the Lambda function; its response is printed to standard output.
+#[derive(Debug)]
+pub struct CliOptions {
+    /// The AWS profile to use instead of the default credentials chain.
+    pub profile: Option<String>,
+
+    /// The AWS region to use instead of the default for the profile.
+    pub region: Option<String>,
+
+    /// Run certificate and batch requests with DryRun set.
+    pub dry_run: bool,
+
+    /// Print the response on a single line instead of pretty-printing it.
+    pub compact: bool,
+
+    /// The request to run.
+    pub request: Value,
+}
+
+impl CliOptions {
+    /// Parse the command-line arguments (without the program name). A request file of "-" is read from standard
+    /// input.
+    pub fn from_args(args: &[String]) -> Result<Self, LambdaError> {
+        let mut profile = None;
+        let mut region = None;
+        let mut dry_run = false;
+        let mut compact = false;
+        let mut request_file = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next().cloned().ok_or_else(|| {
+                    CertificateRequestError::invalid_cli_options(format!("{} requires a value; {}", arg, CLI_USAGE))
+                })
+            };
+
+            match arg.as_str() {
+                "--profile" => profile = Some(value()?),
+                "--region" => region = Some(value()?),
+                "--dry-run" => dry_run = true,
+                "--compact" => compact = true,
+                _ if arg.starts_with("--") => {
+                    return Err(CertificateRequestError::invalid_cli_options(format!(
+                        "Unknown option {}; {}",
+                        arg, CLI_USAGE
+                    )))
+                }
+                _ if request_file.is_none() => request_file = Some(arg.clone()),
+                _ => {
+                    return Err(CertificateRequestError::invalid_cli_options(format!(
+                        "Only one request file can be given; {}",
+                        CLI_USAGE
+                    )))
+                }
+            }
+        }
+
+        let request_file = request_file.ok_or_else(|| {
+            CertificateRequestError::invalid_cli_options(format!("A request file is required; {}", CLI_USAGE))
+        })?;
+
+        let request = if request_file == "-" {
+            let mut request = String::new();
+            stdin().read_to_string(&mut request)?;
+            request
+        } else {
+            read_to_string(&request_file)?
+        };
+
+        let mut options = Self {
+            profile,
+            region,
+            dry_run,
+            compact,
+            request: serde_json::from_str(&request)?,
+        };
+        options.validate()?;
+
+        if options.dry_run {
+            set_dry_run(&mut options.request);
+        }
+
+        Ok(options)
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        match serde_json::from_value::<Request>(self.request.clone()) {
+            Ok(Request::Certificate(_)) | Ok(Request::Batch(_)) | Ok(Request::BatchList(_)) => Ok(()),
+            Ok(_) if self.dry_run => Err(CertificateRequestError::invalid_cli_options(
+                "--dry-run requires a certificate request, batch request, or array of certificate requests",
+            )),
+            Ok(_) => Ok(()),
+            Err(e) => Err(CertificateRequestError::invalid_cli_options(format!("Invalid request: {}", e))),
+        }
+    }
+
+    /// Point the AWS clients at the requested profile and region. This must be called before any AWS client is
+    /// created.
+    pub fn configure_aws(&self) {
+        if let Some(profile) = &self.profile {
+            set_var("AWS_PROFILE", profile);
+        }
+
+        if let Some(region) = &self.region {
+            set_var("AWS_DEFAULT_REGION", region);
+            set_var("AWS_REGION", region);
+        }
+    }
+}
+
+/// Set DryRun on a certificate request, a batch request, or each request in an array of certificate requests.
+fn set_dry_run(request: &mut Value) {
+    match request {
+        Value::Array(requests) => requests.iter_mut().for_each(set_dry_run),
+        Value::Object(request) => {
+            request.insert("DryRun".to_string(), Value::Bool(true));
+        }
+        _ => (),
+    }
+}
+
+/// Run the request given on the command line, printing its response. This returns false if the request or any
+/// certificate in it failed outright.
+pub async fn run_cli(options: &CliOptions) -> Result<bool, LambdaError> {
+    options.configure_aws();
+
+    let budget = RunBudget::new(None);
+    let response = handle_request(options.request.clone(), &budget).await?;
+    let output = if options.compact {
+        serde_json::to_string(&response)?
+    } else {
+        serde_json::to_string_pretty(&response)?
+    };
+    println!("{}", output);
+
+    Ok(response.succeeded())
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {super::set_dry_run, serde_json::json};
+
+    #[test]
+    fn test_set_dry_run() {
+        let mut request = json!({"DomainNames": ["example.com"]});
+        set_dry_run(&mut request);
+        assert_eq!(request["DryRun"], true);
+
+        let mut requests = json!([{"DomainNames": ["example.com"]}, {"DomainNames": ["example.net"], "DryRun": false}]);
+        set_dry_run(&mut requests);
+        assert_eq!(requests[0]["DryRun"], true);
+        assert_eq!(requests[1]["DryRun"], true);
+    }
+}
@@ -0,0 +1,184 @@
+use {
+    crate::{
+        constants::{ENV_CONFIG_SOURCE, EVENT_DETAIL_TYPE_SCHEDULED_EVENT, EVENT_SOURCE_SCHEDULER},
+        errors::CertificateRequestError,
+        events::Request,
+        secrets::{resolve_secret, validate_secret_reference, DefaultSecretStore},
+        storage::get_s3_object_string,
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::info,
+    rusoto_core::Region,
+    rusoto_s3::S3Client,
+    serde_json::Value,
+    std::{
+        env::var,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// Where the request document is loaded from when the function is invoked without one, so an EventBridge schedule
+/// can invoke it with no custom input and the request can be changed without redeploying the rule. This is set with
+/// the `ConfigSource` environment variable, which is one of:
+///
+/// * `s3://<bucket>/<key>`: an S3 object.
+/// * `ssm:<name>` or an SSM parameter ARN: an SSM parameter, decrypted if it is a SecureString.
+/// * `secretsmanager:<name or ARN>` or a Secrets Manager ARN: a Secrets Manager secret string.
+/// * Any other secret reference (see SecretBackend); a reference with no prefix names an SSM parameter.
+///
+/// The document is a certificate request, batch request, or array of certificate requests, exactly as it would be
+/// passed to the function. It is read on every invocation that uses it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConfigSource {
+    S3 {
+        bucket: String,
+        key: String,
+    },
+    Secret(String),
+}
+
+impl ConfigSource {
+    /// Returns the configuration source named by the ConfigSource environment variable, or None if it isn't set.
+    pub fn from_env() -> Result<Option<Self>, LambdaError> {
+        match var(ENV_CONFIG_SOURCE) {
+            Ok(source) if !source.is_empty() => Ok(Some(Self::parse(&source)?)),
+            _ => Ok(None),
+        }
+    }
+
+    fn parse(source: &str) -> Result<Self, LambdaError> {
+        if let Some(path) = source.strip_prefix("s3://") {
+            return match path.split_once('/') {
+                Some((bucket, key)) if !bucket.is_empty() && !key.is_empty() => Ok(Self::S3 {
+                    bucket: bucket.to_string(),
+                    key: key.to_string(),
+                }),
+                _ => Err(CertificateRequestError::invalid_config_source(format!(
+                    "S3 sources must be given as s3://<bucket>/<key>: {}",
+                    source
+                ))),
+            };
+        }
+
+        match validate_secret_reference(source, DefaultSecretStore::Ssm) {
+            Ok(()) => Ok(Self::Secret(source.to_string())),
+            Err(e) => Err(CertificateRequestError::invalid_config_source(format!("{}: {}", source, e))),
+        }
+    }
+
+    /// Read the request document and check that it is a certificate request, batch request, or array of certificate
+    /// requests.
+    pub async fn load(&self) -> Result<Value, LambdaError> {
+        let document = match self {
+            Self::S3 {
+                bucket,
+                key,
+            } => {
+                let s3 = S3Client::new_with_client(aws_client(), Region::default());
+                match get_s3_object_string(&s3, bucket, key.clone()).await? {
+                    Some(document) => document,
+                    None => {
+                        return Err(CertificateRequestError::invalid_config_source(format!(
+                            "s3://{}/{} does not exist",
+                            bucket, key
+                        )))
+                    }
+                }
+            }
+            Self::Secret(reference) => resolve_secret(reference, DefaultSecretStore::Ssm).await?.0,
+        };
+
+        let request: Value = serde_json::from_str(&document)
+            .map_err(|e| CertificateRequestError::invalid_config_source(format!("{}: {}", self, e)))?;
+
+        match serde_json::from_value(request.clone()) {
+            Ok(Request::Certificate(_)) | Ok(Request::Batch(_)) | Ok(Request::BatchList(_)) => {
+                info!("Loaded request from {}", self);
+                Ok(request)
+            }
+            Ok(_) => Err(CertificateRequestError::invalid_config_source(format!(
+                "{} must hold a certificate request, batch request, or array of certificate requests",
+                self
+            ))),
+            Err(e) => Err(CertificateRequestError::invalid_config_source(format!("{}: Invalid request: {}", self, e))),
+        }
+    }
+}
+
+impl Display for ConfigSource {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::S3 {
+                bucket,
+                key,
+            } => write!(f, "s3://{}/{}", bucket, key),
+            Self::Secret(reference) => f.write_str(reference),
+        }
+    }
+}
+
+/// Returns whether an invocation payload carries no request of its own: null, an empty object, or the event sent by
+/// an EventBridge schedule without a custom input.
+pub fn is_empty_payload(payload: &Value) -> bool {
+    match payload {
+        Value::Null => true,
+        Value::Object(fields) => fields.is_empty() || is_scheduled_event(payload),
+        _ => false,
+    }
+}
+
+/// Returns whether a payload is the event sent by an EventBridge schedule without a custom input.
+pub fn is_scheduled_event(payload: &Value) -> bool {
+    payload["source"] == EVENT_SOURCE_SCHEDULER && payload["detail-type"] == EVENT_DETAIL_TYPE_SCHEDULED_EVENT
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{is_empty_payload, ConfigSource},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_parse_config_source() {
+        assert_eq!(
+            ConfigSource::parse("s3://my-bucket/config/request.json").unwrap(),
+            ConfigSource::S3 {
+                bucket: "my-bucket".to_string(),
+                key: "config/request.json".to_string(),
+            }
+        );
+        assert!(ConfigSource::parse("s3://my-bucket").is_err());
+        assert!(ConfigSource::parse("s3:///request.json").is_err());
+
+        assert_eq!(
+            ConfigSource::parse("ssm:/letsencrypt/Request").unwrap(),
+            ConfigSource::Secret("ssm:/letsencrypt/Request".to_string())
+        );
+        assert_eq!(
+            ConfigSource::parse("secretsmanager:letsencrypt-request").unwrap().to_string(),
+            "secretsmanager:letsencrypt-request"
+        );
+        assert!(ConfigSource::parse("ftp:request.json").is_err());
+    }
+
+    #[test]
+    fn test_is_empty_payload() {
+        assert!(is_empty_payload(&json!(null)));
+        assert!(is_empty_payload(&json!({})));
+        assert!(is_empty_payload(&json!({
+            "source": "aws.events",
+            "detail-type": "Scheduled Event",
+            "resources": ["arn:aws:events:us-east-1:123456789012:rule/renew"],
+            "detail": {},
+        })));
+
+        assert!(!is_empty_payload(&json!({"DomainNames": ["example.com"]})));
+        assert!(!is_empty_payload(
+            &json!({"source": "aws.acm", "detail-type": "ACM Certificate Approaching Expiration"})
+        ));
+        assert!(!is_empty_payload(&json!([])));
+    }
+}
@@ -0,0 +1,269 @@
+use {
+    crate::{
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        trust_bundle::TrustBundle,
+        utils::{default_true, CertificateComponents, CertificateInfo},
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{debug, error},
+    openssl::{
+        pkey::PKey,
+        stack::Stack,
+        x509::{store::X509StoreBuilder, X509StoreContext, X509},
+    },
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+};
+
+/// How a certificate is checked before it is written to any storage target. The private key must match the leaf
+/// certificate, the certificate's subject alternative names must cover every requested domain name, and (unless
+/// VerifyChain is false) the chain must verify up to a trusted root. This applies both to newly issued certificates
+/// and to certificates copied from another storage target. In JSON:
+///
+///     {
+///         // If false, the chain isn't required to verify up to a trusted root. The private key and domain names
+///         // are still checked. The default is true.
+///         "VerifyChain": bool,
+///
+///         // Additional root CAs to trust when verifying the chain, alongside the system trust store. This is
+///         // needed for Let's Encrypt's staging environment and for private CAs, whose roots aren't in the
+///         // system trust store. See TrustBundle.
+///         "TrustBundle": { ... },
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ConsistencyCheck {
+    #[serde(rename = "VerifyChain", default = "default_true")]
+    pub verify_chain: bool,
+
+    #[serde(rename = "TrustBundle", default, skip_serializing_if = "Option::is_none")]
+    pub trust_bundle: Option<TrustBundle>,
+}
+
+impl Default for ConsistencyCheck {
+    fn default() -> Self {
+        Self {
+            verify_chain: true,
+            trust_bundle: None,
+        }
+    }
+}
+
+impl ConsistencyCheck {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        if let Some(trust_bundle) = &self.trust_bundle {
+            if !self.verify_chain {
+                return Err(InvalidCertificateRequest::invalid_trust_bundle(
+                    "A TrustBundle has no effect unless VerifyChain is true",
+                ));
+            }
+
+            trust_bundle.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the certificate and private key, failing with the first problem found.
+    pub async fn check(&self, components: &CertificateComponents, domain_names: &[String]) -> Result<(), LambdaError> {
+        let roots = match (&self.trust_bundle, self.verify_chain) {
+            (Some(trust_bundle), true) => trust_bundle.x509_certificates().await?,
+            _ => Vec::new(),
+        };
+
+        check_components(components, domain_names, self.verify_chain, &roots)
+    }
+}
+
+/// Check the certificate and private key, verifying the chains against the system trust store and the given roots
+/// if verify_chain is set.
+pub fn check_components(
+    components: &CertificateComponents,
+    domain_names: &[String],
+    verify_chain: bool,
+    roots: &[X509],
+) -> Result<(), LambdaError> {
+    let leaf = X509::from_pem(components.cert_pem.as_bytes())?;
+
+    // A target that doesn't hold the private key can't be a copy source, but be lenient if the key is absent.
+    if !components.pkey_pem.is_empty() {
+        let pkey = PKey::private_key_from_pem(components.pkey_pem.as_bytes())?;
+        if !leaf.public_key()?.public_eq(&pkey) {
+            error!("The private key does not match the certificate for {}", domain_names.join(" "));
+            return Err(CertificateRequestError::certificate_key_mismatch(domain_names.join(" ")));
+        }
+    }
+
+    let info = CertificateInfo::from_pem(&components.cert_pem)?;
+    let missing: Vec<&str> =
+        domain_names.iter().filter(|dn| !info.covers(std::slice::from_ref(*dn))).map(|dn| dn.as_str()).collect();
+    if !missing.is_empty() {
+        error!("The certificate does not cover {}", missing.join(" "));
+        return Err(CertificateRequestError::certificate_domain_mismatch(missing.join(" ")));
+    }
+
+    // The full chain is what most consumers serve, so it must start with the leaf and hold the same chain.
+    let fullchain = X509::stack_from_pem(components.fullchain_pem.as_bytes())?;
+    let chain = X509::stack_from_pem(components.chain_pem.as_bytes())?;
+    if !same_certs(&fullchain, &leaf, &chain)? {
+        error!("The full chain does not match the certificate and chain for {}", domain_names.join(" "));
+        return Err(CertificateRequestError::untrusted_certificate_chain(
+            "the full chain does not match the certificate and chain",
+        ));
+    }
+
+    if !verify_chain {
+        debug!("Not verifying the certificate chain for {}", domain_names.join(" "));
+        return Ok(());
+    }
+
+    if let Some(reason) = verification_failure(&leaf, &chain, roots)? {
+        error!("The certificate chain for {} does not verify: {}", domain_names.join(" "), reason);
+        return Err(CertificateRequestError::untrusted_certificate_chain(reason));
+    }
+
+    if let Some(alternate) = &components.alternate_chain {
+        let alternate_chain = X509::stack_from_pem(alternate.chain_pem.as_bytes())?;
+        if let Some(reason) = verification_failure(&leaf, &alternate_chain, roots)? {
+            error!("The alternate certificate chain for {} does not verify: {}", domain_names.join(" "), reason);
+            return Err(CertificateRequestError::untrusted_certificate_chain(format!("alternate chain: {}", reason)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Indicates whether fullchain consists of the leaf followed by the chain.
+fn same_certs(fullchain: &[X509], leaf: &X509, chain: &[X509]) -> Result<bool, LambdaError> {
+    if fullchain.len() != chain.len() + 1 {
+        return Ok(false);
+    }
+
+    for (cert, expected) in fullchain.iter().zip(std::iter::once(leaf).chain(chain.iter())) {
+        if cert.to_der()? != expected.to_der()? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+/// Verify the leaf certificate through the intermediates up to a root in the system trust store or roots, returning
+/// the reason verification failed, if it did.
+fn verification_failure(leaf: &X509, intermediates: &[X509], roots: &[X509]) -> Result<Option<String>, LambdaError> {
+    let mut store_builder = X509StoreBuilder::new()?;
+    store_builder.set_default_paths()?;
+    for root in roots {
+        store_builder.add_cert(root.clone())?;
+    }
+    let store = store_builder.build();
+
+    let mut chain = Stack::new()?;
+    for cert in intermediates {
+        chain.push(cert.clone())?;
+    }
+
+    let mut context = X509StoreContext::new()?;
+    let failure = context.init(&store, leaf, &chain, |c| {
+        c.verify_cert().map(|verified| {
+            if verified {
+                None
+            } else {
+                Some(c.error().error_string().to_string())
+            }
+        })
+    })?;
+
+    Ok(failure)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::check_components,
+        crate::{keys::KeyAlgorithm, utils::CertificateComponents},
+        openssl::{
+            asn1::Asn1Time,
+            hash::MessageDigest,
+            nid::Nid,
+            pkey::{PKey, Private},
+            x509::{
+                extension::{BasicConstraints, SubjectAlternativeName},
+                X509Builder, X509NameBuilder, X509,
+            },
+        },
+        std::str::from_utf8,
+    };
+
+    fn cert(cn: &str, sans: &[&str], pkey: &PKey<Private>, issuer: Option<(&X509, &PKey<Private>)>) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, cn).unwrap();
+        let name = name.build();
+
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(issuer.map(|(ca, _)| ca.subject_name()).unwrap_or(&name)).unwrap();
+        builder.set_pubkey(pkey).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(90).unwrap()).unwrap();
+        if issuer.is_none() {
+            builder.append_extension(BasicConstraints::new().critical().ca().build().unwrap()).unwrap();
+        }
+        if !sans.is_empty() {
+            let mut san = SubjectAlternativeName::new();
+            for dns_name in sans {
+                san.dns(dns_name);
+            }
+            let san = san.build(&builder.x509v3_context(issuer.map(|(ca, _)| ca.as_ref()), None)).unwrap();
+            builder.append_extension(san).unwrap();
+        }
+        builder.sign(issuer.map(|(_, key)| key).unwrap_or(pkey), MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn pem(cert: &X509) -> String {
+        from_utf8(&cert.to_pem().unwrap()).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_check_components() {
+        let root_key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let root = cert("Test Root", &[], &root_key, None);
+        let leaf_key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let leaf = cert("example.com", &["example.com", "www.example.com"], &leaf_key, Some((&root, &root_key)));
+
+        let components = CertificateComponents {
+            cert_pem: pem(&leaf),
+            chain_pem: String::new(),
+            fullchain_pem: pem(&leaf),
+            pkey_pem: from_utf8(&leaf_key.private_key_to_pem_pkcs8().unwrap()).unwrap().to_string(),
+            alternate_chain: None,
+        };
+        let domain_names = vec!["example.com".to_string(), "WWW.example.com".to_string()];
+
+        check_components(&components, &domain_names, true, std::slice::from_ref(&root)).unwrap();
+
+        // The test root isn't in the system trust store.
+        let e = check_components(&components, &domain_names, true, &[]).unwrap_err();
+        assert!(e.to_string().contains("does not verify"), "{}", e);
+        check_components(&components, &domain_names, false, &[]).unwrap();
+
+        let e = check_components(&components, &["api.example.com".to_string()], false, &[]).unwrap_err();
+        assert!(e.to_string().contains("api.example.com"), "{}", e);
+
+        let other_key = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let mismatched = CertificateComponents {
+            pkey_pem: from_utf8(&other_key.private_key_to_pem_pkcs8().unwrap()).unwrap().to_string(),
+            ..components.clone()
+        };
+        let e = check_components(&mismatched, &domain_names, false, &[]).unwrap_err();
+        assert!(e.to_string().contains("does not match"), "{}", e);
+
+        let truncated = CertificateComponents {
+            fullchain_pem: pem(&root),
+            ..components
+        };
+        assert!(check_components(&truncated, &domain_names, false, &[]).is_err());
+    }
+}
@@ -1,17 +1,158 @@
-pub(crate) const ACM_STATUS_ISSUED: &str = "ISSUED";
-pub(crate) const ACM_STATUS_EXPIRED: &str = "EXPIRED";
-pub(crate) const ACM_TYPE_IMPORTED: &str = "IMPORTED";
+pub const ACM_ALL_KEY_TYPES: &[&str] =
+    &["RSA_1024", "RSA_2048", "RSA_3072", "RSA_4096", "EC_prime256v1", "EC_secp384r1", "EC_secp521r1"];
+pub const ACM_MAX_TAGS: usize = 50;
+pub const ACM_STATUS_ISSUED: &str = "ISSUED";
+pub const ACM_STATUS_EXPIRED: &str = "EXPIRED";
+pub const ACM_TYPE_IMPORTED: &str = "IMPORTED";
 
-pub(crate) const CHALLENGE_TYPE_DNS01: &str = "dns-01";
-pub(crate) const CHALLENGE_TYPE_HTTP01: &str = "http-01";
+pub const APIGATEWAY_EDGE_ACM_REGION: &str = "us-east-1";
+pub const APIGATEWAY_ENDPOINT_EDGE: &str = "EDGE";
+pub const APIGATEWAY_ENDPOINT_REGIONAL: &str = "REGIONAL";
 
-pub(crate) const DEFAULT_SSM_ACME_PATH: &str = "/AcmeParameters";
-pub(crate) const ENV_SSM_PARAMETER_PATH: &str = "AcmeParameterPath";
+pub const CHAIN_ALTERNATE: &str = "Alternate";
+pub const CHAIN_DEFAULT: &str = "Default";
 
-pub(crate) const S3_ENCRYPTION_AES: &str = "AES256";
-pub(crate) const S3_ENCRYPTION_KMS: &str = "aws:kms";
+pub const CHALLENGE_TYPE_DNS01: &str = "dns-01";
+pub const CHALLENGE_TYPE_HTTP01: &str = "http-01";
+pub const CHALLENGE_TYPE_TLS_ALPN01: &str = "tls-alpn-01";
 
-pub(crate) const SSM_TIER_STANDARD: &str = "Standard";
-pub(crate) const SSM_TIER_ADVANCED: &str = "Advanced";
-pub(crate) const SSM_TIER_INTELLIGENT_TIERING: &str = "Intelligent-Tiering";
-pub(crate) const SSM_TYPE_SECURE_STRING: &str = "SecureString";
\ No newline at end of file
+pub const CLOUDFRONT_ACM_REGION: &str = "us-east-1";
+pub const CLOUDFRONT_DEFAULT_MINIMUM_PROTOCOL_VERSION: &str = "TLSv1.2_2021";
+pub const CLOUDFRONT_SSL_SUPPORT_SNI_ONLY: &str = "sni-only";
+pub const CLOUDFRONT_SSL_SUPPORT_VIP: &str = "vip";
+
+pub const DEFAULT_ACME_ACCOUNT_LIMIT: usize = 10;
+pub const DEFAULT_ACM_CACHE_FULL_SYNC_HOURS: i64 = 24;
+pub const DEFAULT_AGENT_INTERVAL_MINUTES: u64 = 720;
+pub const DEFAULT_AGENT_RELOAD_SECONDS: u64 = 60;
+pub const DEFAULT_AWS_MAX_CONCURRENCY: usize = 8;
+pub const DEFAULT_BATCH_MAX_CONCURRENCY: usize = 4;
+pub const DEFAULT_BATCH_PROGRESS_INTERVAL_SECONDS: u64 = 60;
+pub const DEFAULT_COST_LEDGER_PREFIX: &str = "cost-ledger/";
+pub const DEFAULT_DEBUG_ARTIFACT_PREFIX: &str = "acme-debug/";
+pub const DEFAULT_EXPIRING_SOON_DAYS: i64 = 14;
+pub const DEFAULT_HEALTH_CHECK_PORT: u16 = 8080;
+pub const DEFAULT_RATE_LIMIT_BACKOFF_SECONDS: i64 = 3600;
+pub const DEFAULT_RETRY_BASE_DELAY_MILLIS: u64 = 200;
+pub const DEFAULT_RETRY_MAX_ATTEMPTS: u32 = 6;
+pub const DEFAULT_RETRY_MAX_DELAY_MILLIS: u64 = 20_000;
+pub const DEFAULT_SCAFFOLD_CODE_PATH: &str = "target/lambda/letsencrypt-certs-aws/bootstrap.zip";
+pub const DEFAULT_SCAFFOLD_FUNCTION_NAME: &str = "letsencrypt-certs-aws";
+pub const DEFAULT_SCAFFOLD_SCHEDULE: &str = "rate(12 hours)";
+pub const DEFAULT_SSM_ACME_PATH: &str = "/AcmeParameters";
+pub const DEFAULT_XRAY_DAEMON_ADDRESS: &str = "127.0.0.1:2000";
+pub const ENV_ACCOUNT_KEY_KMS_KEY_ID: &str = "AccountKeyKmsKeyId";
+pub const ENV_ACCOUNT_KEY_STORE: &str = "AccountKeyStore";
+pub const ENV_ACME_ACCOUNT_LIMIT: &str = "AcmeAccountLimit";
+pub const ENV_ACME_DIRECTORY_REWRITES: &str = "AcmeDirectoryRewrites";
+pub const ENV_ACME_PROXY_URL: &str = "AcmeProxyUrl";
+pub const ENV_ACME_TRUST_ANCHORS_PARAMETER: &str = "AcmeTrustAnchorsParameter";
+pub const ENV_ACM_CACHE_FULL_SYNC_HOURS: &str = "AcmCacheFullSyncHours";
+pub const ENV_ACM_CACHE_TABLE: &str = "AcmCacheTable";
+pub const ENV_AGENT_CONFIG_PARAMETER: &str = "AgentConfigParameter";
+pub const ENV_AWS_MAX_CONCURRENCY: &str = "AwsMaxConcurrency";
+pub const ENV_CONFIG_SOURCE: &str = "ConfigSource";
+pub const ENV_COST_LEDGER_BUCKET: &str = "CostLedgerBucket";
+pub const ENV_COST_LEDGER_PREFIX: &str = "CostLedgerPrefix";
+pub const ENV_COST_LEDGER_TABLE: &str = "CostLedgerTable";
+pub const ENV_DEBUG_ARTIFACT_BUCKET: &str = "DebugArtifactBucket";
+pub const ENV_DEBUG_ARTIFACT_PREFIX: &str = "DebugArtifactPrefix";
+pub const ENV_DOMAIN_POLICY_PARAMETER: &str = "DomainPolicyParameter";
+#[cfg(feature = "doppler-secrets")]
+pub const ENV_DOPPLER_TOKEN: &str = "DOPPLER_TOKEN";
+#[cfg(feature = "fault-injection")]
+pub const ENV_FAULT_INJECTION: &str = "FaultInjection";
+pub const ENV_HEALTH_CHECK_PORT: &str = "HealthCheckPort";
+pub const ENV_ISSUANCE_LIMIT_TABLE: &str = "IssuanceLimitTable";
+pub const ENV_LAMBDA_FUNCTION_MEMORY_SIZE: &str = "AWS_LAMBDA_FUNCTION_MEMORY_SIZE";
+pub const ENV_LAMBDA_RUNTIME_API: &str = "AWS_LAMBDA_RUNTIME_API";
+pub const ENV_LIFECYCLE_EVENT_BUS: &str = "LifecycleEventBus";
+pub const ENV_MAX_ISSUANCES_PER_DAY: &str = "MaxIssuancesPerDay";
+pub const ENV_MAX_ISSUANCES_PER_RUN: &str = "MaxIssuancesPerRun";
+pub const ENV_METRICS_NAMESPACE: &str = "MetricsNamespace";
+pub const ENV_NOTIFICATION_TOPIC_ARN: &str = "NotificationTopicArn";
+pub const ENV_RATE_LIMIT_RETRY_TARGET_ARN: &str = "RateLimitRetryTargetArn";
+pub const ENV_RENEWAL_LOCK_TABLE: &str = "RenewalLockTable";
+pub const ENV_RENEWAL_SCHEDULE_TARGET_ARN: &str = "RenewalScheduleTargetArn";
+pub const ENV_RETRY_BASE_DELAY_MILLIS: &str = "RetryBaseDelayMillis";
+pub const ENV_RETRY_MAX_ATTEMPTS: &str = "RetryMaxAttempts";
+pub const ENV_RETRY_MAX_DELAY_MILLIS: &str = "RetryMaxDelayMillis";
+pub const ENV_RETRY_ON: &str = "RetryOn";
+pub const ENV_RUN_MODE: &str = "RunMode";
+pub const ENV_SSM_PARAMETER_PATH: &str = "AcmeParameterPath";
+#[cfg(feature = "vault-secrets")]
+pub const ENV_VAULT_ADDR: &str = "VAULT_ADDR";
+#[cfg(feature = "vault-secrets")]
+pub const ENV_VAULT_NAMESPACE: &str = "VAULT_NAMESPACE";
+#[cfg(feature = "vault-secrets")]
+pub const ENV_VAULT_TOKEN: &str = "VAULT_TOKEN";
+pub const ENV_XRAY_DAEMON_ADDRESS: &str = "AWS_XRAY_DAEMON_ADDRESS";
+pub const ENV_XRAY_TRACE_ID: &str = "_X_AMZN_TRACE_ID";
+
+pub const EVENT_DETAIL_TYPE_ACM_APPROACHING_EXPIRATION: &str = "ACM Certificate Approaching Expiration";
+pub const EVENT_DETAIL_TYPE_BATCH_PROGRESS: &str = "BatchProgress";
+pub const EVENT_DETAIL_TYPE_CERTIFICATE_ISSUED: &str = "CertificateIssued";
+pub const EVENT_DETAIL_TYPE_CERTIFICATE_NOTIFICATION: &str = "CertificateNotification";
+pub const EVENT_DETAIL_TYPE_CERTIFICATE_RENEWAL_FAILED: &str = "CertificateRenewalFailed";
+pub const EVENT_DETAIL_TYPE_CERTIFICATE_STORED: &str = "CertificateStored";
+pub const EVENT_DETAIL_TYPE_SCHEDULED_EVENT: &str = "Scheduled Event";
+pub const EVENT_SOURCE_ACM: &str = "aws.acm";
+pub const EVENT_SOURCE_LIFECYCLE: &str = "letsencrypt-certs-aws";
+pub const EVENT_SOURCE_SCHEDULER: &str = "aws.events";
+
+pub const IAM_MAX_SERVER_CERTIFICATE_NAME_LENGTH: usize = 128;
+pub const IAM_MAX_TAGS: usize = 50;
+
+pub const K8S_FIELD_MANAGER: &str = "letsencrypt-certs-aws";
+pub const K8S_TOKEN_PREFIX: &str = "k8s-aws-v1.";
+pub const K8S_TOKEN_TTL_SECS: u64 = 60;
+
+pub const RATE_LIMIT_RETRY_RULE_PREFIX: &str = "letsencrypt-retry-";
+
+pub const RENEWAL_LOCK_LEASE_SECONDS: i64 = 900;
+pub const RENEWAL_LOCK_MAX_WAIT_SECONDS: u64 = 600;
+
+pub const RENEWAL_RULE_PREFIX: &str = "letsencrypt-renew-";
+pub const RENEWAL_SCHEDULE_MIN_DELAY_SECONDS: i64 = 300;
+pub const RENEWAL_SCHEDULE_RETRY_SECONDS: i64 = 86400;
+
+pub const RUN_COMMAND_DEFAULT_TIMEOUT_SECONDS: u64 = 300;
+pub const RUN_COMMAND_MAX_TIMEOUT_SECONDS: u64 = 900;
+pub const RUN_COMMAND_POLL_SECONDS: u64 = 5;
+
+pub const RUN_MODE_AGENT: &str = "Agent";
+pub const RUN_MODE_LAMBDA: &str = "Lambda";
+pub const RUN_MODE_ONCE: &str = "Once";
+
+pub const S3_DEFAULT_KEY_TEMPLATE: &str = "{Prefix}{FileName}";
+pub const S3_ENCRYPTION_AES: &str = "AES256";
+pub const S3_ENCRYPTION_KMS: &str = "aws:kms";
+pub const S3_MAX_METADATA_DOMAIN_NAMES_LEN: usize = 1024;
+pub const S3_MAX_OBJECT_TAGS: usize = 10;
+pub const S3_MAX_TAG_KEY_LEN: usize = 128;
+pub const S3_MAX_TAG_VALUE_LEN: usize = 256;
+
+/// ACL grantees that make a bucket public: anyone, and any AWS account.
+pub const S3_PUBLIC_GRANTEE_URIS: &[&str] =
+    &["http://acs.amazonaws.com/groups/global/AllUsers", "http://acs.amazonaws.com/groups/global/AuthenticatedUsers"];
+
+pub const SECRETS_MANAGER_DEFAULT_BUNDLE_NAME_TEMPLATE: &str = "Certificate/{Domain}";
+pub const SECRETS_MANAGER_DEFAULT_SEPARATE_NAME_TEMPLATE: &str = "Certificate/{Domain}/{Component}";
+
+pub const SSM_ADVANCED_MAX_VALUE_LEN: usize = 8192;
+pub const SSM_DEFAULT_NAME_TEMPLATE: &str = "{Path}/Certificate/{Domain}/{Component}";
+pub const SSM_STANDARD_MAX_VALUE_LEN: usize = 4096;
+pub const SSM_TIER_STANDARD: &str = "Standard";
+pub const SSM_TIER_ADVANCED: &str = "Advanced";
+pub const SSM_TIER_INTELLIGENT_TIERING: &str = "Intelligent-Tiering";
+pub const SSM_TYPE_SECURE_STRING: &str = "SecureString";
+pub const SSM_TYPE_STRING: &str = "String";
+
+pub const STORAGE_STAGING_SUFFIX: &str = ".staging";
+
+pub const WINDOWS_DEFAULT_PARAMETER_NAME: &str = "/Certificate/Windows/{Domain}";
+pub const WINDOWS_DEPLOYED_LABEL: &str = "Deployed";
+pub const WINDOWS_MAX_INSTANCE_IDS: usize = 50;
+pub const WINDOWS_RUN_COMMAND_DOCUMENT: &str = "AWS-RunPowerShellScript";
+pub const WINDOWS_STORE_MY: &str = "My";
+pub const WINDOWS_STORE_WEB_HOSTING: &str = "WebHosting";
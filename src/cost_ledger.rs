@@ -0,0 +1,380 @@
+use {
+    crate::{
+        constants::{
+            DEFAULT_COST_LEDGER_PREFIX, ENV_COST_LEDGER_BUCKET, ENV_COST_LEDGER_PREFIX, ENV_COST_LEDGER_TABLE,
+        },
+        errors::InvalidCertificateRequest,
+        rate_limits::format_utc,
+        storage::CertificateStorageResult,
+        utils::{attr_n, attr_s, CertificateInfo},
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    rusoto_core::Region,
+    rusoto_dynamodb::{AttributeValue, DynamoDb, DynamoDbClient, PutItemInput},
+    rusoto_s3::{PutObjectRequest, S3Client, StreamingBody, S3},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, HashMap},
+        env::var,
+    },
+};
+
+/// The tenant recorded for requests that don't name one.
+const UNASSIGNED_TENANT: &str = "Unassigned";
+
+/// The columns of a cost ledger CSV file.
+const CSV_HEADER: &str =
+    "EntryId,RecordedAt,Tenant,DomainName,Serial,StorageTargets,StorageWrites,AdvancedParameters,KmsCalls,Tags";
+
+/// How a request's renewals are attributed in the cost ledger. In JSON:
+///
+///     {
+///         // The tenant (team, customer, or cost center) to charge for this certificate. If omitted, renewals are
+///         // recorded under "Unassigned".
+///         "Tenant": str,
+///
+///         // Additional cost allocation tags to record with each renewal, e.g. {"Project": "storefront"}.
+///         "Tags": {str: str, ...},
+///     }
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct CostAllocation {
+    #[serde(rename = "Tenant", default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+
+    #[serde(rename = "Tags", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl CostAllocation {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        if let Some(tenant) = &self.tenant {
+            if tenant.trim().is_empty() {
+                return Err(InvalidCertificateRequest::invalid_cost_allocation("Tenant cannot be empty"));
+            }
+        }
+
+        if self.tags.keys().any(|key| key.trim().is_empty()) {
+            return Err(InvalidCertificateRequest::invalid_cost_allocation("Tag keys cannot be empty"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Where to record a cost attribution entry for each renewal, so the certificate service can be charged back to
+/// tenants without reconstructing its activity from CloudTrail. This is a global setting, configured through
+/// environment variables:
+///
+/// * `CostLedgerTable`: a DynamoDB table with a string partition key named `Tenant` and a string sort key named
+///   `EntryId`. Each entry is written as an item.
+/// * `CostLedgerBucket`: an S3 bucket in the same region as the function. Each entry is written as a one-row CSV
+///   file (with a header), `<prefix><yyyy>/<mm>/<dd>/<tenant>/<EntryId>.csv`, so the prefix can be queried with
+///   Athena.
+/// * `CostLedgerPrefix`: the prefix for CSV keys. This defaults to `cost-ledger/`.
+///
+/// Either or both of the destinations may be set; if neither is, nothing is recorded. An entry is recorded whenever
+/// a run writes to at least one storage target. See CostLedgerEntry for the contents.
+#[derive(Clone, Debug)]
+pub struct CostLedger {
+    table_name: Option<String>,
+    bucket: Option<String>,
+    prefix: String,
+}
+
+impl CostLedger {
+    /// Returns the ledger configured via environment variables, or None if the cost ledger is disabled.
+    pub fn from_env() -> Option<Self> {
+        let table_name = var(ENV_COST_LEDGER_TABLE).ok().filter(|table| !table.is_empty());
+        let bucket = var(ENV_COST_LEDGER_BUCKET).ok().filter(|bucket| !bucket.is_empty());
+        if table_name.is_none() && bucket.is_none() {
+            return None;
+        }
+
+        let prefix = var(ENV_COST_LEDGER_PREFIX).unwrap_or_else(|_| DEFAULT_COST_LEDGER_PREFIX.to_string());
+        Some(Self {
+            table_name,
+            bucket,
+            prefix,
+        })
+    }
+
+    /// Record an entry in each configured destination. A destination failing is logged but doesn't fail the run.
+    pub async fn record(&self, entry: &CostLedgerEntry) {
+        let (table_result, bucket_result) = tokio::join!(self.put_item(entry), self.put_csv(entry));
+
+        if let Err(e) = table_result {
+            error!("Failed to record cost ledger entry {} in DynamoDB: {:#}", entry.entry_id, e);
+        }
+
+        if let Err(e) = bucket_result {
+            error!("Failed to record cost ledger entry {} in S3: {:#}", entry.entry_id, e);
+        }
+    }
+
+    async fn put_item(&self, entry: &CostLedgerEntry) -> Result<(), LambdaError> {
+        let table_name = match &self.table_name {
+            Some(table_name) => table_name,
+            None => return Ok(()),
+        };
+
+        let ddb = DynamoDbClient::new_with_client(aws_client(), Region::default());
+        let pi_input = PutItemInput {
+            table_name: table_name.clone(),
+            item: entry.item(),
+            ..Default::default()
+        };
+
+        ddb.put_item(pi_input).await?;
+        info!("Recorded cost ledger entry {} in DynamoDB table {}", entry.entry_id, table_name);
+        Ok(())
+    }
+
+    async fn put_csv(&self, entry: &CostLedgerEntry) -> Result<(), LambdaError> {
+        let bucket = match &self.bucket {
+            Some(bucket) => bucket,
+            None => return Ok(()),
+        };
+
+        let key = self.key(entry);
+        let s3 = S3Client::new_with_client(aws_client(), Region::default());
+        let por = PutObjectRequest {
+            bucket: bucket.clone(),
+            key: key.clone(),
+            content_type: Some("text/csv".to_string()),
+            server_side_encryption: Some("AES256".to_string()),
+            body: Some(StreamingBody::from(entry.csv().into_bytes())),
+            ..Default::default()
+        };
+
+        s3.put_object(por).await?;
+        info!("Recorded cost ledger entry {} in s3://{}/{}", entry.entry_id, bucket, key);
+        Ok(())
+    }
+
+    /// The S3 key an entry is written to, partitioned by the UTC date it was recorded and the tenant.
+    fn key(&self, entry: &CostLedgerEntry) -> String {
+        let date = format_utc(entry.recorded_at)[..10].replace('-', "/");
+        let file_name = entry.entry_id.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.', "_");
+        let tenant = entry.tenant.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.', "_");
+        format!("{}{}/{}/{}.csv", self.prefix, date, tenant, file_name)
+    }
+}
+
+/// The cost attribution of a single renewal. In JSON:
+///
+///     {
+///         // Identifies the entry within the tenant: the time it was recorded and the first domain name.
+///         "EntryId": str,
+///
+///         // When the entry was recorded, in seconds since the Unix epoch.
+///         "RecordedAt": int,
+///
+///         // The tenant charged, and the first domain name of the certificate.
+///         "Tenant": str,
+///         "DomainName": str,
+///
+///         // The serial number of the certificate written, if known.
+///         "Serial": str,
+///
+///         // The number of storage targets written, and the objects, parameters, secrets, and other resources
+///         // written to them.
+///         "StorageTargets": int,
+///         "StorageWrites": int,
+///
+///         // The number of SSM parameters written in the Advanced tier, which are billed monthly.
+///         "AdvancedParameters": int,
+///
+///         // An estimate of the KMS requests made to encrypt what was written.
+///         "KmsCalls": int,
+///
+///         // The request's cost allocation tags.
+///         "Tags": {str: str, ...},
+///     }
+#[derive(Clone, Debug, Serialize)]
+pub struct CostLedgerEntry {
+    #[serde(rename = "EntryId")]
+    pub entry_id: String,
+
+    #[serde(rename = "RecordedAt")]
+    pub recorded_at: i64,
+
+    #[serde(rename = "Tenant")]
+    pub tenant: String,
+
+    #[serde(rename = "DomainName")]
+    pub domain_name: String,
+
+    #[serde(rename = "Serial", skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+
+    #[serde(rename = "StorageTargets")]
+    pub storage_targets: u32,
+
+    #[serde(rename = "StorageWrites")]
+    pub storage_writes: u32,
+
+    #[serde(rename = "AdvancedParameters")]
+    pub advanced_parameters: u32,
+
+    #[serde(rename = "KmsCalls")]
+    pub kms_calls: u32,
+
+    #[serde(rename = "Tags")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl CostLedgerEntry {
+    /// Summarize the storage writes of a run. Storage targets that failed aren't counted.
+    pub fn new(
+        allocation: &CostAllocation,
+        domain_names: &[String],
+        certificate: Option<&CertificateInfo>,
+        storage: &[CertificateStorageResult],
+        recorded_at: i64,
+    ) -> Self {
+        let written: Vec<&CertificateStorageResult> =
+            storage.iter().filter(|result| !matches!(result, CertificateStorageResult::Error(_))).collect();
+        let domain_name = domain_names.first().cloned().unwrap_or_default();
+
+        Self {
+            entry_id: format!("{}#{}", format_utc(recorded_at), domain_name),
+            recorded_at,
+            tenant: allocation.tenant.clone().unwrap_or_else(|| UNASSIGNED_TENANT.to_string()),
+            domain_name,
+            serial: certificate.map(|info| info.serial.clone()),
+            storage_targets: written.len() as u32,
+            storage_writes: written.iter().map(|result| result.writes()).sum(),
+            advanced_parameters: written.iter().map(|result| result.advanced_parameters()).sum(),
+            kms_calls: written.iter().map(|result| result.kms_calls()).sum(),
+            tags: allocation.tags.clone(),
+        }
+    }
+
+    /// The entry as a DynamoDB item.
+    fn item(&self) -> HashMap<String, AttributeValue> {
+        let mut item: HashMap<String, AttributeValue> = vec![
+            ("EntryId".to_string(), attr_s(&self.entry_id)),
+            ("RecordedAt".to_string(), attr_n(self.recorded_at)),
+            ("Tenant".to_string(), attr_s(&self.tenant)),
+            ("DomainName".to_string(), attr_s(&self.domain_name)),
+            ("StorageTargets".to_string(), attr_n(self.storage_targets.into())),
+            ("StorageWrites".to_string(), attr_n(self.storage_writes.into())),
+            ("AdvancedParameters".to_string(), attr_n(self.advanced_parameters.into())),
+            ("KmsCalls".to_string(), attr_n(self.kms_calls.into())),
+        ]
+        .into_iter()
+        .collect();
+
+        if let Some(serial) = &self.serial {
+            item.insert("Serial".to_string(), attr_s(serial));
+        }
+
+        if !self.tags.is_empty() {
+            item.insert(
+                "Tags".to_string(),
+                AttributeValue {
+                    m: Some(self.tags.iter().map(|(key, value)| (key.clone(), attr_s(value))).collect()),
+                    ..Default::default()
+                },
+            );
+        }
+
+        item
+    }
+
+    /// The entry as a CSV file with a header row. Tags are written as "key=value" pairs separated by ";".
+    fn csv(&self) -> String {
+        let tags: Vec<String> = self.tags.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        let fields = [
+            self.entry_id.clone(),
+            self.recorded_at.to_string(),
+            self.tenant.clone(),
+            self.domain_name.clone(),
+            self.serial.clone().unwrap_or_default(),
+            self.storage_targets.to_string(),
+            self.storage_writes.to_string(),
+            self.advanced_parameters.to_string(),
+            self.kms_calls.to_string(),
+            tags.join(";"),
+        ];
+
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        format!("{}\n{}\n", CSV_HEADER, row.join(","))
+    }
+}
+
+/// Quote a CSV field if it contains a delimiter, quote, or line break (RFC 4180).
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{csv_field, CostAllocation, CostLedger, CostLedgerEntry},
+        crate::storage::{CertificateStorageResult, SsmParameterStorageResult},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_cost_ledger_entry() {
+        let allocation: CostAllocation =
+            serde_json::from_value(json!({"Tenant": "payments", "Tags": {"Project": "checkout, web"}})).unwrap();
+        assert!(allocation.validate().is_ok());
+
+        let storage = vec![
+            CertificateStorageResult::SsmParameter(SsmParameterStorageResult {
+                cert_param: Some("/Certificate/example.com/Certificate".to_string()),
+                cert_arn: Some(
+                    "arn:aws:ssm:us-east-1:123456789012:parameter/Certificate/example.com/Certificate".into(),
+                ),
+                pkey_param: Some("/Certificate/example.com/PrivateKey".to_string()),
+                pkey_arn: Some(
+                    "arn:aws:ssm:us-east-1:123456789012:parameter/Certificate/example.com/PrivateKey".into(),
+                ),
+                advanced_parameters: vec!["/Certificate/example.com/Certificate".to_string()],
+                ..Default::default()
+            }),
+            CertificateStorageResult::Error("AccessDenied".to_string()),
+        ];
+
+        let domain_names = vec!["example.com".to_string()];
+        let entry = CostLedgerEntry::new(&allocation, &domain_names, None, &storage, 86400);
+        assert_eq!(entry.entry_id, "1970-01-02T00:00:00Z#example.com");
+        assert_eq!(entry.tenant, "payments");
+        assert_eq!(entry.storage_targets, 1);
+        assert_eq!(entry.storage_writes, 2);
+        assert_eq!(entry.advanced_parameters, 1);
+        assert_eq!(entry.kms_calls, 1);
+
+        let csv = entry.csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[1],
+            "1970-01-02T00:00:00Z#example.com,86400,payments,example.com,,1,2,1,1,\"Project=checkout, web\""
+        );
+
+        let ledger = CostLedger {
+            table_name: None,
+            bucket: Some("ledger".to_string()),
+            prefix: "cost-ledger/".to_string(),
+        };
+        assert_eq!(ledger.key(&entry), "cost-ledger/1970/01/02/payments/1970-01-02T00_00_00Z_example.com.csv");
+
+        let entry = CostLedgerEntry::new(&CostAllocation::default(), &domain_names, None, &[], 0);
+        assert_eq!(entry.tenant, "Unassigned");
+        assert_eq!(entry.storage_writes, 0);
+
+        assert_eq!(csv_field("a\"b"), "\"a\"\"b\"");
+        assert!(serde_json::from_value::<CostAllocation>(json!({"Tenant": ""})).unwrap().validate().is_err());
+    }
+}
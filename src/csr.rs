@@ -0,0 +1,192 @@
+use {
+    crate::{errors::InvalidCertificateRequest, keys::KeyAlgorithm, utils::default_false},
+    lambda_runtime::Error as LambdaError,
+    openssl::{
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{PKey, Private},
+        stack::Stack,
+        x509::{
+            extension::{ExtendedKeyUsage, KeyUsage, SubjectAlternativeName},
+            X509Extension, X509NameBuilder, X509Req, X509ReqBuilder,
+        },
+    },
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+};
+
+/// The OID of the TLS Feature extension (RFC 7633).
+const TLS_FEATURE_OID: &str = "1.3.6.1.5.5.7.1.24";
+
+/// The value of a TLS Feature extension requiring status_request (5): the DER encoding of SEQUENCE { INTEGER 5 }.
+const TLS_FEATURE_STATUS_REQUEST: &str = "DER:30:03:02:01:05";
+
+/// The longest common name allowed in a certificate subject (RFC 5280 ub-common-name).
+const MAX_COMMON_NAME_LENGTH: usize = 64;
+
+/// A key usage that can be requested in the CSR.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum CsrKeyUsage {
+    DigitalSignature,
+    NonRepudiation,
+    KeyEncipherment,
+    DataEncipherment,
+    KeyAgreement,
+}
+
+/// An extended key usage that can be requested in the CSR.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum CsrExtendedKeyUsage {
+    ServerAuth,
+    ClientAuth,
+}
+
+/// Extensions to request in the certificate signing request (CSR). By default, the CSR only carries the domain
+/// names. In JSON:
+///
+///     {
+///         // Request the TLS Feature extension with status_request (RFC 7633, "OCSP Must-Staple"), so clients
+///         // reject the certificate unless the server staples a valid OCSP response. The default is false.
+///         "MustStaple": bool,
+///
+///         // Key usages to request: "DigitalSignature", "NonRepudiation", "KeyEncipherment", "DataEncipherment",
+///         // or "KeyAgreement". "KeyEncipherment" requires an RSA key. If omitted, the CA's defaults are used.
+///         "KeyUsage": [str, ...],
+///
+///         // Extended key usages to request: "ServerAuth" or "ClientAuth". If omitted, the CA's defaults are used.
+///         "ExtendedKeyUsage": [str, ...],
+///     }
+///
+/// CAs decide which requested extensions to honor. Let's Encrypt honors MustStaple but sets the key usages itself.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct CsrOptions {
+    #[serde(rename = "MustStaple", default = "default_false")]
+    pub must_staple: bool,
+
+    #[serde(rename = "KeyUsage", default, skip_serializing_if = "Vec::is_empty")]
+    pub key_usage: Vec<CsrKeyUsage>,
+
+    #[serde(rename = "ExtendedKeyUsage", default, skip_serializing_if = "Vec::is_empty")]
+    pub extended_key_usage: Vec<CsrExtendedKeyUsage>,
+}
+
+impl CsrOptions {
+    pub fn validate(&self, key_algorithm: KeyAlgorithm) -> Result<(), LambdaError> {
+        let is_rsa = matches!(key_algorithm, KeyAlgorithm::Rsa2048 | KeyAlgorithm::Rsa4096);
+        if !is_rsa && self.key_usage.contains(&CsrKeyUsage::KeyEncipherment) {
+            return Err(InvalidCertificateRequest::invalid_csr_options(format!(
+                "KeyEncipherment cannot be used with {}",
+                key_algorithm
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Indicates whether no extensions beyond the domain names are requested, in which case the ACME client's own
+    /// CSR is used.
+    pub fn is_default(&self) -> bool {
+        !self.must_staple && self.key_usage.is_empty() && self.extended_key_usage.is_empty()
+    }
+
+    /// Build and sign a CSR for the domain names with the requested extensions.
+    pub fn build(&self, pkey: &PKey<Private>, domain_names: &[String]) -> Result<X509Req, LambdaError> {
+        let mut builder = X509ReqBuilder::new()?;
+        builder.set_version(0)?;
+        builder.set_pubkey(pkey)?;
+
+        if let Some(common_name) = domain_names.iter().find(|dn| dn.len() <= MAX_COMMON_NAME_LENGTH) {
+            let mut name = X509NameBuilder::new()?;
+            name.append_entry_by_nid(Nid::COMMONNAME, common_name)?;
+            builder.set_subject_name(&name.build())?;
+        }
+
+        let mut extensions = Stack::new()?;
+        let mut san = SubjectAlternativeName::new();
+        for domain_name in domain_names {
+            san.dns(domain_name);
+        }
+        extensions.push(san.build(&builder.x509v3_context(None))?)?;
+
+        if self.must_staple {
+            extensions.push(X509Extension::new(None, None, TLS_FEATURE_OID, TLS_FEATURE_STATUS_REQUEST)?)?;
+        }
+
+        if !self.key_usage.is_empty() {
+            let mut key_usage = KeyUsage::new();
+            key_usage.critical();
+            for usage in &self.key_usage {
+                match usage {
+                    CsrKeyUsage::DigitalSignature => key_usage.digital_signature(),
+                    CsrKeyUsage::NonRepudiation => key_usage.non_repudiation(),
+                    CsrKeyUsage::KeyEncipherment => key_usage.key_encipherment(),
+                    CsrKeyUsage::DataEncipherment => key_usage.data_encipherment(),
+                    CsrKeyUsage::KeyAgreement => key_usage.key_agreement(),
+                };
+            }
+            extensions.push(key_usage.build()?)?;
+        }
+
+        if !self.extended_key_usage.is_empty() {
+            let mut extended_key_usage = ExtendedKeyUsage::new();
+            for usage in &self.extended_key_usage {
+                match usage {
+                    CsrExtendedKeyUsage::ServerAuth => extended_key_usage.server_auth(),
+                    CsrExtendedKeyUsage::ClientAuth => extended_key_usage.client_auth(),
+                };
+            }
+            extensions.push(extended_key_usage.build()?)?;
+        }
+
+        builder.add_extensions(&extensions)?;
+        builder.sign(pkey, MessageDigest::sha256())?;
+        Ok(builder.build())
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{CsrKeyUsage, CsrOptions},
+        crate::keys::KeyAlgorithm,
+        serde_json::json,
+    };
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack.windows(needle.len()).any(|window| window == needle)
+    }
+
+    #[test]
+    fn test_csr_options() {
+        let options: CsrOptions = serde_json::from_value(json!({})).unwrap();
+        assert!(options.is_default());
+
+        let options: CsrOptions = serde_json::from_value(json!({
+            "MustStaple": true,
+            "KeyUsage": ["DigitalSignature"],
+            "ExtendedKeyUsage": ["ServerAuth", "ClientAuth"],
+        }))
+        .unwrap();
+        assert!(!options.is_default());
+        assert!(options.validate(KeyAlgorithm::EcdsaP256).is_ok());
+
+        let pkey = KeyAlgorithm::EcdsaP256.generate().unwrap();
+        let domain_names = vec!["example.com".to_string(), "www.example.com".to_string()];
+        let csr = options.build(&pkey, &domain_names).unwrap();
+        assert!(csr.verify(&pkey).unwrap());
+
+        let der = csr.to_der().unwrap();
+        // The TLS Feature OID followed by its status_request value.
+        assert!(contains(&der, &[0x06, 0x08, 0x2b, 0x06, 0x01, 0x05, 0x05, 0x07, 0x01, 0x18]));
+        assert!(contains(&der, &[0x30, 0x03, 0x02, 0x01, 0x05]));
+        assert!(contains(&der, b"www.example.com"));
+
+        let options = CsrOptions {
+            key_usage: vec![CsrKeyUsage::KeyEncipherment],
+            ..Default::default()
+        };
+        assert!(options.validate(KeyAlgorithm::Rsa2048).is_ok());
+        assert!(options.validate(KeyAlgorithm::EcdsaP384).is_err());
+    }
+}
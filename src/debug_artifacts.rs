@@ -0,0 +1,376 @@
+use {
+    crate::{
+        constants::{DEFAULT_DEBUG_ARTIFACT_PREFIX, ENV_DEBUG_ARTIFACT_BUCKET, ENV_DEBUG_ARTIFACT_PREFIX},
+        utils::now_epoch_secs,
+        xray::aws_client,
+    },
+    acme2::{Authorization, Challenge, Error as AcmeError, Order, ServerError},
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    openssl::rand::rand_bytes,
+    rusoto_core::Region,
+    rusoto_s3::{PutObjectRequest, S3Client, StreamingBody, S3},
+    serde::{self, Serialize},
+    std::{env::var, sync::Mutex},
+};
+
+/// Where to save debugging artifacts when an ACME order fails. This is a global setting, configured through
+/// environment variables:
+///
+/// * `DebugArtifactBucket`: the S3 bucket to write artifacts to. It must be in the same region as the function. If
+///   unset, no artifacts are saved.
+/// * `DebugArtifactPrefix`: the prefix for artifact keys. This defaults to `acme-debug/`.
+///
+/// Each failed order is written as a single JSON object, `<prefix><id>.json`, and the id is included in the error
+/// returned for the request. See AcmeDebugArtifact for the contents.
+#[derive(Clone, Debug)]
+pub struct DebugArtifactStore {
+    bucket: String,
+    prefix: String,
+}
+
+impl DebugArtifactStore {
+    /// Returns the store configured via environment variables, or None if debug artifacts are disabled.
+    pub fn from_env() -> Option<Self> {
+        let bucket = var(ENV_DEBUG_ARTIFACT_BUCKET).ok().filter(|bucket| !bucket.is_empty())?;
+        let prefix = var(ENV_DEBUG_ARTIFACT_PREFIX).unwrap_or_else(|_| DEFAULT_DEBUG_ARTIFACT_PREFIX.to_string());
+        Some(Self {
+            bucket,
+            prefix,
+        })
+    }
+
+    /// The S3 URL the artifact with the given id is written to.
+    pub fn location(&self, id: &str) -> String {
+        format!("s3://{}/{}", self.bucket, self.key(id))
+    }
+
+    fn key(&self, id: &str) -> String {
+        format!("{}{}.json", self.prefix, id)
+    }
+
+    /// Write an artifact to S3.
+    pub async fn save(&self, artifact: &AcmeDebugArtifact) -> Result<(), LambdaError> {
+        let s3 = S3Client::new_with_client(aws_client(), Region::default());
+        let por = PutObjectRequest {
+            bucket: self.bucket.clone(),
+            key: self.key(&artifact.id),
+            content_type: Some("application/json".to_string()),
+            server_side_encryption: Some("AES256".to_string()),
+            body: Some(StreamingBody::from(serde_json::to_vec_pretty(artifact)?)),
+            ..Default::default()
+        };
+
+        match s3.put_object(por).await {
+            Ok(_) => {
+                info!("Saved ACME debug artifacts to {}", self.location(&artifact.id));
+                Ok(())
+            }
+            Err(e) => {
+                error!("Failed to save ACME debug artifacts to {}: {:#}", self.location(&artifact.id), e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+/// Everything we know about a failed ACME order. This never includes keys, challenge tokens, or other secrets. In
+/// JSON:
+///
+///     {
+///         // The id of the artifact, as reported in the error.
+///         "Id": str,
+///
+///         // When the failure was captured, in seconds since the Unix epoch.
+///         "CapturedAt": int,
+///
+///         // The ACME directory and domain names requested.
+///         "Directory": str,
+///         "DomainNames": [str, ...],
+///
+///         // The error returned for the request.
+///         "Error": str,
+///
+///         // The problem documents returned by the ACME server, if any. See AcmeProblem.
+///         "Problems": [{ ... }, ...],
+///
+///         // The order and its authorizations as last seen from the ACME server, including the problem document
+///         // for each failed challenge.
+///         "Order": { ... },
+///         "Authorizations": [{ ... }, ...],
+///
+///         // The last answer from each nameserver checked while waiting for DNS-01 challenge records to
+///         // propagate. See DnsLookupEvidence.
+///         "DnsLookups": [{ ... }, ...],
+///     }
+#[derive(Debug, Default, Serialize)]
+pub struct AcmeDebugArtifact {
+    #[serde(rename = "Id")]
+    pub id: String,
+
+    #[serde(rename = "CapturedAt")]
+    pub captured_at: i64,
+
+    #[serde(rename = "Directory")]
+    pub directory: String,
+
+    #[serde(rename = "DomainNames")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "Error")]
+    pub error: String,
+
+    #[serde(rename = "Problems", skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<AcmeProblem>,
+
+    #[serde(rename = "Order", skip_serializing_if = "Option::is_none")]
+    pub order: Option<OrderSnapshot>,
+
+    #[serde(rename = "Authorizations", skip_serializing_if = "Vec::is_empty")]
+    pub authorizations: Vec<AuthorizationSnapshot>,
+
+    #[serde(rename = "DnsLookups", skip_serializing_if = "Vec::is_empty")]
+    pub dns_lookups: Vec<DnsLookupEvidence>,
+}
+
+/// An RFC 8555 problem document returned by the ACME server. acme2 doesn't expose subproblems, so only the top-level
+/// problem is recorded.
+#[derive(Clone, Debug, Serialize)]
+pub struct AcmeProblem {
+    #[serde(rename = "Type", skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+
+    #[serde(rename = "Title", skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+
+    #[serde(rename = "Status", skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+
+    #[serde(rename = "Detail", skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl From<&ServerError> for AcmeProblem {
+    fn from(problem: &ServerError) -> Self {
+        Self {
+            type_: problem.r#type.clone(),
+            title: problem.title.clone(),
+            status: problem.status,
+            detail: problem.detail.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct OrderSnapshot {
+    #[serde(rename = "Status")]
+    pub status: String,
+
+    #[serde(rename = "Expires", skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+
+    #[serde(rename = "Error", skip_serializing_if = "Option::is_none")]
+    pub error: Option<AcmeProblem>,
+}
+
+impl From<&Order> for OrderSnapshot {
+    fn from(order: &Order) -> Self {
+        Self {
+            status: format!("{:?}", order.status),
+            expires: order.expires.clone(),
+            error: order.error.as_ref().map(AcmeProblem::from),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuthorizationSnapshot {
+    #[serde(rename = "Identifier")]
+    pub identifier: String,
+
+    #[serde(rename = "Status")]
+    pub status: String,
+
+    #[serde(rename = "Wildcard")]
+    pub wildcard: bool,
+
+    #[serde(rename = "Expires", skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+
+    #[serde(rename = "Challenges")]
+    pub challenges: Vec<ChallengeSnapshot>,
+}
+
+impl From<&Authorization> for AuthorizationSnapshot {
+    fn from(auth: &Authorization) -> Self {
+        Self {
+            identifier: auth.identifier.value.clone(),
+            status: format!("{:?}", auth.status),
+            wildcard: auth.wildcard.unwrap_or(false),
+            expires: auth.expires.clone(),
+            challenges: auth.challenges.iter().map(ChallengeSnapshot::from).collect(),
+        }
+    }
+}
+
+/// A challenge offered for an authorization. The token is deliberately omitted.
+#[derive(Clone, Debug, Serialize)]
+pub struct ChallengeSnapshot {
+    #[serde(rename = "Type")]
+    pub type_: String,
+
+    #[serde(rename = "Status")]
+    pub status: String,
+
+    #[serde(rename = "Validated", skip_serializing_if = "Option::is_none")]
+    pub validated: Option<String>,
+
+    #[serde(rename = "Error", skip_serializing_if = "Option::is_none")]
+    pub error: Option<AcmeProblem>,
+}
+
+impl From<&Challenge> for ChallengeSnapshot {
+    fn from(challenge: &Challenge) -> Self {
+        Self {
+            type_: challenge.r#type.clone(),
+            status: format!("{:?}", challenge.status),
+            validated: challenge.validated.clone(),
+            error: challenge.error.as_ref().map(AcmeProblem::from),
+        }
+    }
+}
+
+/// What one authoritative nameserver returned for a DNS-01 challenge record. In JSON:
+///
+///     {
+///         "RecordName": str,
+///         "Nameserver": str,
+///
+///         // The TXT value we were waiting for, and the values the nameserver returned.
+///         "Expected": str,
+///         "Values": [str, ...],
+///
+///         // If the lookup failed, the reason.
+///         "Error": str,
+///
+///         // When the lookup was made, in seconds since the Unix epoch.
+///         "CheckedAt": int,
+///     }
+#[derive(Clone, Debug, Serialize)]
+pub struct DnsLookupEvidence {
+    #[serde(rename = "RecordName")]
+    pub record_name: String,
+
+    #[serde(rename = "Nameserver")]
+    pub nameserver: String,
+
+    #[serde(rename = "Expected")]
+    pub expected: String,
+
+    #[serde(rename = "Values")]
+    pub values: Vec<String>,
+
+    #[serde(rename = "Error", skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(rename = "CheckedAt")]
+    pub checked_at: i64,
+}
+
+/// Collects the state of an ACME order as it's processed so it can be saved if the order fails.
+#[derive(Debug, Default)]
+pub struct AcmeDebugCapture {
+    artifact: Mutex<AcmeDebugArtifact>,
+}
+
+impl AcmeDebugCapture {
+    /// Record the order and fetch the current state of its authorizations from the ACME server.
+    pub async fn record_order(&self, order: &Order) {
+        let authorizations = match order.authorizations().await {
+            Ok(authorizations) => authorizations.iter().map(AuthorizationSnapshot::from).collect(),
+            Err(e) => {
+                error!("Failed to fetch authorizations for debug artifacts: {:#}", e);
+                vec![]
+            }
+        };
+
+        let mut artifact = self.artifact.lock().expect("Debug artifact lock poisoned");
+        artifact.order = Some(OrderSnapshot::from(order));
+        if !authorizations.is_empty() {
+            artifact.authorizations = authorizations;
+        }
+    }
+
+    /// Finish the artifact for a failed order. If the error came from the ACME server, its problem document is
+    /// included.
+    pub fn finish(
+        self,
+        directory: &str,
+        domain_names: &[String],
+        e: &LambdaError,
+        dns_lookups: Vec<DnsLookupEvidence>,
+    ) -> Result<AcmeDebugArtifact, LambdaError> {
+        let mut artifact = self.artifact.into_inner().expect("Debug artifact lock poisoned");
+        artifact.id = new_artifact_id()?;
+        artifact.captured_at = now_epoch_secs();
+        artifact.directory = directory.to_string();
+        artifact.domain_names = domain_names.to_vec();
+        artifact.error = format!("{:#}", e);
+        if let Some(AcmeError::Server(problem)) = e.downcast_ref::<AcmeError>() {
+            artifact.problems.push(AcmeProblem::from(problem));
+        }
+        if let Some(order_problem) = artifact.order.as_ref().and_then(|order| order.error.clone()) {
+            artifact.problems.push(order_problem);
+        }
+        artifact.dns_lookups = dns_lookups;
+        Ok(artifact)
+    }
+}
+
+/// A new artifact id: the capture time followed by random hex, so ids sort by time and don't collide.
+fn new_artifact_id() -> Result<String, LambdaError> {
+    let mut suffix = [0u8; 6];
+    rand_bytes(&mut suffix)?;
+    let suffix: Vec<String> = suffix.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("{}-{}", now_epoch_secs(), suffix.join("")))
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{new_artifact_id, AcmeDebugArtifact, AcmeDebugCapture, DebugArtifactStore, DnsLookupEvidence},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_debug_artifact() {
+        let e: lambda_runtime::Error = "Challenge failed for domain example.com".into();
+        let lookup = DnsLookupEvidence {
+            record_name: "_acme-challenge.example.com".to_string(),
+            nameserver: "205.251.192.1".to_string(),
+            expected: "abc".to_string(),
+            values: vec![],
+            error: Some("no records found".to_string()),
+            checked_at: 1700000000,
+        };
+
+        let artifact = AcmeDebugCapture::default()
+            .finish("https://acme.example/dir", &["example.com".to_string()], &e, vec![lookup])
+            .unwrap();
+        assert!(artifact.problems.is_empty());
+        assert!(artifact.order.is_none());
+
+        let value = serde_json::to_value(&artifact).unwrap();
+        assert_eq!(value["Error"], json!("Challenge failed for domain example.com"));
+        assert_eq!(value["DnsLookups"][0]["Nameserver"], json!("205.251.192.1"));
+        assert!(value.get("Authorizations").is_none());
+
+        let store = DebugArtifactStore {
+            bucket: "debug".to_string(),
+            prefix: "acme-debug/".to_string(),
+        };
+        assert_eq!(store.location("123-abc"), "s3://debug/acme-debug/123-abc.json");
+        assert_ne!(new_artifact_id().unwrap(), new_artifact_id().unwrap());
+    }
+}
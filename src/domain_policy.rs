@@ -0,0 +1,200 @@
+use {
+    crate::{
+        constants::ENV_DOMAIN_POLICY_PARAMETER,
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        utils::ssm_acme_parameter_path,
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    regex::{Regex, RegexBuilder},
+    rusoto_core::{Region, RusotoError},
+    rusoto_ssm::{GetParameterError, GetParameterRequest, Ssm, SsmClient},
+    serde::{self, Deserialize, Serialize},
+    std::env::var,
+};
+
+/// The prefix that marks a pattern as a regular expression rather than a glob.
+const REGEX_PREFIX: &str = "regex:";
+
+/// The domain names this function is allowed to request certificates for. This is read from an SSM parameter (by
+/// default `{AcmeParameterPath}/DomainPolicy`, or the parameter named by the `DomainPolicyParameter` environment
+/// variable) rather than the request, so the patterns can be controlled by a different team than the one deploying
+/// certificates. If the parameter doesn't exist, any domain name may be requested. In JSON:
+///
+///     {
+///         // If non-empty, every domain name on a certificate must match at least one of these patterns.
+///         "AllowedDomainPatterns": [str, ...],
+///
+///         // No domain name on a certificate may match any of these patterns. Denied patterns take precedence
+///         // over allowed patterns.
+///         "DeniedDomainPatterns": [str, ...],
+///     }
+///
+/// Patterns are matched against the whole domain name, case-insensitively. A pattern is either a glob, where "*"
+/// matches within a single label and "**" matches any number of labels (e.g. "*.example.com" or
+/// "**.internal.example.com"), or a regular expression prefixed with "regex:" (e.g. "regex:app[0-9]+\.example\.com"),
+/// which is anchored at both ends.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DomainPolicy {
+    #[serde(rename = "AllowedDomainPatterns", default)]
+    pub allowed_domain_patterns: Vec<String>,
+
+    #[serde(rename = "DeniedDomainPatterns", default)]
+    pub denied_domain_patterns: Vec<String>,
+}
+
+impl DomainPolicy {
+    /// Returns the name of the SSM parameter holding the policy.
+    fn parameter_name() -> String {
+        match var(ENV_DOMAIN_POLICY_PARAMETER) {
+            Ok(name) if !name.is_empty() => name,
+            _ => format!("{}/DomainPolicy", ssm_acme_parameter_path()),
+        }
+    }
+
+    /// Load the policy from SSM, returning None if no policy is configured. Any other failure is an error so a
+    /// policy that can't be read doesn't silently allow everything.
+    pub async fn load() -> Result<Option<Self>, LambdaError> {
+        let param_name = Self::parameter_name();
+        let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+        let gp_request = GetParameterRequest {
+            name: param_name.clone(),
+            with_decryption: Some(true),
+        };
+
+        let value = match ssm.get_parameter(gp_request).await {
+            Ok(response) => response.parameter.and_then(|p| p.value),
+            Err(RusotoError::Service(GetParameterError::ParameterNotFound(_))) => None,
+            Err(e) => {
+                error!("Failed to read domain policy from SSM parameter {}: {:#}", param_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        match value {
+            None => Ok(None),
+            Some(value) => match serde_json::from_str(&value) {
+                Ok(policy) => {
+                    info!("Loaded domain policy from SSM parameter {}", param_name);
+                    Ok(Some(policy))
+                }
+                Err(e) => Err(CertificateRequestError::invalid_domain_policy(format!(
+                    "SSM parameter {} is not a valid policy: {}",
+                    param_name, e
+                ))),
+            },
+        }
+    }
+
+    /// Check that every domain name is allowed by the policy.
+    pub fn check(&self, domain_names: &[String]) -> Result<(), LambdaError> {
+        let allowed = compile_patterns(&self.allowed_domain_patterns)?;
+        let denied = compile_patterns(&self.denied_domain_patterns)?;
+
+        for domain_name in domain_names {
+            let domain_name = domain_name.trim_end_matches('.');
+
+            if let Some((pattern, _)) = denied.iter().find(|(_, re)| re.is_match(domain_name)) {
+                return Err(InvalidCertificateRequest::domain_not_allowed(format!(
+                    "{} matches denied pattern {}",
+                    domain_name, pattern
+                )));
+            }
+
+            if !allowed.is_empty() && !allowed.iter().any(|(_, re)| re.is_match(domain_name)) {
+                return Err(InvalidCertificateRequest::domain_not_allowed(format!(
+                    "{} does not match any allowed pattern",
+                    domain_name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Compile patterns into anchored, case-insensitive regular expressions, keeping the original pattern for errors.
+fn compile_patterns(patterns: &[String]) -> Result<Vec<(&str, Regex)>, LambdaError> {
+    let mut result = Vec::with_capacity(patterns.len());
+
+    for pattern in patterns {
+        let re = match pattern.strip_prefix(REGEX_PREFIX) {
+            Some(re) => format!("^(?:{})$", re),
+            None => format!("^{}$", glob_to_regex(pattern)),
+        };
+
+        match RegexBuilder::new(&re).case_insensitive(true).build() {
+            Ok(re) => result.push((pattern.as_str(), re)),
+            Err(e) => {
+                return Err(CertificateRequestError::invalid_domain_policy(format!(
+                    "Invalid pattern {:?}: {}",
+                    pattern, e
+                )))
+            }
+        }
+    }
+
+    Ok(result)
+}
+
+/// Convert a glob to an (unanchored) regular expression: "**" matches anything, "*" matches anything but a dot, and
+/// everything else is literal.
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::with_capacity(glob.len() * 2);
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == '*' {
+            if chars.peek() == Some(&'*') {
+                chars.next();
+                re.push_str(".*");
+            } else {
+                re.push_str("[^.]*");
+            }
+        } else {
+            re.push_str(&regex::escape(&c.to_string()));
+        }
+    }
+
+    re
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::DomainPolicy;
+
+    fn policy(allowed: &[&str], denied: &[&str]) -> DomainPolicy {
+        DomainPolicy {
+            allowed_domain_patterns: allowed.iter().map(|s| s.to_string()).collect(),
+            denied_domain_patterns: denied.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_domain_policy() {
+        let p = policy(&["example.com", "*.example.com", "**.corp.example.net"], &["regex:(.*\\.)?secret\\..*"]);
+        assert!(p.check(&names(&["example.com", "www.example.com", "*.example.com"])).is_ok());
+        assert!(p.check(&names(&["WWW.Example.COM"])).is_ok());
+        assert!(p.check(&names(&["a.b.corp.example.net", "x.corp.example.net"])).is_ok());
+        assert!(p.check(&names(&["a.www.example.com"])).is_err());
+        assert!(p.check(&names(&["example.org"])).is_err());
+        assert!(p.check(&names(&["secret.example.com"])).is_err());
+        assert!(p.check(&names(&["www.secret.corp.example.net"])).is_err());
+
+        // Regexes are anchored and dots in globs are literal.
+        let p = policy(&["regex:app[0-9]+\\.example\\.com", "api.example.com"], &[]);
+        assert!(p.check(&names(&["app12.example.com"])).is_ok());
+        assert!(p.check(&names(&["app12.example.com.evil.com"])).is_err());
+        assert!(p.check(&names(&["apixexample.com"])).is_err());
+
+        // An empty policy allows everything; an invalid pattern fails closed.
+        assert!(policy(&[], &[]).check(&names(&["anything.example"])).is_ok());
+        assert!(policy(&["regex:("], &[]).check(&names(&["example.com"])).is_err());
+    }
+}
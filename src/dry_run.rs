@@ -0,0 +1,251 @@
+use {
+    crate::errors::InvalidCertificateRequest,
+    lambda_runtime::Error as LambdaError,
+    rusoto_core::Region,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+};
+
+/// ACME directories with a staging counterpart that dry runs can issue from, and that counterpart.
+const STAGING_DIRECTORIES: &[(&str, &str)] = &[
+    ("https://acme-v02.api.letsencrypt.org/directory", "https://acme-staging-v02.api.letsencrypt.org/directory"),
+    (
+        "https://acme-staging-v02.api.letsencrypt.org/directory",
+        "https://acme-staging-v02.api.letsencrypt.org/directory",
+    ),
+    ("https://dv.acme-v02.api.pki.goog/directory", "https://dv.acme-v02.test-api.pki.goog/directory"),
+    ("https://dv.acme-v02.test-api.pki.goog/directory", "https://dv.acme-v02.test-api.pki.goog/directory"),
+];
+
+/// How a dry run (a request with "DryRun": true) gets a certificate. In JSON, the "DryRunIssuance" key of a request:
+///
+/// * `"Skip"`: don't contact the CA at all. This is the default.
+/// * `"Staging"`: issue a certificate from the staging counterpart of Directory (Let's Encrypt and Google Trust
+///   Services have one), exercising the account and authorization setup end to end. This creates the challenge
+///   responses (e.g. Route 53 TXT records) and, if needed, a staging ACME account key, as a real run would; these
+///   writes are listed in the report's StagingWrites. The staging certificate is checked but never stored.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum DryRunIssuance {
+    #[default]
+    Skip,
+    Staging,
+}
+
+/// Returns the staging directory to issue from instead of the given (resolved) directory in a Staging dry run.
+pub fn staging_directory(directory: &str) -> Result<String, LambdaError> {
+    let directory = directory.trim_end_matches('/');
+    match STAGING_DIRECTORIES.iter().find(|(url, _)| *url == directory) {
+        Some((_, staging)) => Ok(staging.to_string()),
+        None => Err(InvalidCertificateRequest::invalid_dry_run(format!(
+            "No staging directory is known for {}; use \"DryRunIssuance\": \"Skip\"",
+            directory
+        ))),
+    }
+}
+
+/// A write a storage target would make, reported by dry runs. In JSON:
+///
+///     {
+///         // The API call (or file operation) that would be made, e.g. "s3:PutObject" or "file:Write".
+///         "Action": str,
+///
+///         // What would be written: an ARN, an S3 URL, a parameter or secret name, a file path, etc.
+///         "Resource": str,
+///
+///         // The region of the resource, for regional AWS services.
+///         "Region": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct PlannedWrite {
+    #[serde(rename = "Action")]
+    pub action: String,
+
+    #[serde(rename = "Resource")]
+    pub resource: String,
+
+    #[serde(rename = "Region", default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+}
+
+impl PlannedWrite {
+    /// A write to a global resource, such as an IAM server certificate or a file.
+    pub fn new<A: Into<String>, R: Into<String>>(action: A, resource: R) -> Self {
+        Self {
+            action: action.into(),
+            resource: resource.into(),
+            region: None,
+        }
+    }
+
+    /// A write to a resource in a region.
+    pub fn in_region<A: Into<String>, R: Into<String>>(action: A, resource: R, region: &Region) -> Self {
+        Self {
+            action: action.into(),
+            resource: resource.into(),
+            region: Some(region.name().to_string()),
+        }
+    }
+}
+
+/// What a dry run found. In JSON:
+///
+///     {
+///         // How the certificate was obtained: "Skip" (no certificate was issued) or "Staging".
+///         "Issuance": str,
+///
+///         // The directory a real run would order the certificate from, or that the staging certificate was
+///         // issued from.
+///         "Directory": str,
+///
+///         // For Staging issuance, the serial number of the staging certificate.
+///         "Serial": str,
+///
+///         // For Staging issuance, the writes made to obtain the staging certificate: the staging account key (if
+///         // it didn't exist yet) and the challenge responses, which are deleted again once authorized. See
+///         // PlannedWrite.
+///         "StagingWrites": [{ ... }, ...],
+///
+///         // The writes each storage target that isn't up to date would receive. See DryRunTarget.
+///         "Targets": [{ ... }, ...],
+///     }
+///
+/// The Plan of the response lists the action for every target, including those that are up to date.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct DryRunReport {
+    #[serde(rename = "Issuance")]
+    pub issuance: DryRunIssuance,
+
+    #[serde(rename = "Directory")]
+    pub directory: String,
+
+    #[serde(rename = "Serial", default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+
+    #[serde(rename = "StagingWrites", default, skip_serializing_if = "Vec::is_empty")]
+    pub staging_writes: Vec<PlannedWrite>,
+
+    #[serde(rename = "Targets", default)]
+    pub targets: Vec<DryRunTarget>,
+}
+
+impl DryRunReport {
+    /// The total number of writes that would be made.
+    pub fn writes(&self) -> usize {
+        self.targets.iter().map(|target| target.writes.len()).sum()
+    }
+}
+
+/// The writes a storage target would receive in a dry run. In JSON:
+///
+///     {
+///         // The index of the target in the request's Storage list (after replicas are expanded).
+///         "StorageIndex": int,
+///
+///         // The target's "Type".
+///         "Type": str,
+///
+///         // The writes it would receive. See PlannedWrite.
+///         "Writes": [{ ... }, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct DryRunTarget {
+    #[serde(rename = "StorageIndex")]
+    pub storage_index: usize,
+
+    #[serde(rename = "Type")]
+    pub type_name: String,
+
+    #[serde(rename = "Writes")]
+    pub writes: Vec<PlannedWrite>,
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{staging_directory, DryRunIssuance},
+        crate::{auth::CertificateAuthorization, storage::CertificateStorage},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_staging_directory() {
+        assert_eq!(
+            staging_directory("https://acme-v02.api.letsencrypt.org/directory").unwrap(),
+            "https://acme-staging-v02.api.letsencrypt.org/directory"
+        );
+        assert_eq!(
+            staging_directory("https://acme-staging-v02.api.letsencrypt.org/directory/").unwrap(),
+            "https://acme-staging-v02.api.letsencrypt.org/directory"
+        );
+        assert!(staging_directory("https://acme.zerossl.com/v2/DV90").is_err());
+
+        let issuance: DryRunIssuance = serde_json::from_str("\"Staging\"").unwrap();
+        assert_eq!(issuance, DryRunIssuance::Staging);
+        assert_eq!(DryRunIssuance::default(), DryRunIssuance::Skip);
+    }
+
+    #[test]
+    fn test_planned_writes() {
+        let domain_names = vec!["*.example.com".to_string()];
+        let resources = |storage: serde_json::Value, store_alternate_chain: bool| -> Vec<String> {
+            let storage: CertificateStorage = serde_json::from_value(storage).unwrap();
+            storage
+                .planned_writes(&domain_names, store_alternate_chain)
+                .into_iter()
+                .map(|write| write.resource)
+                .collect()
+        };
+
+        assert_eq!(
+            resources(json!({"Type": "File", "Directory": "/etc/ssl/{Domain}", "AllowPrivateKey": false}), false),
+            vec![
+                "/etc/ssl/_.example.com/cert.pem",
+                "/etc/ssl/_.example.com/chain.pem",
+                "/etc/ssl/_.example.com/fullchain.pem"
+            ]
+        );
+
+        // Only targets receiving the default chain store the alternate chain alongside it.
+        assert_eq!(resources(json!({"Type": "File", "Directory": "/certs"}), true).len(), 6);
+        assert_eq!(resources(json!({"Type": "File", "Directory": "/certs", "Chain": "Alternate"}), true).len(), 4);
+
+        let storage: CertificateStorage = serde_json::from_value(
+            json!({"Type": "IamServerCertificate", "NamePrefix": "example-", "Path": "/certs/"}),
+        )
+        .unwrap();
+        let writes = storage.planned_writes(&domain_names, false);
+        assert_eq!(writes[0].action, "iam:UploadServerCertificate");
+        assert_eq!(writes[0].resource, "/certs/example-<serial>");
+        assert_eq!(writes[0].region, None);
+        assert_eq!(writes[1].action, "iam:DeleteServerCertificate");
+    }
+    #[test]
+    fn test_staging_writes() {
+        let writes = |auth: serde_json::Value, domain_names: &[&str]| -> Vec<String> {
+            let auth: CertificateAuthorization = serde_json::from_value(auth).unwrap();
+            let domain_names: Vec<String> = domain_names.iter().map(|dn| dn.to_string()).collect();
+            auth.planned_writes(&domain_names)
+                .into_iter()
+                .map(|write| format!("{} {}", write.action, write.resource))
+                .collect()
+        };
+
+        // A wildcard shares its challenge record with its base domain.
+        assert_eq!(
+            writes(json!({"Type": "DnsRoute53"}), &["*.example.com", "example.com", "www.example.net"]),
+            vec![
+                "route53:ChangeResourceRecordSets _acme-challenge.example.com (TXT)",
+                "route53:ChangeResourceRecordSets _acme-challenge.www.example.net (TXT)",
+            ]
+        );
+        assert_eq!(
+            writes(json!({"Type": "HttpS3", "Bucket": "web", "Prefix": "{DomainName}/"}), &["example.com"]),
+            vec!["s3:PutObject s3://web/example.com/.well-known/acme-challenge/<token>"]
+        );
+        assert_eq!(
+            writes(json!({"Type": "TlsAlpn"}), &["example.com"]),
+            vec!["ssm:PutParameter /AcmeParameters/TlsAlpnChallenge/example.com"]
+        );
+    }
+}
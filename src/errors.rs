@@ -5,80 +5,402 @@ use std::{
 
 /// Error respresenting the reasons why a certificate request failed.
 #[derive(Debug)]
-pub(crate) enum CertificateRequestError {
+pub enum CertificateRequestError {
+    /// A named ACME account couldn't be registered because the AcmeAccountLimit was reached.
+    AccountLimitReached(String, usize),
+
+    /// The ACME server refused to create an account with an External Account Binding.
+    AccountRegistrationFailed(String),
+
+    /// A one-shot agent run (RunMode=Once) failed.
+    AgentRunFailed,
+
     /// Authorization unexpectedly failed for the specified domain.
     AuthorizationFailed(String),
 
+    /// The certificate's subject alternative names don't cover the specified requested domain names.
+    CertificateDomainMismatch(String),
+
+    /// The private key doesn't match the certificate for the specified domain names.
+    CertificateKeyMismatch(String),
+
     /// Challenge failed for the specified domain.
     ChallengeFailed(String),
 
+    /// The DNS challenge record was not visible on all authoritative nameservers before the timeout.
+    DnsPropagationTimeout(String),
+
+    /// A request to a DNS provider outside of AWS (e.g. Cloudflare) failed.
+    DnsProviderFailed(String),
+
     /// The specified challenge type was not presented as an option for the specified domain.
     ChallengeNotAvailable(String, String),
 
+    /// The HTTP challenge response was not served at the challenge URL before the timeout.
+    ChallengeNotServed(String),
+
+    /// The storage target the reconcile plan copies from doesn't hold the certificate's private key.
+    CopySourceMissingKey(String),
+
     /// No certificates were returned by the ACME server; this is unexpected.
     EmptyCertificateResult,
 
+    /// The ACME order failed; the error, the id of the debug artifact captured for it, and where it was saved.
+    FailedWithDebugArtifact(String, String, String),
+
+    /// A post-issuance hook failed.
+    HookFailed(String),
+
+    /// A fault was injected for testing. This is only produced by builds with the "fault-injection" feature.
+    #[cfg(feature = "fault-injection")]
+    InjectedFault(String),
+
+    /// The AccountKeyStore or AccountKeyKmsKeyId environment variables were invalid.
+    InvalidAccountKeyStore(String),
+
+    /// The AcmeDirectoryRewrites, AcmeTrustAnchorsParameter, or AcmeProxyUrl settings were invalid.
+    InvalidAcmeGateway(String),
+
+    /// The agent mode configuration in SSM could not be parsed.
+    InvalidAgentConfiguration(String),
+
+    /// The arguments to the command-line tool were invalid.
+    InvalidCliOptions(String),
+
+    /// The ConfigSource environment variable was invalid, or the request document it names couldn't be read.
+    InvalidConfigSource(String),
+
+    /// The domain policy in SSM could not be parsed.
+    InvalidDomainPolicy(String),
+
+    /// The MaxIssuancesPerRun, MaxIssuancesPerDay, or IssuanceLimitTable environment variables were invalid.
+    InvalidIssuanceLimits(String),
+
+    /// The arguments to the scaffold subcommand were invalid.
+    InvalidScaffoldOptions(String),
+
+    /// No inventory record exists for the specified domain names, so there is no current certificate to act on.
+    InventoryNotFound(String),
+
+    /// Issuing a certificate would exceed MaxIssuancesPerRun or MaxIssuancesPerDay.
+    IssuanceLimitExceeded(String),
+
+    /// A Kubernetes API request failed.
+    KubernetesApiFailed(String),
+
+    /// A notification channel rejected a notification.
+    NotificationRejected(String),
+
     /// The certificate order (request) failed unexpectedly.
     OrderFailed,
 
+    /// The pre-issuance readiness checks found a domain name that can't be validated.
+    PreflightFailed(String),
+
+    /// The ACME server rejected a request because a rate limit was exceeded. This holds the problem detail, when
+    /// the request can be retried, and the name of the EventBridge rule scheduled to retry it, if any.
+    RateLimited(String, String, Option<String>),
+
+    /// The ACME server refused to revoke a certificate.
+    RevocationFailed(String),
+
+    /// No storage target holds the certificate to revoke along with its private key.
+    RevocationTargetNotFound(String),
+
+    /// A deployment failed verification but could not be rolled back because the previous certificate was not
+    /// recorded.
+    RollbackNotPossible(String),
+
+    /// A configuration secret could not be read from its secret backend.
+    SecretUnavailable(String),
+
+    /// A certificate component is too large to store in an SSM parameter.
+    SsmParameterTooLarge(String),
+
+    /// The certificate's key pair could not be converted to OpenSSH formats. This is only produced by builds with the
+    /// "ssh-output" feature.
+    #[cfg(feature = "ssh-output")]
+    SshOutputFailed(String),
+
     /// The ACME server unexpectedly did not present challenge tokens for the specified challenge type for the
     /// specified domain.
     TokenNotAvailable(String, String),
 
+    /// A response from the ACME server was unexpected, or a key can't be used to sign ACME requests.
+    UnexpectedAcmeResponse(String),
+
     /// A response from AWS was unexpected.
     UnexpectedAwsResponse(String),
+
+    /// The certificate chain doesn't verify up to a trusted root; this holds the reason.
+    UntrustedCertificateChain(String),
+
+    /// A storage location was last written by a different writer, and the target is configured to fail rather
+    /// than overwrite it. This holds the location and a description of the other writer.
+    WriterConflict(String, String),
 }
 
 impl CertificateRequestError {
-    pub(crate) fn authorization_failed<S: Into<String>>(domain_name: S) -> Box<Self> {
+    pub fn account_limit_reached<S: Into<String>>(account_name: S, limit: usize) -> Box<Self> {
+        Box::new(Self::AccountLimitReached(account_name.into(), limit))
+    }
+
+    pub fn account_registration_failed<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::AccountRegistrationFailed(msg.into()))
+    }
+
+    pub fn agent_run_failed() -> Box<Self> {
+        Box::new(Self::AgentRunFailed)
+    }
+
+    pub fn authorization_failed<S: Into<String>>(domain_name: S) -> Box<Self> {
         Box::new(Self::AuthorizationFailed(domain_name.into()))
     }
 
-    pub(crate) fn challenge_failed<S: Into<String>>(domain_name: S) -> Box<Self> {
+    pub fn certificate_domain_mismatch<S: Into<String>>(domain_names: S) -> Box<Self> {
+        Box::new(Self::CertificateDomainMismatch(domain_names.into()))
+    }
+
+    pub fn certificate_key_mismatch<S: Into<String>>(domain_names: S) -> Box<Self> {
+        Box::new(Self::CertificateKeyMismatch(domain_names.into()))
+    }
+
+    pub fn challenge_failed<S: Into<String>>(domain_name: S) -> Box<Self> {
         Box::new(Self::ChallengeFailed(domain_name.into()))
     }
 
-    pub(crate) fn challenge_not_available<S1: Into<String>, S2: Into<String>>(
+    pub fn challenge_not_available<S1: Into<String>, S2: Into<String>>(
         challenge_type: S1,
         domain_name: S2,
     ) -> Box<Self> {
         Box::new(Self::ChallengeNotAvailable(challenge_type.into(), domain_name.into()))
     }
 
-    pub(crate) fn empty_certificate_result() -> Box<Self> {
+    pub fn challenge_not_served<S: Into<String>>(url: S) -> Box<Self> {
+        Box::new(Self::ChallengeNotServed(url.into()))
+    }
+
+    pub fn copy_source_missing_key<S: Into<String>>(location: S) -> Box<Self> {
+        Box::new(Self::CopySourceMissingKey(location.into()))
+    }
+
+    pub fn dns_propagation_timeout<S: Into<String>>(record_name: S) -> Box<Self> {
+        Box::new(Self::DnsPropagationTimeout(record_name.into()))
+    }
+
+    pub fn dns_provider_failed<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::DnsProviderFailed(msg.into()))
+    }
+
+    pub fn empty_certificate_result() -> Box<Self> {
         Box::new(Self::EmptyCertificateResult)
     }
 
-    pub(crate) fn order_failed() -> Box<Self> {
+    pub fn failed_with_debug_artifact<S1: Into<String>, S2: Into<String>, S3: Into<String>>(
+        error: S1,
+        id: S2,
+        location: S3,
+    ) -> Box<Self> {
+        Box::new(Self::FailedWithDebugArtifact(error.into(), id.into(), location.into()))
+    }
+
+    pub fn hook_failed<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::HookFailed(msg.into()))
+    }
+
+    #[cfg(feature = "fault-injection")]
+    pub fn injected_fault<S: Into<String>>(fault: S) -> Box<Self> {
+        Box::new(Self::InjectedFault(fault.into()))
+    }
+
+    pub fn invalid_account_key_store<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidAccountKeyStore(msg.into()))
+    }
+
+    pub fn invalid_acme_gateway<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidAcmeGateway(msg.into()))
+    }
+
+    pub fn invalid_agent_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidAgentConfiguration(msg.into()))
+    }
+
+    pub fn invalid_cli_options<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidCliOptions(msg.into()))
+    }
+
+    pub fn invalid_config_source<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidConfigSource(msg.into()))
+    }
+
+    pub fn invalid_domain_policy<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidDomainPolicy(msg.into()))
+    }
+
+    pub fn invalid_issuance_limits<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidIssuanceLimits(msg.into()))
+    }
+
+    pub fn invalid_scaffold_options<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidScaffoldOptions(msg.into()))
+    }
+
+    pub fn inventory_not_found<S: Into<String>>(domain_names: S) -> Box<Self> {
+        Box::new(Self::InventoryNotFound(domain_names.into()))
+    }
+
+    pub fn issuance_limit_exceeded<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::IssuanceLimitExceeded(msg.into()))
+    }
+
+    pub fn kubernetes_api_failed<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::KubernetesApiFailed(msg.into()))
+    }
+
+    pub fn notification_rejected<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::NotificationRejected(msg.into()))
+    }
+
+    pub fn order_failed() -> Box<Self> {
         Box::new(Self::OrderFailed)
     }
 
-    pub(crate) fn token_not_available<S1: Into<String>, S2: Into<String>>(
-        challenge_type: S1,
-        domain_name: S2,
+    pub fn preflight_failed<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::PreflightFailed(msg.into()))
+    }
+
+    pub fn rate_limited<S1: Into<String>, S2: Into<String>>(
+        detail: S1,
+        retry_after: S2,
+        retry_rule: Option<String>,
     ) -> Box<Self> {
+        Box::new(Self::RateLimited(detail.into(), retry_after.into(), retry_rule))
+    }
+
+    pub fn revocation_failed<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::RevocationFailed(msg.into()))
+    }
+
+    pub fn revocation_target_not_found<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::RevocationTargetNotFound(msg.into()))
+    }
+
+    pub fn rollback_not_possible<S: Into<String>>(resource: S) -> Box<Self> {
+        Box::new(Self::RollbackNotPossible(resource.into()))
+    }
+
+    pub fn secret_unavailable<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::SecretUnavailable(msg.into()))
+    }
+
+    pub fn ssm_parameter_too_large<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::SsmParameterTooLarge(msg.into()))
+    }
+
+    #[cfg(feature = "ssh-output")]
+    pub fn ssh_output_failed<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::SshOutputFailed(msg.into()))
+    }
+
+    pub fn token_not_available<S1: Into<String>, S2: Into<String>>(challenge_type: S1, domain_name: S2) -> Box<Self> {
         Box::new(Self::TokenNotAvailable(challenge_type.into(), domain_name.into()))
     }
 
-    pub(crate) fn unexpected_aws_response<S: Into<String>>(msg: S) -> Box<Self> {
+    pub fn unexpected_acme_response<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::UnexpectedAcmeResponse(msg.into()))
+    }
+
+    pub fn unexpected_aws_response<S: Into<String>>(msg: S) -> Box<Self> {
         Box::new(Self::UnexpectedAwsResponse(msg.into()))
     }
+
+    pub fn untrusted_certificate_chain<S: Into<String>>(reason: S) -> Box<Self> {
+        Box::new(Self::UntrustedCertificateChain(reason.into()))
+    }
+
+    pub fn writer_conflict<S1: Into<String>, S2: Into<String>>(location: S1, writer: S2) -> Box<Self> {
+        Box::new(Self::WriterConflict(location.into(), writer.into()))
+    }
 }
 
 impl Display for CertificateRequestError {
     fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
         match self {
+            Self::AccountLimitReached(account_name, limit) => {
+                write!(
+                    f,
+                    "Cannot register ACME account {}: the limit of {} accounts has been reached",
+                    account_name, limit
+                )
+            }
+            Self::AccountRegistrationFailed(msg) => write!(f, "ACME account registration failed: {}", msg),
+            Self::AgentRunFailed => write!(f, "Agent run failed"),
             Self::AuthorizationFailed(domain_name) => write!(f, "Authorization failed for domain {}", domain_name),
+            Self::CertificateDomainMismatch(domain_names) => write!(f, "Certificate does not cover {}", domain_names),
+            Self::CertificateKeyMismatch(domain_names) => {
+                write!(f, "Private key does not match the certificate for {}", domain_names)
+            }
             Self::ChallengeFailed(domain_name) => write!(f, "Challenge failed for domain {}", domain_name),
             Self::ChallengeNotAvailable(challenge_type, domain_name) => {
                 write!(f, "Challenge type {} not available for domain {}", challenge_type, domain_name)
             }
+            Self::ChallengeNotServed(url) => write!(f, "Timed out waiting for the challenge to be served at {}", url),
+            Self::CopySourceMissingKey(location) => {
+                write!(f, "Cannot copy the certificate at {}: its private key is not available", location)
+            }
+            Self::DnsPropagationTimeout(record_name) => {
+                write!(f, "Timed out waiting for {} to propagate to all nameservers", record_name)
+            }
+            Self::DnsProviderFailed(msg) => write!(f, "DNS provider request failed: {}", msg),
             Self::EmptyCertificateResult => write!(f, "No certificates returned"),
+            Self::FailedWithDebugArtifact(error, id, location) => {
+                write!(f, "{} (debug artifact {} saved to {})", error, id, location)
+            }
+            Self::HookFailed(msg) => write!(f, "Post-issuance hook failed: {}", msg),
+            #[cfg(feature = "fault-injection")]
+            Self::InjectedFault(fault) => write!(f, "Injected fault: {}", fault),
+            Self::InvalidAccountKeyStore(msg) => write!(f, "Invalid account key store: {}", msg),
+            Self::InvalidAcmeGateway(msg) => write!(f, "Invalid ACME gateway configuration: {}", msg),
+            Self::InvalidAgentConfiguration(msg) => write!(f, "Invalid agent configuration: {}", msg),
+            Self::InvalidCliOptions(msg) => write!(f, "Invalid command-line options: {}", msg),
+            Self::InvalidConfigSource(msg) => write!(f, "Invalid configuration source: {}", msg),
+            Self::InvalidDomainPolicy(msg) => write!(f, "Invalid domain policy: {}", msg),
+            Self::InvalidIssuanceLimits(msg) => write!(f, "Invalid issuance limits: {}", msg),
+            Self::InvalidScaffoldOptions(msg) => write!(f, "Invalid scaffold options: {}", msg),
+            Self::InventoryNotFound(domain_names) => write!(f, "No inventory record found for {}", domain_names),
+            Self::IssuanceLimitExceeded(msg) => write!(f, "Issuance limit exceeded: {}", msg),
+            Self::KubernetesApiFailed(msg) => write!(f, "Kubernetes API request failed: {}", msg),
+            Self::NotificationRejected(msg) => write!(f, "Notification rejected: {}", msg),
             Self::OrderFailed => write!(f, "Order failed"),
+            Self::PreflightFailed(msg) => write!(f, "Preflight checks failed: {}", msg),
+            Self::RateLimited(detail, retry_after, None) => {
+                write!(f, "Rate limited by the ACME server until {}: {}", retry_after, detail)
+            }
+            Self::RateLimited(detail, retry_after, Some(rule)) => write!(
+                f,
+                "Rate limited by the ACME server until {}: {} (retry scheduled by EventBridge rule {})",
+                retry_after, detail, rule
+            ),
+            Self::RevocationFailed(msg) => write!(f, "Revocation failed: {}", msg),
+            Self::RevocationTargetNotFound(msg) => write!(f, "No certificate to revoke: {}", msg),
+            Self::RollbackNotPossible(resource) => {
+                write!(f, "Cannot roll back {}: no previous certificate was recorded", resource)
+            }
+            Self::SecretUnavailable(msg) => write!(f, "Secret unavailable: {}", msg),
+            Self::SsmParameterTooLarge(msg) => write!(f, "Certificate too large for SSM: {}", msg),
+            #[cfg(feature = "ssh-output")]
+            Self::SshOutputFailed(msg) => write!(f, "OpenSSH output failed: {}", msg),
             Self::TokenNotAvailable(challenge_type, domain_name) => {
                 write!(f, "No token available for {} challenge for {}", challenge_type, domain_name)
             }
+            Self::UnexpectedAcmeResponse(msg) => write!(f, "Unexpected ACME response: {}", msg),
             Self::UnexpectedAwsResponse(msg) => write!(f, "Unexpected AWS response: {}", msg),
+            Self::UntrustedCertificateChain(reason) => {
+                write!(f, "Certificate chain does not verify to a trusted root: {}", reason)
+            }
+            Self::WriterConflict(location, writer) => {
+                write!(f, "{} was last written by {}; not overwriting it", location, writer)
+            }
         }
     }
 }
@@ -86,15 +408,118 @@ impl Display for CertificateRequestError {
 impl Error for CertificateRequestError {}
 
 #[derive(Debug)]
-pub(crate) enum InvalidCertificateRequest {
+pub enum InvalidCertificateRequest {
     ContactsEmpty,
     DirectoryEmpty,
     DomainNamesEmpty,
+
+    /// A requested domain name is not allowed by the domain policy.
+    DomainNotAllowed(String),
+
+    /// Requests in a batch would produce duplicate certificates and the batch's DuplicatePolicy is Fail.
+    DuplicateDomainNames(String),
+
+    /// The requested key algorithm can't be used with a storage target.
+    IncompatibleKeyAlgorithm(String),
+
+    /// The named ACME account is invalid, or is registered with a different directory.
+    InvalidAccount(String),
+
     InvalidAcmCertificateArn(String),
     InvalidAcmConfiguration(String),
+
+    /// The API Gateway deployment configuration was invalid.
+    InvalidApiGatewayConfiguration(String),
+
+    /// A batch's MaxConcurrency or its requests' Id and DependsOn were invalid.
+    InvalidBatchConfiguration(String),
+
+    /// The CloudFront deployment configuration was invalid.
+    InvalidCloudFrontConfiguration(String),
+
     InvalidContact(String),
+
+    /// The cost allocation settings were invalid.
+    InvalidCostAllocation(String),
+
+    /// The CSR options were invalid.
+    InvalidCsrOptions(String),
+
+    /// The post-deployment verification configuration of a storage target was invalid.
+    InvalidDeploymentVerification(String),
+
     InvalidDirectoryUrl(String),
 
+    /// A DNS provider's configuration or credentials were invalid.
+    InvalidDnsProviderConfiguration(String),
+
+    /// A requested domain name is malformed.
+    InvalidDomainName(String),
+
+    /// The DryRun or DryRunIssuance settings were invalid for the request.
+    InvalidDryRun(String),
+
+    /// The DynamoDB storage configuration was invalid.
+    InvalidDynamoDbConfiguration(String),
+
+    /// The External Account Binding configuration was invalid, or is required by the ACME server but missing.
+    InvalidExternalAccountBinding(String),
+
+    /// The file storage configuration was invalid.
+    InvalidFileConfiguration(String),
+
+    /// A post-issuance hook was misconfigured.
+    InvalidHookConfiguration(String),
+
+    /// The IAM server certificate storage configuration was invalid.
+    InvalidIamServerCertificateConfiguration(String),
+
+    /// The Kubernetes secret deployment configuration was invalid.
+    InvalidKubernetesConfiguration(String),
+
+    /// The load balancer deployment configuration was invalid.
+    InvalidLoadBalancerConfiguration(String),
+
+    /// LockWaitSeconds was out of range.
+    InvalidLockWait(String),
+
+    /// A notification channel was misconfigured.
+    InvalidNotificationConfiguration(String),
+
+    /// The organization audit request was invalid.
+    InvalidOrganizationAudit(String),
+
+    /// The output formats of a storage target were invalid.
+    InvalidOutputFormat(String),
+
+    /// The preferred certificate chain was invalid.
+    InvalidPreferredChain(String),
+
+    /// The regions a storage target should be replicated to were invalid.
+    InvalidRegions(String),
+
+    /// The renewal jitter specified was out of range.
+    InvalidRenewalJitter(String),
+
+    /// A renewal schedule couldn't be set up, e.g. because no target for the renewal rule is configured.
+    InvalidRenewalSchedule(String),
+
+    /// RenewalThresholdDays was out of range.
+    InvalidRenewalThreshold(String),
+
+    /// The request document didn't match its schema; each error is prefixed with the location of the problem, e.g.
+    /// "Storage[1].Bucket".
+    InvalidRequestDocument(Vec<String>),
+
+    /// The retry policy was invalid.
+    InvalidRetryConfig(String),
+
+    /// The revocation settings were invalid.
+    InvalidRevocation(String),
+
+    /// The role to assume for a storage target was invalid.
+    InvalidRoleArn(String),
+
     /// The Route 53 hosted zone does not match the domain name.
     InvalidRoute53HostedZone(String),
 
@@ -103,68 +528,299 @@ pub(crate) enum InvalidCertificateRequest {
     /// The location of the S3 bucket could not be determined.
     InvalidS3Bucket(String),
 
+    /// The key template or a per-object key of an S3 storage target was invalid.
+    InvalidS3KeyTemplate(String),
+
+    /// The Retention setting of an S3 storage target was invalid, or the bucket doesn't support it.
+    InvalidS3Retention(String),
+
+    /// The tags of an S3 storage target were invalid.
+    InvalidS3Tags(String),
+
+    /// A secret reference named an unknown secret backend, or one not compiled into this build.
+    InvalidSecretReference(String),
+
+    /// The Secrets Manager storage configuration was invalid.
+    InvalidSecretsManagerConfiguration(String),
+
     /// The SSM path specified was invalid.
     InvalidSsmParameterPath(String),
 
     /// The SSM tier specified was invalid.
     InvalidSsmTier(String),
 
+    /// StorageIndexes named a storage target that doesn't exist.
+    InvalidStorageIndexes(String),
+
+    /// A trust bundle configuration was invalid, or the bundle held no certificates.
+    InvalidTrustBundle(String),
+
+    /// The Windows certificate store deployment configuration was invalid.
+    InvalidWindowsConfiguration(String),
+
+    /// The requested domain names span multiple registrable domains and AllowMixedRegistrableDomains is not set.
+    MixedRegistrableDomains(String),
+
+    /// No zones in a DNS provider were found that hold a challenge record; the provider and the record name.
+    NoMatchingDnsZones(String, String),
+
     /// No Route 53 hosted zones were found that match the domain name.
     NoMatchingRoute53Zones(String),
+
+    /// An S3 bucket that would receive a private key could be public and AllowPublicBucket is not set; the bucket
+    /// and the reasons.
+    PublicS3Bucket(String, String),
+
+    /// A requested domain name is a public suffix (e.g. "com" or "*.co.uk").
+    PublicSuffix(String),
+
+    /// One or more storage targets failed validation. Each entry describes one target's error.
+    StorageValidationFailed(Vec<String>),
+
+    /// A wildcard domain name was requested but the authorization handler cannot validate wildcards.
+    WildcardNotSupported(String),
 }
 
 impl InvalidCertificateRequest {
-    pub(crate) fn contacts_empty() -> Box<Self> {
+    pub fn contacts_empty() -> Box<Self> {
         Box::new(Self::ContactsEmpty)
     }
 
-    pub(crate) fn directory_empty() -> Box<Self> {
+    pub fn directory_empty() -> Box<Self> {
         Box::new(Self::DirectoryEmpty)
     }
 
-    pub(crate) fn domain_names_empty() -> Box<Self> {
+    pub fn domain_names_empty() -> Box<Self> {
         Box::new(Self::DomainNamesEmpty)
     }
 
-    pub(crate) fn invalid_acm_certificate_arn<S: Into<String>>(arn: S) -> Box<Self> {
+    pub fn domain_not_allowed<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::DomainNotAllowed(msg.into()))
+    }
+
+    pub fn duplicate_domain_names<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::DuplicateDomainNames(msg.into()))
+    }
+
+    pub fn incompatible_key_algorithm<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::IncompatibleKeyAlgorithm(msg.into()))
+    }
+
+    pub fn invalid_account<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidAccount(msg.into()))
+    }
+
+    pub fn invalid_acm_certificate_arn<S: Into<String>>(arn: S) -> Box<Self> {
         Box::new(Self::InvalidAcmCertificateArn(arn.into()))
     }
 
-    pub(crate) fn invalid_acm_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+    pub fn invalid_acm_configuration<S: Into<String>>(msg: S) -> Box<Self> {
         Box::new(Self::InvalidAcmConfiguration(msg.into()))
     }
 
-    pub(crate) fn invalid_contact<S: Into<String>>(msg: S) -> Box<Self> {
+    pub fn invalid_api_gateway_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidApiGatewayConfiguration(msg.into()))
+    }
+
+    pub fn invalid_batch_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidBatchConfiguration(msg.into()))
+    }
+
+    pub fn invalid_cloudfront_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidCloudFrontConfiguration(msg.into()))
+    }
+
+    pub fn invalid_contact<S: Into<String>>(msg: S) -> Box<Self> {
         Box::new(Self::InvalidContact(msg.into()))
     }
 
-    pub(crate) fn invalid_directory_url<S: Into<String>>(msg: S) -> Box<Self> {
+    pub fn invalid_cost_allocation<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidCostAllocation(msg.into()))
+    }
+
+    pub fn invalid_csr_options<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidCsrOptions(msg.into()))
+    }
+
+    pub fn invalid_deployment_verification<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidDeploymentVerification(msg.into()))
+    }
+
+    pub fn invalid_directory_url<S: Into<String>>(msg: S) -> Box<Self> {
         Box::new(Self::InvalidDirectoryUrl(msg.into()))
     }
 
-    pub(crate) fn invalid_route53_hosted_zone<S: Into<String>>(msg: S) -> Box<Self> {
+    pub fn invalid_dns_provider_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidDnsProviderConfiguration(msg.into()))
+    }
+
+    pub fn invalid_domain_name<S: Into<String>>(domain_name: S) -> Box<Self> {
+        Box::new(Self::InvalidDomainName(domain_name.into()))
+    }
+
+    pub fn invalid_dry_run<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidDryRun(msg.into()))
+    }
+
+    pub fn invalid_dynamodb_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidDynamoDbConfiguration(msg.into()))
+    }
+
+    pub fn invalid_external_account_binding<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidExternalAccountBinding(msg.into()))
+    }
+
+    pub fn invalid_file_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidFileConfiguration(msg.into()))
+    }
+
+    pub fn invalid_hook_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidHookConfiguration(msg.into()))
+    }
+
+    pub fn invalid_iam_server_certificate_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidIamServerCertificateConfiguration(msg.into()))
+    }
+
+    pub fn invalid_kubernetes_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidKubernetesConfiguration(msg.into()))
+    }
+
+    pub fn invalid_load_balancer_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidLoadBalancerConfiguration(msg.into()))
+    }
+
+    pub fn invalid_lock_wait<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidLockWait(msg.into()))
+    }
+
+    pub fn invalid_notification_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidNotificationConfiguration(msg.into()))
+    }
+
+    pub fn invalid_organization_audit<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidOrganizationAudit(msg.into()))
+    }
+
+    pub fn invalid_output_format<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidOutputFormat(msg.into()))
+    }
+
+    pub fn invalid_preferred_chain<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidPreferredChain(msg.into()))
+    }
+
+    pub fn invalid_regions<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidRegions(msg.into()))
+    }
+
+    pub fn invalid_renewal_jitter<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidRenewalJitter(msg.into()))
+    }
+
+    pub fn invalid_renewal_schedule<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidRenewalSchedule(msg.into()))
+    }
+
+    pub fn invalid_renewal_threshold<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidRenewalThreshold(msg.into()))
+    }
+
+    pub fn invalid_request_document(errors: Vec<String>) -> Box<Self> {
+        Box::new(Self::InvalidRequestDocument(errors))
+    }
+
+    pub fn invalid_retry_config<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidRetryConfig(msg.into()))
+    }
+
+    pub fn invalid_revocation<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidRevocation(msg.into()))
+    }
+
+    pub fn invalid_role_arn<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidRoleArn(msg.into()))
+    }
+
+    pub fn invalid_route53_hosted_zone<S: Into<String>>(msg: S) -> Box<Self> {
         Box::new(Self::InvalidRoute53HostedZone(msg.into()))
     }
 
-    pub(crate) fn invalid_s3_bucket<S: Into<String>>(bucket: S) -> Box<Self> {
+    pub fn invalid_s3_bucket<S: Into<String>>(bucket: S) -> Box<Self> {
         Box::new(Self::InvalidS3Bucket(bucket.into()))
     }
 
-    pub(crate) fn invalid_s3_encryption_algorithm<S: Into<String>>(alg: S) -> Box<Self> {
+    pub fn invalid_s3_encryption_algorithm<S: Into<String>>(alg: S) -> Box<Self> {
         Box::new(Self::InvalidS3EncryptionAlgorithm(alg.into()))
     }
 
-    pub(crate) fn invalid_ssm_parameter_path<S: Into<String>>(path: S) -> Box<Self> {
+    pub fn invalid_s3_key_template<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidS3KeyTemplate(msg.into()))
+    }
+
+    pub fn invalid_s3_retention<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidS3Retention(msg.into()))
+    }
+
+    pub fn invalid_s3_tags<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidS3Tags(msg.into()))
+    }
+
+    pub fn invalid_secret_reference<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidSecretReference(msg.into()))
+    }
+
+    pub fn invalid_secrets_manager_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidSecretsManagerConfiguration(msg.into()))
+    }
+
+    pub fn invalid_ssm_parameter_path<S: Into<String>>(path: S) -> Box<Self> {
         Box::new(Self::InvalidSsmParameterPath(path.into()))
     }
 
-    pub(crate) fn invalid_ssm_tier<S: Into<String>>(tier: S) -> Box<Self> {
+    pub fn invalid_ssm_tier<S: Into<String>>(tier: S) -> Box<Self> {
         Box::new(Self::InvalidSsmTier(tier.into()))
     }
 
-    pub(crate) fn no_matching_route53_zones<S: Into<String>>(msg: S) -> Box<Self> {
+    pub fn invalid_storage_indexes<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidStorageIndexes(msg.into()))
+    }
+
+    pub fn invalid_trust_bundle<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidTrustBundle(msg.into()))
+    }
+
+    pub fn invalid_windows_configuration<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::InvalidWindowsConfiguration(msg.into()))
+    }
+
+    pub fn mixed_registrable_domains<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::MixedRegistrableDomains(msg.into()))
+    }
+
+    pub fn no_matching_dns_zones<S1: Into<String>, S2: Into<String>>(provider: S1, record_name: S2) -> Box<Self> {
+        Box::new(Self::NoMatchingDnsZones(provider.into(), record_name.into()))
+    }
+
+    pub fn no_matching_route53_zones<S: Into<String>>(msg: S) -> Box<Self> {
         Box::new(Self::NoMatchingRoute53Zones(msg.into()))
     }
+
+    pub fn public_s3_bucket<S1: Into<String>, S2: Into<String>>(bucket: S1, findings: S2) -> Box<Self> {
+        Box::new(Self::PublicS3Bucket(bucket.into(), findings.into()))
+    }
+
+    pub fn public_suffix<S: Into<String>>(domain_name: S) -> Box<Self> {
+        Box::new(Self::PublicSuffix(domain_name.into()))
+    }
+
+    pub fn storage_validation_failed(errors: Vec<String>) -> Box<Self> {
+        Box::new(Self::StorageValidationFailed(errors))
+    }
+
+    pub fn wildcard_not_supported<S: Into<String>>(msg: S) -> Box<Self> {
+        Box::new(Self::WildcardNotSupported(msg.into()))
+    }
 }
 
 impl Display for InvalidCertificateRequest {
@@ -173,16 +829,77 @@ impl Display for InvalidCertificateRequest {
             Self::ContactsEmpty => f.write_str("Contacts cannot be empty"),
             Self::DirectoryEmpty => f.write_str("Directory cannot be empty"),
             Self::DomainNamesEmpty => f.write_str("DomainNames cannot be empty"),
+            Self::DomainNotAllowed(msg) => write!(f, "Domain name not allowed by policy: {}", msg),
+            Self::DuplicateDomainNames(msg) => write!(f, "Duplicate certificates in batch: {}", msg),
+            Self::IncompatibleKeyAlgorithm(msg) => write!(f, "Incompatible key algorithm: {}", msg),
+            Self::InvalidAccount(msg) => write!(f, "Invalid account: {}", msg),
             Self::InvalidAcmCertificateArn(arn) => write!(f, "Invalid ACM certificate ARN: {}", arn),
             Self::InvalidAcmConfiguration(msg) => write!(f, "Invalid ACM configuration: {}", msg),
+            Self::InvalidApiGatewayConfiguration(msg) => write!(f, "Invalid API Gateway configuration: {}", msg),
+            Self::InvalidBatchConfiguration(msg) => write!(f, "Invalid batch configuration: {}", msg),
+            Self::InvalidCloudFrontConfiguration(msg) => write!(f, "Invalid CloudFront configuration: {}", msg),
             Self::InvalidContact(msg) => write!(f, "Invalid contact: {}", msg),
+            Self::InvalidCostAllocation(msg) => write!(f, "Invalid cost allocation: {}", msg),
+            Self::InvalidCsrOptions(msg) => write!(f, "Invalid CSR options: {}", msg),
+            Self::InvalidDeploymentVerification(msg) => write!(f, "Invalid deployment verification: {}", msg),
             Self::InvalidDirectoryUrl(msg) => write!(f, "Invalid directory URL: {}", msg),
+            Self::InvalidDnsProviderConfiguration(msg) => write!(f, "Invalid DNS provider configuration: {}", msg),
+            Self::InvalidDomainName(domain_name) => write!(f, "Invalid domain name: {}", domain_name),
+            Self::InvalidDryRun(msg) => write!(f, "Invalid dry run: {}", msg),
+            Self::InvalidDynamoDbConfiguration(msg) => write!(f, "Invalid DynamoDB configuration: {}", msg),
+            Self::InvalidExternalAccountBinding(msg) => write!(f, "Invalid external account binding: {}", msg),
+            Self::InvalidFileConfiguration(msg) => write!(f, "Invalid file storage configuration: {}", msg),
+            Self::InvalidHookConfiguration(msg) => write!(f, "Invalid hook configuration: {}", msg),
+            Self::InvalidIamServerCertificateConfiguration(msg) => {
+                write!(f, "Invalid IAM server certificate configuration: {}", msg)
+            }
+            Self::InvalidKubernetesConfiguration(msg) => write!(f, "Invalid Kubernetes configuration: {}", msg),
+            Self::InvalidLoadBalancerConfiguration(msg) => write!(f, "Invalid load balancer configuration: {}", msg),
+            Self::InvalidLockWait(msg) => write!(f, "Invalid lock wait: {}", msg),
+            Self::InvalidNotificationConfiguration(msg) => write!(f, "Invalid notification configuration: {}", msg),
+            Self::InvalidOrganizationAudit(msg) => write!(f, "Invalid organization audit: {}", msg),
+            Self::InvalidOutputFormat(msg) => write!(f, "Invalid output format: {}", msg),
+            Self::InvalidPreferredChain(msg) => write!(f, "Invalid preferred chain: {}", msg),
+            Self::InvalidRegions(msg) => write!(f, "Invalid regions: {}", msg),
+            Self::InvalidRenewalJitter(msg) => write!(f, "Invalid renewal jitter: {}", msg),
+            Self::InvalidRenewalSchedule(msg) => write!(f, "Invalid renewal schedule: {}", msg),
+            Self::InvalidRenewalThreshold(msg) => write!(f, "Invalid renewal threshold: {}", msg),
+            Self::InvalidRequestDocument(errors) => {
+                write!(f, "Request has {} configuration error(s): {}", errors.len(), errors.join("; "))
+            }
+            Self::InvalidRetryConfig(msg) => write!(f, "Invalid retry configuration: {}", msg),
+            Self::InvalidRevocation(msg) => write!(f, "Invalid revocation: {}", msg),
+            Self::InvalidRoleArn(msg) => write!(f, "Invalid role ARN: {}", msg),
             Self::InvalidRoute53HostedZone(msg) => write!(f, "Invalid Route 53 hosted zone: {}", msg),
             Self::InvalidS3EncryptionAlgorithm(alg) => write!(f, "Invalid S3EncryptionAlgorithm: {}", alg),
             Self::InvalidS3Bucket(bucket) => write!(f, "Invalid S3 bucket: {}", bucket),
+            Self::InvalidS3KeyTemplate(msg) => write!(f, "Invalid S3 key template: {}", msg),
+            Self::InvalidS3Retention(msg) => write!(f, "Invalid S3 retention: {}", msg),
+            Self::InvalidS3Tags(msg) => write!(f, "Invalid S3 tags: {}", msg),
+            Self::InvalidSecretReference(msg) => write!(f, "Invalid secret reference: {}", msg),
+            Self::InvalidSecretsManagerConfiguration(msg) => {
+                write!(f, "Invalid Secrets Manager configuration: {}", msg)
+            }
             Self::InvalidSsmParameterPath(path) => write!(f, "Invalid SSM parameter path: {}", path),
             Self::InvalidSsmTier(tier) => write!(f, "Invalid SSM tier: {}", tier),
+            Self::InvalidStorageIndexes(msg) => write!(f, "Invalid storage indexes: {}", msg),
+            Self::InvalidTrustBundle(msg) => write!(f, "Invalid trust bundle: {}", msg),
+            Self::InvalidWindowsConfiguration(msg) => write!(f, "Invalid Windows deployment configuration: {}", msg),
+            Self::MixedRegistrableDomains(msg) => write!(f, "Domain names span multiple registrable domains: {}", msg),
+            Self::NoMatchingDnsZones(provider, record_name) => {
+                write!(f, "No matching {} zones for {}", provider, record_name)
+            }
             Self::NoMatchingRoute53Zones(domain) => write!(f, "No matching Route 53 zones for domain: {}", domain),
+            Self::PublicS3Bucket(bucket, findings) => {
+                write!(f, "S3 bucket {} could be public; not storing the private key there: {}", bucket, findings)
+            }
+            Self::PublicSuffix(domain_name) => {
+                write!(f, "Cannot request a certificate for a public suffix: {}", domain_name)
+            }
+            Self::StorageValidationFailed(errors) => {
+                write!(f, "{} storage target(s) failed validation: {}", errors.len(), errors.join("; "))
+            }
+            Self::WildcardNotSupported(msg) => write!(f, "Wildcard domain names are not supported: {}", msg),
         }
     }
 }
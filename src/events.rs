@@ -1,12 +1,39 @@
 use {
     crate::{
+        account::ExternalAccountBinding,
+        audit::AuditReport,
         auth::CertificateAuthorization,
+        batch::{BatchResponse, CertificateBatchRequest},
+        consistency::ConsistencyCheck,
+        cost_ledger::CostAllocation,
+        csr::CsrOptions,
+        dry_run::{DryRunIssuance, DryRunReport},
+        hooks::{HookResult, PostIssuanceHook},
+        inventory::InventoryDiff,
+        keys::KeyAlgorithm,
+        migrate::{MigrateConfigRequest, MigrateConfigResponse},
+        notifications::{NotificationConfig, NotificationDelivery},
+        org_audit::{OrganizationAuditReport, OrganizationAuditRequest},
+        readiness::ReadinessReport,
+        reconcile::{ReconcileAction, RENEWAL_THRESHOLD_DAYS},
+        report::RunReport,
+        retry::RetryConfig,
+        revoke::{RevocationConfig, RevocationResult},
+        schedule::RenewalSchedule,
+        schema::SchemaRequest,
         storage::{CertificateStorage, CertificateStorageResult},
+        task::{StorageTargetResult, TaskError, TaskStatus},
+        utils::default_false,
     },
     aws_lambda_events::event::{
         alb::{AlbTargetGroupRequest, AlbTargetGroupResponse},
         apigw::{ApiGatewayProxyRequest, ApiGatewayProxyResponse, ApiGatewayV2httpRequest, ApiGatewayV2httpResponse},
     },
+    schemars::{
+        gen::SchemaGenerator,
+        schema::{Schema, SchemaObject, SubschemaValidation},
+        JsonSchema,
+    },
     serde::{
         self,
         de::{
@@ -16,17 +43,27 @@ use {
         },
         Deserialize, Serialize,
     },
+    serde_json::Value,
     std::fmt::{Formatter, Result as FmtResult},
 };
 
+#[cfg(feature = "ssh-output")]
+use crate::ssh::SshOutputConfig;
+
 /// The incoming Lambda request.
 ///
 /// This Lambda function can be called via AWS Step Functions (to keep the state machine powering the certficate
 /// request/renewal going); an AWS Application Load Balancer integrated directly with
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
-pub(crate) enum Request {
+pub enum Request {
     Certificate(Box<CertificateRequest>),
+    Batch(Box<CertificateBatchRequest>),
+    BatchList(Vec<CertificateRequest>),
+    Event(Box<EventBridgeEvent>),
+    Schema(SchemaRequest),
+    MigrateConfig(Box<MigrateConfigRequest>),
+    OrganizationAudit(Box<OrganizationAuditRequest>),
     ApiGatewayV1(Box<ApiGatewayProxyRequest>),
     ApiGatewayV2(Box<ApiGatewayV2httpRequest>),
     Alb(Box<AlbTargetGroupRequest>),
@@ -36,54 +73,351 @@ pub(crate) enum Request {
 /// do not include comments in your JSON):
 ///
 ///     {
-///         // The URL for the ACME server, e.g. "https://acme-staging-v02.api.letsencrypt.org/directory"
+///         // The URL for the ACME server, e.g. "https://acme-staging-v02.api.letsencrypt.org/directory", or one of
+///         // the presets "LetsEncryptProduction", "LetsEncryptStaging", "ZeroSSL", "BuyPass", or "GoogleTrust".
+///         // This may also be given as "DirectoryUrl".
 ///         "Directory": str,
 ///         
 ///         // List of domain names to request/renew certificates for.       
 ///         "DomainNames": [str, ...]
 ///         
+///         // If true, allow DomainNames to span multiple registrable domains (e.g. "example.com" and
+///         // "example.org"). The default is false, which catches copy-and-paste mistakes before they use up
+///         // validation attempts.
+///         "AllowMixedRegistrableDomains": bool
+///
 ///         // List of contact URLs. Note that Let's Encrypt only supports one contact, and it must be a
 ///         // "mailto:user@domain" URL.
 ///         "Contacts": [str, ...]
 ///
-///         // Instruction for handling authorization. See HttpS3Authorization.
+///         // External Account Binding credentials for CAs that require them to create an account, such as
+///         // ZeroSSL and Google Trust Services. See ExternalAccountBinding.
+///         "ExternalAccountBinding": { ... }
+///
+///         // The name of a registered ACME account to use, e.g. "production" or "staging". The account is created
+///         // the first time it's named, and is tied to this Directory from then on. If unset, the account is
+///         // selected by the first contact and the directory's host. See RegisteredAccount.
+///         "Account": str
+///
+///         // Instruction for handling authorization. See DnsRoute53Authorization, DnsCloudflareAuthorization,
+///         // DnsGoogleAuthorization, HttpApiGatewayAuthorization, HttpS3Authorization, and
+///         // TlsAlpnAuthorization.
 ///         "Authorization": { ... }
 ///
-///         // An array of storage mechanisms for the certificate. See AcmStorage, S3Storage, and SsmStorage.
+///         // The algorithm for the certificate's private key: "Rsa2048", "Rsa4096", "EcdsaP256", or
+///         // "EcdsaP384". The default is "Rsa2048".
+///         "KeyAlgorithm": str
+///
+///         // Extensions to request in the certificate signing request, such as OCSP Must-Staple or explicit key
+///         // usages. See CsrOptions.
+///         "Csr": { ... }
+///
+///         // Renew up to this many days earlier than usual, by an amount derived from a hash of DomainNames. This
+///         // spreads the renewals of certificates onboarded at the same time over several days instead of
+///         // renewing them all in one run. Must be between 0 and 15; the default is 0 (no jitter).
+///         "RenewalJitterDays": int
+///
+///         // Storage targets holding a certificate that expires in more than this many days are left alone; if
+///         // all of them are, the CA isn't contacted and the response status is "Skipped". This makes it safe to
+///         // run on a daily schedule. Must be between 1 and 60; the default is 30.
+///         "RenewalThresholdDays": int
+///
+///         // An array of storage mechanisms for the certificate. See AcmStorage, ApiGatewayStorage,
+///         // CloudFrontStorage, IamServerCertificateStorage, LoadBalancerStorage, S3Storage,
+///         // SecretsManagerStorage, and SsmParameterStorage.
 ///         "Storage": []
 ///
+///         // If set, only the storage targets at these indexes in Storage (after replicas are expanded) are written;
+///         // the other targets that need to be written are reported as "Excluded" in the plan. This is typically
+///         // the FailedStorageIndexes of an earlier response, so a Step Functions retry rewrites only the targets
+///         // that failed. Targets that are already up to date are never written.
+///         "StorageIndexes": [int, ...]
+///
+///         // The action to take: "Issue" (the default) to issue or renew the certificate as needed,
+///         // "TestRotation" to publish a synthetic rotation manifest for the current certificate without
+///         // issuing a new one, "Audit" to report the certificate held by each storage target and whether it
+///         // expires within RenewalThresholdDays, without issuing or writing anything (see AuditReport),
+///         // "Revoke" to revoke the certificate held by the storage targets (see Revocation), "Preflight" to
+///         // check whether each domain name is ready to be validated without contacting the CA (see
+///         // ReadinessReport), or "Schedule" to create or update an EventBridge rule that renews the
+///         // certificate when it's due (see RenewalScheduler). A request holding only {"Action": "Schema"}
+///         // returns the JSON schemas of these formats instead; see SchemaRequest. A request with
+///         // {"Action": "MigrateConfig"} rewrites a configuration that uses deprecated fields in the current
+///         // format; see MigrateConfigRequest.
+///         "Action": str
+///
+///         // If true, run the Preflight checks before ordering a certificate, and fail without contacting the CA
+///         // if any domain name is not ready. The default is false.
+///         "Preflight": bool
+///
+///         // If true, move the certificate's renewal rule to the next renewal time after an Issue run (or a day
+///         // later if the run fails). This is set on the requests the Schedule action's rule sends; it's rarely
+///         // useful to set it directly. The default is false.
+///         "ScheduleRenewal": bool
+///
+///         // If the RenewalLockTable environment variable is set, Issue and Revoke runs take a lock on DomainNames
+///         // so that concurrent invocations (e.g. a scheduled run overlapping a manual one) don't race. If another
+///         // invocation holds the lock, wait up to this many seconds for it to be released before returning the
+///         // "AlreadyInProgress" status. Must be between 0 and 600; the default is 0 (don't wait). See RenewalLock.
+///         "LockWaitSeconds": int
+///
+///         // For the Revoke action, which certificate to revoke and why. See RevocationConfig.
+///         "Revocation": { ... }
+///
+///         // If true, store the alternate chain offered by the CA during a chain transition alongside the
+///         // default chain in storage targets that support it (S3, Secrets Manager, and SSM). The default is
+///         // false. Individual storage targets can instead receive the alternate chain in place of the default
+///         // one by setting "Chain": "Alternate" on the target.
+///         "StoreAlternateChain": bool
+///
+///         // The common name of the root CA the default chain should end at, e.g. "ISRG Root X1". Among the
+///         // chains the CA's certificate chain can be shortened to, the first whose topmost certificate was issued
+///         // by this root is used as the default chain. If none matches, the chain served by the ACME server is
+///         // used. If omitted, the chain served by the ACME server is always used.
+///         "PreferredChain": str
+///
+///         // How the certificate is checked before it is written to any storage target: the private key must
+///         // match it, it must cover every domain name, and its chain must verify up to a trusted root. A
+///         // certificate that fails these checks is never stored. See ConsistencyCheck.
+///         "ConsistencyCheck": { ... }
+///
+///         // If true, validate the request and report the writes each storage target that isn't up to date would
+///         // receive (ARNs, object keys, parameter and secret names, etc.) without writing anything, sending
+///         // notifications or events, or running hooks. The response status is "DryRun". This cannot be combined
+///         // with the Revoke, Schedule, or TestRotation actions. The default is false.
+///         "DryRun": bool
+///
+///         // For dry runs, whether to skip issuance ("Skip", the default) or issue a certificate from the
+///         // staging counterpart of Directory ("Staging") to exercise authorization end to end. Staging issuance
+///         // writes the challenge responses (e.g. Route 53 TXT records) and, if needed, a staging account key. See
+///         // DryRunIssuance.
+///         "DryRunIssuance": str
+///
+///         // If true, publish a rotation manifest to SSM whenever the certificate is rotated. The default is
+///         // false. See RotationManifest.
+///         "RotationManifest": bool
+///
+///         // Where to send notifications after the run, and which events each channel receives. See
+///         // NotificationConfig.
+///         "Notifications": { ... }
+///
+///         // The EventBridge event bus to send certificate lifecycle events to. This defaults to the
+///         // LifecycleEventBus environment variable; if neither is set, no events are sent. See
+///         // LifecycleEvent.
+///         "EventBusName": str
+///
+///         // Hooks to run, in order, after the certificate is written to at least one storage target, e.g. to
+///         // invoke a Lambda function with custom distribution logic or reload servers through Run Command. See
+///         // PostIssuanceHook.
+///         "Hooks": [{ ... }, ...]
+///
+///         // The tenant and cost allocation tags recorded in the cost ledger for each renewal, if the ledger is
+///         // enabled. See CostAllocation and CostLedger.
+///         "CostAllocation": { ... }
+///
+///         // How AWS and ACME calls made for this request are retried when they fail with a transient error, e.g.
+///         // to also retry 5xx responses. This defaults to the policy set by the environment. See RetryConfig.
+///         "Retry": { ... }
+///
+///         // Also write the key pair in OpenSSH formats (and optionally an SSH host certificate) to SSM. This is
+///         // only available in builds with the "ssh-output" feature. See SshOutputConfig.
+///         "SshOutput": { ... }
+///
+///         // These are only used in batches; see CertificateBatchRequest. Id identifies this request in other
+///         // requests' DependsOn, and defaults to the first domain name. DependsOn lists the requests that must
+///         // succeed before this one starts. Among requests that are ready to run, those with a higher Priority
+///         // start first; the default is 0.
+///         "Id": str
+///         "DependsOn": [str, ...]
+///         "Priority": int
+///
 ///         // The current state of the request. This should be unset in the initial request. Pass the state
 ///         // from an incomplete response back into this value. All other values must be passed in unchanged
 ///         // from the initial request.
 ///         "State": {}
 ///     }
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct CertificateRequest {
-    /// The URL for the ACME server, e.g. `"https://acme-staging-v02.api.letsencrypt.org/directory"`
-    #[serde(rename = "Directory")]
-    pub(crate) directory: String,
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CertificateRequest {
+    /// The URL for the ACME server, e.g. `"https://acme-staging-v02.api.letsencrypt.org/directory"`, or a preset
+    /// name such as `"LetsEncryptStaging"`; presets are resolved to URLs during validation.
+    #[serde(rename = "Directory", alias = "DirectoryUrl")]
+    pub directory: String,
 
     #[serde(rename = "DomainNames", deserialize_with = "string_or_vec")]
-    pub(crate) domain_names: Vec<String>,
+    #[schemars(schema_with = "string_or_vec_schema")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "AllowMixedRegistrableDomains", default = "default_false")]
+    pub allow_mixed_registrable_domains: bool,
 
     #[serde(rename = "Contacts", deserialize_with = "string_or_vec")]
-    pub(crate) contacts: Vec<String>,
+    #[schemars(schema_with = "string_or_vec_schema")]
+    pub contacts: Vec<String>,
+
+    #[serde(rename = "ExternalAccountBinding", default, skip_serializing_if = "Option::is_none")]
+    pub external_account_binding: Option<ExternalAccountBinding>,
+
+    #[serde(rename = "Account", default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
 
     #[serde(rename = "Authorization")]
-    pub(crate) auth: CertificateAuthorization,
+    pub auth: CertificateAuthorization,
+
+    #[serde(rename = "KeyAlgorithm", default)]
+    pub key_algorithm: KeyAlgorithm,
+
+    #[serde(rename = "Csr", default)]
+    pub csr: CsrOptions,
+
+    #[serde(rename = "Action", default)]
+    pub action: CertificateAction,
+
+    #[serde(rename = "Revocation", default)]
+    pub revocation: RevocationConfig,
+
+    #[serde(rename = "Preflight", default = "default_false")]
+    pub preflight: bool,
+
+    #[serde(rename = "ScheduleRenewal", default = "default_false")]
+    pub schedule_renewal: bool,
+
+    #[serde(rename = "StorageIndexes", default, skip_serializing_if = "Option::is_none")]
+    pub storage_indexes: Option<Vec<usize>>,
+
+    #[serde(rename = "LockWaitSeconds", default)]
+    pub lock_wait_seconds: u64,
+
+    #[serde(rename = "RotationManifest", default = "default_false")]
+    pub rotation_manifest: bool,
+
+    #[serde(rename = "StoreAlternateChain", default = "default_false")]
+    pub store_alternate_chain: bool,
+
+    #[serde(rename = "PreferredChain", default, skip_serializing_if = "Option::is_none")]
+    pub preferred_chain: Option<String>,
+
+    #[serde(rename = "ConsistencyCheck", default)]
+    pub consistency_check: ConsistencyCheck,
+
+    #[serde(rename = "DryRun", default = "default_false")]
+    pub dry_run: bool,
+
+    #[serde(rename = "DryRunIssuance", default)]
+    pub dry_run_issuance: DryRunIssuance,
+
+    #[serde(rename = "Notifications", default)]
+    pub notifications: Option<NotificationConfig>,
+
+    #[serde(rename = "EventBusName", default)]
+    pub event_bus_name: Option<String>,
+
+    #[serde(rename = "Hooks", default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<PostIssuanceHook>,
+
+    #[serde(rename = "CostAllocation", default)]
+    pub cost_allocation: CostAllocation,
+
+    #[serde(rename = "RenewalJitterDays", default)]
+    pub renewal_jitter_days: i64,
+
+    #[serde(rename = "RenewalThresholdDays", default = "default_renewal_threshold_days")]
+    pub renewal_threshold_days: i64,
+
+    #[serde(rename = "Retry", default)]
+    pub retry: RetryConfig,
+
+    #[cfg(feature = "ssh-output")]
+    #[serde(rename = "SshOutput", default)]
+    pub ssh_output: Option<SshOutputConfig>,
+
+    #[serde(rename = "Id", default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+
+    #[serde(rename = "DependsOn", default, deserialize_with = "string_or_vec", skip_serializing_if = "Vec::is_empty")]
+    #[schemars(schema_with = "string_or_vec_schema")]
+    pub depends_on: Vec<String>,
+
+    #[serde(rename = "Priority", default)]
+    pub priority: i32,
 
     #[serde(rename = "Storage", deserialize_with = "cert_storage_or_vec")]
-    pub(crate) storage: Vec<CertificateStorage>,
+    #[schemars(schema_with = "cert_storage_or_vec_schema")]
+    pub storage: Vec<CertificateStorage>,
 }
 
-/// The types of responses we can send back.
+/// The action to take for a certificate request.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum CertificateAction {
+    /// Issue or renew the certificate as needed to bring all storage targets up to date.
+    #[default]
+    Issue,
+
+    /// Publish a synthetic rotation manifest for the current certificate so downstream consumers can test their
+    /// pipelines. No certificate is issued and no storage targets are written.
+    TestRotation,
+
+    /// Report the certificate held by each storage target and alert on any expiring within the renewal threshold.
+    /// No certificate is issued and no storage targets are written.
+    Audit,
+
+    /// Revoke a certificate held by the storage targets through the ACME server. No certificate is issued and no
+    /// storage targets are written.
+    Revoke,
+
+    /// Check whether each domain name's DNS delegation and network reachability allow it to be validated. No
+    /// certificate is issued and no storage targets are written.
+    Preflight,
+
+    /// Create or update the EventBridge rule that renews the certificate when it's due. No certificate is issued and
+    /// no storage targets are written.
+    Schedule,
+}
+
+/// An event delivered by Amazon EventBridge, e.g. an ACM certificate state change or a scheduled event. Only the
+/// fields we use are modeled.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct EventBridgeEvent {
+    #[serde(rename = "detail-type")]
+    pub detail_type: String,
+
+    #[serde(rename = "source")]
+    pub source: String,
+
+    #[serde(rename = "resources", default)]
+    pub resources: Vec<String>,
+
+    #[serde(rename = "detail", default)]
+    pub detail: Value,
+}
+
+/// The types of responses we can send back. Only one is built per invocation, so the size of the largest isn't a
+/// concern.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(untagged)]
-pub(crate) enum Response {
+pub enum Response {
     Certificate(CertificateResponse),
+    Batch(BatchResponse),
+    Event(EventResponse),
     ApiGatewayV1(ApiGatewayProxyResponse),
     ApiGatewayV2(ApiGatewayV2httpResponse),
     Alb(AlbTargetGroupResponse),
+    Schema(Value),
+    MigrateConfig(MigrateConfigResponse),
+    OrganizationAudit(OrganizationAuditReport),
+}
+
+impl Response {
+    /// Returns false if the request or any certificate in it failed outright; a partial success counts as success.
+    pub fn succeeded(&self) -> bool {
+        match self {
+            Self::Certificate(response) => {
+                !matches!(response.status, CertificateResponseStatus::Failed | CertificateResponseStatus::RolledBack)
+            }
+            Self::Batch(response) => response.results.iter().all(|result| result.error.is_none()),
+            _ => true,
+        }
+    }
 }
 
 impl From<ApiGatewayProxyResponse> for Response {
@@ -111,34 +445,160 @@ impl From<AlbTargetGroupResponse> for Response {
 ///         // Inidicates whether the request is completed or additional steps are required.
 ///         "Completed": bool,
 ///
-///         // If the request is completed, this indicates the status of the certificate.
+///         // If the request is completed, this indicates the status of the certificate: "Success",
+///         // "PartialSuccess", "Skipped" (every storage target already held a current certificate),
+///         // "RolledBack" (a deployment failed verification and was reverted to its previous certificate),
+///         // "DryRun" (the request was a dry run and no storage target was written), "AlreadyInProgress" (another
+///         // invocation is renewing the same domain names; see LockWaitSeconds), or "Failed".
 ///         "Status": str,
 ///
+///         // If the request is completed, a machine-readable summary of the status for Step Functions Choice
+///         // states: "Succeeded", "PartialFailure", or "Failed". See TaskStatus.
+///         "TaskStatus": str,
+///
+///         // If any storage target failed (or the run failed as a whole), why. Error is a stable identifier such
+///         // as "StorageTargetsFailed"; Cause describes each failure. See TaskError.
+///         "Error": { ... },
+///
+///         // The result of each storage target that was written, with its index in Storage. See
+///         // StorageTargetResult.
+///         "Targets": []
+///
+///         // The indexes of the storage targets that failed. Pass this as the StorageIndexes of the next request
+///         // to retry only those targets.
+///         "FailedStorageIndexes": [int, ...]
+///
 ///         // If the request is completed, this holds information about where the certificate is
-///         // stored. See AcmStorageResult, S3StorageResult, and SsmParameterStorageResult for details.
+///         // stored. See AcmStorageResult, ApiGatewayStorageResult, CloudFrontStorageResult,
+///         // IamServerCertificateStorageResult, LoadBalancerStorageResult, S3StorageResult,
+///         // SecretsManagerStorageResult, and SsmParameterStorageResult for details.
 ///         "StorageResults": []
 ///
+///         // The actions planned for each storage target. See ReconcileAction for details.
+///         "Plan": []
+///
+///         // The time and memory used by the run, with advice on sizing the function. See RunReport.
+///         "Report": {}
+///
+///         // If a certificate was written and a previous run recorded one in the inventory, what changed
+///         // between them. See InventoryDiff.
+///         "Diff": {}
+///
+///         // The result of delivering the run's notification to each matching channel. See
+///         // NotificationDelivery.
+///         "Notifications": []
+///
+///         // The result of running each post-issuance hook. See HookResult.
+///         "Hooks": []
+///
+///         // For the Audit action, the certificate held by each storage target. The status is "Success" if every
+///         // target holds a certificate expiring after the threshold and "Failed" otherwise. See AuditReport.
+///         "Audit": {}
+///
+///         // For the Revoke action, the certificate that was revoked. See RevocationResult.
+///         "Revocation": {}
+///
+///         // For the Preflight action, or if Preflight was requested, whether each domain name is ready to be
+///         // validated. For the Preflight action, the status is "Success" if every check passed and "Failed"
+///         // otherwise. See ReadinessReport.
+///         "Readiness": {}
+///
+///         // For dry runs, the writes each storage target would receive. See DryRunReport.
+///         "DryRun": {}
+///
+///         // For the Schedule action, or a renewal started by the renewal rule, when the rule will next renew the
+///         // certificate. See RenewalSchedule.
+///         "Schedule": {}
+///
 ///         // If the request is incomplete, this is the state parameter to pass into the subsequent
 ///         // invocation.
 ///         "State": {}
 ///     }
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct CertificateResponse {
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CertificateResponse {
     #[serde(rename = "Finished")]
-    pub(crate) finished: bool,
+    pub finished: bool,
 
     #[serde(rename = "Status")]
-    pub(crate) status: CertificateResponseStatus,
+    pub status: CertificateResponseStatus,
 
     #[serde(rename = "StorageResults")]
-    pub(crate) storage: Vec<CertificateStorageResult>,
+    pub storage: Vec<CertificateStorageResult>,
+
+    #[serde(rename = "Plan", default)]
+    pub plan: Vec<ReconcileAction>,
+
+    #[serde(rename = "Report", default, skip_serializing_if = "Option::is_none")]
+    pub report: Option<RunReport>,
+
+    #[serde(rename = "Diff", default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<InventoryDiff>,
+
+    #[serde(rename = "Notifications", default, skip_serializing_if = "Vec::is_empty")]
+    pub notifications: Vec<NotificationDelivery>,
+
+    #[serde(rename = "Hooks", default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<HookResult>,
+
+    #[serde(rename = "Audit", default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<AuditReport>,
+
+    #[serde(rename = "Revocation", default, skip_serializing_if = "Option::is_none")]
+    pub revocation: Option<RevocationResult>,
+
+    #[serde(rename = "Readiness", default, skip_serializing_if = "Option::is_none")]
+    pub readiness: Option<ReadinessReport>,
+
+    #[serde(rename = "DryRun", default, skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<DryRunReport>,
+
+    #[serde(rename = "Schedule", default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<RenewalSchedule>,
+
+    #[serde(rename = "TaskStatus", default, skip_serializing_if = "Option::is_none")]
+    pub task_status: Option<TaskStatus>,
+
+    #[serde(rename = "Targets", default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<StorageTargetResult>,
+
+    #[serde(rename = "FailedStorageIndexes", default, skip_serializing_if = "Vec::is_empty")]
+    pub failed_storage_indexes: Vec<usize>,
+
+    #[serde(rename = "Error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<TaskError>,
 }
 
+/// The response to an EventBridge event. In JSON:
+///
+///     {
+///         // Whether the event was recognized and acted upon.
+///         "Handled": bool,
+///
+///         // A description of what was done.
+///         "Message": str,
+///     }
 #[derive(Debug, Deserialize, Serialize)]
+pub struct EventResponse {
+    #[serde(rename = "Handled")]
+    pub handled: bool,
+
+    #[serde(rename = "Message")]
+    pub message: String,
+}
+
+const fn default_renewal_threshold_days() -> i64 {
+    RENEWAL_THRESHOLD_DAYS
+}
+
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
 #[serde(untagged)]
-pub(crate) enum CertificateResponseStatus {
+pub enum CertificateResponseStatus {
     Success,
     PartialSuccess,
+    Skipped,
+    RolledBack,
+    DryRun,
+    AlreadyInProgress,
     PendingValidation,
     PendingOrderFulfillment,
     Failed,
@@ -169,7 +629,7 @@ impl<'de> Visitor<'de> for StringOrVec {
 }
 
 /// string_or_vec is a helper function to deserialize a string or a list of strings.
-fn string_or_vec<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+pub fn string_or_vec<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
     D: Deserializer<'de>,
 {
@@ -189,7 +649,7 @@ impl<'de> Visitor<'de> for CertStorageOrVec {
     where
         M: MapAccess<'de>,
     {
-        Deserialize::deserialize(MapAccessDeserializer::new(map))
+        Ok(vec![Deserialize::deserialize(MapAccessDeserializer::new(map))?])
     }
 
     fn visit_seq<S>(self, seq: S) -> Result<Self::Value, S::Error>
@@ -208,6 +668,28 @@ where
     deserializer.deserialize_any(CertStorageOrVec)
 }
 
+/// The schema for a field deserialized with string_or_vec.
+pub fn string_or_vec_schema(gen: &mut SchemaGenerator) -> Schema {
+    one_or_vec_schema::<String>(gen)
+}
+
+/// The schema for a field deserialized with cert_storage_or_vec.
+fn cert_storage_or_vec_schema(gen: &mut SchemaGenerator) -> Schema {
+    one_or_vec_schema::<CertificateStorage>(gen)
+}
+
+fn one_or_vec_schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    SchemaObject {
+        subschemas: Some(Box::new(SubschemaValidation {
+            any_of: Some(vec![gen.subschema_for::<T>(), gen.subschema_for::<Vec<T>>()]),
+            ..Default::default()
+        })),
+        ..Default::default()
+    }
+    .into()
+}
+
+#[cfg(test)]
 #[allow(unused_imports, dead_code)]
 mod test {
     use {
@@ -243,21 +725,21 @@ mod test {
 
     #[tokio::test]
     async fn test_deser_basic_certificate_request() {
-        env_logger::builder().filter_level(LevelFilter::Debug).init();
-        let result = serde_json::from_str::<CertificateRequest>(&BASIC_CERT_REQUEST);
+        let _ = env_logger::builder().filter_level(LevelFilter::Debug).is_test(true).try_init();
+        let result = serde_json::from_str::<CertificateRequest>(BASIC_CERT_REQUEST);
         assert!(result.is_ok(), "Error: {:?}", result);
 
-        let result = serde_json::from_str::<Request>(&BASIC_CERT_REQUEST);
+        let result = serde_json::from_str::<Request>(BASIC_CERT_REQUEST);
         assert!(result.is_ok(), "Error: {:?}", result);
     }
 
     #[tokio::test]
     async fn test_deser_non_list_certificate_request() {
-        env_logger::builder().filter_level(LevelFilter::Debug).init();
-        let result = serde_json::from_str::<CertificateRequest>(&NON_LIST_REQUEST);
+        let _ = env_logger::builder().filter_level(LevelFilter::Debug).is_test(true).try_init();
+        let result = serde_json::from_str::<CertificateRequest>(NON_LIST_REQUEST);
         assert!(result.is_ok(), "Error: {:?}", result);
 
-        let result = serde_json::from_str::<Request>(&NON_LIST_REQUEST);
+        let result = serde_json::from_str::<Request>(NON_LIST_REQUEST);
         assert!(result.is_ok(), "Error: {:?}", result);
     }
 }
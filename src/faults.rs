@@ -0,0 +1,200 @@
+use {lambda_runtime::Error as LambdaError, rusoto_core::RusotoError, std::future::Future};
+
+#[cfg(feature = "fault-injection")]
+use {
+    crate::{constants::ENV_FAULT_INJECTION, errors::CertificateRequestError},
+    bytes::Bytes,
+    http::StatusCode,
+    lazy_static::lazy_static,
+    log::{error, warn},
+    ring::rand::{SecureRandom, SystemRandom},
+    rusoto_core::request::BufferedHttpResponse,
+    std::{env::var, time::Duration},
+    tokio::time::sleep,
+};
+
+// Fault injection for exercising alerting and the retry/rollback paths in staging without a real outage. This is
+// only compiled in with the "fault-injection" feature; without it, every hook below is a no-op.
+//
+// Faults are configured with the FaultInjection environment variable, a comma-separated list of settings:
+//
+//     AcmeServerError=<probability>      Fail ACME orders as if the server returned a 5xx error.
+//     AcmThrottling=<probability>        Fail ACM calls with a ThrottlingException.
+//     SsmFailure=<probability>           Fail individual SSM parameter writes with an internal error.
+//     DnsPropagationDelaySecs=<seconds>  Delay DNS challenge validation after Route 53 reports the change as synced.
+//
+// Probabilities are between 0 and 1 and are evaluated independently for each call, so e.g. SsmFailure=0.25 leaves
+// some components of a certificate written and others not.
+
+/// A fault that can be injected into a call.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fault {
+    AcmeServerError,
+    AcmThrottling,
+    SsmFailure,
+}
+
+#[cfg(feature = "fault-injection")]
+lazy_static! {
+    static ref SETTINGS: FaultSettings = match var(ENV_FAULT_INJECTION) {
+        Ok(spec) => match FaultSettings::parse(&spec) {
+            Ok(settings) => {
+                warn!("Fault injection enabled: {:?}", settings);
+                settings
+            }
+            Err(e) => {
+                error!("Ignoring invalid {} setting {:?}: {}", ENV_FAULT_INJECTION, spec, e);
+                FaultSettings::default()
+            }
+        },
+        Err(_) => FaultSettings::default(),
+    };
+}
+
+#[cfg(feature = "fault-injection")]
+#[derive(Clone, Debug, Default, PartialEq)]
+struct FaultSettings {
+    acme_server_error: f64,
+    acm_throttling: f64,
+    ssm_failure: f64,
+    dns_propagation_delay: Duration,
+}
+
+#[cfg(feature = "fault-injection")]
+impl FaultSettings {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut settings = Self::default();
+
+        for setting in spec.split(',').map(str::trim).filter(|setting| !setting.is_empty()) {
+            let (name, value) = match setting.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim()),
+                None => return Err(format!("Expected Name=Value: {}", setting)),
+            };
+
+            if name == "DnsPropagationDelaySecs" {
+                let secs: u64 = value.parse().map_err(|_| format!("Invalid number of seconds: {}", value))?;
+                settings.dns_propagation_delay = Duration::from_secs(secs);
+                continue;
+            }
+
+            let probability: f64 = value.parse().map_err(|_| format!("Invalid probability: {}", value))?;
+            if !(0.0..=1.0).contains(&probability) {
+                return Err(format!("Probability must be between 0 and 1: {}", value));
+            }
+
+            match name {
+                "AcmeServerError" => settings.acme_server_error = probability,
+                "AcmThrottling" => settings.acm_throttling = probability,
+                "SsmFailure" => settings.ssm_failure = probability,
+                _ => return Err(format!("Unknown fault: {}", name)),
+            }
+        }
+
+        Ok(settings)
+    }
+
+    /// Decide whether to inject a fault on this call.
+    fn fires(&self, fault: Fault) -> bool {
+        let probability = match fault {
+            Fault::AcmeServerError => self.acme_server_error,
+            Fault::AcmThrottling => self.acm_throttling,
+            Fault::SsmFailure => self.ssm_failure,
+        };
+
+        if probability <= 0.0 {
+            return false;
+        }
+
+        let mut buf = [0u8; 4];
+        if SystemRandom::new().fill(&mut buf).is_err() {
+            return false;
+        }
+
+        (u32::from_be_bytes(buf) as f64 / u32::MAX as f64) < probability
+    }
+}
+
+/// Fail with an injected error if the fault is configured to fire on this call.
+#[cfg(feature = "fault-injection")]
+pub fn inject(fault: Fault) -> Result<(), LambdaError> {
+    if SETTINGS.fires(fault) {
+        warn!("Injecting fault: {:?}", fault);
+        Err(CertificateRequestError::injected_fault(format!("{:?}", fault)))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(feature = "fault-injection"))]
+#[inline]
+pub fn inject(_fault: Fault) -> Result<(), LambdaError> {
+    Ok(())
+}
+
+/// Make an AWS call, or fail it without making it if the fault is configured to fire. Injected errors look like the
+/// errors AWS returns (e.g. throttling is reported as a ThrottlingException) so they take the same retry paths.
+#[cfg(feature = "fault-injection")]
+pub async fn aws_call<T, E, F>(fault: Fault, call: F) -> Result<T, RusotoError<E>>
+where
+    F: Future<Output = Result<T, RusotoError<E>>>,
+{
+    if !SETTINGS.fires(fault) {
+        return call.await;
+    }
+
+    warn!("Injecting fault: {:?}", fault);
+    let (status, code) = match fault {
+        Fault::AcmThrottling => (StatusCode::BAD_REQUEST, "ThrottlingException"),
+        _ => (StatusCode::INTERNAL_SERVER_ERROR, "InternalServerError"),
+    };
+
+    Err(RusotoError::Unknown(BufferedHttpResponse {
+        status,
+        body: Bytes::from(format!("{{\"__type\":\"{}\",\"message\":\"Injected fault\"}}", code)),
+        headers: Default::default(),
+    }))
+}
+
+#[cfg(not(feature = "fault-injection"))]
+#[inline]
+pub async fn aws_call<T, E, F>(_fault: Fault, call: F) -> Result<T, RusotoError<E>>
+where
+    F: Future<Output = Result<T, RusotoError<E>>>,
+{
+    call.await
+}
+
+/// Wait for the configured DNS propagation delay, if any.
+#[cfg(feature = "fault-injection")]
+pub async fn dns_propagation_delay() {
+    let delay = SETTINGS.dns_propagation_delay;
+    if delay > Duration::from_secs(0) {
+        warn!("Injecting DNS propagation delay of {}s", delay.as_secs());
+        sleep(delay).await;
+    }
+}
+
+#[cfg(not(feature = "fault-injection"))]
+#[inline]
+pub async fn dns_propagation_delay() {}
+
+#[cfg(feature = "fault-injection")]
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {super::FaultSettings, std::time::Duration};
+
+    #[test]
+    fn test_parse_fault_settings() {
+        let settings = FaultSettings::parse("AcmThrottling=0.5, SsmFailure=1,DnsPropagationDelaySecs=30").unwrap();
+        assert_eq!(settings.acm_throttling, 0.5);
+        assert_eq!(settings.ssm_failure, 1.0);
+        assert_eq!(settings.acme_server_error, 0.0);
+        assert_eq!(settings.dns_propagation_delay, Duration::from_secs(30));
+
+        assert_eq!(FaultSettings::parse("").unwrap(), FaultSettings::default());
+        assert!(FaultSettings::parse("AcmThrottling=2").is_err());
+        assert!(FaultSettings::parse("Unknown=0.5").is_err());
+        assert!(FaultSettings::parse("AcmThrottling").is_err());
+    }
+}
@@ -0,0 +1,830 @@
+use {
+    crate::{
+        account::{resolve_directory, validate_account_name},
+        acm_cache::AcmCache,
+        auth::AuthorizationHandler,
+        batch::{
+            apply_duplicate_policy, BatchItemResult, BatchPhase, BatchProgressReporter, BatchResponse, BatchSchedule,
+            CertificateBatchRequest,
+        },
+        chains::validate_preferred_chain,
+        config_source::{is_empty_payload, is_scheduled_event, ConfigSource},
+        constants::{
+            EVENT_DETAIL_TYPE_ACM_APPROACHING_EXPIRATION, EVENT_SOURCE_ACM, EVENT_SOURCE_SCHEDULER,
+            RENEWAL_LOCK_MAX_WAIT_SECONDS,
+        },
+        domain_policy::DomainPolicy,
+        dry_run::{staging_directory, DryRunIssuance},
+        errors::InvalidCertificateRequest,
+        events::{CertificateAction, CertificateRequest, EventBridgeEvent, EventResponse, Request, Response},
+        inventory::find_inventory_for_acm_certificate,
+        issuance_limits::IssuanceLimits,
+        lifecycle::LifecycleEventEmitter,
+        migrate::{deprecation_warnings, migrate_config},
+        notifications::NotificationConfig,
+        org_audit::handle_organization_audit,
+        reconcile::{renewal_jitter_days, MAX_RENEWAL_JITTER_DAYS, MAX_RENEWAL_THRESHOLD_DAYS},
+        report::{PhaseTimings, RunBudget, RunReport},
+        schedule::RenewalScheduler,
+        schema::{request_schemas, validate_request},
+        utils::{
+            is_public_suffix, is_wildcard_domain_name, registrable_domains, ssm_acme_parameter_path,
+            validate_domain_name,
+        },
+        workflow::ValidatedCertificateRequest,
+        xray::aws_client,
+    },
+    aws_lambda_events::{
+        encodings::Body,
+        event::{
+            alb::{AlbTargetGroupRequest, AlbTargetGroupResponse},
+            apigw::{
+                ApiGatewayProxyRequest, ApiGatewayProxyResponse, ApiGatewayV2httpRequest, ApiGatewayV2httpResponse,
+            },
+        },
+    },
+    futures::{
+        future::join_all,
+        stream::{FuturesUnordered, StreamExt},
+    },
+    http::{HeaderMap, HeaderValue},
+    lambda_runtime::{Error as LambdaError, LambdaEvent},
+    log::{error, info, warn},
+    rusoto_core::Region,
+    rusoto_ssm::{GetParameterRequest, Ssm, SsmClient},
+    serde::{Deserialize, Serialize},
+    serde_json::{Deserializer as JsonDeserializer, Serializer as JsonSerializer, Value},
+    std::{sync::Arc, time::Instant},
+    tokio::time::sleep,
+    url::Url,
+};
+
+/// Entrypoint for Lambda events.
+pub async fn handler_main(req_and_context: LambdaEvent<Value>) -> Result<Response, LambdaError> {
+    let budget = RunBudget::new(Some(req_and_context.context.deadline));
+    handle_request(req_and_context.payload, &budget).await
+}
+
+/// Dispatch a request to the appropriate handler. This is shared by the Lambda handler and agent mode, and is the
+/// entrypoint for other tools that accept the same request documents.
+pub async fn handle_request(basic: Value, budget: &RunBudget) -> Result<Response, LambdaError> {
+    eprintln!("Incoming value: {}", basic);
+    let basic = match ConfigSource::from_env()? {
+        Some(source) if is_empty_payload(&basic) => load_configured_request(&source, &basic).await?,
+        _ => basic,
+    };
+
+    let basic_bytes = Vec::new();
+    let mut ser = JsonSerializer::new(basic_bytes);
+    basic.serialize(&mut ser)?;
+
+    let mut basic_vec: Vec<u8> = ser.into_inner();
+    let basic_bytes: &[u8] = basic_vec.as_mut_slice();
+    let mut des = JsonDeserializer::from_slice(basic_bytes);
+
+    // Check certificate and batch requests against their schema before parsing them, so every configuration error
+    // is reported at once, with its location, instead of just the first one serde finds.
+    let errors = validate_request(&basic);
+    if !errors.is_empty() {
+        for error in &errors {
+            error!("Invalid request: {}", error);
+        }
+        return Err(InvalidCertificateRequest::invalid_request_document(errors));
+    }
+
+    let req = Request::deserialize(&mut des)?;
+
+    if matches!(req, Request::Certificate(_) | Request::Batch(_) | Request::BatchList(_)) {
+        for warning in deprecation_warnings(&basic) {
+            warn!("{}; use the MigrateConfig action to update this request", warning);
+        }
+    }
+
+    match req {
+        Request::Certificate(req) => handle_certificate_request(*req, budget).await,
+        Request::Batch(batch) => handle_batch_request(*batch, budget).await,
+        Request::BatchList(requests) => handle_batch_request(requests.into(), budget).await,
+        Request::Event(event) => handle_event(*event).await,
+        Request::Schema(_) => Ok(Response::Schema(request_schemas())),
+        Request::MigrateConfig(req) => Ok(Response::MigrateConfig(migrate_config(req.config))),
+        Request::OrganizationAudit(req) => Ok(Response::OrganizationAudit(handle_organization_audit(*req).await?)),
+        Request::ApiGatewayV1(req) => handle_apigatewayv1_request(req).await,
+        Request::ApiGatewayV2(req) => handle_apigatewayv2_request(req).await,
+        Request::Alb(req) => handle_alb_request(req).await,
+    }
+}
+
+/// Load the request for an invocation without one from the ConfigSource. A schedule that invokes the function with
+/// no input also keeps the ACM cache (if enabled) in sync, as it would without a ConfigSource.
+async fn load_configured_request(source: &ConfigSource, payload: &Value) -> Result<Value, LambdaError> {
+    if is_scheduled_event(payload) {
+        if let Some(cache) = AcmCache::from_env() {
+            cache.sync_if_due().await?;
+        }
+    }
+
+    source.load().await
+}
+
+/// Handler for a new certificate request. This is invoked by EventBridge or directly through a lambda:Invoke
+/// call.
+async fn handle_certificate_request(req: CertificateRequest, budget: &RunBudget) -> Result<Response, LambdaError> {
+    let mut phases = PhaseTimings::default();
+    let issuance_limits = Arc::new(IssuanceLimits::from_env()?);
+    let mut response = process_certificate_request(req, &issuance_limits, &mut phases).await?;
+
+    if let Response::Certificate(response) = &mut response {
+        let report = RunReport::new(budget, &phases, 1, 1);
+        for recommendation in &report.recommendations {
+            warn!("{}", recommendation);
+        }
+        response.report = Some(report);
+    }
+
+    Ok(response)
+}
+
+/// Validate and run a certificate request, adding the time spent in each phase to `phases`.
+async fn process_certificate_request(
+    req: CertificateRequest,
+    issuance_limits: &Arc<IssuanceLimits>,
+    phases: &mut PhaseTimings,
+) -> Result<Response, LambdaError> {
+    let started = Instant::now();
+    let req = validate_certificate_request(req, issuance_limits.clone()).await;
+    phases.record("Validate", started);
+
+    let mut req = req?;
+    let result = req.run_workflow().await;
+    phases.merge(&req.phases);
+    result
+}
+
+/// A batch request's domain names and the result of validating it, held until the request is started.
+type ValidatedBatchItem = (Vec<String>, Result<ValidatedCertificateRequest, LambdaError>);
+
+/// Handler for a batch of certificate requests. Duplicate certificates are detected (and handled according to the
+/// batch's DuplicatePolicy) and the requests' DependsOn are checked before any certificate is requested, and every
+/// request is validated up front (concurrently) so a single run reports all of the batch's misconfigurations. The
+/// valid requests are then processed up to MaxConcurrency at a time, in the order given by BatchSchedule. A failure
+/// in one request does not prevent the rest from being processed, except for those that depend on it. While the
+/// batch runs, BatchProgress events are sent at most once every ProgressInterval seconds.
+async fn handle_batch_request(batch: CertificateBatchRequest, budget: &RunBudget) -> Result<Response, LambdaError> {
+    if batch.max_concurrency == 0 {
+        return Err(InvalidCertificateRequest::invalid_batch_configuration("MaxConcurrency must be at least 1"));
+    }
+
+    let max_concurrency = batch.max_concurrency;
+    let mut progress = BatchProgressReporter::new(&batch)?;
+    let dry_run = batch.dry_run;
+    let (mut requests, overlaps) = apply_duplicate_policy(batch)?;
+    if dry_run {
+        for req in requests.iter_mut() {
+            req.dry_run = true;
+        }
+    }
+
+    let mut schedule = BatchSchedule::new(&requests)?;
+    if let Some(progress) = &mut progress {
+        progress.send(BatchPhase::Validating, &schedule).await;
+    }
+    let issuance_limits = Arc::new(IssuanceLimits::from_env()?);
+    let n_total = requests.len();
+    let mut phases = PhaseTimings::default();
+
+    let started = Instant::now();
+    let validated = join_all(requests.into_iter().map(|req| {
+        let issuance_limits = issuance_limits.clone();
+        async move {
+            let domain_names = req.domain_names.clone();
+            (domain_names, validate_certificate_request(req, issuance_limits).await)
+        }
+    }))
+    .await;
+    phases.record("Validate", started);
+    if let Some(progress) = &mut progress {
+        progress.send(BatchPhase::Running, &schedule).await;
+    }
+
+    let mut pending: Vec<Option<ValidatedBatchItem>> = validated.into_iter().map(Some).collect();
+    let mut running = FuturesUnordered::new();
+
+    loop {
+        while running.len() < max_concurrency {
+            let index = match schedule.next_ready() {
+                Some(index) => index,
+                None => break,
+            };
+
+            let (domain_names, req) = pending[index].take().expect("request should only be started once");
+            match req {
+                Ok(mut req) => running.push(async move {
+                    let result = req.run_workflow().await;
+                    (index, domain_names, result, req.phases)
+                }),
+                Err(e) => schedule.record(index, batch_item_result(domain_names, Err(e))),
+            }
+        }
+
+        // Wait for the next request to finish, sending progress events while waiting so a stalled request is
+        // visible.
+        let next = match &mut progress {
+            Some(progress) if !running.is_empty() => tokio::select! {
+                next = running.next() => next,
+                _ = sleep(progress.until_next()) => {
+                    progress.send(BatchPhase::Running, &schedule).await;
+                    continue;
+                }
+            },
+            _ => running.next().await,
+        };
+
+        match next {
+            Some((index, domain_names, result, req_phases)) => {
+                phases.merge(&req_phases);
+                schedule.record(index, batch_item_result(domain_names, result));
+            }
+            None => break,
+        }
+    }
+
+    if let Some(progress) = &mut progress {
+        progress.send(BatchPhase::Completed, &schedule).await;
+    }
+
+    let results = schedule.into_results();
+    let report = RunReport::new(budget, &phases, results.len(), n_total);
+    for recommendation in &report.recommendations {
+        warn!("{}", recommendation);
+    }
+
+    Ok(Response::Batch(BatchResponse {
+        results,
+        overlaps,
+        report,
+    }))
+}
+
+fn batch_item_result(domain_names: Vec<String>, result: Result<Response, LambdaError>) -> BatchItemResult {
+    match result {
+        Ok(response) => BatchItemResult {
+            domain_names,
+            response: Some(response),
+            error: None,
+        },
+        Err(e) => {
+            error!("Certificate request for {} failed: {:#}", domain_names.join(" "), e);
+            BatchItemResult {
+                domain_names,
+                response: None,
+                error: Some(format!("{:#}", e)),
+            }
+        }
+    }
+}
+
+/// Validate a certificate request and set up its storage and authorization providers. The returned request is run
+/// with `run_workflow`.
+pub async fn validate_certificate_request(
+    mut req: CertificateRequest,
+    issuance_limits: Arc<IssuanceLimits>,
+) -> Result<ValidatedCertificateRequest, LambdaError> {
+    // Keep a copy of the request as submitted so it can be recorded in the inventory and replayed for renewals.
+    let original = serde_json::to_value(&req)?;
+
+    // Perform some basic parameter validation.
+    if req.directory.is_empty() {
+        return Err(InvalidCertificateRequest::directory_empty());
+    }
+
+    if req.domain_names.is_empty() {
+        return Err(InvalidCertificateRequest::domain_names_empty());
+    }
+
+    for domain_name in &req.domain_names {
+        if !validate_domain_name(domain_name) {
+            return Err(InvalidCertificateRequest::invalid_domain_name(domain_name));
+        }
+
+        // Wildcards can only be validated using DNS-01 challenges.
+        if is_wildcard_domain_name(domain_name) && !req.auth.supports_wildcards() {
+            return Err(InvalidCertificateRequest::wildcard_not_supported(format!(
+                "{} requires a dns-01 authorization, but {} is configured",
+                domain_name,
+                req.auth.challenge_type()
+            )));
+        }
+    }
+
+    // Catch domain names that can't be right: an entire public suffix, or (unless explicitly allowed) names from
+    // unrelated registrable domains mixed in a single certificate.
+    for domain_name in &req.domain_names {
+        if is_public_suffix(domain_name) {
+            return Err(InvalidCertificateRequest::public_suffix(domain_name));
+        }
+    }
+
+    let registrable_domains = registrable_domains(&req.domain_names);
+    if registrable_domains.len() > 1 && !req.allow_mixed_registrable_domains {
+        return Err(InvalidCertificateRequest::mixed_registrable_domains(format!(
+            "{} (set AllowMixedRegistrableDomains to allow this)",
+            registrable_domains.join(" ")
+        )));
+    }
+
+    // Enforce the domain policy set by the account's security team, whatever the action.
+    if let Some(policy) = DomainPolicy::load().await? {
+        policy.check(&req.domain_names)?;
+    }
+
+    if req.contacts.is_empty() {
+        return Err(InvalidCertificateRequest::contacts_empty());
+    }
+
+    if req.renewal_jitter_days < 0 || req.renewal_jitter_days > MAX_RENEWAL_JITTER_DAYS {
+        return Err(InvalidCertificateRequest::invalid_renewal_jitter(format!(
+            "RenewalJitterDays must be between 0 and {}: {}",
+            MAX_RENEWAL_JITTER_DAYS, req.renewal_jitter_days
+        )));
+    }
+
+    if req.renewal_threshold_days < 1 || req.renewal_threshold_days > MAX_RENEWAL_THRESHOLD_DAYS {
+        return Err(InvalidCertificateRequest::invalid_renewal_threshold(format!(
+            "RenewalThresholdDays must be between 1 and {}: {}",
+            MAX_RENEWAL_THRESHOLD_DAYS, req.renewal_threshold_days
+        )));
+    }
+
+    if req.lock_wait_seconds > RENEWAL_LOCK_MAX_WAIT_SECONDS {
+        return Err(InvalidCertificateRequest::invalid_lock_wait(format!(
+            "LockWaitSeconds must be between 0 and {}: {}",
+            RENEWAL_LOCK_MAX_WAIT_SECONDS, req.lock_wait_seconds
+        )));
+    }
+
+    req.directory = resolve_directory(&req.directory);
+
+    if req.dry_run {
+        if matches!(
+            req.action,
+            CertificateAction::Revoke | CertificateAction::Schedule | CertificateAction::TestRotation
+        ) {
+            return Err(InvalidCertificateRequest::invalid_dry_run(format!(
+                "DryRun cannot be used with the {:?} action",
+                req.action
+            )));
+        }
+
+        // Staging issuance uses a fresh staging account: named accounts and external account bindings belong to
+        // the production directory.
+        if req.dry_run_issuance == DryRunIssuance::Staging {
+            req.directory = staging_directory(&req.directory)?;
+            req.account = None;
+            req.external_account_binding = None;
+        }
+    }
+
+    let dir_url =
+        Url::parse(&req.directory).map_err(|e| InvalidCertificateRequest::invalid_directory_url(format!("{}", e)))?;
+
+    match dir_url.scheme() {
+        "http" | "https" => (),
+        _ => {
+            return Err(InvalidCertificateRequest::invalid_directory_url(format!(
+                "Directory URL scheme must be http or https: {}",
+                &req.directory
+            )))
+        }
+    }
+
+    let dir_host = dir_url
+        .host_str()
+        .ok_or_else(|| {
+            InvalidCertificateRequest::invalid_directory_url(format!(
+                "Directory URL must have a host: {}",
+                &req.directory
+            ))
+        })?
+        .to_string();
+
+    // Let's Encrypt requires all contacts to be mailto: contacts.
+    if dir_host.ends_with(".letsencrypt.org") {
+        for ref contact in &req.contacts {
+            if !contact.starts_with("mailto:") {
+                return Err(InvalidCertificateRequest::invalid_contact(format!(
+                    "Let's Encrypt only supports \"mailto:\" contacts: {:#?}",
+                    contact
+                )));
+            }
+        }
+    }
+
+    // Replicate storage providers with a Regions list into one provider per region, then check each provider.
+    let mut storage = Vec::with_capacity(req.storage.len());
+    for provider in req.storage.drain(..) {
+        storage.extend(provider.expand_regions()?);
+    }
+    req.storage = storage;
+
+    // Validate the storage providers concurrently, reporting every misconfiguration rather than just the first.
+    let results = join_all(req.storage.iter_mut().map(|provider| provider.validate())).await;
+    let errors: Vec<String> = results
+        .into_iter()
+        .zip(req.storage.iter())
+        .enumerate()
+        .filter_map(|(i, (result, provider))| {
+            result.err().map(|e| {
+                error!("Failed to validate storage provider {} ({}): {}", i, provider.type_name(), e);
+                format!("Storage[{}] ({}): {}", i, provider.type_name(), e)
+            })
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(InvalidCertificateRequest::storage_validation_failed(errors));
+    }
+
+    // Make sure every target can use the requested key algorithm before anything is issued; e.g. IAM server
+    // certificates must be RSA.
+    let key_algorithm = req.key_algorithm;
+    let results = join_all(req.storage.iter().map(|provider| provider.check_key_algorithm(key_algorithm))).await;
+    let errors: Vec<String> = results
+        .into_iter()
+        .zip(req.storage.iter())
+        .enumerate()
+        .filter_map(|(i, (result, provider))| {
+            result.err().map(|e| {
+                error!("Storage provider {} ({}) can't use {}: {}", i, provider.type_name(), key_algorithm, e);
+                format!("Storage[{}] ({}): {}", i, provider.type_name(), e)
+            })
+        })
+        .collect();
+
+    if !errors.is_empty() {
+        return Err(InvalidCertificateRequest::storage_validation_failed(errors));
+    }
+
+    if let Some(storage_indexes) = &req.storage_indexes {
+        if let Some(index) = storage_indexes.iter().find(|index| **index >= req.storage.len()) {
+            return Err(InvalidCertificateRequest::invalid_storage_indexes(format!(
+                "Storage index {} is out of range; the request has {} storage targets",
+                index,
+                req.storage.len()
+            )));
+        }
+    }
+
+    if let Some(eab) = &req.external_account_binding {
+        eab.validate()?;
+    }
+
+    req.csr.validate(req.key_algorithm)?;
+    req.retry.validate()?;
+    req.revocation.validate()?;
+    req.cost_allocation.validate()?;
+
+    if let Some(preferred_chain) = &req.preferred_chain {
+        validate_preferred_chain(preferred_chain)?;
+    }
+
+    req.consistency_check.validate()?;
+
+    if req.action == CertificateAction::Schedule {
+        RenewalScheduler::required()?;
+    }
+
+    if let Some(account) = &req.account {
+        validate_account_name(account)?;
+    }
+
+    for hook in &req.hooks {
+        hook.validate()?;
+    }
+
+    #[cfg(feature = "ssh-output")]
+    if let Some(ssh_output) = &mut req.ssh_output {
+        ssh_output.validate()?;
+    }
+
+    // And check the authorization provider.
+    match req.auth.setup().await {
+        Ok(()) => (),
+        Err(e) => {
+            error!("Failed to setup authorization provider: {}", e);
+            return Err(e);
+        }
+    }
+
+    let renewal_threshold_days =
+        req.renewal_threshold_days + renewal_jitter_days(&req.domain_names, req.renewal_jitter_days);
+
+    Ok(ValidatedCertificateRequest {
+        directory: req.directory,
+        domain_names: req.domain_names,
+        contacts: req.contacts,
+        external_account_binding: req.external_account_binding,
+        account: req.account,
+        auth: req.auth,
+        key_algorithm: req.key_algorithm,
+        csr: req.csr,
+        action: req.action,
+        rotation_manifest: req.rotation_manifest,
+        store_alternate_chain: req.store_alternate_chain,
+        preferred_chain: req.preferred_chain,
+        consistency_check: req.consistency_check,
+        dry_run: if req.dry_run {
+            Some(req.dry_run_issuance)
+        } else {
+            None
+        },
+        cost_allocation: req.cost_allocation,
+        notifications: NotificationConfig::resolve(req.notifications)?,
+        lifecycle_events: LifecycleEventEmitter::resolve(req.event_bus_name),
+        hooks: req.hooks,
+        #[cfg(feature = "ssh-output")]
+        ssh_output: req.ssh_output,
+        certificate: None,
+        storage: req.storage,
+        dir_host: dir_host.to_string(),
+        renewal_threshold_days,
+        retry: req.retry,
+        revocation: req.revocation,
+        preflight: req.preflight,
+        schedule_renewal: req.schedule_renewal,
+        storage_indexes: req.storage_indexes,
+        lock_wait_seconds: req.lock_wait_seconds,
+        phases: PhaseTimings::default(),
+        issuance_limits,
+        original,
+    })
+}
+
+/// Handler for EventBridge events.
+async fn handle_event(event: EventBridgeEvent) -> Result<Response, LambdaError> {
+    info!("Received {} event from {}", event.detail_type, event.source);
+
+    let message = match event.source.as_str() {
+        EVENT_SOURCE_ACM => {
+            // Keep the ACM cache (if enabled) up to date with certificate state changes.
+            let mut message = match AcmCache::from_env() {
+                None => "ACM cache is not enabled".to_string(),
+                Some(cache) => {
+                    for arn in &event.resources {
+                        cache.refresh_certificate(arn).await?;
+                    }
+                    format!("Refreshed {} certificate(s) in ACM cache", event.resources.len())
+                }
+            };
+
+            if event.detail_type == EVENT_DETAIL_TYPE_ACM_APPROACHING_EXPIRATION {
+                // Renew each certificate independently so one failure doesn't prevent the others from renewing. The
+                // renewals share one set of issuance limits, so an event naming many certificates can't exceed the
+                // per-run limit.
+                let issuance_limits = Arc::new(IssuanceLimits::from_env()?);
+                for arn in &event.resources {
+                    message.push_str("; ");
+                    match renew_acm_certificate(arn, &event.detail, &issuance_limits).await {
+                        Ok(result) => message.push_str(&result),
+                        Err(e) => {
+                            error!("Failed to renew ACM certificate {}: {}", arn, e);
+                            message.push_str(&format!("Failed to renew {}: {}", arn, e));
+                        }
+                    }
+                }
+            }
+
+            message
+        }
+
+        // A scheduled event without a certificate request payload runs the periodic ACM cache sync.
+        EVENT_SOURCE_SCHEDULER => match AcmCache::from_env() {
+            None => "ACM cache is not enabled".to_string(),
+            Some(cache) => {
+                cache.sync_if_due().await?;
+                "ACM cache sync complete".to_string()
+            }
+        },
+
+        _ => {
+            return Ok(Response::Event(EventResponse {
+                handled: false,
+                message: format!("Unsupported event source {}", event.source),
+            }))
+        }
+    };
+
+    Ok(Response::Event(EventResponse {
+        handled: true,
+        message,
+    }))
+}
+
+/// Renew a certificate that ACM reports is approaching expiration. The request that originally issued the
+/// certificate is looked up in the inventory and replayed; certificates not issued by us are ignored.
+async fn renew_acm_certificate(
+    arn: &str,
+    detail: &Value,
+    issuance_limits: &Arc<IssuanceLimits>,
+) -> Result<String, LambdaError> {
+    let record = match find_inventory_for_acm_certificate(arn).await? {
+        Some(record) => record,
+        None => {
+            info!("ACM certificate {} is not managed by this function; ignoring expiration event", arn);
+            return Ok(format!("{} is not managed by this function", arn));
+        }
+    };
+
+    let request_value = match record.request {
+        Some(request_value) => request_value,
+        None => {
+            info!("Inventory record for ACM certificate {} does not include the original request", arn);
+            return Ok(format!("{} has no recorded request to renew from", arn));
+        }
+    };
+
+    let request: CertificateRequest = serde_json::from_value(request_value)?;
+    let mut req = validate_certificate_request(request, issuance_limits.clone()).await?;
+
+    // ACM starts sending expiration events before our usual renewal threshold is reached. Make sure the event
+    // actually results in a renewal.
+    let days_to_expiry = detail.get("DaysToExpiry").and_then(|d| d.as_i64()).unwrap_or(0);
+    req.renewal_threshold_days = req.renewal_threshold_days.max(days_to_expiry + 1);
+
+    info!("Renewing ACM certificate {} for {} ({} day(s) to expiry)", arn, req.domain_names.join(" "), days_to_expiry);
+    match req.run_workflow().await? {
+        Response::Certificate(response) => {
+            Ok(format!("Renewed {} for {}: {:?}", arn, req.domain_names.join(" "), response.status))
+        }
+        _ => Ok(format!("Renewed {} for {}", arn, req.domain_names.join(" "))),
+    }
+}
+
+/// Return the key authentication for a given token from SSM.
+async fn get_key_auth_for_token(token: &str) -> Option<String> {
+    // Get the key authorization from SSM
+    let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+    let token_param_name = format!("{}/Tokens/{}", ssm_acme_parameter_path(), token);
+    let gp_request = GetParameterRequest {
+        name: token_param_name.clone(),
+        with_decryption: Some(true),
+    };
+    info!("Getting key authorization from SSM parameter {}", token_param_name);
+    match ssm.get_parameter(gp_request).await {
+        Ok(result) => match result.parameter {
+            Some(parameter) => match parameter.value {
+                Some(token) => {
+                    info!("Found key authorization for token {}", token_param_name);
+                    Some(token)
+                }
+                None => {
+                    error!("Found key authorization parameter for token {} but no associated value", token_param_name);
+                    None
+                }
+            },
+            None => {
+                error!("No parameter returned for SSM parameter {}", token_param_name);
+                None
+            }
+        },
+        Err(e) => {
+            error!("Failed to retrieve SSM parameter {}: {:#}", token_param_name, e);
+            None
+        }
+    }
+}
+
+/// Handle an HTTP-01 challenge made via an API Gateway v1 request.
+async fn handle_apigatewayv1_request(req: Box<ApiGatewayProxyRequest>) -> Result<Response, LambdaError> {
+    let mut headers = HeaderMap::with_capacity(1);
+    let multi_value_headers = HeaderMap::new();
+    headers.insert("Content-Type", HeaderValue::from_static("text/plain; charset=utf-8"));
+
+    match req.path_parameters.get("token") {
+        None => Ok(ApiGatewayProxyResponse {
+            status_code: 500,
+            headers: headers,
+            multi_value_headers: multi_value_headers,
+            body: Some(Body::Text("token parameter missing in proxy integration".to_string())),
+            is_base64_encoded: Some(false),
+        }
+        .into()),
+        Some(token) => match get_key_auth_for_token(token).await {
+            None => Ok(ApiGatewayProxyResponse {
+                status_code: 404,
+                headers: headers,
+                multi_value_headers: multi_value_headers,
+                body: Some(Body::Text("Not found".to_string())),
+                is_base64_encoded: Some(false),
+            }
+            .into()),
+            Some(auth_value) => Ok(ApiGatewayProxyResponse {
+                status_code: 200,
+                headers: headers,
+                multi_value_headers: multi_value_headers,
+                body: Some(Body::Text(auth_value)),
+                is_base64_encoded: Some(false),
+            }
+            .into()),
+        },
+    }
+}
+
+/// Handle an HTTP-01 challenge made via an API Gateway v2 request.
+async fn handle_apigatewayv2_request(req: Box<ApiGatewayV2httpRequest>) -> Result<Response, LambdaError> {
+    let mut headers = HeaderMap::with_capacity(1);
+    let multi_value_headers = HeaderMap::new();
+    headers.insert("Content-Type", HeaderValue::from_static("text/plain; charset=utf-8"));
+
+    match req.path_parameters.get("token") {
+        None => Ok(ApiGatewayV2httpResponse {
+            status_code: 500,
+            headers: headers,
+            multi_value_headers: multi_value_headers,
+            body: Some(Body::Text("token parameter missing in proxy integration".to_string())),
+            is_base64_encoded: Some(false),
+            cookies: vec![],
+        }
+        .into()),
+        Some(token) => match get_key_auth_for_token(token).await {
+            None => Ok(ApiGatewayV2httpResponse {
+                status_code: 404,
+                headers: headers,
+                multi_value_headers: multi_value_headers,
+                body: Some(Body::Text("Not found".to_string())),
+                is_base64_encoded: Some(false),
+                cookies: vec![],
+            }
+            .into()),
+            Some(auth_value) => Ok(ApiGatewayV2httpResponse {
+                status_code: 200,
+                headers: headers,
+                multi_value_headers: multi_value_headers,
+                body: Some(Body::Text(auth_value)),
+                is_base64_encoded: Some(false),
+                cookies: vec![],
+            }
+            .into()),
+        },
+    }
+}
+
+/// Handle an HTTP-01 challenge made to an application load balancer (ALB).
+async fn handle_alb_request(req: Box<AlbTargetGroupRequest>) -> Result<Response, LambdaError> {
+    let mut headers = HeaderMap::with_capacity(1);
+    let multi_value_headers = HeaderMap::new();
+    headers.insert("Content-Type", HeaderValue::from_static("text/plain; charset=utf-8"));
+
+    match req.path {
+        None => {
+            error!("No path sent from load balancer");
+            Ok(AlbTargetGroupResponse {
+                status_code: 400,
+                status_description: Some("Bad Request".to_string()),
+                headers: headers,
+                multi_value_headers: multi_value_headers,
+                body: Some(Body::Text("path must be present in request".to_string())),
+                is_base64_encoded: false,
+            }
+            .into())
+        }
+        Some(mut path) => {
+            while path.starts_with('/') {
+                path = path.split_at(1).1.to_string();
+            }
+
+            let parts: Vec<&str> = path.split('/').collect();
+            if parts.len() != 3 || parts[0] != ".well-known" || parts[1] != "acme-challenge" {
+                Ok(AlbTargetGroupResponse {
+                    status_code: 404,
+                    status_description: Some("Not Found".to_string()),
+                    headers: headers,
+                    multi_value_headers: multi_value_headers,
+                    body: Some(Body::Text("Not found".to_string())),
+                    is_base64_encoded: false,
+                }
+                .into())
+            } else {
+                match get_key_auth_for_token(parts[2]).await {
+                    None => Ok(AlbTargetGroupResponse {
+                        status_code: 404,
+                        status_description: Some("Not Found".to_string()),
+                        headers: headers,
+                        multi_value_headers: multi_value_headers,
+                        body: Some(Body::Text("Not found".to_string())),
+                        is_base64_encoded: false,
+                    }
+                    .into()),
+                    Some(auth_value) => Ok(AlbTargetGroupResponse {
+                        status_code: 200,
+                        status_description: Some("OK".to_string()),
+                        headers: headers,
+                        multi_value_headers: multi_value_headers,
+                        body: Some(Body::Text(auth_value)),
+                        is_base64_encoded: false,
+                    }
+                    .into()),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use {
+    crate::utils::now_epoch_secs,
+    hyper::{
+        header::CONTENT_TYPE,
+        service::{make_service_fn, service_fn},
+        Body, Request, Response, Server, StatusCode,
+    },
+    log::{error, info},
+    serde::{self, Serialize},
+    std::{
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// The state of the agent loop, as reported by the health endpoints. In JSON:
+///
+///     {
+///         // The version of the agent configuration parameter in effect. This is omitted until the configuration
+///         // has been loaded.
+///         "ConfigVersion": int,
+///
+///         // Whether a run is in progress.
+///         "Running": bool,
+///
+///         // When the last run started and finished, in seconds since the Unix epoch, and whether it succeeded.
+///         "LastRunStarted": int,
+///         "LastRunFinished": int,
+///         "LastRunSucceeded": bool,
+///     }
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AgentStatus {
+    #[serde(rename = "ConfigVersion", skip_serializing_if = "Option::is_none")]
+    pub config_version: Option<i64>,
+
+    #[serde(rename = "Running")]
+    pub running: bool,
+
+    #[serde(rename = "LastRunStarted", skip_serializing_if = "Option::is_none")]
+    pub last_run_started: Option<i64>,
+
+    #[serde(rename = "LastRunFinished", skip_serializing_if = "Option::is_none")]
+    pub last_run_finished: Option<i64>,
+
+    #[serde(rename = "LastRunSucceeded", skip_serializing_if = "Option::is_none")]
+    pub last_run_succeeded: Option<bool>,
+
+    /// The last time the agent loop woke up.
+    #[serde(skip)]
+    heartbeat: Option<Instant>,
+
+    /// How long the loop may go without waking up (outside of a run) before it's considered stuck.
+    #[serde(skip)]
+    stall_after: Duration,
+}
+
+impl AgentStatus {
+    /// Record that the agent loop is alive. The loop must call this again within `stall_after` unless a run is in
+    /// progress.
+    pub fn heartbeat(&mut self, stall_after: Duration) {
+        self.heartbeat = Some(Instant::now());
+        self.stall_after = stall_after;
+    }
+
+    pub fn run_started(&mut self) {
+        self.running = true;
+        self.last_run_started = Some(now_epoch_secs());
+    }
+
+    pub fn run_finished(&mut self, succeeded: bool) {
+        self.running = false;
+        self.last_run_finished = Some(now_epoch_secs());
+        self.last_run_succeeded = Some(succeeded);
+    }
+
+    /// The agent is live if it hasn't started its loop yet, is in the middle of a run (which may legitimately take
+    /// much longer than Lambda's 15 minutes), or has woken up recently.
+    fn is_live(&self) -> bool {
+        match self.heartbeat {
+            None => true,
+            Some(heartbeat) => self.running || heartbeat.elapsed() <= self.stall_after,
+        }
+    }
+
+    /// The agent is ready once its configuration has been loaded.
+    fn is_ready(&self) -> bool {
+        self.config_version.is_some()
+    }
+}
+
+/// Serve the health endpoints until the process exits:
+///
+/// * `/healthz` (liveness): 200 unless the agent loop appears to be stuck.
+/// * `/readyz` (readiness): 200 once the agent configuration has been loaded.
+/// * `/status`: always 200.
+///
+/// Each returns the AgentStatus as JSON.
+pub async fn serve_health(port: u16, status: Arc<Mutex<AgentStatus>>) {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    let make_service = make_service_fn(move |_| {
+        let status = status.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| {
+                let status = status.clone();
+                async move { Ok::<_, Infallible>(health_response(&req, &status)) }
+            }))
+        }
+    });
+
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder.serve(make_service),
+        Err(e) => {
+            error!("Unable to listen for health checks on port {}: {:#}", port, e);
+            return;
+        }
+    };
+
+    info!("Serving health checks on port {}", port);
+    if let Err(e) = server.await {
+        error!("Health check server failed: {:#}", e);
+    }
+}
+
+fn health_response(req: &Request<Body>, status: &Mutex<AgentStatus>) -> Response<Body> {
+    let status = status.lock().expect("Agent status lock poisoned").clone();
+    let healthy = match req.uri().path() {
+        "/healthz" => status.is_live(),
+        "/readyz" => status.is_ready(),
+        "/status" => true,
+        _ => {
+            let mut response = Response::new(Body::from("Not found\n"));
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            return response;
+        }
+    };
+
+    let body = serde_json::to_string(&status).unwrap_or_default();
+    let mut response = Response::new(Body::from(body));
+    response.headers_mut().insert(CONTENT_TYPE, "application/json".parse().expect("Valid header value"));
+    if !healthy {
+        *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+    }
+    response
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {super::AgentStatus, std::time::Duration};
+
+    #[test]
+    fn test_agent_status() {
+        let mut status = AgentStatus::default();
+        assert!(status.is_live());
+        assert!(!status.is_ready());
+
+        status.config_version = Some(3);
+        status.heartbeat(Duration::from_secs(0));
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(status.is_ready());
+        assert!(!status.is_live());
+
+        // A long run doesn't make the agent look stuck.
+        status.run_started();
+        assert!(status.is_live());
+        status.run_finished(true);
+        assert!(!status.is_live());
+        assert_eq!(status.last_run_succeeded, Some(true));
+    }
+}
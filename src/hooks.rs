@@ -0,0 +1,839 @@
+use {
+    crate::{
+        assume_role::AssumeRole,
+        constants::{RUN_COMMAND_DEFAULT_TIMEOUT_SECONDS, RUN_COMMAND_MAX_TIMEOUT_SECONDS, SSM_TYPE_STRING},
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        events::CertificateResponseStatus,
+        storage::{wait_for_command, CertificateStorageResult, RunCommandTarget},
+        utils::{default_true, validate_and_sanitize_ssm_parameter_path, CertificateInfo},
+    },
+    async_trait::async_trait,
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    rusoto_core::Region,
+    rusoto_lambda::{InvocationRequest, Lambda, LambdaClient},
+    rusoto_ssm::{PutParameterRequest, SendCommandRequest, Ssm, SsmClient, Target},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::Value,
+    std::{
+        collections::HashMap,
+        str::{from_utf8, FromStr},
+        time::Duration,
+    },
+};
+
+/// A hook to run after the certificate has been written to at least one storage target, so custom distribution
+/// logic can be plugged in without changing this function. In JSON:
+///
+///     {
+///         // The type of hook: "Lambda", "RunCommand", or "SsmOutputs". The remaining keys depend on the type;
+///         // see LambdaHook, RunCommandHook, and SsmOutputsHook.
+///         "Type": str,
+///     }
+///
+/// Hooks are run in the order listed, and each receives a HookPayload. A hook that fails is reported in the response
+/// but doesn't stop the remaining hooks or affect the result of the run, since the certificate has already been
+/// stored.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(tag = "Type")]
+pub enum PostIssuanceHook {
+    Lambda(LambdaHook),
+    RunCommand(RunCommandHook),
+    SsmOutputs(SsmOutputsHook),
+}
+
+impl PostIssuanceHook {
+    fn hook(&self) -> &(dyn Hook + Send + Sync) {
+        match self {
+            Self::Lambda(hook) => hook,
+            Self::RunCommand(hook) => hook,
+            Self::SsmOutputs(hook) => hook,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        self.hook().validate()
+    }
+}
+
+/// Something that can be run after issuance.
+#[async_trait]
+pub trait Hook {
+    /// A description of the hook for logs and results.
+    fn describe(&self) -> String;
+
+    /// Check the hook's configuration.
+    fn validate(&self) -> Result<(), LambdaError>;
+
+    /// Run the hook with the results of the run.
+    async fn run(&self, payload: &HookPayload) -> Result<(), LambdaError>;
+}
+
+/// The payload passed to each hook. The private key is never included. In JSON:
+///
+///     {
+///         // The domain names on the certificate.
+///         "DomainNames": [str, ...],
+///
+///         // "Success" if every storage target was written, "PartialSuccess" if some failed, or "RolledBack" if a
+///         // deployment failed verification and was reverted.
+///         "Status": str,
+///
+///         // The serial number of the certificate as lowercase hex, and its validity period in seconds since the
+///         // Unix epoch.
+///         "Serial": str,
+///         "NotBefore": int,
+///         "NotAfter": int,
+///
+///         // The result of writing each storage target. See CertificateResponse.
+///         "StorageResults": [],
+///     }
+#[derive(Debug, Serialize)]
+pub struct HookPayload {
+    #[serde(rename = "DomainNames")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "Status")]
+    pub status: String,
+
+    #[serde(rename = "Serial", skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+
+    #[serde(rename = "NotBefore", skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<i64>,
+
+    #[serde(rename = "NotAfter", skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<i64>,
+
+    #[serde(rename = "StorageResults")]
+    pub storage_results: Vec<CertificateStorageResult>,
+}
+
+impl HookPayload {
+    pub fn new(
+        domain_names: &[String],
+        status: &CertificateResponseStatus,
+        certificate: Option<&CertificateInfo>,
+        storage_results: &[CertificateStorageResult],
+    ) -> Self {
+        Self {
+            domain_names: domain_names.to_vec(),
+            status: match status {
+                CertificateResponseStatus::PartialSuccess => "PartialSuccess",
+                CertificateResponseStatus::RolledBack => "RolledBack",
+                _ => "Success",
+            }
+            .to_string(),
+            serial: certificate.map(|info| info.serial.clone()),
+            not_before: certificate.map(|info| info.not_before),
+            not_after: certificate.map(|info| info.not_after),
+            storage_results: storage_results.to_vec(),
+        }
+    }
+}
+
+/// The outcome of running one hook. In JSON:
+///
+///     {
+///         // A description of the hook, e.g. "Lambda distribute-certs".
+///         "Hook": str,
+///
+///         // Whether the hook succeeded. For asynchronous invocations, this only means the event was accepted.
+///         "Succeeded": bool,
+///
+///         // If the hook failed, the reason.
+///         "Error": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct HookResult {
+    #[serde(rename = "Hook")]
+    pub hook: String,
+
+    #[serde(rename = "Succeeded")]
+    pub succeeded: bool,
+
+    #[serde(rename = "Error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Run each hook in order, logging and recording failures rather than returning them.
+pub async fn run_hooks(hooks: &[PostIssuanceHook], payload: &HookPayload) -> Vec<HookResult> {
+    let mut results = Vec::with_capacity(hooks.len());
+
+    for hook in hooks {
+        let hook = hook.hook();
+        let description = hook.describe();
+        info!("Running post-issuance hook {}", description);
+        match hook.run(payload).await {
+            Ok(()) => results.push(HookResult {
+                hook: description,
+                succeeded: true,
+                error: None,
+            }),
+            Err(e) => {
+                error!("Post-issuance hook {} failed: {:#}", description, e);
+                results.push(HookResult {
+                    hook: description,
+                    succeeded: false,
+                    error: Some(format!("{:#}", e)),
+                });
+            }
+        }
+    }
+
+    results
+}
+
+/// Invokes a Lambda function with the HookPayload. In JSON:
+///
+///     {
+///         "Type": "Lambda",
+///
+///         // The name or ARN of the function. Use an ARN to invoke a function in another region.
+///         "FunctionName": str,
+///
+///         // The version or alias to invoke, if not the unqualified function.
+///         "Qualifier": str,
+///
+///         // "RequestResponse" (the default) to wait for the function and fail the hook if the function returns an
+///         // error, or "Event" to queue the invocation and return immediately.
+///         "InvocationType": str,
+///
+///         // The role to assume to invoke the function, e.g. for a function in another account; see AssumeRole.
+///         "RoleArn": str,
+///         "ExternalId": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct LambdaHook {
+    #[serde(rename = "FunctionName")]
+    pub function_name: String,
+
+    #[serde(rename = "Qualifier", default, skip_serializing_if = "Option::is_none")]
+    pub qualifier: Option<String>,
+
+    #[serde(rename = "InvocationType", default)]
+    pub invocation_type: LambdaInvocationType,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+/// How a Lambda hook is invoked.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum LambdaInvocationType {
+    /// Invoke the function synchronously and wait for its result.
+    #[default]
+    RequestResponse,
+
+    /// Queue the invocation and don't wait for it to run.
+    Event,
+}
+
+impl LambdaHook {
+    /// The region of the function: the region in its ARN if FunctionName is an ARN, otherwise our own region.
+    fn lambda_region(&self) -> Result<Region, LambdaError> {
+        if !self.function_name.starts_with("arn:") {
+            return Ok(Region::default());
+        }
+
+        let parts: Vec<&str> = self.function_name.splitn(7, ':').collect();
+        if parts.len() < 7 || parts[2] != "lambda" || parts[5] != "function" {
+            return Err(InvalidCertificateRequest::invalid_hook_configuration(format!(
+                "Invalid Lambda function ARN: {}",
+                self.function_name
+            )));
+        }
+
+        match Region::from_str(parts[3]) {
+            Ok(region) => Ok(region),
+            Err(_) => Err(InvalidCertificateRequest::invalid_hook_configuration(format!(
+                "Invalid region in Lambda function ARN: {}",
+                self.function_name
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl Hook for LambdaHook {
+    fn describe(&self) -> String {
+        match &self.qualifier {
+            Some(qualifier) => format!("Lambda {}:{}", self.function_name, qualifier),
+            None => format!("Lambda {}", self.function_name),
+        }
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        if self.function_name.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_hook_configuration("FunctionName cannot be empty"));
+        }
+
+        if self.qualifier.as_deref() == Some("") {
+            return Err(InvalidCertificateRequest::invalid_hook_configuration("Qualifier cannot be empty"));
+        }
+
+        self.lambda_region()?;
+        self.assume_role.validate()
+    }
+
+    async fn run(&self, payload: &HookPayload) -> Result<(), LambdaError> {
+        let lambda = LambdaClient::new_with_client(self.assume_role.client()?, self.lambda_region()?);
+        let invocation_type = match self.invocation_type {
+            LambdaInvocationType::RequestResponse => "RequestResponse",
+            LambdaInvocationType::Event => "Event",
+        };
+
+        let request = InvocationRequest {
+            function_name: self.function_name.clone(),
+            qualifier: self.qualifier.clone(),
+            invocation_type: Some(invocation_type.to_string()),
+            payload: Some(serde_json::to_vec(payload)?.into()),
+            ..Default::default()
+        };
+
+        let response = match lambda.invoke(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to invoke Lambda function {}: {:#}", self.function_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        match response.function_error {
+            None => Ok(()),
+            Some(function_error) => {
+                let output =
+                    response.payload.as_deref().and_then(|payload| from_utf8(payload).ok()).unwrap_or_default();
+                Err(CertificateRequestError::hook_failed(format!(
+                    "{} returned {}: {}",
+                    self.describe(),
+                    function_error,
+                    output
+                )))
+            }
+        }
+    }
+}
+
+/// Runs an SSM document through Run Command on the instances matching a set of targets, e.g. to reload nginx so
+/// running servers pick up the renewed certificate. In JSON:
+///
+///     {
+///         "Type": "RunCommand",
+///
+///         // The instances to run the document on, as Run Command targets such as
+///         // {"Key": "tag:Role", "Values": ["web"]}.
+///         "Targets": [{"Key": str, "Values": [str, ...]}, ...],
+///
+///         // The SSM document to run, e.g. "AWS-RunShellScript", and optionally the version of the document.
+///         "DocumentName": str,
+///         "DocumentVersion": str,
+///
+///         // The document parameters, e.g. {"commands": ["systemctl reload nginx"]}. "{Domain}" and "{Serial}" in
+///         // values are replaced with the first domain name and the serial number of the certificate.
+///         "Parameters": {str: [str, ...], ...},
+///
+///         // Run Command rate controls, e.g. "1" to reload one server at a time. Run Command's defaults are used
+///         // if these are omitted.
+///         "MaxConcurrency": str,
+///         "MaxErrors": str,
+///
+///         // Whether to wait for the command to finish and fail the hook if it fails on any instance. The default
+///         // is true. If false, the hook succeeds once the command is sent.
+///         "WaitForCompletion": bool,
+///
+///         // How long to wait for the command to finish, in seconds. The default is 300; the maximum is 900.
+///         "TimeoutSeconds": int,
+///
+///         // The region of the instances. If not specified, the current region is used.
+///         "Region": str,
+///
+///         // The role to assume to send the command, e.g. for instances in another account; see AssumeRole.
+///         "RoleArn": str,
+///         "ExternalId": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct RunCommandHook {
+    #[serde(rename = "Targets")]
+    pub targets: Vec<RunCommandTarget>,
+
+    #[serde(rename = "DocumentName")]
+    pub document_name: String,
+
+    #[serde(rename = "DocumentVersion", default, skip_serializing_if = "Option::is_none")]
+    pub document_version: Option<String>,
+
+    #[serde(rename = "Parameters", default)]
+    pub parameters: HashMap<String, Vec<String>>,
+
+    #[serde(rename = "MaxConcurrency", default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<String>,
+
+    #[serde(rename = "MaxErrors", default, skip_serializing_if = "Option::is_none")]
+    pub max_errors: Option<String>,
+
+    #[serde(rename = "WaitForCompletion", default = "default_true")]
+    pub wait_for_completion: bool,
+
+    #[serde(rename = "TimeoutSeconds", default = "default_run_command_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    #[serde(rename = "Region", default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+fn default_run_command_timeout_seconds() -> u64 {
+    RUN_COMMAND_DEFAULT_TIMEOUT_SECONDS
+}
+
+impl RunCommandHook {
+    fn ssm_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
+        }
+    }
+
+    /// The document parameters with "{Domain}" and "{Serial}" replaced.
+    fn parameters(&self, payload: &HookPayload) -> HashMap<String, Vec<String>> {
+        let domain_name = payload.domain_names.first().map(String::as_str).unwrap_or_default();
+        let serial = payload.serial.as_deref().unwrap_or_default();
+        self.parameters
+            .iter()
+            .map(|(name, values)| {
+                let values = values
+                    .iter()
+                    .map(|value| value.replace("{Domain}", domain_name).replace("{Serial}", serial))
+                    .collect();
+                (name.clone(), values)
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Hook for RunCommandHook {
+    fn describe(&self) -> String {
+        let targets: Vec<String> =
+            self.targets.iter().map(|target| format!("{}={}", target.key, target.values.join(","))).collect();
+        format!("RunCommand {} on {}", self.document_name, targets.join(" "))
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        if self.targets.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_hook_configuration("Targets cannot be empty"));
+        }
+
+        if self.targets.iter().any(|target| target.key.is_empty() || target.values.is_empty()) {
+            return Err(InvalidCertificateRequest::invalid_hook_configuration(
+                "Each target must have a Key and at least one value",
+            ));
+        }
+
+        if self.document_name.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_hook_configuration("DocumentName cannot be empty"));
+        }
+
+        if self.timeout_seconds == 0 || self.timeout_seconds > RUN_COMMAND_MAX_TIMEOUT_SECONDS {
+            return Err(InvalidCertificateRequest::invalid_hook_configuration(format!(
+                "TimeoutSeconds must be between 1 and {}",
+                RUN_COMMAND_MAX_TIMEOUT_SECONDS
+            )));
+        }
+
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_hook_configuration(format!(
+                    "Invalid region: {}",
+                    region
+                )));
+            }
+        }
+
+        self.assume_role.validate()
+    }
+
+    async fn run(&self, payload: &HookPayload) -> Result<(), LambdaError> {
+        let ssm = SsmClient::new_with_client(self.assume_role.client()?, self.ssm_region());
+        let sc_request = SendCommandRequest {
+            document_name: self.document_name.clone(),
+            document_version: self.document_version.clone(),
+            targets: Some(
+                self.targets
+                    .iter()
+                    .map(|target| Target {
+                        key: Some(target.key.clone()),
+                        values: Some(target.values.clone()),
+                    })
+                    .collect(),
+            ),
+            parameters: Some(self.parameters(payload)),
+            max_concurrency: self.max_concurrency.clone(),
+            max_errors: self.max_errors.clone(),
+            comment: Some(format!("Certificate issued for {}", payload.domain_names.join(" "))),
+            ..Default::default()
+        };
+
+        let command_id = match ssm.send_command(sc_request).await {
+            Ok(response) => response.command.and_then(|command| command.command_id).unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to send command {}: {:#}", self.document_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        info!("Sent command {} running {}", command_id, self.document_name);
+        if !self.wait_for_completion {
+            return Ok(());
+        }
+
+        let instances = wait_for_command(&ssm, &command_id, Duration::from_secs(self.timeout_seconds)).await?;
+        let failed: Vec<String> = instances
+            .iter()
+            .filter(|instance| instance.status != "Success")
+            .map(|instance| format!("{} ({})", instance.instance_id, instance.status))
+            .collect();
+
+        for instance in &instances {
+            if instance.status != "Success" {
+                error!(
+                    "Command {} failed on {}: {}: {}",
+                    command_id,
+                    instance.instance_id,
+                    instance.status,
+                    instance.output.as_deref().unwrap_or_default()
+                );
+            }
+        }
+
+        if instances.is_empty() {
+            Err(CertificateRequestError::hook_failed(format!("Command {} did not run on any instances", command_id)))
+        } else if !failed.is_empty() {
+            Err(CertificateRequestError::hook_failed(format!("Command {} failed on {}", command_id, failed.join(", "))))
+        } else {
+            info!("Command {} succeeded on {} instance(s)", command_id, instances.len());
+            Ok(())
+        }
+    }
+}
+
+/// Writes selected fields of the results to well-known SSM parameters, so infrastructure-as-code stacks can refer to
+/// the current certificate (e.g. its ACM ARN) without parsing this function's response. CloudFormation templates can
+/// read the parameters with `{{resolve:ssm:name}}` dynamic references or `AWS::SSM::Parameter::Value<String>`
+/// parameters, and Terraform with the `aws_ssm_parameter` data source. (CloudFormation exports can only be created
+/// by a stack, so they can't be written directly.) In JSON:
+///
+///     {
+///         "Type": "SsmOutputs",
+///
+///         // The parameters to write.
+///         "Outputs": [
+///             {
+///                 // The field to write: "Serial", "NotBefore", or "NotAfter" for the certificate, or
+///                 // "{Type}.{Field}" for a field of the first storage result of that type, e.g.
+///                 // "Acm.CertificateArn", "IamServerCertificate.Arn", or "SsmParameter.FullChainArn".
+///                 "Field": str,
+///
+///                 // The name of the String parameter to write the value to.
+///                 "ParameterName": str,
+///             },
+///             ...
+///         ],
+///
+///         // The region of the parameters. If not specified, the current region is used.
+///         "Region": str,
+///
+///         // The role to assume to write the parameters, e.g. for a stack in another account; see AssumeRole.
+///         "RoleArn": str,
+///         "ExternalId": str,
+///     }
+///
+/// Parameters whose fields aren't present in the results (e.g. because that storage target failed) are left
+/// unchanged, and the hook is reported as failed.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SsmOutputsHook {
+    #[serde(rename = "Outputs")]
+    pub outputs: Vec<SsmOutput>,
+
+    #[serde(rename = "Region", default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+/// A result field to write to an SSM parameter; see SsmOutputsHook.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SsmOutput {
+    #[serde(rename = "Field")]
+    pub field: String,
+
+    #[serde(rename = "ParameterName")]
+    pub parameter_name: String,
+}
+
+impl SsmOutputsHook {
+    fn ssm_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
+        }
+    }
+}
+
+/// Returns the value of a field (see SsmOutputsHook) from a serialized HookPayload.
+fn output_value(payload: &Value, field: &str) -> Option<String> {
+    let value = match field.split_once('.') {
+        Some((result_type, key)) => payload["StorageResults"]
+            .as_array()?
+            .iter()
+            .filter(|result| result["Type"].as_str() == Some(result_type))
+            .map(|result| &result[key])
+            .find(|value| !value.is_null())?,
+        None => &payload[field],
+    };
+
+    match value {
+        Value::String(s) if !s.is_empty() => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+#[async_trait]
+impl Hook for SsmOutputsHook {
+    fn describe(&self) -> String {
+        let names: Vec<&str> = self.outputs.iter().map(|output| output.parameter_name.as_str()).collect();
+        format!("SsmOutputs {}", names.join(" "))
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        if self.outputs.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_hook_configuration("Outputs cannot be empty"));
+        }
+
+        for output in &self.outputs {
+            if output.field.is_empty() {
+                return Err(InvalidCertificateRequest::invalid_hook_configuration("Field cannot be empty"));
+            }
+
+            if validate_and_sanitize_ssm_parameter_path(&output.parameter_name).is_none() {
+                return Err(InvalidCertificateRequest::invalid_hook_configuration(format!(
+                    "Invalid ParameterName: {}",
+                    output.parameter_name
+                )));
+            }
+        }
+
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_hook_configuration(format!(
+                    "Invalid region: {}",
+                    region
+                )));
+            }
+        }
+
+        self.assume_role.validate()
+    }
+
+    async fn run(&self, payload: &HookPayload) -> Result<(), LambdaError> {
+        let ssm = SsmClient::new_with_client(self.assume_role.client()?, self.ssm_region());
+        let payload_value = serde_json::to_value(payload)?;
+        let mut failed = Vec::new();
+
+        for output in &self.outputs {
+            let value = match output_value(&payload_value, &output.field) {
+                Some(value) => value,
+                None => {
+                    error!("No value for {} to write to {}", output.field, output.parameter_name);
+                    failed.push(format!("{} (no value for {})", output.parameter_name, output.field));
+                    continue;
+                }
+            };
+
+            let pp_request = PutParameterRequest {
+                name: output.parameter_name.clone(),
+                description: Some(format!(
+                    "{} of the certificate for {}",
+                    output.field,
+                    payload.domain_names.join(" ")
+                )),
+                overwrite: Some(true),
+                type_: Some(SSM_TYPE_STRING.to_string()),
+                value,
+                ..Default::default()
+            };
+
+            match ssm.put_parameter(pp_request).await {
+                Ok(_) => info!("Wrote {} to SSM parameter {}", output.field, output.parameter_name),
+                Err(e) => {
+                    error!("Failed to write {} to SSM parameter {}: {:#}", output.field, output.parameter_name, e);
+                    failed.push(format!("{} ({})", output.parameter_name, e));
+                }
+            }
+        }
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(CertificateRequestError::hook_failed(format!("Failed to write {}", failed.join(", "))))
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{output_value, Hook, HookPayload, LambdaHook, LambdaInvocationType, PostIssuanceHook},
+        crate::storage::{AcmStorageResult, CertificateStorageResult, IamServerCertificateStorageResult},
+        rusoto_core::Region,
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_lambda_hook() {
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "Lambda",
+            "FunctionName": "arn:aws:lambda:eu-west-1:123456789012:function:distribute-certs",
+            "Qualifier": "live",
+        }))
+        .unwrap();
+        assert!(hook.validate().is_ok());
+
+        let lambda_hook = match hook {
+            PostIssuanceHook::Lambda(lambda_hook) => lambda_hook,
+            _ => panic!("Expected a Lambda hook"),
+        };
+        assert_eq!(lambda_hook.invocation_type, LambdaInvocationType::RequestResponse);
+        assert_eq!(lambda_hook.lambda_region().unwrap(), Region::EuWest1);
+
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "Lambda",
+            "FunctionName": "distribute-certs",
+            "InvocationType": "Event",
+        }))
+        .unwrap();
+        assert!(hook.validate().is_ok());
+
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "Lambda",
+            "FunctionName": "arn:aws:sns:us-east-1:123456789012:topic",
+        }))
+        .unwrap();
+        assert!(hook.validate().is_err());
+
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "Lambda",
+            "FunctionName": "distribute-certs",
+            "ExternalId": "secret",
+        }))
+        .unwrap();
+        assert!(hook.validate().is_err());
+    }
+
+    #[test]
+    fn test_run_command_hook() {
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "RunCommand",
+            "Targets": [{"Key": "tag:Role", "Values": "web"}],
+            "DocumentName": "AWS-RunShellScript",
+            "Parameters": {"commands": ["/usr/local/bin/reload-certs {Domain} {Serial}"]},
+        }))
+        .unwrap();
+        assert!(hook.validate().is_ok());
+
+        let run_command_hook = match &hook {
+            PostIssuanceHook::RunCommand(run_command_hook) => run_command_hook,
+            _ => panic!("Expected a RunCommand hook"),
+        };
+        assert!(run_command_hook.wait_for_completion);
+        assert_eq!(run_command_hook.describe(), "RunCommand AWS-RunShellScript on tag:Role=web");
+
+        let payload = HookPayload {
+            domain_names: vec!["example.com".to_string()],
+            status: "Success".to_string(),
+            serial: Some("3a0f".to_string()),
+            not_before: None,
+            not_after: None,
+            storage_results: vec![],
+        };
+        assert_eq!(
+            run_command_hook.parameters(&payload)["commands"],
+            vec!["/usr/local/bin/reload-certs example.com 3a0f".to_string()]
+        );
+
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "RunCommand",
+            "Targets": [],
+            "DocumentName": "AWS-RunShellScript",
+        }))
+        .unwrap();
+        assert!(hook.validate().is_err());
+
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "RunCommand",
+            "Targets": [{"Key": "tag:Role", "Values": ["web"]}],
+            "DocumentName": "AWS-RunShellScript",
+            "TimeoutSeconds": 3600,
+        }))
+        .unwrap();
+        assert!(hook.validate().is_err());
+    }
+
+    #[test]
+    fn test_ssm_outputs_hook() {
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "SsmOutputs",
+            "Outputs": [
+                {"Field": "Acm.CertificateArn", "ParameterName": "/certs/example.com/AcmArn"},
+                {"Field": "NotAfter", "ParameterName": "/certs/example.com/NotAfter"},
+            ],
+        }))
+        .unwrap();
+        assert!(hook.validate().is_ok());
+
+        let hook: PostIssuanceHook = serde_json::from_value(json!({
+            "Type": "SsmOutputs",
+            "Outputs": [{"Field": "Serial", "ParameterName": "certs/serial"}],
+        }))
+        .unwrap();
+        assert!(hook.validate().is_err());
+
+        let payload = HookPayload {
+            domain_names: vec!["example.com".to_string()],
+            status: "Success".to_string(),
+            serial: Some("3a0f".to_string()),
+            not_before: None,
+            not_after: Some(1700000000),
+            storage_results: vec![
+                CertificateStorageResult::IamServerCertificate(IamServerCertificateStorageResult {
+                    server_certificate_name: "example.com-3a0f".to_string(),
+                    server_certificate_id: "ASCAEXAMPLE".to_string(),
+                    arn: "arn:aws:iam::123456789012:server-certificate/example.com-3a0f".to_string(),
+                    deleted_server_certificate_names: vec![],
+                    undeleted_server_certificate_names: vec![],
+                }),
+                CertificateStorageResult::Acm(AcmStorageResult {
+                    certificate_arn: "arn:aws:acm:us-east-1:123456789012:certificate/abc".to_string(),
+                    diff: None,
+                    canary_verification: None,
+                }),
+            ],
+        };
+        let payload = serde_json::to_value(&payload).unwrap();
+        assert_eq!(
+            output_value(&payload, "Acm.CertificateArn").as_deref(),
+            Some("arn:aws:acm:us-east-1:123456789012:certificate/abc")
+        );
+        assert_eq!(output_value(&payload, "NotAfter").as_deref(), Some("1700000000"));
+        assert_eq!(output_value(&payload, "Serial").as_deref(), Some("3a0f"));
+        assert_eq!(output_value(&payload, "NotBefore"), None);
+        assert_eq!(output_value(&payload, "SsmParameter.FullChainArn"), None);
+    }
+}
@@ -0,0 +1,463 @@
+use {
+    crate::{
+        constants::{CHAIN_ALTERNATE, CHAIN_DEFAULT, SSM_TIER_INTELLIGENT_TIERING},
+        storage::CertificateStorageResult,
+        utils::{now_epoch_secs, ssm_acme_parameter_path},
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    ring::digest::{digest, SHA256},
+    rusoto_core::{Region, RusotoError},
+    rusoto_ssm::{
+        GetParameterError, GetParameterRequest, GetParametersByPathRequest, PutParameterRequest, Ssm, SsmClient,
+    },
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::Value,
+    std::collections::{BTreeMap, BTreeSet},
+};
+
+/// A record of the last certificate issued (or copied) for a set of domain names. This is stored as a JSON
+/// document in the SSM parameter `{AcmeParameterPath}/Inventory/{DomainName}-{Hash}`. In JSON:
+///
+///     {
+///         // The domain names on the certificate.
+///         "DomainNames": [str, ...],
+///
+///         // The serial number of the certificate as lowercase hex.
+///         "Serial": str,
+///
+///         // The expiration time of the certificate in seconds since the Unix epoch.
+///         "NotAfter": int,
+///
+///         // The time this record was written in seconds since the Unix epoch.
+///         "UpdatedAt": int,
+///
+///         // The storage results from the run that wrote this record.
+///         "StorageResults": [],
+///
+///         // Whether the alternate chain was stored alongside the default chain.
+///         "AlternateChain": bool,
+///
+///         // The certificate request that wrote this record. This is replayed to renew the certificate when
+///         // ACM reports that it is approaching expiration.
+///         "Request": { ... },
+///     }
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InventoryRecord {
+    #[serde(rename = "DomainNames")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "Serial")]
+    pub serial: String,
+
+    #[serde(rename = "NotAfter")]
+    pub not_after: i64,
+
+    #[serde(rename = "UpdatedAt")]
+    pub updated_at: i64,
+
+    #[serde(rename = "StorageResults", default)]
+    pub storage_results: Vec<CertificateStorageResult>,
+
+    #[serde(rename = "AlternateChain", default)]
+    pub alternate_chain: bool,
+
+    #[serde(rename = "Request", default)]
+    pub request: Option<Value>,
+}
+
+impl InventoryRecord {
+    /// Indicates whether the certificate was written to the specified ACM certificate ARN.
+    pub fn has_acm_certificate(&self, arn: &str) -> bool {
+        self.storage_results.iter().any(|result| match result {
+            CertificateStorageResult::Acm(acm_result) => acm_result.certificate_arn == arn,
+            CertificateStorageResult::ApiGateway(apigw_result) => apigw_result.certificate_arn == arn,
+            CertificateStorageResult::CloudFront(cf_result) => cf_result.certificate_arn == arn,
+            CertificateStorageResult::LoadBalancer(lb_result) => lb_result.certificate_arn == arn,
+            _ => false,
+        })
+    }
+}
+
+/// What changed between the certificate recorded by the previous run and the one just written, so reviewers of
+/// responses and notifications can see at a glance whether anything real changed. In JSON:
+///
+///     {
+///         // The serial numbers of the previous and current certificates, and whether they differ.
+///         "PreviousSerial": str,
+///         "Serial": str,
+///         "SerialChanged": bool,
+///
+///         // The expiration times of the previous and current certificates in seconds since the Unix epoch, and
+///         // the difference between them (positive if the current certificate expires later).
+///         "PreviousNotAfter": int,
+///         "NotAfter": int,
+///         "ExpiryDeltaSecs": int,
+///
+///         // ARNs written by this run but not the previous one, and vice versa.
+///         "AddedArns": [str, ...],
+///         "RemovedArns": [str, ...],
+///
+///         // Secrets and parameters whose version changed. See VersionChange.
+///         "VersionChanges": [{ ... }, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct InventoryDiff {
+    #[serde(rename = "PreviousSerial")]
+    pub previous_serial: String,
+
+    #[serde(rename = "Serial")]
+    pub serial: String,
+
+    #[serde(rename = "SerialChanged")]
+    pub serial_changed: bool,
+
+    #[serde(rename = "PreviousNotAfter")]
+    pub previous_not_after: i64,
+
+    #[serde(rename = "NotAfter")]
+    pub not_after: i64,
+
+    #[serde(rename = "ExpiryDeltaSecs")]
+    pub expiry_delta_secs: i64,
+
+    #[serde(rename = "AddedArns", default)]
+    pub added_arns: Vec<String>,
+
+    #[serde(rename = "RemovedArns", default)]
+    pub removed_arns: Vec<String>,
+
+    #[serde(rename = "VersionChanges", default)]
+    pub version_changes: Vec<VersionChange>,
+}
+
+/// A versioned resource (Secrets Manager secret or SSM parameter) whose version changed. In JSON:
+///
+///     {
+///         // The secret ARN or parameter name.
+///         "Resource": str,
+///
+///         // The version recorded by the previous run, if the resource was written then.
+///         "PreviousVersion": str,
+///
+///         // The version written by this run.
+///         "Version": str,
+///     }
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct VersionChange {
+    #[serde(rename = "Resource")]
+    pub resource: String,
+
+    #[serde(rename = "PreviousVersion", default, skip_serializing_if = "Option::is_none")]
+    pub previous_version: Option<String>,
+
+    #[serde(rename = "Version")]
+    pub version: String,
+}
+
+impl InventoryDiff {
+    /// Compare the record written by a previous run with the record for this run.
+    pub fn new(previous: &InventoryRecord, current: &InventoryRecord) -> Self {
+        let previous_arns: BTreeSet<String> =
+            previous.storage_results.iter().flat_map(|result| result.arns()).collect();
+        let current_arns: BTreeSet<String> = current.storage_results.iter().flat_map(|result| result.arns()).collect();
+
+        let previous_versions: BTreeMap<String, String> =
+            previous.storage_results.iter().flat_map(|result| result.versions()).collect();
+        let version_changes = current
+            .storage_results
+            .iter()
+            .flat_map(|result| result.versions())
+            .filter(|(resource, version)| previous_versions.get(resource) != Some(version))
+            .map(|(resource, version)| VersionChange {
+                previous_version: previous_versions.get(&resource).cloned(),
+                resource,
+                version,
+            })
+            .collect();
+
+        Self {
+            previous_serial: previous.serial.clone(),
+            serial: current.serial.clone(),
+            serial_changed: previous.serial != current.serial,
+            previous_not_after: previous.not_after,
+            not_after: current.not_after,
+            expiry_delta_secs: current.not_after - previous.not_after,
+            added_arns: current_arns.difference(&previous_arns).cloned().collect(),
+            removed_arns: previous_arns.difference(&current_arns).cloned().collect(),
+            version_changes,
+        }
+    }
+
+    /// Indicates whether anything other than the timestamps changed.
+    pub fn has_changes(&self) -> bool {
+        self.serial_changed
+            || !self.added_arns.is_empty()
+            || !self.removed_arns.is_empty()
+            || !self.version_changes.is_empty()
+    }
+}
+
+/// A manifest announcing that the certificate for a set of domain names was rotated. This is stored as a JSON
+/// document in the SSM parameter `{AcmeParameterPath}/Rotation/{DomainName}-{Hash}`; downstream consumers can
+/// subscribe to the Parameter Store change events for this parameter to pick up new certificates. In JSON:
+///
+///     {
+///         // The domain names on the certificate.
+///         "DomainNames": [str, ...],
+///
+///         // The serial number of the current certificate as lowercase hex.
+///         "Serial": str,
+///
+///         // The expiration time of the current certificate in seconds since the Unix epoch.
+///         "NotAfter": int,
+///
+///         // The time of the rotation in seconds since the Unix epoch.
+///         "RotatedAt": int,
+///
+///         // If true, this is a synthetic rotation from a TestRotation request; the certificate has not changed.
+///         "Test": bool,
+///
+///         // Where the certificate is stored.
+///         "StorageResults": [],
+///
+///         // The chains stored for the certificate: "Default", plus "Alternate" if the alternate chain offered
+///         // during a CA chain transition was stored.
+///         "Chains": [str, ...],
+///
+///         // The chain stored in the primary "Chain" and "FullChain" components (and imported into ACM).
+///         "CurrentChain": str,
+///     }
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct RotationManifest {
+    #[serde(rename = "DomainNames")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "Serial")]
+    pub serial: String,
+
+    #[serde(rename = "NotAfter")]
+    pub not_after: i64,
+
+    #[serde(rename = "RotatedAt")]
+    pub rotated_at: i64,
+
+    #[serde(rename = "Test", default)]
+    pub test: bool,
+
+    #[serde(rename = "StorageResults", default)]
+    pub storage_results: Vec<CertificateStorageResult>,
+
+    #[serde(rename = "Chains", default)]
+    pub chains: Vec<String>,
+
+    #[serde(rename = "CurrentChain")]
+    pub current_chain: String,
+}
+
+impl RotationManifest {
+    /// Create a rotation manifest for the certificate recorded in an inventory record.
+    pub fn from_inventory(record: &InventoryRecord, test: bool) -> Self {
+        Self {
+            domain_names: record.domain_names.clone(),
+            serial: record.serial.clone(),
+            not_after: record.not_after,
+            rotated_at: now_epoch_secs(),
+            test,
+            storage_results: record.storage_results.clone(),
+            chains: if record.alternate_chain {
+                vec![CHAIN_DEFAULT.to_string(), CHAIN_ALTERNATE.to_string()]
+            } else {
+                vec![CHAIN_DEFAULT.to_string()]
+            },
+            current_chain: CHAIN_DEFAULT.to_string(),
+        }
+    }
+}
+
+/// Returns the SSM parameter name used to hold the inventory record for a set of domain names. The name includes a
+/// hash of the sorted domain names so that requests sharing a primary domain name do not collide.
+pub fn inventory_parameter_name(domain_names: &[String]) -> String {
+    parameter_name("Inventory", domain_names)
+}
+
+/// Returns the SSM parameter name used to hold the rotation manifest for a set of domain names.
+pub fn rotation_parameter_name(domain_names: &[String]) -> String {
+    parameter_name("Rotation", domain_names)
+}
+
+fn parameter_name(kind: &str, domain_names: &[String]) -> String {
+    let mut sorted = domain_names.iter().map(|dn| dn.to_lowercase()).collect::<Vec<String>>();
+    sorted.sort();
+    sorted.dedup();
+
+    let hash = digest(&SHA256, sorted.join(",").as_bytes());
+    let hash_hex: String = hash.as_ref()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+
+    format!("{}/{}/{}-{}", ssm_acme_parameter_path(), kind, domain_names[0].replace('*', "_"), hash_hex)
+}
+
+/// Read the inventory record for a set of domain names, returning None if no record exists.
+pub async fn read_inventory(domain_names: &[String]) -> Result<Option<InventoryRecord>, LambdaError> {
+    let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+    let param_name = inventory_parameter_name(domain_names);
+    let gp_request = GetParameterRequest {
+        name: param_name.clone(),
+        with_decryption: Some(true),
+    };
+
+    match ssm.get_parameter(gp_request).await {
+        Ok(response) => match response.parameter.and_then(|p| p.value) {
+            None => Ok(None),
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+        },
+        Err(RusotoError::Service(GetParameterError::ParameterNotFound(_))) => Ok(None),
+        Err(e) => {
+            error!("Failed to read inventory parameter {}: {:#}", param_name, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// Write the inventory record for a set of domain names.
+pub async fn write_inventory(record: &InventoryRecord) -> Result<(), LambdaError> {
+    let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+    let param_name = inventory_parameter_name(&record.domain_names);
+    let pp_request = PutParameterRequest {
+        name: param_name.clone(),
+        description: Some(format!("Certificate inventory for {}", record.domain_names.join(" "))),
+        overwrite: Some(true),
+        type_: Some("String".to_string()),
+        value: serde_json::to_string(record)?,
+        tier: Some(SSM_TIER_INTELLIGENT_TIERING.to_string()),
+        ..Default::default()
+    };
+
+    info!("Writing inventory parameter {}", param_name);
+    match ssm.put_parameter(pp_request).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to write inventory parameter {}: {:#}", param_name, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// Write the rotation manifest for a set of domain names.
+pub async fn write_rotation_manifest(manifest: &RotationManifest) -> Result<(), LambdaError> {
+    let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+    let param_name = rotation_parameter_name(&manifest.domain_names);
+    let pp_request = PutParameterRequest {
+        name: param_name.clone(),
+        description: Some(format!("Certificate rotation manifest for {}", manifest.domain_names.join(" "))),
+        overwrite: Some(true),
+        type_: Some("String".to_string()),
+        value: serde_json::to_string(manifest)?,
+        tier: Some(SSM_TIER_INTELLIGENT_TIERING.to_string()),
+        ..Default::default()
+    };
+
+    info!("Writing rotation manifest parameter {} (test={})", param_name, manifest.test);
+    match ssm.put_parameter(pp_request).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Failed to write rotation manifest parameter {}: {:#}", param_name, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// Find the inventory record for the certificate that was imported into the specified ACM certificate ARN. This
+/// returns None if the certificate was not issued by us.
+pub async fn find_inventory_for_acm_certificate(arn: &str) -> Result<Option<InventoryRecord>, LambdaError> {
+    let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+    let path = format!("{}/Inventory", ssm_acme_parameter_path());
+    let mut gpbp_request = GetParametersByPathRequest {
+        path: path.clone(),
+        recursive: Some(false),
+        with_decryption: Some(true),
+        ..Default::default()
+    };
+
+    loop {
+        let response = match ssm.get_parameters_by_path(gpbp_request.clone()).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to list inventory parameters under {}: {:#}", path, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        for param in response.parameters.unwrap_or_default() {
+            let value = match param.value {
+                Some(value) => value,
+                None => continue,
+            };
+
+            match serde_json::from_str::<InventoryRecord>(&value) {
+                Ok(record) if record.has_acm_certificate(arn) => return Ok(Some(record)),
+                Ok(_) => (),
+                Err(e) => error!("Ignoring unparseable inventory parameter {:?}: {:#}", param.name, e),
+            }
+        }
+
+        match response.next_token {
+            None => return Ok(None),
+            Some(token) => gpbp_request.next_token = Some(token),
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{InventoryDiff, InventoryRecord},
+        serde_json::json,
+    };
+
+    fn record(serial: &str, not_after: i64, secret_version: &str, cloudfront: bool) -> InventoryRecord {
+        let mut storage_results = vec![json!({
+            "Type": "SecretsManager",
+            "Secrets": [{
+                "Component": "Bundle",
+                "SecretName": "Certificate/example.com",
+                "SecretArn": "arn:aws:secretsmanager:us-east-1:123456789012:secret:Certificate/example.com-abc123",
+                "VersionId": secret_version,
+            }],
+        })];
+        if cloudfront {
+            storage_results.push(json!({
+                "Type": "CloudFront",
+                "CertificateArn": "arn:aws:acm:us-east-1:123456789012:certificate/1234",
+                "Distributions": [],
+            }));
+        }
+
+        serde_json::from_value(json!({
+            "DomainNames": ["example.com"],
+            "Serial": serial,
+            "NotAfter": not_after,
+            "UpdatedAt": 0,
+            "StorageResults": storage_results,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_inventory_diff() {
+        let diff = InventoryDiff::new(&record("01", 1000, "v1", false), &record("02", 5000, "v2", true));
+        assert!(diff.has_changes());
+        assert!(diff.serial_changed);
+        assert_eq!(diff.expiry_delta_secs, 4000);
+        assert_eq!(diff.added_arns, vec!["arn:aws:acm:us-east-1:123456789012:certificate/1234".to_string()]);
+        assert!(diff.removed_arns.is_empty());
+        assert_eq!(diff.version_changes.len(), 1);
+        assert_eq!(diff.version_changes[0].previous_version, Some("v1".to_string()));
+
+        let diff = InventoryDiff::new(&record("02", 5000, "v2", true), &record("02", 5000, "v2", true));
+        assert!(!diff.has_changes(), "{:?}", diff);
+    }
+}
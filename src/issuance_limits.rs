@@ -0,0 +1,218 @@
+use {
+    crate::{
+        constants::{ENV_ISSUANCE_LIMIT_TABLE, ENV_MAX_ISSUANCES_PER_DAY, ENV_MAX_ISSUANCES_PER_RUN},
+        errors::CertificateRequestError,
+        utils::{attr_n, now_epoch_secs},
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    rusoto_core::{Region, RusotoError},
+    rusoto_dynamodb::{DynamoDb, DynamoDbClient, UpdateItemError, UpdateItemInput},
+    std::{env::var, future::Future},
+    tokio::sync::Mutex,
+};
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Safety limits on the number of certificates issued, so a misconfiguration (e.g. a storage target that never
+/// observes the certificate it was given) stops with a clear error instead of reissuing the same certificate until
+/// the CA's duplicate certificate limit is exhausted. These are global settings, configured through environment
+/// variables:
+///
+/// * `MaxIssuancesPerRun`: the most certificates a single invocation (or agent run) may issue, across all of the
+///   requests in a batch.
+/// * `MaxIssuancesPerDay`: the most certificates that may be issued per UTC day, across all invocations. This
+///   requires `IssuanceLimitTable`, the name of a DynamoDB table with a numeric partition key named `Day` (the
+///   number of days since the Unix epoch). Items expire through the table's TTL attribute, `ExpiresAt`.
+///
+/// Each new order counts against the limits, whether or not it succeeds. Once a limit is reached, requests that need
+/// a new certificate fail; requests that can be satisfied by copying an existing certificate still run.
+#[derive(Debug)]
+pub struct IssuanceLimits {
+    max_per_run: Option<u32>,
+    max_per_day: Option<u32>,
+    table_name: Option<String>,
+    issued_this_run: Mutex<u32>,
+}
+
+impl IssuanceLimits {
+    /// Returns the limits configured via environment variables. Each call starts a new run.
+    pub fn from_env() -> Result<Self, LambdaError> {
+        let parse = |name: &str| -> Result<Option<u32>, LambdaError> {
+            match var(name).ok().filter(|value| !value.is_empty()) {
+                None => Ok(None),
+                Some(value) => match value.parse::<u32>() {
+                    Ok(limit) => Ok(Some(limit)),
+                    Err(_) => Err(CertificateRequestError::invalid_issuance_limits(format!(
+                        "{} must be a non-negative integer: {}",
+                        name, value
+                    ))),
+                },
+            }
+        };
+
+        Self::new(
+            parse(ENV_MAX_ISSUANCES_PER_RUN)?,
+            parse(ENV_MAX_ISSUANCES_PER_DAY)?,
+            var(ENV_ISSUANCE_LIMIT_TABLE).ok().filter(|table| !table.is_empty()),
+        )
+    }
+
+    pub fn new(
+        max_per_run: Option<u32>,
+        max_per_day: Option<u32>,
+        table_name: Option<String>,
+    ) -> Result<Self, LambdaError> {
+        if max_per_day.is_some() && table_name.is_none() {
+            return Err(CertificateRequestError::invalid_issuance_limits(format!(
+                "{} requires {}",
+                ENV_MAX_ISSUANCES_PER_DAY, ENV_ISSUANCE_LIMIT_TABLE
+            )));
+        }
+
+        Ok(Self {
+            max_per_run,
+            max_per_day,
+            table_name,
+            issued_this_run: Mutex::new(0),
+        })
+    }
+
+    /// Count an issuance for the given domain names against the limits, failing if either would be exceeded. This
+    /// must be called before placing an order.
+    pub async fn acquire(&self, domain_names: &[String]) -> Result<(), LambdaError> {
+        self.acquire_with_daily_limit(domain_names, self.acquire_for_day(domain_names)).await
+    }
+
+    /// Check the per-run limit, then the daily limit, and count the issuance against the run only if both allow it.
+    /// The run count stays locked while the daily limit is checked so concurrent requests in a batch can't both take
+    /// the last issuance.
+    async fn acquire_with_daily_limit<F>(&self, domain_names: &[String], acquire_for_day: F) -> Result<(), LambdaError>
+    where
+        F: Future<Output = Result<(), LambdaError>>,
+    {
+        let mut issued = self.issued_this_run.lock().await;
+        if let Some(max_per_run) = self.max_per_run {
+            if *issued >= max_per_run {
+                error!(
+                    "Not issuing a certificate for {}: {} certificate(s) already issued this run",
+                    domain_names.join(" "),
+                    *issued
+                );
+                return Err(CertificateRequestError::issuance_limit_exceeded(format!(
+                    "{} of {} reached; not issuing a certificate for {}",
+                    ENV_MAX_ISSUANCES_PER_RUN,
+                    max_per_run,
+                    domain_names.join(" ")
+                )));
+            }
+        }
+
+        acquire_for_day.await?;
+        *issued += 1;
+        Ok(())
+    }
+
+    async fn acquire_for_day(&self, domain_names: &[String]) -> Result<(), LambdaError> {
+        let (max_per_day, table_name) = match (self.max_per_day, &self.table_name) {
+            (Some(max_per_day), Some(table_name)) => (max_per_day, table_name),
+            _ => return Ok(()),
+        };
+
+        let day = now_epoch_secs() / SECONDS_PER_DAY;
+        let ddb = DynamoDbClient::new_with_client(aws_client(), Region::default());
+        let ui_input = UpdateItemInput {
+            table_name: table_name.clone(),
+            key: vec![("Day".to_string(), attr_n(day))].into_iter().collect(),
+            update_expression: Some("ADD Issued :one SET ExpiresAt = :expires".to_string()),
+            condition_expression: Some("attribute_not_exists(Issued) OR Issued < :max".to_string()),
+            expression_attribute_values: Some(
+                vec![
+                    (":one".to_string(), attr_n(1)),
+                    (":max".to_string(), attr_n(max_per_day as i64)),
+                    (":expires".to_string(), attr_n((day + 2) * SECONDS_PER_DAY)),
+                ]
+                .into_iter()
+                .collect(),
+            ),
+            return_values: Some("UPDATED_NEW".to_string()),
+            ..Default::default()
+        };
+
+        match ddb.update_item(ui_input).await {
+            Ok(response) => {
+                let issued = response.attributes.and_then(|attrs| attrs.get("Issued").and_then(|v| v.n.clone()));
+                info!("Issuance {} of {} today", issued.unwrap_or_default(), max_per_day);
+                Ok(())
+            }
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => {
+                error!(
+                    "Not issuing a certificate for {}: {} certificate(s) already issued today",
+                    domain_names.join(" "),
+                    max_per_day
+                );
+                Err(CertificateRequestError::issuance_limit_exceeded(format!(
+                    "{} of {} reached; not issuing a certificate for {}",
+                    ENV_MAX_ISSUANCES_PER_DAY,
+                    max_per_day,
+                    domain_names.join(" ")
+                )))
+            }
+            Err(e) => {
+                error!("Failed to update the issuance count in {}: {:#}", table_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {super::IssuanceLimits, crate::errors::CertificateRequestError, lambda_runtime::Error as LambdaError};
+
+    async fn daily_limit_ok() -> Result<(), LambdaError> {
+        Ok(())
+    }
+
+    async fn daily_limit_reached() -> Result<(), LambdaError> {
+        Err(CertificateRequestError::issuance_limit_exceeded("MaxIssuancesPerDay of 1 reached"))
+    }
+
+    #[tokio::test]
+    async fn test_issuance_limits() {
+        let domain_names = vec!["example.com".to_string()];
+
+        let unlimited = IssuanceLimits::new(None, None, None).unwrap();
+        for _ in 0..10 {
+            assert!(unlimited.acquire(&domain_names).await.is_ok());
+        }
+
+        let limited = IssuanceLimits::new(Some(2), None, None).unwrap();
+        assert!(limited.acquire(&domain_names).await.is_ok());
+        assert!(limited.acquire(&domain_names).await.is_ok());
+        let e = limited.acquire(&domain_names).await.unwrap_err();
+        assert_eq!(
+            format!("{}", e),
+            "Issuance limit exceeded: MaxIssuancesPerRun of 2 reached; not issuing a certificate for example.com"
+        );
+
+        assert!(IssuanceLimits::new(None, Some(5), None).is_err());
+        assert!(IssuanceLimits::new(None, Some(5), Some("IssuanceLimits".to_string())).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_daily_limit_leaves_run_count() {
+        let domain_names = vec!["example.com".to_string()];
+        let limits = IssuanceLimits::new(Some(1), None, None).unwrap();
+
+        // An issuance refused by the daily limit doesn't use up the run's allowance.
+        assert!(limits.acquire_with_daily_limit(&domain_names, daily_limit_reached()).await.is_err());
+        assert_eq!(*limits.issued_this_run.lock().await, 0);
+
+        assert!(limits.acquire_with_daily_limit(&domain_names, daily_limit_ok()).await.is_ok());
+        assert_eq!(*limits.issued_this_run.lock().await, 1);
+        assert!(limits.acquire_with_daily_limit(&domain_names, daily_limit_ok()).await.is_err());
+    }
+}
@@ -0,0 +1,136 @@
+use {
+    crate::errors::CertificateRequestError,
+    lambda_runtime::Error as LambdaError,
+    openssl::{
+        bn::{BigNum, BigNumContext},
+        ecdsa::EcdsaSig,
+        hash::MessageDigest,
+        nid::Nid,
+        pkey::{Id, PKey, Private},
+        sign::Signer,
+    },
+    reqwest::Client,
+    serde_json::{json, Value},
+};
+
+pub const REPLAY_NONCE: &str = "Replay-Nonce";
+
+/// Encode data as base64url without padding, as JWS requires.
+pub fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+/// Get a fresh nonce from the ACME server.
+pub async fn new_nonce(client: &Client, new_nonce_url: &str) -> Result<String, LambdaError> {
+    let response = client.head(new_nonce_url).send().await?;
+    match response.headers().get(REPLAY_NONCE).and_then(|nonce| nonce.to_str().ok()) {
+        Some(nonce) => Ok(nonce.to_string()),
+        None => Err(CertificateRequestError::unexpected_acme_response("The ACME server did not return a nonce")),
+    }
+}
+
+/// Returns the public JWK (RFC 7517) for a key, along with the JWS algorithm, digest, and (for ECDSA keys) the size
+/// of each signature component.
+fn signing_parameters(pkey: &PKey<Private>) -> Result<(&'static str, MessageDigest, Value, Option<i32>), LambdaError> {
+    match pkey.id() {
+        Id::RSA => {
+            let rsa = pkey.rsa()?;
+            let jwk = json!({"e": encode(&rsa.e().to_vec()), "kty": "RSA", "n": encode(&rsa.n().to_vec())});
+            Ok(("RS256", MessageDigest::sha256(), jwk, None))
+        }
+        Id::EC => {
+            let ec = pkey.ec_key()?;
+            let (alg, digest, crv, size) = match ec.group().curve_name() {
+                Some(Nid::X9_62_PRIME256V1) => ("ES256", MessageDigest::sha256(), "P-256", 32),
+                Some(Nid::SECP384R1) => ("ES384", MessageDigest::sha384(), "P-384", 48),
+                _ => return Err(CertificateRequestError::unexpected_acme_response("Unsupported elliptic curve")),
+            };
+            let mut x = BigNum::new()?;
+            let mut y = BigNum::new()?;
+            let mut ctx = BigNumContext::new()?;
+            ec.public_key().affine_coordinates_gfp(ec.group(), &mut x, &mut y, &mut ctx)?;
+            let jwk = json!({
+                "crv": crv,
+                "kty": "EC",
+                "x": encode(&x.to_vec_padded(size)?),
+                "y": encode(&y.to_vec_padded(size)?),
+            });
+            Ok((alg, digest, jwk, Some(size)))
+        }
+        _ => Err(CertificateRequestError::unexpected_acme_response("Unsupported key type")),
+    }
+}
+
+/// Returns the public JWK (RFC 7517) for a key.
+pub fn jwk(pkey: &PKey<Private>) -> Result<Value, LambdaError> {
+    Ok(signing_parameters(pkey)?.2)
+}
+
+/// Sign an ACME request with a key identified by its JWK (RFC 8555, section 6.2), returning the flattened JWS.
+pub fn sign_jws(pkey: &PKey<Private>, url: &str, nonce: &str, payload: &Value) -> Result<Value, LambdaError> {
+    let (alg, digest, jwk, coordinate_size) = signing_parameters(pkey)?;
+
+    let protected = json!({"alg": alg, "jwk": jwk, "nonce": nonce, "url": url});
+    let protected = encode(protected.to_string().as_bytes());
+    let payload = encode(payload.to_string().as_bytes());
+
+    let mut signer = Signer::new(digest, pkey)?;
+    signer.update(format!("{}.{}", protected, payload).as_bytes())?;
+    let mut signature = signer.sign_to_vec()?;
+
+    // JWS wants ECDSA signatures as the fixed-size concatenation of r and s rather than DER.
+    if let Some(size) = coordinate_size {
+        let sig = EcdsaSig::from_der(&signature)?;
+        signature = sig.r().to_vec_padded(size)?;
+        signature.extend(sig.s().to_vec_padded(size)?);
+    }
+
+    Ok(json!({"protected": protected, "payload": payload, "signature": encode(&signature)}))
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{jwk, sign_jws},
+        crate::keys::KeyAlgorithm,
+        openssl::{bn::BigNum, ecdsa::EcdsaSig, hash::MessageDigest, sign::Verifier},
+        serde_json::{json, Value},
+    };
+
+    #[test]
+    fn test_sign_jws() {
+        let decode = |data: &Value| base64::decode_config(data.as_str().unwrap(), base64::URL_SAFE_NO_PAD).unwrap();
+        let payload = json!({"certificate": "MIIB", "reason": 4});
+
+        for algorithm in &[KeyAlgorithm::Rsa2048, KeyAlgorithm::EcdsaP256, KeyAlgorithm::EcdsaP384] {
+            let pkey = algorithm.generate().unwrap();
+            let jws = sign_jws(&pkey, "https://acme.example/revoke", "nonce", &payload).unwrap();
+            let protected: Value = serde_json::from_slice(&decode(&jws["protected"])).unwrap();
+            assert_eq!(protected["url"], json!("https://acme.example/revoke"));
+            assert_eq!(protected["nonce"], json!("nonce"));
+            assert_eq!(protected["jwk"], jwk(&pkey).unwrap());
+
+            let signing_input = format!("{}.{}", jws["protected"].as_str().unwrap(), jws["payload"].as_str().unwrap());
+            let signature = decode(&jws["signature"]);
+            let (digest, signature) = match protected["alg"].as_str().unwrap() {
+                "RS256" => (MessageDigest::sha256(), signature),
+                alg => {
+                    let (digest, size) = if alg == "ES256" {
+                        (MessageDigest::sha256(), 32)
+                    } else {
+                        (MessageDigest::sha384(), 48)
+                    };
+                    assert_eq!(signature.len(), 2 * size);
+                    let r = BigNum::from_slice(&signature[..size]).unwrap();
+                    let s = BigNum::from_slice(&signature[size..]).unwrap();
+                    (digest, EcdsaSig::from_private_components(r, s).unwrap().to_der().unwrap())
+                }
+            };
+
+            let mut verifier = Verifier::new(digest, &pkey).unwrap();
+            verifier.update(signing_input.as_bytes()).unwrap();
+            assert!(verifier.verify(&signature).unwrap());
+        }
+    }
+}
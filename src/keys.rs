@@ -0,0 +1,126 @@
+use {
+    lambda_runtime::Error as LambdaError,
+    log::error,
+    openssl::{
+        ec::{EcGroup, EcKey},
+        nid::Nid,
+        pkey::{Id, PKey, Private, Public},
+        rsa::Rsa,
+    },
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// The algorithm used to generate the private key for a certificate. In JSON, this is one of `"Rsa2048"` (the
+/// default), `"Rsa4096"`, `"EcdsaP256"`, or `"EcdsaP384"`.
+///
+/// ECDSA certificates are smaller and faster to use than RSA certificates, and are supported by ACM for use with
+/// CloudFront and Application/Network Load Balancers.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum KeyAlgorithm {
+    #[default]
+    Rsa2048,
+    Rsa4096,
+    EcdsaP256,
+    EcdsaP384,
+}
+
+impl KeyAlgorithm {
+    /// Generate a new private key using this algorithm.
+    pub fn generate(&self) -> Result<PKey<Private>, LambdaError> {
+        let result = match self {
+            Self::Rsa2048 => Rsa::generate(2048).and_then(PKey::from_rsa),
+            Self::Rsa4096 => Rsa::generate(4096).and_then(PKey::from_rsa),
+            Self::EcdsaP256 => generate_ec(Nid::X9_62_PRIME256V1),
+            Self::EcdsaP384 => generate_ec(Nid::SECP384R1),
+        };
+
+        match result {
+            Ok(pkey) => Ok(pkey),
+            Err(e) => {
+                error!("Unable to generate {} private key: {:#}", self, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Determine the algorithm used by a public key, if it's one we support.
+    pub fn from_public_key(pkey: &PKey<Public>) -> Option<Self> {
+        match pkey.id() {
+            Id::RSA => match pkey.bits() {
+                2048 => Some(Self::Rsa2048),
+                4096 => Some(Self::Rsa4096),
+                _ => None,
+            },
+            Id::EC => match pkey.ec_key().ok()?.group().curve_name()? {
+                Nid::X9_62_PRIME256V1 => Some(Self::EcdsaP256),
+                Nid::SECP384R1 => Some(Self::EcdsaP384),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The key type as reported by ACM, e.g. "RSA_2048" or "EC_prime256v1".
+    pub fn acm_key_type(&self) -> &'static str {
+        match self {
+            Self::Rsa2048 => "RSA_2048",
+            Self::Rsa4096 => "RSA_4096",
+            Self::EcdsaP256 => "EC_prime256v1",
+            Self::EcdsaP384 => "EC_secp384r1",
+        }
+    }
+
+    /// Indicates whether this is an ECDSA algorithm.
+    pub fn is_ecdsa(&self) -> bool {
+        matches!(self, Self::EcdsaP256 | Self::EcdsaP384)
+    }
+
+    /// Determine the algorithm from an ACM key type, if it's one we support.
+    pub fn from_acm_key_type(key_type: &str) -> Option<Self> {
+        [Self::Rsa2048, Self::Rsa4096, Self::EcdsaP256, Self::EcdsaP384]
+            .iter()
+            .find(|alg| alg.acm_key_type() == key_type)
+            .copied()
+    }
+}
+
+impl Display for KeyAlgorithm {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Rsa2048 => write!(f, "Rsa2048"),
+            Self::Rsa4096 => write!(f, "Rsa4096"),
+            Self::EcdsaP256 => write!(f, "EcdsaP256"),
+            Self::EcdsaP384 => write!(f, "EcdsaP384"),
+        }
+    }
+}
+
+fn generate_ec(curve: Nid) -> Result<PKey<Private>, openssl::error::ErrorStack> {
+    let group = EcGroup::from_curve_name(curve)?;
+    let ec_key = EcKey::generate(&group)?;
+    PKey::from_ec_key(ec_key)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::KeyAlgorithm;
+
+    #[test]
+    fn test_generated_keys_round_trip() {
+        for alg in &[KeyAlgorithm::Rsa2048, KeyAlgorithm::EcdsaP256, KeyAlgorithm::EcdsaP384] {
+            let private_key = alg.generate().unwrap();
+            let public_der = private_key.public_key_to_der().unwrap();
+            let public_key = openssl::pkey::PKey::public_key_from_der(&public_der).unwrap();
+            assert_eq!(KeyAlgorithm::from_public_key(&public_key), Some(*alg));
+        }
+    }
+
+    #[test]
+    fn test_acm_key_types() {
+        assert_eq!(KeyAlgorithm::from_acm_key_type("EC_secp384r1"), Some(KeyAlgorithm::EcdsaP384));
+        assert_eq!(KeyAlgorithm::from_acm_key_type("RSA_1024"), None);
+    }
+}
@@ -0,0 +1,431 @@
+use {
+    crate::{
+        assume_role::AssumeRole,
+        constants::{K8S_FIELD_MANAGER, K8S_TOKEN_PREFIX, K8S_TOKEN_TTL_SECS},
+        dry_run::PlannedWrite,
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        reconcile::ObservedCertificate,
+        storage::{CertificateStorageResult, KubernetesStorageResult},
+        store::CertificateStore,
+        utils::{default_false, CertificateComponents, CertificateInfo},
+    },
+    async_trait::async_trait,
+    hyper::{
+        body::to_bytes,
+        client::HttpConnector,
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        Body, Client as HyperClient, Method, Request as HyperRequest, StatusCode,
+    },
+    hyper_tls::HttpsConnector,
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    native_tls::{Certificate as TlsCertificate, TlsConnector},
+    openssl::x509::X509,
+    rusoto_core::{credential::AwsCredentials, signature::SignedRequest, Region},
+    rusoto_eks::{DescribeClusterRequest, Eks, EksClient},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::{json, Value},
+    std::{collections::BTreeMap, str::FromStr, time::Duration},
+};
+
+/// Configuration for writing a certificate as a kubernetes.io/tls Secret in an Amazon EKS cluster, for ingress
+/// controllers that read TLS secrets directly. In JSON:
+///
+///     {
+///         // The type of storage to use. This must be "Kubernetes".
+///         "Type": "Kubernetes",
+///
+///         // The name of the EKS cluster. This is required.
+///         "ClusterName": str,
+///
+///         // The namespace to write the secret to. This defaults to "default".
+///         "Namespace": str,
+///
+///         // The name of the secret. "{Domain}" is replaced with the first domain name of the certificate, with a
+///         // leading "*." replaced by "wildcard.". This is required.
+///         "SecretName": str,
+///
+///         // If true, the chain is also written to the "ca.crt" key. The default is false.
+///         "IncludeCaCertificate": bool,
+///
+///         // Labels and annotations to set on the secret.
+///         "Labels": {str: str, ...},
+///         "Annotations": {str: str, ...},
+///
+///         // The region of the cluster. If not specified, the current region is used.
+///         "Region": str,
+///     }
+///
+/// The function authenticates to the cluster the same way aws-iam-authenticator does, so its role (or RoleArn, if
+/// specified) must be mapped to a Kubernetes identity, through an EKS access entry or the aws-auth ConfigMap, that
+/// can get and patch secrets in the namespace. The secret is written with server-side apply, so it's created if it
+/// doesn't exist and other fields managers set on it are left alone.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct KubernetesStorage {
+    #[serde(rename = "ClusterName")]
+    pub cluster_name: String,
+
+    #[serde(rename = "Namespace", default = "default_namespace")]
+    pub namespace: String,
+
+    #[serde(rename = "SecretName")]
+    pub secret_name: String,
+
+    #[serde(rename = "IncludeCaCertificate", default = "default_false")]
+    pub include_ca_certificate: bool,
+
+    #[serde(rename = "Labels", default)]
+    pub labels: BTreeMap<String, String>,
+
+    #[serde(rename = "Annotations", default)]
+    pub annotations: BTreeMap<String, String>,
+
+    #[serde(rename = "Region", default)]
+    pub region: Option<String>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+fn default_namespace() -> String {
+    "default".to_string()
+}
+
+/// The API endpoint of a cluster and a client that trusts its certificate authority.
+struct ClusterConnection {
+    endpoint: String,
+    token: String,
+    client: HyperClient<HttpsConnector<HttpConnector>>,
+}
+
+impl KubernetesStorage {
+    fn eks_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
+        }
+    }
+
+    fn get_secret_name(&self, domain_name: &str) -> String {
+        let domain_name = match domain_name.strip_prefix("*.") {
+            Some(base) => format!("wildcard.{}", base),
+            None => domain_name.to_string(),
+        };
+        self.secret_name.replace("{Domain}", &domain_name.to_lowercase())
+    }
+
+    fn secret_path(&self, secret_name: &str) -> String {
+        format!("/api/v1/namespaces/{}/secrets/{}", self.namespace, secret_name)
+    }
+
+    /// Look up the cluster's endpoint and certificate authority and get a token for it.
+    async fn connect(&self) -> Result<ClusterConnection, LambdaError> {
+        let eks = EksClient::new_with_client(self.assume_role.client()?, self.eks_region());
+        let dc_request = DescribeClusterRequest {
+            name: self.cluster_name.clone(),
+        };
+
+        let cluster = match eks.describe_cluster(dc_request).await {
+            Ok(response) => response.cluster.unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to describe EKS cluster {}: {:#}", self.cluster_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let endpoint = cluster.endpoint.ok_or_else(|| {
+            CertificateRequestError::unexpected_aws_response(format!(
+                "EKS cluster {} has no endpoint",
+                self.cluster_name
+            ))
+        })?;
+        let ca_data = cluster.certificate_authority.and_then(|ca| ca.data).ok_or_else(|| {
+            CertificateRequestError::unexpected_aws_response(format!(
+                "EKS cluster {} has no certificate authority",
+                self.cluster_name
+            ))
+        })?;
+
+        let tls = TlsConnector::builder()
+            .add_root_certificate(TlsCertificate::from_pem(&base64::decode(ca_data)?)?)
+            .build()?;
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+
+        Ok(ClusterConnection {
+            endpoint,
+            token: eks_token(&self.cluster_name, &self.eks_region(), &self.assume_role.credentials().await?),
+            client: HyperClient::builder().build(HttpsConnector::from((http, tls.into()))),
+        })
+    }
+
+    /// Make a request to the Kubernetes API, returning the status and the parsed response body.
+    async fn request(
+        &self,
+        conn: &ClusterConnection,
+        method: Method,
+        path_and_query: &str,
+        content_type: Option<&str>,
+        body: Option<Value>,
+    ) -> Result<(StatusCode, Value), LambdaError> {
+        let mut request = HyperRequest::builder()
+            .method(method)
+            .uri(format!("{}{}", conn.endpoint, path_and_query))
+            .header(AUTHORIZATION, format!("Bearer {}", conn.token));
+        if let Some(content_type) = content_type {
+            request = request.header(CONTENT_TYPE, content_type);
+        }
+
+        let body = match body {
+            Some(body) => Body::from(serde_json::to_string(&body)?),
+            None => Body::empty(),
+        };
+
+        let response = conn.client.request(request.body(body)?).await?;
+        let status = response.status();
+        let body = to_bytes(response.into_body()).await?;
+        Ok((status, serde_json::from_slice(&body).unwrap_or(Value::Null)))
+    }
+
+    /// The secret to apply.
+    fn secret(&self, secret_name: &str, components: &CertificateComponents) -> Value {
+        let mut data = json!({
+            "tls.crt": base64::encode(&components.fullchain_pem),
+            "tls.key": base64::encode(&components.pkey_pem),
+        });
+        if self.include_ca_certificate {
+            data["ca.crt"] = Value::String(base64::encode(&components.chain_pem));
+        }
+
+        json!({
+            "apiVersion": "v1",
+            "kind": "Secret",
+            "type": "kubernetes.io/tls",
+            "metadata": {
+                "name": secret_name,
+                "namespace": self.namespace,
+                "labels": self.labels,
+                "annotations": self.annotations,
+            },
+            "data": data,
+        })
+    }
+}
+
+#[async_trait]
+impl CertificateStore for KubernetesStorage {
+    fn type_name(&self) -> &'static str {
+        "Kubernetes"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        if self.cluster_name.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_kubernetes_configuration("ClusterName must be specified"));
+        }
+
+        if !is_dns_label(&self.namespace) {
+            return Err(InvalidCertificateRequest::invalid_kubernetes_configuration(format!(
+                "Invalid Namespace: {}",
+                self.namespace
+            )));
+        }
+
+        if !is_dns_subdomain(&self.get_secret_name("*.example.com")) {
+            return Err(InvalidCertificateRequest::invalid_kubernetes_configuration(format!(
+                "Invalid SecretName: {}",
+                self.secret_name
+            )));
+        }
+
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_kubernetes_configuration(format!(
+                    "Invalid region: {}",
+                    region
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn planned_writes(&self, domain_names: &[String], _alternate_chain: bool) -> Vec<PlannedWrite> {
+        let resource = format!("{}{}", self.cluster_name, self.secret_path(&self.get_secret_name(&domain_names[0])));
+        vec![PlannedWrite::in_region("kubernetes:PatchSecret", resource, &self.eks_region())]
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let secret_name = self.get_secret_name(&domain_names[0]);
+        let conn = self.connect().await?;
+        let path = format!("{}?fieldManager={}&force=true", self.secret_path(&secret_name), K8S_FIELD_MANAGER);
+
+        // Server-side apply accepts JSON, since it's a subset of YAML.
+        let (status, response) = self
+            .request(
+                &conn,
+                Method::PATCH,
+                &path,
+                Some("application/apply-patch+yaml"),
+                Some(self.secret(&secret_name, &components)),
+            )
+            .await?;
+
+        if !status.is_success() {
+            let message = response["message"].as_str().unwrap_or_default();
+            error!("Failed to apply secret {}/{}: HTTP {}: {}", self.namespace, secret_name, status, message);
+            return Err(CertificateRequestError::kubernetes_api_failed(format!(
+                "Apply secret {}/{} in {}: HTTP {}: {}",
+                self.namespace, secret_name, self.cluster_name, status, message
+            )));
+        }
+
+        info!("Applied secret {}/{} in EKS cluster {}", self.namespace, secret_name, self.cluster_name);
+        Ok(vec![CertificateStorageResult::Kubernetes(KubernetesStorageResult {
+            cluster_name: self.cluster_name.clone(),
+            namespace: self.namespace.clone(),
+            secret_name,
+            resource_version: response["metadata"]["resourceVersion"].as_str().map(str::to_string),
+        })])
+    }
+
+    /// Observe the certificate in the secret.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let secret_name = self.get_secret_name(&domain_names[0]);
+        let conn = self.connect().await?;
+        let (status, response) = self.request(&conn, Method::GET, &self.secret_path(&secret_name), None, None).await?;
+
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        } else if !status.is_success() {
+            let message = response["message"].as_str().unwrap_or_default();
+            error!("Failed to read secret {}/{}: HTTP {}: {}", self.namespace, secret_name, status, message);
+            return Err(CertificateRequestError::kubernetes_api_failed(format!(
+                "Get secret {}/{} in {}: HTTP {}: {}",
+                self.namespace, secret_name, self.cluster_name, status, message
+            )));
+        }
+
+        let decode = |key: &str| -> Result<Option<String>, LambdaError> {
+            match response["data"][key].as_str() {
+                Some(data) => Ok(Some(String::from_utf8(base64::decode(data)?)?)),
+                None => Ok(None),
+            }
+        };
+
+        let (fullchain_pem, pkey_pem) = match (decode("tls.crt")?, decode("tls.key")?) {
+            (Some(fullchain_pem), Some(pkey_pem)) => (fullchain_pem, pkey_pem),
+            _ => return Ok(None),
+        };
+
+        // tls.crt holds the full chain; split it back into the certificate and its chain.
+        let mut certs = X509::stack_from_pem(fullchain_pem.as_bytes())?.into_iter();
+        let cert_pem = match certs.next() {
+            Some(cert) => String::from_utf8(cert.to_pem()?)?,
+            None => return Ok(None),
+        };
+        let mut chain_pem = String::new();
+        for cert in certs {
+            chain_pem.push_str(&String::from_utf8(cert.to_pem()?)?);
+        }
+
+        Ok(Some(ObservedCertificate {
+            location: format!("{}/{}/{}", self.cluster_name, self.namespace, secret_name),
+            info: CertificateInfo::from_pem(&cert_pem)?,
+            components: Some(CertificateComponents {
+                cert_pem,
+                chain_pem,
+                fullchain_pem,
+                pkey_pem,
+                alternate_chain: None,
+            }),
+        }))
+    }
+}
+
+/// Generate a bearer token for an EKS cluster, as aws-iam-authenticator does: a presigned STS GetCallerIdentity URL
+/// that includes the cluster name as a signed header, which the cluster's authenticator webhook calls to identify
+/// the caller.
+fn eks_token(cluster_name: &str, region: &Region, credentials: &AwsCredentials) -> String {
+    let mut request = SignedRequest::new("GET", "sts", region, "/");
+    request.add_param("Action", "GetCallerIdentity");
+    request.add_param("Version", "2011-06-15");
+    request.add_header("x-k8s-aws-id", cluster_name);
+    let url = request.generate_presigned_url(credentials, &Duration::from_secs(K8S_TOKEN_TTL_SECS), false);
+    format!("{}{}", K8S_TOKEN_PREFIX, base64::encode_config(url, base64::URL_SAFE_NO_PAD))
+}
+
+/// Indicates whether a name is a valid RFC 1123 label, as Kubernetes requires for namespaces.
+fn is_dns_label(name: &str) -> bool {
+    name.len() <= 63
+        && name.starts_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name.ends_with(|c: char| c.is_ascii_lowercase() || c.is_ascii_digit())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Indicates whether a name is a valid RFC 1123 subdomain, as Kubernetes requires for most object names.
+fn is_dns_subdomain(name: &str) -> bool {
+    name.len() <= 253 && name.split('.').all(is_dns_label)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{eks_token, is_dns_label, is_dns_subdomain, KubernetesStorage},
+        crate::store::CertificateStore,
+        rusoto_core::{credential::AwsCredentials, Region},
+    };
+
+    #[test]
+    fn test_names() {
+        assert!(is_dns_label("cert-manager"));
+        assert!(!is_dns_label("Cert-Manager"));
+        assert!(!is_dns_label("-ingress"));
+        assert!(!is_dns_label("ingress.tls"));
+        assert!(is_dns_subdomain("wildcard.example.com-tls"));
+        assert!(!is_dns_subdomain("example..com"));
+
+        let storage: KubernetesStorage =
+            serde_json::from_str(r#"{"ClusterName": "prod", "Namespace": "ingress", "SecretName": "{Domain}-tls"}"#)
+                .unwrap();
+        assert_eq!(storage.get_secret_name("*.Example.com"), "wildcard.example.com-tls");
+        assert_eq!(storage.get_secret_name("www.example.com"), "www.example.com-tls");
+    }
+
+    #[test]
+    fn test_planned_writes() {
+        let storage: KubernetesStorage = serde_json::from_str(
+            r#"{"ClusterName": "prod", "Namespace": "ingress", "SecretName": "{Domain}-tls", "Region": "us-west-2"}"#,
+        )
+        .unwrap();
+        let writes = storage.planned_writes(&["*.example.com".to_string()], false);
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].action, "kubernetes:PatchSecret");
+        assert_eq!(writes[0].resource, "prod/api/v1/namespaces/ingress/secrets/wildcard.example.com-tls");
+        assert_eq!(writes[0].region.as_deref(), Some("us-west-2"));
+    }
+
+    #[test]
+    fn test_eks_token() {
+        let credentials = AwsCredentials::new("AKIDEXAMPLE", "secret", None, None);
+        let token = eks_token("prod", &Region::UsWest2, &credentials);
+        let url = String::from_utf8(
+            base64::decode_config(token.strip_prefix("k8s-aws-v1.").unwrap(), base64::URL_SAFE_NO_PAD).unwrap(),
+        )
+        .unwrap();
+
+        assert!(url.starts_with("https://sts."));
+        assert!(url.contains("Action=GetCallerIdentity"));
+        assert!(url.contains("x-k8s-aws-id"));
+        assert!(url.contains("X-Amz-Expires=60"));
+    }
+}
@@ -0,0 +1,103 @@
+//! Issue certificates from an ACME server (e.g. Let's Encrypt) and store them in AWS services.
+//!
+//! The Lambda function, agent, and scaffold modes of the `letsencrypt-certs-aws` binary and the
+//! `letsencrypt-certs-cli` command-line tool are thin wrappers around this library, so the same request documents
+//! can be processed by other tools (an ECS task, a CDK custom resource). Most modules are private; the library
+//! exposes:
+//!
+//! * `handle_request`: process a request document (a certificate request, a batch, an event, ...) and return its
+//!   `Response`.
+//! * `handler_main`: the Lambda handler, which runs `handle_request` within the invocation's deadline.
+//! * `validate_certificate_request`: validate a `CertificateRequest` and set up its storage and authorization
+//!   providers, returning a `ValidatedCertificateRequest` to run.
+//! * The request and response documents, and the entrypoints of the agent, scaffold, and CLI modes.
+//! * The extension points for adding storage targets: the `store` module's `CertificateStore` trait and registry,
+//!   along with the `storage`, `auth`, and `utils` types their signatures use.
+
+#![warn(clippy::all)]
+#![allow(clippy::redundant_field_names)]
+
+mod account;
+mod acm_cache;
+mod acme_gateway;
+mod agent;
+mod artifacts;
+/// The IAM role a storage target or authorization handler assumes to make its AWS calls.
+pub mod assume_role;
+mod audit;
+/// ACME challenge handlers (DNS, HTTP, and TLS-ALPN) selected by a request's Authorization.
+pub mod auth;
+mod batch;
+/// Certificate chains offered by the CA, including alternate chains during a chain transition.
+pub mod chains;
+mod cli;
+mod config_source;
+mod consistency;
+mod constants;
+mod cost_ledger;
+mod csr;
+mod debug_artifacts;
+mod domain_policy;
+/// Dry run reports, listing the writes a request would make without making them.
+pub mod dry_run;
+mod errors;
+mod events;
+mod faults;
+mod handler;
+mod health;
+mod hooks;
+mod inventory;
+mod issuance_limits;
+mod jws;
+/// Private key algorithms and key generation.
+pub mod keys;
+mod kubernetes;
+mod lifecycle;
+mod metrics;
+mod migrate;
+mod notifications;
+mod org_audit;
+mod payload_encryption;
+mod policy;
+mod rate_limits;
+mod readiness;
+/// Comparing the certificates held by storage targets against a request and planning what to change.
+pub mod reconcile;
+mod renewal_lock;
+mod report;
+mod retry;
+mod revoke;
+mod scaffold;
+mod schedule;
+mod schema;
+mod secrets;
+#[cfg(feature = "ssh-output")]
+mod ssh;
+/// The built-in storage targets and the results of writing a certificate to them.
+pub mod storage;
+/// The CertificateStore trait and the registry mapping a storage target's "Type" to its implementation.
+pub mod store;
+mod task;
+mod throttle;
+mod trust_bundle;
+/// Certificate components and helpers shared across storage targets.
+pub mod utils;
+mod verify;
+mod warm;
+mod workflow;
+mod writer;
+mod xray;
+
+pub use {
+    agent::{run_agent, run_agent_once, RunMode},
+    cli::{run_cli, CliOptions},
+    events::{
+        CertificateAction, CertificateRequest, CertificateResponse, CertificateResponseStatus, EventBridgeEvent,
+        EventResponse, Request, Response,
+    },
+    handler::{handle_request, handler_main, validate_certificate_request},
+    issuance_limits::IssuanceLimits,
+    report::RunBudget,
+    scaffold::run_scaffold,
+    workflow::ValidatedCertificateRequest,
+};
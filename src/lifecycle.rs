@@ -0,0 +1,190 @@
+use {
+    crate::{
+        batch::BatchProgress,
+        constants::{
+            ENV_LIFECYCLE_EVENT_BUS, EVENT_DETAIL_TYPE_BATCH_PROGRESS, EVENT_DETAIL_TYPE_CERTIFICATE_ISSUED,
+            EVENT_DETAIL_TYPE_CERTIFICATE_RENEWAL_FAILED, EVENT_DETAIL_TYPE_CERTIFICATE_STORED, EVENT_SOURCE_LIFECYCLE,
+        },
+        events::Response,
+        storage::CertificateStorageResult,
+        utils::CertificateInfo,
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    rusoto_core::Region,
+    rusoto_events::{EventBridge, EventBridgeClient, PutEventsRequest, PutEventsRequestEntry},
+    serde_json::{json, Value},
+    std::env::var,
+};
+
+/// PutEvents accepts at most this many entries per call.
+const MAX_ENTRIES_PER_PUT: usize = 10;
+
+/// Returns an EventBridge client for the default region.
+pub fn event_bridge_client() -> EventBridgeClient {
+    EventBridgeClient::new_with_client(aws_client(), Region::default())
+}
+
+/// A certificate lifecycle event to send to EventBridge. Events are sent with the source "letsencrypt-certs-aws"
+/// and one of the following detail types:
+///
+/// * `CertificateIssued`: a new certificate was issued by the ACME server.
+/// * `CertificateStored`: a certificate was written to a storage target. The resources are the ARNs of the
+///   written resources, if any.
+/// * `CertificateRenewalFailed`: the run failed or a storage target could not be written.
+/// * `BatchProgress`: a periodic heartbeat from a running batch. The detail is a BatchProgress rather than the
+///   object below.
+///
+/// The detail is a JSON object:
+///
+///     {
+///         // The domain names on the certificate.
+///         "DomainNames": [str, ...],
+///
+///         // For CertificateIssued and CertificateStored, the serial number and expiration time (seconds since
+///         // the Unix epoch) of the certificate.
+///         "Serial": str,
+///         "NotAfter": int,
+///
+///         // For CertificateStored, the storage result. See CertificateStorageResult.
+///         "StorageResult": { ... },
+///
+///         // For CertificateRenewalFailed, the errors encountered.
+///         "Errors": [str, ...],
+///     }
+#[derive(Debug)]
+pub struct LifecycleEvent {
+    pub detail_type: &'static str,
+    pub resources: Vec<String>,
+    pub detail: Value,
+}
+
+impl LifecycleEvent {
+    pub fn issued(domain_names: &[String], info: &CertificateInfo) -> Self {
+        Self {
+            detail_type: EVENT_DETAIL_TYPE_CERTIFICATE_ISSUED,
+            resources: vec![],
+            detail: json!({
+                "DomainNames": domain_names,
+                "Serial": info.serial,
+                "NotAfter": info.not_after,
+            }),
+        }
+    }
+
+    pub fn stored(domain_names: &[String], info: &CertificateInfo, result: &CertificateStorageResult) -> Self {
+        Self {
+            detail_type: EVENT_DETAIL_TYPE_CERTIFICATE_STORED,
+            resources: result.arns(),
+            detail: json!({
+                "DomainNames": domain_names,
+                "Serial": info.serial,
+                "NotAfter": info.not_after,
+                "StorageResult": result,
+            }),
+        }
+    }
+
+    /// Returns a CertificateRenewalFailed event if the run failed or any storage target could not be written.
+    pub fn renewal_failed(domain_names: &[String], result: &Result<Response, LambdaError>) -> Option<Self> {
+        let errors: Vec<String> = match result {
+            Err(e) => vec![format!("{:#}", e)],
+            Ok(Response::Certificate(response)) => response
+                .storage
+                .iter()
+                .filter_map(|storage_result| match storage_result {
+                    CertificateStorageResult::Error(e) => Some(e.clone()),
+                    _ => None,
+                })
+                .collect(),
+            Ok(_) => vec![],
+        };
+
+        if errors.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            detail_type: EVENT_DETAIL_TYPE_CERTIFICATE_RENEWAL_FAILED,
+            resources: vec![],
+            detail: json!({
+                "DomainNames": domain_names,
+                "Errors": errors,
+            }),
+        })
+    }
+
+    pub fn batch_progress(progress: &BatchProgress) -> Self {
+        Self {
+            detail_type: EVENT_DETAIL_TYPE_BATCH_PROGRESS,
+            resources: vec![],
+            detail: json!(progress),
+        }
+    }
+}
+
+/// Sends lifecycle events to an EventBridge event bus.
+#[derive(Clone, Debug)]
+pub struct LifecycleEventEmitter {
+    pub event_bus_name: String,
+}
+
+impl LifecycleEventEmitter {
+    /// Returns an emitter for the event bus named in the request, falling back to the `LifecycleEventBus`
+    /// environment variable. Returns None if neither is set, disabling lifecycle events.
+    pub fn resolve(event_bus_name: Option<String>) -> Option<Self> {
+        let event_bus_name = match event_bus_name {
+            Some(name) => name,
+            None => var(ENV_LIFECYCLE_EVENT_BUS).ok()?,
+        };
+
+        if event_bus_name.is_empty() {
+            None
+        } else {
+            Some(Self {
+                event_bus_name,
+            })
+        }
+    }
+
+    /// Send the events. Failures are logged and otherwise ignored; lifecycle events are advisory and should not
+    /// cause an otherwise successful run to fail.
+    pub async fn emit(&self, events: Vec<LifecycleEvent>) {
+        if let Err(e) = self.put_events(events).await {
+            error!("Failed to send lifecycle events to event bus {}: {:#}", self.event_bus_name, e);
+        }
+    }
+
+    async fn put_events(&self, events: Vec<LifecycleEvent>) -> Result<(), LambdaError> {
+        let client = event_bridge_client();
+        let mut entries = Vec::with_capacity(events.len());
+
+        for event in events {
+            info!("Sending {} event to event bus {}", event.detail_type, self.event_bus_name);
+            entries.push(PutEventsRequestEntry {
+                event_bus_name: Some(self.event_bus_name.clone()),
+                source: Some(EVENT_SOURCE_LIFECYCLE.to_string()),
+                detail_type: Some(event.detail_type.to_string()),
+                detail: Some(serde_json::to_string(&event.detail)?),
+                resources: Some(event.resources),
+                ..Default::default()
+            });
+        }
+
+        for chunk in entries.chunks(MAX_ENTRIES_PER_PUT) {
+            let pe_request = PutEventsRequest {
+                entries: chunk.to_vec(),
+            };
+
+            let response = client.put_events(pe_request).await?;
+            if let Some(n_failed) = response.failed_entry_count {
+                if n_failed > 0 {
+                    error!("{} lifecycle event(s) were rejected by event bus {}", n_failed, self.event_bus_name);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use {
+    crate::{
+        constants::ENV_METRICS_NAMESPACE,
+        events::{CertificateResponse, Response},
+        reconcile::ReconcileAction,
+        storage::CertificateStorageResult,
+        utils::{now_epoch_secs, CertificateInfo},
+    },
+    lambda_runtime::Error as LambdaError,
+    serde_json::{json, Map, Value},
+    std::{
+        env::var,
+        time::{SystemTime, UNIX_EPOCH},
+    },
+};
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// CloudWatch metrics for issuance outcomes, written to standard output in the CloudWatch Embedded Metric Format so
+/// that Lambda's log delivery turns them into metrics without any API calls. This is a global setting, enabled by
+/// setting the `MetricsNamespace` environment variable to the CloudWatch namespace to publish to.
+///
+/// Each run produces the following metrics with no dimensions:
+///
+/// * `CertificatesIssued`: 1 if a certificate was issued for domain names that had none.
+/// * `CertificatesRenewed`: 1 if a certificate was issued to replace an existing one.
+/// * `StorageFailures`: the number of storage targets that could not be written or were rolled back.
+/// * `RenewalFailures`: 1 if the run failed outright.
+///
+/// and, for each domain name on the certificate, `DaysUntilExpiry` with the `DomainName` dimension: the days
+/// remaining on the certificate now held by the storage targets.
+#[derive(Clone, Debug)]
+pub struct MetricsEmitter {
+    namespace: String,
+}
+
+impl MetricsEmitter {
+    /// Returns the emitter configured via environment variables, or None if metrics are disabled.
+    pub fn from_env() -> Option<Self> {
+        let namespace = var(ENV_METRICS_NAMESPACE).ok().filter(|namespace| !namespace.is_empty())?;
+        Some(Self {
+            namespace,
+        })
+    }
+
+    /// Write the metrics for a run. This goes directly to standard output rather than through the logger, which
+    /// would prefix the line and keep CloudWatch from recognizing it.
+    pub fn emit(
+        &self,
+        domain_names: &[String],
+        certificate: Option<&CertificateInfo>,
+        result: &Result<Response, LambdaError>,
+    ) {
+        for document in self.documents(domain_names, certificate, result, now_epoch_millis()) {
+            println!("{}", document);
+        }
+    }
+
+    /// Returns the EMF documents describing a run.
+    fn documents(
+        &self,
+        domain_names: &[String],
+        certificate: Option<&CertificateInfo>,
+        result: &Result<Response, LambdaError>,
+        timestamp: u64,
+    ) -> Vec<Value> {
+        let response = match result {
+            Ok(Response::Certificate(response)) => Some(response),
+            _ => None,
+        };
+
+        let (issued, renewed) = response.map(issuance_counts).unwrap_or((0, 0));
+        let storage_failures = response.map(storage_failures).unwrap_or(0);
+        let renewal_failures = if result.is_err() {
+            1
+        } else {
+            0
+        };
+
+        let mut documents = vec![self.document(
+            timestamp,
+            &[],
+            &[
+                ("CertificatesIssued", "Count", issued.into()),
+                ("CertificatesRenewed", "Count", renewed.into()),
+                ("StorageFailures", "Count", storage_failures.into()),
+                ("RenewalFailures", "Count", renewal_failures.into()),
+            ],
+        )];
+
+        let not_after = certificate.map(|info| info.not_after).or_else(|| response.and_then(earliest_up_to_date));
+        if let Some(not_after) = not_after {
+            let days = (not_after - now_epoch_secs()) / SECONDS_PER_DAY;
+            for domain_name in domain_names {
+                documents.push(self.document(
+                    timestamp,
+                    &[("DomainName", domain_name)],
+                    &[("DaysUntilExpiry", "None", days.into())],
+                ));
+            }
+        }
+
+        documents
+    }
+
+    /// Returns an EMF document with the given dimensions and (name, unit, value) metrics.
+    fn document(&self, timestamp: u64, dimensions: &[(&str, &str)], metrics: &[(&str, &str, Value)]) -> Value {
+        let mut document = Map::new();
+        let dimension_names: Vec<&str> = dimensions.iter().map(|(name, _)| *name).collect();
+
+        document.insert(
+            "_aws".to_string(),
+            json!({
+                "Timestamp": timestamp,
+                "CloudWatchMetrics": [{
+                    "Namespace": self.namespace,
+                    "Dimensions": [dimension_names],
+                    "Metrics": metrics
+                        .iter()
+                        .map(|(name, unit, _)| json!({"Name": name, "Unit": unit}))
+                        .collect::<Vec<Value>>(),
+                }],
+            }),
+        );
+
+        for (name, value) in dimensions {
+            document.insert(name.to_string(), json!(value));
+        }
+
+        for (name, _, value) in metrics {
+            document.insert(name.to_string(), value.clone());
+        }
+
+        Value::Object(document)
+    }
+}
+
+/// Returns (issued, renewed): whether the plan stored a newly issued certificate, and whether it replaced one.
+fn issuance_counts(response: &CertificateResponse) -> (u32, u32) {
+    let mut stored = false;
+    let mut replaced = false;
+
+    for action in &response.plan {
+        if let ReconcileAction::Store {
+            replaces,
+            ..
+        } = action
+        {
+            stored = true;
+            replaced |= replaces.is_some();
+        }
+    }
+
+    match (stored, replaced) {
+        (false, _) => (0, 0),
+        (true, false) => (1, 0),
+        (true, true) => (0, 1),
+    }
+}
+
+fn storage_failures(response: &CertificateResponse) -> u32 {
+    response
+        .storage
+        .iter()
+        .filter(|result| matches!(result, CertificateStorageResult::Error(_)) || result.rolled_back())
+        .count() as u32
+}
+
+/// Returns the earliest expiration time of the certificates the storage targets already hold.
+fn earliest_up_to_date(response: &CertificateResponse) -> Option<i64> {
+    if let Some(audit) = &response.audit {
+        return audit.earliest_not_after();
+    }
+
+    response
+        .plan
+        .iter()
+        .filter_map(|action| match action {
+            ReconcileAction::UpToDate {
+                not_after,
+                ..
+            } => Some(*not_after),
+            _ => None,
+        })
+        .min()
+}
+
+fn now_epoch_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{MetricsEmitter, SECONDS_PER_DAY},
+        crate::{
+            events::{CertificateResponse, CertificateResponseStatus, Response},
+            reconcile::ReconcileAction,
+            utils::now_epoch_secs,
+        },
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_emf_documents() {
+        let emitter = MetricsEmitter {
+            namespace: "Certificates".to_string(),
+        };
+        let domain_names = vec!["example.com".to_string(), "www.example.com".to_string()];
+        let response = CertificateResponse {
+            finished: true,
+            status: CertificateResponseStatus::Skipped,
+            storage: vec![],
+            plan: vec![ReconcileAction::UpToDate {
+                storage_index: 0,
+                serial: "1234".to_string(),
+                not_after: now_epoch_secs() + 30 * SECONDS_PER_DAY + 60,
+            }],
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: None,
+            revocation: None,
+            readiness: None,
+            dry_run: None,
+            schedule: None,
+            task_status: None,
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: None,
+        };
+
+        let documents = emitter.documents(&domain_names, None, &Ok(Response::Certificate(response)), 1000);
+        assert_eq!(documents.len(), 3);
+        assert_eq!(documents[0]["_aws"]["Timestamp"], json!(1000));
+        assert_eq!(documents[0]["_aws"]["CloudWatchMetrics"][0]["Namespace"], json!("Certificates"));
+        assert_eq!(documents[0]["_aws"]["CloudWatchMetrics"][0]["Dimensions"], json!([[]]));
+        assert_eq!(documents[0]["CertificatesIssued"], json!(0));
+        assert_eq!(documents[0]["RenewalFailures"], json!(0));
+        assert_eq!(documents[2]["_aws"]["CloudWatchMetrics"][0]["Dimensions"], json!([["DomainName"]]));
+        assert_eq!(documents[2]["DomainName"], json!("www.example.com"));
+        assert_eq!(documents[2]["DaysUntilExpiry"], json!(30));
+
+        let documents = emitter.documents(&domain_names, None, &Err("ACME server unavailable".into()), 1000);
+        assert_eq!(documents.len(), 1);
+        assert_eq!(documents[0]["RenewalFailures"], json!(1));
+    }
+}
@@ -0,0 +1,280 @@
+use {
+    crate::{batch::CertificateBatchRequest, events::CertificateRequest},
+    serde::{self, Deserialize, Serialize},
+    serde_json::{Map, Value},
+};
+
+/// A request to rewrite a configuration written for an older version of the request format in the current format,
+/// so that EventBridge rules and other stored requests can be updated before deprecated fields are removed. In JSON:
+///
+///     {
+///         "Action": "MigrateConfig",
+///
+///         // The certificate request, batch request, or array of certificate requests to migrate.
+///         "Config": { ... }
+///     }
+///
+/// The response holds the migrated configuration and a warning for each change made:
+///
+///     {
+///         // The configuration in the current format.
+///         "Config": { ... },
+///
+///         // What was rewritten, and whether the migrated configuration still fails to parse.
+///         "Warnings": [str, ...]
+///     }
+///
+/// The deprecated fields are:
+///
+/// * `"DirectoryUrl"` in a certificate request, renamed to `"Directory"`.
+/// * A single storage target object in `"Storage"`, which becomes a list holding it.
+/// * `"Path"` in S3 storage, renamed to `"Prefix"`.
+/// * `"Pkcs12Password"` in S3 and File storage, renamed to `"KeystorePassword"`.
+///
+/// Requests using them are still accepted, with a warning logged for each.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrateConfigRequest {
+    #[serde(rename = "Action")]
+    pub action: MigrateConfigAction,
+
+    #[serde(rename = "Config")]
+    pub config: Value,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum MigrateConfigAction {
+    MigrateConfig,
+}
+
+/// The response to a MigrateConfigRequest.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MigrateConfigResponse {
+    #[serde(rename = "Config")]
+    pub config: Value,
+
+    #[serde(rename = "Warnings")]
+    pub warnings: Vec<String>,
+}
+
+/// A field that was renamed: the storage types it applies to (None for the certificate request itself), its old
+/// name, and its current name.
+struct Rename {
+    storage_types: Option<&'static [&'static str]>,
+    old: &'static str,
+    new: &'static str,
+}
+
+const RENAMES: &[Rename] = &[
+    Rename {
+        storage_types: None,
+        old: "DirectoryUrl",
+        new: "Directory",
+    },
+    Rename {
+        storage_types: Some(&["S3"]),
+        old: "Path",
+        new: "Prefix",
+    },
+    Rename {
+        storage_types: Some(&["S3", "File"]),
+        old: "Pkcs12Password",
+        new: "KeystorePassword",
+    },
+];
+
+/// Migrate a configuration as described in MigrateConfigRequest.
+pub fn migrate_config(config: Value) -> MigrateConfigResponse {
+    let (config, mut warnings) = migrate(config);
+
+    let parsed = match &config {
+        Value::Array(_) => serde_json::from_value::<Vec<CertificateRequest>>(config.clone()).err(),
+        _ if config.get("Certificates").is_some() => {
+            serde_json::from_value::<CertificateBatchRequest>(config.clone()).err()
+        }
+        _ => serde_json::from_value::<CertificateRequest>(config.clone()).err(),
+    };
+
+    if let Some(e) = parsed {
+        warnings.push(format!("The migrated configuration is not a valid request: {}", e));
+    }
+
+    MigrateConfigResponse {
+        config,
+        warnings,
+    }
+}
+
+/// Returns a warning for each deprecated field used by a certificate or batch request.
+pub fn deprecation_warnings(request: &Value) -> Vec<String> {
+    migrate(request.clone()).1
+}
+
+/// Rewrite the deprecated fields of a certificate request, batch request, or array of certificate requests.
+pub fn migrate(mut config: Value) -> (Value, Vec<String>) {
+    let mut warnings = Vec::new();
+    let is_batch = config.get("Certificates").is_some();
+
+    match &mut config {
+        Value::Array(requests) => {
+            for (i, request) in requests.iter_mut().enumerate() {
+                migrate_request(request, &format!("[{}].", i), &mut warnings);
+            }
+        }
+        Value::Object(batch) if is_batch => {
+            if let Some(Value::Array(requests)) = batch.get_mut("Certificates") {
+                for (i, request) in requests.iter_mut().enumerate() {
+                    migrate_request(request, &format!("Certificates[{}].", i), &mut warnings);
+                }
+            }
+        }
+        _ => migrate_request(&mut config, "", &mut warnings),
+    }
+
+    (config, warnings)
+}
+
+/// Rewrite the deprecated fields of a single certificate request. Warnings name fields relative to the
+/// configuration by prepending prefix.
+fn migrate_request(request: &mut Value, prefix: &str, warnings: &mut Vec<String>) {
+    let request = match request.as_object_mut() {
+        Some(request) => request,
+        None => return,
+    };
+
+    rename_fields(request, None, prefix, warnings);
+
+    if let Some(storage) = request.get_mut("Storage") {
+        if storage.is_object() {
+            *storage = Value::Array(vec![storage.take()]);
+            warnings.push(format!(
+                "{}Storage: a single storage target is deprecated; converted to a list holding it",
+                prefix
+            ));
+        }
+    }
+
+    if let Some(Value::Array(targets)) = request.get_mut("Storage") {
+        for (i, target) in targets.iter_mut().enumerate() {
+            if let Some(target) = target.as_object_mut() {
+                let storage_type = target.get("Type").and_then(Value::as_str).map(str::to_string);
+                let prefix = format!("{}Storage[{}].", prefix, i);
+                rename_fields(target, Some(storage_type.as_deref().unwrap_or_default()), &prefix, warnings);
+            }
+        }
+    }
+}
+
+/// Apply the renames for a certificate request (if storage_type is None) or a storage target of the given type.
+fn rename_fields(
+    fields: &mut Map<String, Value>,
+    storage_type: Option<&str>,
+    prefix: &str,
+    warnings: &mut Vec<String>,
+) {
+    for rename in RENAMES {
+        let applies = match (rename.storage_types, storage_type) {
+            (None, None) => true,
+            (Some(types), Some(storage_type)) => types.contains(&storage_type),
+            _ => false,
+        };
+
+        if !applies {
+            continue;
+        }
+
+        if let Some(value) = fields.remove(rename.old) {
+            if fields.contains_key(rename.new) {
+                warnings.push(format!(
+                    "{}{} is deprecated and was dropped since {} is also set",
+                    prefix, rename.old, rename.new
+                ));
+            } else {
+                warnings.push(format!("{}{} is deprecated; renamed to {}", prefix, rename.old, rename.new));
+                fields.insert(rename.new.to_string(), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{deprecation_warnings, migrate_config, MigrateConfigRequest},
+        crate::events::Request,
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_migrate_config_request() {
+        let request: Request =
+            serde_json::from_value(json!({"Action": "MigrateConfig", "Config": {"DomainNames": ["example.com"]}}))
+                .unwrap();
+        assert!(matches!(request, Request::MigrateConfig(_)));
+        assert!(serde_json::from_value::<MigrateConfigRequest>(json!({"Action": "Schema", "Config": {}})).is_err());
+    }
+
+    #[test]
+    fn test_migrate_config() {
+        let response = migrate_config(json!({
+            "DirectoryUrl": "https://acme-staging-v02.api.letsencrypt.org/directory",
+            "DomainNames": ["example.com"],
+            "Contacts": ["mailto:hello@example.com"],
+            "Authorization": {"Type": "DnsRoute53"},
+            "Storage": {"Type": "S3", "Bucket": "certs", "Path": "example.com/"},
+        }));
+
+        assert_eq!(
+            response.config,
+            json!({
+                "Directory": "https://acme-staging-v02.api.letsencrypt.org/directory",
+                "DomainNames": ["example.com"],
+                "Contacts": ["mailto:hello@example.com"],
+                "Authorization": {"Type": "DnsRoute53"},
+                "Storage": [{"Type": "S3", "Bucket": "certs", "Prefix": "example.com/"}],
+            })
+        );
+        assert_eq!(
+            response.warnings,
+            vec![
+                "DirectoryUrl is deprecated; renamed to Directory",
+                "Storage: a single storage target is deprecated; converted to a list holding it",
+                "Storage[0].Path is deprecated; renamed to Prefix",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_migrate_batch() {
+        let response = migrate_config(json!({
+            "Certificates": [{
+                "Directory": "https://acme-staging-v02.api.letsencrypt.org/directory",
+                "DomainNames": ["example.com"],
+                "Contacts": ["mailto:hello@example.com"],
+                "Authorization": {"Type": "DnsRoute53"},
+                "Storage": [
+                    {"Type": "SsmParameter", "Path": "/certs"},
+                    {"Type": "S3", "Bucket": "certs", "Path": "old/", "Prefix": "new/"},
+                ],
+            }],
+        }));
+
+        let storage = &response.config["Certificates"][0]["Storage"];
+        assert_eq!(storage[0], json!({"Type": "SsmParameter", "Path": "/certs"}));
+        assert_eq!(storage[1], json!({"Type": "S3", "Bucket": "certs", "Prefix": "new/"}));
+        assert_eq!(
+            response.warnings,
+            vec!["Certificates[0].Storage[1].Path is deprecated and was dropped since Prefix is also set"]
+        );
+
+        assert!(deprecation_warnings(&response.config).is_empty());
+    }
+
+    #[test]
+    fn test_migrate_invalid() {
+        let response = migrate_config(json!({"DomainNames": ["example.com"], "Storage": []}));
+        assert_eq!(response.warnings.len(), 1);
+        assert!(response.warnings[0].starts_with("The migrated configuration is not a valid request"));
+    }
+}
@@ -0,0 +1,912 @@
+use {
+    crate::{
+        constants::{
+            DEFAULT_EXPIRING_SOON_DAYS, ENV_NOTIFICATION_TOPIC_ARN, EVENT_DETAIL_TYPE_CERTIFICATE_NOTIFICATION,
+            EVENT_SOURCE_LIFECYCLE,
+        },
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        events::{CertificateResponseStatus, Response},
+        inventory::InventoryDiff,
+        lifecycle::event_bridge_client,
+        payload_encryption::PayloadEncryption,
+        reconcile::ReconcileAction,
+        secrets::{resolve_secret, validate_secret_reference, DefaultSecretStore},
+        storage::CertificateStorageResult,
+        trust_bundle::TrustBundle,
+        utils::{default_true, now_epoch_secs, CertificateInfo},
+        xray::aws_client,
+    },
+    async_trait::async_trait,
+    futures::future::join_all,
+    hyper::{header::CONTENT_TYPE, Body, Client as HyperClient, Method, Request as HyperRequest},
+    hyper_tls::HttpsConnector,
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    rusoto_core::Region,
+    rusoto_events::{EventBridge, PutEventsRequest, PutEventsRequestEntry},
+    rusoto_sns::{PublishInput, Sns, SnsClient},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::json,
+    std::{collections::HashMap, env::var},
+    url::Url,
+};
+
+/// Seconds in a day, for converting ExpiringSoonDays.
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Configuration for notifications sent after each run. If this is omitted from the request, notifications are
+/// sent to the topic in the `NotificationTopicArn` environment variable (if set) using the defaults below. In JSON:
+///
+///     {
+///         // The ARN of the SNS topic to publish to. This defaults to the NotificationTopicArn environment
+///         // variable.
+///         "SnsTopicArn": str,
+///
+///         // Whether to notify SnsTopicArn when a certificate is issued or copied successfully. Failures are
+///         // always notified. The default is true.
+///         "NotifyOnSuccess": bool,
+///
+///         // Whether to notify SnsTopicArn when all storage targets were already up to date. The default is
+///         // false.
+///         "NotifyOnNoChange": bool,
+///
+///         // Additional channels to deliver notifications to, each with its own event filter. See
+///         // NotificationChannel.
+///         "Channels": [{ ... }, ...],
+///
+///         // A run is reported with the ExpiringSoon event if the certificate in place expires within this many
+///         // days. The default is 14.
+///         "ExpiringSoonDays": int,
+///     }
+///
+/// Channels are delivered to concurrently. A channel that fails to deliver is reported in the response but doesn't
+/// affect the other channels or the result of the run.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct NotificationConfig {
+    #[serde(rename = "SnsTopicArn", default)]
+    pub sns_topic_arn: Option<String>,
+
+    #[serde(rename = "NotifyOnSuccess", default = "default_true")]
+    pub notify_on_success: bool,
+
+    #[serde(rename = "NotifyOnNoChange", default)]
+    pub notify_on_no_change: bool,
+
+    #[serde(rename = "Channels", default)]
+    pub channels: Vec<NotificationChannel>,
+
+    #[serde(rename = "ExpiringSoonDays", default = "default_expiring_soon_days")]
+    pub expiring_soon_days: i64,
+}
+
+const fn default_expiring_soon_days() -> i64 {
+    DEFAULT_EXPIRING_SOON_DAYS
+}
+
+/// A destination for notifications. In JSON:
+///
+///     {
+///         // The type of channel: "Sns", "Slack", "Webhook", or "EventBridge". The remaining keys depend on the
+///         // type; see SnsNotifier, SlackNotifier, WebhookNotifier, and EventBridgeNotifier.
+///         "Type": str,
+///
+///         // The events to deliver to this channel: "Success", "Failure", "NoChange", and/or "ExpiringSoon". A
+///         // run is delivered if any of its events are listed. The default is ["Success", "Failure",
+///         // "ExpiringSoon"].
+///         "Events": [str, ...],
+///
+///         // If specified, encrypt the notification for the recipient before delivering it. The SNS subject is
+///         // replaced with a generic one, and EventBridge events are sent without resources. Slack channels can't
+///         // be encrypted. See PayloadEncryption.
+///         "Encryption": { ... },
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct NotificationChannel {
+    #[serde(flatten)]
+    pub target: NotificationTarget,
+
+    #[serde(rename = "Events", default = "default_events")]
+    pub events: Vec<NotificationEvent>,
+
+    #[serde(rename = "Encryption", default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<PayloadEncryption>,
+}
+
+impl NotificationChannel {
+    fn validate(&self) -> Result<(), LambdaError> {
+        let notifier = self.target.notifier();
+        notifier.validate()?;
+
+        if let Some(encryption) = &self.encryption {
+            if !notifier.supports_encryption() {
+                return Err(InvalidCertificateRequest::invalid_notification_configuration(format!(
+                    "{} does not support Encryption",
+                    notifier.describe()
+                )));
+            }
+
+            encryption.validate()?;
+        }
+
+        Ok(())
+    }
+
+    /// Encrypt the notification if configured and deliver it.
+    async fn deliver(&self, notification: &RunNotification) -> Result<(), LambdaError> {
+        let encrypted = match &self.encryption {
+            None => None,
+            Some(encryption) => Some(encryption.encrypt(serde_json::to_string(notification)?.as_bytes()).await?),
+        };
+
+        let payload = NotificationPayload {
+            notification,
+            encrypted,
+        };
+
+        self.target.notifier().deliver(&payload).await
+    }
+}
+
+fn default_events() -> Vec<NotificationEvent> {
+    vec![NotificationEvent::Success, NotificationEvent::Failure, NotificationEvent::ExpiringSoon]
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(tag = "Type")]
+pub enum NotificationTarget {
+    Sns(SnsNotifier),
+    Slack(SlackNotifier),
+    Webhook(WebhookNotifier),
+    EventBridge(EventBridgeNotifier),
+}
+
+impl NotificationTarget {
+    fn notifier(&self) -> &(dyn Notifier + Send + Sync) {
+        match self {
+            Self::Sns(notifier) => notifier,
+            Self::Slack(notifier) => notifier,
+            Self::Webhook(notifier) => notifier,
+            Self::EventBridge(notifier) => notifier,
+        }
+    }
+}
+
+/// The kinds of event a channel can filter on.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum NotificationEvent {
+    /// A certificate was written to at least one storage target and none failed.
+    Success,
+
+    /// The run failed or at least one storage target could not be written.
+    Failure,
+
+    /// All storage targets were already up to date.
+    NoChange,
+
+    /// The certificate in place expires within ExpiringSoonDays.
+    ExpiringSoon,
+}
+
+/// The outcome of delivering a notification to one channel. In JSON:
+///
+///     {
+///         // A description of the channel, e.g. "Sns arn:aws:sns:us-west-2:123456789012:certs".
+///         "Channel": str,
+///
+///         // Whether the notification was delivered.
+///         "Delivered": bool,
+///
+///         // If delivery failed, the reason.
+///         "Error": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct NotificationDelivery {
+    #[serde(rename = "Channel")]
+    pub channel: String,
+
+    #[serde(rename = "Delivered")]
+    pub delivered: bool,
+
+    #[serde(rename = "Error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// A channel that notifications can be delivered to.
+#[async_trait]
+pub trait Notifier {
+    /// A description of the channel for logs and delivery results.
+    fn describe(&self) -> String;
+
+    /// Check the channel's configuration.
+    fn validate(&self) -> Result<(), LambdaError>;
+
+    /// Indicates whether the channel can deliver encrypted payloads.
+    fn supports_encryption(&self) -> bool {
+        true
+    }
+
+    /// Deliver a notification.
+    async fn deliver(&self, payload: &NotificationPayload<'_>) -> Result<(), LambdaError>;
+}
+
+/// A notification as delivered to a channel: either the notification itself or, if the channel is encrypted, an
+/// EncryptedPayload that only the recipient can read.
+pub struct NotificationPayload<'a> {
+    pub notification: &'a RunNotification,
+    pub encrypted: Option<String>,
+}
+
+impl<'a> NotificationPayload<'a> {
+    /// The body to deliver: the encrypted payload if encrypted, otherwise the notification as JSON.
+    fn body(&self) -> Result<String, LambdaError> {
+        match &self.encrypted {
+            Some(encrypted) => Ok(encrypted.clone()),
+            None => Ok(serde_json::to_string(self.notification)?),
+        }
+    }
+
+    /// A subject line for the notification. This doesn't reveal the domain names if the payload is encrypted.
+    fn subject(&self) -> String {
+        match &self.encrypted {
+            Some(_) => "Certificate notification (encrypted)".to_string(),
+            None => self.notification.subject(),
+        }
+    }
+
+    /// The ARNs the notification relates to. These are omitted if the payload is encrypted.
+    fn resources(&self) -> Vec<String> {
+        match &self.encrypted {
+            Some(_) => vec![],
+            None => self.notification.storage_results.iter().flat_map(|result| result.arns()).collect(),
+        }
+    }
+}
+
+impl NotificationConfig {
+    /// Returns the notification configuration from the request, falling back to the environment. The SnsTopicArn
+    /// setting (or environment variable) is added as a channel. Returns None if no channels are configured.
+    pub fn resolve(config: Option<NotificationConfig>) -> Result<Option<Self>, LambdaError> {
+        let env_topic_arn = var(ENV_NOTIFICATION_TOPIC_ARN).ok().filter(|arn| !arn.is_empty());
+        let mut config = match config {
+            Some(config) => config,
+            None => Self {
+                sns_topic_arn: None,
+                notify_on_success: true,
+                notify_on_no_change: false,
+                channels: vec![],
+                expiring_soon_days: DEFAULT_EXPIRING_SOON_DAYS,
+            },
+        };
+
+        if config.sns_topic_arn.is_none() {
+            config.sns_topic_arn = env_topic_arn;
+        }
+
+        if let Some(topic_arn) = &config.sns_topic_arn {
+            let mut events = vec![NotificationEvent::Failure, NotificationEvent::ExpiringSoon];
+            if config.notify_on_success {
+                events.push(NotificationEvent::Success);
+            }
+            if config.notify_on_no_change {
+                events.push(NotificationEvent::NoChange);
+            }
+
+            config.channels.insert(
+                0,
+                NotificationChannel {
+                    target: NotificationTarget::Sns(SnsNotifier {
+                        topic_arn: topic_arn.clone(),
+                    }),
+                    events,
+                    encryption: None,
+                },
+            );
+        }
+
+        if config.expiring_soon_days < 0 {
+            return Err(InvalidCertificateRequest::invalid_notification_configuration(
+                "ExpiringSoonDays cannot be negative",
+            ));
+        }
+
+        for channel in &config.channels {
+            channel.validate()?;
+        }
+
+        if config.channels.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(config))
+        }
+    }
+
+    /// Deliver a notification describing the result of a run to each channel whose filter matches. Channels are
+    /// delivered to concurrently; failures are logged and reported in the results but otherwise ignored so they
+    /// don't mask the result of the run itself.
+    pub async fn notify(
+        &self,
+        domain_names: &[String],
+        certificate: Option<&CertificateInfo>,
+        result: &Result<Response, LambdaError>,
+    ) -> Vec<NotificationDelivery> {
+        let notification = RunNotification::new(domain_names, certificate, result, self.expiring_soon_days);
+
+        let deliveries = self
+            .channels
+            .iter()
+            .filter(|channel| channel.events.iter().any(|event| notification.events.contains(event)))
+            .map(|channel| {
+                let notification = &notification;
+                async move {
+                    let description = channel.target.notifier().describe();
+                    info!("Delivering {:?} notification to {}", notification.outcome, description);
+                    match channel.deliver(notification).await {
+                        Ok(()) => NotificationDelivery {
+                            channel: description,
+                            delivered: true,
+                            error: None,
+                        },
+                        Err(e) => {
+                            error!("Failed to deliver notification to {}: {:#}", description, e);
+                            NotificationDelivery {
+                                channel: description,
+                                delivered: false,
+                                error: Some(format!("{:#}", e)),
+                            }
+                        }
+                    }
+                }
+            });
+
+        join_all(deliveries).await
+    }
+}
+
+/// Publishes notifications to an SNS topic as JSON. In JSON:
+///
+///     {
+///         "Type": "Sns",
+///
+///         // The ARN of the topic to publish to.
+///         "TopicArn": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SnsNotifier {
+    #[serde(rename = "TopicArn")]
+    pub topic_arn: String,
+}
+
+#[async_trait]
+impl Notifier for SnsNotifier {
+    fn describe(&self) -> String {
+        format!("Sns {}", self.topic_arn)
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        if self.topic_arn.starts_with("arn:") {
+            Ok(())
+        } else {
+            Err(InvalidCertificateRequest::invalid_notification_configuration(format!(
+                "Invalid SNS topic ARN: {}",
+                self.topic_arn
+            )))
+        }
+    }
+
+    async fn deliver(&self, payload: &NotificationPayload<'_>) -> Result<(), LambdaError> {
+        let sns = SnsClient::new_with_client(aws_client(), Region::default());
+        let publish_input = PublishInput {
+            topic_arn: Some(self.topic_arn.clone()),
+            subject: Some(payload.subject()),
+            message: payload.body()?,
+            ..Default::default()
+        };
+
+        match sns.publish(publish_input).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Failed to publish to SNS topic {}: {:#}", self.topic_arn, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+/// Posts a human-readable summary to a Slack incoming webhook. In JSON:
+///
+///     {
+///         "Type": "Slack",
+///
+///         // The incoming webhook URL. This must be an https URL.
+///         "WebhookUrl": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SlackNotifier {
+    #[serde(rename = "WebhookUrl")]
+    pub webhook_url: String,
+}
+
+#[async_trait]
+impl Notifier for SlackNotifier {
+    fn describe(&self) -> String {
+        // The webhook URL is a credential; only show the host.
+        match Url::parse(&self.webhook_url) {
+            Ok(url) => format!("Slack {}", url.host_str().unwrap_or_default()),
+            Err(_) => "Slack".to_string(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        validate_https_url(&self.webhook_url)
+    }
+
+    fn supports_encryption(&self) -> bool {
+        // Slack messages are read by people, so an encrypted payload would be useless.
+        false
+    }
+
+    async fn deliver(&self, payload: &NotificationPayload<'_>) -> Result<(), LambdaError> {
+        let body = json!({ "text": payload.notification.summary() });
+        post_json(&self.webhook_url, &HashMap::new(), None, serde_json::to_string(&body)?).await
+    }
+}
+
+/// Posts the notification as JSON to an HTTPS endpoint. In JSON:
+///
+///     {
+///         "Type": "Webhook",
+///
+///         // The URL to post to. This must be an https URL.
+///         "Url": str,
+///
+///         // Additional headers to send, e.g. for authentication.
+///         "Headers": {str: str, ...},
+///
+///         // Additional headers whose values are read from secrets when the notification is sent, e.g.
+///         // {"Authorization": "vault:secret/data/webhooks#authorization"}. Each value is the name or ARN of a
+///         // Secrets Manager secret or a secret reference to another backend; see SecretBackend. This keeps
+///         // tokens out of the request, which is recorded in the inventory.
+///         "HeaderSecrets": {str: str, ...},
+///
+///         // Additional CA certificates to trust for this endpoint, e.g. if it uses a certificate from a
+///         // private CA. See TrustBundle.
+///         "TrustBundle": { ... },
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct WebhookNotifier {
+    #[serde(rename = "Url")]
+    pub url: String,
+
+    #[serde(rename = "Headers", default)]
+    pub headers: HashMap<String, String>,
+
+    #[serde(rename = "HeaderSecrets", default, skip_serializing_if = "HashMap::is_empty")]
+    pub header_secrets: HashMap<String, String>,
+
+    #[serde(rename = "TrustBundle", default, skip_serializing_if = "Option::is_none")]
+    pub trust_bundle: Option<TrustBundle>,
+}
+
+#[async_trait]
+impl Notifier for WebhookNotifier {
+    fn describe(&self) -> String {
+        match Url::parse(&self.url) {
+            Ok(url) => format!("Webhook {}{}", url.host_str().unwrap_or_default(), url.path()),
+            Err(_) => "Webhook".to_string(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        if let Some(trust_bundle) = &self.trust_bundle {
+            trust_bundle.validate()?;
+        }
+
+        for (header, reference) in &self.header_secrets {
+            if let Err(e) = validate_secret_reference(reference, DefaultSecretStore::SecretsManager) {
+                return Err(InvalidCertificateRequest::invalid_notification_configuration(format!(
+                    "Invalid HeaderSecrets value for {}: {}",
+                    header, e
+                )));
+            }
+        }
+
+        validate_https_url(&self.url)
+    }
+
+    async fn deliver(&self, payload: &NotificationPayload<'_>) -> Result<(), LambdaError> {
+        let mut headers = self.headers.clone();
+        for (header, reference) in &self.header_secrets {
+            let value = resolve_secret(reference, DefaultSecretStore::SecretsManager).await?;
+            headers.insert(header.clone(), value.0);
+        }
+
+        post_json(&self.url, &headers, self.trust_bundle.as_ref(), payload.body()?).await
+    }
+}
+
+/// Sends the notification to an EventBridge event bus with the source "letsencrypt-certs-aws" and the detail type
+/// "CertificateNotification". In JSON:
+///
+///     {
+///         "Type": "EventBridge",
+///
+///         // The name or ARN of the event bus. The default is "default".
+///         "EventBusName": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct EventBridgeNotifier {
+    #[serde(rename = "EventBusName", default = "default_event_bus_name")]
+    pub event_bus_name: String,
+}
+
+fn default_event_bus_name() -> String {
+    "default".to_string()
+}
+
+#[async_trait]
+impl Notifier for EventBridgeNotifier {
+    fn describe(&self) -> String {
+        format!("EventBridge {}", self.event_bus_name)
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        if self.event_bus_name.is_empty() {
+            Err(InvalidCertificateRequest::invalid_notification_configuration("EventBusName cannot be empty"))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn deliver(&self, payload: &NotificationPayload<'_>) -> Result<(), LambdaError> {
+        let client = event_bridge_client();
+        let pe_request = PutEventsRequest {
+            entries: vec![PutEventsRequestEntry {
+                event_bus_name: Some(self.event_bus_name.clone()),
+                source: Some(EVENT_SOURCE_LIFECYCLE.to_string()),
+                detail_type: Some(EVENT_DETAIL_TYPE_CERTIFICATE_NOTIFICATION.to_string()),
+                detail: Some(payload.body()?),
+                resources: Some(payload.resources()),
+                ..Default::default()
+            }],
+        };
+
+        let response = client.put_events(pe_request).await?;
+        match response.failed_entry_count {
+            Some(n_failed) if n_failed > 0 => Err(CertificateRequestError::unexpected_aws_response(format!(
+                "Notification was rejected by event bus {}",
+                self.event_bus_name
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn validate_https_url(url: &str) -> Result<(), LambdaError> {
+    match Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "https" => Ok(()),
+        _ => Err(InvalidCertificateRequest::invalid_notification_configuration(format!(
+            "Webhook URLs must be valid https URLs: {}",
+            url
+        ))),
+    }
+}
+
+/// POST a JSON body to a URL, failing if the response is not a 2xx. If a trust bundle is given, its certificates
+/// are trusted in addition to the system trust store.
+async fn post_json(
+    url: &str,
+    headers: &HashMap<String, String>,
+    trust_bundle: Option<&TrustBundle>,
+    body: String,
+) -> Result<(), LambdaError> {
+    let connector = match trust_bundle {
+        Some(trust_bundle) => trust_bundle.https_connector().await?,
+        None => HttpsConnector::new(),
+    };
+    let client = HyperClient::builder().build::<_, Body>(connector);
+    let mut request = HyperRequest::builder().method(Method::POST).uri(url).header(CONTENT_TYPE, "application/json");
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value.as_str());
+    }
+
+    let response = client.request(request.body(Body::from(body))?).await?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(CertificateRequestError::notification_rejected(format!("HTTP {}", response.status())))
+    }
+}
+
+/// The overall outcome of a run.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum RunOutcome {
+    /// A certificate was written to at least one storage target and none failed.
+    Updated,
+
+    /// All storage targets were already up to date.
+    NoChange,
+
+    /// The run failed or at least one storage target could not be written.
+    Failed,
+}
+
+/// The message published after each run. In JSON:
+///
+///     {
+///         // "Updated", "NoChange", or "Failed".
+///         "Outcome": str,
+///
+///         // The events the run matched, used to filter channels: "Success", "Failure", "NoChange", and/or
+///         // "ExpiringSoon".
+///         "Events": [str, ...],
+///
+///         // The domain names on the certificate.
+///         "DomainNames": [str, ...],
+///
+///         // The serial number and expiration time (seconds since the Unix epoch) of the certificate that was
+///         // written or, if nothing was written, the earliest-expiring certificate already in place.
+///         "Serial": str,
+///         "NotAfter": int,
+///
+///         // The per-storage results. See CertificateStorageResult.
+///         "StorageResults": [],
+///
+///         // The errors encountered during the run.
+///         "Errors": [str, ...],
+///
+///         // What changed since the certificate recorded by the previous run, if known. See InventoryDiff.
+///         "Diff": { ... },
+///
+///         // The time of the run in seconds since the Unix epoch.
+///         "Timestamp": int,
+///     }
+#[derive(Debug, Deserialize, Serialize)]
+pub struct RunNotification {
+    #[serde(rename = "Outcome")]
+    pub outcome: RunOutcome,
+
+    #[serde(rename = "Events", default)]
+    pub events: Vec<NotificationEvent>,
+
+    #[serde(rename = "DomainNames")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "Serial", default)]
+    pub serial: Option<String>,
+
+    #[serde(rename = "NotAfter", default)]
+    pub not_after: Option<i64>,
+
+    #[serde(rename = "StorageResults", default)]
+    pub storage_results: Vec<CertificateStorageResult>,
+
+    #[serde(rename = "Errors", default)]
+    pub errors: Vec<String>,
+
+    #[serde(rename = "Diff", default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<InventoryDiff>,
+
+    #[serde(rename = "Timestamp")]
+    pub timestamp: i64,
+}
+
+impl RunNotification {
+    pub fn new(
+        domain_names: &[String],
+        certificate: Option<&CertificateInfo>,
+        result: &Result<Response, LambdaError>,
+        expiring_soon_days: i64,
+    ) -> Self {
+        let mut notification = Self {
+            outcome: RunOutcome::Failed,
+            events: vec![NotificationEvent::Failure],
+            domain_names: domain_names.to_vec(),
+            serial: certificate.map(|info| info.serial.clone()),
+            not_after: certificate.map(|info| info.not_after),
+            storage_results: vec![],
+            errors: vec![],
+            diff: None,
+            timestamp: now_epoch_secs(),
+        };
+
+        let response = match result {
+            Err(e) => {
+                notification.errors.push(format!("{:#}", e));
+                return notification;
+            }
+            Ok(Response::Certificate(response)) => response,
+            Ok(_) => return notification,
+        };
+
+        for storage_result in &response.storage {
+            if let CertificateStorageResult::Error(e) = storage_result {
+                notification.errors.push(e.clone());
+            }
+        }
+
+        notification.storage_results = response.storage.clone();
+        notification.diff = response.diff.clone();
+
+        if let Some(readiness) = &response.readiness {
+            notification.errors.extend(readiness.problems());
+        }
+
+        if let Some(audit) = &response.audit {
+            notification.errors.extend(audit.problems());
+            notification.not_after = audit.earliest_not_after();
+        }
+
+        // If nothing was written, report the certificate already in place.
+        if certificate.is_none() {
+            let earliest = response
+                .plan
+                .iter()
+                .filter_map(|action| match action {
+                    ReconcileAction::UpToDate {
+                        serial,
+                        not_after,
+                        ..
+                    } => Some((serial, *not_after)),
+                    _ => None,
+                })
+                .min_by_key(|(_, not_after)| *not_after);
+
+            if let Some((serial, not_after)) = earliest {
+                notification.serial = Some(serial.clone());
+                notification.not_after = Some(not_after);
+            }
+        }
+
+        notification.outcome = match response.status {
+            CertificateResponseStatus::Skipped => RunOutcome::NoChange,
+            CertificateResponseStatus::Success if response.storage.is_empty() => RunOutcome::NoChange,
+            CertificateResponseStatus::Success => RunOutcome::Updated,
+            _ => RunOutcome::Failed,
+        };
+
+        notification.events = vec![match notification.outcome {
+            RunOutcome::Updated => NotificationEvent::Success,
+            RunOutcome::NoChange => NotificationEvent::NoChange,
+            RunOutcome::Failed => NotificationEvent::Failure,
+        }];
+
+        if let Some(not_after) = notification.not_after {
+            if not_after - notification.timestamp < expiring_soon_days * SECONDS_PER_DAY {
+                notification.events.push(NotificationEvent::ExpiringSoon);
+            }
+        }
+
+        notification
+    }
+
+    /// A one-line human-readable summary of the run, for chat channels.
+    fn summary(&self) -> String {
+        let mut summary = format!("{} (serial {})", self.subject(), self.serial.as_deref().unwrap_or("unknown"));
+        if let Some(not_after) = self.not_after {
+            let days = (not_after - self.timestamp) / SECONDS_PER_DAY;
+            summary.push_str(&format!(", expires in {} days", days));
+        }
+
+        for error in &self.errors {
+            summary.push_str(&format!("\n• {}", error));
+        }
+
+        summary
+    }
+
+    fn subject(&self) -> String {
+        let outcome = match self.outcome {
+            RunOutcome::Updated => "updated",
+            RunOutcome::NoChange => "unchanged",
+            RunOutcome::Failed => "FAILED",
+        };
+
+        // SNS subjects are limited to 100 characters.
+        let mut subject = format!("Certificate {} for {}", outcome, self.domain_names.join(" "));
+        if subject.len() > 100 {
+            let mut end = 97;
+            while !subject.is_char_boundary(end) {
+                end -= 1;
+            }
+            subject.truncate(end);
+            subject.push_str("...");
+        }
+
+        subject
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{NotificationConfig, NotificationEvent, NotificationTarget, RunNotification, RunOutcome},
+        crate::{
+            errors::CertificateRequestError,
+            events::{CertificateResponse, CertificateResponseStatus, Response},
+        },
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_resolve_channels() {
+        let config: NotificationConfig = serde_json::from_value(json!({
+            "SnsTopicArn": "arn:aws:sns:us-west-2:123456789012:certs",
+            "NotifyOnSuccess": false,
+            "Channels": [
+                {"Type": "Slack", "WebhookUrl": "https://hooks.slack.com/services/T0/B0/secret"},
+                {"Type": "EventBridge", "Events": ["Failure"]},
+            ],
+        }))
+        .unwrap();
+
+        let config = NotificationConfig::resolve(Some(config)).unwrap().unwrap();
+        assert_eq!(config.channels.len(), 3);
+        assert!(matches!(config.channels[0].target, NotificationTarget::Sns(_)));
+        assert_eq!(config.channels[0].events, vec![NotificationEvent::Failure, NotificationEvent::ExpiringSoon]);
+        assert_eq!(
+            config.channels[1].events,
+            vec![NotificationEvent::Success, NotificationEvent::Failure, NotificationEvent::ExpiringSoon]
+        );
+        assert_eq!(config.channels[1].target.notifier().describe(), "Slack hooks.slack.com");
+
+        let config: NotificationConfig = serde_json::from_value(json!({
+            "Channels": [{"Type": "Webhook", "Url": "http://example.com/hook"}],
+        }))
+        .unwrap();
+        assert!(NotificationConfig::resolve(Some(config)).is_err());
+
+        let config: NotificationConfig = serde_json::from_value(json!({
+            "Channels": [{
+                "Type": "Webhook",
+                "Url": "https://example.com/hook",
+                "HeaderSecrets": {"Authorization": "ssm:/Webhooks/Token"},
+            }],
+        }))
+        .unwrap();
+        assert!(NotificationConfig::resolve(Some(config)).is_ok());
+
+        let config: NotificationConfig = serde_json::from_value(json!({
+            "Channels": [{
+                "Type": "Webhook",
+                "Url": "https://example.com/hook",
+                "HeaderSecrets": {"Authorization": "keychain:webhook-token"},
+            }],
+        }))
+        .unwrap();
+        assert!(NotificationConfig::resolve(Some(config)).is_err());
+    }
+
+    #[test]
+    fn test_run_notification_events() {
+        let result = Err(CertificateRequestError::order_failed().into());
+        let notification = RunNotification::new(&["example.com".to_string()], None, &result, 14);
+        assert_eq!(notification.events, vec![NotificationEvent::Failure]);
+        assert!(notification.summary().contains("FAILED"));
+        assert!(notification.summary().contains("Order failed"));
+
+        let result = Ok(Response::Certificate(CertificateResponse {
+            finished: true,
+            status: CertificateResponseStatus::Skipped,
+            storage: vec![],
+            plan: vec![],
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: None,
+            revocation: None,
+            readiness: None,
+            dry_run: None,
+            schedule: None,
+            task_status: None,
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: None,
+        }));
+        let notification = RunNotification::new(&["example.com".to_string()], None, &result, 14);
+        assert_eq!(notification.outcome, RunOutcome::NoChange);
+        assert_eq!(notification.events, vec![NotificationEvent::NoChange]);
+    }
+}
@@ -0,0 +1,545 @@
+use {
+    crate::{
+        assume_role::AssumeRole,
+        audit::{days_remaining, expiry_status, AuditStatus},
+        constants::{ACM_ALL_KEY_TYPES, ACM_STATUS_EXPIRED, ACM_STATUS_ISSUED},
+        errors::InvalidCertificateRequest,
+        reconcile::RENEWAL_THRESHOLD_DAYS,
+        throttle,
+        utils::now_epoch_secs,
+        xray::aws_client,
+    },
+    futures::stream::{self, StreamExt},
+    lambda_runtime::Error as LambdaError,
+    log::{error, info, warn},
+    rusoto_acm::{Acm, AcmClient, DescribeCertificateRequest, Filters, ListCertificatesRequest},
+    rusoto_core::{Client, Region},
+    rusoto_organizations::{Account, ListAccountsRequest, Organizations, OrganizationsClient},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::str::FromStr,
+};
+
+/// The role assumed in each account when the request doesn't name one.
+const DEFAULT_AUDIT_ROLE_NAME: &str = "CertificateAudit";
+
+/// The number of accounts audited at once when the request doesn't say.
+const DEFAULT_AUDIT_MAX_CONCURRENCY: usize = 8;
+
+/// Organizations only reports accounts in this state; suspended accounts can't be audited.
+const ORGANIZATIONS_ACCOUNT_ACTIVE: &str = "ACTIVE";
+
+/// An audit of the ACM certificates in every account of an AWS Organization, for central oversight of certificate
+/// expiry. This must be run from the organization's management account (or a delegated administrator). The accounts
+/// are enumerated through Organizations, a role with the same name is assumed in each (so it can be deployed to
+/// every account with a StackSet), and the certificates in each region are checked against the threshold. Nothing is
+/// issued or written. In JSON:
+///
+///     {
+///         // This must be "OrganizationAudit".
+///         "Action": "OrganizationAudit",
+///
+///         // The name of the role to assume in each account. It needs acm:ListCertificates and
+///         // acm:DescribeCertificate, and must trust this function. The default is "CertificateAudit".
+///         "RoleName": str,
+///
+///         // The external id required by the role's trust policy, if any.
+///         "ExternalId": str,
+///
+///         // The regions to audit in each account. The default is the function's region.
+///         "Regions": [str, ...],
+///
+///         // Certificates expiring within this many days are reported as "Expiring". The default is 30.
+///         "ThresholdDays": int,
+///
+///         // The number of accounts to audit at once. The default is 8.
+///         "MaxConcurrency": int,
+///     }
+///
+/// The response is an OrganizationAuditReport.
+#[derive(Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrganizationAuditRequest {
+    #[serde(rename = "Action")]
+    pub action: OrganizationAuditAction,
+
+    #[serde(rename = "RoleName", default = "default_audit_role_name")]
+    pub role_name: String,
+
+    #[serde(rename = "ExternalId", default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+
+    #[serde(rename = "Regions", default)]
+    pub regions: Vec<String>,
+
+    #[serde(rename = "ThresholdDays", default = "default_audit_threshold_days")]
+    pub threshold_days: i64,
+
+    #[serde(rename = "MaxConcurrency", default = "default_audit_max_concurrency")]
+    pub max_concurrency: usize,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum OrganizationAuditAction {
+    OrganizationAudit,
+}
+
+fn default_audit_role_name() -> String {
+    DEFAULT_AUDIT_ROLE_NAME.to_string()
+}
+
+fn default_audit_threshold_days() -> i64 {
+    RENEWAL_THRESHOLD_DAYS
+}
+
+fn default_audit_max_concurrency() -> usize {
+    DEFAULT_AUDIT_MAX_CONCURRENCY
+}
+
+/// An ACM certificate found by an organization audit. In JSON:
+///
+///     {
+///         // The region and ARN of the certificate.
+///         "Region": str,
+///         "CertificateArn": str,
+///
+///         // The certificate's primary domain name.
+///         "DomainName": str,
+///
+///         // How the certificate is managed: "AMAZON_ISSUED" certificates are renewed by ACM, while "IMPORTED"
+///         // ones (including those written by this function) must be renewed by whatever imported them.
+///         "Type": str,
+///
+///         // Whether the certificate is attached to any AWS resource.
+///         "InUse": bool,
+///
+///         // The expiration time (seconds since the Unix epoch) and the whole days remaining until then.
+///         "NotAfter": int,
+///         "DaysRemaining": int,
+///
+///         // "Valid", "Expiring", or "Expired".
+///         "Status": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct AcmCertificateAudit {
+    #[serde(rename = "Region")]
+    pub region: String,
+
+    #[serde(rename = "CertificateArn")]
+    pub certificate_arn: String,
+
+    #[serde(rename = "DomainName")]
+    pub domain_name: String,
+
+    #[serde(rename = "Type")]
+    pub type_: String,
+
+    #[serde(rename = "InUse")]
+    pub in_use: bool,
+
+    #[serde(rename = "NotAfter")]
+    pub not_after: i64,
+
+    #[serde(rename = "DaysRemaining")]
+    pub days_remaining: i64,
+
+    #[serde(rename = "Status")]
+    pub status: AuditStatus,
+}
+
+/// The audit of one account. In JSON:
+///
+///     {
+///         "AccountId": str,
+///         "AccountName": str,
+///
+///         // The certificates found, in every audited region. See AcmCertificateAudit.
+///         "Certificates": [{ ... }, ...],
+///
+///         // Why the account (or a region in it) could not be audited, e.g. because the role couldn't be assumed.
+///         "Errors": [str, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct AccountAudit {
+    #[serde(rename = "AccountId")]
+    pub account_id: String,
+
+    #[serde(rename = "AccountName", default)]
+    pub account_name: String,
+
+    #[serde(rename = "Certificates", default)]
+    pub certificates: Vec<AcmCertificateAudit>,
+
+    #[serde(rename = "Errors", default, skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+/// Totals across an organization audit. In JSON:
+///
+///     {
+///         "Accounts": int,
+///         "AccountsWithErrors": int,
+///         "Certificates": int,
+///         "Expiring": int,
+///         "Expired": int,
+///     }
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct OrganizationAuditSummary {
+    #[serde(rename = "Accounts")]
+    pub accounts: usize,
+
+    #[serde(rename = "AccountsWithErrors")]
+    pub accounts_with_errors: usize,
+
+    #[serde(rename = "Certificates")]
+    pub certificates: usize,
+
+    #[serde(rename = "Expiring")]
+    pub expiring: usize,
+
+    #[serde(rename = "Expired")]
+    pub expired: usize,
+}
+
+/// The response to an OrganizationAuditRequest. In JSON:
+///
+///     {
+///         // Certificates expiring within this many days are reported as "Expiring".
+///         "ThresholdDays": int,
+///
+///         // Totals across the organization. See OrganizationAuditSummary.
+///         "Summary": { ... },
+///
+///         // Each account, ordered by account id. See AccountAudit.
+///         "Accounts": [{ ... }, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct OrganizationAuditReport {
+    #[serde(rename = "ThresholdDays")]
+    pub threshold_days: i64,
+
+    #[serde(rename = "Summary")]
+    pub summary: OrganizationAuditSummary,
+
+    #[serde(rename = "Accounts")]
+    pub accounts: Vec<AccountAudit>,
+}
+
+impl OrganizationAuditReport {
+    fn new(threshold_days: i64, mut accounts: Vec<AccountAudit>) -> Self {
+        accounts.sort_by(|a, b| a.account_id.cmp(&b.account_id));
+
+        let mut summary = OrganizationAuditSummary {
+            accounts: accounts.len(),
+            ..Default::default()
+        };
+
+        for account in &accounts {
+            if !account.errors.is_empty() {
+                summary.accounts_with_errors += 1;
+            }
+
+            for certificate in &account.certificates {
+                summary.certificates += 1;
+                match certificate.status {
+                    AuditStatus::Expiring => summary.expiring += 1,
+                    AuditStatus::Expired => summary.expired += 1,
+                    _ => (),
+                }
+            }
+        }
+
+        Self {
+            threshold_days,
+            summary,
+            accounts,
+        }
+    }
+}
+
+impl OrganizationAuditRequest {
+    /// Check the request, returning the regions to audit.
+    fn validate(&self) -> Result<Vec<Region>, LambdaError> {
+        if self.role_name.is_empty()
+            || !self.role_name.chars().all(|c| c.is_ascii_alphanumeric() || "+=,.@_-".contains(c))
+        {
+            return Err(InvalidCertificateRequest::invalid_organization_audit(format!(
+                "Invalid RoleName: {}",
+                self.role_name
+            )));
+        }
+
+        if self.threshold_days < 1 {
+            return Err(InvalidCertificateRequest::invalid_organization_audit("ThresholdDays must be at least 1"));
+        }
+
+        if self.max_concurrency == 0 {
+            return Err(InvalidCertificateRequest::invalid_organization_audit("MaxConcurrency must be at least 1"));
+        }
+
+        if self.regions.is_empty() {
+            return Ok(vec![Region::default()]);
+        }
+
+        let mut regions = Vec::with_capacity(self.regions.len());
+        for region in &self.regions {
+            match Region::from_str(region) {
+                Ok(region) => regions.push(region),
+                Err(_) => {
+                    return Err(InvalidCertificateRequest::invalid_organization_audit(format!(
+                        "Invalid region: {}",
+                        region
+                    )))
+                }
+            }
+        }
+
+        Ok(regions)
+    }
+
+    /// Returns the role to assume in an account.
+    fn role_for(&self, account: &Account) -> AssumeRole {
+        let account_id = account.id.as_deref().unwrap_or_default();
+        AssumeRole {
+            role_arn: Some(format!("arn:{}:iam::{}:role/{}", partition(account), account_id, self.role_name)),
+            external_id: self.external_id.clone(),
+        }
+    }
+}
+
+/// Audit every active account in the organization. Accounts that can't be audited are reported with their errors
+/// rather than failing the whole audit.
+pub async fn handle_organization_audit(req: OrganizationAuditRequest) -> Result<OrganizationAuditReport, LambdaError> {
+    let regions = req.validate()?;
+    let accounts = list_accounts().await?;
+    info!("Auditing {} account(s) in {} region(s)", accounts.len(), regions.len());
+
+    let now = now_epoch_secs();
+    let audits: Vec<AccountAudit> = stream::iter(accounts)
+        .map(|account| audit_account(&req, account, &regions, now))
+        .buffer_unordered(req.max_concurrency)
+        .collect()
+        .await;
+
+    let report = OrganizationAuditReport::new(req.threshold_days, audits);
+    let summary = &report.summary;
+    if summary.expiring > 0 || summary.expired > 0 || summary.accounts_with_errors > 0 {
+        warn!(
+            "Organization audit: {} certificate(s) expiring within {} days, {} expired, {} account(s) not audited",
+            summary.expiring, req.threshold_days, summary.expired, summary.accounts_with_errors
+        );
+    }
+
+    Ok(report)
+}
+
+/// List the active accounts in the organization.
+async fn list_accounts() -> Result<Vec<Account>, LambdaError> {
+    // Organizations is a global service served from us-east-1.
+    let organizations = OrganizationsClient::new_with_client(aws_client(), Region::UsEast1);
+    let mut la_request = ListAccountsRequest::default();
+    let mut accounts = Vec::new();
+
+    loop {
+        let response = match throttle::call("ListAccounts", || organizations.list_accounts(la_request.clone())).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to list organization accounts: {}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        accounts.extend(
+            response
+                .accounts
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|account| account.status.as_deref() == Some(ORGANIZATIONS_ACCOUNT_ACTIVE)),
+        );
+
+        match response.next_token {
+            None => break,
+            Some(token) => la_request.next_token = Some(token),
+        }
+    }
+
+    Ok(accounts)
+}
+
+async fn audit_account(req: &OrganizationAuditRequest, account: Account, regions: &[Region], now: i64) -> AccountAudit {
+    let mut audit = AccountAudit {
+        account_id: account.id.clone().unwrap_or_default(),
+        account_name: account.name.clone().unwrap_or_default(),
+        certificates: vec![],
+        errors: vec![],
+    };
+
+    let client = match req.role_for(&account).client() {
+        Ok(client) => client,
+        Err(e) => {
+            audit.errors.push(format!("{:#}", e));
+            return audit;
+        }
+    };
+
+    for region in regions {
+        match audit_region(&client, region, req.threshold_days, now).await {
+            Ok(certificates) => audit.certificates.extend(certificates),
+            Err(e) => {
+                error!("Failed to audit account {} in {}: {:#}", audit.account_id, region.name(), e);
+                audit.errors.push(format!("{}: {:#}", region.name(), e));
+            }
+        }
+    }
+
+    audit
+}
+
+/// List and describe the issued and expired certificates in one region of an account.
+async fn audit_region(
+    client: &Client,
+    region: &Region,
+    threshold_days: i64,
+    now: i64,
+) -> Result<Vec<AcmCertificateAudit>, LambdaError> {
+    let acm = AcmClient::new_with_client(client.clone(), region.clone());
+    // ListCertificates only returns RSA-2048 certificates unless other key types are requested explicitly.
+    let mut lc_request = ListCertificatesRequest {
+        certificate_statuses: Some(vec![ACM_STATUS_ISSUED.to_string(), ACM_STATUS_EXPIRED.to_string()]),
+        includes: Some(Filters {
+            key_types: Some(ACM_ALL_KEY_TYPES.iter().map(|kt| kt.to_string()).collect()),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let mut arns = Vec::new();
+
+    loop {
+        let response = throttle::call("ListCertificates", || acm.list_certificates(lc_request.clone())).await?;
+        arns.extend(
+            response.certificate_summary_list.unwrap_or_default().into_iter().filter_map(|s| s.certificate_arn),
+        );
+
+        match response.next_token {
+            None => break,
+            Some(token) => lc_request.next_token = Some(token),
+        }
+    }
+
+    let mut certificates = Vec::with_capacity(arns.len());
+    for certificate_arn in arns {
+        let dc_request = DescribeCertificateRequest {
+            certificate_arn: certificate_arn.clone(),
+        };
+        let detail = match throttle::call("DescribeCertificate", || acm.describe_certificate(dc_request.clone()))
+            .await?
+            .certificate
+        {
+            Some(detail) => detail,
+            None => continue,
+        };
+
+        let not_after = match detail.not_after {
+            Some(not_after) => not_after as i64,
+            None => continue,
+        };
+
+        certificates.push(AcmCertificateAudit {
+            region: region.name().to_string(),
+            certificate_arn,
+            domain_name: detail.domain_name.unwrap_or_default(),
+            type_: detail.type_.unwrap_or_default(),
+            in_use: !detail.in_use_by.unwrap_or_default().is_empty(),
+            not_after,
+            days_remaining: days_remaining(not_after, now),
+            status: expiry_status(not_after, threshold_days, now),
+        });
+    }
+
+    Ok(certificates)
+}
+
+/// Returns the partition an account is in, from its ARN.
+fn partition(account: &Account) -> &str {
+    account.arn.as_deref().and_then(|arn| arn.split(':').nth(1)).filter(|p| !p.is_empty()).unwrap_or("aws")
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{
+            AccountAudit, AcmCertificateAudit, OrganizationAuditReport, OrganizationAuditRequest,
+            OrganizationAuditSummary,
+        },
+        crate::{audit::AuditStatus, events::Request},
+        rusoto_organizations::Account,
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_organization_audit_request() {
+        let req: OrganizationAuditRequest =
+            serde_json::from_value(json!({"Action": "OrganizationAudit", "Regions": ["us-west-2"]})).unwrap();
+        assert_eq!(req.role_name, "CertificateAudit");
+        assert_eq!(req.threshold_days, 30);
+        assert_eq!(req.validate().unwrap().len(), 1);
+
+        let account = Account {
+            id: Some("123456789012".to_string()),
+            arn: Some("arn:aws-us-gov:organizations::111111111111:account/o-abc/123456789012".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(req.role_for(&account).role_arn.unwrap(), "arn:aws-us-gov:iam::123456789012:role/CertificateAudit");
+
+        let req: OrganizationAuditRequest =
+            serde_json::from_value(json!({"Action": "OrganizationAudit", "RoleName": "bad/name"})).unwrap();
+        assert!(req.validate().is_err());
+
+        assert!(serde_json::from_value::<OrganizationAuditRequest>(json!({"Action": "Issue"})).is_err());
+
+        let request: Request = serde_json::from_value(json!({"Action": "OrganizationAudit"})).unwrap();
+        assert!(matches!(request, Request::OrganizationAudit(_)));
+    }
+
+    #[test]
+    fn test_organization_audit_report() {
+        let certificate = |status| AcmCertificateAudit {
+            region: "us-west-2".to_string(),
+            certificate_arn: "arn:aws:acm:us-west-2:123456789012:certificate/1".to_string(),
+            domain_name: "www.example.com".to_string(),
+            type_: "IMPORTED".to_string(),
+            in_use: true,
+            not_after: 0,
+            days_remaining: 0,
+            status,
+        };
+        let account = |id: &str, certificates, errors| AccountAudit {
+            account_id: id.to_string(),
+            account_name: String::new(),
+            certificates,
+            errors,
+        };
+
+        let report = OrganizationAuditReport::new(
+            30,
+            vec![
+                account("2", vec![certificate(AuditStatus::Valid), certificate(AuditStatus::Expiring)], vec![]),
+                account("1", vec![certificate(AuditStatus::Expired)], vec![]),
+                account("3", vec![], vec!["AccessDenied".to_string()]),
+            ],
+        );
+
+        assert_eq!(report.accounts[0].account_id, "1");
+        assert_eq!(
+            report.summary,
+            OrganizationAuditSummary {
+                accounts: 3,
+                accounts_with_errors: 1,
+                certificates: 3,
+                expiring: 1,
+                expired: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1,233 @@
+use {
+    crate::{
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::error,
+    openssl::{
+        pkey::PKey,
+        rand::rand_bytes,
+        rsa::Padding,
+        symm::{encrypt_aead, Cipher},
+    },
+    rusoto_core::Region,
+    rusoto_kms::{GenerateDataKeyRequest, Kms, KmsClient},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+};
+
+/// The size of the AES-256-GCM nonce, in bytes.
+const NONCE_LEN: usize = 12;
+
+/// The size of the AES-256-GCM authentication tag, in bytes.
+const TAG_LEN: usize = 16;
+
+/// How to encrypt notification payloads for a channel, so systems the notification passes through can't read even
+/// the certificate metadata. Payloads are encrypted with a fresh AES-256-GCM data key, and the data key is encrypted
+/// for the recipient. In JSON:
+///
+///     {
+///         // "Kms" to encrypt the data key with a KMS key, or "PublicKey" to encrypt it with an RSA public key
+///         // (RSA-OAEP with SHA-1).
+///         "Type": str,
+///
+///         // For Kms, the id, ARN, or alias of the KMS key. The recipient decrypts the data key with kms:Decrypt.
+///         "KeyId": str,
+///
+///         // For PublicKey, the recipient's PEM-encoded RSA public key.
+///         "PublicKeyPem": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(tag = "Type")]
+pub enum PayloadEncryption {
+    Kms {
+        #[serde(rename = "KeyId")]
+        key_id: String,
+    },
+    PublicKey {
+        #[serde(rename = "PublicKeyPem")]
+        public_key_pem: String,
+    },
+}
+
+/// An encrypted payload, as delivered in place of the plaintext. In JSON:
+///
+///     {
+///         // "Kms" or "PublicKey".
+///         "Encryption": str,
+///
+///         // For Kms, the ARN of the KMS key used.
+///         "KeyId": str,
+///
+///         // The data key, encrypted for the recipient, base64-encoded.
+///         "EncryptedKey": str,
+///
+///         // The AES-256-GCM nonce, base64-encoded.
+///         "Nonce": str,
+///
+///         // The encrypted payload followed by the 16-byte GCM tag, base64-encoded.
+///         "Ciphertext": str,
+///     }
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EncryptedPayload {
+    #[serde(rename = "Encryption")]
+    pub encryption: String,
+
+    #[serde(rename = "KeyId", default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+
+    #[serde(rename = "EncryptedKey")]
+    pub encrypted_key: String,
+
+    #[serde(rename = "Nonce")]
+    pub nonce: String,
+
+    #[serde(rename = "Ciphertext")]
+    pub ciphertext: String,
+}
+
+impl PayloadEncryption {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        match self {
+            Self::Kms {
+                key_id,
+            } if key_id.is_empty() => {
+                Err(InvalidCertificateRequest::invalid_notification_configuration("Encryption KeyId cannot be empty"))
+            }
+            Self::Kms {
+                ..
+            } => Ok(()),
+            Self::PublicKey {
+                public_key_pem,
+            } => match PKey::public_key_from_pem(public_key_pem.as_bytes()) {
+                Ok(key) if key.rsa().is_ok() => Ok(()),
+                _ => Err(InvalidCertificateRequest::invalid_notification_configuration(
+                    "Encryption PublicKeyPem must be a PEM-encoded RSA public key",
+                )),
+            },
+        }
+    }
+
+    /// Encrypt a payload, returning the JSON-serialized EncryptedPayload.
+    pub async fn encrypt(&self, plaintext: &[u8]) -> Result<String, LambdaError> {
+        let (data_key, encrypted_key, key_id) = match self {
+            Self::Kms {
+                key_id,
+            } => {
+                let kms = KmsClient::new_with_client(aws_client(), Region::default());
+                let gdk_request = GenerateDataKeyRequest {
+                    key_id: key_id.clone(),
+                    key_spec: Some("AES_256".to_string()),
+                    ..Default::default()
+                };
+
+                let response = match kms.generate_data_key(gdk_request).await {
+                    Ok(response) => response,
+                    Err(e) => {
+                        error!("Failed to generate data key with KMS key {}: {:#}", key_id, e);
+                        return Err(Box::new(e));
+                    }
+                };
+
+                match (response.plaintext, response.ciphertext_blob) {
+                    (Some(plaintext), Some(ciphertext)) => (plaintext.to_vec(), ciphertext.to_vec(), response.key_id),
+                    _ => {
+                        return Err(CertificateRequestError::unexpected_aws_response(
+                            "GenerateDataKey did not return a data key",
+                        ))
+                    }
+                }
+            }
+            Self::PublicKey {
+                public_key_pem,
+            } => {
+                let rsa = PKey::public_key_from_pem(public_key_pem.as_bytes())?.rsa()?;
+                let mut data_key = vec![0u8; 32];
+                rand_bytes(&mut data_key)?;
+                let mut encrypted_key = vec![0u8; rsa.size() as usize];
+                let len = rsa.public_encrypt(&data_key, &mut encrypted_key, Padding::PKCS1_OAEP)?;
+                encrypted_key.truncate(len);
+                (data_key, encrypted_key, None)
+            }
+        };
+
+        let mut nonce = [0u8; NONCE_LEN];
+        rand_bytes(&mut nonce)?;
+        let mut tag = [0u8; TAG_LEN];
+        let mut ciphertext = encrypt_aead(Cipher::aes_256_gcm(), &data_key, Some(&nonce), &[], plaintext, &mut tag)?;
+        ciphertext.extend_from_slice(&tag);
+
+        let payload = EncryptedPayload {
+            encryption: self.type_name().to_string(),
+            key_id,
+            encrypted_key: base64::encode(encrypted_key),
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        };
+
+        Ok(serde_json::to_string(&payload)?)
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Kms {
+                ..
+            } => "Kms",
+            Self::PublicKey {
+                ..
+            } => "PublicKey",
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{EncryptedPayload, PayloadEncryption, NONCE_LEN, TAG_LEN},
+        openssl::{
+            pkey::PKey,
+            rsa::{Padding, Rsa},
+            symm::{decrypt_aead, Cipher},
+        },
+    };
+
+    #[tokio::test]
+    async fn test_public_key_round_trip() {
+        let rsa = Rsa::generate(2048).unwrap();
+        let public_key_pem = String::from_utf8(rsa.public_key_to_pem().unwrap()).unwrap();
+        let encryption = PayloadEncryption::PublicKey {
+            public_key_pem,
+        };
+        encryption.validate().unwrap();
+
+        let encrypted = encryption.encrypt(b"{\"DomainNames\":[\"secret.example.com\"]}").await.unwrap();
+        assert!(!encrypted.contains("secret.example.com"));
+
+        let payload: EncryptedPayload = serde_json::from_str(&encrypted).unwrap();
+        let encrypted_key = base64::decode(&payload.encrypted_key).unwrap();
+        let mut data_key = vec![0u8; rsa.size() as usize];
+        let len = rsa.private_decrypt(&encrypted_key, &mut data_key, Padding::PKCS1_OAEP).unwrap();
+        data_key.truncate(len);
+
+        let nonce = base64::decode(&payload.nonce).unwrap();
+        let ciphertext = base64::decode(&payload.ciphertext).unwrap();
+        let (ciphertext, tag) = ciphertext.split_at(ciphertext.len() - TAG_LEN);
+        let plaintext = decrypt_aead(Cipher::aes_256_gcm(), &data_key, Some(&nonce), &[], ciphertext, tag).unwrap();
+        assert_eq!(plaintext, b"{\"DomainNames\":[\"secret.example.com\"]}");
+    }
+
+    #[test]
+    fn test_validate_encryption() {
+        let invalid = PayloadEncryption::PublicKey {
+            public_key_pem: "not a key".to_string(),
+        };
+        assert!(invalid.validate().is_err());
+
+        let empty = PayloadEncryption::Kms {
+            key_id: String::new(),
+        };
+        assert!(empty.validate().is_err());
+    }
+}
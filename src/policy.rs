@@ -0,0 +1,456 @@
+use {
+    crate::utils::ssm_acme_parameter_path,
+    serde_json::{json, Value},
+    std::collections::{BTreeMap, BTreeSet},
+};
+
+/// Actions needed to write each storage type, beyond any bucket, table, role, or key named in its configuration.
+/// The storage type is also the id of the statement granting them.
+const STORAGE_ACTIONS: &[(&str, &[&str])] = &[
+    ("Acm", &["acm:AddTagsToCertificate", "acm:DescribeCertificate", "acm:ImportCertificate", "acm:ListCertificates"]),
+    (
+        "ApiGateway",
+        &[
+            "acm:DescribeCertificate",
+            "acm:ImportCertificate",
+            "acm:ListCertificates",
+            "apigateway:GET",
+            "apigateway:PATCH",
+        ],
+    ),
+    (
+        "CloudFront",
+        &[
+            "acm:DescribeCertificate",
+            "acm:ImportCertificate",
+            "acm:ListCertificates",
+            "cloudfront:GetDistributionConfig",
+            "cloudfront:UpdateDistribution",
+        ],
+    ),
+    (
+        "IamServerCertificate",
+        &[
+            "iam:DeleteServerCertificate",
+            "iam:GetServerCertificate",
+            "iam:ListServerCertificates",
+            "iam:UploadServerCertificate",
+        ],
+    ),
+    (
+        "LoadBalancer",
+        &[
+            "acm:DescribeCertificate",
+            "acm:ImportCertificate",
+            "acm:ListCertificates",
+            "elasticloadbalancing:AddListenerCertificates",
+            "elasticloadbalancing:DescribeListenerCertificates",
+            "elasticloadbalancing:DescribeListeners",
+            "elasticloadbalancing:DescribeSSLPolicies",
+            "elasticloadbalancing:ModifyListener",
+            "elasticloadbalancing:RemoveListenerCertificates",
+        ],
+    ),
+    ("SsmParameter", &["ssm:AddTagsToResource", "ssm:GetParameter", "ssm:ListTagsForResource", "ssm:PutParameter"]),
+    (
+        "SecretsManager",
+        &[
+            "secretsmanager:CreateSecret",
+            "secretsmanager:DescribeSecret",
+            "secretsmanager:GetSecretValue",
+            "secretsmanager:PutSecretValue",
+            "secretsmanager:TagResource",
+        ],
+    ),
+    ("Windows", &["ssm:GetCommandInvocation", "ssm:PutParameter", "ssm:SendCommand"]),
+    ("Kubernetes", &["eks:DescribeCluster"]),
+];
+
+/// Builds an IAM policy granting the permissions the function needs to run a request, so that deployments can
+/// grant least privilege instead of broad managed policies. Statements are grouped by the service they apply to.
+///
+/// Storage types registered at runtime are not known here; their permissions have to be added separately.
+#[derive(Debug, Default)]
+pub struct PolicyBuilder {
+    statements: BTreeMap<String, (BTreeSet<String>, BTreeSet<String>)>,
+}
+
+impl PolicyBuilder {
+    /// Returns the policy for a certificate request, a batch request, or an array of certificate requests.
+    pub fn for_request(request: &Value) -> Self {
+        let mut builder = Self::default();
+        builder.allow(
+            "AcmeAccount",
+            &["ssm:GetParameter", "ssm:PutParameter"],
+            &[format!("arn:aws:ssm:*:*:parameter{}/*", ssm_acme_parameter_path())],
+        );
+
+        let requests = match request {
+            Value::Array(requests) => requests.iter().collect(),
+            _ => match request.get("Certificates").and_then(Value::as_array) {
+                Some(requests) => {
+                    if request.get("EventBusName").is_some() {
+                        builder.allow("Events", &["events:PutEvents"], &[event_bus_arn(request)]);
+                    }
+                    requests.iter().collect()
+                }
+                None => vec![request],
+            },
+        };
+
+        for request in requests {
+            builder.add_request(request);
+        }
+
+        builder
+    }
+
+    /// Allow the actions on the resources, under a statement with the given id.
+    pub fn allow<S: AsRef<str>>(&mut self, sid: &str, actions: &[&str], resources: &[S]) {
+        let (statement_actions, statement_resources) = self.statements.entry(sid.to_string()).or_default();
+        statement_actions.extend(actions.iter().map(|action| action.to_string()));
+        statement_resources.extend(resources.iter().map(|resource| resource.as_ref().to_string()));
+    }
+
+    /// Returns the policy's statements. A statement granting an action on every resource only lists "*".
+    pub fn statements(&self) -> Vec<Value> {
+        self.statements
+            .iter()
+            .map(|(sid, (actions, resources))| {
+                let resources: Vec<&String> = if resources.contains("*") {
+                    resources.iter().filter(|resource| *resource == "*").collect()
+                } else {
+                    resources.iter().collect()
+                };
+
+                json!({
+                    "Sid": sid,
+                    "Effect": "Allow",
+                    "Action": actions,
+                    "Resource": resources,
+                })
+            })
+            .collect()
+    }
+
+    fn add_request(&mut self, request: &Value) {
+        let str_field = |value: &Value, name: &str| value.get(name).and_then(Value::as_str).map(str::to_string);
+
+        if let Some(authorization) = request.get("Authorization") {
+            self.add_authorization(authorization);
+        }
+
+        let targets: Vec<&Value> = match request.get("Storage") {
+            Some(Value::Array(targets)) => targets.iter().collect(),
+            Some(target) => vec![target],
+            None => vec![],
+        };
+
+        for target in targets {
+            self.add_storage(target);
+        }
+
+        if let Some(notifications) = request.get("Notifications") {
+            if let Some(topic_arn) = str_field(notifications, "SnsTopicArn") {
+                self.allow("Notifications", &["sns:Publish"], &[topic_arn]);
+            }
+
+            for channel in notifications.get("Channels").and_then(Value::as_array).into_iter().flatten() {
+                match channel.get("Type").and_then(Value::as_str) {
+                    Some("Sns") => self.allow(
+                        "Notifications",
+                        &["sns:Publish"],
+                        &[str_field(channel, "TopicArn").unwrap_or_default()],
+                    ),
+                    Some("EventBridge") => self.allow("Events", &["events:PutEvents"], &[event_bus_arn(channel)]),
+                    _ => (),
+                }
+            }
+        }
+
+        if request.get("EventBusName").is_some() {
+            self.allow("Events", &["events:PutEvents"], &[event_bus_arn(request)]);
+        }
+    }
+
+    fn add_authorization(&mut self, authorization: &Value) {
+        let str_field = |name: &str| authorization.get(name).and_then(Value::as_str);
+
+        match str_field("Type") {
+            Some("DnsRoute53") => {
+                self.allow("Route53Changes", &["route53:GetChange", "route53:ListHostedZones"], &["*"]);
+                let zone = match str_field("HostedZoneId") {
+                    Some(zone) => format!("arn:aws:route53:::hostedzone/{}", zone.trim_start_matches("/hostedzone/")),
+                    None => "arn:aws:route53:::hostedzone/*".to_string(),
+                };
+                self.allow(
+                    "Route53",
+                    &["route53:ChangeResourceRecordSets", "route53:GetHostedZone", "route53:ListResourceRecordSets"],
+                    &[zone],
+                );
+            }
+            Some("DnsCloudflare") => self.allow_secret(str_field("ApiTokenSecretId")),
+            Some("DnsGoogle") => self.allow_secret(str_field("CredentialsSecretId")),
+            Some("HttpS3") => {
+                let bucket = str_field("Bucket").unwrap_or("*");
+                self.allow("S3", &["s3:GetBucketLocation"], &[format!("arn:aws:s3:::{}", bucket)]);
+                self.allow(
+                    "S3",
+                    &["s3:DeleteObject", "s3:PutObject"],
+                    &[format!("arn:aws:s3:::{}/{}*", bucket, str_field("Prefix").unwrap_or_default())],
+                );
+                self.allow_kms_key(str_field("KmsKeyId"));
+            }
+            Some("HttpApiGateway") | Some("TlsAlpn") => {
+                self.allow("Ssm", &["ssm:DeleteParameter", "ssm:PutParameter"], &["*"]);
+                self.allow_kms_key(str_field("KmsKeyId"));
+            }
+            _ => (),
+        }
+    }
+
+    fn add_storage(&mut self, target: &Value) {
+        let str_field = |name: &str| target.get(name).and_then(Value::as_str);
+        let storage_type = str_field("Type").unwrap_or_default();
+        // Transactional writes delete their staged copies, and roll back by deleting components that didn't exist.
+        let transactional = str_field("WriteStrategy") == Some("Transactional");
+
+        if let Some((sid, actions)) = STORAGE_ACTIONS.iter().find(|(name, _)| *name == storage_type) {
+            self.allow(sid, actions, &["*"]);
+        }
+
+        match storage_type {
+            "S3" => {
+                // Replicas expand {Region} into one bucket per region.
+                let bucket = str_field("Bucket").unwrap_or("*").replace("{Region}", "*");
+                self.allow(
+                    "S3",
+                    &[
+                        "s3:GetBucketAcl",
+                        "s3:GetBucketLocation",
+                        "s3:GetBucketPolicyStatus",
+                        "s3:GetBucketPublicAccessBlock",
+                    ],
+                    &[format!("arn:aws:s3:::{}", bucket)],
+                );
+                // Objects are only confined to Prefix if their keys aren't customized.
+                let objects = if target.get("KeyTemplate").is_some() || target.get("Keys").is_some() {
+                    format!("arn:aws:s3:::{}/*", bucket)
+                } else {
+                    format!("arn:aws:s3:::{}/{}*", bucket, str_field("Prefix").unwrap_or_default())
+                };
+                self.allow("S3", &["s3:GetObject", "s3:PutObject"], &[&objects]);
+
+                let tagged =
+                    target.get("Tags").and_then(Value::as_object).map(|tags| !tags.is_empty()).unwrap_or(false)
+                        || target.get("TagCertificateInfo").and_then(Value::as_bool).unwrap_or(false);
+                if tagged {
+                    self.allow("S3", &["s3:PutObjectTagging"], &[&objects]);
+                }
+
+                match target.get("Retention").and_then(|retention| retention.get("Mode")).and_then(Value::as_str) {
+                    Some("Versioning") => {
+                        let bucket_arn = format!("arn:aws:s3:::{}", bucket);
+                        self.allow("S3", &["s3:GetBucketVersioning", "s3:ListBucketVersions"], &[bucket_arn]);
+                        self.allow("S3", &["s3:DeleteObjectVersion"], &[&objects]);
+                    }
+                    Some("TimestampedKeys") => {
+                        self.allow("S3", &["s3:ListBucket"], &[format!("arn:aws:s3:::{}", bucket)]);
+                        self.allow("S3", &["s3:DeleteObject"], &[&objects]);
+                    }
+                    _ => (),
+                }
+                if transactional {
+                    // Rolling back restores each object's previous tags.
+                    self.allow("S3", &["s3:DeleteObject", "s3:GetObjectTagging", "s3:PutObjectTagging"], &[&objects]);
+                }
+                self.allow_kms_key(str_field("ComponentKmsKey"));
+                self.allow_kms_key(str_field("PrivateKeyKmsKey"));
+                self.allow_secret(str_field("KeystorePassword"));
+            }
+            "DynamoDb" => self.allow(
+                "DynamoDb",
+                &["dynamodb:PutItem", "dynamodb:Query", "dynamodb:UpdateItem"],
+                &[format!("arn:aws:dynamodb:*:*:table/{}", str_field("TableName").unwrap_or("*"))],
+            ),
+            "File" => self.allow_secret(str_field("KeystorePassword")),
+            "SsmParameter" => {
+                if transactional {
+                    self.allow("SsmParameter", &["ssm:DeleteParameter", "ssm:DeleteParameters"], &["*"]);
+                }
+                self.allow_kms_key(str_field("KmsKeyId"));
+            }
+            "SecretsManager" | "Windows" => self.allow_kms_key(str_field("KmsKeyId")),
+            _ => (),
+        }
+
+        if let Some(role_arn) = str_field("RoleArn") {
+            self.allow("AssumeRole", &["sts:AssumeRole"], &[role_arn]);
+        }
+    }
+
+    /// Allow reading a secret reference (see secrets::SecretBackend). Only Secrets Manager and SSM references need
+    /// IAM permissions; the others are left alone.
+    fn allow_secret(&mut self, secret: Option<&str>) {
+        let secret = match secret {
+            Some(secret) => secret,
+            None => return,
+        };
+
+        if secret.starts_with("arn:aws:ssm:") {
+            self.allow("SecretParameters", &["ssm:GetParameter"], &[secret]);
+        } else if secret.starts_with("arn:") {
+            self.allow("Secrets", &["secretsmanager:GetSecretValue"], &[secret]);
+        } else if let Some(name) = secret.strip_prefix("ssm:") {
+            let name = name.trim_start_matches('/');
+            self.allow("SecretParameters", &["ssm:GetParameter"], &[format!("arn:aws:ssm:*:*:parameter/{}", name)]);
+        } else if !secret.contains(':') || secret.starts_with("secretsmanager:") {
+            // Secrets Manager appends a random suffix to secret ARNs.
+            let name = secret.trim_start_matches("secretsmanager:");
+            self.allow(
+                "Secrets",
+                &["secretsmanager:GetSecretValue"],
+                &[format!("arn:aws:secretsmanager:*:*:secret:{}-*", name)],
+            );
+        }
+    }
+
+    /// Allow encrypting and decrypting with a KMS key given by ARN, id, or alias.
+    fn allow_kms_key(&mut self, key: Option<&str>) {
+        let key = match key {
+            Some(key) if key.starts_with("arn:") => key.to_string(),
+            Some(key) if key.starts_with("alias/") => format!("arn:aws:kms:*:*:{}", key),
+            Some(key) => format!("arn:aws:kms:*:*:key/{}", key),
+            None => return,
+        };
+
+        self.allow("Kms", &["kms:Decrypt", "kms:Encrypt", "kms:GenerateDataKey"], &[key]);
+    }
+}
+
+/// The ARN of the event bus named by an object's EventBusName, or the default bus.
+fn event_bus_arn(value: &Value) -> String {
+    match value.get("EventBusName").and_then(Value::as_str) {
+        Some(name) if name.starts_with("arn:") => name.to_string(),
+        Some(name) => format!("arn:aws:events:*:*:event-bus/{}", name),
+        None => "arn:aws:events:*:*:event-bus/default".to_string(),
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::PolicyBuilder,
+        serde_json::{json, Value},
+    };
+
+    fn document(request: &Value) -> Value {
+        json!({
+            "Version": "2012-10-17",
+            "Statement": PolicyBuilder::for_request(request).statements(),
+        })
+    }
+
+    #[test]
+    fn test_request_policy() {
+        let request = json!({
+            "DomainNames": ["example.com"],
+            "Authorization": {"Type": "DnsRoute53", "HostedZoneId": "/hostedzone/Z123"},
+            "Storage": [
+                {"Type": "Acm"},
+                {
+                    "Type": "S3",
+                    "Bucket": "certs-{Region}",
+                    "Prefix": "example/",
+                    "PrivateKeyKmsKey": "alias/certs",
+                    "TagCertificateInfo": true,
+                },
+                {"Type": "DynamoDb", "TableName": "Certificates", "RoleArn": "arn:aws:iam::123456789012:role/certs"},
+            ],
+            "Notifications": {"SnsTopicArn": "arn:aws:sns:us-east-1:123456789012:certs"},
+        });
+
+        let policy = document(&request);
+        let statement = |sid: &str| {
+            policy["Statement"].as_array().unwrap().iter().find(|statement| statement["Sid"] == json!(sid)).cloned()
+        };
+
+        assert_eq!(statement("Acm").unwrap()["Resource"], json!(["*"]));
+        assert!(statement("Acm").unwrap()["Action"].as_array().unwrap().contains(&json!("acm:ImportCertificate")));
+
+        let route53 = statement("Route53").unwrap();
+        assert_eq!(route53["Resource"], json!(["arn:aws:route53:::hostedzone/Z123"]));
+        assert_eq!(statement("Route53Changes").unwrap()["Resource"], json!(["*"]));
+        assert!(route53["Action"].as_array().unwrap().contains(&json!("route53:ChangeResourceRecordSets")));
+
+        let s3 = statement("S3").unwrap();
+        assert_eq!(s3["Resource"], json!(["arn:aws:s3:::certs-*", "arn:aws:s3:::certs-*/example/*"]));
+        assert!(s3["Action"].as_array().unwrap().contains(&json!("s3:PutObjectTagging")));
+
+        assert_eq!(statement("Kms").unwrap()["Resource"], json!(["arn:aws:kms:*:*:alias/certs"]));
+        assert_eq!(statement("DynamoDb").unwrap()["Resource"], json!(["arn:aws:dynamodb:*:*:table/Certificates"]));
+        assert_eq!(statement("AssumeRole").unwrap()["Resource"], json!(["arn:aws:iam::123456789012:role/certs"]));
+        assert_eq!(
+            statement("Notifications").unwrap()["Resource"],
+            json!(["arn:aws:sns:us-east-1:123456789012:certs"])
+        );
+        assert!(statement("AcmeAccount").is_some());
+        assert!(statement("Events").is_none());
+    }
+
+    #[test]
+    fn test_batch_policy() {
+        let batch = json!({
+            "Certificates": [
+                {
+                    "Storage": {"Type": "File", "Directory": "/certs", "KeystorePassword": "ssm:/certs/password"},
+                    "Authorization": {"Type": "DnsCloudflare", "ApiTokenSecretId": "cloudflare"},
+                },
+                {"Storage": [{"Type": "SecretsManager"}], "EventBusName": "certs"},
+            ],
+        });
+
+        let policy = document(&batch);
+        let sids: Vec<&str> = policy["Statement"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|statement| statement["Sid"].as_str().unwrap())
+            .collect();
+        assert_eq!(sids, vec!["AcmeAccount", "Events", "SecretParameters", "Secrets", "SecretsManager"]);
+        let statement =
+            |sid: &str| policy["Statement"].as_array().unwrap().iter().find(|s| s["Sid"] == json!(sid)).cloned();
+        assert_eq!(
+            statement("Secrets").unwrap()["Resource"],
+            json!(["arn:aws:secretsmanager:*:*:secret:cloudflare-*"])
+        );
+        assert_eq!(
+            statement("SecretParameters").unwrap()["Resource"],
+            json!(["arn:aws:ssm:*:*:parameter/certs/password"])
+        );
+        assert_eq!(statement("Events").unwrap()["Resource"], json!(["arn:aws:events:*:*:event-bus/certs"]));
+    }
+
+    #[test]
+    fn test_transactional_policy() {
+        let request = json!({
+            "Storage": [
+                {"Type": "S3", "Bucket": "certs", "Prefix": "live/", "WriteStrategy": "Transactional"},
+                {"Type": "SsmParameter", "Path": "/certs", "WriteStrategy": "Transactional"},
+            ],
+        });
+
+        let policy = document(&request);
+        let statement =
+            |sid: &str| policy["Statement"].as_array().unwrap().iter().find(|s| s["Sid"] == json!(sid)).cloned();
+        let actions = |sid: &str| statement(sid).unwrap()["Action"].clone();
+        assert!(actions("S3").as_array().unwrap().contains(&json!("s3:DeleteObject")));
+        assert!(actions("S3").as_array().unwrap().contains(&json!("s3:GetObjectTagging")));
+        assert!(actions("SsmParameter").as_array().unwrap().contains(&json!("ssm:DeleteParameters")));
+
+        let direct = json!({"Storage": {"Type": "SsmParameter", "Path": "/certs"}});
+        let policy = document(&direct);
+        assert!(!policy.to_string().contains("ssm:DeleteParameter"));
+    }
+}
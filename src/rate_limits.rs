@@ -0,0 +1,233 @@
+use {
+    crate::{
+        constants::{
+            DEFAULT_RATE_LIMIT_BACKOFF_SECONDS, ENV_RATE_LIMIT_RETRY_TARGET_ARN, RATE_LIMIT_RETRY_RULE_PREFIX,
+        },
+        errors::CertificateRequestError,
+        lifecycle::event_bridge_client,
+        utils::now_epoch_secs,
+    },
+    acme2::Error as AcmeError,
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    openssl::asn1::Asn1Time,
+    ring::digest::{digest, SHA256},
+    rusoto_events::{EventBridge, PutRuleRequest, PutTargetsRequest, Target},
+    serde_json::Value,
+    std::env::var,
+};
+
+/// The ACME problem type for rate limit errors (RFC 8555 section 6.7).
+const ACME_RATE_LIMITED: &str = "urn:ietf:params:acme:error:rateLimited";
+
+/// A rate limit reported by the ACME server.
+#[derive(Clone, Debug)]
+pub struct RateLimit {
+    /// The problem detail from the server, e.g. which limit was hit.
+    pub detail: String,
+
+    /// When the request can be retried, in seconds since the Unix epoch.
+    pub retry_after: i64,
+}
+
+impl RateLimit {
+    /// Returns the rate limit if the error is an ACME rateLimited problem.
+    ///
+    /// acme2 doesn't expose the Retry-After header, so the time is taken from the problem detail; Let's Encrypt
+    /// includes it as "retry after 2006-01-02 15:04:05 UTC". If the detail has no time, the retry is assumed to be
+    /// possible after DEFAULT_RATE_LIMIT_BACKOFF_SECONDS.
+    pub fn from_error(e: &LambdaError) -> Option<Self> {
+        let problem = match e.downcast_ref::<AcmeError>() {
+            Some(AcmeError::Server(problem)) if problem.r#type.as_deref() == Some(ACME_RATE_LIMITED) => problem,
+            _ => return None,
+        };
+
+        let detail = problem.detail.clone().unwrap_or_default();
+        let retry_after =
+            parse_retry_after(&detail).unwrap_or_else(|| now_epoch_secs() + DEFAULT_RATE_LIMIT_BACKOFF_SECONDS);
+
+        Some(Self {
+            detail,
+            retry_after,
+        })
+    }
+}
+
+/// Extract the time from "retry after YYYY-MM-DD HH:MM:SS UTC" (or an RFC 3339 timestamp) in a problem detail.
+fn parse_retry_after(detail: &str) -> Option<i64> {
+    let start = detail.to_ascii_lowercase().find("retry after ")? + "retry after ".len();
+    let timestamp: String = detail[start..].chars().take(19).filter(|c| c.is_ascii_digit()).collect();
+    if timestamp.len() != 14 {
+        return None;
+    }
+
+    let time = Asn1Time::from_str(&format!("{}Z", timestamp)).ok()?;
+    let diff = Asn1Time::from_unix(0).ok()?.diff(&time).ok()?;
+    Some(i64::from(diff.days) * 86400 + i64::from(diff.secs))
+}
+
+/// Format a time in seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+pub fn format_utc(epoch_secs: i64) -> String {
+    let (year, month, day, hour, minute, second) = utc_components(epoch_secs);
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second)
+}
+
+/// Split a time in seconds since the Unix epoch into UTC calendar components. This uses Howard Hinnant's
+/// civil_from_days algorithm.
+fn utc_components(epoch_secs: i64) -> (i64, i64, i64, i64, i64, i64) {
+    let days = epoch_secs.div_euclid(86400);
+    let secs = epoch_secs.rem_euclid(86400);
+
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {
+        mp + 3
+    } else {
+        mp - 9
+    };
+    let year = yoe + era * 400;
+    let year = if month <= 2 {
+        year + 1
+    } else {
+        year
+    };
+
+    (year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// Schedules a retry of a rate-limited request through a one-time EventBridge rule that invokes this function with
+/// the original request. This is enabled by setting the `RateLimitRetryTargetArn` environment variable to the ARN of
+/// the function; the function needs a resource policy allowing events.amazonaws.com to invoke it from rules named
+/// `letsencrypt-retry-*`.
+///
+/// Each set of domain names has a single retry rule, which is replaced if the request is rate limited again. The
+/// rule's schedule includes the year, so it fires only once.
+#[derive(Clone, Debug)]
+pub struct RateLimitRetryScheduler {
+    target_arn: String,
+}
+
+impl RateLimitRetryScheduler {
+    pub fn from_env() -> Option<Self> {
+        let target_arn = var(ENV_RATE_LIMIT_RETRY_TARGET_ARN).ok().filter(|arn| !arn.is_empty())?;
+        Some(Self {
+            target_arn,
+        })
+    }
+
+    /// Schedule the request to be retried at (or just after) the given time, returning the name of the rule.
+    pub async fn schedule(
+        &self,
+        domain_names: &[String],
+        retry_after: i64,
+        request: &Value,
+    ) -> Result<String, LambdaError> {
+        let rule_name = retry_rule_name(domain_names);
+        let description = format!("Retry certificate request for {} after rate limit", domain_names.join(" "));
+        let schedule_expression =
+            put_one_time_rule(&rule_name, description, retry_after, "Retry", &self.target_arn, request).await?;
+        info!("Scheduled retry with rule {} at {}", rule_name, schedule_expression);
+        Ok(rule_name)
+    }
+}
+
+/// Create or replace an EventBridge rule that fires once, at (or just after) the given time in seconds since the
+/// Unix epoch, invoking target_arn with the request as its input. Returns the rule's schedule expression.
+pub async fn put_one_time_rule(
+    rule_name: &str,
+    description: String,
+    at: i64,
+    target_id: &str,
+    target_arn: &str,
+    request: &Value,
+) -> Result<String, LambdaError> {
+    // Schedules have minute granularity; round up so the rule doesn't fire early.
+    let (year, month, day, hour, minute, _) = utc_components(at + 59);
+    let schedule_expression = format!("cron({} {} {} {} ? {})", minute, hour, day, month, year);
+
+    let client = event_bridge_client();
+    let pr_request = PutRuleRequest {
+        name: rule_name.to_string(),
+        description: Some(description),
+        schedule_expression: Some(schedule_expression.clone()),
+        state: Some("ENABLED".to_string()),
+        ..Default::default()
+    };
+
+    if let Err(e) = client.put_rule(pr_request).await {
+        error!("Failed to create rule {}: {:#}", rule_name, e);
+        return Err(Box::new(e));
+    }
+
+    let pt_request = PutTargetsRequest {
+        rule: rule_name.to_string(),
+        targets: vec![Target {
+            id: target_id.to_string(),
+            arn: target_arn.to_string(),
+            input: Some(serde_json::to_string(request)?),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    match client.put_targets(pt_request).await {
+        Ok(response) if response.failed_entry_count.unwrap_or(0) > 0 => Err(
+            CertificateRequestError::unexpected_aws_response(format!("Failed to set the target of rule {}", rule_name)),
+        ),
+        Ok(_) => Ok(schedule_expression),
+        Err(e) => {
+            error!("Failed to set the target of rule {}: {:#}", rule_name, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+/// The name of the retry rule for a set of domain names.
+fn retry_rule_name(domain_names: &[String]) -> String {
+    domain_rule_name(RATE_LIMIT_RETRY_RULE_PREFIX, domain_names)
+}
+
+/// The name of a rule for a set of domain names: the prefix followed by a hash of the names, so the same names (in
+/// any order or case) always map to the same rule.
+pub fn domain_rule_name(prefix: &str, domain_names: &[String]) -> String {
+    let mut sorted = domain_names.iter().map(|dn| dn.to_lowercase()).collect::<Vec<String>>();
+    sorted.sort();
+    sorted.dedup();
+
+    let hash = digest(&SHA256, sorted.join(",").as_bytes());
+    let hash_hex: String = hash.as_ref()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}{}", prefix, hash_hex)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::{format_utc, parse_retry_after, retry_rule_name};
+
+    #[test]
+    fn test_parse_retry_after() {
+        let detail = "too many certificates (5) already issued for this exact set of domains in the last 168h0m0s, \
+                      retry after 2024-01-03 15:04:05 UTC: see https://letsencrypt.org/docs/rate-limits/";
+        let retry_after = parse_retry_after(detail).unwrap();
+        assert_eq!(retry_after, 1704294245);
+        assert_eq!(format_utc(retry_after), "2024-01-03T15:04:05Z");
+
+        assert_eq!(parse_retry_after("Retry after 2024-01-03T15:04:05Z"), Some(1704294245));
+        assert_eq!(parse_retry_after("too many new orders recently"), None);
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc(951782400), "2000-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn test_retry_rule_name() {
+        let name = retry_rule_name(&["www.example.com".to_string(), "example.com".to_string()]);
+        assert_eq!(name, retry_rule_name(&["example.com".to_string(), "WWW.example.com".to_string()]));
+        assert!(name.starts_with("letsencrypt-retry-"));
+        assert!(name.len() <= 64);
+    }
+}
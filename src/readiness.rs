@@ -0,0 +1,359 @@
+use {
+    crate::{
+        constants::{CHALLENGE_TYPE_HTTP01, CHALLENGE_TYPE_TLS_ALPN01},
+        utils::registrable_domain,
+    },
+    futures::future::join_all,
+    log::{info, warn},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::{net::IpAddr, time::Duration},
+    tokio::{net::TcpStream, time::timeout},
+    trust_dns_resolver::{
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        proto::{
+            rr::{RData, RecordType},
+            xfer::DnsRequestOptions,
+        },
+        TokioAsyncResolver,
+    },
+};
+
+/// How long to wait for a nameserver to answer or a port to accept a connection.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The result of one readiness check. In JSON:
+///
+///     {
+///         // "NsDelegation", "Soa", "Port80", or "Port443".
+///         "Check": str,
+///
+///         // Whether the check passed.
+///         "Passed": bool,
+///
+///         // What was found.
+///         "Detail": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ReadinessCheck {
+    #[serde(rename = "Check")]
+    pub check: String,
+
+    #[serde(rename = "Passed")]
+    pub passed: bool,
+
+    #[serde(rename = "Detail")]
+    pub detail: String,
+}
+
+impl ReadinessCheck {
+    fn new<S: Into<String>>(check: &str, passed: bool, detail: S) -> Self {
+        Self {
+            check: check.to_string(),
+            passed,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// The readiness of one domain name. In JSON:
+///
+///     {
+///         "DomainName": str,
+///
+///         // The zone holding the domain name and its nameservers, if the delegation could be found.
+///         "Zone": str,
+///         "NameServers": [str, ...],
+///
+///         // The checks run for the domain name. See ReadinessCheck.
+///         "Checks": [{ ... }, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct DomainReadiness {
+    #[serde(rename = "DomainName")]
+    pub domain_name: String,
+
+    #[serde(rename = "Zone", default, skip_serializing_if = "Option::is_none")]
+    pub zone: Option<String>,
+
+    #[serde(rename = "NameServers", default, skip_serializing_if = "Vec::is_empty")]
+    pub name_servers: Vec<String>,
+
+    #[serde(rename = "Checks")]
+    pub checks: Vec<ReadinessCheck>,
+}
+
+/// A pre-issuance report on whether each domain name's environment allows it to be validated. Most first-time
+/// failures are caused by broken delegations, unreachable nameservers, or firewalls rather than by this function,
+/// and these are much easier to diagnose before an order fails. For each domain name:
+///
+/// * NsDelegation: the zone holding the domain name is found by walking up from the domain name to its registrable
+///   domain, and each of its nameservers must resolve to an address.
+/// * Soa: each nameserver must answer an SOA query for the zone. Differing serial numbers are reported but don't
+///   fail the check, since they are normal while a change propagates.
+/// * Port80 (http-01) or Port443 (tls-alpn-01): the domain name must accept TCP connections on the port the CA
+///   validates through. Wildcards are skipped, since they can only be validated with dns-01.
+///
+/// The checks are made from where the function runs, which approximates the CA's view only if the function has
+/// direct internet access. In JSON:
+///
+///     {
+///         // The challenge type the checks were chosen for.
+///         "ChallengeType": str,
+///
+///         // The readiness of each domain name. See DomainReadiness.
+///         "Domains": [{ ... }, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ReadinessReport {
+    #[serde(rename = "ChallengeType")]
+    pub challenge_type: String,
+
+    #[serde(rename = "Domains")]
+    pub domains: Vec<DomainReadiness>,
+}
+
+impl ReadinessReport {
+    /// Check each domain name concurrently.
+    pub async fn check(domain_names: &[String], challenge_type: &str) -> Self {
+        let domains = join_all(domain_names.iter().map(|domain_name| check_domain(domain_name, challenge_type))).await;
+
+        Self {
+            challenge_type: challenge_type.to_string(),
+            domains,
+        }
+    }
+
+    /// Indicates whether every check passed.
+    pub fn ready(&self) -> bool {
+        self.domains.iter().all(|domain| domain.checks.iter().all(|check| check.passed))
+    }
+
+    /// Describe each failed check, for errors and notifications.
+    pub fn problems(&self) -> Vec<String> {
+        self.domains
+            .iter()
+            .flat_map(|domain| {
+                domain
+                    .checks
+                    .iter()
+                    .filter(|check| !check.passed)
+                    .map(move |check| format!("{}: {}: {}", domain.domain_name, check.check, check.detail))
+            })
+            .collect()
+    }
+}
+
+async fn check_domain(domain_name: &str, challenge_type: &str) -> DomainReadiness {
+    let mut readiness = DomainReadiness {
+        domain_name: domain_name.to_string(),
+        zone: None,
+        name_servers: vec![],
+        checks: vec![],
+    };
+
+    let resolver = match TokioAsyncResolver::tokio_from_system_conf() {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            readiness.checks.push(ReadinessCheck::new("NsDelegation", false, format!("No resolver available: {}", e)));
+            return readiness;
+        }
+    };
+
+    let base_name = domain_name.strip_prefix("*.").unwrap_or(domain_name);
+    let candidates = zone_candidates(domain_name);
+    let (zone, name_servers) = match find_zone(&resolver, &candidates).await {
+        Some(found) => found,
+        None => {
+            readiness.checks.push(ReadinessCheck::new(
+                "NsDelegation",
+                false,
+                format!(
+                    "No NS records found for {}; check that the domain is registered and delegated",
+                    candidates.last().map(String::as_str).unwrap_or(base_name)
+                ),
+            ));
+            return readiness;
+        }
+    };
+
+    info!("{} is in zone {} served by {}", domain_name, zone, name_servers.join(", "));
+    readiness.zone = Some(zone.clone());
+    readiness.name_servers = name_servers.clone();
+
+    let mut ns_ips: Vec<(String, IpAddr)> = Vec::new();
+    let mut lame = Vec::new();
+    for name_server in &name_servers {
+        match resolver.lookup_ip(name_server.as_str()).await.ok().and_then(|lookup| lookup.iter().next()) {
+            Some(ip) => ns_ips.push((name_server.clone(), ip)),
+            None => lame.push(name_server.clone()),
+        }
+    }
+
+    readiness.checks.push(if lame.is_empty() {
+        ReadinessCheck::new("NsDelegation", true, format!("{} is delegated to {}", zone, name_servers.join(", ")))
+    } else {
+        ReadinessCheck::new("NsDelegation", false, format!("Nameservers {} do not resolve", lame.join(", ")))
+    });
+
+    let soa_answers = join_all(ns_ips.iter().map(|(_, ip)| query_soa_serial(*ip, &zone))).await;
+    let mut serials: Vec<(&str, u32)> = Vec::new();
+    let mut failures = Vec::new();
+    for ((name_server, ip), answer) in ns_ips.iter().zip(soa_answers) {
+        match answer {
+            Ok(serial) => serials.push((name_server.as_str(), serial)),
+            Err(e) => failures.push(format!("{} ({}): {}", name_server, ip, e)),
+        }
+    }
+
+    readiness.checks.push(match serials.first() {
+        _ if !failures.is_empty() => {
+            ReadinessCheck::new("Soa", false, format!("No SOA answer from {}", failures.join("; ")))
+        }
+        None => ReadinessCheck::new("Soa", false, "No nameservers could be queried"),
+        Some((_, first)) if serials.iter().all(|(_, serial)| serial == first) => {
+            ReadinessCheck::new("Soa", true, format!("All nameservers answered with serial {}", first))
+        }
+        Some(_) => {
+            let serials: Vec<String> =
+                serials.iter().map(|(name_server, serial)| format!("{}={}", name_server, serial)).collect();
+            warn!("Nameservers for {} disagree on the SOA serial: {}", zone, serials.join(", "));
+            ReadinessCheck::new(
+                "Soa",
+                true,
+                format!("Nameservers answered with differing serials: {}", serials.join(", ")),
+            )
+        }
+    });
+
+    let port = match challenge_type {
+        CHALLENGE_TYPE_HTTP01 => Some(80),
+        CHALLENGE_TYPE_TLS_ALPN01 => Some(443),
+        _ => None,
+    };
+
+    if let (Some(port), false) = (port, domain_name.starts_with("*.")) {
+        let check = format!("Port{}", port);
+        readiness.checks.push(match timeout(CHECK_TIMEOUT, TcpStream::connect((base_name, port))).await {
+            Ok(Ok(_)) => ReadinessCheck::new(&check, true, format!("{}:{} accepted a connection", base_name, port)),
+            Ok(Err(e)) => ReadinessCheck::new(&check, false, format!("{}:{} is unreachable: {}", base_name, port, e)),
+            Err(_) => ReadinessCheck::new(&check, false, format!("{}:{} timed out", base_name, port)),
+        });
+    }
+
+    readiness
+}
+
+/// Returns the names that could be the apex of the zone holding a domain name, from the domain name itself up to
+/// its registrable domain.
+fn zone_candidates(domain_name: &str) -> Vec<String> {
+    let base_name = domain_name.strip_prefix("*.").unwrap_or(domain_name).trim_end_matches('.').to_lowercase();
+    let registrable = match registrable_domain(&base_name) {
+        Some(registrable) => registrable,
+        None => return vec![base_name],
+    };
+
+    let mut candidates = Vec::new();
+    let mut name = base_name.as_str();
+    loop {
+        candidates.push(name.to_string());
+        if name == registrable {
+            break;
+        }
+
+        match name.find('.') {
+            Some(pos) => name = &name[pos + 1..],
+            None => break,
+        }
+    }
+
+    candidates
+}
+
+/// Find the closest enclosing zone with NS records, returning its name and nameservers. Names with a CNAME can't
+/// be a zone apex, so they are skipped rather than reporting the nameservers of the CNAME's target.
+async fn find_zone(resolver: &TokioAsyncResolver, candidates: &[String]) -> Option<(String, Vec<String>)> {
+    for candidate in candidates {
+        let fqdn = format!("{}.", candidate);
+        if let Ok(lookup) = resolver.lookup(fqdn.clone(), RecordType::CNAME, DnsRequestOptions::default()).await {
+            if lookup.iter().any(|rdata| matches!(rdata, RData::CNAME(_))) {
+                continue;
+            }
+        }
+
+        let ns_lookup = resolver.lookup(fqdn, RecordType::NS, DnsRequestOptions::default()).await;
+        let name_servers: Vec<String> = match ns_lookup {
+            Ok(lookup) => lookup
+                .iter()
+                .filter_map(|rdata| match rdata {
+                    RData::NS(name) => Some(name.to_utf8().trim_end_matches('.').to_lowercase()),
+                    _ => None,
+                })
+                .collect(),
+            Err(_) => vec![],
+        };
+
+        if !name_servers.is_empty() {
+            let mut name_servers = name_servers;
+            name_servers.sort();
+            name_servers.dedup();
+            return Some((candidate.clone(), name_servers));
+        }
+    }
+
+    None
+}
+
+/// Ask a nameserver directly for the zone's SOA serial.
+async fn query_soa_serial(ip: IpAddr, zone: &str) -> Result<u32, String> {
+    let config = ResolverConfig::from_parts(None, vec![], NameServerConfigGroup::from_ips_clear(&[ip], 53, true));
+    let mut opts = ResolverOpts::default();
+    opts.cache_size = 0;
+    opts.attempts = 1;
+    opts.timeout = CHECK_TIMEOUT;
+
+    let resolver = TokioAsyncResolver::tokio(config, opts).map_err(|e| e.to_string())?;
+    let lookup = resolver
+        .lookup(format!("{}.", zone), RecordType::SOA, DnsRequestOptions::default())
+        .await
+        .map_err(|e| e.to_string())?;
+    lookup
+        .iter()
+        .find_map(|rdata| match rdata {
+            RData::SOA(soa) => Some(soa.serial()),
+            _ => None,
+        })
+        .ok_or_else(|| "no SOA record".to_string())
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::{zone_candidates, DomainReadiness, ReadinessCheck, ReadinessReport};
+
+    #[test]
+    fn test_zone_candidates() {
+        assert_eq!(zone_candidates("www.example.com"), vec!["www.example.com", "example.com"]);
+        assert_eq!(zone_candidates("*.api.example.co.uk"), vec!["api.example.co.uk", "example.co.uk"]);
+        assert_eq!(zone_candidates("Example.COM."), vec!["example.com"]);
+    }
+
+    #[test]
+    fn test_readiness_report() {
+        let report = ReadinessReport {
+            challenge_type: "http-01".to_string(),
+            domains: vec![DomainReadiness {
+                domain_name: "www.example.com".to_string(),
+                zone: Some("example.com".to_string()),
+                name_servers: vec!["ns1.example.net".to_string()],
+                checks: vec![
+                    ReadinessCheck::new("NsDelegation", true, "example.com is delegated to ns1.example.net"),
+                    ReadinessCheck::new("Port80", false, "www.example.com:80 timed out"),
+                ],
+            }],
+        };
+
+        assert!(!report.ready());
+        assert_eq!(report.problems(), vec!["www.example.com: Port80: www.example.com:80 timed out"]);
+    }
+}
@@ -0,0 +1,730 @@
+use {
+    crate::{
+        errors::CertificateRequestError,
+        inventory::{read_inventory, InventoryRecord},
+        keys::KeyAlgorithm,
+        storage::CertificateStorage,
+        utils::{now_epoch_secs, CertificateComponents, CertificateInfo},
+    },
+    futures::stream::{FuturesOrdered, StreamExt},
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    ring::digest::{digest, SHA256},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::collections::BTreeSet,
+};
+
+/// Certificates expiring within this many days are considered stale and are renewed, unless the request sets
+/// RenewalThresholdDays.
+pub const RENEWAL_THRESHOLD_DAYS: i64 = 30;
+
+/// The largest allowed RenewalThresholdDays. Larger values would renew 90-day certificates almost as soon as they
+/// were issued.
+pub const MAX_RENEWAL_THRESHOLD_DAYS: i64 = 60;
+
+/// The largest allowed RenewalJitterDays. Larger values would renew certificates with more than half of their
+/// 90-day validity remaining.
+pub const MAX_RENEWAL_JITTER_DAYS: i64 = 15;
+
+const SECONDS_PER_DAY: i64 = 86400;
+
+/// Returns a deterministic number of days, from 0 to `max_jitter_days` inclusive, to add to the renewal threshold
+/// for a set of domain names.
+///
+/// When many certificates are onboarded at once they all expire (and would be renewed) on the same day, landing
+/// in a single invocation and a single rate-limit window. Renewing each certificate a few days early, by an amount
+/// derived from a hash of its domain names, spreads the renewals out. Because the jitter is derived from the
+/// domain names rather than chosen at random, repeated runs agree on when a certificate is due.
+pub fn renewal_jitter_days(domain_names: &[String], max_jitter_days: i64) -> i64 {
+    if max_jitter_days <= 0 {
+        return 0;
+    }
+
+    let mut sorted = domain_names.iter().map(|dn| dn.to_lowercase()).collect::<Vec<String>>();
+    sorted.sort();
+    sorted.dedup();
+
+    let hash = digest(&SHA256, sorted.join(",").as_bytes());
+    let mut value_bytes = [0u8; 8];
+    value_bytes.copy_from_slice(&hash.as_ref()[..8]);
+
+    (u64::from_be_bytes(value_bytes) % (max_jitter_days as u64 + 1)) as i64
+}
+
+/// A certificate currently held by a storage target.
+#[derive(Clone, Debug)]
+pub struct ObservedCertificate {
+    /// Where the certificate was found (ARN, S3 URL, parameter or secret name).
+    pub location: String,
+
+    /// Information parsed from the certificate.
+    pub info: CertificateInfo,
+
+    /// The full set of certificate components, if the storage target holds the private key. This allows the
+    /// certificate to be copied to other targets without issuing a new one.
+    pub components: Option<CertificateComponents>,
+}
+
+/// The identifying details of a certificate, for showing which certificate a write replaces. In JSON:
+///
+///     {
+///         // Where the certificate is held (ARN, S3 URL, parameter or secret name).
+///         "Location": str,
+///
+///         // The domain names on the certificate.
+///         "DomainNames": [str, ...],
+///
+///         // The serial number of the certificate as lowercase hex.
+///         "Serial": str,
+///
+///         // The validity period of the certificate in seconds since the Unix epoch.
+///         "NotBefore": int,
+///         "NotAfter": int,
+///     }
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct CertificateSummary {
+    #[serde(rename = "Location")]
+    pub location: String,
+
+    #[serde(rename = "DomainNames")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "Serial")]
+    pub serial: String,
+
+    #[serde(rename = "NotBefore")]
+    pub not_before: i64,
+
+    #[serde(rename = "NotAfter")]
+    pub not_after: i64,
+}
+
+impl CertificateSummary {
+    pub fn new(location: &str, info: &CertificateInfo) -> Self {
+        Self {
+            location: location.to_string(),
+            domain_names: info.domain_names.clone(),
+            serial: info.serial.clone(),
+            not_before: info.not_before,
+            not_after: info.not_after,
+        }
+    }
+}
+
+impl From<&ObservedCertificate> for CertificateSummary {
+    fn from(observed: &ObservedCertificate) -> Self {
+        Self::new(&observed.location, &observed.info)
+    }
+}
+
+/// A certificate that was replaced and the certificate that replaced it. In JSON:
+///
+///     {
+///         // The certificates before and after the write. See CertificateSummary.
+///         "Before": { ... },
+///         "After": { ... },
+///
+///         // Domain names on the new certificate but not the old one, and vice versa (compared
+///         // case-insensitively).
+///         "AddedDomainNames": [str, ...],
+///         "RemovedDomainNames": [str, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct CertificateDiff {
+    #[serde(rename = "Before")]
+    pub before: CertificateSummary,
+
+    #[serde(rename = "After")]
+    pub after: CertificateSummary,
+
+    #[serde(rename = "AddedDomainNames", default)]
+    pub added_domain_names: Vec<String>,
+
+    #[serde(rename = "RemovedDomainNames", default)]
+    pub removed_domain_names: Vec<String>,
+}
+
+impl CertificateDiff {
+    pub fn new(before: CertificateSummary, after: CertificateSummary) -> Self {
+        let names = |summary: &CertificateSummary| -> BTreeSet<String> {
+            summary.domain_names.iter().map(|dn| dn.to_lowercase()).collect()
+        };
+        let (before_names, after_names) = (names(&before), names(&after));
+
+        Self {
+            added_domain_names: after_names.difference(&before_names).cloned().collect(),
+            removed_domain_names: before_names.difference(&after_names).cloned().collect(),
+            before,
+            after,
+        }
+    }
+}
+
+/// The desired state: every storage target holds a certificate covering the domain names, using the requested key
+/// algorithm, that does not expire within the renewal threshold.
+#[derive(Debug)]
+pub struct DesiredState {
+    pub domain_names: Vec<String>,
+    pub key_algorithm: KeyAlgorithm,
+    pub now: i64,
+    pub renewal_threshold_days: i64,
+}
+
+impl DesiredState {
+    pub fn new(domain_names: Vec<String>, key_algorithm: KeyAlgorithm, renewal_threshold_days: i64) -> Self {
+        Self {
+            domain_names,
+            key_algorithm,
+            now: now_epoch_secs(),
+            renewal_threshold_days,
+        }
+    }
+
+    /// Returns the reason an observed certificate does not satisfy the desired state, or None if it does.
+    fn staleness(&self, observed: &ObservedCertificate) -> Option<String> {
+        let missing: Vec<&str> = self
+            .domain_names
+            .iter()
+            .filter(|dn| !observed.info.covers(&[dn.to_string()]))
+            .map(|dn| dn.as_str())
+            .collect();
+
+        if !missing.is_empty() {
+            return Some(format!("Certificate at {} does not cover {}", observed.location, missing.join(" ")));
+        }
+
+        if observed.info.key_algorithm != Some(self.key_algorithm) {
+            return Some(format!(
+                "Certificate at {} uses key algorithm {}; {} was requested",
+                observed.location,
+                observed.info.key_algorithm.map(|alg| alg.to_string()).unwrap_or_else(|| "unknown".to_string()),
+                self.key_algorithm
+            ));
+        }
+
+        let days_left = (observed.info.not_after - self.now) / SECONDS_PER_DAY;
+        if days_left < self.renewal_threshold_days {
+            return Some(format!("Certificate at {} expires in {} day(s)", observed.location, days_left));
+        }
+
+        None
+    }
+}
+
+/// The observed state of each storage target (in the same order as the request) along with the inventory record
+/// from the last run, if any.
+#[derive(Debug)]
+pub struct ActualState {
+    pub targets: Vec<Result<Option<ObservedCertificate>, String>>,
+    pub inventory: Option<InventoryRecord>,
+}
+
+impl ActualState {
+    /// Observe all storage targets and the inventory concurrently. Failures are recorded rather than propagated so
+    /// that the affected targets are simply rewritten.
+    pub async fn observe(storage: &[CertificateStorage], domain_names: &[String]) -> Self {
+        let mut futures = FuturesOrdered::new();
+        for storage_provider in storage {
+            futures.push(storage_provider.observe(domain_names));
+        }
+
+        let (targets, inventory) = tokio::join!(futures.collect::<Vec<_>>(), read_inventory(domain_names));
+
+        let targets = targets
+            .into_iter()
+            .map(|result| {
+                result.map_err(|e| {
+                    error!("Failed to observe storage target: {:#}", e);
+                    format!("{:#}", e)
+                })
+            })
+            .collect();
+
+        let inventory = match inventory {
+            Ok(inventory) => inventory,
+            Err(e) => {
+                error!("Failed to read inventory; continuing without it: {:#}", e);
+                None
+            }
+        };
+
+        Self {
+            targets,
+            inventory,
+        }
+    }
+
+    fn observed(&self, index: usize) -> Option<&ObservedCertificate> {
+        match &self.targets[index] {
+            Ok(Some(observed)) => Some(observed),
+            _ => None,
+        }
+    }
+}
+
+/// An action to take on a storage target to reach the desired state. In JSON:
+///
+///     {
+///         // The action: "UpToDate", "Copy", "Store", or "Excluded".
+///         "Action": str,
+///
+///         // The index of the storage target in the request.
+///         "StorageIndex": int,
+///
+///         // For UpToDate, the serial number and expiration time (seconds since the Unix epoch) of the
+///         // current certificate.
+///         "Serial": str,
+///         "NotAfter": int,
+///
+///         // For Copy, the index of the storage target holding the certificate to copy.
+///         "SourceIndex": int,
+///
+///         // For Copy and Store, why the storage target needs to be written.
+///         "Reason": str,
+///
+///         // For Copy and Store, the certificate the storage target currently holds, which the write will
+///         // replace, if one was found. See CertificateSummary.
+///         "Replaces": { ... },
+///
+///         // For Excluded, why the storage target wasn't written even though it isn't up to date.
+///         "Reason": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(tag = "Action")]
+pub enum ReconcileAction {
+    /// The storage target already holds a certificate satisfying the desired state.
+    UpToDate {
+        #[serde(rename = "StorageIndex")]
+        storage_index: usize,
+
+        #[serde(rename = "Serial")]
+        serial: String,
+
+        #[serde(rename = "NotAfter")]
+        not_after: i64,
+    },
+
+    /// The storage target will be written with a certificate already held by another target.
+    Copy {
+        #[serde(rename = "StorageIndex")]
+        storage_index: usize,
+
+        #[serde(rename = "SourceIndex")]
+        source_index: usize,
+
+        #[serde(rename = "Reason")]
+        reason: String,
+
+        #[serde(rename = "Replaces", default, skip_serializing_if = "Option::is_none")]
+        replaces: Option<CertificateSummary>,
+    },
+
+    /// The storage target will be written with a newly issued certificate.
+    Store {
+        #[serde(rename = "StorageIndex")]
+        storage_index: usize,
+
+        #[serde(rename = "Reason")]
+        reason: String,
+
+        #[serde(rename = "Replaces", default, skip_serializing_if = "Option::is_none")]
+        replaces: Option<CertificateSummary>,
+    },
+
+    /// The storage target needs to be written, but the request's StorageIndexes left it out of this run.
+    Excluded {
+        #[serde(rename = "StorageIndex")]
+        storage_index: usize,
+
+        #[serde(rename = "Reason")]
+        reason: String,
+    },
+}
+
+/// The minimal set of actions needed to move from the actual state to the desired state.
+#[derive(Debug)]
+pub struct ReconcilePlan {
+    pub actions: Vec<ReconcileAction>,
+}
+
+impl ReconcilePlan {
+    pub fn new(desired: &DesiredState, actual: &ActualState) -> Self {
+        let mut reasons: Vec<Option<String>> = actual
+            .targets
+            .iter()
+            .map(|target| match target {
+                Err(e) => Some(format!("Unable to observe current certificate: {}", e)),
+                Ok(None) => Some("No certificate present".to_string()),
+                Ok(Some(observed)) => desired.staleness(observed),
+            })
+            .collect();
+
+        // A current certificate whose private key we can read can be copied to the other targets instead of issuing
+        // a new one. Prefer the certificate recorded in the inventory so that all targets converge onto it.
+        let expected_serial = actual.inventory.as_ref().map(|record| record.serial.as_str());
+        let candidates: Vec<usize> = (0..reasons.len())
+            .filter(|i| reasons[*i].is_none())
+            .filter(|i| actual.observed(*i).map(|o| o.components.is_some()).unwrap_or(false))
+            .collect();
+
+        let source = candidates
+            .iter()
+            .find(|i| Some(actual.observed(**i).unwrap().info.serial.as_str()) == expected_serial)
+            .or_else(|| candidates.first())
+            .copied();
+
+        // Targets holding a current certificate other than the source's are rewritten so that every target serves
+        // the same certificate. This only happens when copying is possible; otherwise we'd issue a new certificate
+        // just to fix a mismatch.
+        if let Some(source) = source {
+            let source_serial = actual.observed(source).unwrap().info.serial.clone();
+            for (i, reason) in reasons.iter_mut().enumerate() {
+                if reason.is_none() {
+                    let observed = actual.observed(i).unwrap();
+                    if observed.info.serial != source_serial {
+                        *reason = Some(format!(
+                            "Certificate at {} has serial {}; converging on serial {}",
+                            observed.location, observed.info.serial, source_serial
+                        ));
+                    }
+                }
+            }
+        }
+
+        let actions = reasons
+            .into_iter()
+            .enumerate()
+            .map(|(i, reason)| match (reason, source) {
+                (None, _) => {
+                    let observed = actual.observed(i).unwrap();
+                    ReconcileAction::UpToDate {
+                        storage_index: i,
+                        serial: observed.info.serial.clone(),
+                        not_after: observed.info.not_after,
+                    }
+                }
+                (Some(reason), Some(source)) => ReconcileAction::Copy {
+                    storage_index: i,
+                    source_index: source,
+                    reason,
+                    replaces: actual.observed(i).map(CertificateSummary::from),
+                },
+                (Some(reason), None) => ReconcileAction::Store {
+                    storage_index: i,
+                    reason,
+                    replaces: actual.observed(i).map(CertificateSummary::from),
+                },
+            })
+            .collect();
+
+        let plan = Self {
+            actions,
+        };
+
+        for action in &plan.actions {
+            info!("Reconcile plan: {:?}", action);
+        }
+
+        plan
+    }
+
+    /// Restrict the writes to the storage targets listed in storage_indexes (e.g. the targets that failed in a
+    /// previous run); the other targets that need to be written are marked Excluded. A Copy source outside the list
+    /// is still read.
+    pub fn restrict(&mut self, storage_indexes: &[usize]) {
+        for action in self.actions.iter_mut() {
+            let excluded = match action {
+                ReconcileAction::Copy {
+                    storage_index,
+                    reason,
+                    ..
+                }
+                | ReconcileAction::Store {
+                    storage_index,
+                    reason,
+                    ..
+                } if !storage_indexes.contains(storage_index) => ReconcileAction::Excluded {
+                    storage_index: *storage_index,
+                    reason: reason.clone(),
+                },
+                _ => continue,
+            };
+
+            *action = excluded;
+        }
+    }
+
+    /// Indicates whether every storage target is already up to date (or excluded from the run).
+    pub fn is_converged(&self) -> bool {
+        self.actions
+            .iter()
+            .all(|action| matches!(action, ReconcileAction::UpToDate { .. } | ReconcileAction::Excluded { .. }))
+    }
+
+    /// Returns the index of the storage target to copy the certificate from, if the plan copies rather than issues.
+    pub fn copy_source(&self) -> Option<usize> {
+        self.actions.iter().find_map(|action| match action {
+            ReconcileAction::Copy {
+                source_index,
+                ..
+            } => Some(*source_index),
+            _ => None,
+        })
+    }
+
+    /// Returns the components of the certificate to copy to the targets being written, or None if a new certificate
+    /// must be issued. It's an error for the copy source not to hold its private key; storage types that can't read
+    /// it back (ACM, IAM, CloudFront, API Gateway) are never chosen as the source.
+    pub fn copy_components(&self, actual: &ActualState) -> Result<Option<CertificateComponents>, LambdaError> {
+        let observed = match self.copy_source().and_then(|source| actual.observed(source)) {
+            Some(observed) => observed,
+            None => return Ok(None),
+        };
+
+        match &observed.components {
+            Some(components) => {
+                info!("Copying existing certificate from {}", observed.location);
+                Ok(Some(components.clone()))
+            }
+            None => Err(CertificateRequestError::copy_source_missing_key(observed.location.clone())),
+        }
+    }
+
+    /// Returns the indices of the storage targets that need to be written.
+    pub fn targets(&self) -> Vec<usize> {
+        self.actions
+            .iter()
+            .filter_map(|action| match action {
+                ReconcileAction::UpToDate {
+                    ..
+                }
+                | ReconcileAction::Excluded {
+                    ..
+                } => None,
+                ReconcileAction::Copy {
+                    storage_index,
+                    ..
+                }
+                | ReconcileAction::Store {
+                    storage_index,
+                    ..
+                } => Some(*storage_index),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{
+            renewal_jitter_days, ActualState, CertificateDiff, CertificateSummary, DesiredState, ObservedCertificate,
+            ReconcileAction, ReconcilePlan, SECONDS_PER_DAY,
+        },
+        crate::{
+            inventory::InventoryRecord,
+            keys::KeyAlgorithm,
+            utils::{CertificateComponents, CertificateInfo},
+        },
+    };
+
+    fn observed(serial: &str, days_left: i64, with_key: bool) -> ObservedCertificate {
+        ObservedCertificate {
+            location: format!("test-{}", serial),
+            info: CertificateInfo {
+                domain_names: vec!["example.com".to_string()],
+                serial: serial.to_string(),
+                not_before: 1_000_000_000 - 90 * SECONDS_PER_DAY,
+                not_after: 1_000_000_000 + days_left * SECONDS_PER_DAY,
+                key_algorithm: Some(KeyAlgorithm::Rsa2048),
+            },
+            components: if with_key {
+                Some(CertificateComponents {
+                    cert_pem: String::new(),
+                    chain_pem: String::new(),
+                    fullchain_pem: String::new(),
+                    pkey_pem: String::new(),
+                    alternate_chain: None,
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    fn desired() -> DesiredState {
+        DesiredState {
+            domain_names: vec!["example.com".to_string()],
+            key_algorithm: KeyAlgorithm::Rsa2048,
+            now: 1_000_000_000,
+            renewal_threshold_days: 30,
+        }
+    }
+
+    #[test]
+    fn test_plan_converged() {
+        let actual = ActualState {
+            targets: vec![Ok(Some(observed("1", 60, false))), Ok(Some(observed("1", 60, true)))],
+            inventory: None,
+        };
+
+        let plan = ReconcilePlan::new(&desired(), &actual);
+        assert!(plan.is_converged());
+        assert!(plan.targets().is_empty());
+    }
+
+    #[test]
+    fn test_plan_copies_from_current_target() {
+        let actual = ActualState {
+            targets: vec![Ok(Some(observed("1", 10, false))), Ok(Some(observed("2", 60, true))), Ok(None)],
+            inventory: None,
+        };
+
+        let plan = ReconcilePlan::new(&desired(), &actual);
+        assert_eq!(plan.copy_source(), Some(1));
+        assert_eq!(plan.targets(), vec![0, 2]);
+        assert!(plan.copy_components(&actual).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_copy_source_missing_key() {
+        let actual = ActualState {
+            targets: vec![Ok(None), Ok(Some(observed("2", 60, true)))],
+            inventory: None,
+        };
+        let plan = ReconcilePlan::new(&desired(), &actual);
+        assert_eq!(plan.copy_source(), Some(1));
+
+        // The source was observed again without its key (e.g. a target whose observe returns no components).
+        let keyless = ActualState {
+            targets: vec![Ok(None), Ok(Some(observed("2", 60, false)))],
+            inventory: None,
+        };
+        let e = plan.copy_components(&keyless).unwrap_err();
+        assert!(e.to_string().contains("Cannot copy the certificate at test-2"), "{}", e);
+
+        // A source that can no longer be observed falls back to issuing.
+        let gone = ActualState {
+            targets: vec![Ok(None), Err("AccessDenied".to_string())],
+            inventory: None,
+        };
+        assert!(plan.copy_components(&gone).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_plan_issues_when_no_source() {
+        let actual = ActualState {
+            targets: vec![Ok(Some(observed("1", 60, false))), Ok(Some(observed("1", 5, true)))],
+            inventory: None,
+        };
+
+        let plan = ReconcilePlan::new(&desired(), &actual);
+        assert_eq!(plan.copy_source(), None);
+        assert_eq!(plan.targets(), vec![1]);
+        assert!(matches!(plan.actions[1], ReconcileAction::Store { .. }));
+
+        // The plan names the certificate each write replaces.
+        match &plan.actions[1] {
+            ReconcileAction::Store {
+                replaces: Some(replaces),
+                ..
+            } => {
+                assert_eq!(replaces.location, "test-1");
+                assert_eq!(replaces.serial, "1");
+            }
+            action => panic!("Unexpected action: {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_plan_converges_on_inventory_serial() {
+        let actual = ActualState {
+            targets: vec![Ok(Some(observed("1", 60, true))), Ok(Some(observed("2", 60, true)))],
+            inventory: Some(InventoryRecord {
+                domain_names: vec!["example.com".to_string()],
+                serial: "2".to_string(),
+                not_after: 0,
+                updated_at: 0,
+                storage_results: vec![],
+                alternate_chain: false,
+                request: None,
+            }),
+        };
+
+        let plan = ReconcilePlan::new(&desired(), &actual);
+        assert_eq!(plan.copy_source(), Some(1));
+        assert_eq!(plan.targets(), vec![0]);
+    }
+
+    #[test]
+    fn test_plan_reissues_on_key_algorithm_change() {
+        let actual = ActualState {
+            targets: vec![Ok(Some(observed("1", 60, true)))],
+            inventory: None,
+        };
+
+        let desired = DesiredState {
+            key_algorithm: KeyAlgorithm::EcdsaP256,
+            ..desired()
+        };
+
+        let plan = ReconcilePlan::new(&desired, &actual);
+        assert_eq!(plan.copy_source(), None);
+        assert_eq!(plan.targets(), vec![0]);
+    }
+
+    #[test]
+    fn test_plan_restrict() {
+        let actual = ActualState {
+            targets: vec![Ok(Some(observed("1", 10, false))), Ok(Some(observed("2", 60, true))), Ok(None)],
+            inventory: None,
+        };
+
+        let mut plan = ReconcilePlan::new(&desired(), &actual);
+        plan.restrict(&[2]);
+        assert_eq!(plan.copy_source(), Some(1));
+        assert_eq!(plan.targets(), vec![2]);
+        assert!(matches!(plan.actions[0], ReconcileAction::Excluded { .. }));
+        assert!(!plan.is_converged());
+
+        plan.restrict(&[]);
+        assert!(plan.targets().is_empty());
+        assert!(plan.is_converged());
+    }
+
+    #[test]
+    fn test_renewal_jitter_days() {
+        let names = vec!["example.com".to_string(), "www.example.com".to_string()];
+        let reordered = vec!["WWW.example.com".to_string(), "example.com".to_string()];
+
+        assert_eq!(renewal_jitter_days(&names, 0), 0);
+        assert_eq!(renewal_jitter_days(&names, 10), renewal_jitter_days(&reordered, 10));
+
+        // Jitter stays within bounds and actually spreads different certificates across days.
+        let mut seen = std::collections::HashSet::new();
+        for i in 0..50 {
+            let jitter = renewal_jitter_days(&[format!("host{}.example.com", i)], 10);
+            assert!((0..=10).contains(&jitter));
+            seen.insert(jitter);
+        }
+        assert!(seen.len() > 1);
+    }
+
+    #[test]
+    fn test_certificate_diff() {
+        let before = CertificateSummary::from(&observed("1", 10, false));
+        let after = CertificateSummary {
+            domain_names: vec!["Example.com".to_string(), "www.example.com".to_string()],
+            serial: "2".to_string(),
+            ..before.clone()
+        };
+
+        let diff = CertificateDiff::new(before, after);
+        assert_eq!(diff.added_domain_names, vec!["www.example.com".to_string()]);
+        assert!(diff.removed_domain_names.is_empty());
+        assert_eq!(diff.before.serial, "1");
+        assert_eq!(diff.after.serial, "2");
+    }
+}
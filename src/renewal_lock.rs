@@ -0,0 +1,204 @@
+use {
+    crate::{
+        constants::{ENV_RENEWAL_LOCK_TABLE, RENEWAL_LOCK_LEASE_SECONDS},
+        rate_limits::domain_rule_name,
+        utils::{attr_n, attr_s, format_utc_timestamp, now_epoch_secs},
+        xray::aws_client,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info, warn},
+    openssl::rand::rand_bytes,
+    rusoto_core::{Region, RusotoError},
+    rusoto_dynamodb::{
+        AttributeValue, DeleteItemError, DeleteItemInput, DynamoDb, DynamoDbClient, GetItemInput, PutItemError,
+        PutItemInput,
+    },
+    std::{collections::HashMap, env::var, time::Duration},
+    tokio::time::sleep,
+};
+
+/// How often a request waiting for a lock checks whether it was released.
+const LOCK_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Keeps two invocations from renewing the same certificate at once (e.g. a scheduled run overlapping a manual one),
+/// which would otherwise order two certificates and race to write them. This is enabled by setting the
+/// `RenewalLockTable` environment variable to the name of a DynamoDB table with a string partition key named
+/// `LockKey`. Locks are leases: a lock left behind by an invocation that timed out or crashed expires after 15
+/// minutes (the longest a Lambda invocation can run), and the table's TTL attribute, `ExpiresAt`, removes it.
+#[derive(Clone, Debug)]
+pub struct RenewalLock {
+    table_name: String,
+}
+
+/// The result of trying to take a renewal lock.
+#[derive(Debug)]
+pub enum LockAttempt {
+    /// The lock was taken; release it when the run finishes.
+    Acquired(LockGuard),
+
+    /// Another invocation holds the lock; this describes it.
+    Held(String),
+}
+
+impl RenewalLock {
+    pub fn from_env() -> Option<Self> {
+        let table_name = var(ENV_RENEWAL_LOCK_TABLE).ok().filter(|table| !table.is_empty())?;
+        Some(Self {
+            table_name,
+        })
+    }
+
+    /// Take the lock for a set of domain names, waiting up to wait_seconds for another invocation to release it.
+    pub async fn acquire(&self, domain_names: &[String], wait_seconds: u64) -> Result<LockAttempt, LambdaError> {
+        let key = lock_key(domain_names);
+        let mut owner = [0u8; 8];
+        rand_bytes(&mut owner)?;
+        let owner: String = owner.iter().map(|b| format!("{:02x}", b)).collect();
+
+        let give_up_at = now_epoch_secs() + wait_seconds as i64;
+        loop {
+            if self.try_acquire(&key, &owner).await? {
+                info!("Acquired renewal lock {} in {}", key, self.table_name);
+                return Ok(LockAttempt::Acquired(LockGuard {
+                    table_name: self.table_name.clone(),
+                    key,
+                    owner,
+                }));
+            }
+
+            if now_epoch_secs() >= give_up_at {
+                let holder = self.describe_holder(&key).await;
+                warn!("Renewal of {} is already in progress: {}", domain_names.join(" "), holder);
+                return Ok(LockAttempt::Held(holder));
+            }
+
+            info!("Waiting for renewal lock {} in {}", key, self.table_name);
+            sleep(LOCK_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Put the lock item unless an unexpired lock is already there. Returns whether the lock was taken.
+    async fn try_acquire(&self, key: &str, owner: &str) -> Result<bool, LambdaError> {
+        let now = now_epoch_secs();
+        let ddb = DynamoDbClient::new_with_client(aws_client(), Region::default());
+        let pi_input = PutItemInput {
+            table_name: self.table_name.clone(),
+            item: vec![
+                ("LockKey".to_string(), attr_s(key)),
+                ("HolderId".to_string(), attr_s(owner)),
+                ("AcquiredAt".to_string(), attr_n(now)),
+                ("ExpiresAt".to_string(), attr_n(now + RENEWAL_LOCK_LEASE_SECONDS)),
+            ]
+            .into_iter()
+            .collect(),
+            condition_expression: Some("attribute_not_exists(LockKey) OR ExpiresAt < :now".to_string()),
+            expression_attribute_values: Some(vec![(":now".to_string(), attr_n(now))].into_iter().collect()),
+            ..Default::default()
+        };
+
+        match ddb.put_item(pi_input).await {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => Ok(false),
+            Err(e) => {
+                error!("Failed to acquire renewal lock {} in {}: {:#}", key, self.table_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Returns a description of the current holder of a lock, for the AlreadyInProgress response.
+    async fn describe_holder(&self, key: &str) -> String {
+        let ddb = DynamoDbClient::new_with_client(aws_client(), Region::default());
+        let gi_input = GetItemInput {
+            table_name: self.table_name.clone(),
+            key: key_for(key),
+            consistent_read: Some(true),
+            ..Default::default()
+        };
+
+        let item = match ddb.get_item(gi_input).await {
+            Ok(response) => response.item,
+            Err(e) => {
+                error!("Failed to read renewal lock {} from {}: {:#}", key, self.table_name, e);
+                None
+            }
+        };
+
+        let number = |name: &str| -> Option<i64> {
+            item.as_ref().and_then(|item| item.get(name)).and_then(|v| v.n.as_ref()).and_then(|n| n.parse().ok())
+        };
+
+        match (number("AcquiredAt"), number("ExpiresAt")) {
+            (Some(acquired_at), Some(expires_at)) => format!(
+                "Renewal lock {} was acquired at {} and expires at {}",
+                key,
+                format_utc_timestamp(acquired_at),
+                format_utc_timestamp(expires_at)
+            ),
+            _ => format!("Renewal lock {} is held by another invocation", key),
+        }
+    }
+}
+
+/// A renewal lock held by this invocation.
+#[derive(Debug)]
+pub struct LockGuard {
+    table_name: String,
+    key: String,
+    owner: String,
+}
+
+impl LockGuard {
+    /// Release the lock if this invocation still holds it. Failures are logged but otherwise ignored; the lock
+    /// expires on its own.
+    pub async fn release(self) {
+        let ddb = DynamoDbClient::new_with_client(aws_client(), Region::default());
+        let di_input = DeleteItemInput {
+            table_name: self.table_name.clone(),
+            key: key_for(&self.key),
+            condition_expression: Some("HolderId = :owner".to_string()),
+            expression_attribute_values: Some(vec![(":owner".to_string(), attr_s(&self.owner))].into_iter().collect()),
+            ..Default::default()
+        };
+
+        match ddb.delete_item(di_input).await {
+            Ok(_) => info!("Released renewal lock {} in {}", self.key, self.table_name),
+            Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => {
+                warn!("Renewal lock {} expired and was taken by another invocation before it was released", self.key)
+            }
+            Err(e) => error!("Failed to release renewal lock {} in {}: {:#}", self.key, self.table_name, e),
+        }
+    }
+}
+
+/// Returns the lock key for a set of domain names: the first of them in sorted order (for readability) and a hash of
+/// all of them, so the same set of names always maps to the same lock regardless of order or case.
+fn lock_key(domain_names: &[String]) -> String {
+    let first = domain_names.iter().map(|dn| dn.to_lowercase()).min().unwrap_or_default();
+    domain_rule_name(&format!("{}-", first.replace('*', "_")), domain_names)
+}
+
+fn key_for(lock_key: &str) -> HashMap<String, AttributeValue> {
+    let mut key = HashMap::new();
+    key.insert("LockKey".to_string(), attr_s(lock_key));
+    key
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::lock_key;
+
+    #[test]
+    fn test_lock_key() {
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+
+        let key = lock_key(&names(&["*.example.com", "example.com"]));
+        assert!(key.starts_with("_.example.com-"));
+        assert_eq!(key.len(), "_.example.com-".len() + 16);
+
+        // The same names in a different order or case share the lock; different names don't.
+        assert_eq!(lock_key(&names(&["Example.com", "*.example.com"])), key);
+        assert_ne!(lock_key(&names(&["*.example.com"])), key);
+    }
+}
@@ -0,0 +1,249 @@
+use {
+    crate::constants::ENV_LAMBDA_FUNCTION_MEMORY_SIZE,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::{
+        env::var,
+        fs::read_to_string,
+        time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Peak memory above this fraction of the function's memory size is reported as too close to the limit.
+const MEMORY_HIGH_FRACTION: f64 = 0.8;
+
+/// Peak memory below this fraction of the function's memory size is reported as oversized.
+const MEMORY_LOW_FRACTION: f64 = 0.2;
+
+/// Memory sizes at or below this aren't worth recommending a reduction for.
+const MEMORY_LOW_FLOOR_MB: u64 = 256;
+
+/// Runs projected to use more than this fraction of the timeout are reported as at risk.
+const TIME_HIGH_FRACTION: f64 = 0.8;
+
+/// The time and memory budget for an invocation, used to advise operators when the function is badly sized.
+#[derive(Clone, Debug)]
+pub struct RunBudget {
+    started: Instant,
+
+    /// The time from the start of the invocation to its deadline.
+    timeout: Option<Duration>,
+
+    /// The function's configured memory size in MB.
+    memory_limit_mb: Option<u64>,
+}
+
+impl RunBudget {
+    /// Create a budget for an invocation with the given deadline (milliseconds since the Unix epoch, as supplied by
+    /// the Lambda runtime). The memory size is read from the environment.
+    pub fn new(deadline_millis: Option<u64>) -> Self {
+        let now_millis = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        Self {
+            started: Instant::now(),
+            timeout: deadline_millis.map(|deadline| Duration::from_millis(deadline.saturating_sub(now_millis))),
+            memory_limit_mb: var(ENV_LAMBDA_FUNCTION_MEMORY_SIZE).ok().and_then(|size| size.parse().ok()),
+        }
+    }
+}
+
+/// The time taken by one phase of a run.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct PhaseTiming {
+    #[serde(rename = "Phase")]
+    pub phase: String,
+
+    #[serde(rename = "DurationMillis")]
+    pub duration_millis: u64,
+}
+
+/// Accumulates the time spent in each phase of a run.
+#[derive(Clone, Debug, Default)]
+pub struct PhaseTimings {
+    phases: Vec<PhaseTiming>,
+}
+
+impl PhaseTimings {
+    /// Record the time elapsed since `started` against a phase. Repeated phases are summed.
+    pub fn record(&mut self, phase: &str, started: Instant) {
+        let millis = started.elapsed().as_millis() as u64;
+        match self.phases.iter_mut().find(|timing| timing.phase == phase) {
+            Some(timing) => timing.duration_millis += millis,
+            None => self.phases.push(PhaseTiming {
+                phase: phase.to_string(),
+                duration_millis: millis,
+            }),
+        }
+    }
+
+    /// Add all of the timings from another run.
+    pub fn merge(&mut self, other: &PhaseTimings) {
+        for timing in &other.phases {
+            match self.phases.iter_mut().find(|t| t.phase == timing.phase) {
+                Some(t) => t.duration_millis += timing.duration_millis,
+                None => self.phases.push(timing.clone()),
+            }
+        }
+    }
+}
+
+/// A summary of the resources used by a run, along with advice on sizing the function. In JSON:
+///
+///     {
+///         // The wall-clock time of the run in milliseconds.
+///         "DurationMillis": int,
+///
+///         // The function's timeout and memory size, if known.
+///         "TimeoutMillis": int,
+///         "MemoryLimitMb": int,
+///
+///         // The peak resident memory of the process in MB, if known.
+///         "PeakMemoryMb": int,
+///
+///         // The time spent in each phase of the run (e.g. "Validate", "Observe", "Issue", "Store").
+///         "Phases": [{"Phase": str, "DurationMillis": int}, ...],
+///
+///         // Recommendations for resizing the function's memory or timeout, if any.
+///         "Recommendations": [str, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct RunReport {
+    #[serde(rename = "DurationMillis")]
+    pub duration_millis: u64,
+
+    #[serde(rename = "TimeoutMillis", default, skip_serializing_if = "Option::is_none")]
+    pub timeout_millis: Option<u64>,
+
+    #[serde(rename = "MemoryLimitMb", default, skip_serializing_if = "Option::is_none")]
+    pub memory_limit_mb: Option<u64>,
+
+    #[serde(rename = "PeakMemoryMb", default, skip_serializing_if = "Option::is_none")]
+    pub peak_memory_mb: Option<u64>,
+
+    #[serde(rename = "Phases", default)]
+    pub phases: Vec<PhaseTiming>,
+
+    #[serde(rename = "Recommendations", default)]
+    pub recommendations: Vec<String>,
+}
+
+impl RunReport {
+    /// Build a report for a run that processed `n_processed` of `n_total` certificates.
+    pub fn new(budget: &RunBudget, phases: &PhaseTimings, n_processed: usize, n_total: usize) -> Self {
+        let duration = budget.started.elapsed();
+        let peak_memory_mb = peak_memory_mb();
+
+        Self {
+            duration_millis: duration.as_millis() as u64,
+            timeout_millis: budget.timeout.map(|timeout| timeout.as_millis() as u64),
+            memory_limit_mb: budget.memory_limit_mb,
+            peak_memory_mb,
+            phases: phases.phases.clone(),
+            recommendations: recommendations(
+                duration,
+                budget.timeout,
+                peak_memory_mb,
+                budget.memory_limit_mb,
+                n_processed,
+                n_total,
+            ),
+        }
+    }
+}
+
+/// Produce sizing advice from the observed duration and memory use.
+fn recommendations(
+    duration: Duration,
+    timeout: Option<Duration>,
+    peak_memory_mb: Option<u64>,
+    memory_limit_mb: Option<u64>,
+    n_processed: usize,
+    n_total: usize,
+) -> Vec<String> {
+    let mut recommendations = Vec::new();
+
+    if let (Some(timeout), true) = (timeout, n_processed > 0) {
+        let per_certificate = duration.as_secs_f64() / n_processed as f64;
+        let projected = per_certificate * n_total.max(n_processed) as f64;
+
+        if projected > timeout.as_secs_f64() * TIME_HIGH_FRACTION {
+            if n_total > 1 {
+                recommendations.push(format!(
+                    "At about {:.0}s per certificate, {} certificates need about {:.0}s, which won't fit \
+                     comfortably in the {:.0}s timeout; increase the timeout or split the batch",
+                    per_certificate,
+                    n_total,
+                    projected,
+                    timeout.as_secs_f64()
+                ));
+            } else {
+                recommendations.push(format!(
+                    "The run took {:.0}s of the {:.0}s timeout; increase the timeout",
+                    projected,
+                    timeout.as_secs_f64()
+                ));
+            }
+        }
+    }
+
+    if let (Some(peak), Some(limit)) = (peak_memory_mb, memory_limit_mb) {
+        let fraction = peak as f64 / limit as f64;
+        if fraction > MEMORY_HIGH_FRACTION {
+            recommendations
+                .push(format!("Peak memory was {} MB of the {} MB memory size; increase the memory size", peak, limit));
+        } else if fraction < MEMORY_LOW_FRACTION && limit > MEMORY_LOW_FLOOR_MB {
+            recommendations.push(format!(
+                "Peak memory was only {} MB of the {} MB memory size; the memory size can likely be reduced",
+                peak, limit
+            ));
+        }
+    }
+
+    recommendations
+}
+
+/// Returns the peak resident memory (high water mark) of this process in MB, if available. This is only supported
+/// on Linux, where it's read from `/proc/self/status`.
+fn peak_memory_mb() -> Option<u64> {
+    let status = read_to_string("/proc/self/status").ok()?;
+    parse_vm_hwm_mb(&status)
+}
+
+fn parse_vm_hwm_mb(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    let kb: u64 = line.trim_start_matches("VmHWM:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kb.div_ceil(1024))
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{parse_vm_hwm_mb, recommendations},
+        std::time::Duration,
+    };
+
+    #[test]
+    fn test_parse_vm_hwm() {
+        let status = "Name:\tbootstrap\nVmPeak:\t  200000 kB\nVmHWM:\t   51200 kB\nVmRSS:\t   40000 kB\n";
+        assert_eq!(parse_vm_hwm_mb(status), Some(50));
+        assert_eq!(parse_vm_hwm_mb("Name:\tbootstrap\n"), None);
+    }
+
+    #[test]
+    fn test_recommendations() {
+        // 47 certificates at 10s each won't fit in a 5-minute timeout.
+        let recs = recommendations(Duration::from_secs(30), Some(Duration::from_secs(300)), None, None, 3, 47);
+        assert_eq!(recs.len(), 1);
+        assert!(recs[0].contains("47 certificates"), "{}", recs[0]);
+
+        // A well-sized run has no recommendations.
+        let recs = recommendations(Duration::from_secs(30), Some(Duration::from_secs(300)), Some(100), Some(256), 1, 1);
+        assert!(recs.is_empty(), "{:?}", recs);
+
+        // Memory close to the limit and far below it.
+        let recs = recommendations(Duration::from_secs(1), None, Some(120), Some(128), 1, 1);
+        assert!(recs[0].contains("increase the memory size"));
+        let recs = recommendations(Duration::from_secs(1), None, Some(60), Some(1024), 1, 1);
+        assert!(recs[0].contains("can likely be reduced"));
+    }
+}
@@ -0,0 +1,347 @@
+use {
+    crate::{
+        constants::{
+            DEFAULT_RETRY_BASE_DELAY_MILLIS, DEFAULT_RETRY_MAX_ATTEMPTS, DEFAULT_RETRY_MAX_DELAY_MILLIS,
+            ENV_RETRY_BASE_DELAY_MILLIS, ENV_RETRY_MAX_ATTEMPTS, ENV_RETRY_MAX_DELAY_MILLIS, ENV_RETRY_ON,
+        },
+        errors::InvalidCertificateRequest,
+        utils::is_throttling_error,
+    },
+    acme2::Error as AcmeError,
+    lambda_runtime::Error as LambdaError,
+    lazy_static::lazy_static,
+    log::{debug, warn},
+    ring::rand::{SecureRandom, SystemRandom},
+    rusoto_core::RusotoError,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::{env::var, error::Error, future::Future, str::FromStr, time::Duration},
+    tokio::time::sleep,
+};
+
+/// The most attempts a retry policy may allow.
+const MAX_RETRY_ATTEMPTS: u32 = 20;
+
+lazy_static! {
+    /// The retry policy set by the environment, used for calls made outside of a request with its own policy.
+    static ref DEFAULT_RETRY_CONFIG: RetryConfig = RetryConfig::from_env();
+}
+
+tokio::task_local! {
+    /// The retry policy of the certificate request being processed.
+    static REQUEST_RETRY_CONFIG: RetryConfig;
+}
+
+/// How calls to AWS (e.g. ACM imports, SSM parameter reads and writes, and S3 object writes) and to the ACME server
+/// are retried when they fail with a transient error. In JSON:
+///
+///     {
+///         // The most attempts at a call, including the first. Must be between 1 and 20. The default is the
+///         // RetryMaxAttempts environment variable, or 6 if that isn't set.
+///         "MaxAttempts": int,
+///
+///         // The backoff before the first retry, in milliseconds. This doubles on each retry, up to MaxDelay, and
+///         // the actual delay is a random fraction of it so callers that failed together don't retry together.
+///         // The default is the RetryBaseDelayMillis environment variable, or 200.
+///         "BaseDelay": int,
+///
+///         // The longest backoff, in milliseconds. The default is the RetryMaxDelayMillis environment variable, or
+///         // 20000.
+///         "MaxDelay": int,
+///
+///         // The classes of errors to retry: "Throttling" (AWS throttling and request limit errors),
+///         // "ServerError" (5xx responses from AWS or the ACME server), and "Network" (connection failures and
+///         // timeouts). The default is the RetryOn environment variable (a comma-separated list), or
+///         // ["Throttling"].
+///         "RetryOn": [str, ...],
+///     }
+///
+/// A certificate request's "Retry" applies to the calls made while processing it; other calls use the policy set by
+/// the environment. ACME rate limits are never retried this way; see RateLimitRetryScheduler.
+#[derive(Clone, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct RetryConfig {
+    #[serde(rename = "MaxAttempts", default = "default_max_attempts")]
+    pub max_attempts: u32,
+
+    #[serde(rename = "BaseDelay", default = "default_base_delay")]
+    pub base_delay: u64,
+
+    #[serde(rename = "MaxDelay", default = "default_max_delay")]
+    pub max_delay: u64,
+
+    #[serde(rename = "RetryOn", default = "default_retry_on")]
+    pub retry_on: Vec<RetryableError>,
+}
+
+fn default_max_attempts() -> u32 {
+    DEFAULT_RETRY_CONFIG.max_attempts
+}
+
+fn default_base_delay() -> u64 {
+    DEFAULT_RETRY_CONFIG.base_delay
+}
+
+fn default_max_delay() -> u64 {
+    DEFAULT_RETRY_CONFIG.max_delay
+}
+
+fn default_retry_on() -> Vec<RetryableError> {
+    DEFAULT_RETRY_CONFIG.retry_on.clone()
+}
+
+/// A class of transient errors that can be retried.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum RetryableError {
+    Throttling,
+    ServerError,
+    Network,
+}
+
+impl FromStr for RetryableError {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Throttling" => Ok(Self::Throttling),
+            "ServerError" => Ok(Self::ServerError),
+            "Network" => Ok(Self::Network),
+            _ => Err(format!("Unknown error class: {}", s)),
+        }
+    }
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        DEFAULT_RETRY_CONFIG.clone()
+    }
+}
+
+impl RetryConfig {
+    fn from_env() -> Self {
+        let retry_on = match var(ENV_RETRY_ON) {
+            Err(_) => vec![RetryableError::Throttling],
+            Ok(value) => match value.split(',').map(str::trim).filter(|c| !c.is_empty()).map(str::parse).collect() {
+                Ok(retry_on) => retry_on,
+                Err(e) => {
+                    warn!("Ignoring invalid {} setting {:?}: {}", ENV_RETRY_ON, value, e);
+                    vec![RetryableError::Throttling]
+                }
+            },
+        };
+
+        let config = Self {
+            max_attempts: env_setting(ENV_RETRY_MAX_ATTEMPTS, DEFAULT_RETRY_MAX_ATTEMPTS),
+            base_delay: env_setting(ENV_RETRY_BASE_DELAY_MILLIS, DEFAULT_RETRY_BASE_DELAY_MILLIS),
+            max_delay: env_setting(ENV_RETRY_MAX_DELAY_MILLIS, DEFAULT_RETRY_MAX_DELAY_MILLIS),
+            retry_on,
+        };
+
+        match config.validate() {
+            Ok(()) => config,
+            Err(e) => {
+                warn!("Ignoring invalid retry settings in the environment: {}", e);
+                Self {
+                    max_attempts: DEFAULT_RETRY_MAX_ATTEMPTS,
+                    base_delay: DEFAULT_RETRY_BASE_DELAY_MILLIS,
+                    max_delay: DEFAULT_RETRY_MAX_DELAY_MILLIS,
+                    ..config
+                }
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        if self.max_attempts == 0 || self.max_attempts > MAX_RETRY_ATTEMPTS {
+            return Err(InvalidCertificateRequest::invalid_retry_config(format!(
+                "MaxAttempts must be between 1 and {}",
+                MAX_RETRY_ATTEMPTS
+            )));
+        }
+
+        if self.base_delay == 0 || self.max_delay < self.base_delay {
+            return Err(InvalidCertificateRequest::invalid_retry_config(
+                "BaseDelay must be positive and no greater than MaxDelay",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The backoff ceiling before the given retry (1 for the first retry).
+    fn backoff(&self, retry: u32) -> Duration {
+        let max_delay = Duration::from_millis(self.max_delay);
+        Duration::from_millis(self.base_delay)
+            .checked_mul(1 << retry.saturating_sub(1).min(16))
+            .unwrap_or(max_delay)
+            .min(max_delay)
+    }
+
+    /// Indicates whether an error of the given class (if it has one) should be retried.
+    fn retries(&self, class: Option<RetryableError>) -> bool {
+        match class {
+            Some(class) => self.retry_on.contains(&class),
+            None => false,
+        }
+    }
+}
+
+/// Read a positive setting from the environment, falling back to the default if it's unset or invalid.
+pub fn env_setting<T: FromStr + PartialOrd + Default>(name: &str, default: T) -> T {
+    match var(name) {
+        Err(_) => default,
+        Ok(value) => match value.trim().parse::<T>() {
+            Ok(parsed) if parsed > T::default() => parsed,
+            _ => {
+                warn!("Ignoring invalid {} setting {:?}", name, value);
+                default
+            }
+        },
+    }
+}
+
+/// Pick a random delay between zero and the ceiling ("full jitter").
+fn jitter(ceiling: Duration) -> Duration {
+    let mut buf = [0u8; 4];
+    if SystemRandom::new().fill(&mut buf).is_err() {
+        return ceiling;
+    }
+
+    ceiling.mul_f64(u32::from_be_bytes(buf) as f64 / u32::MAX as f64)
+}
+
+/// Returns the retry policy in effect: the current request's, or the environment's.
+pub fn current_retry_config() -> RetryConfig {
+    REQUEST_RETRY_CONFIG.try_with(RetryConfig::clone).unwrap_or_default()
+}
+
+/// Run a future with a request's retry policy in effect.
+pub async fn with_retry_config<F: Future>(config: RetryConfig, f: F) -> F::Output {
+    REQUEST_RETRY_CONFIG.scope(config, f).await
+}
+
+/// Classify an AWS error.
+pub fn classify_aws_error<E>(error: &RusotoError<E>) -> Option<RetryableError> {
+    match error {
+        _ if is_throttling_error(error) => Some(RetryableError::Throttling),
+        RusotoError::HttpDispatch(_) => Some(RetryableError::Network),
+        RusotoError::Unknown(response) if response.status.is_server_error() => Some(RetryableError::ServerError),
+        _ => None,
+    }
+}
+
+/// Classify an ACME error. Problem documents with a 5xx status are server errors; transport failures are network
+/// errors if the connection couldn't be made or timed out.
+pub fn classify_acme_error(error: &AcmeError) -> Option<RetryableError> {
+    if let AcmeError::Server(problem) = error {
+        return match problem.status {
+            Some(status) if status >= 500 => Some(RetryableError::ServerError),
+            _ => None,
+        };
+    }
+
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let transport = cause
+            .downcast_ref::<reqwest::Error>()
+            .or_else(|| cause.downcast_ref::<Box<reqwest::Error>>().map(|e| e.as_ref()));
+        if let Some(e) = transport {
+            return if e.is_connect() || e.is_timeout() {
+                Some(RetryableError::Network)
+            } else {
+                None
+            };
+        }
+
+        source = cause.source();
+    }
+
+    None
+}
+
+/// Make a call, retrying it according to the retry policy in effect if it fails with a retryable error. `f` creates
+/// the call's future, and is called again for each attempt.
+pub async fn retry<T, E, F, Fut, C>(operation: &str, classify: C, mut f: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    C: Fn(&E) -> Option<RetryableError>,
+{
+    let config = current_retry_config();
+    let mut attempt = 1;
+
+    loop {
+        match f().await {
+            Err(e) if attempt < config.max_attempts && config.retries(classify(&e)) => {
+                let delay = jitter(config.backoff(attempt));
+                debug!(
+                    "{} failed ({:?}); retrying in {}ms (attempt {} of {})",
+                    operation,
+                    classify(&e),
+                    delay.as_millis(),
+                    attempt + 1,
+                    config.max_attempts
+                );
+                attempt += 1;
+                sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{current_retry_config, jitter, with_retry_config, RetryConfig, RetryableError},
+        serde_json::json,
+        std::time::Duration,
+    };
+
+    #[test]
+    fn test_retry_config() {
+        let config: RetryConfig = serde_json::from_value(json!({
+            "MaxAttempts": 4,
+            "BaseDelay": 100,
+            "MaxDelay": 1000,
+            "RetryOn": ["Throttling", "ServerError"],
+        }))
+        .unwrap();
+        assert!(config.validate().is_ok());
+
+        assert_eq!(config.backoff(1), Duration::from_millis(100));
+        assert_eq!(config.backoff(2), Duration::from_millis(200));
+        assert_eq!(config.backoff(4), Duration::from_millis(800));
+        assert_eq!(config.backoff(5), Duration::from_millis(1000));
+        assert_eq!(config.backoff(100), Duration::from_millis(1000));
+
+        assert!(config.retries(Some(RetryableError::ServerError)));
+        assert!(!config.retries(Some(RetryableError::Network)));
+        assert!(!config.retries(None));
+
+        for _ in 0..100 {
+            assert!(jitter(Duration::from_millis(400)) <= Duration::from_millis(400));
+        }
+
+        let defaults: RetryConfig = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(defaults, RetryConfig::default());
+
+        assert!(serde_json::from_value::<RetryConfig>(json!({"RetryOn": ["Everything"]})).is_err());
+        assert!(serde_json::from_value::<RetryConfig>(json!({"MaxAttempts": 0})).unwrap().validate().is_err());
+        assert!(serde_json::from_value::<RetryConfig>(json!({"BaseDelay": 500, "MaxDelay": 100}))
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_request_retry_config() {
+        let config = RetryConfig {
+            max_attempts: 2,
+            ..Default::default()
+        };
+
+        assert_eq!(current_retry_config(), RetryConfig::default());
+        assert_eq!(with_retry_config(config.clone(), async { current_retry_config() }).await, config);
+    }
+}
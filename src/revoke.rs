@@ -0,0 +1,235 @@
+use {
+    crate::{
+        acme_gateway::AcmeGateway,
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        jws::{new_nonce, sign_jws, REPLAY_NONCE},
+        reconcile::{ActualState, ObservedCertificate},
+        utils::{normalize_serial, now_epoch_secs},
+    },
+    lambda_runtime::Error as LambdaError,
+    log::{error, info, warn},
+    openssl::{pkey::PKey, x509::X509},
+    reqwest::header::CONTENT_TYPE,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::{json, Value},
+};
+
+/// How many times to retry a revocation rejected with a badNonce error, each time with the fresh nonce returned.
+const MAX_BAD_NONCE_RETRIES: usize = 3;
+
+const ACME_ERROR_ALREADY_REVOKED: &str = "urn:ietf:params:acme:error:alreadyRevoked";
+const ACME_ERROR_BAD_NONCE: &str = "urn:ietf:params:acme:error:badNonce";
+
+/// Which certificate to revoke for the Revoke action, and why. In JSON:
+///
+///     {
+///         // The serial number of the certificate to revoke, in hex. A storage target holding the certificate and
+///         // its private key must still be configured. This defaults to the certificate the storage targets
+///         // currently hold.
+///         "Serial": str,
+///
+///         // The reason for revocation: "Unspecified" (the default), "KeyCompromise", "AffiliationChanged",
+///         // "Superseded", or "CessationOfOperation".
+///         "Reason": str,
+///     }
+///
+/// The revocation request is signed with the certificate's private key (re-read from the storage target) rather
+/// than the account key, so certificates issued under another account can also be revoked.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct RevocationConfig {
+    #[serde(rename = "Serial", default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+
+    #[serde(rename = "Reason", default)]
+    pub reason: RevocationReason,
+}
+
+/// The CRL reason codes accepted by ACME servers (RFC 5280, section 5.3.1).
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum RevocationReason {
+    #[default]
+    Unspecified,
+    KeyCompromise,
+    AffiliationChanged,
+    Superseded,
+    CessationOfOperation,
+}
+
+impl RevocationReason {
+    fn code(&self) -> u8 {
+        match self {
+            Self::Unspecified => 0,
+            Self::KeyCompromise => 1,
+            Self::AffiliationChanged => 3,
+            Self::Superseded => 4,
+            Self::CessationOfOperation => 5,
+        }
+    }
+}
+
+/// The result of revoking a certificate. In JSON:
+///
+///     {
+///         // The serial number and expiration time (seconds since the Unix epoch) of the revoked certificate.
+///         "Serial": str,
+///         "NotAfter": int,
+///
+///         // Where the certificate and private key were read from.
+///         "Location": str,
+///
+///         // The reason given for revocation.
+///         "Reason": str,
+///
+///         // When the certificate was revoked (seconds since the Unix epoch).
+///         "RevokedAt": int,
+///
+///         // True if the ACME server reported that the certificate had already been revoked.
+///         "AlreadyRevoked": bool,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct RevocationResult {
+    #[serde(rename = "Serial")]
+    pub serial: String,
+
+    #[serde(rename = "NotAfter")]
+    pub not_after: i64,
+
+    #[serde(rename = "Location")]
+    pub location: String,
+
+    #[serde(rename = "Reason")]
+    pub reason: RevocationReason,
+
+    #[serde(rename = "RevokedAt")]
+    pub revoked_at: i64,
+
+    #[serde(rename = "AlreadyRevoked")]
+    pub already_revoked: bool,
+}
+
+impl RevocationConfig {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        if let Some(serial) = &self.serial {
+            if serial.is_empty() || !serial.chars().all(|c| c.is_ascii_hexdigit() || c == ':') {
+                return Err(InvalidCertificateRequest::invalid_revocation(format!("Invalid Serial: {}", serial)));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Choose the certificate to revoke from what the storage targets hold. Only targets holding the private key
+    /// qualify, since it's needed to sign the request.
+    pub fn select<'a>(&self, actual: &'a ActualState) -> Option<&'a ObservedCertificate> {
+        let serial = self.serial.as_deref().map(normalize_serial);
+        let mut candidates = actual
+            .targets
+            .iter()
+            .filter_map(|target| target.as_ref().ok().and_then(Option::as_ref))
+            .filter(|observed| observed.components.is_some());
+
+        match serial {
+            Some(serial) => candidates.find(|observed| observed.info.serial == serial),
+            None => candidates.max_by_key(|observed| observed.info.not_after),
+        }
+    }
+
+    /// Revoke the certificate through the ACME server's revokeCert endpoint.
+    pub async fn revoke(
+        &self,
+        directory: &str,
+        observed: &ObservedCertificate,
+    ) -> Result<RevocationResult, LambdaError> {
+        let components = observed.components.as_ref().expect("Revocation target must hold the private key");
+        let cert = X509::from_pem(components.cert_pem.as_bytes())?;
+        let pkey = PKey::private_key_from_pem(components.pkey_pem.as_bytes())?;
+
+        let gateway = AcmeGateway::from_env()?;
+        let client = gateway.http_client().await?.unwrap_or_default();
+        let directory: Value =
+            serde_json::from_str(&client.get(gateway.rewrite_directory(directory)).send().await?.text().await?)?;
+        let (new_nonce_url, revoke_url) = match (directory["newNonce"].as_str(), directory["revokeCert"].as_str()) {
+            (Some(new_nonce_url), Some(revoke_url)) => (new_nonce_url, revoke_url),
+            _ => return Err(CertificateRequestError::revocation_failed("The ACME directory has no revokeCert URL")),
+        };
+
+        let payload = json!({
+            "certificate": base64::encode_config(cert.to_der()?, base64::URL_SAFE_NO_PAD),
+            "reason": self.reason.code(),
+        });
+
+        info!("Revoking certificate {} from {} ({:?})", observed.info.serial, observed.location, self.reason);
+        let mut nonce = new_nonce(&client, new_nonce_url).await?;
+        let mut attempt = 0;
+
+        let already_revoked = loop {
+            let body = sign_jws(&pkey, revoke_url, &nonce, &payload)?;
+            let response = client
+                .post(revoke_url)
+                .header(CONTENT_TYPE, "application/jose+json")
+                .body(body.to_string())
+                .send()
+                .await?;
+            let status = response.status();
+            let next_nonce = response.headers().get(REPLAY_NONCE).and_then(|n| n.to_str().ok()).map(str::to_string);
+
+            if status.is_success() {
+                break false;
+            }
+
+            let problem: Value = serde_json::from_str(&response.text().await?).unwrap_or(Value::Null);
+            match (problem["type"].as_str(), next_nonce) {
+                (Some(ACME_ERROR_ALREADY_REVOKED), _) => {
+                    warn!("Certificate {} was already revoked", observed.info.serial);
+                    break true;
+                }
+                (Some(ACME_ERROR_BAD_NONCE), Some(next_nonce)) if attempt < MAX_BAD_NONCE_RETRIES => {
+                    attempt += 1;
+                    nonce = next_nonce;
+                }
+                _ => {
+                    let detail = problem["detail"].as_str().unwrap_or_default();
+                    error!("Failed to revoke certificate {}: HTTP {}: {}", observed.info.serial, status, detail);
+                    return Err(CertificateRequestError::revocation_failed(format!(
+                        "Certificate {}: HTTP {}: {}",
+                        observed.info.serial, status, detail
+                    )));
+                }
+            }
+        };
+
+        Ok(RevocationResult {
+            serial: observed.info.serial.clone(),
+            not_after: observed.info.not_after,
+            location: observed.location.clone(),
+            reason: self.reason,
+            revoked_at: now_epoch_secs(),
+            already_revoked,
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{RevocationConfig, RevocationReason},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_revocation_config() {
+        let config: RevocationConfig = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(config.reason, RevocationReason::Unspecified);
+        assert!(config.validate().is_ok());
+
+        let config: RevocationConfig =
+            serde_json::from_value(json!({"Serial": "03:A1:FF", "Reason": "KeyCompromise"})).unwrap();
+        assert_eq!(config.reason.code(), 1);
+        assert!(config.validate().is_ok());
+
+        let config: RevocationConfig = serde_json::from_value(json!({"Serial": "not-a-serial"})).unwrap();
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,510 @@
+use {
+    crate::{
+        constants::{DEFAULT_SCAFFOLD_CODE_PATH, DEFAULT_SCAFFOLD_FUNCTION_NAME, DEFAULT_SCAFFOLD_SCHEDULE},
+        errors::CertificateRequestError,
+        events::Request,
+        policy::PolicyBuilder,
+    },
+    lambda_runtime::Error as LambdaError,
+    serde_json::{json, Value},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        fs::read_to_string,
+        io::{stdin, Read},
+        str::FromStr,
+    },
+};
+
+const SCAFFOLD_USAGE: &str = "Usage: letsencrypt-certs-aws scaffold --format sam|cdk|terraform \
+                              [--schedule EXPRESSION] [--function-name NAME] [--code PATH] REQUEST_FILE";
+
+/// The memory size, in MB, and timeout, in seconds, of the generated function.
+const FUNCTION_MEMORY_SIZE: u32 = 512;
+const FUNCTION_TIMEOUT: u32 = 900;
+
+const BASIC_EXECUTION_POLICY_ARN: &str = "arn:aws:iam::aws:policy/service-role/AWSLambdaBasicExecutionRole";
+
+/// The infrastructure-as-code tool to generate a stack for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ScaffoldFormat {
+    /// An AWS SAM template, in JSON.
+    Sam,
+
+    /// An AWS CDK stack, in TypeScript.
+    Cdk,
+
+    /// A Terraform configuration, in JSON (a .tf.json file).
+    Terraform,
+}
+
+impl FromStr for ScaffoldFormat {
+    type Err = LambdaError;
+
+    fn from_str(s: &str) -> Result<Self, LambdaError> {
+        if s.eq_ignore_ascii_case("sam") {
+            Ok(Self::Sam)
+        } else if s.eq_ignore_ascii_case("cdk") {
+            Ok(Self::Cdk)
+        } else if s.eq_ignore_ascii_case("terraform") {
+            Ok(Self::Terraform)
+        } else {
+            Err(CertificateRequestError::invalid_scaffold_options(format!("Unknown format: {}", s)))
+        }
+    }
+}
+
+impl Display for ScaffoldFormat {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Sam => f.write_str("sam"),
+            Self::Cdk => f.write_str("cdk"),
+            Self::Terraform => f.write_str("terraform"),
+        }
+    }
+}
+
+/// Options for the scaffold subcommand, which prints a ready-to-deploy stack for a request: the Lambda function,
+/// an EventBridge schedule invoking it with the request, an IAM policy granting what the request needs (see
+/// PolicyBuilder), and an SNS topic the function publishes notifications to.
+#[derive(Debug)]
+pub struct ScaffoldOptions {
+    pub format: ScaffoldFormat,
+
+    /// The EventBridge schedule expression, e.g. "rate(12 hours)" or "cron(0 6 * * ? *)".
+    pub schedule: String,
+
+    pub function_name: String,
+
+    /// The path to the function's deployment package.
+    pub code: String,
+
+    /// The certificate request, batch request, or array of certificate requests to run on the schedule.
+    pub request: Value,
+}
+
+impl ScaffoldOptions {
+    /// Parse the arguments following "scaffold". A request file of "-" is read from standard input.
+    pub fn from_args(args: &[String]) -> Result<Self, LambdaError> {
+        let mut format = None;
+        let mut schedule = DEFAULT_SCAFFOLD_SCHEDULE.to_string();
+        let mut function_name = DEFAULT_SCAFFOLD_FUNCTION_NAME.to_string();
+        let mut code = DEFAULT_SCAFFOLD_CODE_PATH.to_string();
+        let mut request_file = None;
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next().cloned().ok_or_else(|| {
+                    CertificateRequestError::invalid_scaffold_options(format!(
+                        "{} requires a value; {}",
+                        arg, SCAFFOLD_USAGE
+                    ))
+                })
+            };
+
+            match arg.as_str() {
+                "--format" => format = Some(value()?.parse()?),
+                "--schedule" => schedule = value()?,
+                "--function-name" => function_name = value()?,
+                "--code" => code = value()?,
+                _ if arg.starts_with("--") => {
+                    return Err(CertificateRequestError::invalid_scaffold_options(format!(
+                        "Unknown option {}; {}",
+                        arg, SCAFFOLD_USAGE
+                    )))
+                }
+                _ if request_file.is_none() => request_file = Some(arg.clone()),
+                _ => {
+                    return Err(CertificateRequestError::invalid_scaffold_options(format!(
+                        "Only one request file can be given; {}",
+                        SCAFFOLD_USAGE
+                    )))
+                }
+            }
+        }
+
+        let format = format.ok_or_else(|| {
+            CertificateRequestError::invalid_scaffold_options(format!("--format is required; {}", SCAFFOLD_USAGE))
+        })?;
+        let request_file = request_file.ok_or_else(|| {
+            CertificateRequestError::invalid_scaffold_options(format!("A request file is required; {}", SCAFFOLD_USAGE))
+        })?;
+
+        let request = if request_file == "-" {
+            let mut request = String::new();
+            stdin().read_to_string(&mut request)?;
+            request
+        } else {
+            read_to_string(&request_file)?
+        };
+
+        Ok(Self {
+            format,
+            schedule,
+            function_name,
+            code,
+            request: serde_json::from_str(&request)?,
+        })
+    }
+
+    /// Returns the stack as the text of a template or source file.
+    pub fn generate(&self) -> Result<String, LambdaError> {
+        self.validate()?;
+
+        match self.format {
+            ScaffoldFormat::Sam => Ok(serde_json::to_string_pretty(&self.sam_template()?)?),
+            ScaffoldFormat::Cdk => self.cdk_stack(),
+            ScaffoldFormat::Terraform => Ok(serde_json::to_string_pretty(&self.terraform_config()?)?),
+        }
+    }
+
+    fn validate(&self) -> Result<(), LambdaError> {
+        if !(self.schedule.starts_with("rate(") || self.schedule.starts_with("cron(")) || !self.schedule.ends_with(')')
+        {
+            return Err(CertificateRequestError::invalid_scaffold_options(format!(
+                "Schedule must be a rate(...) or cron(...) expression: {}",
+                self.schedule
+            )));
+        }
+
+        if self.function_name.is_empty() {
+            return Err(CertificateRequestError::invalid_scaffold_options("Function name cannot be empty"));
+        }
+
+        match serde_json::from_value::<Request>(self.request.clone()) {
+            Ok(Request::Certificate(_)) | Ok(Request::Batch(_)) | Ok(Request::BatchList(_)) => Ok(()),
+            Ok(_) => Err(CertificateRequestError::invalid_scaffold_options(
+                "The request file must hold a certificate request, batch request, or array of certificate requests",
+            )),
+            Err(e) => Err(CertificateRequestError::invalid_scaffold_options(format!("Invalid request: {}", e))),
+        }
+    }
+
+    fn sam_template(&self) -> Result<Value, LambdaError> {
+        let mut statements = PolicyBuilder::for_request(&self.request).statements();
+        statements.push(json!({
+            "Sid": "NotificationTopic",
+            "Effect": "Allow",
+            "Action": ["sns:Publish"],
+            "Resource": [{"Ref": "CertificateTopic"}],
+        }));
+
+        Ok(json!({
+            "AWSTemplateFormatVersion": "2010-09-09",
+            "Transform": "AWS::Serverless-2016-10-31",
+            "Description": format!("Let's Encrypt certificate renewal for {}", self.function_name),
+            "Resources": {
+                "CertificateTopic": {
+                    "Type": "AWS::SNS::Topic",
+                },
+                "CertificateFunction": {
+                    "Type": "AWS::Serverless::Function",
+                    "Properties": {
+                        "FunctionName": self.function_name,
+                        "CodeUri": self.code,
+                        "Handler": "bootstrap",
+                        "Runtime": "provided.al2",
+                        "MemorySize": FUNCTION_MEMORY_SIZE,
+                        "Timeout": FUNCTION_TIMEOUT,
+                        "Environment": {
+                            "Variables": {
+                                "NotificationTopicArn": {"Ref": "CertificateTopic"},
+                            },
+                        },
+                        "Policies": [{
+                            "Version": "2012-10-17",
+                            "Statement": statements,
+                        }],
+                        "Events": {
+                            "RenewalSchedule": {
+                                "Type": "Schedule",
+                                "Properties": {
+                                    "Schedule": self.schedule,
+                                    "Input": serde_json::to_string(&self.request)?,
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+            "Outputs": {
+                "NotificationTopicArn": {
+                    "Value": {"Ref": "CertificateTopic"},
+                },
+            },
+        }))
+    }
+
+    fn terraform_config(&self) -> Result<Value, LambdaError> {
+        // Strings from the request are embedded in Terraform strings, so interpolation sequences must be escaped.
+        let mut statements =
+            escape_terraform_value(Value::Array(PolicyBuilder::for_request(&self.request).statements()));
+        if let Value::Array(statements) = &mut statements {
+            statements.push(json!({
+                "Sid": "NotificationTopic",
+                "Effect": "Allow",
+                "Action": ["sns:Publish"],
+                "Resource": ["${aws_sns_topic.certificates.arn}"],
+            }));
+        }
+
+        let policy = json!({"Version": "2012-10-17", "Statement": statements});
+        let assume_role_policy = json!({
+            "Version": "2012-10-17",
+            "Statement": [{
+                "Effect": "Allow",
+                "Principal": {"Service": "lambda.amazonaws.com"},
+                "Action": "sts:AssumeRole",
+            }],
+        });
+
+        let function_name = escape_terraform(&self.function_name);
+
+        Ok(json!({
+            "resource": {
+                "aws_sns_topic": {
+                    "certificates": {
+                        "name": format!("{}-notifications", function_name),
+                    },
+                },
+                "aws_iam_role": {
+                    "certificates": {
+                        "name": function_name,
+                        "assume_role_policy": serde_json::to_string(&assume_role_policy)?,
+                    },
+                },
+                "aws_iam_role_policy": {
+                    "certificates": {
+                        "name": function_name,
+                        "role": "${aws_iam_role.certificates.id}",
+                        "policy": serde_json::to_string(&policy)?,
+                    },
+                },
+                "aws_iam_role_policy_attachment": {
+                    "certificates_basic_execution": {
+                        "role": "${aws_iam_role.certificates.name}",
+                        "policy_arn": BASIC_EXECUTION_POLICY_ARN,
+                    },
+                },
+                "aws_lambda_function": {
+                    "certificates": {
+                        "function_name": function_name,
+                        "filename": escape_terraform(&self.code),
+                        "handler": "bootstrap",
+                        "runtime": "provided.al2",
+                        "memory_size": FUNCTION_MEMORY_SIZE,
+                        "timeout": FUNCTION_TIMEOUT,
+                        "role": "${aws_iam_role.certificates.arn}",
+                        "environment": {
+                            "variables": {
+                                "NotificationTopicArn": "${aws_sns_topic.certificates.arn}",
+                            },
+                        },
+                    },
+                },
+                "aws_cloudwatch_event_rule": {
+                    "certificates": {
+                        "name": function_name,
+                        "schedule_expression": escape_terraform(&self.schedule),
+                    },
+                },
+                "aws_cloudwatch_event_target": {
+                    "certificates": {
+                        "rule": "${aws_cloudwatch_event_rule.certificates.name}",
+                        "arn": "${aws_lambda_function.certificates.arn}",
+                        "input": escape_terraform(&serde_json::to_string(&self.request)?),
+                    },
+                },
+                "aws_lambda_permission": {
+                    "certificates": {
+                        "statement_id": "AllowEventBridgeInvoke",
+                        "action": "lambda:InvokeFunction",
+                        "function_name": "${aws_lambda_function.certificates.function_name}",
+                        "principal": "events.amazonaws.com",
+                        "source_arn": "${aws_cloudwatch_event_rule.certificates.arn}",
+                    },
+                },
+            },
+            "output": {
+                "notification_topic_arn": {
+                    "value": "${aws_sns_topic.certificates.arn}",
+                },
+            },
+        }))
+    }
+
+    fn cdk_stack(&self) -> Result<String, LambdaError> {
+        // JSON values and strings are valid TypeScript expressions, so they are embedded as-is.
+        let request = serde_json::to_string_pretty(&self.request)?;
+        let statements = serde_json::to_string_pretty(&PolicyBuilder::for_request(&self.request).statements())?;
+
+        Ok(format!(
+            r#"import {{ Duration, Stack, StackProps }} from "aws-cdk-lib";
+import * as events from "aws-cdk-lib/aws-events";
+import * as targets from "aws-cdk-lib/aws-events-targets";
+import * as iam from "aws-cdk-lib/aws-iam";
+import * as lambda from "aws-cdk-lib/aws-lambda";
+import * as sns from "aws-cdk-lib/aws-sns";
+import {{ Construct }} from "constructs";
+
+const request = {request};
+
+const statements = {statements};
+
+export class CertificateStack extends Stack {{
+  constructor(scope: Construct, id: string, props?: StackProps) {{
+    super(scope, id, props);
+
+    const topic = new sns.Topic(this, "CertificateTopic");
+
+    const fn = new lambda.Function(this, "CertificateFunction", {{
+      functionName: {function_name},
+      code: lambda.Code.fromAsset({code}),
+      handler: "bootstrap",
+      runtime: lambda.Runtime.PROVIDED_AL2,
+      memorySize: {memory_size},
+      timeout: Duration.seconds({timeout}),
+      environment: {{ NotificationTopicArn: topic.topicArn }},
+    }});
+
+    topic.grantPublish(fn);
+    for (const statement of statements) {{
+      fn.addToRolePolicy(iam.PolicyStatement.fromJson(statement));
+    }}
+
+    new events.Rule(this, "RenewalSchedule", {{
+      schedule: events.Schedule.expression({schedule}),
+      targets: [new targets.LambdaFunction(fn, {{ event: events.RuleTargetInput.fromObject(request) }})],
+    }});
+  }}
+}}
+"#,
+            request = request,
+            statements = statements,
+            function_name = serde_json::to_string(&self.function_name)?,
+            code = serde_json::to_string(&self.code)?,
+            memory_size = FUNCTION_MEMORY_SIZE,
+            timeout = FUNCTION_TIMEOUT,
+            schedule = serde_json::to_string(&self.schedule)?,
+        ))
+    }
+}
+
+/// Run the scaffold subcommand with the arguments following "scaffold", printing the generated stack.
+pub fn run_scaffold(args: &[String]) -> Result<(), LambdaError> {
+    let options = ScaffoldOptions::from_args(args)?;
+    println!("{}", options.generate()?);
+    Ok(())
+}
+
+/// Escape Terraform's interpolation ("${") and directive ("%{") sequences in a string.
+fn escape_terraform(s: &str) -> String {
+    s.replace("${", "$${").replace("%{", "%%{")
+}
+
+/// Escape every string in a JSON value; see escape_terraform.
+fn escape_terraform_value(value: Value) -> Value {
+    match value {
+        Value::String(s) => Value::String(escape_terraform(&s)),
+        Value::Array(values) => Value::Array(values.into_iter().map(escape_terraform_value).collect()),
+        Value::Object(fields) => {
+            Value::Object(fields.into_iter().map(|(k, v)| (k, escape_terraform_value(v))).collect())
+        }
+        value => value,
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{ScaffoldFormat, ScaffoldOptions},
+        serde_json::{json, Value},
+    };
+
+    fn options(format: ScaffoldFormat) -> ScaffoldOptions {
+        ScaffoldOptions {
+            format,
+            schedule: "rate(1 day)".to_string(),
+            function_name: "certs".to_string(),
+            code: "bootstrap.zip".to_string(),
+            request: json!({
+                "Directory": "https://acme-staging-v02.api.letsencrypt.org/directory",
+                "DomainNames": ["example.com"],
+                "Contacts": ["mailto:hello@example.com"],
+                "Authorization": {"Type": "DnsRoute53"},
+                "Storage": [{"Type": "S3", "Bucket": "certs", "Prefix": "${Domain}/"}],
+            }),
+        }
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>();
+
+        assert_eq!("Terraform".parse::<ScaffoldFormat>().unwrap(), ScaffoldFormat::Terraform);
+        assert!("pulumi".parse::<ScaffoldFormat>().is_err());
+        assert!(ScaffoldOptions::from_args(&args(&["request.json"])).is_err());
+        assert!(ScaffoldOptions::from_args(&args(&["--format", "sam"])).is_err());
+        assert!(ScaffoldOptions::from_args(&args(&["--format"])).is_err());
+        assert!(ScaffoldOptions::from_args(&args(&["--format", "sam", "--region", "us-east-1", "a.json"])).is_err());
+    }
+
+    #[test]
+    fn test_sam() {
+        let template: Value = serde_json::from_str(&options(ScaffoldFormat::Sam).generate().unwrap()).unwrap();
+        let function = &template["Resources"]["CertificateFunction"]["Properties"];
+
+        assert_eq!(template["Resources"]["CertificateTopic"]["Type"], json!("AWS::SNS::Topic"));
+        assert_eq!(function["Environment"]["Variables"]["NotificationTopicArn"], json!({"Ref": "CertificateTopic"}));
+        assert_eq!(function["Events"]["RenewalSchedule"]["Properties"]["Schedule"], json!("rate(1 day)"));
+
+        let input: Value =
+            serde_json::from_str(function["Events"]["RenewalSchedule"]["Properties"]["Input"].as_str().unwrap())
+                .unwrap();
+        assert_eq!(input, options(ScaffoldFormat::Sam).request);
+
+        let statements = function["Policies"][0]["Statement"].as_array().unwrap();
+        assert!(statements.iter().any(|statement| statement["Sid"] == json!("Route53")));
+        assert!(statements.iter().any(|statement| statement["Resource"] == json!([{"Ref": "CertificateTopic"}])));
+    }
+
+    #[test]
+    fn test_terraform() {
+        let config: Value = serde_json::from_str(&options(ScaffoldFormat::Terraform).generate().unwrap()).unwrap();
+        let resources = &config["resource"];
+
+        let input = resources["aws_cloudwatch_event_target"]["certificates"]["input"].as_str().unwrap();
+        assert!(input.contains("$${Domain}/"));
+
+        let policy: Value =
+            serde_json::from_str(resources["aws_iam_role_policy"]["certificates"]["policy"].as_str().unwrap()).unwrap();
+        let statements = policy["Statement"].as_array().unwrap();
+        let s3 = statements.iter().find(|statement| statement["Sid"] == json!("S3")).unwrap();
+        assert!(s3["Resource"].as_array().unwrap().contains(&json!("arn:aws:s3:::certs/$${Domain}/*")));
+        assert_eq!(statements.last().unwrap()["Resource"], json!(["${aws_sns_topic.certificates.arn}"]));
+
+        assert_eq!(resources["aws_lambda_function"]["certificates"]["function_name"], json!("certs"));
+        assert_eq!(resources["aws_cloudwatch_event_rule"]["certificates"]["schedule_expression"], json!("rate(1 day)"));
+    }
+
+    #[test]
+    fn test_cdk() {
+        let stack = options(ScaffoldFormat::Cdk).generate().unwrap();
+        assert!(stack.contains("events.Schedule.expression(\"rate(1 day)\")"));
+        assert!(stack.contains("functionName: \"certs\""));
+        assert!(stack.contains("\"DomainNames\": ["));
+        assert!(stack.contains("iam.PolicyStatement.fromJson(statement)"));
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let mut invalid = options(ScaffoldFormat::Sam);
+        invalid.schedule = "every day".to_string();
+        assert!(invalid.generate().is_err());
+
+        let mut invalid = options(ScaffoldFormat::Sam);
+        invalid.request = json!({"Action": "Schema"});
+        assert!(invalid.generate().is_err());
+    }
+}
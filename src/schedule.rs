@@ -0,0 +1,149 @@
+use {
+    crate::{
+        constants::{ENV_RENEWAL_SCHEDULE_TARGET_ARN, RENEWAL_RULE_PREFIX},
+        errors::InvalidCertificateRequest,
+        rate_limits::{domain_rule_name, format_utc, put_one_time_rule},
+        reconcile::ActualState,
+    },
+    lambda_runtime::Error as LambdaError,
+    log::info,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    serde_json::Value,
+    std::env::var,
+};
+
+/// Keeps a one-time EventBridge rule per certificate that invokes this function when the certificate is due for
+/// renewal. The Schedule action creates the rule, and each renewal it starts moves the rule to the next renewal
+/// time, so the schedule follows the certificate's actual lifetime (e.g. 60 days after issuance for a 90-day
+/// certificate and the default RenewalThresholdDays) instead of a hand-written cron expression.
+///
+/// This is enabled by setting the `RenewalScheduleTargetArn` environment variable to the ARN of the function; the
+/// function needs a resource policy allowing events.amazonaws.com to invoke it from rules named
+/// `letsencrypt-renew-*`.
+#[derive(Clone, Debug)]
+pub struct RenewalScheduler {
+    target_arn: String,
+}
+
+impl RenewalScheduler {
+    pub fn from_env() -> Option<Self> {
+        let target_arn = var(ENV_RENEWAL_SCHEDULE_TARGET_ARN).ok().filter(|arn| !arn.is_empty())?;
+        Some(Self {
+            target_arn,
+        })
+    }
+
+    /// Returns the scheduler, failing if it isn't configured.
+    pub fn required() -> Result<Self, LambdaError> {
+        match Self::from_env() {
+            Some(scheduler) => Ok(scheduler),
+            None => Err(InvalidCertificateRequest::invalid_renewal_schedule(format!(
+                "The {} environment variable must be set to the ARN of this function",
+                ENV_RENEWAL_SCHEDULE_TARGET_ARN
+            ))),
+        }
+    }
+
+    /// Create or move the renewal rule for a certificate so it fires at (or just after) renew_at. The rule invokes
+    /// the function with the request as originally submitted, changed to the Issue action with ScheduleRenewal set so
+    /// the renewal reschedules itself.
+    pub async fn schedule(
+        &self,
+        domain_names: &[String],
+        renew_at: i64,
+        original: &Value,
+    ) -> Result<RenewalSchedule, LambdaError> {
+        let rule_name = domain_rule_name(RENEWAL_RULE_PREFIX, domain_names);
+        let mut request = original.clone();
+        if let Value::Object(map) = &mut request {
+            map.insert("Action".to_string(), Value::String("Issue".to_string()));
+            map.insert("ScheduleRenewal".to_string(), Value::Bool(true));
+        }
+
+        let description = format!("Renew certificate for {}", domain_names.join(" "));
+        let schedule_expression =
+            put_one_time_rule(&rule_name, description, renew_at, "Renew", &self.target_arn, &request).await?;
+        info!("Scheduled renewal of {} with rule {} at {}", domain_names.join(" "), rule_name, schedule_expression);
+
+        Ok(RenewalSchedule {
+            rule_name,
+            renew_at: format_utc(renew_at),
+            schedule_expression,
+        })
+    }
+}
+
+/// The renewal rule for a certificate. In JSON:
+///
+///     {
+///         // The name of the EventBridge rule.
+///         "RuleName": str,
+///
+///         // When the rule will invoke the function to renew the certificate, as an RFC 3339 UTC timestamp.
+///         "RenewAt": str,
+///
+///         // The rule's schedule expression.
+///         "ScheduleExpression": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct RenewalSchedule {
+    #[serde(rename = "RuleName")]
+    pub rule_name: String,
+
+    #[serde(rename = "RenewAt")]
+    pub renew_at: String,
+
+    #[serde(rename = "ScheduleExpression")]
+    pub schedule_expression: String,
+}
+
+/// Returns when the certificate should next be renewed, in seconds since the Unix epoch: renewal_threshold_days
+/// before the earliest expiration among the storage targets, but no earlier than `earliest`. A target without a
+/// (readable) certificate makes the renewal due immediately.
+pub fn next_renewal(actual: &ActualState, renewal_threshold_days: i64, earliest: i64) -> i64 {
+    let expirations: Vec<Option<i64>> = actual
+        .targets
+        .iter()
+        .map(|target| match target {
+            Ok(Some(observed)) => Some(observed.info.not_after),
+            _ => None,
+        })
+        .collect();
+
+    renewal_time(&expirations, renewal_threshold_days, earliest)
+}
+
+fn renewal_time(expirations: &[Option<i64>], renewal_threshold_days: i64, earliest: i64) -> i64 {
+    if expirations.iter().any(Option::is_none) {
+        return earliest;
+    }
+
+    expirations
+        .iter()
+        .flatten()
+        .map(|not_after| not_after - renewal_threshold_days * 86400)
+        .min()
+        .unwrap_or(earliest)
+        .max(earliest)
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::renewal_time;
+
+    #[test]
+    fn test_renewal_time() {
+        let now = 1_700_000_000;
+        let day = 86400;
+
+        // The earliest expiration drives the renewal.
+        assert_eq!(renewal_time(&[Some(now + 90 * day), Some(now + 80 * day)], 30, now), now + 50 * day);
+
+        // Certificates already within the threshold, and targets without one, are renewed as soon as allowed.
+        assert_eq!(renewal_time(&[Some(now + 10 * day)], 30, now + 300), now + 300);
+        assert_eq!(renewal_time(&[Some(now + 90 * day), None], 30, now + 300), now + 300);
+        assert_eq!(renewal_time(&[], 30, now + 300), now + 300);
+    }
+}
@@ -0,0 +1,463 @@
+use {
+    crate::{
+        batch::{BatchResponse, CertificateBatchRequest},
+        events::{CertificateRequest, CertificateResponse},
+        migrate::migrate,
+    },
+    schemars::{gen::SchemaSettings, JsonSchema},
+    serde::{self, Deserialize, Serialize},
+    serde_json::{json, Map, Value},
+};
+
+/// How many $refs are followed to resolve a schema; schemars never chains more than one.
+const MAX_REF_DEPTH: usize = 8;
+
+/// A request for the JSON schemas of the request and response formats, so that configurations can be validated
+/// (e.g. by IaC tooling or editors) before they're deployed and typed clients can be generated. In JSON:
+///
+///     {
+///         "Action": "Schema"
+///     }
+///
+/// The response is an object holding a JSON Schema (draft 7) document for each format:
+///
+///     {
+///         // A single certificate request. See CertificateRequest.
+///         "Request": {},
+///
+///         // A batch of certificate requests. See CertificateBatchRequest.
+///         "BatchRequest": {},
+///
+///         // The response to a certificate request. See CertificateResponse.
+///         "Response": {},
+///
+///         // The response to a batch request. See BatchResponse.
+///         "BatchResponse": {}
+///     }
+///
+/// Storage types registered at runtime are included; those registered without a schema accept any object.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchemaRequest {
+    #[serde(rename = "Action")]
+    pub action: SchemaAction,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum SchemaAction {
+    Schema,
+}
+
+/// Returns the schemas described in SchemaRequest.
+pub fn request_schemas() -> Value {
+    json!({
+        "Request": root_schema_for::<CertificateRequest>(),
+        "BatchRequest": root_schema_for::<CertificateBatchRequest>(),
+        "Response": root_schema_for::<CertificateResponse>(),
+        "BatchResponse": root_schema_for::<BatchResponse>(),
+    })
+}
+
+fn root_schema_for<T: JsonSchema>() -> Value {
+    let schema = SchemaSettings::draft07().into_generator().into_root_schema_for::<T>();
+    serde_json::to_value(schema).expect("Failed to serialize schema")
+}
+
+/// Check a certificate request, batch request, or array of certificate requests against its schema, returning every
+/// error found, each prefixed with its location in the request (e.g. "Storage[1].Bucket: is required"). Deprecated
+/// fields are migrated first, so requests still using them pass. Other requests (events, Schema, ...) aren't checked
+/// and return no errors.
+pub fn validate_request(request: &Value) -> Vec<String> {
+    let (request, _) = migrate(request.clone());
+    let schema = match &request {
+        Value::Array(_) => root_schema_for::<Vec<CertificateRequest>>(),
+        Value::Object(fields) if fields.contains_key("Certificates") => root_schema_for::<CertificateBatchRequest>(),
+        Value::Object(fields)
+            if ["DomainNames", "Directory", "Storage"].iter().any(|key| fields.contains_key(*key)) =>
+        {
+            root_schema_for::<CertificateRequest>()
+        }
+        _ => return Vec::new(),
+    };
+
+    let validator = SchemaValidator::new(&schema);
+    let mut errors = Vec::new();
+    validator.validate(&schema, &request, "", &mut errors);
+    errors
+}
+
+/// Checks documents against a JSON schema generated by schemars, collecting every error rather than stopping at the
+/// first. Only the keywords schemars generates are supported: $ref (to definitions), type, const, enum, minimum,
+/// maximum, properties, required, additionalProperties, items, allOf, anyOf, and oneOf. oneOf is checked like
+/// anyOf; the variants schemars generates don't overlap.
+struct SchemaValidator<'a> {
+    definitions: Option<&'a Map<String, Value>>,
+}
+
+impl<'a> SchemaValidator<'a> {
+    fn new(root: &'a Value) -> Self {
+        Self {
+            definitions: root.get("definitions").and_then(Value::as_object),
+        }
+    }
+
+    /// Follow $refs to the schema they name.
+    fn resolve(&self, mut schema: &'a Value) -> &'a Value {
+        for _ in 0..MAX_REF_DEPTH {
+            let target = schema
+                .get("$ref")
+                .and_then(Value::as_str)
+                .and_then(|reference| reference.strip_prefix("#/definitions/"))
+                .and_then(|name| self.definitions.and_then(|definitions| definitions.get(name)));
+
+            match target {
+                Some(target) => schema = target,
+                None => break,
+            }
+        }
+
+        schema
+    }
+
+    fn validate(&self, schema: &'a Value, instance: &Value, path: &str, errors: &mut Vec<String>) {
+        let schema = match self.resolve(schema) {
+            Value::Object(schema) => schema,
+            Value::Bool(false) => {
+                errors.push(schema_error(path, "is not allowed"));
+                return;
+            }
+            _ => return,
+        };
+
+        if let Some(types) = schema.get("type") {
+            if !type_matches(types, instance) {
+                errors.push(schema_error(
+                    path,
+                    format!("expected {}, found {}", describe_types(types), type_name(instance)),
+                ));
+                return;
+            }
+        }
+
+        if let Some(expected) = schema.get("const") {
+            if instance != expected {
+                errors.push(schema_error(path, format!("expected {}, found {}", expected, instance)));
+            }
+        }
+
+        if let Some(Value::Array(allowed)) = schema.get("enum") {
+            if !allowed.contains(instance) {
+                errors
+                    .push(schema_error(path, format!("expected one of {}, found {}", join_values(allowed), instance)));
+            }
+        }
+
+        if let Some(n) = instance.as_f64() {
+            if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64).filter(|minimum| n < *minimum) {
+                errors.push(schema_error(path, format!("must be at least {}, found {}", minimum, instance)));
+            }
+
+            if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64).filter(|maximum| n > *maximum) {
+                errors.push(schema_error(path, format!("must be at most {}, found {}", maximum, instance)));
+            }
+        }
+
+        match instance {
+            Value::Object(fields) => self.validate_object(schema, fields, path, errors),
+            Value::Array(elements) => match schema.get("items") {
+                Some(Value::Array(items)) => {
+                    for (i, (item, element)) in items.iter().zip(elements).enumerate() {
+                        self.validate(item, element, &format!("{}[{}]", path, i), errors);
+                    }
+                }
+                Some(items) => {
+                    for (i, element) in elements.iter().enumerate() {
+                        self.validate(items, element, &format!("{}[{}]", path, i), errors);
+                    }
+                }
+                None => (),
+            },
+            _ => (),
+        }
+
+        if let Some(Value::Array(subschemas)) = schema.get("allOf") {
+            for subschema in subschemas {
+                self.validate(subschema, instance, path, errors);
+            }
+        }
+
+        for keyword in &["anyOf", "oneOf"] {
+            if let Some(Value::Array(variants)) = schema.get(*keyword) {
+                self.validate_variants(variants, instance, path, errors);
+            }
+        }
+    }
+
+    fn validate_object(
+        &self,
+        schema: &'a Map<String, Value>,
+        fields: &Map<String, Value>,
+        path: &str,
+        errors: &mut Vec<String>,
+    ) {
+        if let Some(Value::Array(required)) = schema.get("required") {
+            for name in required.iter().filter_map(Value::as_str) {
+                if !fields.contains_key(name) {
+                    errors.push(schema_error(&property_path(path, name), "is required"));
+                }
+            }
+        }
+
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (name, value) in fields {
+            match (properties.and_then(|properties| properties.get(name)), schema.get("additionalProperties")) {
+                (Some(property), _) => self.validate(property, value, &property_path(path, name), errors),
+                (None, Some(Value::Bool(false))) => {
+                    errors.push(schema_error(&property_path(path, name), "is not a recognized setting"))
+                }
+                (None, Some(additional)) => self.validate(additional, value, &property_path(path, name), errors),
+                (None, None) => (),
+            }
+        }
+    }
+
+    /// Check an instance that must match one of several schemas. If none match, the errors reported are those of
+    /// the variant the instance was most likely meant to be: one of the right type, with the same discriminating
+    /// constants (e.g. a storage target's "Type"), and with the fewest errors.
+    fn validate_variants(&self, variants: &'a [Value], instance: &Value, path: &str, errors: &mut Vec<String>) {
+        let mut candidates: Vec<&'a Value> =
+            variants.iter().filter(|variant| self.accepts_type(variant, instance)).collect();
+        if candidates.is_empty() {
+            errors.push(schema_error(path, format!("has an unexpected type: {}", type_name(instance))));
+            return;
+        }
+
+        if let Value::Object(fields) = instance {
+            let discriminators: Vec<Vec<(&'a str, &'a Value)>> =
+                candidates.iter().map(|candidate| self.discriminators(candidate)).collect();
+            let present =
+                discriminators.iter().flatten().map(|(name, _)| *name).find(|name| fields.contains_key(*name));
+
+            if let Some(name) = present {
+                let value = &fields[name];
+                let matches = |d: &Vec<(&str, &Value)>| d.iter().any(|(n, v)| *n == name && *v == value);
+                let matching: Vec<&'a Value> = candidates
+                    .iter()
+                    .zip(&discriminators)
+                    .filter(|(_, d)| matches(d))
+                    .map(|(candidate, _)| *candidate)
+                    .collect();
+
+                if matching.is_empty() {
+                    let expected: Vec<Value> = discriminators
+                        .iter()
+                        .flatten()
+                        .filter(|(n, _)| *n == name)
+                        .map(|(_, v)| (*v).clone())
+                        .collect();
+                    errors.push(schema_error(
+                        &property_path(path, name),
+                        format!("expected one of {}, found {}", join_values(&expected), value),
+                    ));
+                    return;
+                }
+
+                candidates = matching;
+            }
+        }
+
+        let mut best: Option<Vec<String>> = None;
+        for candidate in candidates {
+            let mut candidate_errors = Vec::new();
+            self.validate(candidate, instance, path, &mut candidate_errors);
+            if candidate_errors.is_empty() {
+                return;
+            }
+
+            let better = match &best {
+                Some(best) => candidate_errors.len() < best.len(),
+                None => true,
+            };
+            if better {
+                best = Some(candidate_errors);
+            }
+        }
+
+        errors.extend(best.unwrap_or_default());
+    }
+
+    /// Returns whether a schema allows the instance's JSON type, without checking anything else.
+    fn accepts_type(&self, schema: &'a Value, instance: &Value) -> bool {
+        let schema = match self.resolve(schema) {
+            Value::Object(schema) => schema,
+            Value::Bool(allowed) => return *allowed,
+            _ => return true,
+        };
+
+        if let Some(types) = schema.get("type") {
+            return type_matches(types, instance);
+        }
+
+        let variants = schema.get("anyOf").or_else(|| schema.get("oneOf")).and_then(Value::as_array);
+        match variants {
+            Some(variants) => variants.iter().any(|variant| self.accepts_type(variant, instance)),
+            None => true,
+        }
+    }
+
+    /// Returns the properties of an object schema that must have a single value, e.g. ("Type", "S3").
+    fn discriminators(&self, schema: &'a Value) -> Vec<(&'a str, &'a Value)> {
+        let properties = match self.resolve(schema).get("properties").and_then(Value::as_object) {
+            Some(properties) => properties,
+            None => return Vec::new(),
+        };
+
+        properties
+            .iter()
+            .filter_map(|(name, property)| {
+                let property = self.resolve(property);
+                match (property.get("const"), property.get("enum").and_then(Value::as_array)) {
+                    (Some(value), _) => Some((name.as_str(), value)),
+                    (None, Some(values)) if values.len() == 1 => Some((name.as_str(), &values[0])),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+}
+
+fn schema_error<S: AsRef<str>>(path: &str, msg: S) -> String {
+    if path.is_empty() {
+        format!("Request: {}", msg.as_ref())
+    } else {
+        format!("{}: {}", path, msg.as_ref())
+    }
+}
+
+fn property_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", path, name)
+    }
+}
+
+fn type_matches(types: &Value, instance: &Value) -> bool {
+    match types {
+        Value::String(expected) => type_is(expected, instance),
+        Value::Array(expected) => expected.iter().filter_map(Value::as_str).any(|expected| type_is(expected, instance)),
+        _ => true,
+    }
+}
+
+fn type_is(expected: &str, instance: &Value) -> bool {
+    match expected {
+        "null" => instance.is_null(),
+        "boolean" => instance.is_boolean(),
+        "integer" => instance.is_i64() || instance.is_u64(),
+        "number" => instance.is_number(),
+        "string" => instance.is_string(),
+        "array" => instance.is_array(),
+        "object" => instance.is_object(),
+        _ => true,
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn describe_types(types: &Value) -> String {
+    match types {
+        Value::Array(types) => types.iter().filter_map(Value::as_str).collect::<Vec<&str>>().join(" or "),
+        Value::String(expected) => expected.clone(),
+        _ => "any type".to_string(),
+    }
+}
+
+fn join_values(values: &[Value]) -> String {
+    values.iter().map(Value::to_string).collect::<Vec<String>>().join(", ")
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{request_schemas, validate_request, SchemaRequest},
+        crate::events::Request,
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_schema_request() {
+        let request: Request = serde_json::from_value(json!({"Action": "Schema"})).unwrap();
+        assert!(matches!(request, Request::Schema(_)));
+        assert!(serde_json::from_value::<SchemaRequest>(json!({"Action": "Issue"})).is_err());
+    }
+
+    #[test]
+    fn test_request_schemas() {
+        let schemas = request_schemas();
+        let request = &schemas["Request"];
+        assert!(request["properties"]["Directory"].is_object());
+        assert!(request["required"].as_array().unwrap().contains(&json!("Storage")));
+
+        let storage = &request["definitions"]["CertificateStorage"]["oneOf"];
+        let s3 = storage
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|variant| variant["properties"]["Type"]["const"] == json!("S3"))
+            .expect("S3 storage missing from schema");
+        assert!(s3["properties"]["Bucket"].is_object());
+        assert!(s3["required"].as_array().unwrap().contains(&json!("Type")));
+
+        assert!(schemas["BatchRequest"]["properties"]["Certificates"].is_object());
+        assert!(schemas["Response"]["properties"]["Status"].is_object());
+        assert!(schemas["BatchResponse"]["properties"]["Results"].is_object());
+    }
+
+    #[test]
+    fn test_validate_request() {
+        // Deprecated fields are migrated before the request is checked.
+        let request = json!({
+            "DirectoryUrl": "https://acme-staging-v02.api.letsencrypt.org/directory",
+            "DomainNames": "example.com",
+            "Contacts": ["mailto:hello@example.com"],
+            "Authorization": {"Type": "DnsRoute53"},
+            "Storage": {"Type": "S3", "Bucket": "certs", "Path": "example.com/"},
+        });
+        assert!(validate_request(&request).is_empty());
+
+        let errors = validate_request(&json!({
+            "Directory": "https://acme-staging-v02.api.letsencrypt.org/directory",
+            "DomainNames": ["example.com"],
+            "Authorization": {"Type": "DnsRoute53"},
+            "Storage": [{"Type": "S3", "Bucket": "certs"}, {"Type": "S3"}, {"Type": "S4"}],
+            "RenewalThresholdDays": "thirty",
+        }));
+        assert!(errors.contains(&"Contacts: is required".to_string()), "{:?}", errors);
+        assert!(errors.contains(&"Storage[1].Bucket: is required".to_string()), "{:?}", errors);
+        assert!(errors
+            .iter()
+            .any(|e| e.starts_with("Storage[2].Type: expected one of ") && e.ends_with("found \"S4\"")));
+        assert!(errors.contains(&"RenewalThresholdDays: expected integer, found string".to_string()), "{:?}", errors);
+        assert!(!errors.iter().any(|e| e.starts_with("Storage[0]")), "{:?}", errors);
+
+        // Batches report errors by their position in the batch.
+        let errors = validate_request(&json!({"Certificates": [request, {"DomainNames": 5}]}));
+        assert!(errors.contains(&"Certificates[1].Directory: is required".to_string()), "{:?}", errors);
+        assert!(!errors.iter().any(|e| e.starts_with("Certificates[0]")), "{:?}", errors);
+
+        // Other requests aren't checked.
+        assert!(validate_request(&json!({"Action": "Schema"})).is_empty());
+    }
+}
@@ -0,0 +1,450 @@
+use {
+    crate::{
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        utils::validate_and_sanitize_ssm_parameter_path,
+        xray::aws_client,
+    },
+    async_trait::async_trait,
+    lambda_runtime::Error as LambdaError,
+    log::error,
+    rusoto_core::Region,
+    rusoto_secretsmanager::{GetSecretValueRequest, SecretsManager, SecretsManagerClient},
+    rusoto_ssm::{GetParameterRequest, Ssm, SsmClient},
+    std::{
+        fmt::{Debug, Error as FormatError, Formatter},
+        str::FromStr,
+    },
+};
+
+#[cfg(any(feature = "vault-secrets", feature = "doppler-secrets"))]
+use {reqwest::Client, serde_json::Value};
+
+#[cfg(feature = "doppler-secrets")]
+use crate::constants::ENV_DOPPLER_TOKEN;
+
+#[cfg(feature = "vault-secrets")]
+use crate::constants::{ENV_VAULT_ADDR, ENV_VAULT_NAMESPACE, ENV_VAULT_TOKEN};
+
+#[cfg(any(feature = "vault-secrets", feature = "doppler-secrets"))]
+use std::env::var;
+
+/// The Doppler API endpoint for reading a single secret.
+#[cfg(feature = "doppler-secrets")]
+const DOPPLER_SECRET_URL: &str = "https://api.doppler.com/v3/configs/config/secret";
+
+/// The Vault address used when VAULT_ADDR is not set: the local proxy run by the Vault Lambda extension, which
+/// authenticates to Vault with the function's IAM role.
+#[cfg(feature = "vault-secrets")]
+const DEFAULT_VAULT_ADDR: &str = "http://127.0.0.1:8200";
+
+/// A secret read from a secret backend. The value is kept out of Debug output.
+#[derive(Clone)]
+pub struct SecretValue(pub String);
+
+impl Debug for SecretValue {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), FormatError> {
+        f.write_str("SecretValue(..)")
+    }
+}
+
+/// Where a secret reference without a scheme prefix is read from. Each setting that takes a secret reference keeps
+/// the store it has always used, so existing configurations are unchanged.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DefaultSecretStore {
+    SecretsManager,
+    Ssm,
+}
+
+/// A secret manager that configuration secrets (DNS provider credentials, EAB keys, webhook tokens) can be read
+/// from. A secret reference selects a backend with a scheme prefix:
+///
+/// * `secretsmanager:<name or ARN>`: an AWS Secrets Manager secret string.
+/// * `ssm:<name>`: an SSM parameter, decrypted if it is a SecureString.
+/// * `vault:<path>#<field>`: a field of a HashiCorp Vault secret, e.g. `vault:secret/data/acme#hmac_key`. This is
+///   only available in builds with the "vault-secrets" feature.
+/// * `doppler:<project>/<config>/<name>`: a Doppler secret. The project and config may be omitted when the token is
+///   a service token scoped to one config. This is only available in builds with the "doppler-secrets" feature.
+///
+/// AWS ARNs are read from the service they name, and references with no prefix are read from the setting's default
+/// store.
+#[async_trait]
+pub trait SecretBackend: Send + Sync {
+    /// The scheme prefix that selects this backend.
+    fn scheme(&self) -> &'static str;
+
+    /// Check the reference (with the scheme prefix removed) without reading the secret.
+    fn validate(&self, path: &str) -> Result<(), LambdaError> {
+        if path.is_empty() {
+            Err(InvalidCertificateRequest::invalid_secret_reference(format!(
+                "{} secret name cannot be empty",
+                self.scheme()
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Read the secret named by the reference (with the scheme prefix removed).
+    async fn read(&self, path: &str) -> Result<SecretValue, LambdaError>;
+}
+
+/// Reads Secrets Manager secret strings. The secret is read from the region in its ARN, or from the current region
+/// if a name is given.
+pub struct SecretsManagerBackend;
+
+#[async_trait]
+impl SecretBackend for SecretsManagerBackend {
+    fn scheme(&self) -> &'static str {
+        "secretsmanager"
+    }
+
+    async fn read(&self, path: &str) -> Result<SecretValue, LambdaError> {
+        let sm = SecretsManagerClient::new_with_client(aws_client(), arn_region(path)?);
+        let gsv_request = GetSecretValueRequest {
+            secret_id: path.to_string(),
+            ..Default::default()
+        };
+
+        match sm.get_secret_value(gsv_request).await {
+            Ok(response) => non_empty(path, response.secret_string),
+            Err(e) => {
+                error!("Failed to read Secrets Manager secret {}: {:#}", path, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+/// Reads SSM parameters, decrypting SecureString parameters. The parameter is read from the region in its ARN, or
+/// from the current region if a name is given.
+pub struct SsmParameterBackend;
+
+#[async_trait]
+impl SecretBackend for SsmParameterBackend {
+    fn scheme(&self) -> &'static str {
+        "ssm"
+    }
+
+    fn validate(&self, path: &str) -> Result<(), LambdaError> {
+        if path.starts_with("arn:") || validate_and_sanitize_ssm_parameter_path(path).is_some() {
+            Ok(())
+        } else {
+            Err(InvalidCertificateRequest::invalid_ssm_parameter_path(path))
+        }
+    }
+
+    async fn read(&self, path: &str) -> Result<SecretValue, LambdaError> {
+        let ssm = SsmClient::new_with_client(aws_client(), arn_region(path)?);
+        let gp_request = GetParameterRequest {
+            name: path.to_string(),
+            with_decryption: Some(true),
+        };
+
+        match ssm.get_parameter(gp_request).await {
+            Ok(response) => non_empty(path, response.parameter.and_then(|parameter| parameter.value)),
+            Err(e) => {
+                error!("Failed to read SSM parameter {}: {:#}", path, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+/// Reads a field of a HashiCorp Vault secret through the HTTP API. Both KV version 1 and 2 mounts are supported;
+/// for version 2, the path includes the "data" segment (e.g. `secret/data/acme`). The field may be omitted if the
+/// secret has only one.
+///
+/// Vault is reached at the `VAULT_ADDR` environment variable, defaulting to the Vault Lambda extension's local
+/// proxy. `VAULT_TOKEN` and `VAULT_NAMESPACE` are sent if set; they are not needed with the extension, which
+/// authenticates with the function's IAM role.
+#[cfg(feature = "vault-secrets")]
+pub struct VaultBackend;
+
+#[cfg(feature = "vault-secrets")]
+#[async_trait]
+impl SecretBackend for VaultBackend {
+    fn scheme(&self) -> &'static str {
+        "vault"
+    }
+
+    async fn read(&self, path: &str) -> Result<SecretValue, LambdaError> {
+        let (secret_path, field) = split_field(path);
+        let addr = var(ENV_VAULT_ADDR).ok().filter(|addr| !addr.is_empty());
+        let url = format!(
+            "{}/v1/{}",
+            addr.as_deref().unwrap_or(DEFAULT_VAULT_ADDR).trim_end_matches('/'),
+            secret_path.trim_start_matches('/')
+        );
+
+        let mut request = Client::new().get(&url);
+        if let Ok(token) = var(ENV_VAULT_TOKEN) {
+            request = request.header("X-Vault-Token", token);
+        }
+        if let Ok(namespace) = var(ENV_VAULT_NAMESPACE) {
+            request = request.header("X-Vault-Namespace", namespace);
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            error!("Failed to read Vault secret {}: {}: {}", secret_path, status, body);
+            return Err(CertificateRequestError::secret_unavailable(format!(
+                "Vault returned {} for {}",
+                status, secret_path
+            )));
+        }
+
+        let body: Value = serde_json::from_str(&body)?;
+        let data = match body["data"].get("data") {
+            Some(data) if data.is_object() => data,
+            _ => &body["data"],
+        };
+
+        non_empty(path, secret_field(data, field))
+    }
+}
+
+/// Reads a Doppler secret through the HTTP API, authenticating with the token in the `DOPPLER_TOKEN` environment
+/// variable. The computed value (with references to other secrets expanded) is used.
+#[cfg(feature = "doppler-secrets")]
+pub struct DopplerBackend;
+
+#[cfg(feature = "doppler-secrets")]
+#[async_trait]
+impl SecretBackend for DopplerBackend {
+    fn scheme(&self) -> &'static str {
+        "doppler"
+    }
+
+    fn validate(&self, path: &str) -> Result<(), LambdaError> {
+        match doppler_path(path) {
+            Some(_) => Ok(()),
+            None => Err(InvalidCertificateRequest::invalid_secret_reference(format!(
+                "Doppler secrets must be named as <project>/<config>/<name> or <name>: {}",
+                path
+            ))),
+        }
+    }
+
+    async fn read(&self, path: &str) -> Result<SecretValue, LambdaError> {
+        self.validate(path)?;
+        let (project_config, name) = doppler_path(path).unwrap_or((None, path));
+
+        let token = match var(ENV_DOPPLER_TOKEN) {
+            Ok(token) if !token.is_empty() => token,
+            _ => {
+                return Err(CertificateRequestError::secret_unavailable(format!(
+                    "{} must be set to read Doppler secrets",
+                    ENV_DOPPLER_TOKEN
+                )))
+            }
+        };
+
+        let mut query = vec![("name", name)];
+        if let Some((project, config)) = project_config {
+            query.push(("project", project));
+            query.push(("config", config));
+        }
+
+        let response = Client::new().get(DOPPLER_SECRET_URL).bearer_auth(token).query(&query).send().await?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            error!("Failed to read Doppler secret {}: {}: {}", path, status, body);
+            return Err(CertificateRequestError::secret_unavailable(format!(
+                "Doppler returned {} for {}",
+                status, path
+            )));
+        }
+
+        let body: Value = serde_json::from_str(&body)?;
+        let value = body["value"]["computed"].as_str().or_else(|| body["value"]["raw"].as_str());
+        non_empty(path, value.map(|value| value.to_string()))
+    }
+}
+
+/// Returns the backend for a secret reference, along with the reference to pass to it.
+pub fn select_backend(
+    reference: &str,
+    default: DefaultSecretStore,
+) -> Result<(Box<dyn SecretBackend>, &str), LambdaError> {
+    if reference.starts_with("arn:") {
+        return match reference.split(':').nth(2) {
+            Some("secretsmanager") => Ok((Box::new(SecretsManagerBackend), reference)),
+            Some("ssm") => Ok((Box::new(SsmParameterBackend), reference)),
+            _ => Err(InvalidCertificateRequest::invalid_secret_reference(format!(
+                "Only Secrets Manager and SSM ARNs can be used as secret references: {}",
+                reference
+            ))),
+        };
+    }
+
+    let (scheme, path) = match reference.find(':') {
+        Some(pos) => (&reference[..pos], &reference[pos + 1..]),
+        None => {
+            return Ok(match default {
+                DefaultSecretStore::SecretsManager => (Box::new(SecretsManagerBackend), reference),
+                DefaultSecretStore::Ssm => (Box::new(SsmParameterBackend), reference),
+            })
+        }
+    };
+
+    let backend: Box<dyn SecretBackend> = match scheme {
+        "secretsmanager" => Box::new(SecretsManagerBackend),
+        "ssm" => Box::new(SsmParameterBackend),
+        #[cfg(feature = "vault-secrets")]
+        "vault" => Box::new(VaultBackend),
+        #[cfg(feature = "doppler-secrets")]
+        "doppler" => Box::new(DopplerBackend),
+        _ if scheme == "vault" || scheme == "doppler" => {
+            return Err(InvalidCertificateRequest::invalid_secret_reference(format!(
+                "{} secrets require a build with the \"{}-secrets\" feature",
+                scheme, scheme
+            )))
+        }
+        _ => {
+            return Err(InvalidCertificateRequest::invalid_secret_reference(format!(
+                "Unknown secret backend {}: {}",
+                scheme, reference
+            )))
+        }
+    };
+
+    Ok((backend, path))
+}
+
+/// Check a secret reference without reading the secret.
+pub fn validate_secret_reference(reference: &str, default: DefaultSecretStore) -> Result<(), LambdaError> {
+    let (backend, path) = select_backend(reference, default)?;
+    backend.validate(path)
+}
+
+/// Read the secret named by a secret reference. See SecretBackend for the reference syntax.
+pub async fn resolve_secret(reference: &str, default: DefaultSecretStore) -> Result<SecretValue, LambdaError> {
+    let (backend, path) = select_backend(reference, default)?;
+    backend.validate(path)?;
+    backend.read(path).await
+}
+
+/// Read a secret, looking up references without a scheme prefix in Secrets Manager.
+pub async fn read_secret(reference: &str) -> Result<SecretValue, LambdaError> {
+    resolve_secret(reference, DefaultSecretStore::SecretsManager).await
+}
+
+/// Returns the region named in an AWS ARN, or the current region for a plain name.
+fn arn_region(path: &str) -> Result<Region, LambdaError> {
+    match path.split(':').nth(3) {
+        Some(region) if path.starts_with("arn:") => Ok(Region::from_str(region)?),
+        _ => Ok(Region::default()),
+    }
+}
+
+fn non_empty(path: &str, value: Option<String>) -> Result<SecretValue, LambdaError> {
+    match value {
+        Some(value) if !value.trim().is_empty() => Ok(SecretValue(value.trim().to_string())),
+        _ => Err(CertificateRequestError::secret_unavailable(format!("Secret {} has no string value", path))),
+    }
+}
+
+/// Split a Vault reference into the secret path and the optional field after '#'.
+#[cfg(feature = "vault-secrets")]
+fn split_field(path: &str) -> (&str, Option<&str>) {
+    match path.rfind('#') {
+        Some(pos) => (&path[..pos], Some(&path[pos + 1..])),
+        None => (path, None),
+    }
+}
+
+/// Returns the named string field of a secret, or its only field if no name is given.
+#[cfg(feature = "vault-secrets")]
+fn secret_field(data: &Value, field: Option<&str>) -> Option<String> {
+    let data = data.as_object()?;
+    let value = match field {
+        Some(field) => data.get(field)?,
+        None if data.len() == 1 => data.values().next()?,
+        None => return None,
+    };
+
+    value.as_str().map(|value| value.to_string())
+}
+
+/// Split a Doppler reference into the optional (project, config) and the secret name.
+#[cfg(feature = "doppler-secrets")]
+fn doppler_path(path: &str) -> Option<(Option<(&str, &str)>, &str)> {
+    let parts: Vec<&str> = path.split('/').collect();
+    if parts.iter().any(|part| part.is_empty()) {
+        return None;
+    }
+
+    match parts.as_slice() {
+        [name] => Some((None, *name)),
+        [project, config, name] => Some((Some((*project, *config)), *name)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{select_backend, validate_secret_reference, DefaultSecretStore},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_select_backend() {
+        let (backend, path) = select_backend("cloudflare-token", DefaultSecretStore::SecretsManager).unwrap();
+        assert_eq!(backend.scheme(), "secretsmanager");
+        assert_eq!(path, "cloudflare-token");
+
+        let (backend, path) = select_backend("/AcmeParameters/Eab/ZeroSsl", DefaultSecretStore::Ssm).unwrap();
+        assert_eq!(backend.scheme(), "ssm");
+        assert_eq!(path, "/AcmeParameters/Eab/ZeroSsl");
+
+        let (backend, path) = select_backend("ssm:/Webhooks/Token", DefaultSecretStore::SecretsManager).unwrap();
+        assert_eq!(backend.scheme(), "ssm");
+        assert_eq!(path, "/Webhooks/Token");
+
+        let arn = "arn:aws:secretsmanager:us-west-2:123456789012:secret:token-AbCdEf";
+        let (backend, path) = select_backend(arn, DefaultSecretStore::Ssm).unwrap();
+        assert_eq!(backend.scheme(), "secretsmanager");
+        assert_eq!(path, arn);
+
+        assert!(select_backend("arn:aws:s3:::bucket", DefaultSecretStore::SecretsManager).is_err());
+        assert!(select_backend("keychain:token", DefaultSecretStore::SecretsManager).is_err());
+        assert_eq!(
+            select_backend("vault:secret/data/acme#hmac_key", DefaultSecretStore::Ssm).is_ok(),
+            cfg!(feature = "vault-secrets")
+        );
+
+        assert!(validate_secret_reference("/AcmeParameters/Eab/ZeroSsl", DefaultSecretStore::Ssm).is_ok());
+        assert!(validate_secret_reference("AcmeParameters//ZeroSsl", DefaultSecretStore::Ssm).is_err());
+        assert!(validate_secret_reference("secretsmanager:", DefaultSecretStore::Ssm).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "vault-secrets")]
+    fn test_vault_fields() {
+        use super::{secret_field, split_field};
+
+        assert_eq!(split_field("secret/data/acme#hmac_key"), ("secret/data/acme", Some("hmac_key")));
+        assert_eq!(split_field("secret/data/acme"), ("secret/data/acme", None));
+
+        let data = json!({"hmac_key": "abc", "key_id": "kid-1"});
+        assert_eq!(secret_field(&data, Some("hmac_key")), Some("abc".to_string()));
+        assert_eq!(secret_field(&data, None), None);
+        assert_eq!(secret_field(&json!({"token": "xyz"}), None), Some("xyz".to_string()));
+    }
+
+    #[test]
+    #[cfg(feature = "doppler-secrets")]
+    fn test_doppler_path() {
+        use super::doppler_path;
+
+        assert_eq!(doppler_path("certs/prd/EAB_HMAC_KEY"), Some((Some(("certs", "prd")), "EAB_HMAC_KEY")));
+        assert_eq!(doppler_path("EAB_HMAC_KEY"), Some((None, "EAB_HMAC_KEY")));
+        assert_eq!(doppler_path("certs/EAB_HMAC_KEY"), None);
+        assert_eq!(doppler_path("certs//EAB_HMAC_KEY"), None);
+    }
+}
@@ -1,55 +1,287 @@
 use {
     crate::{
-        constants::{ACM_STATUS_EXPIRED, ACM_STATUS_ISSUED, ACM_TYPE_IMPORTED, S3_ENCRYPTION_AES, S3_ENCRYPTION_KMS},
+        acm_cache::AcmCache,
+        artifacts::{build_pkcs12, Artifact, ArtifactFormats, ArtifactKind},
+        assume_role::AssumeRole,
+        chains::{common_name, CertificateChain, ChainVariant},
+        constants::{
+            ACM_ALL_KEY_TYPES, ACM_MAX_TAGS, ACM_STATUS_EXPIRED, ACM_STATUS_ISSUED, ACM_TYPE_IMPORTED,
+            APIGATEWAY_EDGE_ACM_REGION, APIGATEWAY_ENDPOINT_EDGE, APIGATEWAY_ENDPOINT_REGIONAL, CLOUDFRONT_ACM_REGION,
+            CLOUDFRONT_DEFAULT_MINIMUM_PROTOCOL_VERSION, CLOUDFRONT_SSL_SUPPORT_SNI_ONLY, CLOUDFRONT_SSL_SUPPORT_VIP,
+            IAM_MAX_SERVER_CERTIFICATE_NAME_LENGTH, IAM_MAX_TAGS, RUN_COMMAND_DEFAULT_TIMEOUT_SECONDS,
+            RUN_COMMAND_MAX_TIMEOUT_SECONDS, RUN_COMMAND_POLL_SECONDS, S3_DEFAULT_KEY_TEMPLATE, S3_ENCRYPTION_AES,
+            S3_ENCRYPTION_KMS, S3_MAX_METADATA_DOMAIN_NAMES_LEN, S3_MAX_OBJECT_TAGS, S3_MAX_TAG_KEY_LEN,
+            S3_MAX_TAG_VALUE_LEN, S3_PUBLIC_GRANTEE_URIS, SECRETS_MANAGER_DEFAULT_BUNDLE_NAME_TEMPLATE,
+            SECRETS_MANAGER_DEFAULT_SEPARATE_NAME_TEMPLATE, SSM_ADVANCED_MAX_VALUE_LEN, SSM_DEFAULT_NAME_TEMPLATE,
+            SSM_STANDARD_MAX_VALUE_LEN, SSM_TIER_ADVANCED, SSM_TIER_INTELLIGENT_TIERING, SSM_TIER_STANDARD,
+            SSM_TYPE_SECURE_STRING, SSM_TYPE_STRING, STORAGE_STAGING_SUFFIX, WINDOWS_DEFAULT_PARAMETER_NAME,
+            WINDOWS_DEPLOYED_LABEL, WINDOWS_MAX_INSTANCE_IDS, WINDOWS_RUN_COMMAND_DOCUMENT, WINDOWS_STORE_MY,
+            WINDOWS_STORE_WEB_HOSTING,
+        },
+        dry_run::PlannedWrite,
         errors::{CertificateRequestError, InvalidCertificateRequest},
+        events::{string_or_vec, string_or_vec_schema},
+        faults::{self, Fault},
+        keys::KeyAlgorithm,
+        reconcile::{CertificateDiff, CertificateSummary, ObservedCertificate},
+        store::{lookup_store, registered_store_schemas, registered_store_types, CertificateStore},
+        throttle,
         utils::{
-            default_aes256, default_false, empty_string, s3_bucket_location_constraint_to_region,
-            validate_and_sanitize_ssm_parameter_path, CertificateComponents,
+            attr_n, attr_s, default_aes256, default_false, default_true, domain_name_for_path, empty_string,
+            format_utc_timestamp, normalize_serial, now_epoch_secs, s3_bucket_location_constraint_to_region,
+            validate_and_sanitize_ssm_parameter_path, CertificateComponents, CertificateInfo,
         },
+        verify::{CanaryRollout, DeploymentVerification, VerificationResult},
+        writer::{writer_metadata, PreviousWriter, WriterCheck, WRITER_METADATA_KEY},
     },
+    async_trait::async_trait,
     bytes::Bytes,
     futures::{
         future::ready,
-        stream::{FuturesOrdered, StreamExt},
+        stream::{FuturesOrdered, StreamExt, TryStreamExt},
     },
     lambda_runtime::Error as LambdaError,
-    log::{debug, error, info},
-    rusoto_acm::{Acm, AcmClient, DescribeCertificateRequest, ImportCertificateRequest, ListCertificatesRequest},
-    rusoto_core::Region,
-    rusoto_s3::{GetBucketLocationRequest, PutObjectRequest, S3Client, StreamingBody, S3},
-    rusoto_ssm::{GetParameterRequest, PutParameterRequest, Ssm, SsmClient},
-    serde::{self, Deserialize, Serialize},
-    std::str::FromStr,
+    log::{debug, error, info, warn},
+    openssl::{hash::MessageDigest, pkcs12::Pkcs12, rand::rand_bytes, x509::X509},
+    rusoto_acm::{
+        Acm, AcmClient, AddTagsToCertificateRequest, DescribeCertificateError, DescribeCertificateRequest, Filters,
+        ImportCertificateRequest, ListCertificatesRequest, Tag,
+    },
+    rusoto_apigateway::{ApiGateway, ApiGatewayClient, GetDomainNameRequest, PatchOperation, UpdateDomainNameRequest},
+    rusoto_cloudfront::{
+        CloudFront, CloudFrontClient, DistributionConfig, GetDistributionConfigRequest, UpdateDistributionRequest,
+        ViewerCertificate,
+    },
+    rusoto_core::{Region, RusotoError},
+    rusoto_dynamodb::{AttributeValue, DynamoDb, DynamoDbClient, PutItemInput, QueryInput, UpdateItemInput},
+    rusoto_elbv2::{
+        AddListenerCertificatesInput, Certificate as ListenerCertificate, DescribeListenerCertificatesInput,
+        DescribeListenersInput, DescribeSSLPoliciesInput, Elb, ElbClient, ModifyListenerInput,
+        RemoveListenerCertificatesInput,
+    },
+    rusoto_iam::{
+        DeleteServerCertificateRequest, GetServerCertificateRequest, Iam, IamClient, ListServerCertificatesRequest,
+        ServerCertificateMetadata, Tag as IamTag, UploadServerCertificateRequest,
+    },
+    rusoto_s3::{
+        DeleteObjectRequest, GetBucketAclRequest, GetBucketLocationRequest, GetBucketPolicyStatusRequest,
+        GetBucketVersioningRequest, GetObjectError, GetObjectRequest, GetObjectTaggingRequest,
+        GetPublicAccessBlockRequest, HeadObjectError, HeadObjectRequest, ListObjectVersionsRequest,
+        ListObjectsV2Request, PutObjectError, PutObjectRequest, S3Client, StreamingBody, S3,
+    },
+    rusoto_secretsmanager::{
+        CreateSecretRequest, GetSecretValueError, GetSecretValueRequest, SecretsManager, SecretsManagerClient,
+        UpdateSecretError, UpdateSecretRequest,
+    },
+    rusoto_ssm::{
+        AddTagsToResourceRequest, DeleteParameterRequest, DeleteParametersRequest, GetParameterError,
+        GetParameterRequest, LabelParameterVersionRequest, ListCommandInvocationsRequest, ListCommandsRequest,
+        ListTagsForResourceError, ListTagsForResourceRequest, PutParameterRequest, SendCommandRequest, Ssm, SsmClient,
+        Tag as SsmTag, Target,
+    },
+    schemars::{
+        gen::SchemaGenerator,
+        schema::{InstanceType, Schema, SchemaObject, SubschemaValidation},
+        JsonSchema,
+    },
+    serde::{self, de::Error as DeError, ser::Error as SerError, Deserialize, Deserializer, Serialize, Serializer},
+    serde_json::Value,
+    std::{
+        cmp::Reverse,
+        collections::{BTreeMap, HashMap},
+        fs::{read, remove_file, rename, set_permissions, DirBuilder, OpenOptions, Permissions},
+        io::{ErrorKind, Write},
+        os::unix::fs::{chown, DirBuilderExt, OpenOptionsExt, PermissionsExt},
+        path::{Path, PathBuf},
+        str::FromStr,
+        time::Duration,
+    },
+    tokio::time::{sleep, Instant},
+    url::form_urlencoded,
 };
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-#[serde(tag = "Type")]
-pub(crate) enum CertificateStorage {
-    Acm(AcmStorage),
-    S3(S3Storage),
-    SsmParameter(SsmParameterStorage),
-}
+/// A storage target for a certificate. The "Type" key selects the CertificateStore to use from the store registry;
+/// see store.rs. Every storage type also accepts the RoleArn and ExternalId keys to write through an assumed role
+/// (e.g. into another account); see AssumeRole. The "Chain" key selects which chain the target receives; see
+/// ChainVariant.
+#[derive(Debug)]
+pub struct CertificateStorage(Box<dyn CertificateStore>, ChainVariant);
 
 impl CertificateStorage {
-    pub(crate) async fn validate(&mut self) -> Result<(), LambdaError> {
-        match self {
-            CertificateStorage::Acm(storage) => storage.validate().await,
-            CertificateStorage::S3(storage) => storage.validate().await,
-            CertificateStorage::SsmParameter(storage) => storage.validate().await,
+    pub async fn validate(&mut self) -> Result<(), LambdaError> {
+        self.0.assume_role().validate()?;
+        self.0.validate().await
+    }
+
+    /// Check that a certificate with the given key algorithm can be used by this target.
+    pub async fn check_key_algorithm(&self, key_algorithm: KeyAlgorithm) -> Result<(), LambdaError> {
+        self.0.check_key_algorithm(key_algorithm).await
+    }
+
+    /// Expand a target with a Regions list into one target per region, so each replica is observed, planned, and
+    /// written independently (and in parallel with the others). Targets without Regions are returned unchanged.
+    pub fn expand_regions(self) -> Result<Vec<CertificateStorage>, LambdaError> {
+        match self.0.expand_regions()? {
+            Some(replicas) => Ok(replicas.into_iter().map(|replica| Self(replica, self.1)).collect()),
+            None => Ok(vec![self]),
         }
     }
 
-    pub(crate) async fn save_certificate(
+    /// Returns the "Type" of this storage target.
+    pub fn type_name(&self) -> &'static str {
+        self.0.type_name()
+    }
+
+    /// Returns the chain variant this storage target receives.
+    pub fn chain(&self) -> ChainVariant {
+        self.1
+    }
+
+    /// Indicates whether the private key may be written to this storage target. Targets that serve the certificate
+    /// directly (ACM and the services that use ACM certificates, and IAM) always require the key; the others can opt
+    /// out with AllowPrivateKey so the key is only stored where it's needed.
+    pub fn allows_private_key(&self) -> bool {
+        self.0.allows_private_key()
+    }
+
+    /// Describe the writes save_certificate() would make, for dry runs. store_alternate_chain is the request's
+    /// StoreAlternateChain setting; only targets receiving the default chain store the alternate one alongside it.
+    pub fn planned_writes(&self, domain_names: &[String], store_alternate_chain: bool) -> Vec<PlannedWrite> {
+        self.0.planned_writes(domain_names, self.1 == ChainVariant::Default && store_alternate_chain)
+    }
+
+    pub async fn save_certificate(
         &self,
         domain_names: Vec<String>,
         components: CertificateComponents,
     ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
-        match self {
-            CertificateStorage::Acm(storage) => storage.save_certificate(domain_names, components).await,
-            CertificateStorage::S3(storage) => storage.save_certificate(domain_names, components).await,
-            CertificateStorage::SsmParameter(storage) => storage.save_certificate(domain_names, components).await,
+        // Strip the key here rather than relying on each backend to skip it, so a target that isn't allowed the key
+        // never sees it.
+        let components = if self.allows_private_key() {
+            components
+        } else {
+            components.without_private_key()
+        };
+
+        self.0.save_certificate(domain_names, components).await
+    }
+
+    /// Observe the certificate currently held by this storage target. This returns None if no certificate (or
+    /// only an incomplete set of components) is present.
+    pub async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        self.0.observe(domain_names).await
+    }
+}
+
+impl Clone for CertificateStorage {
+    fn clone(&self) -> Self {
+        Self(self.0.clone_store(), self.1)
+    }
+}
+
+impl Serialize for CertificateStorage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = self.0.to_json().map_err(SerError::custom)?;
+        if let Value::Object(map) = &mut value {
+            map.insert("Type".to_string(), Value::String(self.0.type_name().to_string()));
+            if self.1 != ChainVariant::Default {
+                map.insert("Chain".to_string(), serde_json::to_value(self.1).map_err(SerError::custom)?);
+            }
+        }
+
+        value.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for CertificateStorage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        let chain = match value.as_object_mut().and_then(|map| map.remove("Chain")) {
+            Some(chain) => serde_json::from_value(chain).map_err(DeError::custom)?,
+            None => ChainVariant::default(),
+        };
+
+        let type_name = match value.get("Type") {
+            Some(Value::String(type_name)) => type_name.clone(),
+            _ => return Err(DeError::missing_field("Type")),
+        };
+
+        let factory = match lookup_store(&type_name) {
+            Some(factory) => factory,
+            None => {
+                return Err(DeError::custom(format!(
+                    "unknown storage type {:?}, expected one of: {}",
+                    type_name,
+                    registered_store_types().join(", ")
+                )))
+            }
+        };
+
+        factory(value).map(|store| Self(store, chain)).map_err(DeError::custom)
+    }
+}
+
+/// The schema is one of the registered stores' configurations, each with its "Type" and the optional "Chain".
+impl JsonSchema for CertificateStorage {
+    fn schema_name() -> String {
+        "CertificateStorage".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        let chain = gen.subschema_for::<ChainVariant>();
+        let mut variants = Vec::new();
+
+        for (type_name, schema) in registered_store_schemas() {
+            let mut variant = match schema {
+                Some(schema) => schema(gen).into_object(),
+                None => SchemaObject {
+                    instance_type: Some(InstanceType::Object.into()),
+                    ..Default::default()
+                },
+            };
+
+            let type_schema = SchemaObject {
+                const_value: Some(Value::String(type_name)),
+                ..Default::default()
+            };
+
+            let object = variant.object();
+            object.properties.insert("Type".to_string(), type_schema.into());
+            object.properties.insert("Chain".to_string(), chain.clone());
+            object.required.insert("Type".to_string());
+            variants.push(variant.into());
+        }
+
+        SchemaObject {
+            subschemas: Some(Box::new(SubschemaValidation {
+                one_of: Some(variants),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+/// Validate and deduplicate the regions a storage target is replicated to. Region (the single-region setting) is
+/// mutually exclusive with Regions.
+fn replica_regions(regions: &[String], region: Option<&str>) -> Result<Vec<String>, LambdaError> {
+    if region.is_some() {
+        return Err(InvalidCertificateRequest::invalid_regions("Region and Regions cannot both be specified"));
+    }
+
+    let mut result: Vec<String> = Vec::with_capacity(regions.len());
+    for region in regions {
+        if Region::from_str(region).is_err() {
+            return Err(InvalidCertificateRequest::invalid_regions(format!("Invalid region: {}", region)));
+        }
+
+        if !result.contains(region) {
+            result.push(region.clone());
         }
     }
+
+    Ok(result)
 }
 
 /// Configuration for storing a certificate in an AWS Certificate Manager (ACM) certificate. In JSON:
@@ -66,71 +298,148 @@ impl CertificateStorage {
 ///         // over CertificateArn (if specified) or a certificate that matches the domain name(s) if found.
 ///         // If no matching certificate is found, a new one is imported. The default is false.
 ///         "ForceNewImport": bool,
+///
+///         // Tags to apply to the certificate, e.g. for cost allocation. These are applied when the
+///         // certificate is first imported and reapplied after each reimport. Tags not listed here are left
+///         // untouched.
+///         "Tags": {str: str, ...},
+///
+///         // The region to import the certificate into. This defaults to the region the function is running
+///         // in.
+///         "Region": str,
+///
+///         // Import the certificate into each of these regions in parallel instead of a single region (e.g.
+///         // us-east-1 for CloudFront plus the workload region). This cannot be combined with Region or
+///         // CertificateArns, and cannot be used for the ACM certificate of a deployment target.
+///         "Regions": [str, ...],
+///
+///         // When reimporting into several certificate ARNs, reimport into a subset first and probe its endpoints
+///         // before reimporting into the rest. See CanaryRollout. This cannot be specified if ForceNewImport is
+///         // true.
+///         "Canary": { ... },
 ///     }
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub(crate) struct AcmStorage {
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct AcmStorage {
     #[serde(rename = "CertificateArns", default)]
-    pub(crate) certificate_arns: Option<Vec<String>>,
+    pub certificate_arns: Option<Vec<String>>,
 
     #[serde(rename = "ForceNewImport", default = "default_false")]
-    pub(crate) force_new_import: bool,
+    pub force_new_import: bool,
+
+    #[serde(rename = "Tags", default)]
+    pub tags: Option<HashMap<String, String>>,
+
+    #[serde(rename = "Region", default)]
+    pub region: Option<String>,
+
+    #[serde(rename = "Regions", default)]
+    pub regions: Vec<String>,
+
+    #[serde(rename = "Canary", default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<CanaryRollout>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
 }
 
 impl AcmStorage {
-    pub(crate) async fn validate(&mut self) -> Result<(), LambdaError> {
-        if let Some(existing_arns) = &self.certificate_arns {
-            if self.force_new_import {
-                return Err(InvalidCertificateRequest::invalid_acm_configuration(
-                    "Cannot specify CertificateArn and ForceNewImport",
-                ));
-            }
-
-            for arn_str in existing_arns {
-                // Should be arn:aws...:acm:region:account:certificate/certificate-id
-                let parts = arn_str.split(':').collect::<Vec<&str>>();
-                if parts.len() == 6
-                    && parts[0] == "arn"
-                    && parts[1].len() > 0
-                    && parts[2] == "acm"
-                    && parts[4].len() == 12
-                    && parts[5].starts_with("certificate/")
-                {
-                    let region_str = parts[3];
-                    if Region::from_str(region_str).is_ok() {
-                        continue;
-                    }
-                }
-                return Err(InvalidCertificateRequest::invalid_acm_certificate_arn(arn_str));
-            }
+    /// Deployment targets import the certificate into ACM in the same account as the resource they update, so the
+    /// ACM certificate inherits the deployment target's role.
+    fn inherit_assume_role(&mut self, assume_role: &AssumeRole) -> Result<(), LambdaError> {
+        if self.assume_role.is_assumed() && self.assume_role != *assume_role {
+            return Err(InvalidCertificateRequest::invalid_role_arn(
+                "The ACM certificate of a deployment target must use the deployment target's RoleArn",
+            ));
         }
 
+        self.assume_role = assume_role.clone();
         Ok(())
     }
 
-    /// Write the certificate and all of its components to AWS Certificate Manager (ACM).
-    pub(crate) async fn save_certificate(
-        &self,
-        domain_names: Vec<String>,
-        components: CertificateComponents,
-    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
-        if self.force_new_import {
-            self.import_new_certificate(domain_names, components).await
-        } else if let Some(existing_arns) = &self.certificate_arns {
-            self.reimport_certificate(domain_names, existing_arns.clone(), components).await
+    /// Deployment targets can only use a certificate in the region of the resource they update.
+    fn check_no_deployment_regions(&self) -> Result<(), LambdaError> {
+        if self.regions.is_empty() {
+            Ok(())
         } else {
-            let existing_arns = self.find_matching_certificate(&domain_names).await?;
-            if existing_arns.len() == 0 {
-                self.import_new_certificate(domain_names, components).await
-            } else {
-                self.reimport_certificate(domain_names, existing_arns, components).await
+            Err(InvalidCertificateRequest::invalid_regions(
+                "Regions cannot be specified for the ACM certificate of a deployment target",
+            ))
+        }
+    }
+
+    /// Returns the configured tags in the form ACM expects, or None if there are no tags.
+    fn acm_tags(&self) -> Option<Vec<Tag>> {
+        let tags = self.tags.as_ref()?;
+        if tags.is_empty() {
+            return None;
+        }
+
+        let mut acm_tags: Vec<Tag> = tags
+            .iter()
+            .map(|(key, value)| Tag {
+                key: key.clone(),
+                value: Some(value.clone()),
+            })
+            .collect();
+        acm_tags.sort_by(|a, b| a.key.cmp(&b.key));
+        Some(acm_tags)
+    }
+
+    /// Update the ACM cache (if enabled) after importing a certificate. Failures are logged and otherwise ignored;
+    /// the next full sync or ACM event will correct the cache.
+    async fn refresh_acm_cache(&self, certificate_arn: &str) {
+        if self.region.is_some() || self.assume_role.is_assumed() {
+            return;
+        }
+
+        if let Some(cache) = AcmCache::from_env() {
+            if let Err(e) = cache.refresh_certificate(certificate_arn).await {
+                error!("Failed to update ACM cache for {}: {:#}", certificate_arn, e);
             }
         }
     }
 
+    /// Returns an ACM client for the configured region.
+    fn acm_client(&self) -> Result<AcmClient, LambdaError> {
+        Ok(AcmClient::new_with_client(self.assume_role.client()?, self.acm_region()))
+    }
+
+    /// Returns an ACM client for the region of a certificate ARN. This only differs from acm_client when Region is
+    /// unspecified and CertificateArns names certificates in other regions.
+    fn acm_client_for_arn(&self, arn: &str) -> Result<AcmClient, LambdaError> {
+        let region = match (&self.region, arn.split(':').nth(3).map(Region::from_str)) {
+            (None, Some(Ok(region))) => region,
+            _ => self.acm_region(),
+        };
+
+        Ok(AcmClient::new_with_client(self.assume_role.client()?, region))
+    }
+
+    fn acm_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
+        }
+    }
+
     async fn find_matching_certificate(&self, domain_names: &Vec<String>) -> Result<Vec<String>, LambdaError> {
-        let acm = AcmClient::new(Region::default());
+        // Prefer the ACM cache if it's enabled; listing and describing every certificate in a large account is slow
+        // and prone to throttling. The cache only covers the region and account the function is running in.
+        if let (None, false, Some(cache)) = (&self.region, self.assume_role.is_assumed(), AcmCache::from_env()) {
+            match cache.find_matching_certificates(domain_names).await {
+                Ok(arns) => return Ok(arns),
+                Err(e) => error!("Failed to query ACM cache; falling back to listing certificates: {:#}", e),
+            }
+        }
+
+        let acm = self.acm_client()?;
+        // ListCertificates only returns RSA-2048 certificates unless other key types are requested explicitly.
         let mut lc_request = ListCertificatesRequest {
             certificate_statuses: Some(vec![ACM_STATUS_ISSUED.to_string(), ACM_STATUS_EXPIRED.to_string()]),
+            includes: Some(Filters {
+                key_types: Some(ACM_ALL_KEY_TYPES.iter().map(|kt| kt.to_string()).collect()),
+                ..Default::default()
+            }),
             ..Default::default()
         };
         let mut candidates = Vec::new();
@@ -138,7 +447,7 @@ impl AcmStorage {
         debug!("Looking for existing certificates that match: {}", domain_names.join(" "));
 
         loop {
-            match acm.list_certificates(lc_request.clone()).await {
+            match throttle::call("ListCertificates", || acm.list_certificates(lc_request.clone())).await {
                 Err(e) => {
                     error!("Failed to list ACM certificates: {:#}", e);
                     return Err(Box::new(e));
@@ -148,16 +457,17 @@ impl AcmStorage {
                         for summary in summaries {
                             debug!(
                                 "Considering certificate {} with domain name {}",
-                                summary.certificate_arn.as_ref().map(|s| s.as_str()).unwrap_or("<unknown>"),
-                                summary.domain_name.as_ref().map(|s| s.as_str()).unwrap_or("<unknown>")
+                                summary.certificate_arn.as_deref().unwrap_or("<unknown>"),
+                                summary.domain_name.as_deref().unwrap_or("<unknown>")
                             );
 
                             if let Some(summary_domain_name) = summary.domain_name {
                                 for domain_name in domain_names {
-                                    if summary_domain_name == domain_name.as_str() {
+                                    if summary_domain_name.eq_ignore_ascii_case(domain_name) {
                                         let summary_cert_arn = summary.certificate_arn.as_ref().unwrap().clone();
                                         info!("Certificate {} is a possible candidate", summary_cert_arn);
                                         candidates.push(summary_cert_arn);
+                                        break;
                                     }
                                 }
                             }
@@ -172,8 +482,11 @@ impl AcmStorage {
             }
         }
 
-        let mut domain_names_sorted = domain_names.clone();
+        // Compare SANs case-insensitively. Wildcard entries ("*.example.com") are compared literally, so a wildcard
+        // certificate only matches a request for the same wildcard (plus any other names requested alongside it).
+        let mut domain_names_sorted = domain_names.iter().map(|dn| dn.to_lowercase()).collect::<Vec<String>>();
         domain_names_sorted.sort();
+        domain_names_sorted.dedup();
 
         // Check the candidates to see if they have the same domain names.
         let mut futures = FuturesOrdered::new();
@@ -182,7 +495,10 @@ impl AcmStorage {
             let dc_request = DescribeCertificateRequest {
                 certificate_arn: candidate.clone(),
             };
-            futures.push(Box::pin(acm.describe_certificate(dc_request)));
+            let acm = &acm;
+            futures.push(Box::pin(throttle::call("DescribeCertificate", move || {
+                acm.describe_certificate(dc_request.clone())
+            })));
         }
 
         let results: Vec<String> = futures
@@ -197,9 +513,11 @@ impl AcmStorage {
                         Some(detail) => {
                             if detail.type_.is_some() && detail.type_.unwrap() == ACM_TYPE_IMPORTED {
                                 if let Some(alt_names) = detail.subject_alternative_names {
-                                    let mut alt_names_sorted = alt_names.clone();
+                                    let mut alt_names_sorted =
+                                        alt_names.iter().map(|an| an.to_lowercase()).collect::<Vec<String>>();
                                     alt_names_sorted.sort();
-                                    if &alt_names_sorted == domain_names {
+                                    alt_names_sorted.dedup();
+                                    if alt_names_sorted == domain_names_sorted {
                                         Some(detail.certificate_arn.unwrap())
                                     } else {
                                         None
@@ -226,21 +544,28 @@ impl AcmStorage {
         components: CertificateComponents,
     ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
         info!("Importing certificate to ACM for {}", domain_names.join(" "));
-        let acm = AcmClient::new(Region::default());
+        let acm = self.acm_client()?;
         let imp_req = ImportCertificateRequest {
             certificate: Bytes::from(components.cert_pem),
             certificate_chain: Some(Bytes::from(components.chain_pem)),
             private_key: Bytes::from(components.pkey_pem),
-            tags: None,
+            tags: self.acm_tags(),
             ..Default::default()
         };
 
-        match acm.import_certificate(imp_req).await {
+        match throttle::call("ImportCertificate", || {
+            faults::aws_call(Fault::AcmThrottling, acm.import_certificate(imp_req.clone()))
+        })
+        .await
+        {
             Ok(response) => {
                 let certificate_arn = response.certificate_arn.unwrap();
                 info!("Certificate imported as {}", certificate_arn);
+                self.refresh_acm_cache(&certificate_arn).await;
                 Ok(vec![CertificateStorageResult::Acm(AcmStorageResult {
                     certificate_arn,
+                    diff: None,
+                    canary_verification: None,
                 })])
             }
             Err(e) => {
@@ -256,20 +581,78 @@ impl AcmStorage {
         existing_arns: Vec<String>,
         components: CertificateComponents,
     ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let canary = match &self.canary {
+            Some(canary) => canary,
+            None => return Ok(self.reimport_certificate_into(&domain_names, existing_arns, &components).await),
+        };
+
+        let (canary_arns, remaining_arns) = canary.split(existing_arns);
+        if canary_arns.is_empty() {
+            return Ok(self.reimport_certificate_into(&domain_names, remaining_arns, &components).await);
+        }
+
+        info!(
+            "Reimporting into canary {} before the remaining {} certificate(s)",
+            canary_arns.join(" "),
+            remaining_arns.len()
+        );
+        let mut results = self.reimport_certificate_into(&domain_names, canary_arns.clone(), &components).await;
+
+        // Tagging failures are reported alongside a successful reimport; only a missing reimport halts the rollout.
+        let n_reimported = results.iter().filter(|result| matches!(result, CertificateStorageResult::Acm(_))).count();
+        let halt_reason = if n_reimported < canary_arns.len() {
+            Some("a canary reimport failed".to_string())
+        } else {
+            let serial = CertificateInfo::from_pem(&components.cert_pem)?.serial;
+            let verification = canary.verification(&canary_arns).verify(&domain_names, &serial).await;
+            for result in results.iter_mut() {
+                if let CertificateStorageResult::Acm(result) = result {
+                    result.canary_verification = Some(verification.clone());
+                }
+            }
+
+            if verification.verified {
+                None
+            } else {
+                Some(format!("canary verification failed: {}", verification.failures.join("; ")))
+            }
+        };
+
+        match halt_reason {
+            None => {
+                info!("Canary verified; reimporting into {}", remaining_arns.join(" "));
+                results.extend(self.reimport_certificate_into(&domain_names, remaining_arns, &components).await);
+            }
+            Some(reason) => {
+                error!("Halting rollout to {}: {}", remaining_arns.join(" "), reason);
+                for arn in remaining_arns {
+                    results.push(CertificateStorageResult::Error(format!("Not reimported into {}: {}", arn, reason)));
+                }
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Reimport the certificate over each of the ARNs in parallel.
+    async fn reimport_certificate_into(
+        &self,
+        domain_names: &[String],
+        arns: Vec<String>,
+        components: &CertificateComponents,
+    ) -> Vec<CertificateStorageResult> {
         let mut futures = FuturesOrdered::new();
-        let n_arns = domain_names.len();
+        let n_arns = arns.len();
 
-        for arn in existing_arns {
-            futures.push(self.reimport_certificate_for_arn(domain_names.clone(), arn, components.clone()));
+        for arn in arns {
+            futures.push(self.reimport_certificate_for_arn(domain_names.to_vec(), arn, components.clone()));
         }
 
         let mut results = Vec::with_capacity(n_arns);
 
         while let Some(result) = futures.next().await {
             match result {
-                Ok(arn) => results.push(CertificateStorageResult::Acm(AcmStorageResult {
-                    certificate_arn: arn,
-                })),
+                Ok(arn_results) => results.extend(arn_results),
                 Err(e) => {
                     error!("Failed to reimport certificate: {:#}", e);
                     results.push(CertificateStorageResult::Error(format!("Failed to reimport certificate: {:#}", e)));
@@ -277,17 +660,49 @@ impl AcmStorage {
             }
         }
 
-        Ok(results)
+        results
     }
 
+    /// Reimport the certificate over an existing ARN, returning its result (including how the certificate changed)
+    /// and any tagging failure.
     async fn reimport_certificate_for_arn(
         &self,
         domain_names: Vec<String>,
         cert_arn: String,
         components: CertificateComponents,
-    ) -> Result<String, LambdaError> {
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
         info!("Reimporting certificate for {} over {}", domain_names.join(" "), cert_arn);
-        let acm = AcmClient::new(Region::default());
+        let acm = self.acm_client_for_arn(&cert_arn)?;
+
+        // Record the certificate being replaced so the result shows exactly what changed. This is informational
+        // only; failing to describe the existing certificate doesn't prevent the reimport.
+        let before = match describe_acm_certificate(&acm, &cert_arn).await {
+            Ok(before) => before,
+            Err(e) => {
+                warn!("Unable to describe certificate {} before reimporting it: {:#}", cert_arn, e);
+                None
+            }
+        };
+        let diff = match (before, CertificateInfo::from_pem(&components.cert_pem)) {
+            (Some(before), Ok(after)) => {
+                let diff = CertificateDiff::new(
+                    CertificateSummary::new(&cert_arn, &before),
+                    CertificateSummary::new(&cert_arn, &after),
+                );
+                info!(
+                    "Replacing certificate {} (serial {}, expires {}) with serial {} (expires {})",
+                    cert_arn, diff.before.serial, diff.before.not_after, diff.after.serial, diff.after.not_after
+                );
+                if !diff.added_domain_names.is_empty() || !diff.removed_domain_names.is_empty() {
+                    info!(
+                        "Domain names on {}: added {:?}, removed {:?}",
+                        cert_arn, diff.added_domain_names, diff.removed_domain_names
+                    );
+                }
+                Some(diff)
+            }
+            _ => None,
+        };
         let imp_req = ImportCertificateRequest {
             certificate: Bytes::from(components.cert_pem.clone()),
             certificate_arn: Some(cert_arn.clone()),
@@ -296,446 +711,7704 @@ impl AcmStorage {
             tags: None,
         };
 
-        match acm.import_certificate(imp_req).await {
+        match throttle::call("ImportCertificate", || {
+            faults::aws_call(Fault::AcmThrottling, acm.import_certificate(imp_req.clone()))
+        })
+        .await
+        {
             Err(e) => {
                 error!("Failed to reimport certificate: {:#}", e);
-                Err(Box::new(e))
+                return Err(Box::new(e));
             }
 
             Ok(_) => {
                 info!("Certificate re-imported as {}", cert_arn);
-                Ok(cert_arn)
+                self.refresh_acm_cache(&cert_arn).await;
             }
         }
-    }
-}
 
-/// Configuration for storing a certificate in Amazon S3. In JSON:
-///
-///     {
-///         // The type of storage to use. This must be "S3".
-///         "Type": "S3",
-///
-///         // The bucket to store the certificate into. This is required.
-///         "Bucket": str,
-///
-///         // The prefix to use for the certificate keys. Note that a "/" is not automatically
-///         // appended.
-///         "Prefix": str,
-///
-///         // The encryption type to use for the certificate components. This must be either "AES256" or "aws:kms". This defaults to "AES256".
-///         "ComponentEncryptionType": str,
-///
-///         // If ComponentEncryptionType is "aws:kms", this is the KMS key ARN to use for encryption. If
-///         // not specified, the default "aws/s3" key is used.
-///         "ComponentKmsKey": str,
-///
-///         // The encryption type to use for the private key. This must be either "AES256" or "aws:kms". This defaults to "AES256".
-///         "PrivateKeyEncryptionType": str,
-///
-///         // If PrivateKeyEncryptionType is "aws:kms", this is the KMS key ARN to use for encryption. If
-///         // not specified, the default "aws/s3" key is used.
-///         "PrivateKeyKmsKey": str,
-///     }
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub(crate) struct S3Storage {
-    #[serde(rename = "Bucket")]
-    pub(crate) bucket: String,
+        let mut results = vec![CertificateStorageResult::Acm(AcmStorageResult {
+            certificate_arn: cert_arn.clone(),
+            diff,
+            canary_verification: None,
+        })];
 
-    #[serde(rename = "Prefix", default = "empty_string")]
-    pub(crate) prefix: String,
+        // ACM rejects tags on reimport, so apply them separately. The certificate itself has already been written, so
+        // a tagging failure is reported alongside it rather than failing the write.
+        if let Some(tags) = self.acm_tags() {
+            let att_request = AddTagsToCertificateRequest {
+                certificate_arn: cert_arn.clone(),
+                tags,
+            };
 
-    #[serde(rename = "ComponentEncryptionType", default = "default_aes256")]
-    pub(crate) component_encryption_type: String,
+            if let Err(e) =
+                throttle::call("AddTagsToCertificate", || acm.add_tags_to_certificate(att_request.clone())).await
+            {
+                error!("Failed to tag certificate {}: {:#}", cert_arn, e);
+                results
+                    .push(CertificateStorageResult::Error(format!("Failed to tag certificate {}: {:#}", cert_arn, e)));
+            }
+        }
 
-    #[serde(rename = "ComponentKmsKey", default)]
-    pub(crate) component_kms_key: Option<String>,
+        Ok(results)
+    }
+}
 
-    #[serde(rename = "PrivateKeyEncryptionType", default = "default_aes256")]
-    pub(crate) pkey_encryption_type: String,
+/// Describe an ACM certificate, returning None if it doesn't exist.
+async fn describe_acm_certificate(acm: &AcmClient, arn: &str) -> Result<Option<CertificateInfo>, LambdaError> {
+    let dc_request = DescribeCertificateRequest {
+        certificate_arn: arn.to_string(),
+    };
 
-    #[serde(rename = "PrivateKeyKmsKey", default)]
-    pub(crate) pkey_kms_key: Option<String>,
+    let detail = match throttle::call("DescribeCertificate", || acm.describe_certificate(dc_request.clone())).await {
+        Ok(response) => response.certificate,
+        Err(RusotoError::Service(DescribeCertificateError::ResourceNotFound(_))) => None,
+        Err(e) => {
+            error!("Failed to describe ACM certificate {}: {:#}", arn, e);
+            return Err(Box::new(e));
+        }
+    };
 
-    #[serde(skip)]
-    pub(crate) region: Option<Region>,
+    Ok(detail.map(|detail| CertificateInfo {
+        domain_names: detail.subject_alternative_names.unwrap_or_default(),
+        serial: normalize_serial(detail.serial.as_deref().unwrap_or("")),
+        not_before: detail.not_before.map(|t| t as i64).unwrap_or(0),
+        not_after: detail.not_after.map(|t| t as i64).unwrap_or(0),
+        key_algorithm: detail.key_algorithm.as_deref().and_then(KeyAlgorithm::from_acm_key_type),
+    }))
 }
 
-impl S3Storage {
-    pub(crate) async fn validate(&mut self) -> Result<(), LambdaError> {
-        if self.bucket.is_empty() {
-            return Err(InvalidCertificateRequest::invalid_s3_bucket(self.bucket.clone()));
+#[async_trait]
+impl CertificateStore for AcmStorage {
+    fn type_name(&self) -> &'static str {
+        "Acm"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        if self.regions.is_empty() {
+            return Ok(None);
         }
 
-        match self.component_encryption_type.as_ref() {
-            S3_ENCRYPTION_AES | S3_ENCRYPTION_KMS => {}
-            _ => {
-                return Err(InvalidCertificateRequest::invalid_s3_encryption_algorithm(
-                    self.component_encryption_type.clone(),
-                ));
+        if self.certificate_arns.is_some() {
+            return Err(InvalidCertificateRequest::invalid_regions("CertificateArns cannot be specified with Regions"));
+        }
+
+        let regions = replica_regions(&self.regions, self.region.as_deref())?;
+        Ok(Some(
+            regions
+                .into_iter()
+                .map(|region| -> Box<dyn CertificateStore> {
+                    Box::new(AcmStorage {
+                        region: Some(region),
+                        regions: vec![],
+                        ..self.clone()
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_acm_configuration(format!(
+                    "Invalid region: {}",
+                    region
+                )));
             }
         }
 
-        match self.pkey_encryption_type.as_ref() {
-            S3_ENCRYPTION_AES | S3_ENCRYPTION_KMS => {}
-            _ => {
-                return Err(InvalidCertificateRequest::invalid_s3_encryption_algorithm(
-                    self.component_encryption_type.clone(),
+        if let Some(canary) = &self.canary {
+            if self.force_new_import {
+                return Err(InvalidCertificateRequest::invalid_acm_configuration(
+                    "Cannot specify Canary and ForceNewImport",
                 ));
             }
+
+            canary.validate()?;
         }
 
-        let s3_client = S3Client::new(Region::default());
-        let gblr = GetBucketLocationRequest {
-            bucket: self.bucket.clone(),
-            ..Default::default()
-        };
-        match s3_client.get_bucket_location(gblr).await {
-            Ok(response) => {
-                self.region = Some(s3_bucket_location_constraint_to_region(response.location_constraint)?);
-                Ok(())
-            }
-            Err(e) => {
-                error!("Failed to get location for S3 bucket {}: {}", self.bucket, e);
-                Err(InvalidCertificateRequest::invalid_s3_bucket(self.bucket.clone()))
+        if let Some(existing_arns) = &self.certificate_arns {
+            if self.force_new_import {
+                return Err(InvalidCertificateRequest::invalid_acm_configuration(
+                    "Cannot specify CertificateArn and ForceNewImport",
+                ));
             }
-        }
-    }
 
-    pub(crate) async fn save_certificate(
-        &self,
-        domain_names: Vec<String>,
-        components: CertificateComponents,
-    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
-        let s3_client = S3Client::new(self.region.clone().expect("Region should be set here"));
-        let cert_key = format!("{}cert.pem", self.prefix);
-        let chain_key = format!("{}chain.pem", self.prefix);
-        let fullchain_key = format!("{}fullchain.pem", self.prefix);
-        let pkey_key = format!("{}privkey.pem", self.prefix);
-
-        info!("Saving certificate for {} to s3://{}/{}", domain_names.join(" "), self.bucket, cert_key);
-        let cert_por = PutObjectRequest {
-            bucket: self.bucket.clone(),
-            key: cert_key.clone(),
-            server_side_encryption: Some(self.component_encryption_type.clone()),
-            ssekms_key_id: self.component_kms_key.clone(),
-            body: Some(StreamingBody::from(components.cert_pem.into_bytes())),
-            ..Default::default()
-        };
+            for arn_str in existing_arns {
+                // Should be arn:aws...:acm:region:account:certificate/certificate-id
+                let parts = arn_str.split(':').collect::<Vec<&str>>();
+                if parts.len() == 6
+                    && parts[0] == "arn"
+                    && !parts[1].is_empty()
+                    && parts[2] == "acm"
+                    && parts[4].len() == 12
+                    && parts[5].starts_with("certificate/")
+                {
+                    let region_str = parts[3];
+                    if Region::from_str(region_str).is_ok()
+                        && self.region.as_deref().unwrap_or(region_str) == region_str
+                    {
+                        continue;
+                    }
+                }
+                return Err(InvalidCertificateRequest::invalid_acm_certificate_arn(arn_str));
+            }
+        }
 
-        info!("Saving certificate chain for {} to s3://{}/{}", domain_names.join(" "), self.bucket, chain_key);
-        let chain_por = PutObjectRequest {
-            bucket: self.bucket.clone(),
-            key: chain_key.clone(),
-            server_side_encryption: Some(self.component_encryption_type.clone()),
-            ssekms_key_id: self.component_kms_key.clone(),
-            body: Some(StreamingBody::from(components.chain_pem.into_bytes())),
-            ..Default::default()
-        };
+        if let Some(tags) = &self.tags {
+            if tags.len() > ACM_MAX_TAGS {
+                return Err(InvalidCertificateRequest::invalid_acm_configuration(format!(
+                    "At most {} tags can be applied to a certificate",
+                    ACM_MAX_TAGS
+                )));
+            }
 
-        info!("Saving certificate fullchain for {} to s3://{}/{}", domain_names.join(" "), self.bucket, fullchain_key);
-        let fullchain_por = PutObjectRequest {
-            bucket: self.bucket.clone(),
-            key: fullchain_key.clone(),
-            server_side_encryption: Some(self.component_encryption_type.clone()),
-            ssekms_key_id: self.component_kms_key.clone(),
-            body: Some(StreamingBody::from(components.fullchain_pem.into_bytes())),
-            ..Default::default()
-        };
+            for (key, value) in tags {
+                if key.is_empty() || key.chars().count() > 128 || key.to_lowercase().starts_with("aws:") {
+                    return Err(InvalidCertificateRequest::invalid_acm_configuration(format!(
+                        "Invalid tag key {:?}: must be 1-128 characters and cannot start with \"aws:\"",
+                        key
+                    )));
+                }
 
-        info!("Saving private key for {} to s3://{}/{}", domain_names.join(" "), self.bucket, pkey_key);
-        let pkey_por = PutObjectRequest {
-            bucket: self.bucket.clone(),
-            key: pkey_key.clone(),
-            server_side_encryption: Some(self.pkey_encryption_type.clone()),
-            ssekms_key_id: self.pkey_kms_key.clone(),
-            body: Some(StreamingBody::from(components.pkey_pem.into_bytes())),
-            ..Default::default()
+                if value.chars().count() > 256 {
+                    return Err(InvalidCertificateRequest::invalid_acm_configuration(format!(
+                        "Invalid value for tag {:?}: must be at most 256 characters",
+                        key
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn planned_writes(&self, domain_names: &[String], _alternate_chain: bool) -> Vec<PlannedWrite> {
+        let region = self.acm_region();
+        let mut writes: Vec<PlannedWrite> = match (&self.certificate_arns, self.force_new_import) {
+            (Some(arns), _) => {
+                arns.iter().map(|arn| PlannedWrite::new("acm:ImportCertificate", arn.as_str())).collect()
+            }
+            (None, true) => vec![PlannedWrite::in_region("acm:ImportCertificate", "(new certificate)", &region)],
+            (None, false) => vec![PlannedWrite::in_region(
+                "acm:ImportCertificate",
+                format!("(the certificate for {}, or a new certificate if none is found)", domain_names.join(" ")),
+                &region,
+            )],
         };
 
-        let (cert_result, chain_result, fullchain_result, pkey_result) = tokio::join!(
-            s3_client.put_object(cert_por),
-            s3_client.put_object(chain_por),
-            s3_client.put_object(fullchain_por),
-            s3_client.put_object(pkey_por),
-        );
+        // Tags are applied by the import itself for new certificates, and reapplied separately after a reimport.
+        if self.acm_tags().is_some() && !self.force_new_import {
+            let tagged: Vec<PlannedWrite> = writes
+                .iter()
+                .map(|write| PlannedWrite {
+                    action: "acm:AddTagsToCertificate".to_string(),
+                    ..write.clone()
+                })
+                .collect();
+            writes.extend(tagged);
+        }
 
-        if let Err(e) = cert_result {
-            error!("Failed to save certificate: {}", e);
-            Err(Box::new(e))
-        } else if let Err(e) = chain_result {
-            error!("Failed to save certificate chain: {}", e);
-            Err(Box::new(e))
-        } else if let Err(e) = fullchain_result {
-            error!("Failed to save full certificate chain: {}", e);
-            Err(Box::new(e))
-        } else if let Err(e) = pkey_result {
-            error!("Failed to save private key: {}", e);
-            Err(Box::new(e))
+        writes
+    }
+
+    /// Write the certificate and all of its components to AWS Certificate Manager (ACM).
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        if self.force_new_import {
+            self.import_new_certificate(domain_names, components).await
+        } else if let Some(existing_arns) = &self.certificate_arns {
+            self.reimport_certificate(domain_names, existing_arns.clone(), components).await
         } else {
-            let s3sr = S3StorageResult {
-                bucket: self.bucket.clone(),
-                certificate: cert_key,
-                chain: chain_key,
-                fullchain: fullchain_key,
-                pkey: pkey_key,
+            let existing_arns = self.find_matching_certificate(&domain_names).await?;
+            if existing_arns.is_empty() {
+                self.import_new_certificate(domain_names, components).await
+            } else {
+                self.reimport_certificate(domain_names, existing_arns, components).await
+            }
+        }
+    }
+
+    /// Observe the certificate currently held in ACM. If multiple ARNs are targeted, the one that expires first is
+    /// reported. ACM never returns the private key, so the observed certificate never has components.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let arns = match &self.certificate_arns {
+            Some(arns) => arns.clone(),
+            None => self.find_matching_certificate(&domain_names.to_vec()).await?,
+        };
+
+        let mut earliest: Option<ObservedCertificate> = None;
+
+        for arn in arns {
+            // If any targeted certificate is missing, the entire target needs to be rewritten.
+            let acm = self.acm_client_for_arn(&arn)?;
+            let info = match describe_acm_certificate(&acm, &arn).await? {
+                None => return Ok(None),
+                Some(info) => info,
             };
-            Ok(vec![CertificateStorageResult::S3(s3sr)])
+
+            match &earliest {
+                Some(observed) if observed.info.not_after <= info.not_after => (),
+                _ => {
+                    earliest = Some(ObservedCertificate {
+                        location: arn,
+                        info,
+                        components: None,
+                    })
+                }
+            }
         }
+
+        Ok(earliest)
     }
 }
 
-/// Configuration for storing a certificate in AWS Systems Manager parameter store. In JSON:
+/// Configuration for deploying a certificate to one or more Amazon API Gateway custom domain names. This works for
+/// custom domain names used by REST APIs as well as HTTP and WebSocket APIs. The certificate is imported into ACM
+/// in the region the domain names require: us-east-1 for edge-optimized domain names, or the domain names' own
+/// region for regional domain names. In JSON:
 ///
 ///     {
-///         // The type of storage to use. This must be "SsmParameter".
-///         "Type": "SsmParameter",
+///         // The type of storage to use. This must be "ApiGateway".
+///         "Type": "ApiGateway",
 ///
-///         // The path to store the certificate in. This must start with a "/".
-///         "Path": str,
+///         // The custom domain names to update, e.g. "api.example.com". At least one is required. These must
+///         // already exist and must all have the same endpoint type.
+///         "DomainNames": [str, ...],
+///
+///         // The endpoint type of the custom domain names: "EDGE" or "REGIONAL". If omitted, this is looked up
+///         // from API Gateway. If specified, it must match the domain names' actual endpoint type.
+///         "EndpointType": str,
+///
+///         // The region of the custom domain names. This defaults to the region the function is running in.
+///         "Region": str,
+///
+///         // How the certificate is imported into ACM. See AcmStorage; the Region is set automatically from the
+///         // endpoint type and must match it if specified.
+///         "Acm": { ... },
 ///     }
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub(crate) struct SsmParameterStorage {
-    #[serde(rename = "Path")]
-    pub(crate) path: String,
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ApiGatewayStorage {
+    #[serde(rename = "DomainNames", deserialize_with = "string_or_vec")]
+    #[schemars(schema_with = "string_or_vec_schema")]
+    pub domain_names: Vec<String>,
+
+    #[serde(rename = "EndpointType", default)]
+    pub endpoint_type: Option<String>,
+
+    #[serde(rename = "Region", default)]
+    pub region: Option<String>,
+
+    #[serde(rename = "Acm", default)]
+    pub acm: AcmStorage,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
 }
 
-impl SsmParameterStorage {
-    pub(crate) async fn validate(&mut self) -> Result<(), LambdaError> {
-        match validate_and_sanitize_ssm_parameter_path(&self.path) {
-            Some(path) => {
-                self.path = path;
-                Ok(())
-            }
-            None => Err(InvalidCertificateRequest::invalid_ssm_parameter_path(self.path.clone())),
+impl ApiGatewayStorage {
+    fn api_gateway_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
         }
     }
 
-    pub(crate) async fn save_certificate(
-        &self,
-        domain_names: Vec<String>,
-        components: CertificateComponents,
-    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
-        let (cert, chain, fullchain, pkey) = tokio::join!(
-            self.write_cert_component_to_ssm(domain_names[0].clone(), components.cert_pem, "Certificate", false),
-            self.write_cert_component_to_ssm(domain_names[0].clone(), components.chain_pem, "Chain", false),
-            self.write_cert_component_to_ssm(domain_names[0].clone(), components.fullchain_pem, "FullChain", false),
-            self.write_cert_component_to_ssm(domain_names[0].clone(), components.pkey_pem, "PrivateKey", true),
-        );
+    /// The region the certificate must be imported into for this endpoint type.
+    fn required_acm_region(&self) -> String {
+        if self.endpoint_type.as_deref() == Some(APIGATEWAY_ENDPOINT_EDGE) {
+            APIGATEWAY_EDGE_ACM_REGION.to_string()
+        } else {
+            self.api_gateway_region().name().to_string()
+        }
+    }
 
-        let (cert_param, cert_arn) = cert?;
-        let (chain_param, chain_arn) = chain?;
-        let (fullchain_param, fullchain_arn) = fullchain?;
-        let (pkey_param, pkey_arn) = pkey?;
-        let ssm_result = SsmParameterStorageResult {
-            cert_param,
-            chain_param,
-            fullchain_param,
-            pkey_param,
-            cert_arn,
-            chain_arn,
-            fullchain_arn,
-            pkey_arn,
+    /// Returns the endpoint type ("EDGE" or "REGIONAL") of a custom domain name.
+    async fn get_endpoint_type(&self, domain_name: &str) -> Result<String, LambdaError> {
+        let apigw = ApiGatewayClient::new_with_client(self.assume_role.client()?, self.api_gateway_region());
+        let gdn_request = GetDomainNameRequest {
+            domain_name: domain_name.to_string(),
         };
-        Ok(vec![CertificateStorageResult::SsmParameter(ssm_result)])
-    }
 
-    /// Write a PEM certificate to SSM.
-    async fn write_cert_component_to_ssm(
-        &self,
-        domain_name: String,
-        data: String,
-        component: &'static str,
-        secure: bool,
-    ) -> Result<(String, String), LambdaError> {
-        let ssm = SsmClient::new(Region::default());
-        let path_with_slash = if self.path.ends_with('/') {
-            self.path.to_string()
-        } else {
-            format!("{}/", self.path)
+        let response = match apigw.get_domain_name(gdn_request).await {
+            Ok(response) => response,
+            Err(e) => {
+                error!("Failed to get API Gateway custom domain name {}: {:#}", domain_name, e);
+                return Err(InvalidCertificateRequest::invalid_api_gateway_configuration(format!(
+                    "Unable to get custom domain name {}: {:#}",
+                    domain_name, e
+                )));
+            }
         };
 
-        let param_name = format!("{}Certificate/{}/{}", path_with_slash, domain_name, component);
-        let param_type = if secure {
-            Some("SecureString".to_string())
+        let types = response.endpoint_configuration.and_then(|ec| ec.types).unwrap_or_default();
+        match types.first() {
+            Some(endpoint_type) => Ok(endpoint_type.clone()),
+            // Domain names created before regional endpoints existed have no endpoint configuration.
+            None => Ok(APIGATEWAY_ENDPOINT_EDGE.to_string()),
+        }
+    }
+
+    /// The patch path holding the certificate ARN for this endpoint type.
+    fn certificate_path(&self) -> &'static str {
+        if self.endpoint_type.as_deref() == Some(APIGATEWAY_ENDPOINT_EDGE) {
+            "/certificateArn"
         } else {
-            Some("String".to_string())
-        };
+            "/regionalCertificateArn"
+        }
+    }
 
-        let pp_request = PutParameterRequest {
-            name: param_name.clone(),
-            description: Some(format!("SSL {} for {}", component, domain_name)),
-            overwrite: Some(true),
-            type_: param_type,
-            value: data,
-            tier: Some("Intelligent-Tiering".to_string()),
-            ..Default::default()
+    /// Returns the certificate ARN a custom domain name is currently using.
+    async fn get_certificate_arn(&self, domain_name: &str) -> Result<Option<String>, LambdaError> {
+        let apigw = ApiGatewayClient::new_with_client(self.assume_role.client()?, self.api_gateway_region());
+        let gdn_request = GetDomainNameRequest {
+            domain_name: domain_name.to_string(),
         };
 
-        let gp_request = GetParameterRequest {
-            name: param_name.clone(),
-            ..Default::default()
-        };
+        match apigw.get_domain_name(gdn_request).await {
+            Ok(response) => Ok(if self.endpoint_type.as_deref() == Some(APIGATEWAY_ENDPOINT_EDGE) {
+                response.certificate_arn
+            } else {
+                response.regional_certificate_arn
+            }),
+            Err(e) => {
+                error!("Failed to get API Gateway custom domain name {}: {:#}", domain_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
 
-        info!("Writing SSM parameter {}", param_name);
+    /// Point a custom domain name at the certificate. Returns false if it was already using it.
+    async fn update_domain_name(&self, domain_name: &str, certificate_arn: &str) -> Result<bool, LambdaError> {
+        if self.get_certificate_arn(domain_name).await?.as_deref() == Some(certificate_arn) {
+            info!("API Gateway custom domain name {} is already using {}", domain_name, certificate_arn);
+            return Ok(false);
+        }
 
-        match ssm.put_parameter(pp_request).await {
-            Ok(_) => {
-                info!("SSM parameter {} written successfully", param_name);
+        let apigw = ApiGatewayClient::new_with_client(self.assume_role.client()?, self.api_gateway_region());
+        let udn_request = UpdateDomainNameRequest {
+            domain_name: domain_name.to_string(),
+            patch_operations: Some(vec![PatchOperation {
+                op: Some("replace".to_string()),
+                path: Some(self.certificate_path().to_string()),
+                value: Some(certificate_arn.to_string()),
+                from: None,
+            }]),
+        };
 
-                match ssm.get_parameter(gp_request).await {
-                    Ok(response) => match response.parameter {
-                        None => {
-                            error!("Unable to get ARN for parameter {}: no parameter returned", param_name);
-                            Err(CertificateRequestError::unexpected_aws_response(format!(
-                                "Unable to get ARN for parameter {}: no parameter returned",
-                                param_name
-                            )))
-                        }
-                        Some(parameter) => match parameter.arn {
-                            None => {
-                                error!("Unable to get ARN for parameter {}: no ARN returned", param_name);
-                                Err(CertificateRequestError::unexpected_aws_response(format!(
-                                    "Unable to get ARN for parameter {}: no ARN returned",
-                                    param_name
-                                )))
-                            }
-                            Some(arn) => Ok((param_name, arn)),
-                        },
-                    },
-                    Err(e) => {
-                        error!("Unable to get ARN for parameter {}: {}", param_name, e);
-                        Err(CertificateRequestError::unexpected_aws_response(format!(
-                            "Unable to get ARN for parameter {}: {}",
-                            param_name, e
-                        )))
-                    }
-                }
-            }
+        info!("Updating API Gateway custom domain name {} to use {}", domain_name, certificate_arn);
+        match apigw.update_domain_name(udn_request).await {
+            Ok(_) => Ok(true),
             Err(e) => {
-                error!("Failed to write SSM parameter {}: {:#}", param_name, e);
+                error!("Failed to update API Gateway custom domain name {}: {:#}", domain_name, e);
                 Err(Box::new(e))
             }
         }
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(tag = "Type")]
-pub(crate) enum CertificateStorageResult {
-    Acm(AcmStorageResult),
-    S3(S3StorageResult),
-    SsmParameter(SsmParameterStorageResult),
-    Error(String),
-}
+#[async_trait]
+impl CertificateStore for ApiGatewayStorage {
+    fn type_name(&self) -> &'static str {
+        "ApiGateway"
+    }
 
-/// The results of storing a certificate in ACM. In JSON:
-///
-///     {
-///         // The type of storage. Always "Acm".
-///         "Type": "Acm",
-///
-///         // The ARN of the certificate.
-///         "CertificateArn": str
-///     }
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct AcmStorageResult {
-    #[serde(rename = "CertificateArn")]
-    pub(crate) certificate_arn: String,
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        self.acm.check_no_deployment_regions()?;
+        Ok(None)
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        self.acm.inherit_assume_role(&self.assume_role)?;
+
+        if self.domain_names.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_api_gateway_configuration(
+                "At least one DomainName must be specified",
+            ));
+        }
+
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_api_gateway_configuration(format!(
+                    "Invalid region: {}",
+                    region
+                )));
+            }
+        }
+
+        match self.endpoint_type.as_deref() {
+            None | Some(APIGATEWAY_ENDPOINT_EDGE) | Some(APIGATEWAY_ENDPOINT_REGIONAL) => (),
+            Some(endpoint_type) => {
+                return Err(InvalidCertificateRequest::invalid_api_gateway_configuration(format!(
+                    "Invalid EndpointType: {}",
+                    endpoint_type
+                )))
+            }
+        }
+
+        // Make sure the domain names exist and agree on the endpoint type.
+        for domain_name in &self.domain_names {
+            let endpoint_type = self.get_endpoint_type(domain_name).await?;
+            match self.endpoint_type.as_deref() {
+                None => self.endpoint_type = Some(endpoint_type),
+                Some(expected) if expected == endpoint_type => (),
+                Some(expected) => {
+                    return Err(InvalidCertificateRequest::invalid_api_gateway_configuration(format!(
+                        "Custom domain name {} has endpoint type {}, but {} was expected",
+                        domain_name, endpoint_type, expected
+                    )))
+                }
+            }
+        }
+
+        let acm_region = self.required_acm_region();
+        match self.acm.region.as_deref() {
+            Some(region) if region != acm_region => {
+                return Err(InvalidCertificateRequest::invalid_api_gateway_configuration(format!(
+                    "Certificates for {} custom domain names must be imported into {}, not {}",
+                    self.endpoint_type.as_deref().unwrap_or_default(),
+                    acm_region,
+                    region
+                )))
+            }
+            Some(_) => (),
+            None if Region::default().name() == acm_region => (),
+            None => self.acm.region = Some(acm_region),
+        }
+
+        self.acm.validate().await
+    }
+
+    fn planned_writes(&self, domain_names: &[String], alternate_chain: bool) -> Vec<PlannedWrite> {
+        let region = self.api_gateway_region();
+        let mut writes = self.acm.planned_writes(domain_names, alternate_chain);
+        writes.extend(
+            self.domain_names.iter().map(|domain_name| {
+                PlannedWrite::in_region("apigateway:UpdateDomainName", domain_name.as_str(), &region)
+            }),
+        );
+        writes
+    }
+
+    /// Import the certificate into ACM and point each custom domain name at it.
+    async fn check_key_algorithm(&self, key_algorithm: KeyAlgorithm) -> Result<(), LambdaError> {
+        if self.endpoint_type.as_deref() == Some(APIGATEWAY_ENDPOINT_EDGE) && key_algorithm == KeyAlgorithm::EcdsaP384 {
+            Err(InvalidCertificateRequest::incompatible_key_algorithm(
+                "Edge-optimized API Gateway domain names are served by CloudFront, which does not support EcdsaP384 \
+                 certificates; use EcdsaP256, Rsa2048, or Rsa4096, or a regional domain name",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let acm_results = self.acm.save_certificate(domain_names, components).await?;
+        let mut results = Vec::with_capacity(acm_results.len());
+
+        for acm_result in acm_results {
+            let certificate_arn = match acm_result {
+                CertificateStorageResult::Acm(acm_result) => acm_result.certificate_arn,
+                other => {
+                    results.push(other);
+                    continue;
+                }
+            };
+
+            let mut custom_domain_names = Vec::with_capacity(self.domain_names.len());
+            for domain_name in &self.domain_names {
+                match self.update_domain_name(domain_name, &certificate_arn).await {
+                    Ok(updated) => custom_domain_names.push(ApiGatewayDomainNameResult {
+                        domain_name: domain_name.clone(),
+                        updated,
+                    }),
+                    Err(e) => results.push(CertificateStorageResult::Error(format!(
+                        "Failed to update API Gateway custom domain name {}: {:#}",
+                        domain_name, e
+                    ))),
+                }
+            }
+
+            results.push(CertificateStorageResult::ApiGateway(ApiGatewayStorageResult {
+                certificate_arn,
+                endpoint_type: self.endpoint_type.clone().unwrap_or_default(),
+                domain_names: custom_domain_names,
+            }));
+        }
+
+        Ok(results)
+    }
+
+    /// Observe the certificate in ACM. If any custom domain name is not using it, this reports no certificate so
+    /// that the target is rewritten.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let observed = match self.acm.observe(domain_names).await? {
+            Some(observed) => observed,
+            None => return Ok(None),
+        };
+
+        for domain_name in &self.domain_names {
+            let current_arn = self.get_certificate_arn(domain_name).await?;
+            if current_arn.as_deref() != Some(observed.location.as_str()) {
+                info!(
+                    "API Gateway custom domain name {} is using {:?} instead of {}",
+                    domain_name, current_arn, observed.location
+                );
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(observed))
+    }
 }
 
-/// The results of storing a certificate in S3. In JSON:
+/// Configuration for deploying a certificate to one or more Amazon CloudFront distributions. The certificate is
+/// imported into ACM in us-east-1 (the only region CloudFront can use certificates from) and each distribution is
+/// then updated to use it. In JSON:
 ///
 ///     {
-///         // The type of storage. Always "S3".
-///         "Type": "S3",
+///         // The type of storage to use. This must be "CloudFront".
+///         "Type": "CloudFront",
 ///
-///         // The bucket where the certificate is stored.
-///         "Bucket": str,
+///         // The ids of the distributions to update. At least one is required.
+///         "DistributionIds": [str, ...],
 ///
-///         // The S3 key for the certificate itself.
-///         "Certificate": str,
+///         // How the certificate is imported into ACM. See AcmStorage; the Region is always us-east-1.
+///         "Acm": { ... },
 ///
-///         // The S3 key for the intermediate certificate chain.
-///         "Chain": str,
+///         // The SSL support method to set if a distribution is not already using a custom certificate:
+///         // "sni-only" (the default) or "vip".
+///         "SslSupportMethod": str,
 ///
-///         // The S3 key for the concatenated certificate and intermediate chain.
-///         "FullChain": str,
+///         // The minimum TLS protocol version to set if a distribution is not already using a custom
+///         // certificate. This defaults to "TLSv1.2_2021".
+///         "MinimumProtocolVersion": str,
 ///
-///         // The S3 key for the certificate private key.
-///         "PrivateKey": str,
+///         // Probe the distributions' domain names after updating them, restoring each distribution's previous
+///         // viewer certificate if the new one fails the TLS handshake. See DeploymentVerification.
+///         "Verify": { ... },
 ///     }
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct S3StorageResult {
-    #[serde(rename = "Bucket")]
-    pub(crate) bucket: String,
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CloudFrontStorage {
+    #[serde(rename = "DistributionIds", deserialize_with = "string_or_vec")]
+    #[schemars(schema_with = "string_or_vec_schema")]
+    pub distribution_ids: Vec<String>,
 
-    #[serde(rename = "Certificate")]
-    pub(crate) certificate: String,
+    #[serde(rename = "Acm", default)]
+    pub acm: AcmStorage,
 
-    #[serde(rename = "Chain")]
-    pub(crate) chain: String,
+    #[serde(rename = "SslSupportMethod", default)]
+    pub ssl_support_method: Option<String>,
 
-    #[serde(rename = "FullChain")]
-    pub(crate) fullchain: String,
+    #[serde(rename = "MinimumProtocolVersion", default)]
+    pub minimum_protocol_version: Option<String>,
+
+    #[serde(rename = "Verify", default)]
+    pub verify: Option<DeploymentVerification>,
 
-    #[serde(rename = "PrivateKey")]
-    pub(crate) pkey: String,
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
 }
 
-/// The results of storaing a certificate in the AWS Systems Manager parameter store. In JSON:
+impl CloudFrontStorage {
+    async fn get_distribution_config(
+        &self,
+        distribution_id: &str,
+    ) -> Result<(DistributionConfig, Option<String>), LambdaError> {
+        let cloudfront = CloudFrontClient::new_with_client(self.assume_role.client()?, Region::UsEast1);
+        let gdc_request = GetDistributionConfigRequest {
+            id: distribution_id.to_string(),
+        };
+
+        match cloudfront.get_distribution_config(gdc_request).await {
+            Ok(response) => match response.distribution_config {
+                Some(config) => Ok((config, response.e_tag)),
+                None => Err(CertificateRequestError::unexpected_aws_response(format!(
+                    "No configuration returned for CloudFront distribution {}",
+                    distribution_id
+                ))),
+            },
+            Err(e) => {
+                error!("Failed to get configuration for CloudFront distribution {}: {:#}", distribution_id, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Returns the viewer certificate settings that point a distribution at the certificate. The existing TLS settings
+    /// are kept if the distribution already uses a custom certificate.
+    fn viewer_certificate_for(
+        &self,
+        mut viewer_certificate: ViewerCertificate,
+        certificate_arn: &str,
+    ) -> ViewerCertificate {
+        let had_custom_certificate =
+            viewer_certificate.acm_certificate_arn.is_some() || viewer_certificate.iam_certificate_id.is_some();
+        if !had_custom_certificate || viewer_certificate.ssl_support_method.is_none() {
+            viewer_certificate.ssl_support_method =
+                Some(self.ssl_support_method.clone().unwrap_or_else(|| CLOUDFRONT_SSL_SUPPORT_SNI_ONLY.to_string()));
+        }
+        if !had_custom_certificate || viewer_certificate.minimum_protocol_version.is_none() {
+            viewer_certificate.minimum_protocol_version = Some(
+                self.minimum_protocol_version
+                    .clone()
+                    .unwrap_or_else(|| CLOUDFRONT_DEFAULT_MINIMUM_PROTOCOL_VERSION.to_string()),
+            );
+        }
+
+        viewer_certificate.acm_certificate_arn = Some(certificate_arn.to_string());
+        viewer_certificate.iam_certificate_id = None;
+        viewer_certificate.cloud_front_default_certificate = Some(false);
+        viewer_certificate
+    }
+
+    /// Point a distribution at the certificate, returning the viewer certificate settings it had before the update,
+    /// or None if the distribution was already using the certificate.
+    async fn update_distribution(
+        &self,
+        distribution_id: &str,
+        certificate_arn: &str,
+    ) -> Result<Option<ViewerCertificate>, LambdaError> {
+        let (mut config, e_tag) = self.get_distribution_config(distribution_id).await?;
+        let viewer_certificate = config.viewer_certificate.take().unwrap_or_default();
+
+        if viewer_certificate.acm_certificate_arn.as_deref() == Some(certificate_arn) {
+            info!("CloudFront distribution {} is already using {}", distribution_id, certificate_arn);
+            return Ok(None);
+        }
+
+        let previous = viewer_certificate.clone();
+        config.viewer_certificate = Some(self.viewer_certificate_for(viewer_certificate, certificate_arn));
+
+        info!("Updating CloudFront distribution {} to use {}", distribution_id, certificate_arn);
+        self.put_distribution_config(distribution_id, config, e_tag).await?;
+        Ok(Some(previous))
+    }
+
+    /// Restore the viewer certificate settings a distribution had before it was updated.
+    async fn restore_distribution(
+        &self,
+        distribution_id: &str,
+        viewer_certificate: ViewerCertificate,
+    ) -> Result<(), LambdaError> {
+        let (mut config, e_tag) = self.get_distribution_config(distribution_id).await?;
+        config.viewer_certificate = Some(viewer_certificate);
+
+        warn!("Rolling back CloudFront distribution {} to its previous certificate", distribution_id);
+        self.put_distribution_config(distribution_id, config, e_tag).await
+    }
+
+    async fn put_distribution_config(
+        &self,
+        distribution_id: &str,
+        config: DistributionConfig,
+        e_tag: Option<String>,
+    ) -> Result<(), LambdaError> {
+        let cloudfront = CloudFrontClient::new_with_client(self.assume_role.client()?, Region::UsEast1);
+        let ud_request = UpdateDistributionRequest {
+            distribution_config: config,
+            id: distribution_id.to_string(),
+            if_match: e_tag,
+        };
+
+        match cloudfront.update_distribution(ud_request).await {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                error!("Failed to update CloudFront distribution {}: {:#}", distribution_id, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CertificateStore for CloudFrontStorage {
+    fn type_name(&self) -> &'static str {
+        "CloudFront"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        self.acm.check_no_deployment_regions()?;
+        Ok(None)
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        self.acm.inherit_assume_role(&self.assume_role)?;
+
+        if self.distribution_ids.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_cloudfront_configuration(
+                "At least one DistributionId must be specified",
+            ));
+        }
+
+        match self.ssl_support_method.as_deref() {
+            None | Some(CLOUDFRONT_SSL_SUPPORT_SNI_ONLY) | Some(CLOUDFRONT_SSL_SUPPORT_VIP) => (),
+            Some(method) => {
+                return Err(InvalidCertificateRequest::invalid_cloudfront_configuration(format!(
+                    "Invalid SslSupportMethod: {}",
+                    method
+                )))
+            }
+        }
+
+        if let Some(verify) = &self.verify {
+            verify.validate()?;
+        }
+
+        match self.acm.region.as_deref() {
+            None | Some(CLOUDFRONT_ACM_REGION) => self.acm.region = Some(CLOUDFRONT_ACM_REGION.to_string()),
+            Some(region) => {
+                return Err(InvalidCertificateRequest::invalid_cloudfront_configuration(format!(
+                    "CloudFront certificates must be imported into {}, not {}",
+                    CLOUDFRONT_ACM_REGION, region
+                )))
+            }
+        }
+
+        self.acm.validate().await
+    }
+
+    fn planned_writes(&self, domain_names: &[String], alternate_chain: bool) -> Vec<PlannedWrite> {
+        let mut writes = self.acm.planned_writes(domain_names, alternate_chain);
+        writes.extend(
+            self.distribution_ids
+                .iter()
+                .map(|distribution_id| PlannedWrite::new("cloudfront:UpdateDistribution", distribution_id.as_str())),
+        );
+        writes
+    }
+
+    /// Import the certificate into ACM and point each distribution at it.
+    async fn check_key_algorithm(&self, key_algorithm: KeyAlgorithm) -> Result<(), LambdaError> {
+        if key_algorithm == KeyAlgorithm::EcdsaP384 {
+            Err(InvalidCertificateRequest::incompatible_key_algorithm(
+                "CloudFront does not support EcdsaP384 certificates; use EcdsaP256, Rsa2048, or Rsa4096",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let serial = CertificateInfo::from_pem(&components.cert_pem).ok().map(|info| info.serial);
+        let acm_results = self.acm.save_certificate(domain_names.clone(), components).await?;
+        let mut results = Vec::with_capacity(acm_results.len());
+
+        for acm_result in acm_results {
+            let certificate_arn = match acm_result {
+                CertificateStorageResult::Acm(acm_result) => acm_result.certificate_arn,
+                other => {
+                    results.push(other);
+                    continue;
+                }
+            };
+
+            let mut distributions = Vec::with_capacity(self.distribution_ids.len());
+            let mut previous_viewer_certificates = HashMap::new();
+            for distribution_id in &self.distribution_ids {
+                match self.update_distribution(distribution_id, &certificate_arn).await {
+                    Ok(previous) => {
+                        distributions.push(CloudFrontDistributionResult {
+                            distribution_id: distribution_id.clone(),
+                            updated: previous.is_some(),
+                            previous_certificate_arn: previous.as_ref().and_then(|vc| vc.acm_certificate_arn.clone()),
+                            rolled_back: false,
+                        });
+                        if let Some(previous) = previous {
+                            previous_viewer_certificates.insert(distribution_id.clone(), previous);
+                        }
+                    }
+                    Err(e) => results.push(CertificateStorageResult::Error(format!(
+                        "Failed to update CloudFront distribution {}: {:#}",
+                        distribution_id, e
+                    ))),
+                }
+            }
+
+            let verification = match (&self.verify, &serial) {
+                (Some(verify), Some(serial)) => Some(verify.verify(&domain_names, serial).await),
+                _ => None,
+            };
+
+            if let (Some(verify), Some(verification)) = (&self.verify, &verification) {
+                if verify.should_roll_back(verification) {
+                    for distribution in distributions.iter_mut() {
+                        let previous = match previous_viewer_certificates.remove(&distribution.distribution_id) {
+                            Some(previous) => previous,
+                            None => continue,
+                        };
+
+                        match self.restore_distribution(&distribution.distribution_id, previous).await {
+                            Ok(()) => distribution.rolled_back = true,
+                            Err(e) => results.push(CertificateStorageResult::Error(format!(
+                                "Failed to roll back CloudFront distribution {}: {:#}",
+                                distribution.distribution_id, e
+                            ))),
+                        }
+                    }
+                }
+
+                if !verification.verified && !distributions.iter().any(|d| d.rolled_back) {
+                    results.push(CertificateStorageResult::Error(format!(
+                        "CloudFront deployment of {} failed verification: {}",
+                        certificate_arn,
+                        verification.failures.join("; ")
+                    )));
+                }
+            }
+
+            results.push(CertificateStorageResult::CloudFront(CloudFrontStorageResult {
+                certificate_arn,
+                distributions,
+                verification,
+            }));
+        }
+
+        Ok(results)
+    }
+
+    /// Observe the certificate in ACM. If any distribution is not using it, this reports no certificate so that
+    /// the target is rewritten.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let observed = match self.acm.observe(domain_names).await? {
+            Some(observed) => observed,
+            None => return Ok(None),
+        };
+
+        for distribution_id in &self.distribution_ids {
+            let (config, _) = self.get_distribution_config(distribution_id).await?;
+            let current_arn = config.viewer_certificate.and_then(|vc| vc.acm_certificate_arn);
+            if current_arn.as_deref() != Some(observed.location.as_str()) {
+                info!(
+                    "CloudFront distribution {} is using {:?} instead of {}",
+                    distribution_id, current_arn, observed.location
+                );
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(observed))
+    }
+}
+
+/// Configuration for storing a certificate as an IAM server certificate, for services (such as CloudFront and
+/// Classic Load Balancers) in accounts that can't use ACM. IAM server certificates can't be updated in place, so
+/// each certificate is uploaded under a new name made of the NamePrefix and the certificate's serial number, and
+/// previous certificates with the same prefix are deleted afterwards. In JSON:
 ///
 ///     {
-///         // The type of storage. Always "SsmParameter".
-///         "Type": "SsmParameter",
+///         // The type of storage to use. This must be "IamServerCertificate".
+///         "Type": "IamServerCertificate",
 ///
-///         // The name of the parameter containing the certificate.
-///         "CertificateParameterName": str,
+///         // The prefix for server certificate names. This defaults to the first domain name followed by "-",
+///         // with "*" replaced by "_".
+///         "NamePrefix": str,
 ///
-///         // The name opf the parameter containing the intermediate certificate(s).
-///         "ChainParameterName": str,
+///         // The IAM path for the server certificates. This must begin and end with "/". Use "/cloudfront/" (or a
+///         // path beneath it) for certificates used by CloudFront. The default is "/".
+///         "Path": str,
 ///
-///         // The name of the parameter containing the concatenated certificate and intermediate chain.
-///         "FullChainParameterName": str,
+///         // If true (the default), delete previous server certificates with the same NamePrefix and Path after
+///         // uploading a new one. Certificates that are still in use can't be deleted; these are reported in the
+///         // result and retried on the next upload.
+///         "DeletePrevious": bool,
 ///
-///         // The name of the parameter containing the certificate private key.
-///         "PrivateKeyParameterName": str,
+///         // Tags to apply to each server certificate.
+///         "Tags": {str: str, ...},
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct IamServerCertificateStorage {
+    #[serde(rename = "NamePrefix", default)]
+    pub name_prefix: Option<String>,
+
+    #[serde(rename = "Path", default)]
+    pub path: Option<String>,
+
+    #[serde(rename = "DeletePrevious", default = "default_true")]
+    pub delete_previous: bool,
+
+    #[serde(rename = "Tags", default)]
+    pub tags: Option<HashMap<String, String>>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+/// The longest serial number (in hex) that is appended to the name prefix. RFC 5280 limits serial numbers to 20
+/// octets.
+const IAM_SERIAL_HEX_LENGTH: usize = 40;
+
+impl IamServerCertificateStorage {
+    fn iam_client(&self) -> Result<IamClient, LambdaError> {
+        Ok(IamClient::new_with_client(self.assume_role.client()?, Region::default()))
+    }
+
+    fn name_prefix(&self, domain_names: &[String]) -> String {
+        match &self.name_prefix {
+            Some(prefix) => prefix.clone(),
+            None => {
+                let mut prefix = format!("{}-", domain_name_for_path(&domain_names[0]));
+                prefix.truncate(IAM_MAX_SERVER_CERTIFICATE_NAME_LENGTH - IAM_SERIAL_HEX_LENGTH);
+                prefix
+            }
+        }
+    }
+
+    fn path(&self) -> String {
+        self.path.clone().unwrap_or_else(|| "/".to_string())
+    }
+
+    /// Returns the server certificates with our path and name prefix.
+    async fn list_server_certificates(
+        &self,
+        domain_names: &[String],
+    ) -> Result<Vec<ServerCertificateMetadata>, LambdaError> {
+        let iam = self.iam_client()?;
+        let prefix = self.name_prefix(domain_names);
+        let path = self.path();
+        let mut lsc_request = ListServerCertificatesRequest {
+            path_prefix: Some(path.clone()),
+            ..Default::default()
+        };
+        let mut certificates = Vec::new();
+
+        loop {
+            match iam.list_server_certificates(lsc_request.clone()).await {
+                Ok(response) => {
+                    certificates.extend(
+                        response
+                            .server_certificate_metadata_list
+                            .into_iter()
+                            .filter(|meta| meta.path == path && meta.server_certificate_name.starts_with(&prefix)),
+                    );
+
+                    match (response.is_truncated, response.marker) {
+                        (Some(true), Some(marker)) => lsc_request.marker = Some(marker),
+                        _ => break,
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to list IAM server certificates: {:#}", e);
+                    return Err(Box::new(e));
+                }
+            }
+        }
+
+        Ok(certificates)
+    }
+}
+
+#[async_trait]
+impl CertificateStore for IamServerCertificateStorage {
+    fn type_name(&self) -> &'static str {
+        "IamServerCertificate"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        if let Some(prefix) = &self.name_prefix {
+            if prefix.is_empty()
+                || prefix.len() > IAM_MAX_SERVER_CERTIFICATE_NAME_LENGTH - IAM_SERIAL_HEX_LENGTH
+                || !prefix.chars().all(is_iam_name_char)
+            {
+                return Err(InvalidCertificateRequest::invalid_iam_server_certificate_configuration(format!(
+                    "NamePrefix must be 1-{} characters from [A-Za-z0-9_+=,.@-]: {:?}",
+                    IAM_MAX_SERVER_CERTIFICATE_NAME_LENGTH - IAM_SERIAL_HEX_LENGTH,
+                    prefix
+                )));
+            }
+        }
+
+        if let Some(path) = &self.path {
+            if !path.starts_with('/') || !path.ends_with('/') || path.len() > 512 {
+                return Err(InvalidCertificateRequest::invalid_iam_server_certificate_configuration(format!(
+                    "Path must begin and end with \"/\" and be at most 512 characters: {:?}",
+                    path
+                )));
+            }
+        }
+
+        if let Some(tags) = &self.tags {
+            if tags.len() > IAM_MAX_TAGS {
+                return Err(InvalidCertificateRequest::invalid_iam_server_certificate_configuration(format!(
+                    "At most {} tags can be applied to a server certificate",
+                    IAM_MAX_TAGS
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn planned_writes(&self, domain_names: &[String], _alternate_chain: bool) -> Vec<PlannedWrite> {
+        // The name ends with the serial number of the certificate, which isn't known until it's issued.
+        let name = format!("{}{}<serial>", self.path(), self.name_prefix(domain_names));
+        let mut writes = vec![PlannedWrite::new("iam:UploadServerCertificate", name)];
+        if self.delete_previous {
+            writes.push(PlannedWrite::new(
+                "iam:DeleteServerCertificate",
+                format!("(previous certificates named {}{}*)", self.path(), self.name_prefix(domain_names)),
+            ));
+        }
+        writes
+    }
+
+    /// Upload the certificate as a new server certificate and delete the ones it replaces.
+    async fn check_key_algorithm(&self, key_algorithm: KeyAlgorithm) -> Result<(), LambdaError> {
+        if key_algorithm.is_ecdsa() {
+            Err(InvalidCertificateRequest::incompatible_key_algorithm(format!(
+                "IAM server certificates require an RSA key, not {}; use Rsa2048 or Rsa4096, or use ACM (e.g. the \
+                 LoadBalancer storage type) for ECDSA certificates",
+                key_algorithm
+            )))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let info = CertificateInfo::from_pem(&components.cert_pem)?;
+        let mut serial = info.serial.clone();
+        serial.truncate(IAM_SERIAL_HEX_LENGTH);
+        let name = format!("{}{}", self.name_prefix(&domain_names), serial);
+
+        let tags = self.tags.as_ref().map(|tags| {
+            let mut iam_tags: Vec<IamTag> = tags
+                .iter()
+                .map(|(key, value)| IamTag {
+                    key: key.clone(),
+                    value: value.clone(),
+                })
+                .collect();
+            iam_tags.sort_by(|a, b| a.key.cmp(&b.key));
+            iam_tags
+        });
+
+        let iam = self.iam_client()?;
+        let usc_request = UploadServerCertificateRequest {
+            certificate_body: components.cert_pem,
+            certificate_chain: Some(components.chain_pem),
+            path: Some(self.path()),
+            private_key: components.pkey_pem,
+            server_certificate_name: name.clone(),
+            tags,
+        };
+
+        info!("Uploading IAM server certificate {} for {}", name, domain_names.join(" "));
+        let metadata = match iam.upload_server_certificate(usc_request).await {
+            Ok(response) => match response.server_certificate_metadata {
+                Some(metadata) => metadata,
+                None => {
+                    return Ok(vec![CertificateStorageResult::Error(format!(
+                        "No metadata returned for IAM server certificate {}",
+                        name
+                    ))])
+                }
+            },
+            Err(e) => {
+                error!("Failed to upload IAM server certificate {}: {:#}", name, e);
+                return Ok(vec![CertificateStorageResult::Error(format!(
+                    "Failed to upload IAM server certificate {}: {:#}",
+                    name, e
+                ))]);
+            }
+        };
+
+        let mut deleted = Vec::new();
+        let mut not_deleted = Vec::new();
+
+        if self.delete_previous {
+            for previous in self.list_server_certificates(&domain_names).await? {
+                if previous.server_certificate_name == name {
+                    continue;
+                }
+
+                let dsc_request = DeleteServerCertificateRequest {
+                    server_certificate_name: previous.server_certificate_name.clone(),
+                };
+
+                info!("Deleting previous IAM server certificate {}", previous.server_certificate_name);
+                match iam.delete_server_certificate(dsc_request).await {
+                    Ok(_) => deleted.push(previous.server_certificate_name),
+                    Err(e) => {
+                        // Most likely still attached to a load balancer or distribution.
+                        error!(
+                            "Failed to delete previous IAM server certificate {}: {:#}",
+                            previous.server_certificate_name, e
+                        );
+                        not_deleted.push(previous.server_certificate_name);
+                    }
+                }
+            }
+        }
+
+        Ok(vec![CertificateStorageResult::IamServerCertificate(IamServerCertificateStorageResult {
+            server_certificate_name: metadata.server_certificate_name,
+            server_certificate_id: metadata.server_certificate_id,
+            arn: metadata.arn,
+            deleted_server_certificate_names: deleted,
+            undeleted_server_certificate_names: not_deleted,
+        })])
+    }
+
+    /// Observe the most recently uploaded server certificate with our name prefix. IAM never returns the private
+    /// key, so the observed certificate never has components.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let latest = self
+            .list_server_certificates(domain_names)
+            .await?
+            .into_iter()
+            .max_by(|a, b| a.upload_date.cmp(&b.upload_date));
+
+        let latest = match latest {
+            Some(latest) => latest,
+            None => return Ok(None),
+        };
+
+        let gsc_request = GetServerCertificateRequest {
+            server_certificate_name: latest.server_certificate_name.clone(),
+        };
+
+        let server_certificate = match self.iam_client()?.get_server_certificate(gsc_request).await {
+            Ok(response) => response.server_certificate,
+            Err(e) => {
+                error!("Failed to get IAM server certificate {}: {:#}", latest.server_certificate_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        Ok(Some(ObservedCertificate {
+            location: latest.arn,
+            info: CertificateInfo::from_pem(&server_certificate.certificate_body)?,
+            components: None,
+        }))
+    }
+}
+
+/// Indicates whether a character is allowed in an IAM server certificate name.
+fn is_iam_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "_+=,.@-".contains(c)
+}
+
+/// Configuration for deploying a certificate to one or more Elastic Load Balancing (Application or Network Load
+/// Balancer) listeners. The certificate is imported into ACM in the listeners' region and attached to each listener.
+/// In JSON:
 ///
-///         // The ARN of the parameter for the certificate.
-///         "CertificateArn": str,
+///     {
+///         // The type of storage to use. This must be "LoadBalancer".
+///         "Type": "LoadBalancer",
 ///
-///         // The ARN of the parameter for the intermediate certificate chain.
-///         "ChainArn": str,
+///         // The ARNs of the HTTPS or TLS listeners to update. At least one is required, and all must be in the
+///         // same region.
+///         "ListenerArns": [str, ...],
 ///
-///         // The ARN of the parameter for the concatenated certificate and intermediate chain.
-///         "FullChainArn": str,
+///         // How the certificate is imported into ACM. See AcmStorage; the Region defaults to the listeners'
+///         // region and must match it if specified.
+///         "Acm": { ... },
 ///
-///         // The ARN of the parameter for the certificate private key.
-///         "PrivateKeyArn": str,
+///         // If true (the default), make the certificate the listener's default certificate. Otherwise, add it to
+///         // the listener's certificate list for SNI.
+///         "Default": bool,
+///
+///         // If true, remove other certificates with the same domain names from the listener's certificate list
+///         // after attaching this one. The removed certificates are not deleted from ACM. The default is false.
+///         "RemovePreviousCertificate": bool,
+///
+///         // Probe the listeners' endpoints after attaching the certificate. If the new certificate fails the TLS
+///         // handshake, each updated listener is reverted: its previous default certificate is restored (or the
+///         // new certificate is removed from its certificate list) and any removed certificates are re-added. See
+///         // DeploymentVerification.
+///         "Verify": { ... },
 ///     }
-#[derive(Debug, Deserialize, Serialize)]
-pub(crate) struct SsmParameterStorageResult {
-    #[serde(rename = "CertificateParameterName")]
-    pub(crate) cert_param: String,
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct LoadBalancerStorage {
+    #[serde(rename = "ListenerArns", deserialize_with = "string_or_vec")]
+    #[schemars(schema_with = "string_or_vec_schema")]
+    pub listener_arns: Vec<String>,
 
-    #[serde(rename = "ChainParameterName")]
-    pub(crate) chain_param: String,
+    #[serde(rename = "Acm", default)]
+    pub acm: AcmStorage,
 
-    #[serde(rename = "FullChainParameterName")]
-    pub(crate) fullchain_param: String,
+    #[serde(rename = "Default", default = "default_true")]
+    pub default: bool,
 
-    #[serde(rename = "PrivateKeyParameterName")]
-    pub(crate) pkey_param: String,
+    #[serde(rename = "RemovePreviousCertificate", default = "default_false")]
+    pub remove_previous_certificate: bool,
 
-    #[serde(rename = "CertificateArn")]
-    pub(crate) cert_arn: String,
+    #[serde(rename = "Verify", default)]
+    pub verify: Option<DeploymentVerification>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+impl LoadBalancerStorage {
+    fn elb_client(&self) -> Result<ElbClient, LambdaError> {
+        Ok(ElbClient::new_with_client(self.assume_role.client()?, self.acm.acm_region()))
+    }
+
+    /// Returns all certificates attached to a listener, including the default certificate.
+    async fn get_listener_certificates(&self, listener_arn: &str) -> Result<Vec<ListenerCertificate>, LambdaError> {
+        let elb = self.elb_client()?;
+
+        // DescribeListenerCertificates doesn't reliably flag the default certificate, so get it from the listener.
+        let dl_request = DescribeListenersInput {
+            listener_arns: Some(vec![listener_arn.to_string()]),
+            ..Default::default()
+        };
+
+        let mut certificates = match elb.describe_listeners(dl_request).await {
+            Ok(response) => response
+                .listeners
+                .unwrap_or_default()
+                .into_iter()
+                .flat_map(|listener| listener.certificates.unwrap_or_default())
+                .map(|cert| ListenerCertificate {
+                    certificate_arn: cert.certificate_arn,
+                    is_default: Some(true),
+                })
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                error!("Failed to describe listener {}: {:#}", listener_arn, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let mut dlc_request = DescribeListenerCertificatesInput {
+            listener_arn: listener_arn.to_string(),
+            ..Default::default()
+        };
+
+        loop {
+            match elb.describe_listener_certificates(dlc_request.clone()).await {
+                Ok(response) => {
+                    for cert in response.certificates.unwrap_or_default() {
+                        if !certificates.iter().any(|c| c.certificate_arn == cert.certificate_arn) {
+                            certificates.push(cert);
+                        }
+                    }
+
+                    match response.next_marker {
+                        None => break,
+                        Some(marker) => dlc_request.marker = Some(marker),
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to describe certificates for listener {}: {:#}", listener_arn, e);
+                    return Err(Box::new(e));
+                }
+            }
+        }
 
-    #[serde(rename = "ChainArn")]
-    pub(crate) chain_arn: String,
+        Ok(certificates)
+    }
+
+    /// Attach the certificate to a listener and, if requested, remove certificates it replaces.
+    async fn update_listener(
+        &self,
+        listener_arn: &str,
+        certificate_arn: &str,
+        domain_names: &[String],
+    ) -> Result<ListenerResult, LambdaError> {
+        let elb = self.elb_client()?;
+        let existing = self.get_listener_certificates(listener_arn).await?;
+        let new_certificate = ListenerCertificate {
+            certificate_arn: Some(certificate_arn.to_string()),
+            is_default: None,
+        };
 
-    #[serde(rename = "FullChainArn")]
-    pub(crate) fullchain_arn: String,
+        let previous_certificate_arn =
+            existing.iter().find(|cert| cert.is_default == Some(true)).and_then(|cert| cert.certificate_arn.clone());
 
-    #[serde(rename = "PrivateKeyArn")]
-    pub(crate) pkey_arn: String,
+        let already_attached = existing.iter().any(|cert| {
+            cert.certificate_arn.as_deref() == Some(certificate_arn) && (!self.default || cert.is_default == Some(true))
+        });
+
+        let updated = if already_attached {
+            info!("Listener {} is already using {}", listener_arn, certificate_arn);
+            false
+        } else if self.default {
+            info!("Setting the default certificate for listener {} to {}", listener_arn, certificate_arn);
+            let ml_request = ModifyListenerInput {
+                listener_arn: listener_arn.to_string(),
+                certificates: Some(vec![new_certificate]),
+                ..Default::default()
+            };
+
+            if let Err(e) = elb.modify_listener(ml_request).await {
+                error!("Failed to modify listener {}: {:#}", listener_arn, e);
+                return Err(Box::new(e));
+            }
+            true
+        } else {
+            info!("Adding {} to the certificates for listener {}", certificate_arn, listener_arn);
+            let alc_request = AddListenerCertificatesInput {
+                listener_arn: listener_arn.to_string(),
+                certificates: vec![new_certificate],
+            };
+
+            if let Err(e) = elb.add_listener_certificates(alc_request).await {
+                error!("Failed to add certificate to listener {}: {:#}", listener_arn, e);
+                return Err(Box::new(e));
+            }
+            true
+        };
+
+        let mut removed_certificate_arns = Vec::new();
+        if self.remove_previous_certificate {
+            for cert in existing {
+                let arn = match cert.certificate_arn {
+                    Some(arn) if arn != certificate_arn && cert.is_default != Some(true) => arn,
+                    _ => continue,
+                };
+
+                if self.has_same_domain_names(&arn, domain_names).await? {
+                    removed_certificate_arns.push(arn);
+                }
+            }
+
+            if !removed_certificate_arns.is_empty() {
+                info!("Removing {} from listener {}", removed_certificate_arns.join(" "), listener_arn);
+                let rlc_request = RemoveListenerCertificatesInput {
+                    listener_arn: listener_arn.to_string(),
+                    certificates: removed_certificate_arns
+                        .iter()
+                        .map(|arn| ListenerCertificate {
+                            certificate_arn: Some(arn.clone()),
+                            is_default: None,
+                        })
+                        .collect(),
+                };
+
+                if let Err(e) = elb.remove_listener_certificates(rlc_request).await {
+                    error!("Failed to remove certificates from listener {}: {:#}", listener_arn, e);
+                    return Err(Box::new(e));
+                }
+            }
+        }
+
+        Ok(ListenerResult {
+            listener_arn: listener_arn.to_string(),
+            updated,
+            previous_certificate_arn,
+            removed_certificate_arns,
+            rolled_back: false,
+        })
+    }
+
+    /// Undo update_listener: restore the previous default certificate (or remove the certificate from the
+    /// certificate list) and re-add any certificates that were removed.
+    async fn rollback_listener(&self, listener: &ListenerResult, certificate_arn: &str) -> Result<(), LambdaError> {
+        let elb = self.elb_client()?;
+        let listener_arn = &listener.listener_arn;
+        let certificate = |arn: &str| ListenerCertificate {
+            certificate_arn: Some(arn.to_string()),
+            is_default: None,
+        };
+
+        if self.default {
+            let previous = match &listener.previous_certificate_arn {
+                Some(previous) => previous,
+                None => return Err(CertificateRequestError::rollback_not_possible(listener_arn)),
+            };
+
+            warn!("Rolling back the default certificate for listener {} to {}", listener_arn, previous);
+            let ml_request = ModifyListenerInput {
+                listener_arn: listener_arn.clone(),
+                certificates: Some(vec![certificate(previous)]),
+                ..Default::default()
+            };
+
+            if let Err(e) = elb.modify_listener(ml_request).await {
+                error!("Failed to modify listener {}: {:#}", listener_arn, e);
+                return Err(Box::new(e));
+            }
+        } else {
+            warn!("Rolling back listener {} by removing {}", listener_arn, certificate_arn);
+            let rlc_request = RemoveListenerCertificatesInput {
+                listener_arn: listener_arn.clone(),
+                certificates: vec![certificate(certificate_arn)],
+            };
+
+            if let Err(e) = elb.remove_listener_certificates(rlc_request).await {
+                error!("Failed to remove certificate from listener {}: {:#}", listener_arn, e);
+                return Err(Box::new(e));
+            }
+        }
+
+        if !listener.removed_certificate_arns.is_empty() {
+            info!("Re-adding {} to listener {}", listener.removed_certificate_arns.join(" "), listener_arn);
+            let alc_request = AddListenerCertificatesInput {
+                listener_arn: listener_arn.clone(),
+                certificates: listener.removed_certificate_arns.iter().map(|arn| certificate(arn)).collect(),
+            };
+
+            if let Err(e) = elb.add_listener_certificates(alc_request).await {
+                error!("Failed to add certificates to listener {}: {:#}", listener_arn, e);
+                return Err(Box::new(e));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Indicates whether an ACM certificate covers exactly the requested domain names. Certificates that can't be
+    /// described (e.g. IAM server certificates) are never considered the same.
+    async fn has_same_domain_names(&self, certificate_arn: &str, domain_names: &[String]) -> Result<bool, LambdaError> {
+        if !certificate_arn.contains(":acm:") {
+            return Ok(false);
+        }
+
+        let dc_request = DescribeCertificateRequest {
+            certificate_arn: certificate_arn.to_string(),
+        };
+
+        let acm = self.acm.acm_client()?;
+        let detail = match throttle::call("DescribeCertificate", || acm.describe_certificate(dc_request.clone())).await
+        {
+            Ok(response) => response.certificate,
+            Err(RusotoError::Service(DescribeCertificateError::ResourceNotFound(_))) => None,
+            Err(e) => {
+                error!("Failed to describe ACM certificate {}: {:#}", certificate_arn, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let alt_names = detail.and_then(|detail| detail.subject_alternative_names).unwrap_or_default();
+        Ok(same_domain_names(&alt_names, domain_names))
+    }
+}
+
+/// Indicates whether two lists of domain names cover the same names, ignoring case, order, and duplicates.
+fn same_domain_names(a: &[String], b: &[String]) -> bool {
+    let normalize = |names: &[String]| {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_lowercase()).collect();
+        names.sort();
+        names.dedup();
+        names
+    };
+
+    normalize(a) == normalize(b)
+}
+
+#[async_trait]
+impl CertificateStore for LoadBalancerStorage {
+    fn type_name(&self) -> &'static str {
+        "LoadBalancer"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        self.acm.check_no_deployment_regions()?;
+        Ok(None)
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        self.acm.inherit_assume_role(&self.assume_role)?;
+
+        if self.listener_arns.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_load_balancer_configuration(
+                "At least one ListenerArn must be specified",
+            ));
+        }
+
+        let mut listener_region: Option<&str> = None;
+        for arn_str in &self.listener_arns {
+            // Should be arn:aws...:elasticloadbalancing:region:account:listener/app-or-net/name/lb-id/listener-id
+            let parts = arn_str.split(':').collect::<Vec<&str>>();
+            if parts.len() != 6
+                || parts[0] != "arn"
+                || parts[1].is_empty()
+                || parts[2] != "elasticloadbalancing"
+                || parts[4].len() != 12
+                || !parts[5].starts_with("listener/")
+                || Region::from_str(parts[3]).is_err()
+            {
+                return Err(InvalidCertificateRequest::invalid_load_balancer_configuration(format!(
+                    "Invalid listener ARN: {}",
+                    arn_str
+                )));
+            }
+
+            match listener_region {
+                None => listener_region = Some(parts[3]),
+                Some(region) if region == parts[3] => (),
+                Some(region) => {
+                    return Err(InvalidCertificateRequest::invalid_load_balancer_configuration(format!(
+                        "All listeners must be in the same region: found {} and {}",
+                        region, parts[3]
+                    )))
+                }
+            }
+        }
+
+        if let Some(verify) = &self.verify {
+            verify.validate()?;
+        }
+
+        // ELB can only use ACM certificates from its own region.
+        let listener_region = listener_region.expect("At least one listener ARN should be present here").to_string();
+        match self.acm.region.as_deref() {
+            Some(region) if region != listener_region => {
+                return Err(InvalidCertificateRequest::invalid_load_balancer_configuration(format!(
+                    "Certificates for listeners in {} must be imported into {}, not {}",
+                    listener_region, listener_region, region
+                )))
+            }
+            Some(_) => (),
+            None if Region::default().name() == listener_region => (),
+            None => self.acm.region = Some(listener_region),
+        }
+
+        self.acm.validate().await
+    }
+
+    fn planned_writes(&self, domain_names: &[String], alternate_chain: bool) -> Vec<PlannedWrite> {
+        let action = if self.default {
+            "elasticloadbalancing:ModifyListener"
+        } else {
+            "elasticloadbalancing:AddListenerCertificates"
+        };
+
+        let mut writes = self.acm.planned_writes(domain_names, alternate_chain);
+        for listener_arn in &self.listener_arns {
+            writes.push(PlannedWrite::new(action, listener_arn.as_str()));
+            if self.remove_previous_certificate {
+                writes
+                    .push(PlannedWrite::new("elasticloadbalancing:RemoveListenerCertificates", listener_arn.as_str()));
+            }
+        }
+        writes
+    }
+
+    /// Import the certificate into ACM and attach it to each listener.
+    /// ECDSA certificates can only be negotiated if the listener's security policy includes ECDSA cipher suites (or
+    /// TLS 1.3, whose suites work with either key type).
+    async fn check_key_algorithm(&self, key_algorithm: KeyAlgorithm) -> Result<(), LambdaError> {
+        if !key_algorithm.is_ecdsa() {
+            return Ok(());
+        }
+
+        let elb = self.elb_client()?;
+        let dl_request = DescribeListenersInput {
+            listener_arns: Some(self.listener_arns.clone()),
+            ..Default::default()
+        };
+
+        let listeners = match elb.describe_listeners(dl_request).await {
+            Ok(response) => response.listeners.unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to describe listeners: {:#}", e);
+                return Err(Box::new(e));
+            }
+        };
+
+        for listener in listeners {
+            let policy_name = match listener.ssl_policy {
+                Some(policy_name) => policy_name,
+                None => continue,
+            };
+
+            let dsp_request = DescribeSSLPoliciesInput {
+                names: Some(vec![policy_name.clone()]),
+                ..Default::default()
+            };
+
+            let ciphers: Vec<String> = match elb.describe_ssl_policies(dsp_request).await {
+                Ok(response) => response
+                    .ssl_policies
+                    .unwrap_or_default()
+                    .into_iter()
+                    .flat_map(|policy| policy.ciphers.unwrap_or_default())
+                    .filter_map(|cipher| cipher.name)
+                    .collect(),
+                Err(e) => {
+                    error!("Failed to describe SSL policy {}: {:#}", policy_name, e);
+                    return Err(Box::new(e));
+                }
+            };
+
+            if !ciphers.iter().any(|cipher| cipher.contains("ECDSA") || cipher.starts_with("TLS_")) {
+                return Err(InvalidCertificateRequest::incompatible_key_algorithm(format!(
+                    "Listener {} uses security policy {}, which has no ECDSA cipher suites; use Rsa2048 or change \
+                     the listener to a policy that supports ECDSA, such as ELBSecurityPolicy-TLS13-1-2-2021-06",
+                    listener.listener_arn.unwrap_or_default(),
+                    policy_name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let serial = CertificateInfo::from_pem(&components.cert_pem).ok().map(|info| info.serial);
+        let acm_results = self.acm.save_certificate(domain_names.clone(), components).await?;
+        let mut results = Vec::with_capacity(acm_results.len());
+
+        for acm_result in acm_results {
+            let certificate_arn = match acm_result {
+                CertificateStorageResult::Acm(acm_result) => acm_result.certificate_arn,
+                other => {
+                    results.push(other);
+                    continue;
+                }
+            };
+
+            let mut listeners = Vec::with_capacity(self.listener_arns.len());
+            for listener_arn in &self.listener_arns {
+                match self.update_listener(listener_arn, &certificate_arn, &domain_names).await {
+                    Ok(listener_result) => listeners.push(listener_result),
+                    Err(e) => results.push(CertificateStorageResult::Error(format!(
+                        "Failed to update listener {}: {:#}",
+                        listener_arn, e
+                    ))),
+                }
+            }
+
+            let verification = match (&self.verify, &serial) {
+                (Some(verify), Some(serial)) => Some(verify.verify(&domain_names, serial).await),
+                _ => None,
+            };
+
+            if let (Some(verify), Some(verification)) = (&self.verify, &verification) {
+                if verify.should_roll_back(verification) {
+                    for listener in listeners.iter_mut().filter(|l| l.updated) {
+                        match self.rollback_listener(listener, &certificate_arn).await {
+                            Ok(()) => listener.rolled_back = true,
+                            Err(e) => results.push(CertificateStorageResult::Error(format!(
+                                "Failed to roll back listener {}: {:#}",
+                                listener.listener_arn, e
+                            ))),
+                        }
+                    }
+                }
+
+                if !verification.verified && !listeners.iter().any(|l| l.rolled_back) {
+                    results.push(CertificateStorageResult::Error(format!(
+                        "Load balancer deployment of {} failed verification: {}",
+                        certificate_arn,
+                        verification.failures.join("; ")
+                    )));
+                }
+            }
+
+            results.push(CertificateStorageResult::LoadBalancer(LoadBalancerStorageResult {
+                certificate_arn,
+                listeners,
+                verification,
+            }));
+        }
+
+        Ok(results)
+    }
+
+    /// Observe the certificate in ACM. If any listener is not using it, this reports no certificate so that the
+    /// target is rewritten.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let observed = match self.acm.observe(domain_names).await? {
+            Some(observed) => observed,
+            None => return Ok(None),
+        };
+
+        for listener_arn in &self.listener_arns {
+            let attached = self.get_listener_certificates(listener_arn).await?.into_iter().any(|cert| {
+                cert.certificate_arn.as_deref() == Some(observed.location.as_str())
+                    && (!self.default || cert.is_default == Some(true))
+            });
+
+            if !attached {
+                info!("Listener {} is not using {}", listener_arn, observed.location);
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(observed))
+    }
+}
+
+/// How a storage backend that writes each component separately (S3 and SsmParameter) writes them. In JSON, one of:
+///
+///     // Overwrite each component in place. If one write fails after another has succeeded, consumers can read a
+///     // certificate that doesn't match its private key until the next successful renewal.
+///     "Direct"
+///
+///     // Write every component under a staging name (the final name with ".staging" appended) first, so that a
+///     // permission, size, or encryption problem is caught before anything is overwritten. The components are then
+///     // overwritten in place; if any of those writes fails, the previous values and their tags are restored (or
+///     // the new ones deleted, if there were none). The staged copies are deleted afterwards. This needs permission
+///     // to delete objects or parameters, and for S3, to read and write object tags.
+///     "Transactional"
+///
+/// The default is "Direct".
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum WriteStrategy {
+    #[default]
+    Direct,
+    Transactional,
+}
+
+impl WriteStrategy {
+    pub fn is_direct(&self) -> bool {
+        *self == WriteStrategy::Direct
+    }
+}
+
+/// Configuration for storing a certificate in Amazon S3. In JSON:
+///
+///     {
+///         // The type of storage to use. This must be "S3".
+///         "Type": "S3",
+///
+///         // The bucket to store the certificate into. This is required.
+///         "Bucket": str,
+///
+///         // The prefix to use for the certificate keys. Note that a "/" is not automatically
+///         // appended. The deprecated name "Path" is still accepted; see MigrateConfigRequest.
+///         "Prefix": str,
+///
+///         // The template for object keys. "{Prefix}" is replaced with Prefix, "{Domain}" with the first domain
+///         // name of the certificate, and "{FileName}" with the object's file name (see below). {FileName} is
+///         // required. The default is "{Prefix}{FileName}".
+///         "KeyTemplate": str,
+///
+///         // Templates for individual objects, overriding KeyTemplate, e.g.
+///         // {"FullChain": "{Prefix}{Domain}/fullchain.pem"}. The objects are "Certificate", "Chain",
+///         // "FullChain", "PrivateKey", "AlternateChain", "AlternateFullChain", "Bundle", "DerCertificate",
+///         // "DerPrivateKey", "Pkcs12", and "Jks". Every object must have a distinct key.
+///         "Keys": {str: str, ...},
+///
+///         // If true, also write a combined PEM bundle of the full chain followed by the private key (e.g. for
+///         // HAProxy). This requires the Pem output format and AllowPrivateKey. The default is false.
+///         "Bundle": bool,
+///
+///         // Tags to apply to every object, e.g. for lifecycle rules.
+///         "Tags": {str: str, ...},
+///
+///         // If true, also tag every object with its certificate's domain name, expiration, serial number, and
+///         // issuer (see below). S3 allows at most 10 tags per object, including these 4. The default is false.
+///         "TagCertificateInfo": bool,
+///
+///         // Keep previous versions of each object, and prune older ones after each write; see S3Retention. By
+///         // default, only the current version is managed.
+///         "Retention": {"Mode": "Versioning" | "TimestampedKeys", "KeepPrevious": int},
+///
+///         // "Direct" or "Transactional"; see WriteStrategy. The default is "Direct".
+///         "WriteStrategy": str,
+///
+///         // The encryption type to use for the certificate components. This must be either "AES256" or "aws:kms". This defaults to "AES256".
+///         "ComponentEncryptionType": str,
+///
+///         // If ComponentEncryptionType is "aws:kms", this is the KMS key ARN to use for encryption. If
+///         // not specified, the default "aws/s3" key is used.
+///         "ComponentKmsKey": str,
+///
+///         // The encryption type to use for the private key. This must be either "AES256" or "aws:kms". This defaults to "AES256".
+///         "PrivateKeyEncryptionType": str,
+///
+///         // If PrivateKeyEncryptionType is "aws:kms", this is the KMS key ARN to use for encryption. If
+///         // not specified, the default "aws/s3" key is used.
+///         "PrivateKeyKmsKey": str,
+///
+///         // If false, the private key is never written to this bucket; only the certificate and chains are
+///         // stored (e.g. for a public distribution bucket). The default is true.
+///         "AllowPrivateKey": bool,
+///
+///         // If true, store the private key even if the bucket could be public. By default, the bucket's public
+///         // access block, policy status, and ACL are checked during validation, and the request is rejected if
+///         // the bucket's policy or ACL grants public access that the public access block doesn't override, or
+///         // if the checks can't be made. The default is false.
+///         "AllowPublicBucket": bool,
+///
+///         // Write the certificate to a bucket in each of these regions in parallel. Bucket must contain
+///         // "{Region}", which is replaced with each region name (e.g. "certs-{Region}"); each bucket must be
+///         // in the region it's named for.
+///         "Regions": [str, ...],
+///
+///         // The formats to write ("Pem", "Der", "Pkcs12", and/or "Jks"), the keystore password, and whether to
+///         // use legacy PKCS#12 encryption; see ArtifactFormats. The default is ["Pem"].
+///         "OutputFormats": [str, ...],
+///         "KeystorePassword": str,
+///         "Pkcs12Legacy": bool,
+///
+///         // How to detect another writer of these objects; see WriterCheck.
+///         "WriterId": str,
+///         "OnWriterConflict": str,
+///     }
+///
+/// The file names of the PEM components are "cert.pem", "chain.pem", "fullchain.pem", and "privkey.pem". If the
+/// request stores the alternate chain, its file names are "chain-alternate.pem" and "fullchain-alternate.pem". The
+/// bundle's file name is "bundle.pem". The DER certificate and private key are "cert.der" and "privkey.der", the
+/// PKCS#12 bundle is "certificate.pfx", and the Java KeyStore is "keystore.jks". Objects holding the private key are
+/// encrypted like it.
+///
+/// Each object's "certificate-writer" metadata records the writer fingerprint. Its "certificate-domain-names"
+/// (space-separated, up to 1024 characters), "certificate-not-after" (e.g. "2024-01-31T12:00:00Z"),
+/// "certificate-serial", and "certificate-issuer" (the issuer's common name) metadata describe the certificate, for
+/// inventory reports. With TagCertificateInfo, the same values are also written as the tags "CertificateDomainName"
+/// (the first domain name only), "CertificateNotAfter", "CertificateSerial", and "CertificateIssuer", with
+/// characters that S3 doesn't allow in tags (such as the "*" of a wildcard) replaced by "_". Writing tags requires
+/// the s3:PutObjectTagging permission.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct S3Storage {
+    #[serde(rename = "Bucket")]
+    pub bucket: String,
+
+    #[serde(rename = "Prefix", alias = "Path", default = "empty_string")]
+    pub prefix: String,
+
+    #[serde(rename = "KeyTemplate", default, skip_serializing_if = "Option::is_none")]
+    pub key_template: Option<String>,
+
+    #[serde(rename = "Keys", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<S3Object, String>,
+
+    #[serde(rename = "Bundle", default = "default_false")]
+    pub bundle: bool,
+
+    #[serde(rename = "Tags", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+
+    #[serde(rename = "TagCertificateInfo", default = "default_false")]
+    pub tag_certificate_info: bool,
+
+    #[serde(rename = "Retention", default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<S3Retention>,
+
+    #[serde(rename = "WriteStrategy", default, skip_serializing_if = "WriteStrategy::is_direct")]
+    pub write_strategy: WriteStrategy,
+
+    #[serde(rename = "ComponentEncryptionType", default = "default_aes256")]
+    pub component_encryption_type: String,
+
+    #[serde(rename = "ComponentKmsKey", default)]
+    pub component_kms_key: Option<String>,
+
+    #[serde(rename = "PrivateKeyEncryptionType", default = "default_aes256")]
+    pub pkey_encryption_type: String,
+
+    #[serde(rename = "PrivateKeyKmsKey", default)]
+    pub pkey_kms_key: Option<String>,
+
+    #[serde(rename = "AllowPrivateKey", default = "default_true")]
+    pub allow_private_key: bool,
+
+    #[serde(rename = "AllowPublicBucket", default)]
+    pub allow_public_bucket: bool,
+
+    #[serde(rename = "Regions", default)]
+    pub regions: Vec<String>,
+
+    #[serde(flatten)]
+    pub artifacts: ArtifactFormats,
+
+    /// Why the bucket could be public, if AllowPublicBucket was needed to store the private key there. This is
+    /// filled in during validation.
+    #[serde(skip)]
+    pub public_access_findings: Vec<String>,
+
+    /// The bucket's region. This is looked up during validation; for replicas, it's set beforehand to the region
+    /// the bucket is expected to be in.
+    #[serde(skip)]
+    pub region: Option<Region>,
+
+    #[serde(flatten)]
+    pub writer_check: WriterCheck,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+/// How S3Storage keeps previous versions of the objects it writes. In JSON:
+///
+///     {
+///         // "Versioning" relies on bucket versioning, which must be enabled on the bucket; the version ID of each
+///         // object written is recorded in the result. "TimestampedKeys" also writes each object to a key
+///         // suffixed with the certificate's issue time, e.g. "cert.pem.20240131T120000Z".
+///         "Mode": str,
+///
+///         // The number of previous versions of each object to keep. Older versions (or timestamped copies) are
+///         // deleted after each write. Zero keeps only the current version.
+///         "KeepPrevious": int,
+///     }
+///
+/// Pruning happens after the certificate has been stored, so a failure to prune is reported alongside the stored
+/// certificate rather than failing the write.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct S3Retention {
+    #[serde(rename = "Mode")]
+    pub mode: S3RetentionMode,
+
+    #[serde(rename = "KeepPrevious")]
+    pub keep_previous: u32,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum S3RetentionMode {
+    Versioning,
+    TimestampedKeys,
+}
+
+/// What S3Storage did to retain and prune previous versions, and the objects it failed to prune.
+#[derive(Debug, Default)]
+struct S3RetentionOutcome {
+    version_ids: BTreeMap<String, String>,
+    archived_keys: Vec<String>,
+    pruned_versions: u32,
+    errors: Vec<String>,
+}
+
+/// The tags S3Storage applies with TagCertificateInfo.
+const S3_CERTIFICATE_TAGS: [&str; 4] =
+    ["CertificateDomainName", "CertificateNotAfter", "CertificateSerial", "CertificateIssuer"];
+
+/// The metadata and tagging (in URL query format) written with each object by S3Storage, the suffix of the
+/// timestamped copy to write alongside it, if any, and whether to write the object's staged copy instead.
+#[derive(Clone, Debug)]
+struct S3ObjectAttributes {
+    metadata: HashMap<String, String>,
+    tagging: Option<String>,
+    archive_suffix: Option<String>,
+    staging: bool,
+}
+
+impl S3ObjectAttributes {
+    /// Returns the attributes for staging an object: the same metadata and tags, without a timestamped copy.
+    fn staged(&self) -> Self {
+        S3ObjectAttributes {
+            archive_suffix: None,
+            staging: true,
+            ..self.clone()
+        }
+    }
+
+    /// Returns the key to write an object to: its staged key when staging, otherwise the key itself.
+    fn object_key(&self, key: &str) -> String {
+        if self.staging {
+            staged_name(key)
+        } else {
+            key.to_string()
+        }
+    }
+}
+
+/// An object as it was before a transactional write, so it can be put back if the write fails.
+#[derive(Debug)]
+struct S3ObjectSnapshot {
+    body: Vec<u8>,
+    metadata: HashMap<String, String>,
+    tags: Vec<(String, String)>,
+}
+
+impl S3ObjectSnapshot {
+    /// Returns the attributes to restore the object with: its previous metadata and tags.
+    fn restore_attributes(&self) -> S3ObjectAttributes {
+        S3ObjectAttributes {
+            metadata: self.metadata.clone(),
+            tagging: s3_tagging(self.tags.clone()),
+            archive_suffix: None,
+            staging: false,
+        }
+    }
+}
+
+/// The keys of the objects written by S3Storage.
+#[derive(Debug)]
+struct S3WrittenObjects {
+    certificate: Option<String>,
+    chain: Option<String>,
+    fullchain: Option<String>,
+    pkey: Option<String>,
+    bundle: Option<String>,
+    artifacts: Vec<(String, bool)>,
+    alternate_chain: Option<String>,
+    alternate_fullchain: Option<String>,
+}
+
+/// Replace characters that S3 doesn't allow in tags with "_", and truncate the result to the maximum tag value
+/// length.
+fn s3_tag_value(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == ' ' || "+-=._:/@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(S3_MAX_TAG_VALUE_LEN)
+        .collect()
+}
+
+/// Encode tags in the URL query format PutObject takes, or None if there are none.
+fn s3_tagging(tags: Vec<(String, String)>) -> Option<String> {
+    if tags.is_empty() {
+        None
+    } else {
+        Some(form_urlencoded::Serializer::new(String::new()).extend_pairs(tags).finish())
+    }
+}
+
+/// Indicates whether a key suffix is a timestamp written by TimestampedKeys retention, e.g. "20240131T120000Z".
+fn is_archive_timestamp(suffix: &str) -> bool {
+    suffix.len() == 16
+        && suffix.char_indices().all(|(i, c)| match i {
+            8 => c == 'T',
+            15 => c == 'Z',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// Returns the versions no longer retained: all but the newest KeepPrevious + 1 (the current version and the previous
+/// ones kept), ordered by the given key.
+fn expired_versions<T, K: Ord, F: Fn(&T) -> K>(mut versions: Vec<T>, keep_previous: u32, sort_key: F) -> Vec<T> {
+    versions.sort_by_key(|version| Reverse(sort_key(version)));
+    versions.into_iter().skip(keep_previous as usize + 1).collect()
+}
+
+/// An object written by S3Storage, for naming it in Keys.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, Ord, PartialEq, PartialOrd, Serialize)]
+pub enum S3Object {
+    Certificate,
+    Chain,
+    FullChain,
+    PrivateKey,
+    AlternateChain,
+    AlternateFullChain,
+    Bundle,
+    DerCertificate,
+    DerPrivateKey,
+    Pkcs12,
+    Jks,
+}
+
+impl S3Object {
+    const ALL: [Self; 11] = [
+        Self::Certificate,
+        Self::Chain,
+        Self::FullChain,
+        Self::PrivateKey,
+        Self::AlternateChain,
+        Self::AlternateFullChain,
+        Self::Bundle,
+        Self::DerCertificate,
+        Self::DerPrivateKey,
+        Self::Pkcs12,
+        Self::Jks,
+    ];
+
+    /// The value of {FileName} in KeyTemplate.
+    fn file_name(self) -> &'static str {
+        match self {
+            Self::Certificate => "cert.pem",
+            Self::Chain => "chain.pem",
+            Self::FullChain => "fullchain.pem",
+            Self::PrivateKey => "privkey.pem",
+            Self::AlternateChain => "chain-alternate.pem",
+            Self::AlternateFullChain => "fullchain-alternate.pem",
+            Self::Bundle => "bundle.pem",
+            Self::DerCertificate => ArtifactKind::DerCertificate.default_name(),
+            Self::DerPrivateKey => ArtifactKind::DerPrivateKey.default_name(),
+            Self::Pkcs12 => ArtifactKind::Pkcs12.default_name(),
+            Self::Jks => ArtifactKind::Jks.default_name(),
+        }
+    }
+}
+
+impl From<ArtifactKind> for S3Object {
+    fn from(kind: ArtifactKind) -> Self {
+        match kind {
+            ArtifactKind::DerCertificate => Self::DerCertificate,
+            ArtifactKind::DerPrivateKey => Self::DerPrivateKey,
+            ArtifactKind::Pkcs12 => Self::Pkcs12,
+            ArtifactKind::Jks => Self::Jks,
+        }
+    }
+}
+
+impl S3Storage {
+    /// The key of an object for the given domain name.
+    fn key(&self, object: S3Object, domain_name: &str) -> String {
+        let template = match self.keys.get(&object) {
+            Some(template) => template.as_str(),
+            None => self.key_template.as_deref().unwrap_or(S3_DEFAULT_KEY_TEMPLATE),
+        };
+
+        template
+            .replace("{Prefix}", &self.prefix)
+            .replace("{Domain}", &domain_name_for_path(domain_name))
+            .replace("{FileName}", object.file_name())
+    }
+
+    /// The key of the object whose writer is checked and whose presence means a certificate is stored: the
+    /// certificate PEM, or the primary artifact if PEM components aren't written.
+    fn primary_key(&self, domain_name: &str) -> String {
+        match self.artifacts.primary() {
+            None => self.key(S3Object::Certificate, domain_name),
+            Some(kind) => self.key(kind.into(), domain_name),
+        }
+    }
+
+    fn validate_keys(&self) -> Result<(), LambdaError> {
+        let key_template = self.key_template.as_deref().unwrap_or(S3_DEFAULT_KEY_TEMPLATE);
+        if !key_template.contains("{FileName}") {
+            return Err(InvalidCertificateRequest::invalid_s3_key_template(format!(
+                "KeyTemplate must contain {{FileName}}: {}",
+                key_template
+            )));
+        }
+
+        let mut keys: Vec<String> = S3Object::ALL.iter().map(|object| self.key(*object, "example.com")).collect();
+        if keys.iter().any(|key| key.is_empty()) {
+            return Err(InvalidCertificateRequest::invalid_s3_key_template("Object keys cannot be empty"));
+        }
+
+        keys.sort_unstable();
+        keys.dedup();
+        if keys.len() != S3Object::ALL.len() {
+            return Err(InvalidCertificateRequest::invalid_s3_key_template("Every object must have a distinct key"));
+        }
+
+        if self.bundle && (!self.allow_private_key || !self.artifacts.writes_pem()) {
+            return Err(InvalidCertificateRequest::invalid_s3_key_template(
+                "Bundle requires the Pem output format and AllowPrivateKey",
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn validate_tags(&self) -> Result<(), LambdaError> {
+        let certificate_tags = if self.tag_certificate_info {
+            S3_CERTIFICATE_TAGS.len()
+        } else {
+            0
+        };
+
+        if self.tags.len() + certificate_tags > S3_MAX_OBJECT_TAGS {
+            return Err(InvalidCertificateRequest::invalid_s3_tags(format!(
+                "At most {} tags can be applied to an object, including the {} added by TagCertificateInfo",
+                S3_MAX_OBJECT_TAGS,
+                S3_CERTIFICATE_TAGS.len()
+            )));
+        }
+
+        for (key, value) in &self.tags {
+            if key.is_empty() || key.len() > S3_MAX_TAG_KEY_LEN || s3_tag_value(key) != *key {
+                return Err(InvalidCertificateRequest::invalid_s3_tags(format!("Invalid tag key: {:?}", key)));
+            }
+
+            if key.starts_with("aws:") {
+                return Err(InvalidCertificateRequest::invalid_s3_tags(format!(
+                    "Tag keys cannot start with \"aws:\": {}",
+                    key
+                )));
+            }
+
+            if self.tag_certificate_info && S3_CERTIFICATE_TAGS.contains(&key.as_str()) {
+                return Err(InvalidCertificateRequest::invalid_s3_tags(format!(
+                    "Tag {} is set by TagCertificateInfo",
+                    key
+                )));
+            }
+
+            if value.len() > S3_MAX_TAG_VALUE_LEN || s3_tag_value(value) != *value {
+                return Err(InvalidCertificateRequest::invalid_s3_tags(format!(
+                    "Invalid value for tag {}: {:?}",
+                    key, value
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the metadata and tags to write with each object: the writer fingerprint, a description of the
+    /// certificate, and the configured tags.
+    fn object_attributes(
+        &self,
+        domain_names: &[String],
+        components: &CertificateComponents,
+        fingerprint: &str,
+    ) -> Result<S3ObjectAttributes, LambdaError> {
+        let cert = X509::from_pem(components.cert_pem.as_bytes())?;
+        let info = CertificateInfo::from_pem(&components.cert_pem)?;
+        let issuer = common_name(cert.issuer_name()).unwrap_or_default();
+        let not_after = format_utc_timestamp(info.not_after);
+
+        // S3 limits user metadata to 2 KB in all, so only list as many domain names as fit in a share of it.
+        let mut metadata_domain_names = String::new();
+        for domain_name in domain_names {
+            if metadata_domain_names.len() + domain_name.len() + 1 > S3_MAX_METADATA_DOMAIN_NAMES_LEN {
+                break;
+            }
+
+            if !metadata_domain_names.is_empty() {
+                metadata_domain_names.push(' ');
+            }
+            metadata_domain_names.push_str(domain_name);
+        }
+
+        let mut metadata = writer_metadata(fingerprint);
+        metadata.insert("certificate-domain-names".to_string(), metadata_domain_names);
+        metadata.insert("certificate-not-after".to_string(), not_after.clone());
+        metadata.insert("certificate-serial".to_string(), info.serial.clone());
+        // Metadata is sent as HTTP headers, so it must be ASCII.
+        metadata.insert(
+            "certificate-issuer".to_string(),
+            issuer
+                .chars()
+                .map(|c| {
+                    if c.is_ascii() && !c.is_ascii_control() {
+                        c
+                    } else {
+                        '_'
+                    }
+                })
+                .collect(),
+        );
+
+        let mut tags: Vec<(String, String)> = self.tags.clone().into_iter().collect();
+        if self.tag_certificate_info {
+            let values = [&domain_names[0], &not_after, &info.serial, &issuer];
+            for (key, value) in S3_CERTIFICATE_TAGS.iter().zip(values.iter()) {
+                tags.push((key.to_string(), s3_tag_value(value)));
+            }
+        }
+
+        let tagging = s3_tagging(tags);
+
+        let archive_suffix = match &self.retention {
+            Some(retention) if retention.mode == S3RetentionMode::TimestampedKeys => {
+                Some(format!(".{}", format_utc_timestamp(info.not_before).replace(&['-', ':'][..], "")))
+            }
+            _ => None,
+        };
+
+        Ok(S3ObjectAttributes {
+            metadata,
+            tagging,
+            archive_suffix,
+            staging: false,
+        })
+    }
+
+    /// Check that the bucket supports the configured retention mode.
+    async fn validate_retention(&self, s3_client: &S3Client) -> Result<(), LambdaError> {
+        match &self.retention {
+            Some(retention) if retention.mode == S3RetentionMode::Versioning => {
+                let gbv_request = GetBucketVersioningRequest {
+                    bucket: self.bucket.clone(),
+                    ..Default::default()
+                };
+
+                match s3_client.get_bucket_versioning(gbv_request).await {
+                    Ok(response) if response.status.as_deref() == Some("Enabled") => Ok(()),
+                    Ok(_) => Err(InvalidCertificateRequest::invalid_s3_retention(format!(
+                        "Versioning retention requires versioning to be enabled on S3 bucket {}",
+                        self.bucket
+                    ))),
+                    Err(e) => {
+                        error!("Failed to get versioning status of S3 bucket {}: {}", self.bucket, e);
+                        Err(InvalidCertificateRequest::invalid_s3_retention(format!(
+                            "Unable to get versioning status of S3 bucket {}: {}",
+                            self.bucket, e
+                        )))
+                    }
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Record the versions just written and prune the ones no longer retained.
+    async fn retain_versions(
+        &self,
+        s3_client: &S3Client,
+        keys: &[String],
+        attributes: &S3ObjectAttributes,
+    ) -> S3RetentionOutcome {
+        let mut outcome = S3RetentionOutcome::default();
+        let retention = match &self.retention {
+            Some(retention) => retention,
+            None => return outcome,
+        };
+
+        for key in keys {
+            let result = match retention.mode {
+                S3RetentionMode::Versioning => {
+                    self.prune_object_versions(s3_client, key, retention, &mut outcome).await
+                }
+                S3RetentionMode::TimestampedKeys => {
+                    let suffix = attributes.archive_suffix.as_deref().expect("TimestampedKeys sets an archive suffix");
+                    outcome.archived_keys.push(format!("{}{}", key, suffix));
+                    self.prune_timestamped_keys(s3_client, key, retention, &mut outcome).await
+                }
+            };
+
+            if let Err(e) = result {
+                error!("Failed to prune previous versions of s3://{}/{}: {:#}", self.bucket, key, e);
+                outcome
+                    .errors
+                    .push(format!("Failed to prune previous versions of s3://{}/{}: {:#}", self.bucket, key, e));
+            }
+        }
+
+        outcome
+    }
+
+    /// Record the current version ID of an object and delete all but the newest KeepPrevious noncurrent versions.
+    async fn prune_object_versions(
+        &self,
+        s3_client: &S3Client,
+        key: &str,
+        retention: &S3Retention,
+        outcome: &mut S3RetentionOutcome,
+    ) -> Result<(), LambdaError> {
+        let mut versions = Vec::new();
+        let mut key_marker = None;
+        let mut version_id_marker = None;
+
+        loop {
+            let lov_request = ListObjectVersionsRequest {
+                bucket: self.bucket.clone(),
+                prefix: Some(key.to_string()),
+                key_marker: key_marker.clone(),
+                version_id_marker: version_id_marker.clone(),
+                ..Default::default()
+            };
+            let response =
+                throttle::call("ListObjectVersions", || s3_client.list_object_versions(lov_request.clone())).await?;
+
+            for version in response.versions.unwrap_or_default() {
+                if version.key.as_deref() == Some(key) {
+                    if let Some(version_id) = version.version_id {
+                        versions.push((version.last_modified.unwrap_or_default(), version_id, version.is_latest));
+                    }
+                }
+            }
+
+            if response.is_truncated != Some(true) {
+                break;
+            }
+
+            key_marker = response.next_key_marker;
+            version_id_marker = response.next_version_id_marker;
+        }
+
+        if let Some((_, version_id, _)) = versions.iter().find(|(_, _, is_latest)| *is_latest == Some(true)) {
+            outcome.version_ids.insert(key.to_string(), version_id.clone());
+        }
+
+        // Timestamps are ISO 8601, so they sort chronologically.
+        for (_, version_id, _) in expired_versions(versions, retention.keep_previous, |version| version.0.clone()) {
+            self.delete_object(s3_client, key, Some(version_id)).await?;
+            outcome.pruned_versions += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Delete all but the newest KeepPrevious + 1 timestamped copies of an object.
+    async fn prune_timestamped_keys(
+        &self,
+        s3_client: &S3Client,
+        key: &str,
+        retention: &S3Retention,
+        outcome: &mut S3RetentionOutcome,
+    ) -> Result<(), LambdaError> {
+        let prefix = format!("{}.", key);
+        let mut archived = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let lo_request = ListObjectsV2Request {
+                bucket: self.bucket.clone(),
+                prefix: Some(prefix.clone()),
+                continuation_token: continuation_token.clone(),
+                ..Default::default()
+            };
+            let response = throttle::call("ListObjectsV2", || s3_client.list_objects_v2(lo_request.clone())).await?;
+
+            for object in response.contents.unwrap_or_default() {
+                if let Some(object_key) = object.key {
+                    if is_archive_timestamp(&object_key[prefix.len()..]) {
+                        archived.push(object_key);
+                    }
+                }
+            }
+
+            continuation_token = response.next_continuation_token;
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+
+        // The timestamp suffixes sort chronologically.
+        for object_key in expired_versions(archived, retention.keep_previous, |object_key| object_key.clone()) {
+            self.delete_object(s3_client, &object_key, None).await?;
+            outcome.pruned_versions += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn delete_object(
+        &self,
+        s3_client: &S3Client,
+        key: &str,
+        version_id: Option<String>,
+    ) -> Result<(), LambdaError> {
+        info!("Deleting s3://{}/{} (version {})", self.bucket, key, version_id.as_deref().unwrap_or("null"));
+        let do_request = DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            version_id,
+            ..Default::default()
+        };
+        throttle::call("DeleteObject", || s3_client.delete_object(do_request.clone())).await?;
+        Ok(())
+    }
+
+    /// Check whether the bucket could be public, returning the reasons it could be. The public access block is
+    /// taken into account: a public policy doesn't count if RestrictPublicBuckets is set, nor a public ACL if
+    /// IgnorePublicAcls is set. A check that fails (e.g. for lack of permission) is itself a reason, since the
+    /// bucket can't be shown to be private.
+    async fn public_access_findings(&self, s3_client: &S3Client) -> Vec<String> {
+        let gpab_request = GetPublicAccessBlockRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        };
+        let gbps_request = GetBucketPolicyStatusRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        };
+        let gba_request = GetBucketAclRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        };
+
+        let (block, policy_status, acl) = tokio::join!(
+            s3_client.get_public_access_block(gpab_request),
+            s3_client.get_bucket_policy_status(gbps_request),
+            s3_client.get_bucket_acl(gba_request),
+        );
+
+        let mut findings = Vec::new();
+        let (ignore_public_acls, restrict_public_buckets) = match block {
+            Ok(response) => match response.public_access_block_configuration {
+                Some(config) => (config.ignore_public_acls == Some(true), config.restrict_public_buckets == Some(true)),
+                None => (false, false),
+            },
+            // NoSuchPublicAccessBlockConfiguration: nothing is blocked.
+            Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => (false, false),
+            Err(e) => {
+                findings.push(format!("could not read the public access block: {}", e));
+                (false, false)
+            }
+        };
+
+        if !restrict_public_buckets {
+            match policy_status {
+                Ok(response) => {
+                    if response.policy_status.and_then(|status| status.is_public) == Some(true) {
+                        findings.push("the bucket policy grants public access".to_string());
+                    }
+                }
+                // NoSuchBucketPolicy: there's no policy to grant access.
+                Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => (),
+                Err(e) => findings.push(format!("could not read the bucket policy status: {}", e)),
+            }
+        }
+
+        if !ignore_public_acls {
+            match acl {
+                Ok(response) => {
+                    for grant in response.grants.unwrap_or_default() {
+                        let uri = grant.grantee.and_then(|grantee| grantee.uri);
+                        if let Some(uri) = uri.filter(|uri| S3_PUBLIC_GRANTEE_URIS.contains(&uri.as_str())) {
+                            findings.push(format!(
+                                "the bucket ACL grants {} to {}",
+                                grant.permission.unwrap_or_default(),
+                                uri
+                            ));
+                        }
+                    }
+                }
+                Err(e) => findings.push(format!("could not read the bucket ACL: {}", e)),
+            }
+        }
+
+        findings
+    }
+
+    /// Find out who last wrote the certificate object.
+    async fn previous_writer(&self, s3_client: &S3Client, cert_key: &str) -> Result<PreviousWriter, LambdaError> {
+        let ho_request = HeadObjectRequest {
+            bucket: self.bucket.clone(),
+            key: cert_key.to_string(),
+            ..Default::default()
+        };
+
+        match s3_client.head_object(ho_request).await {
+            Ok(response) => {
+                Ok(match response.metadata.and_then(|metadata| metadata.get(WRITER_METADATA_KEY).cloned()) {
+                    Some(writer) => PreviousWriter::Writer(writer),
+                    None => PreviousWriter::Unknown,
+                })
+            }
+            Err(RusotoError::Service(HeadObjectError::NoSuchKey(_))) => Ok(PreviousWriter::Nothing),
+            // HEAD responses have no body to carry an error code, so a missing object is usually a bare 404.
+            Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => Ok(PreviousWriter::Nothing),
+            Err(e) => {
+                error!("Failed to read metadata of s3://{}/{}: {}", self.bucket, cert_key, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Write an object with the given metadata and tags, along with its timestamped copy if TimestampedKeys retention
+    /// is used, or only its staged copy if the attributes are for staging. The request is rebuilt for each attempt,
+    /// since the body can only be sent once.
+    async fn put_object(
+        &self,
+        s3_client: &S3Client,
+        key: &str,
+        body: &[u8],
+        private_key: bool,
+        attributes: &S3ObjectAttributes,
+    ) -> Result<(), RusotoError<PutObjectError>> {
+        let (encryption_type, kms_key) = if private_key {
+            (&self.pkey_encryption_type, &self.pkey_kms_key)
+        } else {
+            (&self.component_encryption_type, &self.component_kms_key)
+        };
+
+        let key = attributes.object_key(key);
+
+        if let Some(suffix) = &attributes.archive_suffix {
+            let archive_key = format!("{}{}", key, suffix);
+            throttle::call("PutObject", || {
+                s3_client.put_object(PutObjectRequest {
+                    bucket: self.bucket.clone(),
+                    key: archive_key.clone(),
+                    server_side_encryption: Some(encryption_type.clone()),
+                    ssekms_key_id: kms_key.clone(),
+                    body: Some(StreamingBody::from(body.to_vec())),
+                    metadata: Some(attributes.metadata.clone()),
+                    tagging: attributes.tagging.clone(),
+                    ..Default::default()
+                })
+            })
+            .await?;
+        }
+
+        throttle::call("PutObject", || {
+            s3_client.put_object(PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                server_side_encryption: Some(encryption_type.clone()),
+                ssekms_key_id: kms_key.clone(),
+                body: Some(StreamingBody::from(body.to_vec())),
+                metadata: Some(attributes.metadata.clone()),
+                tagging: attributes.tagging.clone(),
+                ..Default::default()
+            })
+        })
+        .await
+        .map(|_| ())
+    }
+
+    /// Save the alternate chain alongside the default chain, returning the keys for the chain and fullchain.
+    async fn save_alternate_chain(
+        &self,
+        s3_client: &S3Client,
+        domain_name: &str,
+        alternate: CertificateChain,
+        attributes: &S3ObjectAttributes,
+    ) -> Result<(String, String), LambdaError> {
+        let chain_key = self.key(S3Object::AlternateChain, domain_name);
+        let fullchain_key = self.key(S3Object::AlternateFullChain, domain_name);
+
+        info!("Saving alternate certificate chain to s3://{}/{}", self.bucket, chain_key);
+        info!("Saving alternate certificate fullchain to s3://{}/{}", self.bucket, fullchain_key);
+        let (chain_result, fullchain_result) = tokio::join!(
+            self.put_object(s3_client, &chain_key, alternate.chain_pem.as_bytes(), false, attributes),
+            self.put_object(s3_client, &fullchain_key, alternate.fullchain_pem.as_bytes(), false, attributes),
+        );
+
+        if let Err(e) = chain_result {
+            error!("Failed to save alternate certificate chain: {}", e);
+            Err(Box::new(e))
+        } else if let Err(e) = fullchain_result {
+            error!("Failed to save alternate full certificate chain: {}", e);
+            Err(Box::new(e))
+        } else {
+            Ok((chain_key, fullchain_key))
+        }
+    }
+
+    /// Write a PEM component, unless the Pem output format is disabled.
+    async fn put_pem_object(
+        &self,
+        s3_client: &S3Client,
+        key: &str,
+        body: &str,
+        private_key: bool,
+        attributes: &S3ObjectAttributes,
+    ) -> Result<(), RusotoError<PutObjectError>> {
+        if !self.artifacts.writes_pem() {
+            return Ok(());
+        }
+
+        self.put_object(s3_client, key, body.as_bytes(), private_key, attributes).await
+    }
+
+    /// Write the artifacts for the non-PEM output formats, returning their keys and whether each holds the private
+    /// key.
+    async fn put_artifacts(
+        &self,
+        s3_client: &S3Client,
+        domain_names: &[String],
+        components: &CertificateComponents,
+        attributes: &S3ObjectAttributes,
+    ) -> Result<Vec<(String, bool)>, LambdaError> {
+        let mut keys = Vec::new();
+        for artifact in self.artifacts.build(components, &domain_names[0], self.allow_private_key).await? {
+            let key = self.key(artifact.kind.into(), &domain_names[0]);
+            let private = artifact.kind.is_private();
+            info!("Saving {:?} for {} to s3://{}/{}", artifact.kind, domain_names.join(" "), self.bucket, key);
+            self.put_object(s3_client, &key, &artifact.data, private, attributes).await?;
+            keys.push((key, private));
+        }
+
+        Ok(keys)
+    }
+
+    /// Write the objects for a certificate, returning their keys. With staging attributes, each object is written
+    /// under its staging key instead.
+    async fn write_objects(
+        &self,
+        s3_client: &S3Client,
+        domain_names: &[String],
+        components: &CertificateComponents,
+        attributes: &S3ObjectAttributes,
+    ) -> Result<S3WrittenObjects, LambdaError> {
+        let pem = self.artifacts.writes_pem();
+        let domain_name = &domain_names[0];
+        let cert_key = self.key(S3Object::Certificate, domain_name);
+        let chain_key = self.key(S3Object::Chain, domain_name);
+        let fullchain_key = self.key(S3Object::FullChain, domain_name);
+        let pkey_key = self.key(S3Object::PrivateKey, domain_name);
+        let bundle_key = self.key(S3Object::Bundle, domain_name);
+
+        if pem {
+            info!("Saving certificate for {} to s3://{}/{}", domain_names.join(" "), self.bucket, cert_key);
+            info!("Saving certificate chain for {} to s3://{}/{}", domain_names.join(" "), self.bucket, chain_key);
+            info!(
+                "Saving certificate fullchain for {} to s3://{}/{}",
+                domain_names.join(" "),
+                self.bucket,
+                fullchain_key
+            );
+        }
+
+        let pkey_put = async {
+            if !self.allow_private_key || !pem {
+                return Ok(None);
+            }
+
+            info!("Saving private key for {} to s3://{}/{}", domain_names.join(" "), self.bucket, pkey_key);
+            self.put_object(s3_client, &pkey_key, components.pkey_pem.as_bytes(), true, attributes)
+                .await
+                .map(|_| Some(pkey_key.clone()))
+        };
+
+        let bundle_put = async {
+            if !self.bundle {
+                return Ok(None);
+            }
+
+            info!("Saving certificate bundle for {} to s3://{}/{}", domain_names.join(" "), self.bucket, bundle_key);
+            let bundle = format!("{}{}", components.fullchain_pem, components.pkey_pem);
+            self.put_object(s3_client, &bundle_key, bundle.as_bytes(), true, attributes)
+                .await
+                .map(|_| Some(bundle_key.clone()))
+        };
+
+        let (cert_result, chain_result, fullchain_result, pkey_result, bundle_result, artifacts_result) = tokio::join!(
+            self.put_pem_object(s3_client, &cert_key, &components.cert_pem, false, attributes),
+            self.put_pem_object(s3_client, &chain_key, &components.chain_pem, false, attributes),
+            self.put_pem_object(s3_client, &fullchain_key, &components.fullchain_pem, false, attributes),
+            pkey_put,
+            bundle_put,
+            self.put_artifacts(s3_client, domain_names, components, attributes),
+        );
+
+        if let Err(e) = cert_result {
+            error!("Failed to save certificate: {}", e);
+            Err(Box::new(e))
+        } else if let Err(e) = chain_result {
+            error!("Failed to save certificate chain: {}", e);
+            Err(Box::new(e))
+        } else if let Err(e) = fullchain_result {
+            error!("Failed to save full certificate chain: {}", e);
+            Err(Box::new(e))
+        } else if let Err(e) = pkey_result {
+            error!("Failed to save private key: {}", e);
+            Err(Box::new(e))
+        } else if let Err(e) = bundle_result {
+            error!("Failed to save certificate bundle: {}", e);
+            Err(Box::new(e))
+        } else if let Err(e) = artifacts_result {
+            error!("Failed to save output format artifacts: {}", e);
+            Err(e)
+        } else {
+            let pkey = pkey_result.expect("private key result was checked above");
+            let bundle = bundle_result.expect("bundle result was checked above");
+            let artifacts = artifacts_result.expect("artifacts result was checked above");
+            let (alternate_chain, alternate_fullchain) = match &components.alternate_chain {
+                Some(alternate) if pem => {
+                    let (alt_chain_key, alt_fullchain_key) =
+                        self.save_alternate_chain(s3_client, domain_name, alternate.clone(), attributes).await?;
+                    (Some(alt_chain_key), Some(alt_fullchain_key))
+                }
+                _ => (None, None),
+            };
+
+            let (certificate, chain, fullchain) = if pem {
+                (Some(cert_key), Some(chain_key), Some(fullchain_key))
+            } else {
+                (None, None, None)
+            };
+
+            Ok(S3WrittenObjects {
+                certificate,
+                chain,
+                fullchain,
+                pkey,
+                bundle,
+                artifacts,
+                alternate_chain,
+                alternate_fullchain,
+            })
+        }
+    }
+
+    /// Returns the keys of the objects that will be written for a certificate, and whether each holds the private
+    /// key.
+    fn object_keys(&self, domain_name: &str, alternate_chain: bool) -> Vec<(String, bool)> {
+        let mut keys = Vec::new();
+        if self.artifacts.writes_pem() {
+            keys.push((self.key(S3Object::Certificate, domain_name), false));
+            keys.push((self.key(S3Object::Chain, domain_name), false));
+            keys.push((self.key(S3Object::FullChain, domain_name), false));
+            if self.allow_private_key {
+                keys.push((self.key(S3Object::PrivateKey, domain_name), true));
+            }
+            if alternate_chain {
+                keys.push((self.key(S3Object::AlternateChain, domain_name), false));
+                keys.push((self.key(S3Object::AlternateFullChain, domain_name), false));
+            }
+        }
+
+        if self.bundle {
+            keys.push((self.key(S3Object::Bundle, domain_name), true));
+        }
+
+        for kind in self.artifacts.kinds(self.allow_private_key) {
+            keys.push((self.key(kind.into(), domain_name), kind.is_private()));
+        }
+
+        keys
+    }
+
+    /// Write the objects for a certificate with the Transactional write strategy: stage every object, then
+    /// overwrite the live objects, restoring their previous contents and tags if any write fails. Staged objects are
+    /// deleted afterwards either way.
+    async fn write_objects_transactionally(
+        &self,
+        s3_client: &S3Client,
+        domain_names: &[String],
+        components: &CertificateComponents,
+        attributes: &S3ObjectAttributes,
+    ) -> Result<S3WrittenObjects, LambdaError> {
+        let keys = self.object_keys(&domain_names[0], components.alternate_chain.is_some());
+        let mut previous = Vec::with_capacity(keys.len());
+        for (key, private) in &keys {
+            previous.push((key.clone(), *private, self.get_object_snapshot(s3_client, key).await?));
+        }
+
+        let staging = attributes.staged();
+
+        info!("Staging {} objects in s3://{} for {}", keys.len(), self.bucket, domain_names.join(" "));
+        if let Err(e) = self.write_objects(s3_client, domain_names, components, &staging).await {
+            error!("Failed to stage objects in s3://{}; nothing was overwritten: {}", self.bucket, e);
+            self.delete_staged_objects(s3_client, &keys).await;
+            return Err(e);
+        }
+
+        let result = self.write_objects(s3_client, domain_names, components, attributes).await;
+        if let Err(e) = &result {
+            error!("Failed to write objects in s3://{}; restoring their previous contents: {}", self.bucket, e);
+            for (key, private, snapshot) in previous {
+                let restored = match snapshot {
+                    Some(snapshot) => self
+                        .put_object(s3_client, &key, &snapshot.body, private, &snapshot.restore_attributes())
+                        .await
+                        .map_err(|e| Box::new(e) as LambdaError),
+                    None => self.delete_object(s3_client, &key, None).await,
+                };
+
+                if let Err(e) = restored {
+                    error!("Failed to restore s3://{}/{}: {}", self.bucket, key, e);
+                }
+            }
+        }
+
+        self.delete_staged_objects(s3_client, &keys).await;
+        result
+    }
+
+    /// Read an object's body, metadata, and tags, returning None if it doesn't exist.
+    async fn get_object_snapshot(
+        &self,
+        s3_client: &S3Client,
+        key: &str,
+    ) -> Result<Option<S3ObjectSnapshot>, LambdaError> {
+        let go_request = GetObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            ..Default::default()
+        };
+
+        match throttle::call("GetObject", || s3_client.get_object(go_request.clone())).await {
+            Ok(response) => {
+                let body = match response.body {
+                    Some(body) => body.map_ok(|b| b.to_vec()).try_concat().await?,
+                    None => Vec::new(),
+                };
+
+                let got_request = GetObjectTaggingRequest {
+                    bucket: self.bucket.clone(),
+                    key: key.to_string(),
+                    ..Default::default()
+                };
+                let tags = match throttle::call("GetObjectTagging", || {
+                    s3_client.get_object_tagging(got_request.clone())
+                })
+                .await
+                {
+                    Ok(response) => response.tag_set.into_iter().map(|tag| (tag.key, tag.value)).collect(),
+                    Err(e) => {
+                        error!("Failed to read the tags of s3://{}/{}: {}", self.bucket, key, e);
+                        return Err(Box::new(e));
+                    }
+                };
+
+                Ok(Some(S3ObjectSnapshot {
+                    body,
+                    metadata: response.metadata.unwrap_or_default(),
+                    tags,
+                }))
+            }
+            Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => Ok(None),
+            Err(e) => {
+                error!("Failed to read s3://{}/{}: {}", self.bucket, key, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Delete the staged copies of objects. Failures are only logged, since the live objects are already written.
+    async fn delete_staged_objects(&self, s3_client: &S3Client, keys: &[(String, bool)]) {
+        for (key, _) in keys {
+            let staged_key = staged_name(key);
+            if let Err(e) = self.delete_object(s3_client, &staged_key, None).await {
+                warn!("Failed to delete staged object s3://{}/{}: {}", self.bucket, staged_key, e);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CertificateStore for S3Storage {
+    fn type_name(&self) -> &'static str {
+        "S3"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn allows_private_key(&self) -> bool {
+        self.allow_private_key
+    }
+
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        if self.regions.is_empty() {
+            return Ok(None);
+        }
+
+        if !self.bucket.contains("{Region}") {
+            return Err(InvalidCertificateRequest::invalid_regions(
+                "Bucket must contain {Region} when Regions is specified",
+            ));
+        }
+
+        let regions = replica_regions(&self.regions, None)?;
+        Ok(Some(
+            regions
+                .into_iter()
+                .map(|region| -> Box<dyn CertificateStore> {
+                    Box::new(S3Storage {
+                        bucket: self.bucket.replace("{Region}", &region),
+                        regions: vec![],
+                        region: Some(Region::from_str(&region).expect("Region should be validated here")),
+                        ..self.clone()
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        if self.bucket.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_s3_bucket(self.bucket.clone()));
+        }
+
+        self.artifacts.validate(self.allow_private_key)?;
+        self.validate_keys()?;
+        self.validate_tags()?;
+
+        match self.component_encryption_type.as_ref() {
+            S3_ENCRYPTION_AES | S3_ENCRYPTION_KMS => {}
+            _ => {
+                return Err(InvalidCertificateRequest::invalid_s3_encryption_algorithm(
+                    self.component_encryption_type.clone(),
+                ));
+            }
+        }
+
+        match self.pkey_encryption_type.as_ref() {
+            S3_ENCRYPTION_AES | S3_ENCRYPTION_KMS => {}
+            _ => {
+                return Err(InvalidCertificateRequest::invalid_s3_encryption_algorithm(
+                    self.component_encryption_type.clone(),
+                ));
+            }
+        }
+
+        let s3_client = S3Client::new_with_client(self.assume_role.client()?, Region::default());
+        let gblr = GetBucketLocationRequest {
+            bucket: self.bucket.clone(),
+            ..Default::default()
+        };
+        match s3_client.get_bucket_location(gblr).await {
+            Ok(response) => {
+                let location = s3_bucket_location_constraint_to_region(response.location_constraint)?;
+                if let Some(expected) = &self.region {
+                    if expected.name() != location.name() {
+                        return Err(InvalidCertificateRequest::invalid_regions(format!(
+                            "S3 bucket {} is in {}, not {}",
+                            self.bucket,
+                            location.name(),
+                            expected.name()
+                        )));
+                    }
+                }
+
+                self.region = Some(location);
+            }
+            Err(e) => {
+                error!("Failed to get location for S3 bucket {}: {}", self.bucket, e);
+                return Err(InvalidCertificateRequest::invalid_s3_bucket(self.bucket.clone()));
+            }
+        }
+
+        let s3_client = S3Client::new_with_client(
+            self.assume_role.client()?,
+            self.region.clone().expect("Region should be set here"),
+        );
+        self.validate_retention(&s3_client).await?;
+
+        if self.allow_private_key {
+            let s3_client = S3Client::new_with_client(
+                self.assume_role.client()?,
+                self.region.clone().expect("Region should be set here"),
+            );
+            let findings = self.public_access_findings(&s3_client).await;
+            if !findings.is_empty() {
+                if !self.allow_public_bucket {
+                    return Err(InvalidCertificateRequest::public_s3_bucket(self.bucket.clone(), findings.join("; ")));
+                }
+
+                warn!(
+                    "S3 bucket {} could be public; storing the private key anyway: {}",
+                    self.bucket,
+                    findings.join("; ")
+                );
+                self.public_access_findings = findings;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn planned_writes(&self, domain_names: &[String], alternate_chain: bool) -> Vec<PlannedWrite> {
+        let write = |action: &str, key: String| PlannedWrite {
+            action: action.to_string(),
+            resource: format!("s3://{}/{}", self.bucket, key),
+            region: self.region.as_ref().map(|region| region.name().to_string()),
+        };
+
+        let mut writes = Vec::new();
+        for (key, _) in self.object_keys(&domain_names[0], alternate_chain) {
+            if self.write_strategy == WriteStrategy::Transactional {
+                writes.push(write("s3:PutObject", format!("{}{}", key, STORAGE_STAGING_SUFFIX)));
+            }
+
+            writes.push(write("s3:PutObject", key.clone()));
+
+            match self.retention.as_ref().map(|retention| retention.mode) {
+                Some(S3RetentionMode::TimestampedKeys) => {
+                    writes.push(write("s3:PutObject", format!("{}.<timestamp>", key)));
+                    writes.push(write("s3:DeleteObject", format!("{}.<timestamp> (beyond KeepPrevious)", key)));
+                }
+                Some(S3RetentionMode::Versioning) => {
+                    writes.push(write("s3:DeleteObjectVersion", format!("{} (versions beyond KeepPrevious)", key)));
+                }
+                None => (),
+            }
+
+            if self.write_strategy == WriteStrategy::Transactional {
+                writes.push(write("s3:DeleteObject", format!("{}{}", key, STORAGE_STAGING_SUFFIX)));
+            }
+        }
+
+        writes
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let s3_client = S3Client::new_with_client(
+            self.assume_role.client()?,
+            self.region.clone().expect("Region should be set here"),
+        );
+        let domain_name = &domain_names[0];
+
+        let fingerprint = self.writer_check.fingerprint(self)?;
+        let primary_key = self.primary_key(domain_name);
+        let previous = self.previous_writer(&s3_client, &primary_key).await?;
+        self.writer_check.check(&format!("s3://{}/{}", self.bucket, primary_key), &previous, &fingerprint)?;
+        let attributes = self.object_attributes(&domain_names, &components, &fingerprint)?;
+
+        let written = match self.write_strategy {
+            WriteStrategy::Direct => self.write_objects(&s3_client, &domain_names, &components, &attributes).await?,
+            WriteStrategy::Transactional => {
+                self.write_objects_transactionally(&s3_client, &domain_names, &components, &attributes).await?
+            }
+        };
+        let S3WrittenObjects {
+            certificate,
+            chain,
+            fullchain,
+            pkey,
+            bundle,
+            artifacts,
+            alternate_chain,
+            alternate_fullchain,
+        } = written;
+
+        let private_artifacts = artifacts.iter().filter(|(_, private)| *private).count();
+        let component_objects = [&certificate, &chain, &fullchain, &alternate_chain, &alternate_fullchain]
+            .iter()
+            .filter(|key| key.is_some())
+            .count()
+            + artifacts.len()
+            - private_artifacts;
+        let private_objects = pkey.iter().count() + bundle.iter().count() + private_artifacts;
+        // Timestamped copies are encrypted like the objects they copy.
+        let copies = if attributes.archive_suffix.is_some() {
+            2
+        } else {
+            1
+        };
+        let count = |objects: usize, encryption_type: &str| {
+            if encryption_type == S3_ENCRYPTION_KMS {
+                (objects * copies) as u32
+            } else {
+                0
+            }
+        };
+        let kms_encrypted_objects = count(component_objects, &self.component_encryption_type)
+            + count(private_objects, &self.pkey_encryption_type);
+
+        let artifacts: Vec<String> = artifacts.into_iter().map(|(key, _)| key).collect();
+        let written: Vec<String> =
+            [&certificate, &chain, &fullchain, &pkey, &bundle, &alternate_chain, &alternate_fullchain]
+                .iter()
+                .filter_map(|key| (*key).clone())
+                .chain(artifacts.iter().cloned())
+                .collect();
+        let retention = self.retain_versions(&s3_client, &written, &attributes).await;
+
+        let s3sr = S3StorageResult {
+            bucket: self.bucket.clone(),
+            certificate,
+            chain,
+            fullchain,
+            pkey,
+            bundle,
+            artifacts,
+            alternate_chain,
+            alternate_fullchain,
+            public_access_findings: self.public_access_findings.clone(),
+            kms_encrypted_objects,
+            version_ids: retention.version_ids,
+            archived_keys: retention.archived_keys,
+            pruned_versions: retention.pruned_versions,
+        };
+
+        let mut results = vec![CertificateStorageResult::S3(s3sr)];
+        results.extend(retention.errors.into_iter().map(CertificateStorageResult::Error));
+        Ok(results)
+    }
+
+    /// Observe the certificate currently stored in S3. The bundle and the artifacts of the other output formats must
+    /// be present as well.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let s3_client = S3Client::new_with_client(
+            self.assume_role.client()?,
+            self.region.clone().expect("Region should be set here"),
+        );
+        let domain_name = &domain_names[0];
+        let cert_key = self.primary_key(domain_name);
+
+        if self.bundle
+            && get_s3_object_bytes(&s3_client, &self.bucket, self.key(S3Object::Bundle, domain_name)).await?.is_none()
+        {
+            return Ok(None);
+        }
+
+        let mut artifacts = Vec::new();
+        for kind in self.artifacts.kinds(self.allow_private_key) {
+            let key = self.key(kind.into(), domain_name);
+            match get_s3_object_bytes(&s3_client, &self.bucket, key).await? {
+                Some(data) => artifacts.push(Artifact {
+                    kind,
+                    data,
+                }),
+                None => return Ok(None),
+            }
+        }
+
+        if !self.artifacts.writes_pem() {
+            return self.artifacts.observe(format!("s3://{}/{}", self.bucket, cert_key), &artifacts).await;
+        }
+
+        let pkey = async {
+            if self.allow_private_key {
+                get_s3_object_string(&s3_client, &self.bucket, self.key(S3Object::PrivateKey, domain_name)).await
+            } else {
+                Ok(None)
+            }
+        };
+
+        let (cert, chain, fullchain, pkey, alt_chain, alt_fullchain) = tokio::join!(
+            get_s3_object_string(&s3_client, &self.bucket, cert_key.clone()),
+            get_s3_object_string(&s3_client, &self.bucket, self.key(S3Object::Chain, domain_name)),
+            get_s3_object_string(&s3_client, &self.bucket, self.key(S3Object::FullChain, domain_name)),
+            pkey,
+            get_s3_object_string(&s3_client, &self.bucket, self.key(S3Object::AlternateChain, domain_name)),
+            get_s3_object_string(&s3_client, &self.bucket, self.key(S3Object::AlternateFullChain, domain_name)),
+        );
+
+        match (cert?, chain?, fullchain?, pkey?) {
+            (Some(cert_pem), Some(chain_pem), Some(fullchain_pem), Some(pkey_pem)) => Ok(Some(ObservedCertificate {
+                location: format!("s3://{}/{}", self.bucket, cert_key),
+                info: CertificateInfo::from_pem(&cert_pem)?,
+                components: Some(CertificateComponents {
+                    cert_pem,
+                    chain_pem,
+                    fullchain_pem,
+                    pkey_pem,
+                    alternate_chain: alternate_chain_from_parts(alt_chain?, alt_fullchain?),
+                }),
+            })),
+            // Without the key, this target is complete once the certificate and chains are present, but it can't be
+            // used as a copy source.
+            (Some(cert_pem), Some(_), Some(_), None) if !self.allow_private_key => Ok(Some(ObservedCertificate {
+                location: format!("s3://{}/{}", self.bucket, cert_key),
+                info: CertificateInfo::from_pem(&cert_pem)?,
+                components: None,
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Configuration for storing a certificate in AWS Systems Manager parameter store. In JSON:
+///
+///     {
+///         // The type of storage to use. This must be "SsmParameter".
+///         "Type": "SsmParameter",
+///
+///         // The path to store the certificate in. This must start with a "/". This is required unless
+///         // NameTemplate is specified and doesn't use {Path}.
+///         "Path": str,
+///
+///         // The template for parameter names. "{Path}" is replaced with Path, "{Domain}" with the first domain
+///         // name of the certificate, "{Component}" with the component name ("Certificate", "Chain",
+///         // "FullChain", "PrivateKey", "AlternateChain", or "AlternateFullChain"), and "{Environment}" with
+///         // Environment. {Component} is required. The default is "{Path}/Certificate/{Domain}/{Component}".
+///         "NameTemplate": str,
+///
+///         // The value of {Environment} in NameTemplate, e.g. "prod".
+///         "Environment": str,
+///
+///         // If false, the private key is never written to parameter store; only the certificate and chains are
+///         // stored. The default is true.
+///         "AllowPrivateKey": bool,
+///
+///         // The region to write the parameters in. This defaults to the region the function is running in.
+///         "Region": str,
+///
+///         // Write the parameters to each of these regions in parallel instead of a single region (e.g. to
+///         // replicate them to disaster recovery regions). This cannot be combined with Region.
+///         "Regions": [str, ...],
+///
+///         // The SSM tier to store the parameters in: "Standard", "Advanced", or "Intelligent-Tiering". Standard
+///         // parameters are limited to 4 KB, so a component that is too large (typically a full chain) is written
+///         // as an Advanced parameter instead. The default is "Intelligent-Tiering".
+///         "Tier": str,
+///
+///         // The KMS key to encrypt the private key (a SecureString parameter) with, e.g. a customer managed key
+///         // with restricted decrypt permissions. If not specified, the default "aws/ssm" key is used.
+///         "KmsKeyId": str,
+///
+///         // If true, all of the components are written to a single SecureString parameter (with a {Component}
+///         // of "Bundle") as a JSON object with "Certificate", "Chain", "FullChain", and "PrivateKey" keys, so
+///         // consumers need one GetParameter call instead of four. The default is false.
+///         "Bundle": bool,
+///
+///         // "Direct" or "Transactional"; see WriteStrategy. A bundle is a single parameter, so it is always
+///         // written directly. The default is "Direct".
+///         "WriteStrategy": str,
+///
+///         // How to detect another writer of these parameters; see WriterCheck.
+///         "WriterId": str,
+///         "OnWriterConflict": str,
+///     }
+///
+/// Advanced parameters are limited to 8 KB; if any component (or the bundle) is larger than that, nothing is
+/// written. Each parameter's "certificate-writer" tag records the writer fingerprint.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SsmParameterStorage {
+    #[serde(rename = "Path", default)]
+    pub path: String,
+
+    #[serde(rename = "NameTemplate", default)]
+    pub name_template: Option<String>,
+
+    #[serde(rename = "Environment", default)]
+    pub environment: Option<String>,
+
+    #[serde(rename = "AllowPrivateKey", default = "default_true")]
+    pub allow_private_key: bool,
+
+    #[serde(rename = "Region", default)]
+    pub region: Option<String>,
+
+    #[serde(rename = "Regions", default)]
+    pub regions: Vec<String>,
+
+    #[serde(rename = "Tier", default = "default_ssm_tier")]
+    pub tier: String,
+
+    #[serde(rename = "KmsKeyId", default)]
+    pub kms_key_id: Option<String>,
+
+    #[serde(rename = "Bundle", default = "default_false")]
+    pub bundle: bool,
+
+    #[serde(rename = "WriteStrategy", default, skip_serializing_if = "WriteStrategy::is_direct")]
+    pub write_strategy: WriteStrategy,
+
+    #[serde(flatten)]
+    pub writer_check: WriterCheck,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+fn default_ssm_tier() -> String {
+    SSM_TIER_INTELLIGENT_TIERING.to_string()
+}
+
+/// Check that every value fits in an (Advanced) SSM parameter, naming the ones that don't.
+fn check_ssm_value_sizes(sizes: Vec<(&str, usize)>) -> Result<(), LambdaError> {
+    let oversized: Vec<String> = sizes
+        .into_iter()
+        .filter(|(_, len)| *len > SSM_ADVANCED_MAX_VALUE_LEN)
+        .map(|(component, len)| format!("{} ({} bytes)", component, len))
+        .collect();
+
+    if oversized.is_empty() {
+        Ok(())
+    } else {
+        Err(CertificateRequestError::ssm_parameter_too_large(format!(
+            "{} exceeded the {}-byte limit for SSM parameters",
+            oversized.join(", "),
+            SSM_ADVANCED_MAX_VALUE_LEN
+        )))
+    }
+}
+
+/// Returns the name of the staged copy of an S3 object or SSM parameter written with the Transactional write
+/// strategy.
+fn staged_name(name: &str) -> String {
+    format!("{}{}", name, STORAGE_STAGING_SUFFIX)
+}
+
+/// Returns the request re-applying the tags a parameter had before a failed write, or None if it had none.
+fn restore_tags_request(param_name: &str, tags: Vec<SsmTag>) -> Option<AddTagsToResourceRequest> {
+    if tags.is_empty() {
+        None
+    } else {
+        Some(AddTagsToResourceRequest {
+            resource_id: param_name.to_string(),
+            resource_type: "Parameter".to_string(),
+            tags,
+        })
+    }
+}
+
+impl SsmParameterStorage {
+    fn ssm_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
+        }
+    }
+
+    fn name_template(&self) -> &str {
+        self.name_template.as_deref().unwrap_or(SSM_DEFAULT_NAME_TEMPLATE)
+    }
+
+    fn get_parameter_name(&self, domain_name: &str, component: &str) -> String {
+        self.name_template()
+            .replace("{Path}", self.path.trim_end_matches('/'))
+            .replace("{Environment}", self.environment.as_deref().unwrap_or_default())
+            .replace("{Domain}", &domain_name_for_path(domain_name))
+            .replace("{Component}", component)
+    }
+
+    /// Read a PEM certificate component from SSM, returning None if the parameter does not exist.
+    async fn read_cert_component_from_ssm(
+        &self,
+        domain_name: &str,
+        component: &'static str,
+    ) -> Result<Option<String>, LambdaError> {
+        let ssm = SsmClient::new_with_client(self.assume_role.client()?, self.ssm_region());
+        let param_name = self.get_parameter_name(domain_name, component);
+        let gp_request = GetParameterRequest {
+            name: param_name.clone(),
+            with_decryption: Some(true),
+        };
+
+        match throttle::call("GetParameter", || ssm.get_parameter(gp_request.clone())).await {
+            Ok(response) => Ok(response.parameter.and_then(|p| p.value)),
+            Err(RusotoError::Service(GetParameterError::ParameterNotFound(_))) => Ok(None),
+            Err(e) => {
+                error!("Failed to read SSM parameter {}: {:#}", param_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Make sure we may overwrite the certificate (or bundle) parameter, given who last wrote it. This is done once,
+    /// before any component is written.
+    async fn check_previous_writer(&self, domain_name: &str, component: &str) -> Result<(), LambdaError> {
+        let ssm = SsmClient::new_with_client(self.assume_role.client()?, self.ssm_region());
+        let param_name = self.get_parameter_name(domain_name, component);
+        let ltfr_request = ListTagsForResourceRequest {
+            resource_id: param_name.clone(),
+            resource_type: "Parameter".to_string(),
+        };
+
+        let previous = match ssm.list_tags_for_resource(ltfr_request).await {
+            Ok(response) => {
+                match response.tag_list.unwrap_or_default().into_iter().find(|tag| tag.key == WRITER_METADATA_KEY) {
+                    Some(tag) => PreviousWriter::Writer(tag.value),
+                    None => PreviousWriter::Unknown,
+                }
+            }
+            Err(RusotoError::Service(ListTagsForResourceError::InvalidResourceId(_))) => PreviousWriter::Nothing,
+            Err(e) => {
+                error!("Failed to read tags of SSM parameter {}: {:#}", param_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        self.writer_check.check(&param_name, &previous, &self.writer_check.fingerprint(self)?)
+    }
+
+    /// Returns the tier to write a value in: the configured tier, unless the value is too large for a Standard
+    /// parameter.
+    fn tier_for_value(&self, value: &str) -> &str {
+        if self.tier == SSM_TIER_STANDARD && value.len() > SSM_STANDARD_MAX_VALUE_LEN {
+            SSM_TIER_ADVANCED
+        } else {
+            &self.tier
+        }
+    }
+
+    /// Make sure every component fits in an SSM parameter before anything is written, so a certificate is never
+    /// left half-written.
+    fn check_component_sizes(&self, components: &CertificateComponents) -> Result<(), LambdaError> {
+        let mut sizes: Vec<(&str, usize)> = vec![
+            ("Certificate", components.cert_pem.len()),
+            ("Chain", components.chain_pem.len()),
+            ("FullChain", components.fullchain_pem.len()),
+        ];
+
+        if self.allow_private_key {
+            sizes.push(("PrivateKey", components.pkey_pem.len()));
+        }
+
+        if let Some(alternate) = &components.alternate_chain {
+            sizes.push(("AlternateChain", alternate.chain_pem.len()));
+            sizes.push(("AlternateFullChain", alternate.fullchain_pem.len()));
+        }
+
+        check_ssm_value_sizes(sizes)
+    }
+
+    /// Serialize the components to the JSON bundle, making sure it fits in an SSM parameter.
+    fn bundle_value(&self, components: CertificateComponents) -> Result<String, LambdaError> {
+        let (alternate_chain_pem, alternate_fullchain_pem) = match components.alternate_chain {
+            Some(alternate) => (Some(alternate.chain_pem), Some(alternate.fullchain_pem)),
+            None => (None, None),
+        };
+
+        let bundle = CertificateBundle {
+            cert_pem: components.cert_pem,
+            chain_pem: components.chain_pem,
+            fullchain_pem: components.fullchain_pem,
+            pkey_pem: if self.allow_private_key {
+                Some(components.pkey_pem)
+            } else {
+                None
+            },
+            alternate_chain_pem,
+            alternate_fullchain_pem,
+        };
+
+        let value = serde_json::to_string(&bundle)?;
+        check_ssm_value_sizes(vec![("Bundle", value.len())])?;
+        Ok(value)
+    }
+
+    /// Write all of the components to a single SecureString parameter as a JSON bundle.
+    async fn save_bundle(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let value = self.bundle_value(components)?;
+        self.check_previous_writer(&domain_names[0], "Bundle").await?;
+        let (bundle_param, bundle_arn, version, tier) =
+            self.write_cert_component_to_ssm(domain_names[0].clone(), value, "Bundle", true).await?;
+
+        let mut parameter_versions = BTreeMap::new();
+        if let Some(version) = version {
+            parameter_versions.insert(bundle_param.clone(), version);
+        }
+
+        let advanced_parameters = if tier.as_deref() == Some(SSM_TIER_ADVANCED) {
+            vec![bundle_param.clone()]
+        } else {
+            vec![]
+        };
+
+        let ssm_result = SsmParameterStorageResult {
+            bundle_param: Some(bundle_param),
+            bundle_arn: Some(bundle_arn),
+            parameter_versions,
+            advanced_parameters,
+            ..Default::default()
+        };
+        Ok(vec![CertificateStorageResult::SsmParameter(ssm_result)])
+    }
+
+    /// Observe a certificate stored as a JSON bundle.
+    async fn observe_bundle(&self, domain_name: &str) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let bundle: CertificateBundle = match self.read_cert_component_from_ssm(domain_name, "Bundle").await? {
+            None => return Ok(None),
+            Some(value) => serde_json::from_str(&value)?,
+        };
+
+        // As with Secrets Manager bundles, a target that holds the key is only complete if the key is present.
+        let info = CertificateInfo::from_pem(&bundle.cert_pem)?;
+        let components = match (self.allow_private_key, bundle.pkey_pem) {
+            (true, Some(pkey_pem)) => Some(CertificateComponents {
+                cert_pem: bundle.cert_pem,
+                chain_pem: bundle.chain_pem,
+                fullchain_pem: bundle.fullchain_pem,
+                pkey_pem,
+                alternate_chain: alternate_chain_from_parts(bundle.alternate_chain_pem, bundle.alternate_fullchain_pem),
+            }),
+            (true, None) => return Ok(None),
+            (false, _) => None,
+        };
+
+        Ok(Some(ObservedCertificate {
+            location: self.get_parameter_name(domain_name, "Bundle"),
+            info,
+            components,
+        }))
+    }
+
+    /// Write each component to its own parameter.
+    async fn write_components(
+        &self,
+        domain_name: &str,
+        components: CertificateComponents,
+    ) -> Result<SsmParameterStorageResult, LambdaError> {
+        let pkey_pem = components.pkey_pem;
+        let pkey = async {
+            if self.allow_private_key {
+                Some(self.write_cert_component_to_ssm(domain_name.to_string(), pkey_pem, "PrivateKey", true).await)
+            } else {
+                None
+            }
+        };
+
+        let (cert, chain, fullchain, pkey) = tokio::join!(
+            self.write_cert_component_to_ssm(domain_name.to_string(), components.cert_pem, "Certificate", false),
+            self.write_cert_component_to_ssm(domain_name.to_string(), components.chain_pem, "Chain", false),
+            self.write_cert_component_to_ssm(domain_name.to_string(), components.fullchain_pem, "FullChain", false),
+            pkey,
+        );
+
+        let mut parameter_versions = BTreeMap::new();
+        let mut advanced_parameters = Vec::new();
+        let mut record_version = |(param, arn, version, tier): (String, String, Option<i64>, Option<String>)| {
+            if let Some(version) = version {
+                parameter_versions.insert(param.clone(), version);
+            }
+            if tier.as_deref() == Some(SSM_TIER_ADVANCED) {
+                advanced_parameters.push(param.clone());
+            }
+            (param, arn)
+        };
+
+        let (cert_param, cert_arn) = record_version(cert?);
+        let (chain_param, chain_arn) = record_version(chain?);
+        let (fullchain_param, fullchain_arn) = record_version(fullchain?);
+        let (pkey_param, pkey_arn) = match pkey {
+            Some(pkey) => {
+                let (param, arn) = record_version(pkey?);
+                (Some(param), Some(arn))
+            }
+            None => (None, None),
+        };
+
+        let (alternate_chain, alternate_fullchain) = match components.alternate_chain {
+            None => (None, None),
+            Some(alternate) => {
+                let (alt_chain, alt_fullchain) = tokio::join!(
+                    self.write_cert_component_to_ssm(
+                        domain_name.to_string(),
+                        alternate.chain_pem,
+                        "AlternateChain",
+                        false
+                    ),
+                    self.write_cert_component_to_ssm(
+                        domain_name.to_string(),
+                        alternate.fullchain_pem,
+                        "AlternateFullChain",
+                        false
+                    ),
+                );
+                (Some(record_version(alt_chain?)), Some(record_version(alt_fullchain?)))
+            }
+        };
+
+        let ssm_result = SsmParameterStorageResult {
+            cert_param: Some(cert_param),
+            chain_param: Some(chain_param),
+            fullchain_param: Some(fullchain_param),
+            pkey_param,
+            cert_arn: Some(cert_arn),
+            chain_arn: Some(chain_arn),
+            fullchain_arn: Some(fullchain_arn),
+            pkey_arn,
+            alternate_chain_param: alternate_chain.as_ref().map(|(param, _)| param.clone()),
+            alternate_fullchain_param: alternate_fullchain.as_ref().map(|(param, _)| param.clone()),
+            alternate_chain_arn: alternate_chain.map(|(_, arn)| arn),
+            alternate_fullchain_arn: alternate_fullchain.map(|(_, arn)| arn),
+            parameter_versions,
+            advanced_parameters,
+            ..Default::default()
+        };
+        Ok(ssm_result)
+    }
+
+    /// Returns the components to write, with whether each is a SecureString.
+    fn component_values(&self, components: &CertificateComponents) -> Vec<(&'static str, String, bool)> {
+        let mut values = vec![
+            ("Certificate", components.cert_pem.clone(), false),
+            ("Chain", components.chain_pem.clone(), false),
+            ("FullChain", components.fullchain_pem.clone(), false),
+        ];
+
+        if self.allow_private_key {
+            values.push(("PrivateKey", components.pkey_pem.clone(), true));
+        }
+
+        if let Some(alternate) = &components.alternate_chain {
+            values.push(("AlternateChain", alternate.chain_pem.clone(), false));
+            values.push(("AlternateFullChain", alternate.fullchain_pem.clone(), false));
+        }
+
+        values
+    }
+
+    /// Write each component with the Transactional write strategy: stage every component, then overwrite the live
+    /// parameters, restoring their previous values and tags if any write fails. Staged parameters are deleted
+    /// afterwards either way.
+    async fn write_components_transactionally(
+        &self,
+        domain_name: &str,
+        components: CertificateComponents,
+    ) -> Result<SsmParameterStorageResult, LambdaError> {
+        let ssm = SsmClient::new_with_client(self.assume_role.client()?, self.ssm_region());
+        let values = self.component_values(&components);
+        let mut previous = Vec::with_capacity(values.len());
+        for (component, _, secure) in &values {
+            let value = self.read_cert_component_from_ssm(domain_name, component).await?;
+            let tags = match value {
+                Some(_) => self.read_parameter_tags(&ssm, &self.get_parameter_name(domain_name, component)).await?,
+                None => Vec::new(),
+            };
+            previous.push((*component, *secure, value, tags));
+        }
+
+        let staged_names: Vec<String> = values
+            .iter()
+            .map(|(component, _, _)| staged_name(&self.get_parameter_name(domain_name, component)))
+            .collect();
+
+        for ((_, value, secure), staged_name) in values.into_iter().zip(staged_names.iter()) {
+            info!("Staging SSM parameter {}", staged_name);
+            if let Err(e) = self.put_parameter_value(&ssm, staged_name, value, secure).await {
+                error!("Failed to stage SSM parameters for {}; nothing was overwritten: {:#}", domain_name, e);
+                self.delete_staged_parameters(&ssm, &staged_names).await;
+                return Err(e);
+            }
+        }
+
+        let result = self.write_components(domain_name, components).await;
+        if let Err(e) = &result {
+            error!("Failed to write SSM parameters for {}; restoring their previous values: {:#}", domain_name, e);
+            for (component, secure, value, tags) in previous {
+                let param_name = self.get_parameter_name(domain_name, component);
+                let restored = match value {
+                    Some(value) => match self.put_parameter_value(&ssm, &param_name, value, secure).await {
+                        Ok(()) => self.restore_parameter_tags(&ssm, &param_name, tags).await,
+                        Err(e) => Err(e),
+                    },
+                    None => {
+                        let dp_request = DeleteParameterRequest {
+                            name: param_name.clone(),
+                        };
+                        match throttle::call("DeleteParameter", || ssm.delete_parameter(dp_request.clone())).await {
+                            Ok(_) => Ok(()),
+                            Err(e) => Err(Box::new(e) as LambdaError),
+                        }
+                    }
+                };
+
+                if let Err(e) = restored {
+                    error!("Failed to restore SSM parameter {}: {:#}", param_name, e);
+                }
+            }
+        }
+
+        self.delete_staged_parameters(&ssm, &staged_names).await;
+        result
+    }
+
+    /// Overwrite a parameter's value, without the tagging and bookkeeping of write_cert_component_to_ssm.
+    async fn put_parameter_value(
+        &self,
+        ssm: &SsmClient,
+        param_name: &str,
+        value: String,
+        secure: bool,
+    ) -> Result<(), LambdaError> {
+        let (param_type, key_id) = if secure {
+            (SSM_TYPE_SECURE_STRING, self.kms_key_id.clone())
+        } else {
+            (SSM_TYPE_STRING, None)
+        };
+
+        let pp_request = PutParameterRequest {
+            name: param_name.to_string(),
+            overwrite: Some(true),
+            type_: Some(param_type.to_string()),
+            tier: Some(self.tier_for_value(&value).to_string()),
+            key_id,
+            value,
+            ..Default::default()
+        };
+
+        throttle::call("PutParameter", || faults::aws_call(Fault::SsmFailure, ssm.put_parameter(pp_request.clone())))
+            .await?;
+        Ok(())
+    }
+
+    /// Returns the tags on a parameter.
+    async fn read_parameter_tags(&self, ssm: &SsmClient, param_name: &str) -> Result<Vec<SsmTag>, LambdaError> {
+        let ltfr_request = ListTagsForResourceRequest {
+            resource_id: param_name.to_string(),
+            resource_type: "Parameter".to_string(),
+        };
+
+        match throttle::call("ListTagsForResource", || ssm.list_tags_for_resource(ltfr_request.clone())).await {
+            Ok(response) => Ok(response.tag_list.unwrap_or_default()),
+            Err(e) => {
+                error!("Failed to read tags of SSM parameter {}: {:#}", param_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Put back the tags a parameter had before a failed write, which may have replaced its writer tag.
+    async fn restore_parameter_tags(
+        &self,
+        ssm: &SsmClient,
+        param_name: &str,
+        tags: Vec<SsmTag>,
+    ) -> Result<(), LambdaError> {
+        let attr_request = match restore_tags_request(param_name, tags) {
+            Some(attr_request) => attr_request,
+            None => return Ok(()),
+        };
+
+        match throttle::call("AddTagsToResource", || ssm.add_tags_to_resource(attr_request.clone())).await {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    /// Delete staged parameters. Failures are only logged, since the live parameters are already written.
+    async fn delete_staged_parameters(&self, ssm: &SsmClient, staged_names: &[String]) {
+        let dp_request = DeleteParametersRequest {
+            names: staged_names.to_vec(),
+        };
+
+        if let Err(e) = throttle::call("DeleteParameters", || ssm.delete_parameters(dp_request.clone())).await {
+            warn!("Failed to delete staged SSM parameters {}: {:#}", staged_names.join(", "), e);
+        }
+    }
+
+    /// Write a PEM certificate to SSM.
+    async fn write_cert_component_to_ssm(
+        &self,
+        domain_name: String,
+        data: String,
+        component: &'static str,
+        secure: bool,
+    ) -> Result<(String, String, Option<i64>, Option<String>), LambdaError> {
+        let ssm = SsmClient::new_with_client(self.assume_role.client()?, self.ssm_region());
+        let param_name = self.get_parameter_name(&domain_name, component);
+        let (param_type, key_id) = if secure {
+            (Some(SSM_TYPE_SECURE_STRING.to_string()), self.kms_key_id.clone())
+        } else {
+            (Some("String".to_string()), None)
+        };
+
+        let tier = self.tier_for_value(&data);
+        if tier != self.tier {
+            info!("SSM parameter {} is {} bytes; writing it as a {} parameter", param_name, data.len(), tier);
+        }
+
+        let pp_request = PutParameterRequest {
+            name: param_name.clone(),
+            description: Some(format!("SSL {} for {}", component, domain_name)),
+            overwrite: Some(true),
+            type_: param_type,
+            tier: Some(tier.to_string()),
+            key_id,
+            value: data,
+            ..Default::default()
+        };
+
+        let gp_request = GetParameterRequest {
+            name: param_name.clone(),
+            ..Default::default()
+        };
+
+        info!("Writing SSM parameter {}", param_name);
+
+        match throttle::call("PutParameter", || {
+            faults::aws_call(Fault::SsmFailure, ssm.put_parameter(pp_request.clone()))
+        })
+        .await
+        {
+            Ok(response) => {
+                info!("SSM parameter {} written successfully", param_name);
+                let tier = response.tier;
+
+                // PutParameter can't tag a parameter it overwrites, so the fingerprint is applied separately. The
+                // certificate is already written, so a failure here is only logged.
+                let attr_request = AddTagsToResourceRequest {
+                    resource_id: param_name.clone(),
+                    resource_type: "Parameter".to_string(),
+                    tags: vec![SsmTag {
+                        key: WRITER_METADATA_KEY.to_string(),
+                        value: self.writer_check.fingerprint(self)?,
+                    }],
+                };
+                if let Err(e) =
+                    throttle::call("AddTagsToResource", || ssm.add_tags_to_resource(attr_request.clone())).await
+                {
+                    warn!("Failed to record the writer of SSM parameter {}: {:#}", param_name, e);
+                }
+
+                match throttle::call("GetParameter", || ssm.get_parameter(gp_request.clone())).await {
+                    Ok(response) => match response.parameter {
+                        None => {
+                            error!("Unable to get ARN for parameter {}: no parameter returned", param_name);
+                            Err(CertificateRequestError::unexpected_aws_response(format!(
+                                "Unable to get ARN for parameter {}: no parameter returned",
+                                param_name
+                            )))
+                        }
+                        Some(parameter) => match parameter.arn {
+                            None => {
+                                error!("Unable to get ARN for parameter {}: no ARN returned", param_name);
+                                Err(CertificateRequestError::unexpected_aws_response(format!(
+                                    "Unable to get ARN for parameter {}: no ARN returned",
+                                    param_name
+                                )))
+                            }
+                            Some(arn) => Ok((param_name, arn, parameter.version, tier)),
+                        },
+                    },
+                    Err(e) => {
+                        error!("Unable to get ARN for parameter {}: {}", param_name, e);
+                        Err(CertificateRequestError::unexpected_aws_response(format!(
+                            "Unable to get ARN for parameter {}: {}",
+                            param_name, e
+                        )))
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to write SSM parameter {}: {:#}", param_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CertificateStore for SsmParameterStorage {
+    fn type_name(&self) -> &'static str {
+        "SsmParameter"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn allows_private_key(&self) -> bool {
+        self.allow_private_key
+    }
+
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        if self.regions.is_empty() {
+            return Ok(None);
+        }
+
+        let regions = replica_regions(&self.regions, self.region.as_deref())?;
+        Ok(Some(
+            regions
+                .into_iter()
+                .map(|region| -> Box<dyn CertificateStore> {
+                    Box::new(SsmParameterStorage {
+                        region: Some(region),
+                        regions: vec![],
+                        ..self.clone()
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_regions(format!("Invalid region: {}", region)));
+            }
+        }
+
+        match self.tier.as_str() {
+            SSM_TIER_STANDARD | SSM_TIER_ADVANCED | SSM_TIER_INTELLIGENT_TIERING => (),
+            _ => return Err(InvalidCertificateRequest::invalid_ssm_tier(self.tier.clone())),
+        }
+
+        let template = self.name_template().to_string();
+        if template.contains("{Path}") || !self.path.is_empty() {
+            match validate_and_sanitize_ssm_parameter_path(&self.path) {
+                Some(path) if !self.path.is_empty() => self.path = path,
+                _ => return Err(InvalidCertificateRequest::invalid_ssm_parameter_path(self.path.clone())),
+            }
+        }
+
+        if !template.contains("{Component}") {
+            return Err(InvalidCertificateRequest::invalid_ssm_parameter_path(format!(
+                "NameTemplate must contain {{Component}}: {}",
+                template
+            )));
+        }
+
+        if template.contains("{Environment}") && self.environment.as_deref().unwrap_or_default().is_empty() {
+            return Err(InvalidCertificateRequest::invalid_ssm_parameter_path(format!(
+                "NameTemplate uses {{Environment}} but Environment is not set: {}",
+                template
+            )));
+        }
+
+        // Make sure the template produces valid parameter names.
+        let sample = self.get_parameter_name("example.com", "Certificate");
+        match validate_and_sanitize_ssm_parameter_path(&sample) {
+            Some(sanitized) if sanitized == sample => Ok(()),
+            _ => Err(InvalidCertificateRequest::invalid_ssm_parameter_path(format!(
+                "NameTemplate produces invalid parameter names such as {}",
+                sample
+            ))),
+        }
+    }
+
+    fn planned_writes(&self, domain_names: &[String], alternate_chain: bool) -> Vec<PlannedWrite> {
+        let mut components = vec!["Certificate", "Chain", "FullChain"];
+        if self.allow_private_key {
+            components.push("PrivateKey");
+        }
+        if alternate_chain {
+            components.extend(&["AlternateChain", "AlternateFullChain"]);
+        }
+        if self.bundle {
+            components = vec!["Bundle"];
+        }
+
+        let region = self.ssm_region();
+        let mut writes = Vec::new();
+        for component in components {
+            let name = self.get_parameter_name(&domain_names[0], component);
+            if self.write_strategy == WriteStrategy::Transactional && !self.bundle {
+                let staged = format!("{}{}", name, STORAGE_STAGING_SUFFIX);
+                writes.push(PlannedWrite::in_region("ssm:PutParameter", staged.as_str(), &region));
+                writes.push(PlannedWrite::in_region("ssm:PutParameter", name, &region));
+                writes.push(PlannedWrite::in_region("ssm:DeleteParameters", staged, &region));
+            } else {
+                writes.push(PlannedWrite::in_region("ssm:PutParameter", name, &region));
+            }
+        }
+
+        writes
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        if self.bundle {
+            return self.save_bundle(domain_names, components).await;
+        }
+
+        self.check_component_sizes(&components)?;
+        self.check_previous_writer(&domain_names[0], "Certificate").await?;
+
+        let ssm_result = match self.write_strategy {
+            WriteStrategy::Direct => self.write_components(&domain_names[0], components).await?,
+            WriteStrategy::Transactional => self.write_components_transactionally(&domain_names[0], components).await?,
+        };
+        Ok(vec![CertificateStorageResult::SsmParameter(ssm_result)])
+    }
+
+    /// Observe the certificate currently stored in SSM.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let domain_name = domain_names[0].as_str();
+        if self.bundle {
+            return self.observe_bundle(domain_name).await;
+        }
+
+        let (cert, chain, fullchain, pkey, alt_chain, alt_fullchain) = tokio::join!(
+            self.read_cert_component_from_ssm(domain_name, "Certificate"),
+            self.read_cert_component_from_ssm(domain_name, "Chain"),
+            self.read_cert_component_from_ssm(domain_name, "FullChain"),
+            async {
+                if self.allow_private_key {
+                    self.read_cert_component_from_ssm(domain_name, "PrivateKey").await
+                } else {
+                    Ok(None)
+                }
+            },
+            self.read_cert_component_from_ssm(domain_name, "AlternateChain"),
+            self.read_cert_component_from_ssm(domain_name, "AlternateFullChain"),
+        );
+
+        match (cert?, chain?, fullchain?, pkey?) {
+            (Some(cert_pem), Some(chain_pem), Some(fullchain_pem), Some(pkey_pem)) => Ok(Some(ObservedCertificate {
+                location: self.get_parameter_name(domain_name, "Certificate"),
+                info: CertificateInfo::from_pem(&cert_pem)?,
+                components: Some(CertificateComponents {
+                    cert_pem,
+                    chain_pem,
+                    fullchain_pem,
+                    pkey_pem,
+                    alternate_chain: alternate_chain_from_parts(alt_chain?, alt_fullchain?),
+                }),
+            })),
+            (Some(cert_pem), Some(_), Some(_), None) if !self.allow_private_key => Ok(Some(ObservedCertificate {
+                location: self.get_parameter_name(domain_name, "Certificate"),
+                info: CertificateInfo::from_pem(&cert_pem)?,
+                components: None,
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Configuration for storing a certificate in AWS Secrets Manager. In JSON:
+///
+///     {
+///         // The type of storage to use. This must be "SecretsManager".
+///         "Type": "SecretsManager",
+///
+///         // The template for the secret name. "{Domain}" is replaced with the first domain name of the
+///         // certificate. "{Component}" is replaced with the component name ("Certificate", "Chain",
+///         // "FullChain", "PrivateKey", or, if the alternate chain is stored, "AlternateChain" and
+///         // "AlternateFullChain"); it must be present if SeparateSecrets is true and must be absent
+///         // otherwise. This defaults to "Certificate/{Domain}" or, if SeparateSecrets is true,
+///         // "Certificate/{Domain}/{Component}".
+///         "SecretNameTemplate": str,
+///
+///         // If true, each component is stored in its own secret as a PEM string. Otherwise, all of the
+///         // components are stored in a single secret as a JSON object with "Certificate", "Chain",
+///         // "FullChain", and "PrivateKey" keys. The default is false.
+///         "SeparateSecrets": bool,
+///
+///         // The KMS key to use to encrypt the secret(s). If not specified, the default
+///         // "aws/secretsmanager" key is used.
+///         "KmsKeyId": str,
+///
+///         // If false, the private key is never written to Secrets Manager; the "PrivateKey" secret (or bundle
+///         // key) is omitted. The default is true.
+///         "AllowPrivateKey": bool,
+///
+///         // The region to write the secret(s) in. This defaults to the region the function is running in.
+///         "Region": str,
+///
+///         // Write the secret(s) to each of these regions in parallel instead of a single region. This cannot be
+///         // combined with Region. If KmsKeyId is specified, it must be usable in every region (e.g. a
+///         // multi-Region key alias).
+///         "Regions": [str, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SecretsManagerStorage {
+    #[serde(rename = "SecretNameTemplate", default)]
+    pub secret_name_template: Option<String>,
+
+    #[serde(rename = "SeparateSecrets", default = "default_false")]
+    pub separate_secrets: bool,
+
+    #[serde(rename = "KmsKeyId", default)]
+    pub kms_key_id: Option<String>,
+
+    #[serde(rename = "AllowPrivateKey", default = "default_true")]
+    pub allow_private_key: bool,
+
+    #[serde(rename = "Region", default)]
+    pub region: Option<String>,
+
+    #[serde(rename = "Regions", default)]
+    pub regions: Vec<String>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+/// The JSON object written to Secrets Manager when SeparateSecrets is false, and to SSM when Bundle is true.
+#[derive(Debug, Deserialize, Serialize)]
+struct CertificateBundle {
+    #[serde(rename = "Certificate")]
+    cert_pem: String,
+
+    #[serde(rename = "Chain")]
+    chain_pem: String,
+
+    #[serde(rename = "FullChain")]
+    fullchain_pem: String,
+
+    #[serde(rename = "PrivateKey", default, skip_serializing_if = "Option::is_none")]
+    pkey_pem: Option<String>,
+
+    #[serde(rename = "AlternateChain", default, skip_serializing_if = "Option::is_none")]
+    alternate_chain_pem: Option<String>,
+
+    #[serde(rename = "AlternateFullChain", default, skip_serializing_if = "Option::is_none")]
+    alternate_fullchain_pem: Option<String>,
+}
+
+impl SecretsManagerStorage {
+    fn secrets_manager_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
+        }
+    }
+
+    fn get_secret_name(&self, domain_name: &str, component: &str) -> String {
+        self.secret_name_template
+            .as_ref()
+            .expect("SecretNameTemplate should be set here")
+            .replace("{Domain}", &domain_name_for_path(domain_name))
+            .replace("{Component}", component)
+    }
+
+    /// Read a secret from Secrets Manager, returning None if the secret does not exist.
+    async fn read_secret(&self, domain_name: &str, component: &'static str) -> Result<Option<String>, LambdaError> {
+        let sm = SecretsManagerClient::new_with_client(self.assume_role.client()?, self.secrets_manager_region());
+        let secret_name = self.get_secret_name(domain_name, component);
+        let gsv_request = GetSecretValueRequest {
+            secret_id: secret_name.clone(),
+            ..Default::default()
+        };
+
+        match sm.get_secret_value(gsv_request).await {
+            Ok(response) => Ok(response.secret_string),
+            Err(RusotoError::Service(GetSecretValueError::ResourceNotFound(_))) => Ok(None),
+            Err(e) => {
+                error!("Failed to read Secrets Manager secret {}: {:#}", secret_name, e);
+                Err(Box::new(e))
+            }
+        }
+    }
+
+    /// Write a certificate component (or the JSON bundle of all components) to Secrets Manager, creating the
+    /// secret if it does not already exist.
+    async fn write_secret(
+        &self,
+        domain_name: &str,
+        component: &'static str,
+        value: String,
+    ) -> Result<SecretsManagerSecretResult, LambdaError> {
+        let sm = SecretsManagerClient::new_with_client(self.assume_role.client()?, self.secrets_manager_region());
+        let secret_name = self.get_secret_name(domain_name, component);
+        let description = format!("SSL {} for {}", component, domain_name);
+
+        let us_request = UpdateSecretRequest {
+            secret_id: secret_name.clone(),
+            description: Some(description.clone()),
+            kms_key_id: self.kms_key_id.clone(),
+            secret_string: Some(value.clone()),
+            ..Default::default()
+        };
+
+        info!("Writing Secrets Manager secret {}", secret_name);
+
+        let (arn, version_id) = match sm.update_secret(us_request).await {
+            Ok(response) => (response.arn, response.version_id),
+            Err(RusotoError::Service(UpdateSecretError::ResourceNotFound(_))) => {
+                info!("Secrets Manager secret {} does not exist; creating it", secret_name);
+                let cs_request = CreateSecretRequest {
+                    name: secret_name.clone(),
+                    description: Some(description),
+                    kms_key_id: self.kms_key_id.clone(),
+                    secret_string: Some(value),
+                    ..Default::default()
+                };
+
+                match sm.create_secret(cs_request).await {
+                    Ok(response) => (response.arn, response.version_id),
+                    Err(e) => {
+                        error!("Failed to create Secrets Manager secret {}: {:#}", secret_name, e);
+                        return Err(Box::new(e));
+                    }
+                }
+            }
+            Err(e) => {
+                error!("Failed to write Secrets Manager secret {}: {:#}", secret_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        match arn {
+            None => {
+                error!("Unable to get ARN for secret {}: no ARN returned", secret_name);
+                Err(CertificateRequestError::unexpected_aws_response(format!(
+                    "Unable to get ARN for secret {}: no ARN returned",
+                    secret_name
+                )))
+            }
+            Some(arn) => {
+                info!("Secrets Manager secret {} written successfully", secret_name);
+                Ok(SecretsManagerSecretResult {
+                    component: component.to_string(),
+                    secret_name,
+                    secret_arn: arn,
+                    version_id,
+                })
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CertificateStore for SecretsManagerStorage {
+    fn type_name(&self) -> &'static str {
+        "SecretsManager"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn allows_private_key(&self) -> bool {
+        self.allow_private_key
+    }
+
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        if self.regions.is_empty() {
+            return Ok(None);
+        }
+
+        let regions = replica_regions(&self.regions, self.region.as_deref())?;
+        Ok(Some(
+            regions
+                .into_iter()
+                .map(|region| -> Box<dyn CertificateStore> {
+                    Box::new(SecretsManagerStorage {
+                        region: Some(region),
+                        regions: vec![],
+                        ..self.clone()
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_regions(format!("Invalid region: {}", region)));
+            }
+        }
+
+        let template = match &self.secret_name_template {
+            Some(template) => template.clone(),
+            None if self.separate_secrets => SECRETS_MANAGER_DEFAULT_SEPARATE_NAME_TEMPLATE.to_string(),
+            None => SECRETS_MANAGER_DEFAULT_BUNDLE_NAME_TEMPLATE.to_string(),
+        };
+
+        if template.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_secrets_manager_configuration(
+                "SecretNameTemplate cannot be empty",
+            ));
+        }
+
+        let has_component = template.contains("{Component}");
+        if self.separate_secrets && !has_component {
+            return Err(InvalidCertificateRequest::invalid_secrets_manager_configuration(
+                "SecretNameTemplate must contain {Component} when SeparateSecrets is true",
+            ));
+        }
+
+        if !self.separate_secrets && has_component {
+            return Err(InvalidCertificateRequest::invalid_secrets_manager_configuration(
+                "SecretNameTemplate cannot contain {Component} when SeparateSecrets is false",
+            ));
+        }
+
+        self.secret_name_template = Some(template);
+        Ok(())
+    }
+
+    fn planned_writes(&self, domain_names: &[String], alternate_chain: bool) -> Vec<PlannedWrite> {
+        let components = if self.separate_secrets {
+            let mut components = vec!["Certificate", "Chain", "FullChain"];
+            if self.allow_private_key {
+                components.push("PrivateKey");
+            }
+            if alternate_chain {
+                components.extend(&["AlternateChain", "AlternateFullChain"]);
+            }
+            components
+        } else {
+            vec!["Bundle"]
+        };
+
+        // Secrets that don't exist yet are created instead of updated.
+        let region = self.secrets_manager_region();
+        components
+            .into_iter()
+            .map(|component| {
+                let name = self.get_secret_name(&domain_names[0], component);
+                PlannedWrite::in_region("secretsmanager:UpdateSecret", name, &region)
+            })
+            .collect()
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let domain_name = domain_names[0].as_str();
+
+        let secrets = if self.separate_secrets {
+            let pkey_pem = components.pkey_pem;
+            let pkey = async {
+                if self.allow_private_key {
+                    Some(self.write_secret(domain_name, "PrivateKey", pkey_pem).await)
+                } else {
+                    None
+                }
+            };
+
+            let (cert, chain, fullchain, pkey) = tokio::join!(
+                self.write_secret(domain_name, "Certificate", components.cert_pem),
+                self.write_secret(domain_name, "Chain", components.chain_pem),
+                self.write_secret(domain_name, "FullChain", components.fullchain_pem),
+                pkey,
+            );
+
+            let mut secrets = vec![cert?, chain?, fullchain?];
+            if let Some(pkey) = pkey {
+                secrets.push(pkey?);
+            }
+
+            if let Some(alternate) = components.alternate_chain {
+                let (alt_chain, alt_fullchain) = tokio::join!(
+                    self.write_secret(domain_name, "AlternateChain", alternate.chain_pem),
+                    self.write_secret(domain_name, "AlternateFullChain", alternate.fullchain_pem),
+                );
+                secrets.push(alt_chain?);
+                secrets.push(alt_fullchain?);
+            }
+
+            secrets
+        } else {
+            let (alternate_chain_pem, alternate_fullchain_pem) = match components.alternate_chain {
+                Some(alternate) => (Some(alternate.chain_pem), Some(alternate.fullchain_pem)),
+                None => (None, None),
+            };
+
+            let bundle = CertificateBundle {
+                cert_pem: components.cert_pem,
+                chain_pem: components.chain_pem,
+                fullchain_pem: components.fullchain_pem,
+                pkey_pem: if self.allow_private_key {
+                    Some(components.pkey_pem)
+                } else {
+                    None
+                },
+                alternate_chain_pem,
+                alternate_fullchain_pem,
+            };
+
+            vec![self.write_secret(domain_name, "Bundle", serde_json::to_string(&bundle)?).await?]
+        };
+
+        Ok(vec![CertificateStorageResult::SecretsManager(SecretsManagerStorageResult {
+            secrets,
+        })])
+    }
+
+    /// Observe the certificate currently stored in Secrets Manager.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let domain_name = domain_names[0].as_str();
+
+        let bundle = if self.separate_secrets {
+            let (cert, chain, fullchain, pkey, alt_chain, alt_fullchain) = tokio::join!(
+                self.read_secret(domain_name, "Certificate"),
+                self.read_secret(domain_name, "Chain"),
+                self.read_secret(domain_name, "FullChain"),
+                async {
+                    if self.allow_private_key {
+                        self.read_secret(domain_name, "PrivateKey").await
+                    } else {
+                        Ok(None)
+                    }
+                },
+                self.read_secret(domain_name, "AlternateChain"),
+                self.read_secret(domain_name, "AlternateFullChain"),
+            );
+
+            match (cert?, chain?, fullchain?) {
+                (Some(cert_pem), Some(chain_pem), Some(fullchain_pem)) => CertificateBundle {
+                    cert_pem,
+                    chain_pem,
+                    fullchain_pem,
+                    pkey_pem: pkey?,
+                    alternate_chain_pem: alt_chain?,
+                    alternate_fullchain_pem: alt_fullchain?,
+                },
+                _ => return Ok(None),
+            }
+        } else {
+            match self.read_secret(domain_name, "Bundle").await? {
+                None => return Ok(None),
+                Some(value) => serde_json::from_str(&value)?,
+            }
+        };
+
+        // A target that holds the key is only complete if the key is present. One that doesn't can't be used as a
+        // copy source, so its components aren't returned.
+        let info = CertificateInfo::from_pem(&bundle.cert_pem)?;
+        let components = match (self.allow_private_key, bundle.pkey_pem) {
+            (true, Some(pkey_pem)) => Some(CertificateComponents {
+                cert_pem: bundle.cert_pem,
+                chain_pem: bundle.chain_pem,
+                fullchain_pem: bundle.fullchain_pem,
+                pkey_pem,
+                alternate_chain: alternate_chain_from_parts(bundle.alternate_chain_pem, bundle.alternate_fullchain_pem),
+            }),
+            (true, None) => return Ok(None),
+            (false, _) => None,
+        };
+
+        Ok(Some(ObservedCertificate {
+            location: self.get_secret_name(domain_name, "Certificate"),
+            info,
+            components,
+        }))
+    }
+}
+
+/// Configuration for storing a certificate in a DynamoDB table, keeping every issued certificate as a separate
+/// version so consumers can query the history and roll back. In JSON:
+///
+///     {
+///         // The type of storage to use. This must be "DynamoDb".
+///         "Type": "DynamoDb",
+///
+///         // The name of the table. The table must have a string partition key named "DomainName" and a numeric
+///         // sort key named "IssuedAt".
+///         "TableName": str,
+///
+///         // If set, each time a new certificate is written, older versions are given an "ExpiresAt" attribute
+///         // this many days in the future. Enable TTL on ExpiresAt in the table to have DynamoDB delete them. The
+///         // current version never expires. If not set, old versions are kept indefinitely.
+///         "RetentionDays": int,
+///
+///         // If false, the private key is never written to the table. The default is true.
+///         "AllowPrivateKey": bool,
+///
+///         // The region of the table. This defaults to the region the function is running in.
+///         "Region": str,
+///
+///         // Write to a table with the same name in each of these regions in parallel instead of a single region.
+///         // This cannot be combined with Region.
+///         "Regions": [str, ...],
+///     }
+///
+/// Each version is an item with the first domain name of the certificate (lowercase) as DomainName, the start of
+/// the certificate's validity period (seconds since the Unix epoch) as IssuedAt, and the attributes DomainNames,
+/// Serial, NotBefore, NotAfter, StoredAt, Certificate, Chain, FullChain, PrivateKey, and (if stored)
+/// AlternateChain and AlternateFullChain. The latest version is the item with the highest IssuedAt. Writing the same
+/// certificate again overwrites its version rather than adding a new one.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct DynamoDbStorage {
+    #[serde(rename = "TableName")]
+    pub table_name: String,
+
+    #[serde(rename = "RetentionDays", default)]
+    pub retention_days: Option<i64>,
+
+    #[serde(rename = "AllowPrivateKey", default = "default_true")]
+    pub allow_private_key: bool,
+
+    #[serde(rename = "Region", default)]
+    pub region: Option<String>,
+
+    #[serde(rename = "Regions", default)]
+    pub regions: Vec<String>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+impl DynamoDbStorage {
+    fn dynamodb_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
+        }
+    }
+
+    /// Build the item for a version of the certificate.
+    fn certificate_item(
+        &self,
+        domain_names: &[String],
+        components: &CertificateComponents,
+        info: &CertificateInfo,
+    ) -> HashMap<String, AttributeValue> {
+        let mut item = HashMap::new();
+        item.insert("DomainName".to_string(), attr_s(&domain_names[0].to_lowercase()));
+        item.insert("IssuedAt".to_string(), attr_n(info.not_before));
+        item.insert(
+            "DomainNames".to_string(),
+            AttributeValue {
+                ss: Some(domain_names.to_vec()),
+                ..Default::default()
+            },
+        );
+        item.insert("Serial".to_string(), attr_s(&info.serial));
+        item.insert("NotBefore".to_string(), attr_n(info.not_before));
+        item.insert("NotAfter".to_string(), attr_n(info.not_after));
+        item.insert("StoredAt".to_string(), attr_n(now_epoch_secs()));
+        item.insert("Certificate".to_string(), attr_s(&components.cert_pem));
+        item.insert("Chain".to_string(), attr_s(&components.chain_pem));
+        item.insert("FullChain".to_string(), attr_s(&components.fullchain_pem));
+        if self.allow_private_key {
+            item.insert("PrivateKey".to_string(), attr_s(&components.pkey_pem));
+        }
+        if let Some(alternate) = &components.alternate_chain {
+            item.insert("AlternateChain".to_string(), attr_s(&alternate.chain_pem));
+            item.insert("AlternateFullChain".to_string(), attr_s(&alternate.fullchain_pem));
+        }
+        item
+    }
+
+    /// Set ExpiresAt on every version older than `issued_at` that doesn't already have it. Returns the number of
+    /// versions updated.
+    async fn expire_old_versions(
+        &self,
+        ddb: &DynamoDbClient,
+        domain_name: &str,
+        issued_at: i64,
+        retention_days: i64,
+    ) -> Result<usize, LambdaError> {
+        let expires_at = now_epoch_secs() + retention_days * 86400;
+        let mut q_input = QueryInput {
+            table_name: self.table_name.clone(),
+            key_condition_expression: Some("DomainName = :d AND IssuedAt < :t".to_string()),
+            filter_expression: Some("attribute_not_exists(ExpiresAt)".to_string()),
+            projection_expression: Some("DomainName, IssuedAt".to_string()),
+            expression_attribute_values: Some(
+                vec![(":d".to_string(), attr_s(domain_name)), (":t".to_string(), attr_n(issued_at))]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        };
+
+        let mut n_expired = 0;
+        loop {
+            let response = match ddb.query(q_input.clone()).await {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("Failed to query old versions of {} in {}: {:#}", domain_name, self.table_name, e);
+                    return Err(Box::new(e));
+                }
+            };
+
+            for key in response.items.unwrap_or_default() {
+                let ui_input = UpdateItemInput {
+                    table_name: self.table_name.clone(),
+                    key,
+                    update_expression: Some("SET ExpiresAt = :e".to_string()),
+                    expression_attribute_values: Some(
+                        vec![(":e".to_string(), attr_n(expires_at))].into_iter().collect(),
+                    ),
+                    ..Default::default()
+                };
+
+                if let Err(e) = ddb.update_item(ui_input).await {
+                    error!("Failed to expire an old version of {} in {}: {:#}", domain_name, self.table_name, e);
+                    return Err(Box::new(e));
+                }
+                n_expired += 1;
+            }
+
+            match response.last_evaluated_key {
+                None => break,
+                Some(key) => q_input.exclusive_start_key = Some(key),
+            }
+        }
+
+        Ok(n_expired)
+    }
+}
+
+#[async_trait]
+impl CertificateStore for DynamoDbStorage {
+    fn type_name(&self) -> &'static str {
+        "DynamoDb"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn allows_private_key(&self) -> bool {
+        self.allow_private_key
+    }
+
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        if self.regions.is_empty() {
+            return Ok(None);
+        }
+
+        let regions = replica_regions(&self.regions, self.region.as_deref())?;
+        Ok(Some(
+            regions
+                .into_iter()
+                .map(|region| -> Box<dyn CertificateStore> {
+                    Box::new(DynamoDbStorage {
+                        region: Some(region),
+                        regions: vec![],
+                        ..self.clone()
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_regions(format!("Invalid region: {}", region)));
+            }
+        }
+
+        if self.table_name.is_empty() {
+            return Err(InvalidCertificateRequest::invalid_dynamodb_configuration("TableName cannot be empty"));
+        }
+
+        match self.retention_days {
+            Some(days) if days <= 0 => Err(InvalidCertificateRequest::invalid_dynamodb_configuration(format!(
+                "Invalid RetentionDays: {}",
+                days
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn planned_writes(&self, domain_names: &[String], _alternate_chain: bool) -> Vec<PlannedWrite> {
+        let region = self.dynamodb_region();
+        let mut writes = vec![PlannedWrite::in_region(
+            "dynamodb:PutItem",
+            format!("{} (DomainName {})", self.table_name, domain_names[0]),
+            &region,
+        )];
+        if self.retention_days.is_some() {
+            writes.push(PlannedWrite::in_region(
+                "dynamodb:UpdateItem",
+                format!("{} (previous versions of {})", self.table_name, domain_names[0]),
+                &region,
+            ));
+        }
+        writes
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let ddb = DynamoDbClient::new_with_client(self.assume_role.client()?, self.dynamodb_region());
+        let info = CertificateInfo::from_pem(&components.cert_pem)?;
+        let domain_name = domain_names[0].to_lowercase();
+        let item = self.certificate_item(&domain_names, &components, &info);
+
+        let pi_input = PutItemInput {
+            table_name: self.table_name.clone(),
+            item,
+            ..Default::default()
+        };
+
+        info!("Writing certificate {} for {} to DynamoDB table {}", info.serial, domain_name, self.table_name);
+        if let Err(e) = ddb.put_item(pi_input).await {
+            error!("Failed to write certificate to DynamoDB table {}: {:#}", self.table_name, e);
+            return Err(Box::new(e));
+        }
+
+        // The new version is safely written, so a failure to expire old versions only affects storage costs.
+        let expired_versions = match self.retention_days {
+            None => 0,
+            Some(days) => match self.expire_old_versions(&ddb, &domain_name, info.not_before, days).await {
+                Ok(n_expired) => n_expired,
+                Err(e) => {
+                    error!("Old versions of {} were not expired: {:#}", domain_name, e);
+                    0
+                }
+            },
+        };
+
+        Ok(vec![CertificateStorageResult::DynamoDb(DynamoDbStorageResult {
+            table_name: self.table_name.clone(),
+            domain_name,
+            issued_at: info.not_before,
+            expired_versions,
+        })])
+    }
+
+    /// Observe the latest version of the certificate in the table.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let ddb = DynamoDbClient::new_with_client(self.assume_role.client()?, self.dynamodb_region());
+        let domain_name = domain_names[0].to_lowercase();
+        let q_input = QueryInput {
+            table_name: self.table_name.clone(),
+            key_condition_expression: Some("DomainName = :d".to_string()),
+            expression_attribute_values: Some(vec![(":d".to_string(), attr_s(&domain_name))].into_iter().collect()),
+            scan_index_forward: Some(false),
+            limit: Some(1),
+            consistent_read: Some(true),
+            ..Default::default()
+        };
+
+        let item = match ddb.query(q_input).await {
+            Ok(response) => match response.items.and_then(|items| items.into_iter().next()) {
+                Some(item) => item,
+                None => return Ok(None),
+            },
+            Err(e) => {
+                error!("Failed to query {} in DynamoDB table {}: {:#}", domain_name, self.table_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let get_s = |name: &str| item.get(name).and_then(|v| v.s.clone());
+        let cert_pem = match get_s("Certificate") {
+            Some(cert_pem) => cert_pem,
+            None => return Ok(None),
+        };
+
+        let location = format!("dynamodb:{}/{}", self.table_name, domain_name);
+        let info = CertificateInfo::from_pem(&cert_pem)?;
+        let components = match (get_s("Chain"), get_s("FullChain"), get_s("PrivateKey")) {
+            (Some(chain_pem), Some(fullchain_pem), Some(pkey_pem)) if self.allow_private_key => {
+                Some(CertificateComponents {
+                    cert_pem,
+                    chain_pem,
+                    fullchain_pem,
+                    pkey_pem,
+                    alternate_chain: alternate_chain_from_parts(get_s("AlternateChain"), get_s("AlternateFullChain")),
+                })
+            }
+            (Some(_), Some(_), _) if !self.allow_private_key => None,
+            _ => return Ok(None),
+        };
+
+        Ok(Some(ObservedCertificate {
+            location,
+            info,
+            components,
+        }))
+    }
+}
+
+/// Configuration for writing a certificate to a local directory, e.g. an EFS access point mounted into the Lambda
+/// function, so EC2 instances and containers mounting the same file system pick up new certificates immediately.
+/// In JSON:
+///
+///     {
+///         // The type of storage to use. This must be "File".
+///         "Type": "File",
+///
+///         // The absolute path of the directory to write to. "{Domain}" is replaced with the first domain name of
+///         // the certificate. The directory is created if it doesn't exist.
+///         "Directory": str,
+///
+///         // The file name for each component. The PEM defaults match certbot's: "cert.pem", "chain.pem",
+///         // "fullchain.pem", "privkey.pem", "alt-chain.pem", and "alt-fullchain.pem". The other output formats
+///         // default to "cert.der", "privkey.der", "certificate.pfx", and "keystore.jks".
+///         "FileNames": {
+///             "Certificate": str,
+///             "Chain": str,
+///             "FullChain": str,
+///             "PrivateKey": str,
+///             "AlternateChain": str,
+///             "AlternateFullChain": str,
+///             "DerCertificate": str,
+///             "DerPrivateKey": str,
+///             "Pkcs12": str,
+///             "Jks": str,
+///         },
+///
+///         // The formats to write ("Pem", "Der", "Pkcs12", and/or "Jks"), the keystore password, and whether to
+///         // use legacy PKCS#12 encryption; see ArtifactFormats. The default is ["Pem"].
+///         "OutputFormats": [str, ...],
+///         "KeystorePassword": str,
+///         "Pkcs12Legacy": bool,
+///
+///         // The POSIX permissions, in octal, for the certificate and chain files (default "0644"), the private
+///         // key file (default "0600"), and any directories created (default "0755").
+///         "FileMode": str,
+///         "PrivateKeyMode": str,
+///         "DirectoryMode": str,
+///
+///         // The numeric user and group ids to own the files. If not specified, ownership is left as the
+///         // function's user (on EFS, the access point's user).
+///         "Uid": int,
+///         "Gid": int,
+///
+///         // If false, the private key is never written. The default is true.
+///         "AllowPrivateKey": bool,
+///     }
+///
+/// Each file is written to a temporary file in the same directory and renamed into place, so readers never see a
+/// partially written file. The DER private key, PKCS#12 bundle, and Java KeyStore get PrivateKeyMode.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct FileStorage {
+    #[serde(rename = "Directory")]
+    pub directory: String,
+
+    #[serde(rename = "FileNames", default)]
+    pub file_names: FileNames,
+
+    #[serde(rename = "FileMode", default = "default_file_mode")]
+    pub file_mode: String,
+
+    #[serde(rename = "PrivateKeyMode", default = "default_private_key_mode")]
+    pub private_key_mode: String,
+
+    #[serde(rename = "DirectoryMode", default = "default_directory_mode")]
+    pub directory_mode: String,
+
+    #[serde(rename = "Uid", default)]
+    pub uid: Option<u32>,
+
+    #[serde(rename = "Gid", default)]
+    pub gid: Option<u32>,
+
+    #[serde(rename = "AllowPrivateKey", default = "default_true")]
+    pub allow_private_key: bool,
+
+    #[serde(flatten)]
+    pub artifacts: ArtifactFormats,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+/// The file names for each certificate component in FileStorage.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct FileNames {
+    #[serde(rename = "Certificate", default = "default_cert_file_name")]
+    pub cert: String,
+
+    #[serde(rename = "Chain", default = "default_chain_file_name")]
+    pub chain: String,
+
+    #[serde(rename = "FullChain", default = "default_fullchain_file_name")]
+    pub fullchain: String,
+
+    #[serde(rename = "PrivateKey", default = "default_pkey_file_name")]
+    pub pkey: String,
+
+    #[serde(rename = "AlternateChain", default = "default_alternate_chain_file_name")]
+    pub alternate_chain: String,
+
+    #[serde(rename = "AlternateFullChain", default = "default_alternate_fullchain_file_name")]
+    pub alternate_fullchain: String,
+
+    #[serde(rename = "DerCertificate", default = "default_der_cert_file_name")]
+    pub der_cert: String,
+
+    #[serde(rename = "DerPrivateKey", default = "default_der_pkey_file_name")]
+    pub der_pkey: String,
+
+    #[serde(rename = "Pkcs12", default = "default_pkcs12_file_name")]
+    pub pkcs12: String,
+
+    #[serde(rename = "Jks", default = "default_jks_file_name")]
+    pub jks: String,
+}
+
+impl Default for FileNames {
+    fn default() -> Self {
+        Self {
+            cert: default_cert_file_name(),
+            chain: default_chain_file_name(),
+            fullchain: default_fullchain_file_name(),
+            pkey: default_pkey_file_name(),
+            alternate_chain: default_alternate_chain_file_name(),
+            alternate_fullchain: default_alternate_fullchain_file_name(),
+            der_cert: default_der_cert_file_name(),
+            der_pkey: default_der_pkey_file_name(),
+            pkcs12: default_pkcs12_file_name(),
+            jks: default_jks_file_name(),
+        }
+    }
+}
+
+impl FileNames {
+    fn all(&self) -> [&str; 10] {
+        [
+            &self.cert,
+            &self.chain,
+            &self.fullchain,
+            &self.pkey,
+            &self.alternate_chain,
+            &self.alternate_fullchain,
+            &self.der_cert,
+            &self.der_pkey,
+            &self.pkcs12,
+            &self.jks,
+        ]
+    }
+
+    fn artifact(&self, kind: ArtifactKind) -> &str {
+        match kind {
+            ArtifactKind::DerCertificate => &self.der_cert,
+            ArtifactKind::DerPrivateKey => &self.der_pkey,
+            ArtifactKind::Pkcs12 => &self.pkcs12,
+            ArtifactKind::Jks => &self.jks,
+        }
+    }
+}
+
+fn default_cert_file_name() -> String {
+    "cert.pem".to_string()
+}
+
+fn default_chain_file_name() -> String {
+    "chain.pem".to_string()
+}
+
+fn default_fullchain_file_name() -> String {
+    "fullchain.pem".to_string()
+}
+
+fn default_pkey_file_name() -> String {
+    "privkey.pem".to_string()
+}
+
+fn default_alternate_chain_file_name() -> String {
+    "alt-chain.pem".to_string()
+}
+
+fn default_alternate_fullchain_file_name() -> String {
+    "alt-fullchain.pem".to_string()
+}
+
+fn default_der_cert_file_name() -> String {
+    ArtifactKind::DerCertificate.default_name().to_string()
+}
+
+fn default_der_pkey_file_name() -> String {
+    ArtifactKind::DerPrivateKey.default_name().to_string()
+}
+
+fn default_pkcs12_file_name() -> String {
+    ArtifactKind::Pkcs12.default_name().to_string()
+}
+
+fn default_jks_file_name() -> String {
+    ArtifactKind::Jks.default_name().to_string()
+}
+
+fn default_file_mode() -> String {
+    "0644".to_string()
+}
+
+fn default_private_key_mode() -> String {
+    "0600".to_string()
+}
+
+fn default_directory_mode() -> String {
+    "0755".to_string()
+}
+
+/// Parse an octal POSIX permission string such as "0640".
+fn parse_file_mode(mode: &str) -> Option<u32> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o7777 => Some(mode),
+        _ => None,
+    }
+}
+
+impl FileStorage {
+    fn directory_for(&self, domain_name: &str) -> PathBuf {
+        PathBuf::from(self.directory.replace("{Domain}", &domain_name_for_path(domain_name)))
+    }
+
+    fn mode(mode: &str) -> u32 {
+        parse_file_mode(mode).expect("Mode should be validated here")
+    }
+
+    /// Write a file atomically: write a temporary file alongside it with the final permissions and ownership, then
+    /// rename it into place.
+    fn write_file(&self, directory: &Path, file_name: &str, data: &[u8], mode: u32) -> Result<String, LambdaError> {
+        let path = directory.join(file_name);
+        let temp_path = directory.join(format!(".{}.tmp", file_name));
+
+        let result = (|| -> Result<(), LambdaError> {
+            let mut file = OpenOptions::new().write(true).create(true).truncate(true).mode(mode).open(&temp_path)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+
+            // The mode passed to open() is subject to the umask, and doesn't apply if the file already existed.
+            set_permissions(&temp_path, Permissions::from_mode(mode))?;
+            if self.uid.is_some() || self.gid.is_some() {
+                chown(&temp_path, self.uid, self.gid)?;
+            }
+
+            rename(&temp_path, &path)?;
+            Ok(())
+        })();
+
+        match result {
+            Ok(()) => {
+                info!("Wrote {}", path.display());
+                Ok(path.to_string_lossy().into_owned())
+            }
+            Err(e) => {
+                error!("Failed to write {}: {:#}", path.display(), e);
+                remove_file(&temp_path).ok();
+                Err(e)
+            }
+        }
+    }
+
+    /// Read a file, returning None if it doesn't exist.
+    fn read_file(directory: &Path, file_name: &str) -> Result<Option<String>, LambdaError> {
+        match Self::read_file_bytes(directory, file_name)? {
+            Some(data) => Ok(Some(String::from_utf8(data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Read a binary file, returning None if it doesn't exist.
+    fn read_file_bytes(directory: &Path, file_name: &str) -> Result<Option<Vec<u8>>, LambdaError> {
+        let path = directory.join(file_name);
+        match read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => {
+                error!("Failed to read {}: {:#}", path.display(), e);
+                Err(Box::new(e))
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl CertificateStore for FileStorage {
+    fn type_name(&self) -> &'static str {
+        "File"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    fn allows_private_key(&self) -> bool {
+        self.allow_private_key
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        if !self.directory.starts_with('/') {
+            return Err(InvalidCertificateRequest::invalid_file_configuration(format!(
+                "Directory must be an absolute path: {}",
+                self.directory
+            )));
+        }
+
+        if self.assume_role.role_arn.is_some() {
+            return Err(InvalidCertificateRequest::invalid_file_configuration(
+                "RoleArn cannot be used with File storage",
+            ));
+        }
+
+        for (name, mode) in [
+            ("FileMode", &self.file_mode),
+            ("PrivateKeyMode", &self.private_key_mode),
+            ("DirectoryMode", &self.directory_mode),
+        ] {
+            if parse_file_mode(mode).is_none() {
+                return Err(InvalidCertificateRequest::invalid_file_configuration(format!(
+                    "Invalid {}: {}",
+                    name, mode
+                )));
+            }
+        }
+
+        let mut file_names: Vec<&str> = self.file_names.all().to_vec();
+        if file_names.iter().any(|name| name.is_empty() || name.contains('/') || *name == "." || *name == "..") {
+            return Err(InvalidCertificateRequest::invalid_file_configuration(
+                "FileNames must be non-empty and cannot contain directories",
+            ));
+        }
+
+        file_names.sort_unstable();
+        file_names.dedup();
+        if file_names.len() != self.file_names.all().len() {
+            return Err(InvalidCertificateRequest::invalid_file_configuration("FileNames must be distinct"));
+        }
+
+        self.artifacts.validate(self.allow_private_key)
+    }
+
+    fn planned_writes(&self, domain_names: &[String], alternate_chain: bool) -> Vec<PlannedWrite> {
+        let directory = self.directory_for(&domain_names[0]);
+        let names = &self.file_names;
+        let mut files: Vec<&str> = Vec::new();
+        if self.artifacts.writes_pem() {
+            files.extend(&[names.cert.as_str(), names.chain.as_str(), names.fullchain.as_str()]);
+            if self.allow_private_key {
+                files.push(&names.pkey);
+            }
+            if alternate_chain {
+                files.extend(&[names.alternate_chain.as_str(), names.alternate_fullchain.as_str()]);
+            }
+        }
+
+        for kind in self.artifacts.kinds(self.allow_private_key) {
+            files.push(names.artifact(kind));
+        }
+
+        files
+            .into_iter()
+            .map(|name| PlannedWrite::new("file:Write", directory.join(name).to_string_lossy().into_owned()))
+            .collect()
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let directory = self.directory_for(&domain_names[0]);
+        if let Err(e) = DirBuilder::new().recursive(true).mode(Self::mode(&self.directory_mode)).create(&directory) {
+            error!("Failed to create directory {}: {:#}", directory.display(), e);
+            return Err(Box::new(e));
+        }
+
+        let file_mode = Self::mode(&self.file_mode);
+        let private_key_mode = Self::mode(&self.private_key_mode);
+        let names = &self.file_names;
+        let pem = self.artifacts.writes_pem();
+
+        // Write the key first so the certificate is never visible without its matching key, and the certificate
+        // after its chains so a reader watching the certificate file sees a complete set.
+        let pkey = if self.allow_private_key && pem {
+            Some(self.write_file(&directory, &names.pkey, components.pkey_pem.as_bytes(), private_key_mode)?)
+        } else {
+            None
+        };
+
+        let mut artifacts = Vec::new();
+        for artifact in self.artifacts.build(&components, &domain_names[0], self.allow_private_key).await? {
+            let mode = if artifact.kind.is_private() {
+                private_key_mode
+            } else {
+                file_mode
+            };
+            artifacts.push(self.write_file(&directory, names.artifact(artifact.kind), &artifact.data, mode)?);
+        }
+
+        let (alternate_chain, alternate_fullchain) = match &components.alternate_chain {
+            Some(alternate) if pem => (
+                Some(self.write_file(&directory, &names.alternate_chain, alternate.chain_pem.as_bytes(), file_mode)?),
+                Some(self.write_file(
+                    &directory,
+                    &names.alternate_fullchain,
+                    alternate.fullchain_pem.as_bytes(),
+                    file_mode,
+                )?),
+            ),
+            _ => (None, None),
+        };
+
+        let (certificate, chain, fullchain) = if pem {
+            let chain = self.write_file(&directory, &names.chain, components.chain_pem.as_bytes(), file_mode)?;
+            let fullchain =
+                self.write_file(&directory, &names.fullchain, components.fullchain_pem.as_bytes(), file_mode)?;
+            let certificate = self.write_file(&directory, &names.cert, components.cert_pem.as_bytes(), file_mode)?;
+            (Some(certificate), Some(chain), Some(fullchain))
+        } else {
+            (None, None, None)
+        };
+
+        Ok(vec![CertificateStorageResult::File(FileStorageResult {
+            directory: directory.to_string_lossy().into_owned(),
+            certificate,
+            chain,
+            fullchain,
+            pkey,
+            artifacts,
+            alternate_chain,
+            alternate_fullchain,
+        })])
+    }
+
+    /// Observe the certificate currently in the directory. The files of the other output formats must be present as
+    /// well.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let directory = self.directory_for(&domain_names[0]);
+        let names = &self.file_names;
+
+        let mut artifacts = Vec::new();
+        for kind in self.artifacts.kinds(self.allow_private_key) {
+            match Self::read_file_bytes(&directory, names.artifact(kind))? {
+                Some(data) => artifacts.push(Artifact {
+                    kind,
+                    data,
+                }),
+                None => return Ok(None),
+            }
+        }
+
+        if let Some(primary) = self.artifacts.primary() {
+            let location = directory.join(names.artifact(primary)).to_string_lossy().into_owned();
+            return self.artifacts.observe(location, &artifacts).await;
+        }
+
+        let cert = Self::read_file(&directory, &names.cert)?;
+        let chain = Self::read_file(&directory, &names.chain)?;
+        let fullchain = Self::read_file(&directory, &names.fullchain)?;
+        let pkey = if self.allow_private_key {
+            Self::read_file(&directory, &names.pkey)?
+        } else {
+            None
+        };
+        let location = directory.join(&names.cert).to_string_lossy().into_owned();
+
+        match (cert, chain, fullchain, pkey) {
+            (Some(cert_pem), Some(chain_pem), Some(fullchain_pem), Some(pkey_pem)) => Ok(Some(ObservedCertificate {
+                location,
+                info: CertificateInfo::from_pem(&cert_pem)?,
+                components: Some(CertificateComponents {
+                    cert_pem,
+                    chain_pem,
+                    fullchain_pem,
+                    pkey_pem,
+                    alternate_chain: alternate_chain_from_parts(
+                        Self::read_file(&directory, &names.alternate_chain)?,
+                        Self::read_file(&directory, &names.alternate_fullchain)?,
+                    ),
+                }),
+            })),
+            (Some(cert_pem), Some(_), Some(_), None) if !self.allow_private_key => Ok(Some(ObservedCertificate {
+                location,
+                info: CertificateInfo::from_pem(&cert_pem)?,
+                components: None,
+            })),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Configuration for deploying a certificate to the machine certificate store of EC2 Windows instances through SSM
+/// Run Command, optionally rebinding IIS sites to it. In JSON:
+///
+///     {
+///         // The type of storage to use. This must be "Windows".
+///         "Type": "Windows",
+///
+///         // The instances to deploy to, either by id (at most 50) or by Run Command targets such as
+///         // {"Key": "tag:Role", "Values": ["web"]}. Exactly one of these must be specified.
+///         "InstanceIds": [str, ...],
+///         "Targets": [{"Key": str, "Values": [str, ...]}, ...],
+///
+///         // The SecureString parameter used to hand the PKCS#12 bundle to the instances. "{Domain}" is replaced
+///         // with the first domain name of the certificate. This defaults to "/Certificate/Windows/{Domain}".
+///         "ParameterName": str,
+///
+///         // The KMS key used to encrypt the parameter. If not specified, the default "aws/ssm" key is used.
+///         "KmsKeyId": str,
+///
+///         // The LocalMachine store to import into: "My" (the default) or "WebHosting".
+///         "StoreName": str,
+///
+///         // IIS https bindings to point at the new certificate. A binding is created if it doesn't exist. Port
+///         // defaults to 443; if HostHeader is specified, the binding uses SNI.
+///         "IisBindings": [{"Site": str, "Port": int, "HostHeader": str}, ...],
+///
+///         // How long to wait for the instances to finish, in seconds. The default is 300.
+///         "TimeoutSeconds": int,
+///
+///         // The region of the instances and the parameter. If not specified, the current region is used.
+///         "Region": str,
+///     }
+///
+/// The private key is never placed in the command itself, where it would be visible in the Run Command history.
+/// Instead, the bundle is written to the parameter and each instance fetches it with Get-SSMParameter, so the
+/// instance profile needs ssm:GetParameter on the parameter (and kms:Decrypt on its key), and the instances need the
+/// AWS Tools for PowerShell (included in the Amazon Windows AMIs). Once every instance succeeds, the parameter
+/// version is labeled "Deployed"; observe() uses this label, so a target with any failed instance is redeployed on
+/// the next run.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct WindowsStorage {
+    #[serde(rename = "InstanceIds", default)]
+    pub instance_ids: Vec<String>,
+
+    #[serde(rename = "Targets", default)]
+    pub targets: Vec<RunCommandTarget>,
+
+    #[serde(rename = "ParameterName", default = "default_windows_parameter_name")]
+    pub parameter_name: String,
+
+    #[serde(rename = "KmsKeyId", default)]
+    pub kms_key_id: Option<String>,
+
+    #[serde(rename = "StoreName", default = "default_windows_store_name")]
+    pub store_name: String,
+
+    #[serde(rename = "IisBindings", default)]
+    pub iis_bindings: Vec<IisBinding>,
+
+    #[serde(rename = "TimeoutSeconds", default = "default_windows_timeout_seconds")]
+    pub timeout_seconds: u64,
+
+    #[serde(rename = "Region", default)]
+    pub region: Option<String>,
+
+    #[serde(flatten)]
+    pub assume_role: AssumeRole,
+}
+
+/// A Run Command target, e.g. {"Key": "tag:Role", "Values": ["web"]}, for WindowsStorage and RunCommandHook.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct RunCommandTarget {
+    #[serde(rename = "Key")]
+    pub key: String,
+
+    #[serde(rename = "Values", deserialize_with = "string_or_vec")]
+    #[schemars(schema_with = "string_or_vec_schema")]
+    pub values: Vec<String>,
+}
+
+/// An IIS https binding for WindowsStorage.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct IisBinding {
+    #[serde(rename = "Site")]
+    pub site: String,
+
+    #[serde(rename = "Port", default = "default_https_port")]
+    pub port: u16,
+
+    #[serde(rename = "HostHeader", default)]
+    pub host_header: Option<String>,
+}
+
+fn default_windows_parameter_name() -> String {
+    WINDOWS_DEFAULT_PARAMETER_NAME.to_string()
+}
+
+fn default_windows_store_name() -> String {
+    WINDOWS_STORE_MY.to_string()
+}
+
+fn default_windows_timeout_seconds() -> u64 {
+    RUN_COMMAND_DEFAULT_TIMEOUT_SECONDS
+}
+
+fn default_https_port() -> u16 {
+    443
+}
+
+/// The value of the parameter handed to Windows instances.
+#[derive(Debug, Deserialize, Serialize)]
+struct WindowsBundle {
+    #[serde(rename = "Pfx")]
+    pfx: String,
+
+    #[serde(rename = "Password")]
+    password: String,
+}
+
+/// Quote a string for PowerShell.
+fn powershell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+impl WindowsStorage {
+    fn ssm_region(&self) -> Region {
+        match &self.region {
+            Some(region) => Region::from_str(region).expect("Region should be validated here"),
+            None => Region::default(),
+        }
+    }
+
+    fn ssm_client(&self) -> Result<SsmClient, LambdaError> {
+        Ok(SsmClient::new_with_client(self.assume_role.client()?, self.ssm_region()))
+    }
+
+    fn get_parameter_name(&self, domain_name: &str) -> String {
+        self.parameter_name.replace("{Domain}", &domain_name_for_path(domain_name))
+    }
+
+    /// Build a PKCS#12 bundle of the key, certificate, and chain protected by a random password. The legacy 3DES
+    /// algorithm is used so that Windows Server 2016 and earlier can import it.
+    fn build_bundle(components: &CertificateComponents, friendly_name: &str) -> Result<WindowsBundle, LambdaError> {
+        let mut password = [0u8; 24];
+        rand_bytes(&mut password)?;
+        let password = base64::encode(password);
+        let pfx = build_pkcs12(components, friendly_name, &password, true)?;
+
+        Ok(WindowsBundle {
+            pfx: base64::encode(pfx),
+            password,
+        })
+    }
+
+    /// The PowerShell script run on each instance.
+    fn script(&self, param_name: &str, thumbprint: &str) -> String {
+        let store = powershell_quote(&format!("Cert:\\LocalMachine\\{}", self.store_name));
+        let mut lines = vec![
+            "$ErrorActionPreference = 'Stop'".to_string(),
+            format!(
+                "$bundle = (Get-SSMParameter -Name {} -WithDecryption $true -Region {}).Value | ConvertFrom-Json",
+                powershell_quote(param_name),
+                powershell_quote(self.ssm_region().name())
+            ),
+            "$path = Join-Path $env:TEMP ([guid]::NewGuid().ToString() + '.pfx')".to_string(),
+            "[IO.File]::WriteAllBytes($path, [Convert]::FromBase64String($bundle.Pfx))".to_string(),
+            "try {".to_string(),
+            "    $password = ConvertTo-SecureString -String $bundle.Password -AsPlainText -Force".to_string(),
+            format!(
+                "    Import-PfxCertificate -FilePath $path -CertStoreLocation {} -Password $password | Out-Null",
+                store
+            ),
+            "} finally {".to_string(),
+            "    Remove-Item -Force $path".to_string(),
+            "}".to_string(),
+            format!(
+                "if (-not (Test-Path (Join-Path {} {}))) {{ throw 'Certificate was not imported' }}",
+                store,
+                powershell_quote(thumbprint)
+            ),
+            format!("Write-Output {}", powershell_quote(&format!("Imported {}", thumbprint))),
+        ];
+
+        if !self.iis_bindings.is_empty() {
+            lines.push("Import-Module WebAdministration".to_string());
+        }
+
+        for binding in &self.iis_bindings {
+            let host_header = binding.host_header.as_deref().unwrap_or_default();
+            let ssl_flags = if host_header.is_empty() {
+                0
+            } else {
+                1
+            };
+            let selector = format!(
+                "-Name {} -Protocol https -Port {} -HostHeader {}",
+                powershell_quote(&binding.site),
+                binding.port,
+                powershell_quote(host_header)
+            );
+
+            lines.push(format!(
+                "if (-not (Get-WebBinding {0})) {{ New-WebBinding {0} -SslFlags {1} }}",
+                selector, ssl_flags
+            ));
+            lines.push(format!(
+                "(Get-WebBinding {}).AddSslCertificate({}, {})",
+                selector,
+                powershell_quote(thumbprint),
+                powershell_quote(&self.store_name)
+            ));
+            lines.push(format!(
+                "Write-Output {}",
+                powershell_quote(&format!("Bound {}:{}:{}", binding.site, binding.port, host_header))
+            ));
+        }
+
+        lines.join("\n")
+    }
+}
+
+/// Wait up to `timeout` for a Run Command command to finish on every instance, returning the per-instance results.
+pub async fn wait_for_command(
+    ssm: &SsmClient,
+    command_id: &str,
+    timeout: Duration,
+) -> Result<Vec<WindowsInstanceResult>, LambdaError> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let lc_request = ListCommandsRequest {
+            command_id: Some(command_id.to_string()),
+            ..Default::default()
+        };
+
+        let status = match ssm.list_commands(lc_request).await {
+            Ok(response) => response.commands.unwrap_or_default().into_iter().next().and_then(|command| command.status),
+            Err(e) => {
+                error!("Failed to get the status of command {}: {:#}", command_id, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        match status.as_deref() {
+            Some("Pending") | Some("InProgress") | Some("Cancelling") | None => (),
+            _ => break,
+        }
+
+        if Instant::now() >= deadline {
+            warn!("Timed out waiting for command {}", command_id);
+            break;
+        }
+
+        sleep(Duration::from_secs(RUN_COMMAND_POLL_SECONDS)).await;
+    }
+
+    let mut lci_request = ListCommandInvocationsRequest {
+        command_id: Some(command_id.to_string()),
+        details: Some(true),
+        ..Default::default()
+    };
+    let mut results = Vec::new();
+
+    loop {
+        match ssm.list_command_invocations(lci_request.clone()).await {
+            Ok(response) => {
+                for invocation in response.command_invocations.unwrap_or_default() {
+                    let output = invocation
+                        .command_plugins
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|plugin| plugin.output)
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    results.push(WindowsInstanceResult {
+                        instance_id: invocation.instance_id.unwrap_or_default(),
+                        status: invocation.status.unwrap_or_default(),
+                        output: if output.is_empty() {
+                            None
+                        } else {
+                            Some(output)
+                        },
+                    });
+                }
+
+                match response.next_token {
+                    None => break,
+                    Some(token) => lci_request.next_token = Some(token),
+                }
+            }
+            Err(e) => {
+                error!("Failed to list invocations of command {}: {:#}", command_id, e);
+                return Err(Box::new(e));
+            }
+        }
+    }
+
+    Ok(results)
+}
+
+#[async_trait]
+impl CertificateStore for WindowsStorage {
+    fn type_name(&self) -> &'static str {
+        "Windows"
+    }
+
+    fn assume_role(&self) -> &AssumeRole {
+        &self.assume_role
+    }
+
+    async fn validate(&mut self) -> Result<(), LambdaError> {
+        match (self.instance_ids.is_empty(), self.targets.is_empty()) {
+            (true, true) | (false, false) => {
+                return Err(InvalidCertificateRequest::invalid_windows_configuration(
+                    "Exactly one of InstanceIds or Targets must be specified",
+                ))
+            }
+            _ => (),
+        }
+
+        if self.instance_ids.len() > WINDOWS_MAX_INSTANCE_IDS {
+            return Err(InvalidCertificateRequest::invalid_windows_configuration(format!(
+                "At most {} InstanceIds can be specified; use Targets instead",
+                WINDOWS_MAX_INSTANCE_IDS
+            )));
+        }
+
+        if self.targets.iter().any(|target| target.key.is_empty() || target.values.is_empty()) {
+            return Err(InvalidCertificateRequest::invalid_windows_configuration(
+                "Each target must have a Key and at least one value",
+            ));
+        }
+
+        if validate_and_sanitize_ssm_parameter_path(&self.get_parameter_name("example.com")).is_none() {
+            return Err(InvalidCertificateRequest::invalid_windows_configuration(format!(
+                "Invalid ParameterName: {}",
+                self.parameter_name
+            )));
+        }
+
+        if self.store_name != WINDOWS_STORE_MY && self.store_name != WINDOWS_STORE_WEB_HOSTING {
+            return Err(InvalidCertificateRequest::invalid_windows_configuration(format!(
+                "StoreName must be {} or {}: {}",
+                WINDOWS_STORE_MY, WINDOWS_STORE_WEB_HOSTING, self.store_name
+            )));
+        }
+
+        if self.iis_bindings.iter().any(|binding| binding.site.is_empty() || binding.port == 0) {
+            return Err(InvalidCertificateRequest::invalid_windows_configuration(
+                "Each IIS binding must have a Site and a non-zero Port",
+            ));
+        }
+
+        if self.timeout_seconds == 0 || self.timeout_seconds > RUN_COMMAND_MAX_TIMEOUT_SECONDS {
+            return Err(InvalidCertificateRequest::invalid_windows_configuration(format!(
+                "TimeoutSeconds must be between 1 and {}",
+                RUN_COMMAND_MAX_TIMEOUT_SECONDS
+            )));
+        }
+
+        if let Some(region) = &self.region {
+            if Region::from_str(region).is_err() {
+                return Err(InvalidCertificateRequest::invalid_windows_configuration(format!(
+                    "Invalid region: {}",
+                    region
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn planned_writes(&self, domain_names: &[String], _alternate_chain: bool) -> Vec<PlannedWrite> {
+        let region = self.ssm_region();
+        let param_name = self.get_parameter_name(&domain_names[0]);
+        let mut writes = vec![PlannedWrite::in_region("ssm:PutParameter", param_name.as_str(), &region)];
+        for instance_id in &self.instance_ids {
+            writes.push(PlannedWrite::in_region("ssm:SendCommand", instance_id.as_str(), &region));
+        }
+        for target in &self.targets {
+            let resource = format!("Key={},Values={}", target.key, target.values.join(","));
+            writes.push(PlannedWrite::in_region("ssm:SendCommand", resource, &region));
+        }
+        writes.push(PlannedWrite::in_region("ssm:LabelParameterVersion", param_name, &region));
+        writes
+    }
+
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+        let ssm = self.ssm_client()?;
+        let param_name = self.get_parameter_name(&domain_names[0]);
+        let digest = X509::from_pem(components.cert_pem.as_bytes())?.digest(MessageDigest::sha1())?;
+        let thumbprint: String = digest.iter().map(|b| format!("{:02X}", b)).collect();
+
+        let bundle = serde_json::to_string(&Self::build_bundle(&components, &domain_names[0])?)?;
+        check_ssm_value_sizes(vec![("Bundle", bundle.len())])?;
+
+        let pp_request = PutParameterRequest {
+            name: param_name.clone(),
+            value: bundle,
+            type_: Some(SSM_TYPE_SECURE_STRING.to_string()),
+            key_id: self.kms_key_id.clone(),
+            overwrite: Some(true),
+            tier: Some(SSM_TIER_INTELLIGENT_TIERING.to_string()),
+            ..Default::default()
+        };
+
+        let version = match ssm.put_parameter(pp_request).await {
+            Ok(response) => response.version,
+            Err(e) => {
+                error!("Failed to write parameter {}: {:#}", param_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let mut parameters = HashMap::new();
+        parameters.insert("commands".to_string(), vec![self.script(&param_name, &thumbprint)]);
+        parameters.insert("executionTimeout".to_string(), vec![self.timeout_seconds.to_string()]);
+
+        let sc_request = SendCommandRequest {
+            document_name: WINDOWS_RUN_COMMAND_DOCUMENT.to_string(),
+            instance_ids: if self.instance_ids.is_empty() {
+                None
+            } else {
+                Some(self.instance_ids.clone())
+            },
+            targets: if self.targets.is_empty() {
+                None
+            } else {
+                Some(
+                    self.targets
+                        .iter()
+                        .map(|target| Target {
+                            key: Some(target.key.clone()),
+                            values: Some(target.values.clone()),
+                        })
+                        .collect(),
+                )
+            },
+            parameters: Some(parameters),
+            comment: Some(format!("Import certificate {}", thumbprint)),
+            ..Default::default()
+        };
+
+        let command_id = match ssm.send_command(sc_request).await {
+            Ok(response) => response.command.and_then(|command| command.command_id).unwrap_or_default(),
+            Err(e) => {
+                error!("Failed to send the certificate import command for {}: {:#}", param_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        info!("Sent command {} to import certificate {}", command_id, thumbprint);
+        let instances = wait_for_command(&ssm, &command_id, Duration::from_secs(self.timeout_seconds)).await?;
+        let failed: Vec<String> = instances
+            .iter()
+            .filter(|instance| instance.status != "Success")
+            .map(|instance| format!("{} ({})", instance.instance_id, instance.status))
+            .collect();
+
+        for instance in &instances {
+            if instance.status == "Success" {
+                info!("Imported certificate {} on {}", thumbprint, instance.instance_id);
+            } else {
+                error!(
+                    "Failed to import certificate {} on {}: {}: {}",
+                    thumbprint,
+                    instance.instance_id,
+                    instance.status,
+                    instance.output.as_deref().unwrap_or_default()
+                );
+            }
+        }
+
+        let mut results = vec![CertificateStorageResult::Windows(WindowsStorageResult {
+            parameter_name: param_name.clone(),
+            command_id: command_id.clone(),
+            thumbprint,
+            instances: instances.clone(),
+        })];
+
+        if instances.is_empty() {
+            results
+                .push(CertificateStorageResult::Error(format!("Command {} did not run on any instances", command_id)));
+        } else if !failed.is_empty() {
+            results.push(CertificateStorageResult::Error(format!(
+                "Failed to import the certificate on {}",
+                failed.join(", ")
+            )));
+        } else {
+            let lpv_request = LabelParameterVersionRequest {
+                name: param_name.clone(),
+                parameter_version: version,
+                labels: vec![WINDOWS_DEPLOYED_LABEL.to_string()],
+            };
+
+            if let Err(e) = ssm.label_parameter_version(lpv_request).await {
+                // The certificate was deployed; the next run will just redeploy it.
+                warn!("Failed to label parameter {}: {:#}", param_name, e);
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Observe the certificate last deployed to every instance, as recorded by the parameter's "Deployed" label.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+        let ssm = self.ssm_client()?;
+        let param_name = self.get_parameter_name(&domain_names[0]);
+        let gp_request = GetParameterRequest {
+            name: format!("{}:{}", param_name, WINDOWS_DEPLOYED_LABEL),
+            with_decryption: Some(true),
+        };
+
+        let value = match ssm.get_parameter(gp_request).await {
+            Ok(response) => match response.parameter.and_then(|p| p.value) {
+                Some(value) => value,
+                None => return Ok(None),
+            },
+            Err(RusotoError::Service(GetParameterError::ParameterNotFound(_)))
+            | Err(RusotoError::Service(GetParameterError::ParameterVersionNotFound(_))) => return Ok(None),
+            Err(e) => {
+                error!("Failed to read parameter {}: {:#}", param_name, e);
+                return Err(Box::new(e));
+            }
+        };
+
+        let bundle: WindowsBundle = serde_json::from_str(&value)?;
+        let pfx = Pkcs12::from_der(&base64::decode(&bundle.pfx)?)?.parse(&bundle.password)?;
+
+        Ok(Some(ObservedCertificate {
+            location: param_name,
+            info: CertificateInfo::from_pem(&String::from_utf8(pfx.cert.to_pem()?)?)?,
+            components: None,
+        }))
+    }
+}
+
+/// Assemble the alternate chain from its stored parts. Both parts must be present.
+fn alternate_chain_from_parts(chain_pem: Option<String>, fullchain_pem: Option<String>) -> Option<CertificateChain> {
+    match (chain_pem, fullchain_pem) {
+        (Some(chain_pem), Some(fullchain_pem)) => Some(CertificateChain {
+            chain_pem,
+            fullchain_pem,
+        }),
+        _ => None,
+    }
+}
+
+/// Read an S3 object as a UTF-8 string, returning None if the object does not exist.
+pub async fn get_s3_object_string(
+    s3_client: &S3Client,
+    bucket: &str,
+    key: String,
+) -> Result<Option<String>, LambdaError> {
+    match get_s3_object_bytes(s3_client, bucket, key).await? {
+        Some(data) => Ok(Some(String::from_utf8(data)?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn get_s3_object_bytes(
+    s3_client: &S3Client,
+    bucket: &str,
+    key: String,
+) -> Result<Option<Vec<u8>>, LambdaError> {
+    let go_request = GetObjectRequest {
+        bucket: bucket.to_string(),
+        key: key.clone(),
+        ..Default::default()
+    };
+
+    match s3_client.get_object(go_request).await {
+        Ok(response) => match response.body {
+            None => Ok(None),
+            Some(body) => {
+                let data: Vec<u8> = body.map_ok(|b| b.to_vec()).try_concat().await?;
+                Ok(Some(data))
+            }
+        },
+        Err(RusotoError::Service(GetObjectError::NoSuchKey(_))) => Ok(None),
+        Err(e) => {
+            error!("Failed to read s3://{}/{}: {}", bucket, key, e);
+            Err(Box::new(e))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+#[serde(tag = "Type")]
+pub enum CertificateStorageResult {
+    Acm(AcmStorageResult),
+    ApiGateway(ApiGatewayStorageResult),
+    CloudFront(CloudFrontStorageResult),
+    DynamoDb(DynamoDbStorageResult),
+    File(FileStorageResult),
+    IamServerCertificate(IamServerCertificateStorageResult),
+    Kubernetes(KubernetesStorageResult),
+    LoadBalancer(LoadBalancerStorageResult),
+    S3(S3StorageResult),
+    SecretsManager(SecretsManagerStorageResult),
+    SsmParameter(SsmParameterStorageResult),
+    Windows(WindowsStorageResult),
+    Error(String),
+}
+
+impl CertificateStorageResult {
+    /// Returns the ARNs of the AWS resources written, if known.
+    pub fn arns(&self) -> Vec<String> {
+        match self {
+            Self::Acm(result) => vec![result.certificate_arn.clone()],
+            Self::ApiGateway(result) => vec![result.certificate_arn.clone()],
+            Self::CloudFront(result) => vec![result.certificate_arn.clone()],
+            Self::IamServerCertificate(result) => vec![result.arn.clone()],
+            Self::LoadBalancer(result) => vec![result.certificate_arn.clone()],
+            Self::DynamoDb(_)
+            | Self::File(_)
+            | Self::Kubernetes(_)
+            | Self::S3(_)
+            | Self::Windows(_)
+            | Self::Error(_) => vec![],
+            Self::SecretsManager(result) => result.secrets.iter().map(|secret| secret.secret_arn.clone()).collect(),
+            Self::SsmParameter(result) => {
+                let mut arns: Vec<String> = result.bundle_arn.iter().cloned().collect();
+                arns.extend(result.cert_arn.iter().cloned());
+                arns.extend(result.chain_arn.iter().cloned());
+                arns.extend(result.fullchain_arn.iter().cloned());
+                arns.extend(result.pkey_arn.iter().cloned());
+                arns.extend(result.alternate_chain_arn.iter().cloned());
+                arns.extend(result.alternate_fullchain_arn.iter().cloned());
+                arns
+            }
+        }
+    }
+
+    /// Indicates whether a deployment was rolled back because it failed verification.
+    pub fn rolled_back(&self) -> bool {
+        match self {
+            Self::CloudFront(result) => result.distributions.iter().any(|distribution| distribution.rolled_back),
+            Self::LoadBalancer(result) => result.listeners.iter().any(|listener| listener.rolled_back),
+            _ => false,
+        }
+    }
+
+    /// Returns the number of objects, parameters, secrets, or other resources written.
+    pub fn writes(&self) -> u32 {
+        match self {
+            Self::Error(_) => 0,
+            Self::S3(result) => {
+                [
+                    &result.certificate,
+                    &result.chain,
+                    &result.fullchain,
+                    &result.pkey,
+                    &result.bundle,
+                    &result.alternate_chain,
+                    &result.alternate_fullchain,
+                ]
+                .iter()
+                .filter(|key| key.is_some())
+                .count() as u32
+                    + result.artifacts.len() as u32
+                    + result.archived_keys.len() as u32
+            }
+            Self::SecretsManager(_) | Self::SsmParameter(_) => self.arns().len() as u32,
+            _ => 1,
+        }
+    }
+
+    /// Returns the number of SSM parameters written in the Advanced tier.
+    pub fn advanced_parameters(&self) -> u32 {
+        match self {
+            Self::SsmParameter(result) => result.advanced_parameters.len() as u32,
+            _ => 0,
+        }
+    }
+
+    /// Returns an estimate of the KMS requests made to encrypt what was written: one for each SSE-KMS object,
+    /// SecureString parameter, and secret.
+    pub fn kms_calls(&self) -> u32 {
+        match self {
+            Self::S3(result) => result.kms_encrypted_objects,
+            Self::SecretsManager(result) => result.secrets.len() as u32,
+            Self::SsmParameter(result) => result.bundle_param.iter().chain(result.pkey_param.iter()).count() as u32,
+            Self::Windows(_) => 1,
+            _ => 0,
+        }
+    }
+
+    /// Returns the versions of the resources written, keyed by resource name or ARN, for resources that are
+    /// versioned in place (Secrets Manager secrets and SSM parameters).
+    pub fn versions(&self) -> Vec<(String, String)> {
+        match self {
+            Self::SecretsManager(result) => result
+                .secrets
+                .iter()
+                .filter_map(|secret| secret.version_id.clone().map(|version| (secret.secret_arn.clone(), version)))
+                .collect(),
+            Self::SsmParameter(result) => {
+                result.parameter_versions.iter().map(|(name, version)| (name.clone(), version.to_string())).collect()
+            }
+            _ => vec![],
+        }
+    }
+}
+
+/// The results of storing a certificate in ACM. In JSON:
+///
+///     {
+///         // The type of storage. Always "Acm".
+///         "Type": "Acm",
+///
+///         // The ARN of the certificate.
+///         "CertificateArn": str,
+///
+///         // If the certificate was reimported over an existing one, the certificate that was replaced and the
+///         // certificate that replaced it. See CertificateDiff.
+///         "Diff": { ... },
+///
+///         // If the certificate was reimported as part of a canary (see CanaryRollout), the result of probing the
+///         // canary endpoints.
+///         "CanaryVerification": { ... },
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct AcmStorageResult {
+    #[serde(rename = "CertificateArn")]
+    pub certificate_arn: String,
+
+    #[serde(rename = "Diff", default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<CertificateDiff>,
+
+    #[serde(rename = "CanaryVerification", default, skip_serializing_if = "Option::is_none")]
+    pub canary_verification: Option<VerificationResult>,
+}
+
+/// The results of deploying a certificate to API Gateway custom domain names. In JSON:
+///
+///     {
+///         // The type of storage. Always "ApiGateway".
+///         "Type": "ApiGateway",
+///
+///         // The ARN of the certificate in ACM.
+///         "CertificateArn": str,
+///
+///         // The endpoint type of the custom domain names: "EDGE" or "REGIONAL".
+///         "EndpointType": str,
+///
+///         // The custom domain names that are using the certificate.
+///         "DomainNames": [
+///             {
+///                 // The custom domain name.
+///                 "DomainName": str,
+///
+///                 // Whether the custom domain name was updated. This is false if it was already using the
+///                 // certificate ARN (e.g. because the certificate was reimported).
+///                 "Updated": bool,
+///             }
+///         ]
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ApiGatewayStorageResult {
+    #[serde(rename = "CertificateArn")]
+    pub certificate_arn: String,
+
+    #[serde(rename = "EndpointType")]
+    pub endpoint_type: String,
+
+    #[serde(rename = "DomainNames")]
+    pub domain_names: Vec<ApiGatewayDomainNameResult>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ApiGatewayDomainNameResult {
+    #[serde(rename = "DomainName")]
+    pub domain_name: String,
+
+    #[serde(rename = "Updated")]
+    pub updated: bool,
+}
+
+/// The results of deploying a certificate to CloudFront. In JSON:
+///
+///     {
+///         // The type of storage. Always "CloudFront".
+///         "Type": "CloudFront",
+///
+///         // The ARN of the certificate in ACM (us-east-1).
+///         "CertificateArn": str,
+///
+///         // The distributions that are using the certificate.
+///         "Distributions": [
+///             {
+///                 // The id of the distribution.
+///                 "DistributionId": str,
+///
+///                 // Whether the distribution was updated. This is false if it was already using the
+///                 // certificate ARN (e.g. because the certificate was reimported).
+///                 "Updated": bool,
+///
+///                 // If the distribution was updated, the ACM certificate it used before, if any.
+///                 "PreviousCertificateArn": str,
+///
+///                 // Whether the distribution was reverted to its previous certificate because the deployment
+///                 // failed verification.
+///                 "RolledBack": bool,
+///             }
+///         ],
+///
+///         // If Verify was set, the result of probing the distributions' domain names. See VerificationResult.
+///         "Verification": { ... }
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CloudFrontStorageResult {
+    #[serde(rename = "CertificateArn")]
+    pub certificate_arn: String,
+
+    #[serde(rename = "Distributions")]
+    pub distributions: Vec<CloudFrontDistributionResult>,
+
+    #[serde(rename = "Verification", default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<VerificationResult>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CloudFrontDistributionResult {
+    #[serde(rename = "DistributionId")]
+    pub distribution_id: String,
+
+    #[serde(rename = "Updated")]
+    pub updated: bool,
+
+    #[serde(rename = "PreviousCertificateArn", default, skip_serializing_if = "Option::is_none")]
+    pub previous_certificate_arn: Option<String>,
+
+    #[serde(rename = "RolledBack", default)]
+    pub rolled_back: bool,
+}
+
+/// The results of storing a certificate as an IAM server certificate. In JSON:
+///
+///     {
+///         // The type of storage. Always "IamServerCertificate".
+///         "Type": "IamServerCertificate",
+///
+///         // The name, id, and ARN of the uploaded server certificate.
+///         "ServerCertificateName": str,
+///         "ServerCertificateId": str,
+///         "Arn": str,
+///
+///         // The previous server certificates that were deleted.
+///         "DeletedServerCertificateNames": [str, ...],
+///
+///         // The previous server certificates that could not be deleted, usually because they are still in use.
+///         "UndeletedServerCertificateNames": [str, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct IamServerCertificateStorageResult {
+    #[serde(rename = "ServerCertificateName")]
+    pub server_certificate_name: String,
+
+    #[serde(rename = "ServerCertificateId")]
+    pub server_certificate_id: String,
+
+    #[serde(rename = "Arn")]
+    pub arn: String,
+
+    #[serde(rename = "DeletedServerCertificateNames", default)]
+    pub deleted_server_certificate_names: Vec<String>,
+
+    #[serde(rename = "UndeletedServerCertificateNames", default)]
+    pub undeleted_server_certificate_names: Vec<String>,
+}
+
+/// The results of deploying a certificate to load balancer listeners. In JSON:
+///
+///     {
+///         // The type of storage. Always "LoadBalancer".
+///         "Type": "LoadBalancer",
+///
+///         // The ARN of the certificate in ACM.
+///         "CertificateArn": str,
+///
+///         // The listeners that are using the certificate.
+///         "Listeners": [
+///             {
+///                 // The ARN of the listener.
+///                 "ListenerArn": str,
+///
+///                 // Whether the listener was updated. This is false if it was already using the certificate
+///                 // ARN (e.g. because the certificate was reimported).
+///                 "Updated": bool,
+///
+///                 // The listener's default certificate before the update.
+///                 "PreviousCertificateArn": str,
+///
+///                 // The certificates removed from the listener because RemovePreviousCertificate was set.
+///                 "RemovedCertificateArns": [str, ...],
+///
+///                 // Whether the update was reverted because the deployment failed verification.
+///                 "RolledBack": bool,
+///             }
+///         ],
+///
+///         // If Verify was set, the result of probing the listeners' endpoints. See VerificationResult.
+///         "Verification": { ... }
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct LoadBalancerStorageResult {
+    #[serde(rename = "CertificateArn")]
+    pub certificate_arn: String,
+
+    #[serde(rename = "Listeners")]
+    pub listeners: Vec<ListenerResult>,
+
+    #[serde(rename = "Verification", default, skip_serializing_if = "Option::is_none")]
+    pub verification: Option<VerificationResult>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct ListenerResult {
+    #[serde(rename = "ListenerArn")]
+    pub listener_arn: String,
+
+    #[serde(rename = "Updated")]
+    pub updated: bool,
+
+    #[serde(rename = "PreviousCertificateArn", default, skip_serializing_if = "Option::is_none")]
+    pub previous_certificate_arn: Option<String>,
+
+    #[serde(rename = "RemovedCertificateArns", default)]
+    pub removed_certificate_arns: Vec<String>,
+
+    #[serde(rename = "RolledBack", default)]
+    pub rolled_back: bool,
+}
+
+/// The results of storing a certificate in S3. In JSON:
+///
+///     {
+///         // The type of storage. Always "S3".
+///         "Type": "S3",
+///
+///         // The bucket where the certificate is stored.
+///         "Bucket": str,
+///
+///         // The S3 key for the certificate itself. This and Chain and FullChain are omitted if the Pem output
+///         // format isn't written.
+///         "Certificate": str,
+///
+///         // The S3 key for the intermediate certificate chain.
+///         "Chain": str,
+///
+///         // The S3 key for the concatenated certificate and intermediate chain.
+///         "FullChain": str,
+///
+///         // The S3 key for the certificate private key. This is omitted if AllowPrivateKey is false.
+///         "PrivateKey": str,
+///
+///         // The S3 key for the combined bundle of the full chain and private key, if Bundle was set.
+///         "Bundle": str,
+///
+///         // The S3 keys for the DER, PKCS#12, and Java KeyStore artifacts, if those output formats were written.
+///         "Artifacts": [str, ...],
+///
+///         // The S3 keys for the alternate chain and the concatenated certificate and alternate chain, if the
+///         // alternate chain was stored.
+///         "AlternateChain": str,
+///         "AlternateFullChain": str,
+///
+///         // Why the bucket could be public, if the private key was stored there because AllowPublicBucket was
+///         // set.
+///         "PublicAccessFindings": [str, ...],
+///
+///         // The number of objects written with SSE-KMS encryption.
+///         "KmsEncryptedObjects": int,
+///
+///         // With Versioning retention, the version ID of each object written, by key.
+///         "VersionIds": {str: str, ...},
+///
+///         // With TimestampedKeys retention, the keys of the timestamped copies written.
+///         "ArchivedKeys": [str, ...],
+///
+///         // The number of previous versions or timestamped copies deleted by retention.
+///         "PrunedVersions": int,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct S3StorageResult {
+    #[serde(rename = "Bucket")]
+    pub bucket: String,
+
+    #[serde(rename = "Certificate", default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+
+    #[serde(rename = "Chain", default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+
+    #[serde(rename = "FullChain", default, skip_serializing_if = "Option::is_none")]
+    pub fullchain: Option<String>,
+
+    #[serde(rename = "PrivateKey", default, skip_serializing_if = "Option::is_none")]
+    pub pkey: Option<String>,
+
+    #[serde(rename = "Bundle", default, skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<String>,
+
+    #[serde(rename = "Artifacts", default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+
+    #[serde(rename = "AlternateChain", default, skip_serializing_if = "Option::is_none")]
+    pub alternate_chain: Option<String>,
+
+    #[serde(rename = "AlternateFullChain", default, skip_serializing_if = "Option::is_none")]
+    pub alternate_fullchain: Option<String>,
+
+    #[serde(rename = "PublicAccessFindings", default, skip_serializing_if = "Vec::is_empty")]
+    pub public_access_findings: Vec<String>,
+
+    #[serde(rename = "KmsEncryptedObjects", default)]
+    pub kms_encrypted_objects: u32,
+
+    #[serde(rename = "VersionIds", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub version_ids: BTreeMap<String, String>,
+
+    #[serde(rename = "ArchivedKeys", default, skip_serializing_if = "Vec::is_empty")]
+    pub archived_keys: Vec<String>,
+
+    #[serde(rename = "PrunedVersions", default)]
+    pub pruned_versions: u32,
+}
+
+/// The results of storing a certificate in DynamoDB. In JSON:
+///
+///     {
+///         // The type of storage. Always "DynamoDb".
+///         "Type": "DynamoDb",
+///
+///         // The table written to.
+///         "TableName": str,
+///
+///         // The key of the version written.
+///         "DomainName": str,
+///         "IssuedAt": int,
+///
+///         // The number of older versions given an ExpiresAt attribute, if RetentionDays was set.
+///         "ExpiredVersions": int,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct DynamoDbStorageResult {
+    #[serde(rename = "TableName")]
+    pub table_name: String,
+
+    #[serde(rename = "DomainName")]
+    pub domain_name: String,
+
+    #[serde(rename = "IssuedAt")]
+    pub issued_at: i64,
+
+    #[serde(rename = "ExpiredVersions", default)]
+    pub expired_versions: usize,
+}
+
+/// The results of writing a certificate to a directory. In JSON:
+///
+///     {
+///         // The type of storage. Always "File".
+///         "Type": "File",
+///
+///         // The directory written to.
+///         "Directory": str,
+///
+///         // The paths of the files written. PrivateKey is omitted if AllowPrivateKey was false, the alternate
+///         // chain files are omitted if the CA did not offer an alternate chain, and the PEM files are omitted if
+///         // the Pem output format wasn't written.
+///         "Certificate": str,
+///         "Chain": str,
+///         "FullChain": str,
+///         "PrivateKey": str,
+///         "AlternateChain": str,
+///         "AlternateFullChain": str,
+///
+///         // The paths of the DER, PKCS#12, and Java KeyStore files, if those output formats were written.
+///         "Artifacts": [str, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct FileStorageResult {
+    #[serde(rename = "Directory")]
+    pub directory: String,
+
+    #[serde(rename = "Certificate", default, skip_serializing_if = "Option::is_none")]
+    pub certificate: Option<String>,
+
+    #[serde(rename = "Chain", default, skip_serializing_if = "Option::is_none")]
+    pub chain: Option<String>,
+
+    #[serde(rename = "FullChain", default, skip_serializing_if = "Option::is_none")]
+    pub fullchain: Option<String>,
+
+    #[serde(rename = "PrivateKey", default, skip_serializing_if = "Option::is_none")]
+    pub pkey: Option<String>,
+
+    #[serde(rename = "Artifacts", default, skip_serializing_if = "Vec::is_empty")]
+    pub artifacts: Vec<String>,
+
+    #[serde(rename = "AlternateChain", default, skip_serializing_if = "Option::is_none")]
+    pub alternate_chain: Option<String>,
+
+    #[serde(rename = "AlternateFullChain", default, skip_serializing_if = "Option::is_none")]
+    pub alternate_fullchain: Option<String>,
+}
+
+/// The results of writing a certificate to a Kubernetes secret. In JSON:
+///
+///     {
+///         // The type of storage. Always "Kubernetes".
+///         "Type": "Kubernetes",
+///
+///         // The EKS cluster, namespace, and name of the secret written.
+///         "ClusterName": str,
+///         "Namespace": str,
+///         "SecretName": str,
+///
+///         // The resourceVersion of the secret after it was written.
+///         "ResourceVersion": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct KubernetesStorageResult {
+    #[serde(rename = "ClusterName")]
+    pub cluster_name: String,
+
+    #[serde(rename = "Namespace")]
+    pub namespace: String,
+
+    #[serde(rename = "SecretName")]
+    pub secret_name: String,
+
+    #[serde(rename = "ResourceVersion", default, skip_serializing_if = "Option::is_none")]
+    pub resource_version: Option<String>,
+}
+
+/// The results of storing a certificate in AWS Secrets Manager. In JSON:
+///
+///     {
+///         // The type of storage. Always "SecretsManager".
+///         "Type": "SecretsManager",
+///
+///         // The secrets written. This contains a single "Bundle" secret unless SeparateSecrets was true.
+///         "Secrets": [
+///             {
+///                 // The component stored in this secret: "Bundle", "Certificate", "Chain", "FullChain", or
+///                 // "PrivateKey".
+///                 "Component": str,
+///
+///                 // The name of the secret.
+///                 "SecretName": str,
+///
+///                 // The ARN of the secret.
+///                 "SecretArn": str,
+///
+///                 // The version id of the secret value that was written.
+///                 "VersionId": str,
+///             }
+///         ]
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SecretsManagerStorageResult {
+    #[serde(rename = "Secrets")]
+    pub secrets: Vec<SecretsManagerSecretResult>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct SecretsManagerSecretResult {
+    #[serde(rename = "Component")]
+    pub component: String,
+
+    #[serde(rename = "SecretName")]
+    pub secret_name: String,
+
+    #[serde(rename = "SecretArn")]
+    pub secret_arn: String,
+
+    #[serde(rename = "VersionId", default)]
+    pub version_id: Option<String>,
+}
+
+/// The results of storaing a certificate in the AWS Systems Manager parameter store. In JSON:
+///
+///     {
+///         // The type of storage. Always "SsmParameter".
+///         "Type": "SsmParameter",
+///
+///         // The name and ARN of the parameter containing the JSON bundle of all components. These are only
+///         // present if Bundle was true, in which case the per-component names and ARNs below are omitted.
+///         "BundleParameterName": str,
+///         "BundleArn": str,
+///
+///         // The name of the parameter containing the certificate.
+///         "CertificateParameterName": str,
+///
+///         // The name opf the parameter containing the intermediate certificate(s).
+///         "ChainParameterName": str,
+///
+///         // The name of the parameter containing the concatenated certificate and intermediate chain.
+///         "FullChainParameterName": str,
+///
+///         // The name of the parameter containing the certificate private key. This is omitted if AllowPrivateKey
+///         // is false.
+///         "PrivateKeyParameterName": str,
+///
+///         // The ARN of the parameter for the certificate.
+///         "CertificateArn": str,
+///
+///         // The ARN of the parameter for the intermediate certificate chain.
+///         "ChainArn": str,
+///
+///         // The ARN of the parameter for the concatenated certificate and intermediate chain.
+///         "FullChainArn": str,
+///
+///         // The ARN of the parameter for the certificate private key. This is omitted if AllowPrivateKey is false.
+///         "PrivateKeyArn": str,
+///
+///         // The names and ARNs of the parameters containing the alternate chain and the concatenated
+///         // certificate and alternate chain, if the alternate chain was stored.
+///         "AlternateChainParameterName": str,
+///         "AlternateFullChainParameterName": str,
+///         "AlternateChainArn": str,
+///         "AlternateFullChainArn": str,
+///
+///         // The version of each parameter that was written, keyed by parameter name.
+///         "ParameterVersions": {str: int, ...},
+///
+///         // The names of the parameters written in the Advanced tier, which are billed monthly.
+///         "AdvancedParameters": [str, ...],
+///     }
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct SsmParameterStorageResult {
+    #[serde(rename = "BundleParameterName", default, skip_serializing_if = "Option::is_none")]
+    pub bundle_param: Option<String>,
+
+    #[serde(rename = "BundleArn", default, skip_serializing_if = "Option::is_none")]
+    pub bundle_arn: Option<String>,
+
+    #[serde(rename = "CertificateParameterName", default, skip_serializing_if = "Option::is_none")]
+    pub cert_param: Option<String>,
+
+    #[serde(rename = "ChainParameterName", default, skip_serializing_if = "Option::is_none")]
+    pub chain_param: Option<String>,
+
+    #[serde(rename = "FullChainParameterName", default, skip_serializing_if = "Option::is_none")]
+    pub fullchain_param: Option<String>,
+
+    #[serde(rename = "PrivateKeyParameterName", default, skip_serializing_if = "Option::is_none")]
+    pub pkey_param: Option<String>,
+
+    #[serde(rename = "CertificateArn", default, skip_serializing_if = "Option::is_none")]
+    pub cert_arn: Option<String>,
+
+    #[serde(rename = "ChainArn", default, skip_serializing_if = "Option::is_none")]
+    pub chain_arn: Option<String>,
+
+    #[serde(rename = "FullChainArn", default, skip_serializing_if = "Option::is_none")]
+    pub fullchain_arn: Option<String>,
+
+    #[serde(rename = "PrivateKeyArn", default, skip_serializing_if = "Option::is_none")]
+    pub pkey_arn: Option<String>,
+
+    #[serde(rename = "AlternateChainParameterName", default, skip_serializing_if = "Option::is_none")]
+    pub alternate_chain_param: Option<String>,
+
+    #[serde(rename = "AlternateFullChainParameterName", default, skip_serializing_if = "Option::is_none")]
+    pub alternate_fullchain_param: Option<String>,
+
+    #[serde(rename = "AlternateChainArn", default, skip_serializing_if = "Option::is_none")]
+    pub alternate_chain_arn: Option<String>,
+
+    #[serde(rename = "AlternateFullChainArn", default, skip_serializing_if = "Option::is_none")]
+    pub alternate_fullchain_arn: Option<String>,
+
+    #[serde(rename = "ParameterVersions", default)]
+    pub parameter_versions: BTreeMap<String, i64>,
+
+    #[serde(rename = "AdvancedParameters", default, skip_serializing_if = "Vec::is_empty")]
+    pub advanced_parameters: Vec<String>,
+}
+
+/// The results of deploying a certificate to Windows instances. In JSON:
+///
+///     {
+///         // The type of storage. Always "Windows".
+///         "Type": "Windows",
+///
+///         // The parameter holding the PKCS#12 bundle.
+///         "ParameterName": str,
+///
+///         // The Run Command command id.
+///         "CommandId": str,
+///
+///         // The SHA-1 thumbprint of the certificate, as Windows displays it.
+///         "Thumbprint": str,
+///
+///         // The result on each instance. Status is the Run Command invocation status, e.g. "Success", "Failed",
+///         // or "TimedOut"; Output is the (possibly truncated) script output.
+///         "Instances": [{"InstanceId": str, "Status": str, "Output": str}, ...],
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct WindowsStorageResult {
+    #[serde(rename = "ParameterName")]
+    pub parameter_name: String,
+
+    #[serde(rename = "CommandId")]
+    pub command_id: String,
+
+    #[serde(rename = "Thumbprint")]
+    pub thumbprint: String,
+
+    #[serde(rename = "Instances", default)]
+    pub instances: Vec<WindowsInstanceResult>,
+}
+
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct WindowsInstanceResult {
+    #[serde(rename = "InstanceId")]
+    pub instance_id: String,
+
+    #[serde(rename = "Status")]
+    pub status: String,
+
+    #[serde(rename = "Output", default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{
+            expired_versions, is_archive_timestamp, parse_file_mode, powershell_quote, restore_tags_request,
+            s3_tag_value, same_domain_names, staged_name, ApiGatewayStorage, CertificateStorage,
+            CertificateStorageResult, CloudFrontStorage, DynamoDbStorage, FileStorage, IamServerCertificateStorage,
+            LoadBalancerStorage, S3Object, S3ObjectAttributes, S3ObjectSnapshot, S3Storage, SecretsManagerStorage,
+            SsmParameterStorage, WindowsStorage, IAM_SERIAL_HEX_LENGTH,
+        },
+        crate::{
+            chains::CertificateChain,
+            constants::{
+                IAM_MAX_SERVER_CERTIFICATE_NAME_LENGTH, IAM_MAX_TAGS, S3_MAX_METADATA_DOMAIN_NAMES_LEN,
+                S3_MAX_TAG_VALUE_LEN, SSM_ADVANCED_MAX_VALUE_LEN, SSM_STANDARD_MAX_VALUE_LEN,
+            },
+            store::CertificateStore,
+            utils::{CertificateComponents, CertificateInfo},
+        },
+        openssl::{
+            asn1::Asn1Time,
+            ec::{EcGroup, EcKey},
+            hash::MessageDigest,
+            nid::Nid,
+            pkcs12::Pkcs12,
+            pkey::PKey,
+            x509::{X509NameBuilder, X509},
+        },
+        rusoto_cloudfront::ViewerCertificate,
+        rusoto_ssm::Tag as SsmTag,
+        serde_json::{json, Map, Value},
+        std::collections::HashMap,
+        std::os::unix::fs::PermissionsExt,
+    };
+
+    fn cloudfront_storage(config: Value) -> CloudFrontStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn api_gateway_storage(config: Value) -> ApiGatewayStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn iam_storage(config: Value) -> IamServerCertificateStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn ssm_storage(config: Value) -> SsmParameterStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn components(len: usize) -> CertificateComponents {
+        CertificateComponents {
+            cert_pem: "c".repeat(len),
+            chain_pem: "c".repeat(len),
+            fullchain_pem: "c".repeat(len),
+            pkey_pem: "k".repeat(len),
+            alternate_chain: None,
+        }
+    }
+
+    fn dynamodb_storage(config: Value) -> DynamoDbStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn file_storage(config: Value) -> FileStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn load_balancer_storage(config: Value) -> LoadBalancerStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    fn windows_storage(config: Value) -> WindowsStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    /// A self-signed P-256 certificate and its key.
+    fn self_signed_components(common_name: &str) -> CertificateComponents {
+        let pkey =
+            PKey::from_ec_key(EcKey::generate(&EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap()).unwrap())
+                .unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(90).unwrap()).unwrap();
+        builder.sign(&pkey, MessageDigest::sha256()).unwrap();
+        let cert_pem = String::from_utf8(builder.build().to_pem().unwrap()).unwrap();
+
+        CertificateComponents {
+            chain_pem: cert_pem.clone(),
+            fullchain_pem: format!("{}{}", cert_pem, cert_pem),
+            cert_pem,
+            pkey_pem: String::from_utf8(pkey.private_key_to_pem_pkcs8().unwrap()).unwrap(),
+            alternate_chain: None,
+        }
+    }
+
+    fn secrets_manager_storage(config: Value) -> SecretsManagerStorage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_secrets_manager_default_name_templates() {
+        let mut bundle = secrets_manager_storage(json!({}));
+        bundle.validate().await.unwrap();
+        assert_eq!(bundle.get_secret_name("example.com", "Bundle"), "Certificate/example.com");
+
+        let mut separate = secrets_manager_storage(json!({"SeparateSecrets": true}));
+        separate.validate().await.unwrap();
+        assert_eq!(separate.get_secret_name("example.com", "PrivateKey"), "Certificate/example.com/PrivateKey");
+    }
+
+    #[tokio::test]
+    async fn test_secrets_manager_custom_name_templates() {
+        let mut storage = secrets_manager_storage(json!({
+            "SecretNameTemplate": "tls/{Domain}-{Component}",
+            "SeparateSecrets": true,
+        }));
+        storage.validate().await.unwrap();
+        assert_eq!(storage.get_secret_name("www.example.com", "Chain"), "tls/www.example.com-Chain");
+
+        for config in [
+            json!({"SecretNameTemplate": ""}),
+            json!({"SecretNameTemplate": "tls/{Domain}", "SeparateSecrets": true}),
+            json!({"SecretNameTemplate": "tls/{Domain}/{Component}", "SeparateSecrets": false}),
+        ] {
+            assert!(secrets_manager_storage(config.clone()).validate().await.is_err(), "{} should be rejected", config);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cloudfront_validate() {
+        let mut storage = cloudfront_storage(json!({"DistributionIds": "E123"}));
+        storage.validate().await.unwrap();
+        assert_eq!(storage.distribution_ids, vec!["E123".to_string()]);
+        assert_eq!(storage.acm.region.as_deref(), Some("us-east-1"));
+
+        for config in [
+            json!({"DistributionIds": []}),
+            json!({"DistributionIds": ["E123"], "SslSupportMethod": "static-ip"}),
+            json!({"DistributionIds": ["E123"], "Acm": {"Region": "us-west-2"}}),
+        ] {
+            assert!(cloudfront_storage(config.clone()).validate().await.is_err(), "{} should be rejected", config);
+        }
+    }
+
+    #[test]
+    fn test_cloudfront_viewer_certificate() {
+        let arn = "arn:aws:acm:us-east-1:123456789012:certificate/abc";
+
+        // A distribution using the default certificate gets the configured (or default) TLS settings.
+        let storage = cloudfront_storage(json!({"DistributionIds": ["E123"], "SslSupportMethod": "vip"}));
+        let default_certificate = ViewerCertificate {
+            cloud_front_default_certificate: Some(true),
+            ..Default::default()
+        };
+        let updated = storage.viewer_certificate_for(default_certificate, arn);
+        assert_eq!(updated.acm_certificate_arn.as_deref(), Some(arn));
+        assert_eq!(updated.cloud_front_default_certificate, Some(false));
+        assert_eq!(updated.ssl_support_method.as_deref(), Some("vip"));
+        assert_eq!(updated.minimum_protocol_version.as_deref(), Some("TLSv1.2_2021"));
+
+        // A distribution already using a custom certificate keeps its TLS settings.
+        let iam_certificate = ViewerCertificate {
+            iam_certificate_id: Some("ASCA123".to_string()),
+            ssl_support_method: Some("sni-only".to_string()),
+            minimum_protocol_version: Some("TLSv1.1_2016".to_string()),
+            ..Default::default()
+        };
+        let updated = storage.viewer_certificate_for(iam_certificate, arn);
+        assert_eq!(updated.acm_certificate_arn.as_deref(), Some(arn));
+        assert_eq!(updated.iam_certificate_id, None);
+        assert_eq!(updated.ssl_support_method.as_deref(), Some("sni-only"));
+        assert_eq!(updated.minimum_protocol_version.as_deref(), Some("TLSv1.1_2016"));
+    }
+
+    #[tokio::test]
+    async fn test_load_balancer_validate() {
+        let listener_west =
+            "arn:aws:elasticloadbalancing:us-west-2:123456789012:listener/app/web/50dc6c495c0c9188/f2f7dc8efc522ab2";
+        let listener_east =
+            "arn:aws:elasticloadbalancing:us-east-1:123456789012:listener/app/web/50dc6c495c0c9188/f2f7dc8efc522ab2";
+
+        let mut storage = load_balancer_storage(json!({"ListenerArns": listener_west, "Acm": {"Region": "us-west-2"}}));
+        storage.validate().await.unwrap();
+        assert!(storage.default);
+        assert!(!storage.remove_previous_certificate);
+
+        for config in [
+            json!({"ListenerArns": []}),
+            json!({"ListenerArns": ["arn:aws:elasticloadbalancing:us-west-2:123456789012:loadbalancer/app/web/50dc6c495c0c9188"]}),
+            json!({"ListenerArns": ["arn:aws:elasticloadbalancing:nowhere-1:123456789012:listener/app/web/1/2"]}),
+            json!({"ListenerArns": [listener_west, listener_east]}),
+            json!({"ListenerArns": [listener_west], "Acm": {"Region": "us-east-1"}}),
+        ] {
+            assert!(load_balancer_storage(config.clone()).validate().await.is_err(), "{} should be rejected", config);
+        }
+    }
+
+    #[test]
+    fn test_same_domain_names() {
+        let requested = ["example.com", "*.example.com"].map(String::from);
+        assert!(same_domain_names(&["*.EXAMPLE.com", "example.com", "example.com"].map(String::from), &requested));
+        assert!(!same_domain_names(&["example.com"].map(String::from), &requested));
+        assert!(!same_domain_names(&[], &requested));
+    }
+
+    #[tokio::test]
+    async fn test_api_gateway_validate() {
+        // These are rejected before any custom domain name is looked up.
+        for config in [
+            json!({"DomainNames": []}),
+            json!({"DomainNames": ["api.example.com"], "Region": "nowhere-1"}),
+            json!({"DomainNames": ["api.example.com"], "EndpointType": "PRIVATE"}),
+        ] {
+            assert!(api_gateway_storage(config.clone()).validate().await.is_err(), "{} should be rejected", config);
+        }
+    }
+
+    #[test]
+    fn test_api_gateway_endpoint_types() {
+        let edge = api_gateway_storage(
+            json!({"DomainNames": "api.example.com", "EndpointType": "EDGE", "Region": "eu-west-1"}),
+        );
+        assert_eq!(edge.required_acm_region(), "us-east-1");
+        assert_eq!(edge.certificate_path(), "/certificateArn");
+
+        let regional = api_gateway_storage(
+            json!({"DomainNames": "api.example.com", "EndpointType": "REGIONAL", "Region": "eu-west-1"}),
+        );
+        assert_eq!(regional.required_acm_region(), "eu-west-1");
+        assert_eq!(regional.certificate_path(), "/regionalCertificateArn");
+    }
+
+    /// Returns the writes a storage target plans for *.example.com, as "<action> <resource>".
+    fn planned(storage: Value, alternate_chain: bool) -> Vec<String> {
+        let storage: CertificateStorage = serde_json::from_value(storage).unwrap();
+        storage
+            .planned_writes(&["*.example.com".to_string()], alternate_chain)
+            .into_iter()
+            .map(|write| format!("{} {}", write.action, write.resource))
+            .collect()
+    }
+
+    #[test]
+    fn test_acm_planned_writes() {
+        assert_eq!(
+            planned(json!({"Type": "Acm", "Region": "us-west-2"}), false),
+            vec!["acm:ImportCertificate (the certificate for *.example.com, or a new certificate if none is found)"]
+        );
+        assert_eq!(
+            planned(json!({"Type": "Acm", "ForceNewImport": true, "Tags": {"Team": "web"}}), false),
+            vec!["acm:ImportCertificate (new certificate)"]
+        );
+
+        let arn = "arn:aws:acm:us-west-2:123456789012:certificate/abc";
+        assert_eq!(
+            planned(json!({"Type": "Acm", "CertificateArns": [arn], "Tags": {"Team": "web"}}), false),
+            vec![format!("acm:ImportCertificate {}", arn), format!("acm:AddTagsToCertificate {}", arn)]
+        );
+
+        let storage: CertificateStorage =
+            serde_json::from_value(json!({"Type": "Acm", "Region": "us-west-2"})).unwrap();
+        assert_eq!(
+            storage.planned_writes(&["*.example.com".to_string()], false)[0].region.as_deref(),
+            Some("us-west-2")
+        );
+    }
+
+    #[test]
+    fn test_api_gateway_planned_writes() {
+        let arn = "arn:aws:acm:us-west-2:123456789012:certificate/abc";
+        assert_eq!(
+            planned(
+                json!({
+                    "Type": "ApiGateway",
+                    "DomainNames": ["api.example.com", "www.example.com"],
+                    "Acm": {"CertificateArns": [arn]},
+                }),
+                false
+            ),
+            vec![
+                format!("acm:ImportCertificate {}", arn),
+                "apigateway:UpdateDomainName api.example.com".to_string(),
+                "apigateway:UpdateDomainName www.example.com".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cloudfront_planned_writes() {
+        let arn = "arn:aws:acm:us-east-1:123456789012:certificate/abc";
+        assert_eq!(
+            planned(json!({"Type": "CloudFront", "DistributionIds": "E123", "Acm": {"CertificateArns": [arn]}}), false),
+            vec![format!("acm:ImportCertificate {}", arn), "cloudfront:UpdateDistribution E123".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_iam_server_certificate_planned_writes() {
+        assert_eq!(
+            planned(json!({"Type": "IamServerCertificate", "NamePrefix": "web-", "DeletePrevious": false}), false),
+            vec!["iam:UploadServerCertificate /web-<serial>"]
+        );
+        assert_eq!(
+            planned(json!({"Type": "IamServerCertificate", "NamePrefix": "web-", "Path": "/certs/"}), false),
+            vec![
+                "iam:UploadServerCertificate /certs/web-<serial>",
+                "iam:DeleteServerCertificate (previous certificates named /certs/web-*)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_balancer_planned_writes() {
+        let arn = "arn:aws:acm:us-west-2:123456789012:certificate/abc";
+        let listener = "arn:aws:elasticloadbalancing:us-west-2:123456789012:listener/app/web/1/2";
+        assert_eq!(
+            planned(
+                json!({"Type": "LoadBalancer", "ListenerArns": listener, "Acm": {"CertificateArns": [arn]}}),
+                false
+            ),
+            vec![format!("acm:ImportCertificate {}", arn), format!("elasticloadbalancing:ModifyListener {}", listener)]
+        );
+        assert_eq!(
+            planned(
+                json!({
+                    "Type": "LoadBalancer",
+                    "ListenerArns": [listener],
+                    "Default": false,
+                    "RemovePreviousCertificate": true,
+                    "Acm": {"CertificateArns": [arn]},
+                }),
+                false
+            ),
+            vec![
+                format!("acm:ImportCertificate {}", arn),
+                format!("elasticloadbalancing:AddListenerCertificates {}", listener),
+                format!("elasticloadbalancing:RemoveListenerCertificates {}", listener),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_s3_planned_writes() {
+        assert_eq!(
+            planned(json!({"Type": "S3", "Bucket": "certs", "Prefix": "live/", "AllowPrivateKey": false}), false),
+            vec![
+                "s3:PutObject s3://certs/live/cert.pem",
+                "s3:PutObject s3://certs/live/chain.pem",
+                "s3:PutObject s3://certs/live/fullchain.pem",
+            ]
+        );
+
+        let writes = planned(
+            json!({
+                "Type": "S3",
+                "Bucket": "certs",
+                "KeyTemplate": "{Domain}/{FileName}",
+                "AllowPrivateKey": false,
+                "WriteStrategy": "Transactional",
+                "Retention": {"Mode": "TimestampedKeys", "KeepPrevious": 2},
+            }),
+            false,
+        );
+        assert_eq!(writes.len(), 15);
+        assert_eq!(
+            &writes[..5],
+            &[
+                "s3:PutObject s3://certs/_.example.com/cert.pem.staging",
+                "s3:PutObject s3://certs/_.example.com/cert.pem",
+                "s3:PutObject s3://certs/_.example.com/cert.pem.<timestamp>",
+                "s3:DeleteObject s3://certs/_.example.com/cert.pem.<timestamp> (beyond KeepPrevious)",
+                "s3:DeleteObject s3://certs/_.example.com/cert.pem.staging",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ssm_parameter_planned_writes() {
+        assert_eq!(
+            planned(json!({"Type": "SsmParameter", "Path": "/certs", "AllowPrivateKey": false}), true),
+            vec![
+                "ssm:PutParameter /certs/Certificate/_.example.com/Certificate",
+                "ssm:PutParameter /certs/Certificate/_.example.com/Chain",
+                "ssm:PutParameter /certs/Certificate/_.example.com/FullChain",
+                "ssm:PutParameter /certs/Certificate/_.example.com/AlternateChain",
+                "ssm:PutParameter /certs/Certificate/_.example.com/AlternateFullChain",
+            ]
+        );
+        assert_eq!(
+            planned(json!({"Type": "SsmParameter", "Path": "/certs", "Bundle": true}), true),
+            vec!["ssm:PutParameter /certs/Certificate/_.example.com/Bundle"]
+        );
+        assert_eq!(
+            &planned(json!({"Type": "SsmParameter", "Path": "/certs", "WriteStrategy": "Transactional"}), false)[..3],
+            &[
+                "ssm:PutParameter /certs/Certificate/_.example.com/Certificate.staging",
+                "ssm:PutParameter /certs/Certificate/_.example.com/Certificate",
+                "ssm:DeleteParameters /certs/Certificate/_.example.com/Certificate.staging",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_secrets_manager_planned_writes() {
+        assert_eq!(
+            planned(json!({"Type": "SecretsManager", "SecretNameTemplate": "certs/{Domain}"}), false),
+            vec!["secretsmanager:UpdateSecret certs/_.example.com"]
+        );
+        assert_eq!(
+            planned(
+                json!({
+                    "Type": "SecretsManager",
+                    "SecretNameTemplate": "certs/{Domain}/{Component}",
+                    "SeparateSecrets": true,
+                    "AllowPrivateKey": false,
+                }),
+                false
+            ),
+            vec![
+                "secretsmanager:UpdateSecret certs/_.example.com/Certificate",
+                "secretsmanager:UpdateSecret certs/_.example.com/Chain",
+                "secretsmanager:UpdateSecret certs/_.example.com/FullChain",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dynamodb_planned_writes() {
+        assert_eq!(
+            planned(json!({"Type": "DynamoDb", "TableName": "Certificates"}), false),
+            vec!["dynamodb:PutItem Certificates (DomainName *.example.com)"]
+        );
+        assert_eq!(
+            planned(json!({"Type": "DynamoDb", "TableName": "Certificates", "RetentionDays": 30}), false),
+            vec![
+                "dynamodb:PutItem Certificates (DomainName *.example.com)",
+                "dynamodb:UpdateItem Certificates (previous versions of *.example.com)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_file_planned_writes() {
+        assert_eq!(
+            planned(json!({"Type": "File", "Directory": "/etc/ssl/{Domain}"}), false),
+            vec![
+                "file:Write /etc/ssl/_.example.com/cert.pem",
+                "file:Write /etc/ssl/_.example.com/chain.pem",
+                "file:Write /etc/ssl/_.example.com/fullchain.pem",
+                "file:Write /etc/ssl/_.example.com/privkey.pem",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_windows_planned_writes() {
+        assert_eq!(
+            planned(
+                json!({
+                    "Type": "Windows",
+                    "InstanceIds": ["i-0123456789abcdef0"],
+                    "Targets": [{"Key": "tag:Role", "Values": ["web", "api"]}],
+                }),
+                false
+            ),
+            vec![
+                "ssm:PutParameter /Certificate/Windows/_.example.com",
+                "ssm:SendCommand i-0123456789abcdef0",
+                "ssm:SendCommand Key=tag:Role,Values=web,api",
+                "ssm:LabelParameterVersion /Certificate/Windows/_.example.com",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_iam_server_certificate_limits() {
+        let longest_prefix = "a".repeat(IAM_MAX_SERVER_CERTIFICATE_NAME_LENGTH - IAM_SERIAL_HEX_LENGTH);
+        assert!(iam_storage(json!({"NamePrefix": longest_prefix})).validate().await.is_ok());
+
+        let long_prefix = format!("{}a", longest_prefix);
+        let e = iam_storage(json!({"NamePrefix": long_prefix})).validate().await.unwrap_err();
+        assert!(e.to_string().contains("NamePrefix must be 1-88 characters"), "{}", e);
+
+        // Default prefixes are truncated so the serial number still fits.
+        let domain_names = vec![format!("{}.example.com", "a".repeat(120))];
+        let prefix = iam_storage(json!({})).name_prefix(&domain_names);
+        assert_eq!(prefix.len(), IAM_MAX_SERVER_CERTIFICATE_NAME_LENGTH - IAM_SERIAL_HEX_LENGTH);
+
+        let tags = |n: usize| -> Map<String, Value> {
+            (0..n).map(|i| (format!("Tag{}", i), Value::String("value".to_string()))).collect()
+        };
+        assert!(iam_storage(json!({"Tags": tags(IAM_MAX_TAGS)})).validate().await.is_ok());
+
+        let e = iam_storage(json!({"Tags": tags(IAM_MAX_TAGS + 1)})).validate().await.unwrap_err();
+        assert!(e.to_string().contains("At most 50 tags"), "{}", e);
+    }
+
+    #[test]
+    fn test_staged_names() {
+        assert_eq!(staged_name("live/example.com/cert.pem"), "live/example.com/cert.pem.staging");
+
+        let ssm = ssm_storage(json!({"Path": "/certs/", "WriteStrategy": "Transactional"}));
+        assert_eq!(
+            staged_name(&ssm.get_parameter_name("*.example.com", "Certificate")),
+            "/certs/Certificate/_.example.com/Certificate.staging"
+        );
+
+        let attributes = S3ObjectAttributes {
+            metadata: HashMap::new(),
+            tagging: Some("team=web".to_string()),
+            archive_suffix: Some(".20240101T000000Z".to_string()),
+            staging: false,
+        };
+        assert_eq!(attributes.object_key("live/cert.pem"), "live/cert.pem");
+
+        // Staged objects carry the same tags, but never a timestamped copy.
+        let staged = attributes.staged();
+        assert_eq!(staged.object_key("live/cert.pem"), "live/cert.pem.staging");
+        assert_eq!(staged.tagging, attributes.tagging);
+        assert_eq!(staged.archive_suffix, None);
+    }
+
+    #[test]
+    fn test_restore_tags() {
+        let snapshot = S3ObjectSnapshot {
+            body: b"-----BEGIN CERTIFICATE-----".to_vec(),
+            metadata: HashMap::new(),
+            tags: vec![
+                ("certificate-serial".to_string(), "0a1b".to_string()),
+                ("team".to_string(), "web & api".to_string()),
+            ],
+        };
+        assert_eq!(snapshot.restore_attributes().tagging, Some("certificate-serial=0a1b&team=web+%26+api".to_string()));
+
+        let untagged = S3ObjectSnapshot {
+            tags: Vec::new(),
+            ..snapshot
+        };
+        assert_eq!(untagged.restore_attributes().tagging, None);
+
+        // A restored object is written back to its live key, without a timestamped copy.
+        let restore = untagged.restore_attributes();
+        assert_eq!(restore.object_key("live/cert.pem"), "live/cert.pem");
+        assert_eq!(restore.archive_suffix, None);
+
+        let tags = vec![SsmTag {
+            key: "certificate-writer".to_string(),
+            value: "previous".to_string(),
+        }];
+        let request = restore_tags_request("/certs/example.com/Certificate", tags.clone()).unwrap();
+        assert_eq!(request.resource_id, "/certs/example.com/Certificate");
+        assert_eq!(request.tags, tags);
+        assert!(restore_tags_request("/certs/example.com/Certificate", Vec::new()).is_none());
+    }
+
+    #[test]
+    fn test_ssm_tier_for_value() {
+        let standard = ssm_storage(json!({"Path": "/certs", "Tier": "Standard"}));
+        assert_eq!(standard.tier_for_value(&"c".repeat(SSM_STANDARD_MAX_VALUE_LEN)), "Standard");
+        assert_eq!(standard.tier_for_value(&"c".repeat(SSM_STANDARD_MAX_VALUE_LEN + 1)), "Advanced");
+
+        let default = ssm_storage(json!({"Path": "/certs"}));
+        assert_eq!(default.tier_for_value(&"c".repeat(SSM_STANDARD_MAX_VALUE_LEN + 1)), "Intelligent-Tiering");
+    }
+
+    #[test]
+    fn test_ssm_component_sizes() {
+        let storage = ssm_storage(json!({"Path": "/certs"}));
+        assert!(storage.check_component_sizes(&components(SSM_ADVANCED_MAX_VALUE_LEN)).is_ok());
+
+        let e = storage.check_component_sizes(&components(SSM_ADVANCED_MAX_VALUE_LEN + 1)).unwrap_err();
+        assert!(e.to_string().contains("PrivateKey (8193 bytes)"), "{}", e);
+
+        // The private key isn't checked if it won't be written.
+        let mut oversized_key = components(SSM_ADVANCED_MAX_VALUE_LEN);
+        oversized_key.pkey_pem.push('k');
+        let no_key = ssm_storage(json!({"Path": "/certs", "AllowPrivateKey": false}));
+        assert!(no_key.check_component_sizes(&oversized_key).is_ok());
+
+        let mut oversized_alternate = components(SSM_ADVANCED_MAX_VALUE_LEN);
+        oversized_alternate.alternate_chain = Some(CertificateChain {
+            chain_pem: "c".repeat(SSM_ADVANCED_MAX_VALUE_LEN),
+            fullchain_pem: "c".repeat(SSM_ADVANCED_MAX_VALUE_LEN + 1),
+        });
+        let e = storage.check_component_sizes(&oversized_alternate).unwrap_err();
+        assert!(e.to_string().contains("AlternateFullChain"), "{}", e);
+        assert!(!e.to_string().contains("AlternateChain ("), "{}", e);
+    }
+
+    #[test]
+    fn test_ssm_bundle_value() {
+        let storage = ssm_storage(json!({"Path": "/certs", "Bundle": true}));
+        assert!(storage.bundle);
+        assert!(!ssm_storage(json!({"Path": "/certs"})).bundle);
+
+        let bundle: Value = serde_json::from_str(&storage.bundle_value(components(4)).unwrap()).unwrap();
+        assert_eq!(bundle, json!({"Certificate": "cccc", "Chain": "cccc", "FullChain": "cccc", "PrivateKey": "kkkk"}));
+
+        let mut alternate = components(4);
+        alternate.alternate_chain = Some(CertificateChain {
+            chain_pem: "a".to_string(),
+            fullchain_pem: "af".to_string(),
+        });
+        let no_key = ssm_storage(json!({"Path": "/certs", "Bundle": true, "AllowPrivateKey": false}));
+        let bundle: Value = serde_json::from_str(&no_key.bundle_value(alternate).unwrap()).unwrap();
+        assert_eq!(
+            bundle,
+            json!({
+                "Certificate": "cccc",
+                "Chain": "cccc",
+                "FullChain": "cccc",
+                "AlternateChain": "a",
+                "AlternateFullChain": "af",
+            })
+        );
+    }
+
+    #[test]
+    fn test_ssm_bundle_size() {
+        // The components fit individually, but the bundle holding all of them does not.
+        let storage = ssm_storage(json!({"Path": "/certs", "Bundle": true}));
+        let e = storage.bundle_value(components(SSM_ADVANCED_MAX_VALUE_LEN / 4)).unwrap_err();
+        assert!(e.to_string().contains("Bundle ("), "{}", e);
+
+        assert!(storage.bundle_value(components(SSM_ADVANCED_MAX_VALUE_LEN / 5)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_dynamodb_validate() {
+        assert!(dynamodb_storage(json!({"TableName": "certs"})).validate().await.is_ok());
+        assert!(dynamodb_storage(json!({"TableName": "certs", "RetentionDays": 30})).validate().await.is_ok());
+
+        let e = dynamodb_storage(json!({"TableName": ""})).validate().await.unwrap_err();
+        assert!(e.to_string().contains("TableName cannot be empty"), "{}", e);
+
+        let e = dynamodb_storage(json!({"TableName": "certs", "RetentionDays": 0})).validate().await.unwrap_err();
+        assert!(e.to_string().contains("Invalid RetentionDays: 0"), "{}", e);
+
+        let e = dynamodb_storage(json!({"TableName": "certs", "Region": "nowhere-1"})).validate().await.unwrap_err();
+        assert!(e.to_string().contains("Invalid region: nowhere-1"), "{}", e);
+    }
+
+    #[test]
+    fn test_dynamodb_expand_regions() {
+        assert!(dynamodb_storage(json!({"TableName": "certs"})).expand_regions().unwrap().is_none());
+
+        let storage =
+            dynamodb_storage(json!({"TableName": "certs", "Regions": ["us-west-2", "eu-west-1", "us-west-2"]}));
+        assert_eq!(storage.expand_regions().unwrap().unwrap().len(), 2);
+
+        let both = dynamodb_storage(json!({"TableName": "certs", "Region": "us-east-1", "Regions": ["us-west-2"]}));
+        assert!(both.expand_regions().is_err());
+    }
+
+    #[test]
+    fn test_dynamodb_certificate_item() {
+        let info = CertificateInfo {
+            domain_names: vec!["Example.com".to_string()],
+            serial: "abc123".to_string(),
+            not_before: 1000,
+            not_after: 2000,
+            key_algorithm: None,
+        };
+        let domain_names = vec!["Example.com".to_string(), "www.example.com".to_string()];
+
+        let storage = dynamodb_storage(json!({"TableName": "certs"}));
+        let item = storage.certificate_item(&domain_names, &components(4), &info);
+        assert_eq!(item["DomainName"].s.as_deref(), Some("example.com"));
+        assert_eq!(item["IssuedAt"].n.as_deref(), Some("1000"));
+        assert_eq!(item["NotAfter"].n.as_deref(), Some("2000"));
+        assert_eq!(item["DomainNames"].ss.as_ref(), Some(&domain_names));
+        assert_eq!(item["Serial"].s.as_deref(), Some("abc123"));
+        assert_eq!(item["PrivateKey"].s.as_deref(), Some("kkkk"));
+        assert!(!item.contains_key("AlternateChain"));
+        assert!(!item.contains_key("ExpiresAt"));
+
+        let mut alternate = components(4);
+        alternate.alternate_chain = Some(CertificateChain {
+            chain_pem: "a".to_string(),
+            fullchain_pem: "af".to_string(),
+        });
+        let no_key = dynamodb_storage(json!({"TableName": "certs", "AllowPrivateKey": false}));
+        let item = no_key.certificate_item(&domain_names, &alternate, &info);
+        assert!(!item.contains_key("PrivateKey"));
+        assert_eq!(item["AlternateChain"].s.as_deref(), Some("a"));
+        assert_eq!(item["AlternateFullChain"].s.as_deref(), Some("af"));
+    }
+
+    fn s3_storage(config: Value) -> S3Storage {
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn test_s3_keys() {
+        let s3 = s3_storage(json!({"Bucket": "certs", "Prefix": "tls/"}));
+        assert_eq!(s3.key(S3Object::Certificate, "example.com"), "tls/cert.pem");
+        assert_eq!(s3.key(S3Object::AlternateFullChain, "example.com"), "tls/fullchain-alternate.pem");
+        assert_eq!(s3.key(S3Object::Pkcs12, "example.com"), "tls/certificate.pfx");
+        assert_eq!(s3.primary_key("example.com"), "tls/cert.pem");
+
+        let s3 = s3_storage(json!({
+            "Bucket": "certs",
+            "Prefix": "tls/",
+            "KeyTemplate": "{Prefix}{Domain}/{FileName}",
+            "Keys": {"FullChain": "{Prefix}{Domain}.crt", "PrivateKey": "keys/{Domain}.key"},
+        }));
+        assert_eq!(s3.key(S3Object::Certificate, "*.example.com"), "tls/_.example.com/cert.pem");
+        assert_eq!(s3.key(S3Object::FullChain, "*.example.com"), "tls/_.example.com.crt");
+        assert_eq!(s3.key(S3Object::PrivateKey, "www.example.com"), "keys/www.example.com.key");
+
+        let s3 = s3_storage(json!({"Bucket": "certs", "OutputFormats": ["Der"]}));
+        assert_eq!(s3.primary_key("example.com"), "cert.der");
+    }
+
+    #[test]
+    fn test_s3_validate_keys() {
+        assert!(s3_storage(json!({"Bucket": "certs"})).validate_keys().is_ok());
+        assert!(s3_storage(json!({"Bucket": "certs", "Bundle": true})).validate_keys().is_ok());
+
+        let e =
+            s3_storage(json!({"Bucket": "certs", "KeyTemplate": "{Prefix}{Domain}.pem"})).validate_keys().unwrap_err();
+        assert!(e.to_string().contains("KeyTemplate must contain {FileName}"));
+
+        let e =
+            s3_storage(json!({"Bucket": "certs", "Keys": {"Chain": "{Prefix}cert.pem"}})).validate_keys().unwrap_err();
+        assert!(e.to_string().contains("Every object must have a distinct key"));
+
+        let e = s3_storage(json!({"Bucket": "certs", "Keys": {"Chain": ""}})).validate_keys().unwrap_err();
+        assert!(e.to_string().contains("Object keys cannot be empty"));
+
+        let e = s3_storage(json!({"Bucket": "certs", "Bundle": true, "AllowPrivateKey": false}))
+            .validate_keys()
+            .unwrap_err();
+        assert!(e.to_string().contains("Bundle requires"));
+
+        let e = s3_storage(json!({"Bucket": "certs", "Bundle": true, "OutputFormats": ["Der"]}))
+            .validate_keys()
+            .unwrap_err();
+        assert!(e.to_string().contains("Bundle requires"));
+    }
+
+    #[test]
+    fn test_s3_tag_value() {
+        assert_eq!(s3_tag_value("example.com"), "example.com");
+        assert_eq!(s3_tag_value("*.example.com"), "_.example.com");
+        assert_eq!(s3_tag_value("R3 (Let's Encrypt)"), "R3 _Let_s Encrypt_");
+        assert_eq!(s3_tag_value(&"a".repeat(300)).len(), S3_MAX_TAG_VALUE_LEN);
+    }
+
+    #[test]
+    fn test_s3_validate_tags() {
+        let tags: Map<String, Value> = (0..10).map(|i| (format!("Tag{}", i), json!("value"))).collect();
+        assert!(s3_storage(json!({"Bucket": "certs", "Tags": tags})).validate_tags().is_ok());
+
+        let e = s3_storage(json!({"Bucket": "certs", "Tags": tags, "TagCertificateInfo": true}))
+            .validate_tags()
+            .unwrap_err();
+        assert!(e.to_string().contains("At most 10 tags"));
+
+        let tags: Map<String, Value> = (0..6).map(|i| (format!("Tag{}", i), json!("value"))).collect();
+        assert!(s3_storage(json!({"Bucket": "certs", "Tags": tags, "TagCertificateInfo": true}))
+            .validate_tags()
+            .is_ok());
+
+        for tags in [
+            json!({"": "value"}),
+            json!({"Bad*Key": "value"}),
+            json!({"aws:createdBy": "value"}),
+            json!({"Team": "a*b"}),
+            json!({"Team": "a".repeat(S3_MAX_TAG_VALUE_LEN + 1)}),
+        ] {
+            assert!(s3_storage(json!({"Bucket": "certs", "Tags": tags})).validate_tags().is_err());
+        }
+
+        let e = s3_storage(json!({"Bucket": "certs", "Tags": {"CertificateSerial": "1"}, "TagCertificateInfo": true}))
+            .validate_tags()
+            .unwrap_err();
+        assert!(e.to_string().contains("set by TagCertificateInfo"));
+        assert!(s3_storage(json!({"Bucket": "certs", "Tags": {"CertificateSerial": "1"}})).validate_tags().is_ok());
+    }
+
+    #[test]
+    fn test_s3_object_attributes() {
+        let components = self_signed_components("*.example.com");
+        let info = CertificateInfo::from_pem(&components.cert_pem).unwrap();
+        let domain_names = vec!["*.example.com".to_string(), "example.com".to_string()];
+
+        let s3 = s3_storage(json!({"Bucket": "certs"}));
+        let attributes = s3.object_attributes(&domain_names, &components, "fingerprint").unwrap();
+        assert_eq!(attributes.metadata["certificate-writer"], "fingerprint");
+        assert_eq!(attributes.metadata["certificate-domain-names"], "*.example.com example.com");
+        assert_eq!(attributes.metadata["certificate-serial"], info.serial);
+        assert_eq!(attributes.metadata["certificate-issuer"], "*.example.com");
+        assert!(attributes.metadata["certificate-not-after"].ends_with('Z'));
+        assert!(attributes.tagging.is_none());
+
+        let s3 = s3_storage(json!({"Bucket": "certs", "Tags": {"Team": "web ops"}, "TagCertificateInfo": true}));
+        let attributes = s3.object_attributes(&domain_names, &components, "fingerprint").unwrap();
+        let tagging = attributes.tagging.unwrap();
+        assert!(tagging.starts_with("Team=web+ops&CertificateDomainName=_.example.com&CertificateNotAfter="));
+        assert!(tagging.ends_with(&format!("&CertificateSerial={}&CertificateIssuer=_.example.com", info.serial)));
+
+        // Only as many domain names as fit in the metadata limit are listed.
+        let domain_names: Vec<String> = (0..100).map(|i| format!("host{:02}.example.com", i)).collect();
+        let attributes = s3.object_attributes(&domain_names, &components, "fingerprint").unwrap();
+        let listed = &attributes.metadata["certificate-domain-names"];
+        assert!(listed.len() <= S3_MAX_METADATA_DOMAIN_NAMES_LEN);
+        assert!(listed.starts_with("host00.example.com host01.example.com"));
+        assert!(!listed.contains("host99"));
+    }
+
+    #[test]
+    fn test_is_archive_timestamp() {
+        assert!(is_archive_timestamp("20240131T120000Z"));
+        assert!(!is_archive_timestamp("20240131T120000"));
+        assert!(!is_archive_timestamp("20240131T1200000Z"));
+        assert!(!is_archive_timestamp("20240131-120000Z"));
+        assert!(!is_archive_timestamp("2024013aT120000Z"));
+        assert!(!is_archive_timestamp("pem.20240131T12Z"));
+        assert!(!is_archive_timestamp(""));
+    }
+
+    #[test]
+    fn test_expired_versions() {
+        let versions = vec!["2024-03-01", "2024-01-01", "2024-04-01", "2024-02-01"];
+
+        // The current version is always kept, along with KeepPrevious older ones; the oldest go first.
+        assert_eq!(expired_versions(versions.clone(), 0, |v| *v), vec!["2024-03-01", "2024-02-01", "2024-01-01"]);
+        assert_eq!(expired_versions(versions.clone(), 2, |v| *v), vec!["2024-01-01"]);
+        assert!(expired_versions(versions.clone(), 3, |v| *v).is_empty());
+        assert!(expired_versions(versions, 10, |v| *v).is_empty());
+        assert!(expired_versions(Vec::<&str>::new(), 0, |v| *v).is_empty());
+    }
+
+    #[test]
+    fn test_s3_retention_archive_suffix() {
+        let components = self_signed_components("example.com");
+        let domain_names = vec!["example.com".to_string()];
+
+        let s3 = s3_storage(json!({"Bucket": "certs", "Retention": {"Mode": "TimestampedKeys", "KeepPrevious": 2}}));
+        let suffix = s3.object_attributes(&domain_names, &components, "fingerprint").unwrap().archive_suffix.unwrap();
+        assert!(suffix.starts_with('.'));
+        assert!(is_archive_timestamp(&suffix[1..]));
+
+        let s3 = s3_storage(json!({"Bucket": "certs", "Retention": {"Mode": "Versioning", "KeepPrevious": 2}}));
+        assert!(s3.object_attributes(&domain_names, &components, "fingerprint").unwrap().archive_suffix.is_none());
+        assert!(s3_storage(json!({"Bucket": "certs"}))
+            .object_attributes(&domain_names, &components, "fingerprint")
+            .unwrap()
+            .archive_suffix
+            .is_none());
+    }
+
+    #[test]
+    fn test_parse_file_mode() {
+        assert_eq!(parse_file_mode("0640"), Some(0o640));
+        assert_eq!(parse_file_mode("755"), Some(0o755));
+        assert_eq!(parse_file_mode("4755"), Some(0o4755));
+        assert_eq!(parse_file_mode("7777"), Some(0o7777));
+        assert_eq!(parse_file_mode("17777"), None);
+        assert_eq!(parse_file_mode("0648"), None);
+        assert_eq!(parse_file_mode("rw-r--r--"), None);
+        assert_eq!(parse_file_mode(""), None);
+    }
+
+    #[tokio::test]
+    async fn test_file_validate() {
+        assert!(file_storage(json!({"Directory": "/mnt/certs/{Domain}"})).validate().await.is_ok());
+
+        let e = file_storage(json!({"Directory": "certs"})).validate().await.unwrap_err();
+        assert!(e.to_string().contains("Directory must be an absolute path"), "{}", e);
+
+        let e = file_storage(json!({"Directory": "/certs", "RoleArn": "arn:aws:iam::123456789012:role/certs"}))
+            .validate()
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("RoleArn cannot be used"), "{}", e);
+
+        let e = file_storage(json!({"Directory": "/certs", "PrivateKeyMode": "0800"})).validate().await.unwrap_err();
+        assert!(e.to_string().contains("Invalid PrivateKeyMode: 0800"), "{}", e);
+
+        for name in ["", ".", "..", "keys/privkey.pem"] {
+            let e = file_storage(json!({"Directory": "/certs", "FileNames": {"PrivateKey": name}}))
+                .validate()
+                .await
+                .unwrap_err();
+            assert!(e.to_string().contains("cannot contain directories"), "{}: {}", name, e);
+        }
+
+        let e = file_storage(json!({"Directory": "/certs", "FileNames": {"Chain": "cert.pem"}}))
+            .validate()
+            .await
+            .unwrap_err();
+        assert!(e.to_string().contains("FileNames must be distinct"), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn test_file_save_certificate() {
+        let root = std::env::temp_dir().join(format!("file-storage-test-{}", std::process::id()));
+        let mut storage = file_storage(json!({
+            "Directory": format!("{}/{{Domain}}", root.display()),
+            "FileMode": "0640",
+            "FileNames": {"PrivateKey": "key.pem"},
+        }));
+        storage.validate().await.unwrap();
+
+        let results = storage.save_certificate(vec!["*.example.com".to_string()], components(4)).await.unwrap();
+        let directory = root.join("_.example.com");
+        let mode = |name: &str| std::fs::metadata(directory.join(name)).unwrap().permissions().mode() & 0o7777;
+        assert_eq!(std::fs::read_to_string(directory.join("cert.pem")).unwrap(), "cccc");
+        assert_eq!(std::fs::read_to_string(directory.join("key.pem")).unwrap(), "kkkk");
+        assert_eq!(mode("cert.pem"), 0o640);
+        assert_eq!(mode("fullchain.pem"), 0o640);
+        assert_eq!(mode("key.pem"), 0o600);
+        assert!(!directory.join("alt-chain.pem").exists());
+        assert!(!directory.join(".cert.pem.tmp").exists());
+
+        match &results[..] {
+            [CertificateStorageResult::File(result)] => {
+                assert_eq!(result.pkey.as_deref(), Some(directory.join("key.pem").to_str().unwrap()));
+                assert!(result.alternate_chain.is_none());
+            }
+            _ => panic!("Unexpected results: {:?}", results),
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_powershell_quote() {
+        assert_eq!(powershell_quote("Default Web Site"), "'Default Web Site'");
+        assert_eq!(powershell_quote("it's"), "'it''s'");
+        assert_eq!(powershell_quote("$(Remove-Item C:\\)"), "'$(Remove-Item C:\\)'");
+    }
+
+    #[tokio::test]
+    async fn test_windows_validate() {
+        let mut storage = windows_storage(json!({"InstanceIds": ["i-0123456789abcdef0"]}));
+        storage.validate().await.unwrap();
+        assert_eq!(storage.get_parameter_name("*.example.com"), "/Certificate/Windows/_.example.com");
+        assert_eq!(storage.store_name, "My");
+        assert_eq!(storage.timeout_seconds, 300);
+
+        let targets = windows_storage(json!({"Targets": [{"Key": "tag:Role", "Values": "web"}]}));
+        assert_eq!(targets.targets[0].values, vec!["web".to_string()]);
+
+        let instance_ids: Vec<String> = (0..51).map(|i| format!("i-{:017x}", i)).collect();
+        for (config, message) in [
+            (json!({}), "Exactly one of InstanceIds or Targets"),
+            (
+                json!({"InstanceIds": ["i-1"], "Targets": [{"Key": "tag:Role", "Values": ["web"]}]}),
+                "Exactly one of InstanceIds or Targets",
+            ),
+            (json!({"InstanceIds": instance_ids}), "At most 50 InstanceIds"),
+            (json!({"Targets": [{"Key": "tag:Role", "Values": []}]}), "Each target must have a Key"),
+            (json!({"InstanceIds": ["i-1"], "ParameterName": "Windows/{Domain}"}), "Invalid ParameterName"),
+            (json!({"InstanceIds": ["i-1"], "StoreName": "Root"}), "StoreName must be My or WebHosting"),
+            (json!({"InstanceIds": ["i-1"], "IisBindings": [{"Site": "", "Port": 443}]}), "Each IIS binding"),
+            (json!({"InstanceIds": ["i-1"], "TimeoutSeconds": 0}), "TimeoutSeconds must be between 1 and 900"),
+            (json!({"InstanceIds": ["i-1"], "TimeoutSeconds": 901}), "TimeoutSeconds must be between 1 and 900"),
+        ] {
+            let e = windows_storage(config.clone()).validate().await.unwrap_err();
+            assert!(e.to_string().contains(message), "{}: {}", config, e);
+        }
+    }
+
+    #[test]
+    fn test_windows_script() {
+        let storage = windows_storage(json!({
+            "InstanceIds": ["i-1"],
+            "StoreName": "WebHosting",
+            "Region": "us-west-2",
+            "IisBindings": [
+                {"Site": "Default Web Site"},
+                {"Site": "O'Brien", "Port": 8443, "HostHeader": "www.example.com"},
+            ],
+        }));
+        let script = storage.script("/Certificate/Windows/example.com", "ABCDEF");
+        let lines: Vec<&str> = script.lines().collect();
+
+        assert!(lines.contains(
+            &"$bundle = (Get-SSMParameter -Name '/Certificate/Windows/example.com' -WithDecryption $true -Region \
+               'us-west-2').Value | ConvertFrom-Json"
+        ));
+        assert!(script.contains("-CertStoreLocation 'Cert:\\LocalMachine\\WebHosting'"), "{}", script);
+        assert!(lines.contains(&"Import-Module WebAdministration"));
+        assert!(lines.contains(
+            &"if (-not (Get-WebBinding -Name 'Default Web Site' -Protocol https -Port 443 -HostHeader '')) { \
+               New-WebBinding -Name 'Default Web Site' -Protocol https -Port 443 -HostHeader '' -SslFlags 0 }"
+        ));
+        assert!(lines.contains(
+            &"(Get-WebBinding -Name 'O''Brien' -Protocol https -Port 8443 -HostHeader 'www.example.com')\
+               .AddSslCertificate('ABCDEF', 'WebHosting')"
+        ));
+        assert!(script.contains("-HostHeader 'www.example.com' -SslFlags 1 }"), "{}", script);
+
+        let no_bindings = windows_storage(json!({"InstanceIds": ["i-1"]}));
+        assert!(!no_bindings.script("/p", "ABCDEF").contains("WebAdministration"));
+    }
+
+    #[test]
+    fn test_windows_bundle() {
+        let components = self_signed_components("example.com");
+        let bundle = WindowsStorage::build_bundle(&components, "example.com").unwrap();
+        assert!(!bundle.password.is_empty());
+
+        let pfx = Pkcs12::from_der(&base64::decode(&bundle.pfx).unwrap()).unwrap();
+        assert!(pfx.parse(&format!("{}x", bundle.password)).is_err());
+
+        let parsed = pfx.parse(&bundle.password).unwrap();
+        assert_eq!(parsed.cert.to_pem().unwrap(), components.cert_pem.as_bytes());
+        assert_eq!(parsed.pkey.private_key_to_pem_pkcs8().unwrap(), components.pkey_pem.as_bytes());
+        assert_eq!(parsed.chain.map(|chain| chain.len()), Some(1));
+    }
 }
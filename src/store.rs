@@ -0,0 +1,279 @@
+use {
+    crate::{
+        assume_role::AssumeRole,
+        dry_run::PlannedWrite,
+        keys::KeyAlgorithm,
+        kubernetes::KubernetesStorage,
+        reconcile::ObservedCertificate,
+        storage::{
+            AcmStorage, ApiGatewayStorage, CertificateStorageResult, CloudFrontStorage, DynamoDbStorage, FileStorage,
+            IamServerCertificateStorage, LoadBalancerStorage, S3Storage, SecretsManagerStorage, SsmParameterStorage,
+            WindowsStorage,
+        },
+        utils::CertificateComponents,
+    },
+    async_trait::async_trait,
+    lambda_runtime::Error as LambdaError,
+    lazy_static::lazy_static,
+    schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema},
+    serde::{de::DeserializeOwned, Serialize},
+    serde_json::Value,
+    std::{collections::HashMap, fmt::Debug, sync::RwLock},
+};
+
+/// A backend that certificates can be stored in. Each storage type in a request's Storage list is deserialized
+/// into a CertificateStore through the registry below, keyed by its "Type".
+///
+/// To add a backend, implement this trait on a type that can be deserialized from its JSON configuration and add it
+/// to builtin_stores(), or call register_store() (or register_store_with_schema(), to describe its configuration in
+/// the Schema action's output) before handling requests.
+#[async_trait]
+pub trait CertificateStore: StoreObject + Debug + Send + Sync {
+    /// The value of "Type" that selects this store in JSON.
+    fn type_name(&self) -> &'static str;
+
+    /// Returns the role assumed to access this store.
+    fn assume_role(&self) -> &AssumeRole;
+
+    /// Indicates whether the private key may be written to this store.
+    fn allows_private_key(&self) -> bool {
+        true
+    }
+
+    /// Expand a store that is replicated across regions into one store per region. Stores that aren't replicated
+    /// return None.
+    fn expand_regions(&self) -> Result<Option<Vec<Box<dyn CertificateStore>>>, LambdaError> {
+        Ok(None)
+    }
+
+    /// Check the configuration, filling in defaults that require AWS calls.
+    async fn validate(&mut self) -> Result<(), LambdaError>;
+
+    /// Check that a certificate with the given key algorithm can be used by this store. This is called after
+    /// validate() and before a certificate is issued; errors should say which algorithms would work.
+    async fn check_key_algorithm(&self, _key_algorithm: KeyAlgorithm) -> Result<(), LambdaError> {
+        Ok(())
+    }
+
+    /// Describe the writes save_certificate() would make for the given domain names, for dry runs. This must not
+    /// make any AWS calls. alternate_chain indicates whether the alternate chain would be stored alongside the
+    /// default one. The default describes the store as a whole.
+    fn planned_writes(&self, _domain_names: &[String], _alternate_chain: bool) -> Vec<PlannedWrite> {
+        vec![PlannedWrite::new(format!("{}:SaveCertificate", self.type_name()), "(certificate)")]
+    }
+
+    /// Write the certificate to the store.
+    async fn save_certificate(
+        &self,
+        domain_names: Vec<String>,
+        components: CertificateComponents,
+    ) -> Result<Vec<CertificateStorageResult>, LambdaError>;
+
+    /// Observe the certificate currently held by the store. This returns None if no certificate (or only an
+    /// incomplete set of components) is present.
+    async fn observe(&self, domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError>;
+}
+
+/// Object-safe cloning and serialization for stores. This is implemented automatically for any CertificateStore
+/// that is Clone and Serialize.
+pub trait StoreObject {
+    fn clone_store(&self) -> Box<dyn CertificateStore>;
+
+    /// Serialize the store's configuration, without its "Type".
+    fn to_json(&self) -> Result<Value, serde_json::Error>;
+}
+
+impl<T> StoreObject for T
+where
+    T: CertificateStore + Clone + Serialize + 'static,
+{
+    fn clone_store(&self) -> Box<dyn CertificateStore> {
+        Box::new(self.clone())
+    }
+
+    fn to_json(&self) -> Result<Value, serde_json::Error> {
+        serde_json::to_value(self)
+    }
+}
+
+/// Creates a store from its JSON configuration.
+pub type StoreFactory = fn(Value) -> Result<Box<dyn CertificateStore>, serde_json::Error>;
+
+/// Describes a store's JSON configuration (without its "Type") as a JSON schema.
+pub type StoreSchema = fn(&mut SchemaGenerator) -> Schema;
+
+#[derive(Clone, Copy)]
+struct StoreRegistration {
+    factory: StoreFactory,
+    schema: Option<StoreSchema>,
+}
+
+lazy_static! {
+    static ref REGISTRY: RwLock<HashMap<String, StoreRegistration>> = RwLock::new(builtin_stores());
+}
+
+/// A StoreFactory for any store that can be deserialized from its configuration.
+pub fn store_factory<T>(value: Value) -> Result<Box<dyn CertificateStore>, serde_json::Error>
+where
+    T: CertificateStore + DeserializeOwned + 'static,
+{
+    Ok(Box::new(serde_json::from_value::<T>(value)?))
+}
+
+/// A StoreSchema for any store whose configuration implements JsonSchema.
+pub fn store_schema<T: JsonSchema>(gen: &mut SchemaGenerator) -> Schema {
+    T::json_schema(gen)
+}
+
+fn builtin<T>() -> StoreRegistration
+where
+    T: CertificateStore + DeserializeOwned + JsonSchema + 'static,
+{
+    StoreRegistration {
+        factory: store_factory::<T>,
+        schema: Some(store_schema::<T>),
+    }
+}
+
+fn builtin_stores() -> HashMap<String, StoreRegistration> {
+    let mut stores: HashMap<String, StoreRegistration> = HashMap::new();
+    stores.insert("Acm".to_string(), builtin::<AcmStorage>());
+    stores.insert("ApiGateway".to_string(), builtin::<ApiGatewayStorage>());
+    stores.insert("CloudFront".to_string(), builtin::<CloudFrontStorage>());
+    stores.insert("DynamoDb".to_string(), builtin::<DynamoDbStorage>());
+    stores.insert("File".to_string(), builtin::<FileStorage>());
+    stores.insert("IamServerCertificate".to_string(), builtin::<IamServerCertificateStorage>());
+    stores.insert("Kubernetes".to_string(), builtin::<KubernetesStorage>());
+    stores.insert("LoadBalancer".to_string(), builtin::<LoadBalancerStorage>());
+    stores.insert("S3".to_string(), builtin::<S3Storage>());
+    stores.insert("SecretsManager".to_string(), builtin::<SecretsManagerStorage>());
+    stores.insert("SsmParameter".to_string(), builtin::<SsmParameterStorage>());
+    stores.insert("Windows".to_string(), builtin::<WindowsStorage>());
+    stores
+}
+
+/// Register a store under a "Type", replacing any existing store of that type. Nothing in this crate calls this;
+/// it's the extension point for builds that add their own stores. The store's configuration is described as an
+/// arbitrary object in the Schema action's output.
+pub fn register_store<S: Into<String>>(type_name: S, factory: StoreFactory) {
+    let registration = StoreRegistration {
+        factory,
+        schema: None,
+    };
+    REGISTRY.write().expect("Store registry lock poisoned").insert(type_name.into(), registration);
+}
+
+/// Register a store under a "Type" along with the schema of its configuration.
+pub fn register_store_with_schema<S: Into<String>>(type_name: S, factory: StoreFactory, schema: StoreSchema) {
+    let registration = StoreRegistration {
+        factory,
+        schema: Some(schema),
+    };
+    REGISTRY.write().expect("Store registry lock poisoned").insert(type_name.into(), registration);
+}
+
+/// Returns the factory for a "Type", if one is registered.
+pub fn lookup_store(type_name: &str) -> Option<StoreFactory> {
+    REGISTRY.read().expect("Store registry lock poisoned").get(type_name).map(|registration| registration.factory)
+}
+
+/// Returns the registered types and their schemas, sorted by type.
+pub fn registered_store_schemas() -> Vec<(String, Option<StoreSchema>)> {
+    let mut schemas: Vec<(String, Option<StoreSchema>)> = REGISTRY
+        .read()
+        .expect("Store registry lock poisoned")
+        .iter()
+        .map(|(type_name, registration)| (type_name.clone(), registration.schema))
+        .collect();
+    schemas.sort_by(|a, b| a.0.cmp(&b.0));
+    schemas
+}
+
+/// Returns the registered types, sorted, for error messages.
+pub fn registered_store_types() -> Vec<String> {
+    let mut types: Vec<String> = REGISTRY.read().expect("Store registry lock poisoned").keys().cloned().collect();
+    types.sort();
+    types
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{register_store, store_factory, CertificateStore},
+        crate::{
+            assume_role::AssumeRole,
+            chains::ChainVariant,
+            reconcile::ObservedCertificate,
+            storage::{CertificateStorage, CertificateStorageResult},
+            utils::CertificateComponents,
+        },
+        async_trait::async_trait,
+        lambda_runtime::Error as LambdaError,
+        serde::{Deserialize, Serialize},
+        serde_json::json,
+    };
+
+    #[derive(Clone, Debug, Default, Deserialize, Serialize)]
+    struct MockStore {
+        #[serde(rename = "Name")]
+        name: String,
+
+        #[serde(flatten)]
+        assume_role: AssumeRole,
+    }
+
+    #[async_trait]
+    impl CertificateStore for MockStore {
+        fn type_name(&self) -> &'static str {
+            "Mock"
+        }
+
+        fn assume_role(&self) -> &AssumeRole {
+            &self.assume_role
+        }
+
+        fn allows_private_key(&self) -> bool {
+            false
+        }
+
+        async fn validate(&mut self) -> Result<(), LambdaError> {
+            Ok(())
+        }
+
+        async fn save_certificate(
+            &self,
+            _domain_names: Vec<String>,
+            components: CertificateComponents,
+        ) -> Result<Vec<CertificateStorageResult>, LambdaError> {
+            assert!(components.pkey_pem.is_empty());
+            Ok(vec![])
+        }
+
+        async fn observe(&self, _domain_names: &[String]) -> Result<Option<ObservedCertificate>, LambdaError> {
+            Ok(None)
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let config = json!({"Type": "Mock", "Name": "test"});
+        assert!(serde_json::from_value::<CertificateStorage>(config.clone()).is_err());
+
+        register_store("Mock", store_factory::<MockStore>);
+        let storage: CertificateStorage = serde_json::from_value(config.clone()).unwrap();
+        assert!(!storage.allows_private_key());
+        assert_eq!(serde_json::to_value(&storage).unwrap(), config);
+
+        let storage: CertificateStorage = serde_json::from_value(json!({"Type": "S3", "Bucket": "b"})).unwrap();
+        assert!(storage.allows_private_key());
+        assert!(serde_json::from_value::<CertificateStorage>(json!({"Bucket": "b"})).is_err());
+
+        let config = json!({"Type": "S3", "Bucket": "b", "Chain": "Alternate"});
+        let storage: CertificateStorage = serde_json::from_value(config.clone()).unwrap();
+        assert_eq!(storage.chain(), ChainVariant::Alternate);
+        assert_eq!(serde_json::to_value(&storage).unwrap()["Chain"], config["Chain"]);
+        assert!(serde_json::from_value::<CertificateStorage>(json!({"Type": "S3", "Bucket": "b", "Chain": "Short"}))
+            .is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use {
+    crate::{
+        events::{CertificateResponse, CertificateResponseStatus},
+        storage::CertificateStorageResult,
+    },
+    lambda_runtime::Error as LambdaError,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+};
+
+/// The Error of a storage target that failed to write.
+pub const STORAGE_WRITE_FAILED: &str = "StorageWriteFailed";
+
+/// The Error of a storage target whose deployment failed verification and was reverted to the previous certificate.
+pub const DEPLOYMENT_ROLLED_BACK: &str = "DeploymentRolledBack";
+
+/// The Error of a run in which one or more storage targets failed.
+pub const STORAGE_TARGETS_FAILED: &str = "StorageTargetsFailed";
+
+/// The Error of a run that didn't start because another invocation holds the renewal lock for its domain names.
+pub const ALREADY_IN_PROGRESS: &str = "AlreadyInProgress";
+
+/// The outcome of a finished run, for Step Functions Choice states and other callers that need a single value to
+/// branch on. In JSON, the "TaskStatus" key of a response:
+///
+/// * `"Succeeded"`: every storage target that needed to be written was written (or none needed to be), or the
+///   action (e.g. Audit or Preflight) passed.
+/// * `"PartialFailure"`: some storage targets were written and others failed. FailedStorageIndexes lists the
+///   failed targets; pass it as the StorageIndexes of the next request to retry just those.
+/// * `"Failed"`: nothing was written, or the action failed.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum TaskStatus {
+    Succeeded,
+    PartialFailure,
+    Failed,
+}
+
+impl TaskStatus {
+    /// Returns the status of a run that wrote `succeeded` storage targets and failed to write `failed` of them.
+    pub fn new(succeeded: usize, failed: usize) -> Self {
+        match (succeeded, failed) {
+            (_, 0) => Self::Succeeded,
+            (0, _) => Self::Failed,
+            _ => Self::PartialFailure,
+        }
+    }
+
+    /// Returns the status of a response that didn't write any storage targets, or None if the request isn't finished.
+    pub fn from_status(status: &CertificateResponseStatus) -> Option<Self> {
+        match status {
+            CertificateResponseStatus::Success
+            | CertificateResponseStatus::Skipped
+            | CertificateResponseStatus::DryRun => Some(Self::Succeeded),
+            CertificateResponseStatus::PartialSuccess => Some(Self::PartialFailure),
+            CertificateResponseStatus::RolledBack
+            | CertificateResponseStatus::AlreadyInProgress
+            | CertificateResponseStatus::Failed => Some(Self::Failed),
+            CertificateResponseStatus::PendingValidation | CertificateResponseStatus::PendingOrderFulfillment => None,
+        }
+    }
+
+    /// Fill in the TaskStatus of a finished response that doesn't have one yet.
+    pub fn complete(response: &mut CertificateResponse) {
+        if response.task_status.is_none() && response.finished {
+            response.task_status = Self::from_status(&response.status);
+        }
+    }
+}
+
+/// An error in the form Step Functions uses for task failures, so Catch-style logic in Choice states can match on
+/// Error. In JSON:
+///
+///     {
+///         // A stable identifier for the kind of failure: "StorageWriteFailed", "DeploymentRolledBack", or (for the
+///         // response as a whole) "StorageTargetsFailed" or "AlreadyInProgress".
+///         "Error": str,
+///
+///         // A human-readable description of what went wrong.
+///         "Cause": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, PartialEq, Serialize)]
+pub struct TaskError {
+    #[serde(rename = "Error")]
+    pub error: String,
+
+    #[serde(rename = "Cause")]
+    pub cause: String,
+}
+
+impl TaskError {
+    pub fn new<E: Into<String>, C: Into<String>>(error: E, cause: C) -> Self {
+        Self {
+            error: error.into(),
+            cause: cause.into(),
+        }
+    }
+}
+
+/// Whether a storage target was written. In JSON, one of `"Succeeded"` or `"Failed"`.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum TargetStatus {
+    Succeeded,
+    Failed,
+}
+
+/// The result of writing one storage target. In JSON:
+///
+///     {
+///         // The index of the target in the request's Storage list (after replicas are expanded).
+///         "StorageIndex": int,
+///
+///         // The target's "Type".
+///         "Type": str,
+///
+///         // "Succeeded" or "Failed".
+///         "Status": str,
+///
+///         // What the target wrote, in the same form as the response's StorageResults.
+///         "Results": [{ ... }, ...],
+///
+///         // If the target failed, why. See TaskError.
+///         "Error": { ... },
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct StorageTargetResult {
+    #[serde(rename = "StorageIndex")]
+    pub storage_index: usize,
+
+    #[serde(rename = "Type")]
+    pub type_name: String,
+
+    #[serde(rename = "Status")]
+    pub status: TargetStatus,
+
+    #[serde(rename = "Results", default)]
+    pub results: Vec<CertificateStorageResult>,
+
+    #[serde(rename = "Error", default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<TaskError>,
+}
+
+impl StorageTargetResult {
+    /// Summarize the result of saving the certificate to a storage target. The target failed if saving it returned
+    /// an error, if any of its results is an error, or if its deployment was rolled back.
+    pub fn new(
+        storage_index: usize,
+        type_name: &str,
+        result: Result<Vec<CertificateStorageResult>, LambdaError>,
+    ) -> Self {
+        let (results, error) = match result {
+            Err(e) => {
+                let cause = format!("Failed to save certificate: {:#}", e);
+                (
+                    vec![CertificateStorageResult::Error(cause.clone())],
+                    Some(TaskError::new(STORAGE_WRITE_FAILED, cause)),
+                )
+            }
+            Ok(results) => {
+                let causes: Vec<&str> = results
+                    .iter()
+                    .filter_map(|result| match result {
+                        CertificateStorageResult::Error(e) => Some(e.as_str()),
+                        _ => None,
+                    })
+                    .collect();
+
+                let error = if results.iter().any(|result| result.rolled_back()) {
+                    Some(TaskError::new(
+                        DEPLOYMENT_ROLLED_BACK,
+                        "The deployment failed verification and was rolled back",
+                    ))
+                } else if !causes.is_empty() {
+                    Some(TaskError::new(STORAGE_WRITE_FAILED, causes.join("; ")))
+                } else {
+                    None
+                };
+
+                (results, error)
+            }
+        };
+
+        Self {
+            storage_index,
+            type_name: type_name.to_string(),
+            status: if error.is_some() {
+                TargetStatus::Failed
+            } else {
+                TargetStatus::Succeeded
+            },
+            results,
+            error,
+        }
+    }
+
+    pub fn failed(&self) -> bool {
+        self.status == TargetStatus::Failed
+    }
+}
+
+/// Returns the error for a run in which some storage targets failed, or None if all of them succeeded.
+pub fn targets_error(targets: &[StorageTargetResult]) -> Option<TaskError> {
+    let failed: Vec<String> = targets
+        .iter()
+        .filter(|target| target.failed())
+        .map(|target| {
+            let cause = target.error.as_ref().map(|error| error.cause.as_str()).unwrap_or_default();
+            format!("Storage[{}] ({}): {}", target.storage_index, target.type_name, cause)
+        })
+        .collect();
+
+    if failed.is_empty() {
+        return None;
+    }
+
+    Some(TaskError::new(
+        STORAGE_TARGETS_FAILED,
+        format!("{} of {} storage targets failed: {}", failed.len(), targets.len(), failed.join("; ")),
+    ))
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{
+            targets_error, StorageTargetResult, TargetStatus, TaskStatus, DEPLOYMENT_ROLLED_BACK,
+            STORAGE_TARGETS_FAILED, STORAGE_WRITE_FAILED,
+        },
+        crate::{events::CertificateResponseStatus, storage::CertificateStorageResult},
+        lambda_runtime::Error as LambdaError,
+    };
+
+    #[test]
+    fn test_task_status() {
+        assert_eq!(TaskStatus::new(0, 0), TaskStatus::Succeeded);
+        assert_eq!(TaskStatus::new(2, 0), TaskStatus::Succeeded);
+        assert_eq!(TaskStatus::new(1, 1), TaskStatus::PartialFailure);
+        assert_eq!(TaskStatus::new(0, 2), TaskStatus::Failed);
+
+        assert_eq!(TaskStatus::from_status(&CertificateResponseStatus::Skipped), Some(TaskStatus::Succeeded));
+        assert_eq!(TaskStatus::from_status(&CertificateResponseStatus::Failed), Some(TaskStatus::Failed));
+        assert_eq!(TaskStatus::from_status(&CertificateResponseStatus::PendingValidation), None);
+        assert_eq!(serde_json::to_value(TaskStatus::PartialFailure).unwrap(), "PartialFailure");
+    }
+
+    #[test]
+    fn test_storage_target_result() {
+        let failed: Result<Vec<CertificateStorageResult>, LambdaError> = Err("AccessDenied".into());
+        let targets = vec![
+            StorageTargetResult::new(0, "File", Ok(vec![])),
+            StorageTargetResult::new(1, "S3", failed),
+            StorageTargetResult::new(2, "Acm", Ok(vec![CertificateStorageResult::Error("Throttled".to_string())])),
+        ];
+
+        assert_eq!(targets[0].status, TargetStatus::Succeeded);
+        assert!(targets[0].error.is_none());
+
+        assert_eq!(targets[1].status, TargetStatus::Failed);
+        let error = targets[1].error.as_ref().unwrap();
+        assert_eq!(error.error, STORAGE_WRITE_FAILED);
+        assert_eq!(error.cause, "Failed to save certificate: AccessDenied");
+        assert_eq!(targets[1].results.len(), 1);
+
+        assert_eq!(targets[2].error.as_ref().unwrap().cause, "Throttled");
+
+        let error = targets_error(&targets).unwrap();
+        assert_eq!(error.error, STORAGE_TARGETS_FAILED);
+        assert!(error.cause.starts_with("2 of 3 storage targets failed: Storage[1] (S3)"));
+        assert!(targets_error(&targets[..1]).is_none());
+    }
+}
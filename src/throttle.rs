@@ -0,0 +1,36 @@
+use {
+    crate::{
+        constants::{DEFAULT_AWS_MAX_CONCURRENCY, ENV_AWS_MAX_CONCURRENCY},
+        retry::{classify_aws_error, env_setting, retry},
+    },
+    lazy_static::lazy_static,
+    rusoto_core::RusotoError,
+    std::future::Future,
+    tokio::sync::Semaphore,
+};
+
+// Certificate requests fan out into many AWS calls at once (describing every candidate ACM certificate, reimporting
+// over several ARNs, writing each component to SSM, ...), and batches multiply this. Every call made through `call`
+// below shares a process-wide limit on the number of calls in flight, set by the AwsMaxConcurrency environment
+// variable (8 by default), and failed calls are retried according to the retry policy in effect; see RetryConfig.
+
+lazy_static! {
+    static ref IN_FLIGHT: Semaphore = Semaphore::new(env_setting(ENV_AWS_MAX_CONCURRENCY, DEFAULT_AWS_MAX_CONCURRENCY));
+}
+
+/// Make an AWS call, waiting for a slot under the concurrency limit and retrying it if it fails with a retryable
+/// error. `f` creates the call's future, and is called again for each attempt.
+pub async fn call<T, E, F, Fut>(operation: &str, mut f: F) -> Result<T, RusotoError<E>>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, RusotoError<E>>>,
+{
+    retry(operation, classify_aws_error, || {
+        let call = f();
+        async move {
+            let _permit = IN_FLIGHT.acquire().await.expect("AWS call semaphore closed");
+            call.await
+        }
+    })
+    .await
+}
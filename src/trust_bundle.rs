@@ -0,0 +1,197 @@
+use {
+    crate::{
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        storage::get_s3_object_string,
+        utils::validate_and_sanitize_ssm_parameter_path,
+        xray::aws_client,
+    },
+    hyper::client::HttpConnector,
+    hyper_tls::HttpsConnector,
+    lambda_runtime::Error as LambdaError,
+    log::{error, info},
+    native_tls::{Certificate as TlsCertificate, TlsConnector},
+    openssl::x509::X509,
+    rusoto_core::Region,
+    rusoto_s3::S3Client,
+    rusoto_ssm::{GetParameterRequest, Ssm, SsmClient},
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+};
+
+/// Additional CA certificates to trust, alongside the system trust store, when calling internal HTTPS endpoints
+/// whose certificates are issued by a private CA. The bundle is a PEM file holding one or more certificates, read
+/// from either SSM or S3. In JSON:
+///
+///     {
+///         // The name of an SSM parameter holding the bundle.
+///         "SsmParameter": str,
+///
+///         // Or the S3 bucket and key of an object holding the bundle.
+///         "S3Bucket": str,
+///         "S3Key": str,
+///     }
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct TrustBundle {
+    #[serde(rename = "SsmParameter", default, skip_serializing_if = "Option::is_none")]
+    pub ssm_parameter: Option<String>,
+
+    #[serde(rename = "S3Bucket", default, skip_serializing_if = "Option::is_none")]
+    pub s3_bucket: Option<String>,
+
+    #[serde(rename = "S3Key", default, skip_serializing_if = "Option::is_none")]
+    pub s3_key: Option<String>,
+}
+
+impl TrustBundle {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        match (&self.ssm_parameter, &self.s3_bucket, &self.s3_key) {
+            (Some(param), None, None) => {
+                if validate_and_sanitize_ssm_parameter_path(param).is_none() {
+                    return Err(InvalidCertificateRequest::invalid_trust_bundle(format!(
+                        "Invalid SsmParameter: {}",
+                        param
+                    )));
+                }
+                Ok(())
+            }
+            (None, Some(bucket), Some(key)) if !bucket.is_empty() && !key.is_empty() => Ok(()),
+            _ => Err(InvalidCertificateRequest::invalid_trust_bundle(
+                "Specify either SsmParameter or both S3Bucket and S3Key",
+            )),
+        }
+    }
+
+    /// Where the bundle is read from, for log messages.
+    fn location(&self) -> String {
+        match (&self.ssm_parameter, &self.s3_bucket, &self.s3_key) {
+            (Some(param), _, _) => format!("SSM parameter {}", param),
+            (None, Some(bucket), Some(key)) => format!("s3://{}/{}", bucket, key),
+            _ => "(none)".to_string(),
+        }
+    }
+
+    async fn read_pem(&self) -> Result<String, LambdaError> {
+        let pem = match (&self.ssm_parameter, &self.s3_bucket, &self.s3_key) {
+            (Some(param), _, _) => {
+                let ssm = SsmClient::new_with_client(aws_client(), Region::default());
+                let gp_request = GetParameterRequest {
+                    name: param.clone(),
+                    with_decryption: Some(true),
+                };
+
+                match ssm.get_parameter(gp_request).await {
+                    Ok(response) => response.parameter.and_then(|parameter| parameter.value),
+                    Err(e) => {
+                        error!("Failed to read trust bundle from SSM parameter {}: {:#}", param, e);
+                        return Err(Box::new(e));
+                    }
+                }
+            }
+            (None, Some(bucket), Some(key)) => {
+                let s3 = S3Client::new_with_client(aws_client(), Region::default());
+                get_s3_object_string(&s3, bucket, key.clone()).await?
+            }
+            _ => None,
+        };
+
+        match pem {
+            Some(pem) => Ok(pem),
+            None => Err(CertificateRequestError::unexpected_aws_response(format!(
+                "No trust bundle found at {}",
+                self.location()
+            ))),
+        }
+    }
+
+    /// Read the bundle and return the certificates in it.
+    pub async fn certificates(&self) -> Result<Vec<TlsCertificate>, LambdaError> {
+        let pem = self.read_pem().await?;
+        let certs = parse_bundle(&pem).map_err(|_| {
+            InvalidCertificateRequest::invalid_trust_bundle(format!(
+                "{} does not contain PEM-encoded certificates",
+                self.location()
+            ))
+        })?;
+
+        info!("Loaded {} CA certificate(s) from {}", certs.len(), self.location());
+        Ok(certs)
+    }
+
+    /// Read the bundle and return the certificates in it, for verifying certificate chains with OpenSSL.
+    pub async fn x509_certificates(&self) -> Result<Vec<X509>, LambdaError> {
+        let pem = self.read_pem().await?;
+        match X509::stack_from_pem(pem.as_bytes()) {
+            Ok(certs) if !certs.is_empty() => {
+                info!("Loaded {} CA certificate(s) from {}", certs.len(), self.location());
+                Ok(certs)
+            }
+            _ => Err(InvalidCertificateRequest::invalid_trust_bundle(format!(
+                "{} does not contain PEM-encoded certificates",
+                self.location()
+            ))),
+        }
+    }
+
+    /// Returns an HTTPS connector that trusts the bundle in addition to the system trust store.
+    pub async fn https_connector(&self) -> Result<HttpsConnector<HttpConnector>, LambdaError> {
+        let mut builder = TlsConnector::builder();
+        for cert in self.certificates().await? {
+            builder.add_root_certificate(cert);
+        }
+
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        Ok(HttpsConnector::from((http, builder.build()?.into())))
+    }
+}
+
+/// Split a PEM bundle into certificates, failing if it holds none.
+fn parse_bundle(pem: &str) -> Result<Vec<TlsCertificate>, LambdaError> {
+    let mut result = Vec::new();
+    for cert in X509::stack_from_pem(pem.as_bytes())? {
+        result.push(TlsCertificate::from_der(&cert.to_der()?)?);
+    }
+
+    if result.is_empty() {
+        Err(InvalidCertificateRequest::invalid_trust_bundle("No certificates found"))
+    } else {
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::TrustBundle;
+
+    #[test]
+    fn test_trust_bundle_validate() {
+        let ssm = TrustBundle {
+            ssm_parameter: Some("/AcmeParameters/TrustBundle".to_string()),
+            s3_bucket: None,
+            s3_key: None,
+        };
+        assert!(ssm.validate().is_ok());
+
+        let s3 = TrustBundle {
+            ssm_parameter: None,
+            s3_bucket: Some("config".to_string()),
+            s3_key: Some("ca/internal.pem".to_string()),
+        };
+        assert!(s3.validate().is_ok());
+
+        let both = TrustBundle {
+            ssm_parameter: Some("/AcmeParameters/TrustBundle".to_string()),
+            s3_bucket: Some("config".to_string()),
+            s3_key: Some("ca/internal.pem".to_string()),
+        };
+        assert!(both.validate().is_err());
+
+        let missing_key = TrustBundle {
+            ssm_parameter: None,
+            s3_bucket: Some("config".to_string()),
+            s3_key: None,
+        };
+        assert!(missing_key.validate().is_err());
+    }
+}
@@ -1,57 +1,300 @@
-use crate::constants::{DEFAULT_SSM_ACME_PATH, ENV_SSM_PARAMETER_PATH};
+use crate::{
+    chains::{CertificateChain, ChainVariant},
+    constants::{DEFAULT_SSM_ACME_PATH, ENV_SSM_PARAMETER_PATH},
+    keys::KeyAlgorithm,
+};
+use lambda_runtime::Error as LambdaError;
+use openssl::{asn1::Asn1Time, x509::X509};
+use rusoto_core::{region::ParseRegionError, Region, RusotoError};
+use rusoto_dynamodb::AttributeValue;
 use std::{
     env::var_os,
     str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
 };
-use rusoto_core::{Region, region::ParseRegionError};
 
 #[derive(Clone, Debug)]
-pub(crate) struct CertificateComponents {
-    pub(crate) cert_pem: String,
-    pub(crate) chain_pem: String,
-    pub(crate) fullchain_pem: String,
-    pub(crate) pkey_pem: String,
+pub struct CertificateComponents {
+    pub cert_pem: String,
+    pub chain_pem: String,
+    pub fullchain_pem: String,
+    pub pkey_pem: String,
+
+    /// The alternate chain offered by the CA during a chain transition, if requested and available.
+    pub alternate_chain: Option<CertificateChain>,
+}
+
+impl CertificateComponents {
+    /// Returns the components with the private key removed, for storage targets that must not hold it.
+    pub fn without_private_key(self) -> Self {
+        Self {
+            pkey_pem: String::new(),
+            ..self
+        }
+    }
+
+    /// Returns the components a storage target that wants the given chain variant should receive. Alternate
+    /// targets get the alternate chain in place of the default one; default targets only keep the alternate chain
+    /// alongside it if store_alternate is set.
+    pub fn for_chain(self, variant: ChainVariant, store_alternate: bool) -> Self {
+        match (variant, self.alternate_chain) {
+            (ChainVariant::Alternate, Some(alternate)) => Self {
+                chain_pem: alternate.chain_pem,
+                fullchain_pem: alternate.fullchain_pem,
+                alternate_chain: None,
+                ..self
+            },
+            (_, alternate_chain) => Self {
+                alternate_chain: if store_alternate {
+                    alternate_chain
+                } else {
+                    None
+                },
+                ..self
+            },
+        }
+    }
+}
+
+/// Information extracted from a PEM-encoded certificate.
+#[derive(Clone, Debug)]
+pub struct CertificateInfo {
+    /// The DNS subject alternative names on the certificate.
+    pub domain_names: Vec<String>,
+
+    /// The serial number of the certificate as lowercase hex with no separators or leading zeros.
+    pub serial: String,
+
+    /// The start of the certificate's validity period in seconds since the Unix epoch.
+    pub not_before: i64,
+
+    /// The expiration time of the certificate in seconds since the Unix epoch.
+    pub not_after: i64,
+
+    /// The algorithm of the certificate's key, or None if it isn't one we support (or couldn't be determined).
+    pub key_algorithm: Option<KeyAlgorithm>,
+}
+
+impl CertificateInfo {
+    /// Parse the leaf certificate from a PEM-encoded certificate.
+    pub fn from_pem(pem: &str) -> Result<Self, LambdaError> {
+        let cert = X509::from_pem(pem.as_bytes())?;
+        let mut domain_names = Vec::new();
+        if let Some(sans) = cert.subject_alt_names() {
+            for san in sans.iter() {
+                if let Some(dns_name) = san.dnsname() {
+                    domain_names.push(dns_name.to_string());
+                }
+            }
+        }
+
+        let serial = normalize_serial(&cert.serial_number().to_bn()?.to_hex_str()?);
+        let epoch = Asn1Time::from_unix(0)?;
+        let diff = epoch.diff(cert.not_before())?;
+        let not_before = i64::from(diff.days) * 86400 + i64::from(diff.secs);
+        let diff = epoch.diff(cert.not_after())?;
+        let not_after = i64::from(diff.days) * 86400 + i64::from(diff.secs);
+        let key_algorithm = KeyAlgorithm::from_public_key(&cert.public_key()?);
+
+        Ok(Self {
+            domain_names,
+            serial,
+            not_before,
+            not_after,
+            key_algorithm,
+        })
+    }
+
+    /// Indicates whether the certificate covers all of the specified domain names.
+    pub fn covers(&self, domain_names: &[String]) -> bool {
+        domain_names.iter().all(|dn| self.domain_names.iter().any(|san| san.eq_ignore_ascii_case(dn)))
+    }
+}
+
+/// Normalize a certificate serial number (which may be formatted as "0A:1B:..." by ACM or "0A1B..." by OpenSSL) to
+/// lowercase hex with no separators or leading zeros.
+pub fn normalize_serial(serial: &str) -> String {
+    let serial: String = serial.chars().filter(|c| *c != ':').flat_map(|c| c.to_lowercase()).collect();
+    let trimmed = serial.trim_start_matches('0');
+    if trimmed.is_empty() {
+        "0".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub fn now_epoch_secs() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Format seconds since the Unix epoch as an ISO 8601 UTC timestamp, e.g. "2024-01-31T12:00:00Z".
+pub fn format_utc_timestamp(epoch_secs: i64) -> String {
+    let days = epoch_secs.div_euclid(86400);
+    let secs = epoch_secs.rem_euclid(86400);
+
+    // Convert days since the epoch to a civil date; see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {
+        mp + 3
+    } else {
+        mp - 9
+    };
+    let year = yoe
+        + era * 400
+        + if month <= 2 {
+            1
+        } else {
+            0
+        };
+
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// Returns a DynamoDB string attribute.
+pub fn attr_s(value: &str) -> AttributeValue {
+    AttributeValue {
+        s: Some(value.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Returns a DynamoDB number attribute.
+pub fn attr_n(value: i64) -> AttributeValue {
+    AttributeValue {
+        n: Some(value.to_string()),
+        ..Default::default()
+    }
+}
+
+/// Indicates whether a domain name is a wildcard ("*.example.com").
+pub fn is_wildcard_domain_name(domain_name: &str) -> bool {
+    domain_name.starts_with("*.")
+}
+
+/// Validate a domain name for a certificate request. A wildcard may only appear as the entire leftmost label and must
+/// be followed by at least two labels (so "*.com" is rejected). Labels made up only of underscores are rejected so
+/// they can't collide with the wildcard label in [domain_name_for_path].
+pub fn validate_domain_name(domain_name: &str) -> bool {
+    let (labels, min_labels) = match domain_name.strip_prefix("*.") {
+        Some(base) => (base, 2),
+        None => (domain_name, 1),
+    };
+
+    let labels: Vec<&str> = labels.trim_end_matches('.').split('.').collect();
+    if labels.len() < min_labels || labels.iter().map(|l| l.len()).sum::<usize>() + labels.len() > 254 {
+        return false;
+    }
+
+    labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && !label.bytes().all(|c| c == b'_')
+            && label.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_')
+    })
+}
+
+/// Returns the domain name with any wildcard label removed and a trailing dot stripped, in lowercase.
+fn base_domain_name(domain_name: &str) -> String {
+    domain_name.strip_prefix("*.").unwrap_or(domain_name).trim_end_matches('.').to_lowercase()
+}
+
+/// Indicates whether a domain name (or, for a wildcard, the domain it covers) is itself a public suffix such as "com"
+/// or "co.uk", according to the public suffix list.
+pub fn is_public_suffix(domain_name: &str) -> bool {
+    let base = base_domain_name(domain_name);
+    psl::suffix_str(&base).map(|suffix| suffix == base).unwrap_or(true)
+}
+
+/// Returns the registrable domain (the public suffix plus one label, e.g. "example.co.uk") for a domain name, or None
+/// if the domain name is a public suffix.
+pub fn registrable_domain(domain_name: &str) -> Option<String> {
+    psl::domain_str(&base_domain_name(domain_name)).map(|domain| domain.to_string())
+}
+
+/// Returns the distinct registrable domains covered by a set of domain names, in the order they first appear. Public
+/// suffixes don't have a registrable domain and are skipped.
+pub fn registrable_domains(domain_names: &[String]) -> Vec<String> {
+    let mut registrable_domains = Vec::new();
+    for domain_name in domain_names {
+        if let Some(registrable) = registrable_domain(domain_name) {
+            if !registrable_domains.contains(&registrable) {
+                registrable_domains.push(registrable);
+            }
+        }
+    }
+
+    registrable_domains
+}
+
+/// Returns a form of the domain name that is safe to use in SSM parameter and Secrets Manager secret names, which do
+/// not allow "*". Wildcards are replaced with "_"; [validate_domain_name] rejects labels made up only of underscores,
+/// so this cannot collide with a requested hostname.
+pub fn domain_name_for_path(domain_name: &str) -> String {
+    domain_name.replace('*', "_")
+}
+
+/// Indicates whether an AWS error is due to request throttling. Rusoto doesn't model throttling errors for most
+/// services, so these surface as unknown errors with the error code in the body.
+pub fn is_throttling_error<E>(error: &RusotoError<E>) -> bool {
+    match error {
+        RusotoError::Unknown(response) => {
+            let body = response.body_as_str();
+            response.status.as_u16() == 429
+                || body.contains("Throttling")
+                || body.contains("RequestLimitExceeded")
+                || body.contains("TooManyRequests")
+                || body.contains("SlowDown")
+        }
+        _ => false,
+    }
 }
 
-pub(crate) const fn default_false() -> bool {
+pub const fn default_false() -> bool {
     false
 }
 
-pub(crate) fn default_aes256() -> String {
+pub const fn default_true() -> bool {
+    true
+}
+
+pub fn default_aes256() -> String {
     "AES256".to_string()
 }
 
-pub(crate) fn empty_string() -> String {
+pub fn empty_string() -> String {
     "".to_string()
 }
 
-pub(crate) fn ssm_acme_parameter_path() -> String {
+pub fn ssm_acme_parameter_path() -> String {
     match var_os(ENV_SSM_PARAMETER_PATH) {
         Some(path) => path.to_string_lossy().into(),
         None => DEFAULT_SSM_ACME_PATH.to_string(),
     }
 }
 
-pub(crate) fn validate_and_sanitize_ssm_parameter_path(path: &str) -> Option<String> {
-    let path = if path.ends_with("/") {
-        &path[..path.len() - 1]
-    } else {
-        path
-    };
+pub fn validate_and_sanitize_ssm_parameter_path(path: &str) -> Option<String> {
+    let path = path.strip_suffix('/').unwrap_or(path);
 
     for (i, el) in path.split("/").enumerate() {
         if i == 0 {
-            if el.len() != 0 {
+            if !el.is_empty() {
                 return None;
             }
         } else {
-            if i == 1 {
-                if el == "aws" || el == "ssm" {
-                    return None;
-                }
+            if i == 1 && (el == "aws" || el == "ssm") {
+                return None;
             }
 
-            if el.len() == 0 {
+            if el.is_empty() {
                 return None;
             }
 
@@ -66,7 +309,9 @@ pub(crate) fn validate_and_sanitize_ssm_parameter_path(path: &str) -> Option<Str
     Some(path.to_string())
 }
 
-pub(crate) fn s3_bucket_location_constraint_to_region(location_constraint: Option<String>) -> Result<Region, ParseRegionError> {
+pub fn s3_bucket_location_constraint_to_region(
+    location_constraint: Option<String>,
+) -> Result<Region, ParseRegionError> {
     match location_constraint {
         None => Ok(Region::UsEast1),
         Some(ref name) => match name.as_ref() {
@@ -75,4 +320,76 @@ pub(crate) fn s3_bucket_location_constraint_to_region(location_constraint: Optio
             _ => Region::from_str(name),
         },
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::{
+        domain_name_for_path, is_public_suffix, is_wildcard_domain_name, registrable_domain, registrable_domains,
+        validate_domain_name,
+    };
+
+    #[test]
+    fn test_validate_domain_name() {
+        for domain_name in ["example.com", "*.example.com", "_acme-challenge.example.com", "example.com."] {
+            assert!(validate_domain_name(domain_name), "{} should be accepted", domain_name);
+        }
+
+        for domain_name in
+            ["", "*.com", "a.*.example.com", "*example.com", "_.example.com", "__.example.com", "-a.example.com"]
+        {
+            assert!(!validate_domain_name(domain_name), "{} should be rejected", domain_name);
+        }
+
+        let label_63 = "a".repeat(63);
+        let label_64 = "a".repeat(64);
+        assert!(validate_domain_name(&format!("{}.example.com", label_63)));
+        assert!(!validate_domain_name(&format!("{}.example.com", label_64)));
+        assert!(validate_domain_name(&format!("*.{}.example.com", label_63)));
+        assert!(!validate_domain_name(&format!("*.{}.example.com", label_64)));
+    }
+
+    #[test]
+    fn test_is_wildcard_domain_name() {
+        assert!(is_wildcard_domain_name("*.example.com"));
+        assert!(!is_wildcard_domain_name("example.com"));
+        assert!(!is_wildcard_domain_name("www.*.example.com"));
+        assert!(!is_wildcard_domain_name("_.example.com"));
+    }
+
+    #[test]
+    fn test_is_public_suffix() {
+        for domain_name in ["com", "co.uk", "*.co.uk", "*.com", "CO.UK", "co.uk."] {
+            assert!(is_public_suffix(domain_name), "{} should be a public suffix", domain_name);
+        }
+
+        for domain_name in ["example.co.uk", "*.example.co.uk", "www.example.com", "EXAMPLE.CO.UK", "example.co.uk."] {
+            assert!(!is_public_suffix(domain_name), "{} should not be a public suffix", domain_name);
+        }
+    }
+
+    #[test]
+    fn test_registrable_domain() {
+        assert_eq!(registrable_domain("example.co.uk").as_deref(), Some("example.co.uk"));
+        assert_eq!(registrable_domain("*.example.co.uk").as_deref(), Some("example.co.uk"));
+        assert_eq!(registrable_domain("www.Example.COM.").as_deref(), Some("example.com"));
+        assert_eq!(registrable_domain("co.uk"), None);
+        assert_eq!(registrable_domain("*.co.uk"), None);
+    }
+
+    #[test]
+    fn test_registrable_domains() {
+        let same = ["example.co.uk", "*.example.co.uk", "WWW.EXAMPLE.CO.UK."].map(String::from);
+        assert_eq!(registrable_domains(&same), vec!["example.co.uk".to_string()]);
+
+        let mixed = ["example.com", "www.example.com", "example.org"].map(String::from);
+        assert_eq!(registrable_domains(&mixed), vec!["example.com".to_string(), "example.org".to_string()]);
+    }
+
+    #[test]
+    fn test_domain_name_for_path() {
+        assert_eq!(domain_name_for_path("*.example.com"), "_.example.com");
+        assert_eq!(domain_name_for_path("www.example.com"), "www.example.com");
+    }
+}
@@ -0,0 +1,411 @@
+use {
+    crate::{errors::InvalidCertificateRequest, utils::normalize_serial},
+    lambda_runtime::Error as LambdaError,
+    log::{info, warn},
+    native_tls::TlsConnector,
+    openssl::x509::X509,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    },
+    tokio::{net::TcpStream, time::timeout},
+    tokio_native_tls::TlsConnector as AsyncTlsConnector,
+};
+
+/// The port probed when an endpoint doesn't specify one.
+const DEFAULT_PROBE_PORT: u16 = 443;
+
+/// How long to wait for a single connection and TLS handshake.
+const PROBE_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait between rounds of probes while waiting for the endpoints to serve the new certificate.
+const PROBE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The largest allowed Timeout, in seconds. Lambda invocations can't run longer than 15 minutes.
+const MAX_VERIFICATION_TIMEOUT: u64 = 840;
+
+/// Post-deployment verification for a load balancer or CloudFront storage target. After the certificate is
+/// attached, each endpoint is probed with a TLS handshake (validating the chain and host name against the system
+/// trust store) until every endpoint serves the new certificate. In JSON:
+///
+///     {
+///         // The endpoints to probe, as "host" or "host:port". The host name is also sent as the SNI server name.
+///         // This defaults to the certificate's domain names, skipping wildcards.
+///         "Endpoints": [str, ...],
+///
+///         // How long to keep probing for the endpoints to serve the new certificate, in seconds. CloudFront
+///         // deployments can take several minutes to reach every edge location. The default is 300.
+///         "Timeout": int,
+///
+///         // If true (the default), revert to the certificate that was attached before the update when an endpoint
+///         // fails the TLS handshake with the new certificate. The run is then reported as "RolledBack".
+///         "Rollback": bool,
+///     }
+///
+/// An endpoint that is unreachable, or that still serves the previous certificate when the timeout expires, is
+/// reported as a failure but does not cause a rollback: the previous certificate is still what clients see.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct DeploymentVerification {
+    #[serde(rename = "Endpoints", default)]
+    pub endpoints: Vec<String>,
+
+    #[serde(rename = "Timeout", default = "default_verification_timeout")]
+    pub timeout: u64,
+
+    #[serde(rename = "Rollback", default = "default_rollback")]
+    pub rollback: bool,
+}
+
+fn default_verification_timeout() -> u64 {
+    300
+}
+
+fn default_rollback() -> bool {
+    true
+}
+
+/// A staged rollout for an ACM storage target that reimports the certificate into several certificate ARNs (e.g.
+/// one per region). A subset of the ARNs is reimported first and its endpoints are probed as in
+/// DeploymentVerification; the remaining ARNs are only reimported once the canary endpoints serve the new
+/// certificate. In JSON:
+///
+///     {
+///         // The percentage of the ARNs to reimport first, rounded up to at least one. ARNs are taken in the order
+///         // of CertificateArns (or the order they were found in, if CertificateArns is omitted), so list the
+///         // canary regions first. The default is 10.
+///         "Percentage": int,
+///
+///         // The endpoints served by the certificates in each region, keyed by region, as "host" or "host:port".
+///         // Only the endpoints for the regions of the canary ARNs are probed. If none of those regions are listed,
+///         // the certificate's domain names are probed instead.
+///         "Endpoints": {str: [str, ...], ...},
+///
+///         // How long to keep probing for the canary endpoints to serve the new certificate, in seconds. The
+///         // default is 300.
+///         "Timeout": int,
+///     }
+///
+/// If a canary reimport fails or a canary endpoint fails verification, the remaining ARNs are left untouched and
+/// reported as errors. ACM cannot restore a replaced certificate, so the canary ARNs themselves are not rolled back.
+#[derive(Clone, Debug, Deserialize, JsonSchema, Serialize)]
+pub struct CanaryRollout {
+    #[serde(rename = "Percentage", default = "default_canary_percentage")]
+    pub percentage: u32,
+
+    #[serde(rename = "Endpoints", default, skip_serializing_if = "HashMap::is_empty")]
+    pub endpoints: HashMap<String, Vec<String>>,
+
+    #[serde(rename = "Timeout", default = "default_verification_timeout")]
+    pub timeout: u64,
+}
+
+fn default_canary_percentage() -> u32 {
+    10
+}
+
+impl CanaryRollout {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        if self.percentage == 0 || self.percentage > 100 {
+            return Err(InvalidCertificateRequest::invalid_deployment_verification(
+                "Canary Percentage must be between 1 and 100",
+            ));
+        }
+
+        self.verification(&[]).validate()?;
+        for endpoint in self.endpoints.values().flatten() {
+            if let Err(e) = split_endpoint(endpoint) {
+                return Err(InvalidCertificateRequest::invalid_deployment_verification(e));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Split the ARNs into the canary ARNs and the remaining ARNs. The canary is empty if it would cover every
+    /// ARN, since there would then be nothing to stage.
+    pub fn split(&self, arns: Vec<String>) -> (Vec<String>, Vec<String>) {
+        let n_canary = (arns.len() * self.percentage as usize).div_ceil(100).max(1);
+        if n_canary >= arns.len() {
+            return (vec![], arns);
+        }
+
+        let mut canary = arns;
+        let remaining = canary.split_off(n_canary);
+        (canary, remaining)
+    }
+
+    /// Returns the verification to run after reimporting into the canary ARNs.
+    pub fn verification(&self, canary_arns: &[String]) -> DeploymentVerification {
+        let mut endpoints: Vec<String> = Vec::new();
+        for arn in canary_arns {
+            let region = arn.split(':').nth(3).unwrap_or_default();
+            for endpoint in self.endpoints.get(region).into_iter().flatten() {
+                if !endpoints.contains(endpoint) {
+                    endpoints.push(endpoint.clone());
+                }
+            }
+        }
+
+        DeploymentVerification {
+            endpoints,
+            timeout: self.timeout,
+            rollback: false,
+        }
+    }
+}
+
+/// The outcome of probing an endpoint.
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum ProbeOutcome {
+    /// The endpoint served the expected certificate with a valid chain.
+    Verified,
+
+    /// The handshake succeeded but the endpoint served a different certificate.
+    Stale(String),
+
+    /// The TCP connection could not be established.
+    Unreachable(String),
+
+    /// The TLS handshake failed.
+    Failed(String),
+}
+
+/// The result of verifying a deployment. In JSON:
+///
+///     {
+///         // Whether every endpoint served the new certificate.
+///         "Verified": bool,
+///
+///         // For each endpoint that did not, why not.
+///         "Failures": [str, ...],
+///     }
+#[derive(Clone, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub struct VerificationResult {
+    #[serde(rename = "Verified")]
+    pub verified: bool,
+
+    #[serde(rename = "Failures", default, skip_serializing_if = "Vec::is_empty")]
+    pub failures: Vec<String>,
+
+    /// Whether any endpoint failed the TLS handshake (rather than being unreachable or serving another
+    /// certificate), which calls for a rollback.
+    #[serde(skip)]
+    pub handshake_failed: bool,
+}
+
+impl DeploymentVerification {
+    pub fn validate(&self) -> Result<(), LambdaError> {
+        if self.timeout == 0 || self.timeout > MAX_VERIFICATION_TIMEOUT {
+            return Err(InvalidCertificateRequest::invalid_deployment_verification(format!(
+                "Timeout must be between 1 and {} seconds",
+                MAX_VERIFICATION_TIMEOUT
+            )));
+        }
+
+        for endpoint in &self.endpoints {
+            if let Err(e) = split_endpoint(endpoint) {
+                return Err(InvalidCertificateRequest::invalid_deployment_verification(e));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Indicates whether a deployment should be rolled back after verification.
+    pub fn should_roll_back(&self, result: &VerificationResult) -> bool {
+        self.rollback && result.handshake_failed
+    }
+
+    /// Returns the endpoints to probe for a certificate with the given domain names.
+    fn endpoints_for(&self, domain_names: &[String]) -> Vec<String> {
+        if !self.endpoints.is_empty() {
+            return self.endpoints.clone();
+        }
+
+        domain_names.iter().filter(|dn| !dn.starts_with("*.")).cloned().collect()
+    }
+
+    /// Probe the endpoints until each serves the certificate with the expected serial number, or the timeout
+    /// expires.
+    pub async fn verify(&self, domain_names: &[String], expected_serial: &str) -> VerificationResult {
+        let deadline = Instant::now() + Duration::from_secs(self.timeout);
+        let mut pending = self.endpoints_for(domain_names);
+        let mut failures = Vec::new();
+        let mut handshake_failed = false;
+
+        if pending.is_empty() {
+            warn!("No endpoints to verify for {}", domain_names.join(" "));
+        }
+
+        loop {
+            let mut still_pending = Vec::new();
+            let mut last_outcomes = Vec::new();
+
+            for endpoint in pending {
+                match probe(&endpoint, expected_serial).await {
+                    ProbeOutcome::Verified => info!("Endpoint {} is serving certificate {}", endpoint, expected_serial),
+                    ProbeOutcome::Failed(e) => {
+                        // A broken handshake won't fix itself; stop probing this endpoint.
+                        warn!("Endpoint {} failed TLS verification: {}", endpoint, e);
+                        failures.push(format!("{}: TLS handshake failed: {}", endpoint, e));
+                        handshake_failed = true;
+                    }
+                    outcome => {
+                        last_outcomes.push((endpoint.clone(), outcome));
+                        still_pending.push(endpoint);
+                    }
+                }
+            }
+
+            if still_pending.is_empty() || handshake_failed || Instant::now() + PROBE_INTERVAL > deadline {
+                for (endpoint, outcome) in last_outcomes {
+                    match outcome {
+                        ProbeOutcome::Stale(serial) => failures.push(format!(
+                            "{}: still serving certificate {} instead of {}",
+                            endpoint, serial, expected_serial
+                        )),
+                        ProbeOutcome::Unreachable(e) => failures.push(format!("{}: unreachable: {}", endpoint, e)),
+                        _ => (),
+                    }
+                }
+                break;
+            }
+
+            info!("Waiting for {} to serve certificate {}", still_pending.join(" "), expected_serial);
+            tokio::time::sleep(PROBE_INTERVAL).await;
+            pending = still_pending;
+        }
+
+        VerificationResult {
+            verified: failures.is_empty(),
+            failures,
+            handshake_failed,
+        }
+    }
+}
+
+/// Split an endpoint into its host name and port.
+fn split_endpoint(endpoint: &str) -> Result<(&str, u16), String> {
+    let (host, port) = match endpoint.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) if port > 0 => (host, port),
+            _ => return Err(format!("Invalid port in endpoint {}", endpoint)),
+        },
+        None => (endpoint, DEFAULT_PROBE_PORT),
+    };
+
+    if host.is_empty() || host.contains('/') || host.contains(char::is_whitespace) {
+        return Err(format!("Invalid endpoint {}", endpoint));
+    }
+
+    Ok((host, port))
+}
+
+/// Connect to an endpoint and check the certificate it serves.
+async fn probe(endpoint: &str, expected_serial: &str) -> ProbeOutcome {
+    let (host, port) = match split_endpoint(endpoint) {
+        Ok(host_port) => host_port,
+        Err(e) => return ProbeOutcome::Unreachable(e),
+    };
+
+    let tcp = match timeout(PROBE_CONNECT_TIMEOUT, TcpStream::connect((host, port))).await {
+        Ok(Ok(tcp)) => tcp,
+        Ok(Err(e)) => return ProbeOutcome::Unreachable(e.to_string()),
+        Err(_) => return ProbeOutcome::Unreachable("connection timed out".to_string()),
+    };
+
+    let connector = match TlsConnector::new() {
+        Ok(connector) => AsyncTlsConnector::from(connector),
+        Err(e) => return ProbeOutcome::Failed(e.to_string()),
+    };
+
+    let tls = match timeout(PROBE_CONNECT_TIMEOUT, connector.connect(host, tcp)).await {
+        Ok(Ok(tls)) => tls,
+        Ok(Err(e)) => return ProbeOutcome::Failed(e.to_string()),
+        Err(_) => return ProbeOutcome::Unreachable("TLS handshake timed out".to_string()),
+    };
+
+    let serial = match tls.get_ref().peer_certificate() {
+        Ok(Some(cert)) => cert
+            .to_der()
+            .ok()
+            .and_then(|der| X509::from_der(&der).ok())
+            .and_then(|cert| cert.serial_number().to_bn().ok())
+            .and_then(|serial| serial.to_hex_str().ok())
+            .map(|serial| normalize_serial(&serial)),
+        _ => None,
+    };
+
+    match serial {
+        Some(serial) if serial == normalize_serial(expected_serial) => ProbeOutcome::Verified,
+        Some(serial) => ProbeOutcome::Stale(serial),
+        None => ProbeOutcome::Failed("No certificate presented".to_string()),
+    }
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{split_endpoint, CanaryRollout, DeploymentVerification},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_deployment_verification() {
+        let verification: DeploymentVerification = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(verification.timeout, 300);
+        assert!(verification.rollback);
+        assert!(verification.validate().is_ok());
+
+        let domain_names = vec!["*.example.com".to_string(), "example.com".to_string()];
+        assert_eq!(verification.endpoints_for(&domain_names), vec!["example.com".to_string()]);
+
+        assert_eq!(split_endpoint("www.example.com"), Ok(("www.example.com", 443)));
+        assert_eq!(split_endpoint("www.example.com:8443"), Ok(("www.example.com", 8443)));
+        assert!(split_endpoint("www.example.com:https").is_err());
+        assert!(split_endpoint("https://www.example.com").is_err());
+
+        let verification: DeploymentVerification =
+            serde_json::from_value(json!({"Endpoints": ["lb.example.com:0"], "Timeout": 60})).unwrap();
+        assert!(verification.validate().is_err());
+    }
+
+    #[test]
+    fn test_canary_rollout() {
+        let canary: CanaryRollout = serde_json::from_value(json!({
+            "Percentage": 25,
+            "Endpoints": {
+                "us-east-1": ["use1.example.com"],
+                "eu-west-1": ["euw1.example.com:8443"],
+            },
+        }))
+        .unwrap();
+        assert!(canary.validate().is_ok());
+
+        let arns: Vec<String> = ["us-east-1", "eu-west-1", "ap-southeast-2", "us-west-2", "sa-east-1"]
+            .iter()
+            .map(|region| format!("arn:aws:acm:{}:123456789012:certificate/abc", region))
+            .collect();
+        let (canary_arns, remaining) = canary.split(arns.clone());
+        assert_eq!(canary_arns, arns[..2].to_vec());
+        assert_eq!(remaining, arns[2..].to_vec());
+        assert_eq!(canary.verification(&canary_arns).endpoints, vec!["use1.example.com", "euw1.example.com:8443"]);
+        assert!(canary.verification(&remaining).endpoints.is_empty());
+
+        let (canary_arns, remaining) = canary.split(vec![arns[0].clone()]);
+        assert!(canary_arns.is_empty());
+        assert_eq!(remaining.len(), 1);
+
+        let canary: CanaryRollout = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(canary.percentage, 10);
+        assert_eq!(canary.split(arns).0.len(), 1);
+
+        let canary: CanaryRollout = serde_json::from_value(json!({"Percentage": 0})).unwrap();
+        assert!(canary.validate().is_err());
+        let canary: CanaryRollout = serde_json::from_value(json!({"Endpoints": {"us-east-1": ["a b"]}})).unwrap();
+        assert!(canary.validate().is_err());
+    }
+}
@@ -0,0 +1,96 @@
+use {
+    acme2::Account,
+    lazy_static::lazy_static,
+    ring::digest::{digest, SHA256},
+    rusoto_core::{credential::AutoRefreshingProvider, Client},
+    rusoto_sts::StsAssumeRoleSessionCredentialsProvider,
+    std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// How long a discovered hosted zone is trusted before it's looked up again. Zones rarely change, but a zone being
+/// deleted and recreated (with a new id) shouldn't require a cold start to pick up.
+const HOSTED_ZONE_TTL: Duration = Duration::from_secs(3600);
+
+// Lambda keeps the process alive between invocations, so these survive until the next cold start. They hold the
+// results of the slow lookups repeated on every run: the ACME account (a directory fetch, an SSM read, and a round
+// trip to the ACME server) and Route 53 hosted zone discovery (which lists every hosted zone in the account). Keys
+// are hashes of the configuration that produced each entry, so requests with different settings never share state.
+//
+// AWS clients are cached too. Calls made with the function's own credentials share one client (see `aws_client`),
+// and each assumed role gets a client and credentials provider that are kept here, so the STS session is reused
+// rather than the role being assumed again on every invocation. The providers refresh the session as it nears
+// expiration.
+lazy_static! {
+    static ref ACCOUNTS: Mutex<HashMap<String, Arc<Account>>> = Mutex::new(HashMap::new());
+    static ref HOSTED_ZONES: Mutex<HashMap<String, (String, Instant)>> = Mutex::new(HashMap::new());
+    static ref ROLE_CLIENTS: Mutex<HashMap<String, (Client, RoleCredentialsProvider)>> = Mutex::new(HashMap::new());
+}
+
+/// The credentials for an assumed role, refreshed as the STS session nears expiration. These are shared by the role's
+/// client and by callers that sign requests themselves.
+pub type RoleCredentialsProvider = Arc<AutoRefreshingProvider<StsAssumeRoleSessionCredentialsProvider>>;
+
+/// Returns a key identifying a configuration, for use with the caches below.
+pub fn config_key(parts: &[&str]) -> String {
+    let hash = digest(&SHA256, parts.join("\n").as_bytes());
+    hash.as_ref().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Returns the ACME account established by a previous invocation with the same configuration, if any.
+pub fn cached_account(key: &str) -> Option<Arc<Account>> {
+    ACCOUNTS.lock().expect("Account cache lock poisoned").get(key).cloned()
+}
+
+/// Remember an ACME account for future invocations.
+pub fn cache_account(key: String, account: Arc<Account>) {
+    ACCOUNTS.lock().expect("Account cache lock poisoned").insert(key, account);
+}
+
+/// Returns the hosted zone id previously discovered for a domain name, if it hasn't expired.
+pub fn cached_hosted_zone(key: &str) -> Option<String> {
+    let hosted_zones = HOSTED_ZONES.lock().expect("Hosted zone cache lock poisoned");
+    match hosted_zones.get(key) {
+        Some((hosted_zone_id, discovered)) if discovered.elapsed() < HOSTED_ZONE_TTL => Some(hosted_zone_id.clone()),
+        _ => None,
+    }
+}
+
+/// Remember the hosted zone discovered for a domain name.
+pub fn cache_hosted_zone(key: String, hosted_zone_id: String) {
+    HOSTED_ZONES.lock().expect("Hosted zone cache lock poisoned").insert(key, (hosted_zone_id, Instant::now()));
+}
+
+/// Returns the client and credentials for an assumed role created by a previous invocation, if any.
+pub fn cached_role_client(key: &str) -> Option<(Client, RoleCredentialsProvider)> {
+    ROLE_CLIENTS.lock().expect("Role client cache lock poisoned").get(key).cloned()
+}
+
+/// Remember the client and credentials for an assumed role.
+pub fn cache_role_client(key: String, client: Client, credentials: RoleCredentialsProvider) {
+    ROLE_CLIENTS.lock().expect("Role client cache lock poisoned").insert(key, (client, credentials));
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::{cache_hosted_zone, cached_hosted_zone, config_key};
+
+    #[test]
+    fn test_config_key() {
+        assert_eq!(config_key(&["a", "b"]), config_key(&["a", "b"]));
+        assert_ne!(config_key(&["a", "b"]), config_key(&["ab"]));
+        assert_ne!(config_key(&["a", "b"]), config_key(&["b", "a"]));
+    }
+
+    #[test]
+    fn test_hosted_zone_cache() {
+        let key = config_key(&["us-east-1", "www.example.com"]);
+        assert_eq!(cached_hosted_zone(&key), None);
+        cache_hosted_zone(key.clone(), "Z123".to_string());
+        assert_eq!(cached_hosted_zone(&key), Some("Z123".to_string()));
+    }
+}
@@ -1,71 +1,786 @@
 use {
     crate::{
+        account::{external_account_required, AccountKeyStore, ExternalAccountBinding},
+        acme_gateway::AcmeGateway,
+        audit::AuditReport,
         auth::{AuthorizationHandler, CertificateAuthorization},
-        errors::CertificateRequestError,
-        events::{CertificateResponse, CertificateResponseStatus, Response},
+        chains::{find_alternate_chain, select_preferred_chain, CertificateChain, ChainVariant},
+        consistency::{check_components, ConsistencyCheck},
+        constants::{RENEWAL_SCHEDULE_MIN_DELAY_SECONDS, RENEWAL_SCHEDULE_RETRY_SECONDS},
+        cost_ledger::{CostAllocation, CostLedger, CostLedgerEntry},
+        csr::CsrOptions,
+        debug_artifacts::{AcmeDebugCapture, DebugArtifactStore},
+        dry_run::{DryRunIssuance, DryRunReport, DryRunTarget},
+        errors::{CertificateRequestError, InvalidCertificateRequest},
+        events::{CertificateAction, CertificateResponse, CertificateResponseStatus, Response},
+        faults::{self, Fault},
+        hooks::{run_hooks, HookPayload, PostIssuanceHook},
+        inventory::{
+            read_inventory, write_inventory, write_rotation_manifest, InventoryDiff, InventoryRecord, RotationManifest,
+        },
+        issuance_limits::IssuanceLimits,
+        keys::KeyAlgorithm,
+        lifecycle::{LifecycleEvent, LifecycleEventEmitter},
+        metrics::MetricsEmitter,
+        notifications::NotificationConfig,
+        rate_limits::{format_utc, RateLimit, RateLimitRetryScheduler},
+        readiness::ReadinessReport,
+        reconcile::{ActualState, DesiredState, ReconcilePlan},
+        renewal_lock::{LockAttempt, RenewalLock},
+        report::PhaseTimings,
+        retry::{classify_acme_error, retry, with_retry_config, RetryConfig},
+        revoke::RevocationConfig,
+        schedule::{next_renewal, RenewalScheduler},
         storage::{CertificateStorage, CertificateStorageResult},
-        utils::{ssm_acme_parameter_path, CertificateComponents},
+        task::{targets_error, StorageTargetResult, TaskError, TaskStatus, ALREADY_IN_PROGRESS, STORAGE_WRITE_FAILED},
+        utils::{now_epoch_secs, CertificateComponents, CertificateInfo},
+        warm::{cache_account, cached_account, config_key},
+        xray::{subsegment, traced},
     },
     acme2::{
         Account, AccountBuilder, Authorization, Csr, Directory, DirectoryBuilder, Order, OrderBuilder, OrderStatus,
     },
     futures::stream::{FuturesOrdered, StreamExt},
     lambda_runtime::Error as LambdaError,
-    log::{debug, error, info},
-    openssl::{
-        pkey::{PKey, Private},
-        rsa::Rsa,
+    log::{debug, error, info, warn},
+    openssl::x509::X509Req,
+    serde_json::Value,
+    std::{
+        str::from_utf8,
+        sync::Arc,
+        time::{Duration, Instant},
     },
-    rusoto_core::{Region, RusotoError},
-    rusoto_ssm::{GetParameterError, GetParameterRequest, PutParameterRequest, Ssm, SsmClient},
-    std::{str::from_utf8, sync::Arc, time::Duration},
     tokio::time::sleep,
 };
 
+#[cfg(feature = "ssh-output")]
+use crate::ssh::SshOutputConfig;
+
 const CHECK_WAIT_DURATION: Duration = Duration::from_secs(5);
 const MAX_ORDER_RETRIES: usize = 36; // 36 * 5 = 180 seconds
 
-pub(crate) struct ValidatedCertificateRequest {
+pub struct ValidatedCertificateRequest {
     /// The URL for the ACME server, e.g. `"https://acme-staging-v02.api.letsencrypt.org/directory"`
-    pub(crate) directory: String,
-    pub(crate) domain_names: Vec<String>,
-    pub(crate) contacts: Vec<String>,
-    pub(crate) auth: CertificateAuthorization,
-    pub(crate) storage: Vec<CertificateStorage>,
-    pub(crate) dir_host: String,
+    pub directory: String,
+    pub domain_names: Vec<String>,
+    pub contacts: Vec<String>,
+
+    /// External Account Binding credentials for creating a new ACME account, if the CA requires them.
+    pub external_account_binding: Option<ExternalAccountBinding>,
+
+    /// The registered ACME account to use, if one was named.
+    pub account: Option<String>,
+
+    pub auth: CertificateAuthorization,
+    pub key_algorithm: KeyAlgorithm,
+
+    /// Extensions to request in the CSR.
+    pub csr: CsrOptions,
+
+    pub action: CertificateAction,
+
+    /// Whether to publish a rotation manifest when the certificate is rotated.
+    pub rotation_manifest: bool,
+
+    /// Whether to store the alternate chain offered during a CA chain transition.
+    pub store_alternate_chain: bool,
+
+    /// The common name of the root CA the default chain should end at, if any.
+    pub preferred_chain: Option<String>,
+
+    /// How the certificate is checked before it is stored.
+    pub consistency_check: ConsistencyCheck,
+
+    /// How a certificate is obtained if this is a dry run, or None for a real run.
+    pub dry_run: Option<DryRunIssuance>,
+
+    /// Where to send a notification after the run, if anywhere.
+    pub notifications: Option<NotificationConfig>,
+
+    /// Where to send certificate lifecycle events, if anywhere.
+    pub lifecycle_events: Option<LifecycleEventEmitter>,
+
+    /// Hooks to run after the certificate is stored.
+    pub hooks: Vec<PostIssuanceHook>,
+
+    /// How renewals are attributed in the cost ledger.
+    pub cost_allocation: CostAllocation,
+
+    /// Where to write the key pair in OpenSSH formats, if anywhere.
+    #[cfg(feature = "ssh-output")]
+    pub ssh_output: Option<SshOutputConfig>,
+
+    /// The certificate written during this run, if any.
+    pub certificate: Option<CertificateInfo>,
+
+    pub storage: Vec<CertificateStorage>,
+    pub dir_host: String,
+
+    /// Certificates expiring within this many days are renewed.
+    pub renewal_threshold_days: i64,
+
+    /// How calls made for this request are retried.
+    pub retry: RetryConfig,
+
+    /// Which certificate to revoke for the Revoke action.
+    pub revocation: RevocationConfig,
+
+    /// Whether to run the readiness checks before ordering a certificate.
+    pub preflight: bool,
+
+    /// Whether to move the renewal rule to the next renewal time after an Issue run.
+    pub schedule_renewal: bool,
+
+    /// The storage targets this run may write, if it's restricted to some of them.
+    pub storage_indexes: Option<Vec<usize>>,
+
+    /// How long to wait for another invocation renewing the same domain names to finish.
+    pub lock_wait_seconds: u64,
+
+    /// The time spent in each phase of the run.
+    pub phases: PhaseTimings,
+
+    /// The limits on certificates issued, shared by every request in the run.
+    pub issuance_limits: Arc<IssuanceLimits>,
+
+    /// The request as originally submitted. This is recorded in the inventory so renewals can be triggered by
+    /// events that only identify the certificate.
+    pub original: Value,
 }
 
 impl ValidatedCertificateRequest {
-    pub(crate) async fn run_workflow(&mut self) -> Result<Response, LambdaError> {
-        let mut db = DirectoryBuilder::new(self.directory.clone());
-        let dir: Arc<Directory> = db.build().await?;
+    /// Run the requested action and send notifications with the result if configured, with the request's retry
+    /// policy in effect.
+    pub async fn run_workflow(&mut self) -> Result<Response, LambdaError> {
+        let retry = self.retry.clone();
+        with_retry_config(retry, self.run_and_notify()).await
+    }
 
-        let mut account_builder = AccountBuilder::new(dir);
-        account_builder.contact(self.contacts.clone());
-        account_builder.terms_of_service_agreed(true);
+    /// Run the requested action and send notifications, lifecycle events, and metrics with the result if
+    /// configured. The delivery results are added to the response; a channel failing to deliver doesn't fail the run.
+    async fn run_and_notify(&mut self) -> Result<Response, LambdaError> {
+        let lock = match self.acquire_lock().await? {
+            Some(LockAttempt::Held(holder)) => return Ok(self.already_in_progress(holder)),
+            Some(LockAttempt::Acquired(guard)) => Some(guard),
+            None => None,
+        };
 
-        self.set_private_key(&mut account_builder).await?;
+        let mut result = self.run_action().await;
+        if let Some(lock) = lock {
+            lock.release().await;
+        }
+        if let Ok(Response::Certificate(response)) = &mut result {
+            TaskStatus::complete(response);
+        }
+
+        // Dry runs report what would happen; they don't tell anyone it happened.
+        if self.dry_run.is_some() {
+            return result;
+        }
+
+        if self.schedule_renewal && self.action == CertificateAction::Issue {
+            self.reschedule_renewal(&mut result).await;
+        }
+
+        if let Some(notifications) = &self.notifications {
+            let deliveries = notifications.notify(&self.domain_names, self.certificate.as_ref(), &result).await;
+            if let Ok(Response::Certificate(response)) = &mut result {
+                response.notifications = deliveries;
+            }
+        }
+
+        if let Some(lifecycle_events) = &self.lifecycle_events {
+            if let Some(event) = LifecycleEvent::renewal_failed(&self.domain_names, &result) {
+                lifecycle_events.emit(vec![event]).await;
+            }
+        }
+
+        if let Some(metrics) = MetricsEmitter::from_env() {
+            metrics.emit(&self.domain_names, self.certificate.as_ref(), &result);
+        }
+
+        if let (Some(ledger), Ok(Response::Certificate(response))) = (CostLedger::from_env(), &result) {
+            // Test rotations report the storage results of an earlier run, which were already recorded.
+            if self.action == CertificateAction::Issue && !response.storage.is_empty() {
+                let entry = CostLedgerEntry::new(
+                    &self.cost_allocation,
+                    &self.domain_names,
+                    self.certificate.as_ref(),
+                    &response.storage,
+                    now_epoch_secs(),
+                );
+                ledger.record(&entry).await;
+            }
+        }
+
+        result
+    }
+
+    /// Take the renewal lock on the domain names if locking is configured and this run may write to the storage
+    /// targets (an Issue or Revoke run that isn't a dry run). Returns None if no lock is needed.
+    async fn acquire_lock(&self) -> Result<Option<LockAttempt>, LambdaError> {
+        if self.dry_run.is_some() || !matches!(self.action, CertificateAction::Issue | CertificateAction::Revoke) {
+            return Ok(None);
+        }
+
+        match RenewalLock::from_env() {
+            Some(lock) => Ok(Some(lock.acquire(&self.domain_names, self.lock_wait_seconds).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The response for a run that didn't start because another invocation holds the renewal lock.
+    fn already_in_progress(&self, holder: String) -> Response {
+        Response::Certificate(CertificateResponse {
+            finished: true,
+            status: CertificateResponseStatus::AlreadyInProgress,
+            storage: vec![],
+            plan: vec![],
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: None,
+            revocation: None,
+            readiness: None,
+            dry_run: None,
+            schedule: None,
+            task_status: Some(TaskStatus::Failed),
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: Some(TaskError::new(ALREADY_IN_PROGRESS, holder)),
+        })
+    }
+
+    /// Reconcile the storage targets with the desired state: observe what each target currently holds, plan the
+    /// minimal set of writes, and then execute the plan (issuing a new certificate only if no target holds a
+    /// current certificate that can be copied).
+    async fn run_action(&mut self) -> Result<Response, LambdaError> {
+        if self.action == CertificateAction::TestRotation {
+            return self.test_rotation().await;
+        }
+
+        if self.action == CertificateAction::Audit {
+            return self.audit().await;
+        }
+
+        if self.action == CertificateAction::Revoke {
+            return self.revoke().await;
+        }
+
+        if self.action == CertificateAction::Preflight {
+            return self.preflight().await;
+        }
+
+        if self.action == CertificateAction::Schedule {
+            return self.schedule().await;
+        }
+
+        let started = Instant::now();
+        let desired = DesiredState::new(self.domain_names.clone(), self.key_algorithm, self.renewal_threshold_days);
+        let actual = subsegment("Observe", ActualState::observe(&self.storage, &self.domain_names)).await;
+        let mut plan = ReconcilePlan::new(&desired, &actual);
+        if let Some(storage_indexes) = &self.storage_indexes {
+            plan.restrict(storage_indexes);
+        }
+        self.phases.record("Observe", started);
+
+        if plan.is_converged() {
+            info!(
+                "All storage targets hold a certificate valid for more than {} days; skipping renewal",
+                self.renewal_threshold_days
+            );
+            return Ok(Response::Certificate(CertificateResponse {
+                finished: true,
+                status: CertificateResponseStatus::Skipped,
+                storage: vec![],
+                plan: plan.actions,
+                report: None,
+                diff: None,
+                notifications: vec![],
+                hooks: vec![],
+                audit: None,
+                revocation: None,
+                readiness: None,
+                dry_run: None,
+                schedule: None,
+                task_status: None,
+                targets: vec![],
+                failed_storage_indexes: vec![],
+                error: None,
+            }));
+        }
+
+        if let Some(issuance) = self.dry_run {
+            return self.plan_dry_run(issuance, plan).await;
+        }
+
+        let components = match plan.copy_components(&actual)? {
+            Some(components) => components,
+            None => {
+                if self.preflight {
+                    let readiness = self.check_readiness().await;
+                    if !readiness.ready() {
+                        return Err(CertificateRequestError::preflight_failed(readiness.problems().join("; ")));
+                    }
+                }
+
+                let started = Instant::now();
+                let components = traced("Issue", self.issue_certificate()).await;
+                self.phases.record("Issue", started);
+                let components = match components {
+                    Ok(components) => components,
+                    Err(e) => return Err(self.check_rate_limit(e).await),
+                };
+                if let (Some(lifecycle_events), Ok(info)) =
+                    (&self.lifecycle_events, CertificateInfo::from_pem(&components.cert_pem))
+                {
+                    lifecycle_events.emit(vec![LifecycleEvent::issued(&self.domain_names, &info)]).await;
+                }
+                components
+            }
+        };
+
+        // Never push a certificate that doesn't match its key, domain names, or a trusted root into storage.
+        traced("Check", self.consistency_check.check(&components, &self.domain_names)).await?;
+        self.certificate = CertificateInfo::from_pem(&components.cert_pem).ok();
+
+        let started = Instant::now();
+        let result = self.save_certificates(components, plan).await;
+        self.phases.record("Store", started);
+        result
+    }
+
+    /// Report the writes the storage targets that aren't up to date would receive, without writing anything. With
+    /// Staging issuance, a certificate is first issued from the staging directory (unless the plan copies an existing
+    /// certificate) and checked; it's never stored.
+    async fn plan_dry_run(&mut self, issuance: DryRunIssuance, plan: ReconcilePlan) -> Result<Response, LambdaError> {
+        let issuance = match plan.copy_source() {
+            Some(_) => DryRunIssuance::Skip,
+            None => issuance,
+        };
+
+        let (serial, staging_writes) = match issuance {
+            DryRunIssuance::Skip => (None, vec![]),
+            DryRunIssuance::Staging => {
+                if self.preflight {
+                    let readiness = self.check_readiness().await;
+                    if !readiness.ready() {
+                        return Err(CertificateRequestError::preflight_failed(readiness.problems().join("; ")));
+                    }
+                }
+
+                let started = Instant::now();
+                let components = traced("Issue", self.issue_certificate()).await;
+                self.phases.record("Issue", started);
+                let components = match components {
+                    Ok(components) => components,
+                    Err(e) => return Err(self.check_rate_limit(e).await),
+                };
+
+                // Staging roots aren't in the system trust store, so the chain isn't verified.
+                check_components(&components, &self.domain_names, false, &[])?;
+
+                let store = AccountKeyStore::from_env()?;
+                let mut staging_writes =
+                    vec![store.planned_write(self.account.as_deref(), &self.contacts[0], &self.dir_host)];
+                staging_writes.extend(self.auth.planned_writes(&self.domain_names));
+                (Some(CertificateInfo::from_pem(&components.cert_pem)?.serial), staging_writes)
+            }
+        };
+
+        let targets: Vec<DryRunTarget> = plan
+            .targets()
+            .into_iter()
+            .map(|storage_index| {
+                let storage = &self.storage[storage_index];
+                DryRunTarget {
+                    storage_index,
+                    type_name: storage.type_name().to_string(),
+                    writes: storage.planned_writes(&self.domain_names, self.store_alternate_chain),
+                }
+            })
+            .collect();
+
+        let report = DryRunReport {
+            issuance,
+            directory: self.directory.clone(),
+            serial,
+            staging_writes,
+            targets,
+        };
+
+        info!(
+            "Dry run for {}: {} writes to {} storage targets would be made",
+            self.domain_names.join(" "),
+            report.writes(),
+            report.targets.len()
+        );
+
+        Ok(Response::Certificate(CertificateResponse {
+            finished: true,
+            status: CertificateResponseStatus::DryRun,
+            storage: vec![],
+            plan: plan.actions,
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: None,
+            revocation: None,
+            readiness: None,
+            dry_run: Some(report),
+            schedule: None,
+            task_status: None,
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: None,
+        }))
+    }
+
+    /// Publish a synthetic rotation manifest for the current certificate without issuing a new one.
+    async fn test_rotation(&self) -> Result<Response, LambdaError> {
+        let record = match read_inventory(&self.domain_names).await? {
+            Some(record) => record,
+            None => return Err(CertificateRequestError::inventory_not_found(self.domain_names.join(" "))),
+        };
+
+        info!("Publishing test rotation for certificate with serial {}", record.serial);
+        write_rotation_manifest(&RotationManifest::from_inventory(&record, true)).await?;
+
+        Ok(Response::Certificate(CertificateResponse {
+            finished: true,
+            status: CertificateResponseStatus::Success,
+            storage: record.storage_results,
+            plan: vec![],
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: None,
+            revocation: None,
+            readiness: None,
+            dry_run: None,
+            schedule: None,
+            task_status: None,
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: None,
+        }))
+    }
+
+    /// Report the certificate held by each storage target without issuing or writing anything. The run fails if
+    /// any target's certificate is missing, unreadable, or expires within the renewal threshold, so notifications
+    /// configured for failures act as an alert.
+    async fn audit(&mut self) -> Result<Response, LambdaError> {
+        let started = Instant::now();
+        let actual = ActualState::observe(&self.storage, &self.domain_names).await;
+        let audit = AuditReport::new(&self.storage, &actual, self.renewal_threshold_days, now_epoch_secs());
+        self.phases.record("Observe", started);
+
+        let problems = audit.problems();
+        for problem in &problems {
+            warn!("Audit of {}: {}", self.domain_names.join(" "), problem);
+        }
+
+        Ok(Response::Certificate(CertificateResponse {
+            finished: true,
+            status: if audit.healthy() {
+                CertificateResponseStatus::Success
+            } else {
+                CertificateResponseStatus::Failed
+            },
+            storage: vec![],
+            plan: vec![],
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: Some(audit),
+            revocation: None,
+            readiness: None,
+            dry_run: None,
+            schedule: None,
+            task_status: None,
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: None,
+        }))
+    }
+
+    /// Revoke a certificate held by the storage targets. The certificate and its private key are re-read from a
+    /// target that holds both.
+    async fn revoke(&mut self) -> Result<Response, LambdaError> {
+        let started = Instant::now();
+        let actual = ActualState::observe(&self.storage, &self.domain_names).await;
+        self.phases.record("Observe", started);
+
+        let observed = match self.revocation.select(&actual) {
+            Some(observed) => observed,
+            None => {
+                return Err(CertificateRequestError::revocation_target_not_found(format!(
+                    "no storage target for {} holds {} with its private key",
+                    self.domain_names.join(" "),
+                    match &self.revocation.serial {
+                        Some(serial) => format!("certificate {}", serial),
+                        None => "a certificate".to_string(),
+                    }
+                )))
+            }
+        };
+
+        let started = Instant::now();
+        let revocation = self.revocation.revoke(&self.directory, observed).await;
+        self.phases.record("Revoke", started);
+
+        Ok(Response::Certificate(CertificateResponse {
+            finished: true,
+            status: CertificateResponseStatus::Success,
+            storage: vec![],
+            plan: vec![],
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: None,
+            revocation: Some(revocation?),
+            readiness: None,
+            dry_run: None,
+            schedule: None,
+            task_status: None,
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: None,
+        }))
+    }
+
+    /// Check whether each domain name is ready to be validated without contacting the CA. The run's status is
+    /// Failed if any check failed.
+    async fn preflight(&mut self) -> Result<Response, LambdaError> {
+        let readiness = self.check_readiness().await;
+
+        Ok(Response::Certificate(CertificateResponse {
+            finished: true,
+            status: if readiness.ready() {
+                CertificateResponseStatus::Success
+            } else {
+                CertificateResponseStatus::Failed
+            },
+            storage: vec![],
+            plan: vec![],
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: None,
+            revocation: None,
+            readiness: Some(readiness),
+            dry_run: None,
+            schedule: None,
+            task_status: None,
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: None,
+        }))
+    }
+
+    /// Create or update the renewal rule so it fires when the certificate held by the storage targets is due for
+    /// renewal (or shortly if a target doesn't hold one yet).
+    async fn schedule(&mut self) -> Result<Response, LambdaError> {
+        let scheduler = RenewalScheduler::required()?;
+
+        let started = Instant::now();
+        let actual = ActualState::observe(&self.storage, &self.domain_names).await;
+        self.phases.record("Observe", started);
+
+        let renew_at =
+            next_renewal(&actual, self.renewal_threshold_days, now_epoch_secs() + RENEWAL_SCHEDULE_MIN_DELAY_SECONDS);
+        let schedule = scheduler.schedule(&self.domain_names, renew_at, &self.original).await?;
+
+        Ok(Response::Certificate(CertificateResponse {
+            finished: true,
+            status: CertificateResponseStatus::Success,
+            storage: vec![],
+            plan: vec![],
+            report: None,
+            diff: None,
+            notifications: vec![],
+            hooks: vec![],
+            audit: None,
+            revocation: None,
+            readiness: None,
+            dry_run: None,
+            schedule: Some(schedule),
+            task_status: None,
+            targets: vec![],
+            failed_storage_indexes: vec![],
+            error: None,
+        }))
+    }
+
+    /// Move the renewal rule of a scheduled renewal to the next renewal time: when the certificate now held by the
+    /// storage targets is due, but at least RENEWAL_SCHEDULE_RETRY_SECONDS from now so a target that still lacks a
+    /// certificate (or a failed run) is retried the next day rather than immediately. A failure to reschedule is
+    /// logged; it doesn't change the result of the run.
+    async fn reschedule_renewal(&mut self, result: &mut Result<Response, LambdaError>) {
+        let scheduler = match RenewalScheduler::from_env() {
+            Some(scheduler) => scheduler,
+            None => {
+                warn!("ScheduleRenewal is set, but renewal scheduling is not enabled; not rescheduling");
+                return;
+            }
+        };
+
+        let earliest = now_epoch_secs() + RENEWAL_SCHEDULE_RETRY_SECONDS;
+        let renew_at = match result {
+            Ok(_) => {
+                let actual = ActualState::observe(&self.storage, &self.domain_names).await;
+                next_renewal(&actual, self.renewal_threshold_days, earliest)
+            }
+            Err(_) => earliest,
+        };
+
+        match scheduler.schedule(&self.domain_names, renew_at, &self.original).await {
+            Ok(schedule) => {
+                if let Ok(Response::Certificate(response)) = result {
+                    response.schedule = Some(schedule);
+                }
+            }
+            Err(e) => error!("Failed to reschedule renewal of {}: {:#}", self.domain_names.join(" "), e),
+        }
+    }
+
+    /// Run the readiness checks for the challenge type used by the authorization handler, logging any problems.
+    async fn check_readiness(&mut self) -> ReadinessReport {
+        let started = Instant::now();
+        let readiness = ReadinessReport::check(&self.domain_names, self.auth.challenge_type()).await;
+        self.phases.record("Preflight", started);
+
+        for problem in readiness.problems() {
+            warn!("Preflight for {}: {}", self.domain_names.join(" "), problem);
+        }
+
+        readiness
+    }
+
+    /// Issue a new certificate from the ACME server.
+    async fn issue_certificate(&mut self) -> Result<CertificateComponents, LambdaError> {
+        // Reuse the account established by a previous (warm) invocation if possible. The account depends on the
+        // directory actually contacted and on where its key is kept, as well as on the contacts.
+        let gateway = AcmeGateway::from_env()?;
+        let directory_url = gateway.rewrite_directory(&self.directory);
+        let store = AccountKeyStore::from_env()?;
+        let key_source = store.key_source(self.account.as_deref(), &self.contacts[0], &self.dir_host);
+        let account_key = config_key(&[&directory_url, &key_source, &self.contacts.join(",")]);
+        let account = match cached_account(&account_key) {
+            Some(account) => {
+                info!("Reusing ACME account for directory {} from a previous invocation", self.directory);
+                account
+            }
+            None => {
+                let mut db = DirectoryBuilder::new(directory_url);
+                if let Some(http_client) = gateway.http_client().await? {
+                    db.http_client(http_client);
+                }
+
+                let dir: Arc<Directory> = db.build().await?;
+
+                if external_account_required(&dir) && self.external_account_binding.is_none() {
+                    error!("ACME server at {} requires External Account Binding", self.directory);
+                    return Err(InvalidCertificateRequest::invalid_external_account_binding(format!(
+                        "The ACME server at {} requires ExternalAccountBinding",
+                        self.directory
+                    )));
+                }
+
+                let mut account_builder = AccountBuilder::new(dir);
+                account_builder.contact(self.contacts.clone());
+                account_builder.terms_of_service_agreed(true);
+
+                self.set_private_key(&store, &mut account_builder).await?;
+
+                info!("Creating/finding existing account from directory {}", self.directory);
+                let account: Arc<Account> = account_builder.build().await?;
+                cache_account(account_key, account.clone());
+                account
+            }
+        };
 
         info!("Setting up authorization handler");
         self.auth.setup().await?;
 
-        info!("Creating/finding existing account from directory {}", self.directory);
-        let account: Arc<Account> = account_builder.build().await?;
+        // Account established -- go ahead and generate the order, as long as it's within the issuance limits. Dry
+        // runs only issue from staging directories, which don't count against them.
+        if self.dry_run.is_none() {
+            self.issuance_limits.acquire(&self.domain_names).await?;
+        }
 
-        // Account established -- go ahead and generate the order.
-        let mut order_builder = OrderBuilder::new(account);
-        for domain_name in &self.domain_names {
-            order_builder.add_dns_identifier(domain_name.clone());
+        let capture = AcmeDebugCapture::default();
+        match self.place_order(account, &capture).await {
+            Ok(components) => Ok(components),
+            Err(e) if RateLimit::from_error(&e).is_some() || self.dry_run.is_some() => Err(e),
+            Err(e) => Err(self.save_debug_artifact(capture, e).await),
         }
+    }
+
+    /// If an issuance error is an ACME rate limit, convert it to a RateLimited error, scheduling a retry if
+    /// configured. Other errors are returned unchanged.
+    async fn check_rate_limit(&self, e: LambdaError) -> LambdaError {
+        let rate_limit = match RateLimit::from_error(&e) {
+            Some(rate_limit) => rate_limit,
+            None => return e,
+        };
+
+        let retry_after = format_utc(rate_limit.retry_after);
+        error!("Rate limited by the ACME server until {}: {}", retry_after, rate_limit.detail);
 
+        // A dry run's retry would be another dry run, which isn't worth scheduling.
+        let retry_rule = match RateLimitRetryScheduler::from_env().filter(|_| self.dry_run.is_none()) {
+            None => None,
+            Some(scheduler) => {
+                match scheduler.schedule(&self.domain_names, rate_limit.retry_after, &self.original).await {
+                    Ok(rule_name) => Some(rule_name),
+                    Err(e2) => {
+                        error!("Failed to schedule a retry: {:#}", e2);
+                        None
+                    }
+                }
+            }
+        };
+
+        CertificateRequestError::rate_limited(rate_limit.detail, retry_after, retry_rule)
+    }
+
+    /// Place an order, complete its authorizations, and retrieve the certificate. If the order fails, its state as
+    /// last seen from the ACME server is recorded in `capture`.
+    async fn place_order(
+        &self,
+        account: Arc<Account>,
+        capture: &AcmeDebugCapture,
+    ) -> Result<CertificateComponents, LambdaError> {
         info!("Creating order for domain names: {:?}", self.domain_names);
-        let order = order_builder.build().await?;
+        faults::inject(Fault::AcmeServerError)?;
+        let order = traced(
+            "ACME NewOrder",
+            retry("NewOrder", classify_acme_error, || {
+                let mut order_builder = OrderBuilder::new(account.clone());
+                for domain_name in &self.domain_names {
+                    order_builder.add_dns_identifier(domain_name.clone());
+                }
+                async move { order_builder.build().await }
+            }),
+        )
+        .await?;
         info!("Order created");
         debug!("Order details: {:?}", order);
 
         // Order generated; get the authorizations. There will be one for each domain.
         info!("Getting authorizations for order");
-        let authorizations = order.authorizations().await?;
+        let authorizations = traced(
+            "ACME GetAuthorizations",
+            retry("GetAuthorizations", classify_acme_error, || order.authorizations()),
+        )
+        .await?;
         info!("Authorizations retrieved");
 
         let mut auth_futures = FuturesOrdered::new();
@@ -86,35 +801,40 @@ impl ValidatedCertificateRequest {
         }
 
         if !errors.is_empty() {
+            capture.record_order(&order).await;
             return Err(errors.into_iter().next().unwrap());
         }
 
         // Wait until the order is ready to be finalized.
-        let order = order.wait_ready(CHECK_WAIT_DURATION, MAX_ORDER_RETRIES).await?;
+        let order = traced("ACME WaitReady", order.wait_ready(CHECK_WAIT_DURATION, MAX_ORDER_RETRIES)).await?;
 
         match &order.status {
             OrderStatus::Invalid => {
                 error!("Order has become invalid");
+                capture.record_order(&order).await;
                 return Err(CertificateRequestError::order_failed());
             }
             OrderStatus::Ready => info!("Order is ready to be finalized"),
             _ => {
                 error!("Unexpected order status: {:?}", order.status);
+                capture.record_order(&order).await;
                 return Err(CertificateRequestError::order_failed());
             }
         }
 
         info!("Finalizing order");
-        let (order, pkey_pem) = self.finalize_order(order).await?;
+        let (order, pkey_pem) = traced("ACME Finalize", self.finalize_order(order, capture)).await?;
 
         info!("Retrieving certificates");
-        let components = self.retrieve_order(order, pkey_pem).await?;
-
-        self.save_certificates(components).await
+        traced("ACME Download", self.retrieve_order(order, pkey_pem)).await
     }
 
     async fn handle_authorization(&self, auth: Authorization) -> Result<(), LambdaError> {
         let domain_name = auth.identifier.value.to_string();
+        traced(format!("ACME Authorization {}", domain_name), self.complete_authorization(auth, &domain_name)).await
+    }
+
+    async fn complete_authorization(&self, auth: Authorization, domain_name: &str) -> Result<(), LambdaError> {
         info!("Requesting authorization for domain {}", domain_name);
         let (mut auth, mut challenge, cleanup_directives) = self.auth.auth(auth).await?;
 
@@ -152,30 +872,59 @@ impl ValidatedCertificateRequest {
         Ok(())
     }
 
-    async fn finalize_order(&self, order: Order) -> Result<(Order, String), LambdaError> {
-        info!("Finalizing order");
+    /// Save what we know about a failed order to the debug artifact store, if one is configured, and return an error
+    /// that references it. If no store is configured or the artifact can't be saved, the original error is returned.
+    async fn save_debug_artifact(&self, capture: AcmeDebugCapture, e: LambdaError) -> LambdaError {
+        let store = match DebugArtifactStore::from_env() {
+            Some(store) => store,
+            None => return e,
+        };
 
-        // All authorizations passed successfully. Finalize the order.
-        // Generate a 2048-bit RSA private key for the certificate.
-        let private_key = match Rsa::<Private>::generate(2048) {
-            Ok(pk) => pk,
-            Err(e) => {
-                error!("Unable to generate 2048 bit RSA key: {:#}", e);
-                return Err(Box::new(e));
+        let artifact = match capture.finish(&self.directory, &self.domain_names, &e, self.auth.dns_lookups()) {
+            Ok(artifact) => artifact,
+            Err(e2) => {
+                error!("Failed to capture debug artifacts: {:#}", e2);
+                return e;
             }
         };
 
-        let pkey = PKey::from_rsa(private_key)?;
+        match store.save(&artifact).await {
+            Ok(()) => CertificateRequestError::failed_with_debug_artifact(
+                format!("{:#}", e),
+                &artifact.id,
+                store.location(&artifact.id),
+            ),
+            Err(_) => e,
+        }
+    }
+
+    async fn finalize_order(&self, order: Order, capture: &AcmeDebugCapture) -> Result<(Order, String), LambdaError> {
+        info!("Finalizing order");
+
+        // All authorizations passed successfully. Finalize the order.
+        // Generate a private key for the certificate using the requested algorithm.
+        info!("Generating {} private key for certificate", self.key_algorithm);
+        let pkey = self.key_algorithm.generate()?;
         let pkey_pem = match pkey.private_key_to_pem_pkcs8() {
             Ok(pem) => from_utf8(&pem)?.to_string(),
             Err(e) => {
-                error!("Failed to convert RSA private key to PEM format: {:#}", e);
+                error!("Failed to convert private key to PEM format: {:#}", e);
                 return Err(Box::new(e));
             }
         };
 
-        // Generate the certificate signing request (CSR).
-        let order = match order.finalize(Csr::Automatic(pkey.clone())).await {
+        // Generate the certificate signing request (CSR). The ACME client builds it unless extensions were requested.
+        let csr_der = if self.csr.is_default() {
+            None
+        } else {
+            Some(self.csr.build(&pkey, &self.domain_names)?.to_der()?)
+        };
+        let csr = || match &csr_der {
+            Some(der) => Csr::Custom(X509Req::from_der(der).expect("CSR was just encoded")),
+            None => Csr::Automatic(pkey.clone()),
+        };
+
+        let order = match retry("FinalizeOrder", classify_acme_error, || order.finalize(csr())).await {
             Ok(o) => {
                 info!("Order finalizalization submitted.");
                 o
@@ -201,11 +950,13 @@ impl ValidatedCertificateRequest {
         match &order.status {
             OrderStatus::Invalid => {
                 error!("Error has become invalid");
+                capture.record_order(&order).await;
                 Err(CertificateRequestError::order_failed())
             }
             OrderStatus::Valid => Ok((order, pkey_pem)),
             _ => {
                 error!("Unexpected order status: {:?}", order.status);
+                capture.record_order(&order).await;
                 Err(CertificateRequestError::order_failed())
             }
         }
@@ -215,7 +966,7 @@ impl ValidatedCertificateRequest {
         // Ready -- download the certificates. We expect at least 2 -- our certificate and the
         // intermediate that signed it.
         info!("Downloading certificates");
-        let certs = match order.certificate().await {
+        let certs = match retry("DownloadCertificate", classify_acme_error, || order.certificate()).await {
             Ok(maybe_certs) => match maybe_certs {
                 None => {
                     error!("No certificates returned");
@@ -237,9 +988,40 @@ impl ValidatedCertificateRequest {
             return Err(CertificateRequestError::empty_certificate_result());
         }
 
+        // The alternate chain is needed if it's being stored alongside the default chain or if any target wants it
+        // instead of the default chain.
+        let wants_alternate = self.storage.iter().any(|storage| storage.chain() == ChainVariant::Alternate);
+        let alternate_chain = if self.store_alternate_chain || wants_alternate {
+            match find_alternate_chain(&certs) {
+                Ok(alternate) => alternate,
+                Err(e) => {
+                    error!("Failed to determine alternate certificate chain; storing default chain only: {:#}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        // Replace the default chain with the preferred one, if the CA's chain can be shortened to it.
+        let certs = match &self.preferred_chain {
+            Some(root_cn) => match select_preferred_chain(&certs, root_cn) {
+                Ok(Some(preferred)) => preferred,
+                Ok(None) => {
+                    info!("No certificate chain ends at {}; using the default chain", root_cn);
+                    certs
+                }
+                Err(e) => {
+                    error!("Failed to select preferred certificate chain; using the default chain: {:#}", e);
+                    certs
+                }
+            },
+            None => certs,
+        };
+
         let mut certs_pem = Vec::with_capacity(certs.len());
 
-        for cert in certs {
+        for cert in &certs {
             match cert.to_pem() {
                 Ok(pem) => certs_pem.push(from_utf8(&pem)?.to_string()),
                 Err(e) => {
@@ -252,168 +1034,119 @@ impl ValidatedCertificateRequest {
         let chain_pem = certs_pem[1..].join("\n");
         let cert_pem = certs_pem[0].clone();
         let fullchain_pem = format!("{}\n{}", cert_pem, chain_pem);
+        let alternate_chain = match alternate_chain {
+            Some(intermediates) => Some(CertificateChain::from_certs(&cert_pem, &intermediates)?),
+            None => None,
+        };
 
         Ok(CertificateComponents {
             cert_pem,
             chain_pem,
             fullchain_pem,
             pkey_pem,
+            alternate_chain,
         })
     }
 
-    async fn set_private_key(&self, account_builder: &mut AccountBuilder) -> Result<(), LambdaError> {
-        // Get the existing private key for this account.
-        let ssm_parameter_path = ssm_acme_parameter_path();
-        let ssm = SsmClient::new(Region::default());
-        let pk_param = format!(
-            "{}/PrivateKeys/{}/{}",
-            ssm_parameter_path,
-            self.contacts[0].replace(":", "-").replace("/", "-").replace("@", "_"),
-            self.dir_host,
-        );
-
-        info!("Looking for existing account private key in SSM parameter {}", pk_param);
-
-        let gp_request = GetParameterRequest {
-            name: pk_param.clone(),
-            with_decryption: Some(true),
-        };
-
-        match ssm.get_parameter(gp_request).await {
-            Ok(result) => match result.parameter {
-                None => (),
-                Some(param) => {
-                    // We got a private key from SSM; parse it here.
-                    debug!("Parsing private key as PEM data");
-                    let pkey_str = match param.value {
-                        Some(s) => s,
-                        None => {
-                            error!("No value returned by SSM for {}", pk_param);
-                            return Err(CertificateRequestError::unexpected_aws_response(format!(
-                                "No value returned for SSM parameter {}",
-                                pk_param
-                            )));
-                        }
-                    };
-
-                    match PKey::private_key_from_pem(&pkey_str.as_bytes()) {
-                        Ok(pkey) => {
-                            // Parsed ok -- set it and return.
-                            info!("Using existing private key from SSM parameter {}", pk_param);
-                            account_builder.private_key(pkey);
-                            account_builder.only_return_existing(true);
-                            return Ok(());
-                        }
-                        Err(e) => {
-                            error!("Failed to parse private key from SSM: {:#}", e);
-                            return Err(Box::new(e));
-                        }
-                    }
-                }
-            },
-            Err(e1) => {
-                debug!("SSM error: {:#}", e1);
-                match e1 {
-                    // No private key exists -- this is ok; we'll let the ACME client generate one.
-                    RusotoError::Service(GetParameterError::ParameterNotFound(_)) => (),
-
-                    // Unexpected error -- just propagate it up.
-                    _ => return Err(Box::new(e1)),
-                }
-            }
-        };
-
-        info!("Generating new private key");
-
-        // No private key exists. Generate one.
-        let rsa = match Rsa::generate(2048) {
-            Ok(rsa) => rsa,
-            Err(e) => {
-                error!("Failed to generate 2048-bit RSA key: {}", e);
-                return Err(Box::new(e));
-            }
-        };
-
-        let pkey = match PKey::from_rsa(rsa) {
-            Ok(pkey) => pkey,
-            Err(e) => {
-                error!("Failed to convert RSA private key to PKey<Private>: {}", e);
-                return Err(Box::new(e));
-            }
-        };
-
-        // Save this to SSM first.
-        let pem = match pkey.private_key_to_pem_pkcs8() {
-            Ok(pem) => pem,
-            Err(e) => {
-                error!("Failed to convert RSA private key to PEM: {}", e);
-                return Err(Box::new(e));
-            }
+    /// Set the account key from the account key store, generating and saving a new one if none exists. If a new key
+    /// needs an External Account Binding, its account is created here so acme2 only has to find it.
+    async fn set_private_key(
+        &self,
+        store: &AccountKeyStore,
+        account_builder: &mut AccountBuilder,
+    ) -> Result<(), LambdaError> {
+        let (pkey, existing) = match &self.account {
+            Some(account) => store.load_or_register(account, &self.directory, &self.contacts).await?,
+            None => store.load_or_generate(&self.contacts[0], &self.dir_host).await?,
         };
 
-        let pem_str = match from_utf8(&pem) {
-            Ok(pem_str) => pem_str.to_string(),
-            Err(e) => {
-                error!("Generated RSA private key contains non-UTF8 characters: {}", e);
-                return Err(Box::new(e));
+        // The binding is only needed to create the account; some CAs only allow each binding to be used once.
+        let existing = match (existing, &self.external_account_binding) {
+            (false, Some(eab)) => {
+                info!("Binding new ACME account to external account {}", eab.key_id);
+                eab.register_account(&self.directory, &pkey, &self.contacts).await?;
+                true
             }
+            (existing, _) => existing,
         };
 
-        let pp_request = PutParameterRequest {
-            name: pk_param.clone(),
-            description: Some(format!("ACMEv02 private key for {}", self.contacts[0])),
-            overwrite: Some(true),
-            type_: Some("SecureString".into()),
-            value: pem_str,
-            ..Default::default()
-        };
-
-        info!("Saving account private key to SSM parameter {}", pk_param);
-        match ssm.put_parameter(pp_request).await {
-            Ok(_) => info!("Private key saved"),
-            Err(e) => {
-                error!("Failed to save private key: {:#}", e);
-                return Err(Box::new(e));
-            }
+        account_builder.private_key(pkey);
+        if existing {
+            account_builder.only_return_existing(true);
         }
 
-        // And now set this for the account builder.
-        account_builder.private_key(pkey);
         Ok(())
     }
 
-    async fn save_certificates(&self, components: CertificateComponents) -> Result<Response, LambdaError> {
+    async fn save_certificates(
+        &self,
+        components: CertificateComponents,
+        plan: ReconcilePlan,
+    ) -> Result<Response, LambdaError> {
         let mut n_successes = 0u32;
         let mut n_failures = 0u32;
 
+        let indexes = plan.targets();
         let mut futures = FuturesOrdered::new();
-        for storage_provider in &self.storage {
-            futures.push(storage_provider.save_certificate(self.domain_names.clone(), components.clone()));
+        for index in indexes.iter() {
+            let storage = &self.storage[*index];
+            let components = components.clone().for_chain(storage.chain(), self.store_alternate_chain);
+            let name = format!("Store {} {}", storage.type_name(), index);
+            futures.push(traced(name, storage.save_certificate(self.domain_names.clone(), components)));
         }
 
         let mut results = Vec::new();
+        let mut targets = Vec::with_capacity(indexes.len());
 
-        while let Some(result) = futures.next().await {
-            match result {
-                Ok(result_set) => {
-                    for result in result_set {
-                        match &result {
-                            CertificateStorageResult::Error(_) => n_failures += 1,
-                            _ => n_successes += 1,
-                        }
-
-                        results.push(result);
-                    }
+        for index in indexes {
+            let result = futures.next().await.expect("One result per storage target");
+            if let Err(e) = &result {
+                error!("Failed to save certificate: {:#}", e);
+            }
+
+            let target = StorageTargetResult::new(index, self.storage[index].type_name(), result);
+            for result in &target.results {
+                match result {
+                    CertificateStorageResult::Error(_) => n_failures += 1,
+                    result if result.rolled_back() => n_failures += 1,
+                    _ => n_successes += 1,
                 }
-                Err(e) => {
-                    error!("Failed to save certificate: {:#}", e);
+            }
+
+            results.extend(target.results.iter().cloned());
+            targets.push(target);
+        }
+
+        #[cfg(feature = "ssh-output")]
+        if n_successes > 0 {
+            if let Some(ssh_output) = &self.ssh_output {
+                if let Err(e) = ssh_output.write(&self.domain_names, &components).await {
+                    error!("Failed to write OpenSSH output: {:#}", e);
                     n_failures += 1;
-                    results.push(CertificateStorageResult::Error(format!("Failed to save certificate: {:#}", e)));
+                    results.push(CertificateStorageResult::Error(format!("Failed to write OpenSSH output: {:#}", e)));
                 }
             }
         }
 
-        let status = if n_failures > 0 {
+        let failed_storage_indexes: Vec<usize> =
+            targets.iter().filter(|target| target.failed()).map(|target| target.storage_index).collect();
+
+        // Every storage target was written, but a failure outside of them (the OpenSSH output) was counted; there's
+        // no target to retry, but the run still needs attention.
+        let (task_status, error) = if failed_storage_indexes.is_empty() && n_failures > 0 {
+            let error = results.iter().rev().find_map(|result| match result {
+                CertificateStorageResult::Error(e) => Some(TaskError::new(STORAGE_WRITE_FAILED, e.clone())),
+                _ => None,
+            });
+            (TaskStatus::PartialFailure, error)
+        } else {
+            let n_failed = failed_storage_indexes.len();
+            (TaskStatus::new(targets.len() - n_failed, n_failed), targets_error(&targets))
+        };
+
+        let status = if results.iter().any(|result| result.rolled_back()) {
+            CertificateResponseStatus::RolledBack
+        } else if n_failures > 0 {
             if n_successes > 0 {
                 CertificateResponseStatus::PartialSuccess
             } else {
@@ -423,11 +1156,113 @@ impl ValidatedCertificateRequest {
             CertificateResponseStatus::Success
         };
 
+        let (diff, hooks) = if n_successes > 0 {
+            let diff = self.update_inventory(&components, &results).await;
+            self.emit_stored_events(&components, &results).await;
+            let payload = HookPayload::new(&self.domain_names, &status, self.certificate.as_ref(), &results);
+            (diff, run_hooks(&self.hooks, &payload).await)
+        } else {
+            (None, vec![])
+        };
+
         let cr = CertificateResponse {
             finished: true,
             status,
             storage: results,
+            plan: plan.actions,
+            report: None,
+            diff,
+            notifications: vec![],
+            hooks,
+            audit: None,
+            revocation: None,
+            readiness: None,
+            dry_run: None,
+            schedule: None,
+            task_status: Some(task_status),
+            targets,
+            failed_storage_indexes,
+            error,
         };
         Ok(Response::Certificate(cr))
     }
+
+    /// Send a CertificateStored lifecycle event for each storage target that was written.
+    async fn emit_stored_events(&self, components: &CertificateComponents, results: &[CertificateStorageResult]) {
+        let lifecycle_events = match &self.lifecycle_events {
+            Some(lifecycle_events) => lifecycle_events,
+            None => return,
+        };
+
+        let info = match CertificateInfo::from_pem(&components.cert_pem) {
+            Ok(info) => info,
+            Err(e) => {
+                error!("Failed to parse certificate for lifecycle events: {:#}", e);
+                return;
+            }
+        };
+
+        let events = results
+            .iter()
+            .filter(|result| !matches!(result, CertificateStorageResult::Error(_)))
+            .map(|result| LifecycleEvent::stored(&self.domain_names, &info, result))
+            .collect();
+
+        lifecycle_events.emit(events).await;
+    }
+
+    /// Record the certificate that was written in the inventory, returning what changed since the previous record
+    /// (if there was one). Failures are logged but otherwise ignored; the next run will simply observe the storage
+    /// targets without the benefit of the inventory.
+    async fn update_inventory(
+        &self,
+        components: &CertificateComponents,
+        results: &[CertificateStorageResult],
+    ) -> Option<InventoryDiff> {
+        let info = match CertificateInfo::from_pem(&components.cert_pem) {
+            Ok(info) => info,
+            Err(e) => {
+                error!("Failed to parse certificate for inventory: {:#}", e);
+                return None;
+            }
+        };
+
+        let previous = match read_inventory(&self.domain_names).await {
+            Ok(previous) => previous,
+            Err(e) => {
+                error!("Failed to read previous inventory: {:#}", e);
+                None
+            }
+        };
+
+        let record = InventoryRecord {
+            domain_names: self.domain_names.clone(),
+            serial: info.serial,
+            not_after: info.not_after,
+            updated_at: now_epoch_secs(),
+            storage_results: results.to_vec(),
+            alternate_chain: components.alternate_chain.is_some(),
+            request: Some(self.original.clone()),
+        };
+        let diff = previous.map(|previous| InventoryDiff::new(&previous, &record));
+        match &diff {
+            Some(diff) if diff.has_changes() => {
+                info!("Certificate for {} changed since the previous run: {:?}", self.domain_names.join(" "), diff)
+            }
+            Some(_) => info!("Certificate for {} is unchanged since the previous run", self.domain_names.join(" ")),
+            None => (),
+        }
+
+        if let Err(e) = write_inventory(&record).await {
+            error!("Failed to update inventory: {:#}", e);
+        }
+
+        if self.rotation_manifest {
+            if let Err(e) = write_rotation_manifest(&RotationManifest::from_inventory(&record, false)).await {
+                error!("Failed to publish rotation manifest: {:#}", e);
+            }
+        }
+
+        diff
+    }
 }
@@ -0,0 +1,145 @@
+use {
+    crate::{errors::CertificateRequestError, warm::config_key},
+    lambda_runtime::Error as LambdaError,
+    log::warn,
+    schemars::JsonSchema,
+    serde::{self, Deserialize, Serialize},
+    std::collections::HashMap,
+};
+
+/// The tool recorded in writer fingerprints.
+const WRITER_TOOL: &str = "letsencrypt-certs-aws";
+
+/// The S3 object metadata key and SSM parameter tag key holding the writer fingerprint.
+pub const WRITER_METADATA_KEY: &str = "certificate-writer";
+
+/// Detection of other writers on S3 and SSM storage targets. Each object or parameter written is stamped with a
+/// fingerprint identifying this tool and the storage configuration; before writing, the fingerprint on the existing
+/// material is compared with ours, so two automation systems pointed at the same path don't silently overwrite each
+/// other's certificates. In JSON (alongside the target's other settings):
+///
+///     {
+///         // Identifies this writer in the fingerprint. This defaults to a hash of the storage configuration, so
+///         // two requests writing the same location with different settings are also treated as different
+///         // writers. Set it explicitly to share a location between requests deliberately.
+///         "WriterId": str,
+///
+///         // What to do when the existing material was last written by a different writer (including material
+///         // with no fingerprint, e.g. a manual upload): "Warn" overwrites it and logs a warning, "Fail" leaves it
+///         // untouched and fails this target, and "Ignore" overwrites it silently. This defaults to "Warn".
+///         "OnWriterConflict": str,
+///     }
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct WriterCheck {
+    #[serde(rename = "WriterId", default, skip_serializing_if = "Option::is_none")]
+    pub writer_id: Option<String>,
+
+    #[serde(rename = "OnWriterConflict", default)]
+    pub on_conflict: WriterConflictPolicy,
+}
+
+/// How to handle material last written by a different writer.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, JsonSchema, PartialEq, Serialize)]
+pub enum WriterConflictPolicy {
+    #[default]
+    Warn,
+    Fail,
+    Ignore,
+}
+
+/// Who last wrote a storage location.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PreviousWriter {
+    /// Nothing is stored there yet.
+    Nothing,
+
+    /// Something is stored there without a fingerprint.
+    Unknown,
+
+    /// Something is stored there with this fingerprint.
+    Writer(String),
+}
+
+impl WriterCheck {
+    /// Returns our fingerprint for a storage target with the given configuration.
+    pub fn fingerprint<T: Serialize>(&self, config: &T) -> Result<String, LambdaError> {
+        let writer_id = match &self.writer_id {
+            Some(writer_id) => writer_id.clone(),
+            None => {
+                // Changing these settings shouldn't make us a different writer.
+                let mut config = serde_json::to_value(config)?;
+                if let Some(config) = config.as_object_mut() {
+                    config.remove("WriterId");
+                    config.remove("OnWriterConflict");
+                }
+                config_key(&[&config.to_string()])[..16].to_string()
+            }
+        };
+
+        Ok(format!("{}/{}", WRITER_TOOL, writer_id))
+    }
+
+    /// Decide whether a location may be written, given who last wrote it.
+    pub fn check(&self, location: &str, previous: &PreviousWriter, fingerprint: &str) -> Result<(), LambdaError> {
+        let other = match previous {
+            PreviousWriter::Nothing => return Ok(()),
+            PreviousWriter::Writer(writer) if writer == fingerprint => return Ok(()),
+            PreviousWriter::Writer(writer) => writer.clone(),
+            PreviousWriter::Unknown => "an unknown writer".to_string(),
+        };
+
+        match self.on_conflict {
+            WriterConflictPolicy::Ignore => Ok(()),
+            WriterConflictPolicy::Warn => {
+                warn!("{} was last written by {}; overwriting it as {}", location, other, fingerprint);
+                Ok(())
+            }
+            WriterConflictPolicy::Fail => Err(CertificateRequestError::writer_conflict(location, other)),
+        }
+    }
+}
+
+/// Returns S3 object metadata recording the writer fingerprint.
+pub fn writer_metadata(fingerprint: &str) -> HashMap<String, String> {
+    let mut metadata = HashMap::new();
+    metadata.insert(WRITER_METADATA_KEY.to_string(), fingerprint.to_string());
+    metadata
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use {
+        super::{PreviousWriter, WriterCheck, WriterConflictPolicy},
+        serde_json::json,
+    };
+
+    #[test]
+    fn test_writer_check() {
+        let check: WriterCheck = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(check.on_conflict, WriterConflictPolicy::Warn);
+
+        let ours = check.fingerprint(&json!({"Bucket": "certs", "Prefix": "www/"})).unwrap();
+        let theirs =
+            check.fingerprint(&json!({"Bucket": "certs", "Prefix": "www/", "AllowPrivateKey": false})).unwrap();
+        assert!(ours.starts_with("letsencrypt-certs-aws/"));
+        assert_ne!(ours, theirs);
+        assert_eq!(
+            check.fingerprint(&json!({"Bucket": "certs", "Prefix": "www/", "OnWriterConflict": "Fail"})).unwrap(),
+            ours
+        );
+
+        let named = WriterCheck {
+            writer_id: Some("prod".to_string()),
+            on_conflict: WriterConflictPolicy::Fail,
+        };
+        assert_eq!(named.fingerprint(&json!({})).unwrap(), "letsencrypt-certs-aws/prod");
+
+        for previous in &[PreviousWriter::Nothing, PreviousWriter::Writer(ours.clone())] {
+            assert!(named.check("s3://certs/www/cert.pem", previous, &ours).is_ok());
+        }
+        assert!(named.check("s3://certs/www/cert.pem", &PreviousWriter::Unknown, &ours).is_err());
+        assert!(named.check("s3://certs/www/cert.pem", &PreviousWriter::Writer(theirs.clone()), &ours).is_err());
+        assert!(check.check("s3://certs/www/cert.pem", &PreviousWriter::Writer(theirs), &ours).is_ok());
+    }
+}
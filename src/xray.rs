@@ -0,0 +1,400 @@
+use {
+    crate::constants::{DEFAULT_XRAY_DAEMON_ADDRESS, ENV_XRAY_DAEMON_ADDRESS, ENV_XRAY_TRACE_ID},
+    lazy_static::lazy_static,
+    log::debug,
+    openssl::rand::rand_bytes,
+    rusoto_core::{
+        credential::DefaultCredentialsProvider,
+        request::{DispatchSignedRequest, DispatchSignedRequestFuture, HttpClient},
+        signature::SignedRequest,
+        Client,
+    },
+    serde::{self, Serialize},
+    std::{
+        collections::BTreeMap,
+        env::var,
+        fmt::Display,
+        future::Future,
+        net::UdpSocket,
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+/// Every document sent to the X-Ray daemon starts with this header line.
+const DAEMON_HEADER: &str = "{\"format\": \"json\", \"version\": 1}\n";
+
+/// The longest segment name X-Ray accepts.
+const MAX_NAME_LENGTH: usize = 200;
+
+lazy_static! {
+    /// The function's own credentials. These are fetched once and refreshed as they near expiration.
+    static ref DEFAULT_CREDENTIALS: DefaultCredentialsProvider =
+        DefaultCredentialsProvider::new().expect("Failed to create AWS credentials provider");
+
+    /// The Rusoto client used for AWS calls made with the function's own credentials. This is Rusoto's shared client
+    /// with a dispatcher that traces each call.
+    static ref SHARED_CLIENT: Client =
+        Client::new_with(default_credentials(), TracingDispatcher::new().expect("Failed to create HTTP client"));
+
+    /// The socket subsegments are sent to the X-Ray daemon from, if one could be opened.
+    static ref DAEMON_SOCKET: Option<UdpSocket> = UdpSocket::bind("0.0.0.0:0").ok();
+}
+
+tokio::task_local! {
+    /// The id of the subsegment being recorded, which calls made within it are nested under.
+    static PARENT_ID: String;
+}
+
+/// Returns the Rusoto client to create AWS service clients with, so their calls are traced. Use this instead of the
+/// service client's `new(region)`, which uses an untraced shared client.
+pub fn aws_client() -> Client {
+    SHARED_CLIENT.clone()
+}
+
+/// Returns the provider for the function's own credentials, shared with the client returned by `aws_client`.
+pub fn default_credentials() -> DefaultCredentialsProvider {
+    DEFAULT_CREDENTIALS.clone()
+}
+
+/// Record the time spent in `future` as an X-Ray subsegment named `name`, nested under the current subsegment (or
+/// the function's segment). Subsegments are only recorded when the invocation is sampled, i.e. the function has
+/// active tracing enabled; otherwise this just runs the future.
+pub async fn subsegment<N: Into<String>, F: Future>(name: N, future: F) -> F::Output {
+    let mut segment = match Subsegment::start(&name.into()) {
+        Some(segment) => segment,
+        None => return future.await,
+    };
+
+    let output = PARENT_ID.scope(segment.id.clone(), future).await;
+    segment.finish();
+    output
+}
+
+/// Like `subsegment`, but marks the subsegment as an error with the error message as its cause if the future fails.
+pub async fn traced<N, F, T, E>(name: N, future: F) -> Result<T, E>
+where
+    N: Into<String>,
+    F: Future<Output = Result<T, E>>,
+    E: Display,
+{
+    let mut segment = match Subsegment::start(&name.into()) {
+        Some(segment) => segment,
+        None => return future.await,
+    };
+
+    let result = PARENT_ID.scope(segment.id.clone(), future).await;
+    if let Err(e) = &result {
+        segment.set_error(e);
+    }
+    segment.finish();
+    result
+}
+
+/// The trace context Lambda passes to the function through the `_X_AMZN_TRACE_ID` environment variable, e.g.
+/// `Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct TraceHeader {
+    root: String,
+    parent: String,
+    sampled: bool,
+}
+
+impl TraceHeader {
+    fn parse(header: &str) -> Option<Self> {
+        let mut root = None;
+        let mut parent = None;
+        let mut sampled = false;
+
+        for part in header.split(';') {
+            match part.trim().split_once('=') {
+                Some(("Root", value)) => root = Some(value.to_string()),
+                Some(("Parent", value)) => parent = Some(value.to_string()),
+                Some(("Sampled", value)) => sampled = value == "1",
+                _ => (),
+            }
+        }
+
+        Some(Self {
+            root: root?,
+            parent: parent?,
+            sampled,
+        })
+    }
+
+    /// Returns the trace context of the current invocation, if it is being traced.
+    fn current() -> Option<Self> {
+        var(ENV_XRAY_TRACE_ID).ok().and_then(|header| Self::parse(&header)).filter(|header| header.sampled)
+    }
+}
+
+/// An X-Ray subsegment document. See https://docs.aws.amazon.com/xray/latest/devguide/xray-api-segmentdocuments.html
+#[derive(Debug, Serialize)]
+struct Subsegment {
+    name: String,
+    id: String,
+    trace_id: String,
+    parent_id: String,
+    #[serde(rename = "type")]
+    segment_type: &'static str,
+    start_time: f64,
+    end_time: f64,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    namespace: Option<&'static str>,
+
+    #[serde(skip_serializing_if = "is_false")]
+    error: bool,
+
+    #[serde(skip_serializing_if = "is_false")]
+    fault: bool,
+
+    #[serde(skip_serializing_if = "is_false")]
+    throttle: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cause: Option<SubsegmentCause>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    aws: BTreeMap<&'static str, String>,
+
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    http: BTreeMap<&'static str, BTreeMap<&'static str, u16>>,
+}
+
+#[derive(Debug, Serialize)]
+struct SubsegmentCause {
+    exceptions: Vec<SubsegmentException>,
+}
+
+#[derive(Debug, Serialize)]
+struct SubsegmentException {
+    id: String,
+    message: String,
+}
+
+impl Subsegment {
+    /// Start a subsegment under the current subsegment, or None if the invocation isn't being traced.
+    fn start(name: &str) -> Option<Self> {
+        let trace = TraceHeader::current()?;
+        let parent_id = PARENT_ID.try_with(|id| id.clone()).unwrap_or(trace.parent);
+
+        Some(Self {
+            name: segment_name(name),
+            id: new_id()?,
+            trace_id: trace.root,
+            parent_id,
+            segment_type: "subsegment",
+            start_time: now_secs(),
+            end_time: 0.0,
+            namespace: None,
+            error: false,
+            fault: false,
+            throttle: false,
+            cause: None,
+            aws: BTreeMap::new(),
+            http: BTreeMap::new(),
+        })
+    }
+
+    fn set_error<E: Display>(&mut self, e: &E) {
+        self.error = true;
+        self.cause = Some(SubsegmentCause {
+            exceptions: vec![SubsegmentException {
+                id: self.id.clone(),
+                message: format!("{:#}", e),
+            }],
+        });
+    }
+
+    /// Record the HTTP status of an AWS response, classifying it the way X-Ray does: throttling (429) and other
+    /// client errors are errors, server errors are faults.
+    fn set_status(&mut self, status: u16) {
+        self.http.insert("response", vec![("status", status)].into_iter().collect());
+        match status {
+            429 => {
+                self.error = true;
+                self.throttle = true;
+            }
+            400..=499 => self.error = true,
+            500..=599 => self.fault = true,
+            _ => (),
+        }
+    }
+
+    /// End the subsegment and send it to the X-Ray daemon. Failures are logged but otherwise ignored; tracing never
+    /// fails a run.
+    fn finish(&mut self) {
+        self.end_time = now_secs();
+
+        let socket = match DAEMON_SOCKET.as_ref() {
+            Some(socket) => socket,
+            None => return,
+        };
+
+        let document = match serde_json::to_string(self) {
+            Ok(document) => document,
+            Err(e) => {
+                debug!("Failed to serialize X-Ray subsegment {}: {}", self.name, e);
+                return;
+            }
+        };
+
+        if let Err(e) = socket.send_to(format!("{}{}", DAEMON_HEADER, document).as_bytes(), daemon_address()) {
+            debug!("Failed to send X-Ray subsegment {} to the daemon: {}", self.name, e);
+        }
+    }
+}
+
+/// A Rusoto dispatcher that records each AWS call as a subsegment in the AWS namespace, named for the service and
+/// annotated with the operation, region, request id, and HTTP status, so throttled or slow calls show up in traces.
+pub struct TracingDispatcher {
+    inner: HttpClient,
+}
+
+impl TracingDispatcher {
+    pub fn new() -> Result<Self, rusoto_core::request::TlsError> {
+        Ok(Self {
+            inner: HttpClient::new()?,
+        })
+    }
+}
+
+impl DispatchSignedRequest for TracingDispatcher {
+    fn dispatch(&self, request: SignedRequest, timeout: Option<Duration>) -> DispatchSignedRequestFuture {
+        let mut segment = match Subsegment::start(service_name(&request.service)) {
+            Some(segment) => segment,
+            None => return self.inner.dispatch(request, timeout),
+        };
+
+        segment.namespace = Some("aws");
+        segment.aws.insert("operation", operation_name(&request));
+        segment.aws.insert("region", request.region.name().to_string());
+
+        let response = self.inner.dispatch(request, timeout);
+        Box::pin(async move {
+            let result = response.await;
+            match &result {
+                Ok(response) => {
+                    let request_id = ["x-amzn-requestid", "x-amz-request-id"]
+                        .iter()
+                        .find_map(|header| response.headers.get(*header).cloned());
+                    if let Some(request_id) = request_id {
+                        segment.aws.insert("request_id", request_id);
+                    }
+                    segment.set_status(response.status.as_u16());
+                }
+                Err(e) => {
+                    segment.set_error(e);
+                    segment.fault = true;
+                }
+            }
+            segment.finish();
+            result
+        })
+    }
+}
+
+/// Returns the name X-Ray uses for an AWS service, given its signing name.
+fn service_name(signing_name: &str) -> &str {
+    match signing_name {
+        "acm" => "ACM",
+        "apigateway" => "APIGateway",
+        "cloudfront" => "CloudFront",
+        "dynamodb" => "DynamoDB",
+        "eks" => "EKS",
+        "elasticloadbalancing" => "ElasticLoadBalancing",
+        "events" => "EventBridge",
+        "iam" => "IAM",
+        "kms" => "KMS",
+        "lambda" => "Lambda",
+        "organizations" => "Organizations",
+        "route53" => "Route53",
+        "s3" => "S3",
+        "secretsmanager" => "SecretsManager",
+        "sns" => "SNS",
+        "ssm" => "SSM",
+        "sts" => "STS",
+        other => other,
+    }
+}
+
+/// Returns the operation of an AWS request: the target of JSON protocol requests (e.g. "ImportCertificate"), the
+/// action of query protocol requests (e.g. "UploadServerCertificate"), or the HTTP method of REST requests.
+fn operation_name(request: &SignedRequest) -> String {
+    if let Some(target) = request.headers.get("x-amz-target").and_then(|values| values.first()) {
+        let target = String::from_utf8_lossy(target);
+        return target.rsplit('.').next().unwrap_or_default().to_string();
+    }
+
+    if let Some(Some(action)) = request.params.get("Action") {
+        return action.clone();
+    }
+
+    request.method.clone()
+}
+
+/// Replace the characters X-Ray doesn't accept in segment names and truncate the name to the longest allowed.
+fn segment_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c.is_whitespace() || "_.:/%&#=+\\-@".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(MAX_NAME_LENGTH)
+        .collect()
+}
+
+/// Returns the UDP address of the X-Ray daemon. Lambda sets `AWS_XRAY_DAEMON_ADDRESS` to either `host:port` or
+/// `tcp:host:port udp:host:port`.
+fn daemon_address() -> String {
+    let address = var(ENV_XRAY_DAEMON_ADDRESS).unwrap_or_default();
+    match address.split_whitespace().find_map(|part| part.strip_prefix("udp:")) {
+        Some(udp) => udp.to_string(),
+        None if !address.is_empty() && !address.contains(' ') => address,
+        None => DEFAULT_XRAY_DAEMON_ADDRESS.to_string(),
+    }
+}
+
+fn new_id() -> Option<String> {
+    let mut id = [0u8; 8];
+    rand_bytes(&mut id).ok()?;
+    Some(id.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn now_secs() -> f64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64()).unwrap_or(0.0)
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[cfg(test)]
+#[allow(unused_imports, dead_code)]
+mod test {
+    use super::{segment_name, service_name, TraceHeader};
+
+    #[test]
+    fn test_trace_header() {
+        let header =
+            TraceHeader::parse("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1").unwrap();
+        assert_eq!(header.root, "1-5759e988-bd862e3fe1be46a994272793");
+        assert_eq!(header.parent, "53995c3f42cd8ad8");
+        assert!(header.sampled);
+
+        let header = TraceHeader::parse("Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=0");
+        assert!(!header.unwrap().sampled);
+        assert!(TraceHeader::parse("Root=1-5759e988-bd862e3fe1be46a994272793").is_none());
+    }
+
+    #[test]
+    fn test_segment_names() {
+        assert_eq!(segment_name("Store S3 (bucket) [1]"), "Store S3 _bucket_ _1_");
+        assert_eq!(segment_name(&"a".repeat(300)).len(), 200);
+        assert_eq!(service_name("elasticloadbalancing"), "ElasticLoadBalancing");
+        assert_eq!(service_name("codebuild"), "codebuild");
+    }
+}